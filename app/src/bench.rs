@@ -0,0 +1,187 @@
+//! `sf bench`: repeatable indexing and query measurements, printed as JSON
+//! so runs can be compared across versions and machines.
+
+use std::error::Error;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+
+use serde_json::{Value, json};
+use source_fast_core::{ContentStore, path_is_within_root, search_database_file, verify_hits};
+use source_fast_fs::{IndexBuilder, IndexHandleExt};
+use source_fast_progress::ScanEvent;
+use tokio::task;
+use tracing::info;
+
+use crate::cli::{default_db_path, resolve_root};
+
+/// Removes the scratch index of `sf bench index` when dropped.
+struct ScratchDir(PathBuf);
+
+impl Drop for ScratchDir {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.0);
+    }
+}
+
+/// Build a fresh index of `root` and report indexing throughput. The index
+/// goes to `db` (cleared first) or to a temporary directory, so the
+/// repository's own index is left alone.
+pub async fn run_bench_index(
+    root: Option<PathBuf>,
+    db: Option<PathBuf>,
+) -> Result<(), Box<dyn Error>> {
+    let root = resolve_root(root);
+    let (db_path, _scratch) = match db {
+        Some(db) => (db, None),
+        None => {
+            let dir = std::env::temp_dir().join(format!("sf-bench-{}", std::process::id()));
+            (dir.join("index.mdb"), Some(ScratchDir(dir)))
+        }
+    };
+    info!(root = %root.display(), db = %db_path.display(), "bench index requested");
+
+    let report = task::spawn_blocking(move || -> Result<Value, Box<dyn Error + Send + Sync>> {
+        let handle = IndexBuilder::new(&root).db_path(db_path).open()?;
+        handle.index().clear_content()?;
+
+        let files = Arc::new(AtomicU64::new(0));
+        let bytes = Arc::new(AtomicU64::new(0));
+        let progress: Arc<dyn Fn(ScanEvent) + Send + Sync> = {
+            let files = Arc::clone(&files);
+            let bytes = Arc::clone(&bytes);
+            Arc::new(move |event| {
+                if let ScanEvent::FileFinished { bytes: len, .. } = event {
+                    files.fetch_add(1, Ordering::Relaxed);
+                    bytes.fetch_add(len, Ordering::Relaxed);
+                }
+            })
+        };
+
+        let started = Instant::now();
+        handle.scan_with_progress(progress)?;
+        handle.flush()?;
+        let elapsed = started.elapsed();
+
+        let files = files.load(Ordering::Relaxed);
+        let bytes = bytes.load(Ordering::Relaxed);
+        let secs = elapsed.as_secs_f64().max(f64::EPSILON);
+        Ok(json!({
+            "benchmark": "index",
+            "root": root.display().to_string(),
+            "files": files,
+            "bytes": bytes,
+            "elapsed_ms": millis(elapsed),
+            "files_per_sec": files as f64 / secs,
+            "mb_per_sec": bytes as f64 / (1024.0 * 1024.0) / secs,
+        }))
+    })
+    .await?
+    .map_err(|err| err.to_string())?;
+
+    println!("{}", serde_json::to_string_pretty(&report)?);
+    Ok(())
+}
+
+/// Time every query in `queries` (one per line) `runs` times against the
+/// existing index of `root`, after one untimed warm-up pass.
+pub async fn run_bench_search(
+    root: Option<PathBuf>,
+    db: Option<PathBuf>,
+    queries: PathBuf,
+    runs: usize,
+) -> Result<(), Box<dyn Error>> {
+    let root = resolve_root(root);
+    let db_path = db.unwrap_or_else(|| default_db_path(&root));
+    info!(root = %root.display(), db = %db_path.display(), queries = %queries.display(), "bench search requested");
+
+    if !db_path.exists() {
+        return Err(format!(
+            "no index found at {}; build one with `sf index build` first",
+            db_path.display()
+        )
+        .into());
+    }
+    let queries: Vec<String> = std::fs::read_to_string(&queries)
+        .map_err(|err| format!("failed to read {}: {err}", queries.display()))?
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty())
+        .map(str::to_string)
+        .collect();
+    if queries.is_empty() {
+        return Err("the queries file has no queries".into());
+    }
+    let runs = runs.max(1);
+
+    let report = task::spawn_blocking(move || -> Result<Value, String> {
+        let store = crate::cli::open_content_store(&root, &db_path);
+        let mut all = Vec::with_capacity(queries.len() * runs);
+        let mut results = Vec::with_capacity(queries.len());
+        for query in &queries {
+            let mut hits = run_query(&db_path, &root, query, store.as_ref())?;
+            let mut samples = Vec::with_capacity(runs);
+            for _ in 0..runs {
+                let started = Instant::now();
+                hits = run_query(&db_path, &root, query, store.as_ref())?;
+                samples.push(millis(started.elapsed()));
+            }
+            samples.sort_by(f64::total_cmp);
+            results.push(json!({
+                "query": query,
+                "hits": hits,
+                "p50_ms": percentile(&samples, 0.50),
+                "p95_ms": percentile(&samples, 0.95),
+            }));
+            all.extend(samples);
+        }
+        all.sort_by(f64::total_cmp);
+
+        Ok(json!({
+            "benchmark": "search",
+            "root": root.display().to_string(),
+            "queries": queries.len(),
+            "runs": runs,
+            "samples": all.len(),
+            "p50_ms": percentile(&all, 0.50),
+            "p95_ms": percentile(&all, 0.95),
+            "max_ms": all.last().copied().unwrap_or(0.0),
+            "mean_ms": all.iter().sum::<f64>() / all.len() as f64,
+            "results": results,
+        }))
+    })
+    .await??;
+
+    println!("{}", serde_json::to_string_pretty(&report)?);
+    Ok(())
+}
+
+/// One search as `sf search --count` answers it without a daemon: index
+/// lookup, root filter and verification. Returns the hit count.
+fn run_query(
+    db_path: &Path,
+    root: &Path,
+    query: &str,
+    store: Option<&ContentStore>,
+) -> Result<usize, String> {
+    let hits: Vec<_> = search_database_file(db_path, query)
+        .map_err(|err| format!("search for {query:?} failed: {err}"))?
+        .into_iter()
+        .filter(|hit| path_is_within_root(&hit.path, root))
+        .collect();
+    Ok(verify_hits(hits, query, store).len())
+}
+
+fn millis(duration: Duration) -> f64 {
+    duration.as_secs_f64() * 1000.0
+}
+
+/// Nearest-rank percentile of ascending `samples`.
+fn percentile(samples: &[f64], p: f64) -> f64 {
+    if samples.is_empty() {
+        return 0.0;
+    }
+    let rank = (p * samples.len() as f64).ceil() as usize;
+    samples[rank.clamp(1, samples.len()) - 1]
+}
@@ -0,0 +1,116 @@
+//! Downloading prebuilt index snapshots for `sf index build --bootstrap-url`.
+//!
+//! A snapshot is published together with a checksum file at
+//! `<url>.sha256` in `sha256sum` format, which `sf export` writes next to
+//! the snapshot. The download is only moved into place once its SHA-256
+//! matches.
+
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use sha2::{Digest, Sha256};
+use tracing::info;
+
+/// Suffix of the checksum file published next to a snapshot.
+pub const CHECKSUM_SUFFIX: &str = ".sha256";
+
+fn checksum_path(snapshot: &Path) -> PathBuf {
+    let mut name = snapshot.as_os_str().to_os_string();
+    name.push(CHECKSUM_SUFFIX);
+    PathBuf::from(name)
+}
+
+fn hash_reader(mut reader: impl Read, mut sink: impl Write) -> io::Result<String> {
+    let mut hasher = Sha256::new();
+    let mut buf = vec![0u8; 64 * 1024];
+    loop {
+        let read = reader.read(&mut buf)?;
+        if read == 0 {
+            break;
+        }
+        hasher.update(&buf[..read]);
+        sink.write_all(&buf[..read])?;
+    }
+    sink.flush()?;
+    Ok(format!("{:x}", hasher.finalize()))
+}
+
+/// Write `<snapshot>.sha256` in `sha256sum` format and return its path.
+pub fn write_checksum_file(snapshot: &Path) -> io::Result<PathBuf> {
+    let digest = hash_reader(std::fs::File::open(snapshot)?, io::sink())?;
+    let file_name = snapshot
+        .file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    let path = checksum_path(snapshot);
+    std::fs::write(&path, format!("{digest}  {file_name}\n"))?;
+    Ok(path)
+}
+
+/// Extract the hex digest from a `sha256sum`-style line or a bare digest.
+fn parse_checksum(text: &str) -> Option<String> {
+    let digest = text.split_whitespace().next()?.to_ascii_lowercase();
+    (digest.len() == 64 && digest.bytes().all(|b| b.is_ascii_hexdigit())).then_some(digest)
+}
+
+fn agent() -> ureq::Agent {
+    ureq::AgentBuilder::new()
+        .timeout_connect(Duration::from_secs(10))
+        .timeout_read(Duration::from_secs(60))
+        .build()
+}
+
+/// Download the snapshot at `url` to `dest`, verifying it against
+/// `expected_sha256` or, when that is `None`, against `<url>.sha256`.
+pub fn download_snapshot(
+    url: &str,
+    expected_sha256: Option<&str>,
+    dest: &Path,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let agent = agent();
+    let expected = match expected_sha256 {
+        Some(digest) => {
+            parse_checksum(digest).ok_or_else(|| format!("invalid SHA-256 checksum: {digest}"))?
+        }
+        None => {
+            let checksum_url = format!("{url}{CHECKSUM_SUFFIX}");
+            let text = agent
+                .get(&checksum_url)
+                .call()
+                .map_err(|err| format!("failed to fetch checksum from {checksum_url}: {err}"))?
+                .into_string()?;
+            parse_checksum(&text)
+                .ok_or_else(|| format!("{checksum_url} does not contain a SHA-256 checksum"))?
+        }
+    };
+
+    info!(url, dest = %dest.display(), "downloading index snapshot");
+    let response = agent
+        .get(url)
+        .call()
+        .map_err(|err| format!("failed to download {url}: {err}"))?;
+
+    if let Some(parent) = dest.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    let partial = dest.with_extension("part");
+    let file = io::BufWriter::new(std::fs::File::create(&partial)?);
+    let actual = match hash_reader(response.into_reader(), file) {
+        Ok(digest) => digest,
+        Err(err) => {
+            let _ = std::fs::remove_file(&partial);
+            return Err(err.into());
+        }
+    };
+
+    if actual != expected {
+        let _ = std::fs::remove_file(&partial);
+        return Err(
+            format!("checksum mismatch for {url}: expected {expected}, got {actual}").into(),
+        );
+    }
+
+    std::fs::rename(&partial, dest)?;
+    Ok(())
+}
@@ -1,22 +1,41 @@
-use std::io::{self, Write};
+use std::borrow::Cow;
+use std::collections::BTreeMap;
+use std::io::{self, IsTerminal, Write};
+use std::ops::ControlFlow;
 use std::path::{Path, PathBuf};
 use std::sync::Arc;
-use std::sync::Mutex;
-use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Mutex, OnceLock};
 use std::time::{Duration, Instant};
 
 use regex::Regex;
+use schemars::JsonSchema;
+use serde::Deserialize;
 use source_fast_core::{
-    IndexError, PersistentIndex, extract_snippets, is_leader_active_readonly, normalize_path,
-    normalize_path_for_prefix, now_millis, path_is_within_root, read_meta_readonly,
-    rewrite_root_paths, search_database_file_filtered, search_files_in_database,
+    AuditRules, ContentKey, ContentPolicy, ContentStore, ExtensionStat, FileEdit, FileSuggestion,
+    HistoryEntry, INCLUDE_PATHS_META_KEY, IndexConfig, IndexError, IndexSnapshot, MatchFilter,
+    PersistentIndex, PlanAction, SHORT_QUERY_SCAN_LIMIT, SavedQueries, SavedQuery,
+    SearchDiagnostics, SearchHit, SearchOutcome, SecretRules, Severity, ShortQuery, Snippet,
+    WatchMode, audit_database_file, clear_history, count_database_file, count_occurrences,
+    decode_path, diagnose_database_file, explain_database_file, export_snapshot,
+    extension_stats_in_database, identifier_spellings, largest_files_in_database,
+    list_files_in_database, normalize_path, normalize_path_for_prefix, now_millis,
+    parse_include_paths, path_is_within_root, plan_replace, read_history, read_leader_readonly,
+    read_meta_readonly, record_history, replace_index, rewrite_root_paths,
+    search_database_file_batch, search_database_file_within, search_files_in_database,
+    short_query_in_database, similar_files_in_database, staging_db_path, stream_snippets_filtered,
+    stream_snippets_unordered, suggest_files_in_database, top_trigrams_in_database,
+    verify_hits_filtered, verify_hits_limited,
 };
-use source_fast_fs::smart_scan_with_progress;
-use source_fast_progress::{IndexPhase, IndexProgress, ScanEvent};
+use source_fast_fs::{
+    GitLocation, HeadDrift, SCAN_CURSOR_META_KEY, ScanLimits, ScanStatus, Throttle, head_drift,
+    smart_scan, smart_scan_with_limits,
+};
+use source_fast_progress::{IndexPhase, IndexProgress, ScanEvent, WatcherHealth, WatcherState};
 use tokio::task;
 use tracing::{debug, error, info, warn};
 
-use crate::daemon;
+use crate::{bootstrap, daemon, rpc};
 
 const INDEX_ROOT_META: &str = "index_root";
 
@@ -33,12 +52,169 @@ pub fn resolve_root(root: Option<PathBuf>) -> PathBuf {
     root.canonicalize().unwrap_or(root)
 }
 
+/// Where the index for a root is stored when `--db` is not given.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum DbLocation {
+    /// `<root>/.source_fast/index.mdb`
+    Repo,
+    /// `<user cache dir>/source_fast/<hash of root>/index.mdb`, for
+    /// checkouts that must not be written to.
+    Global,
+}
+
+const DB_LOCATION_ENV: &str = "SOURCE_FAST_DB_LOCATION";
+
+pub(crate) const WATCH_MODE_ENV: &str = "SOURCE_FAST_WATCH_MODE";
+
+pub(crate) const THREADS_ENV: &str = "SOURCE_FAST_THREADS";
+
+pub(crate) const BACKGROUND_NICE_ENV: &str = "SOURCE_FAST_BACKGROUND_NICE";
+
+/// File in a global index directory recording which root it belongs to.
+pub const GLOBAL_ROOT_MARKER: &str = "root.txt";
+
+static DB_LOCATION: OnceLock<DbLocation> = OnceLock::new();
+static WATCH_MODE: OnceLock<WatchMode> = OnceLock::new();
+static THREADS: OnceLock<usize> = OnceLock::new();
+static BACKGROUND_NICE: OnceLock<bool> = OnceLock::new();
+
+/// Apply `--db-location` for the rest of the process.
+pub fn set_db_location(location: DbLocation) {
+    let _ = DB_LOCATION.set(location);
+}
+
+fn configured_db_location() -> Option<DbLocation> {
+    if let Some(location) = DB_LOCATION.get() {
+        return Some(*location);
+    }
+    let value = std::env::var(DB_LOCATION_ENV).ok()?;
+    match clap::ValueEnum::from_str(&value, true) {
+        Ok(location) => Some(location),
+        Err(_) => {
+            warn!(value = %value, "ignoring invalid {DB_LOCATION_ENV}");
+            None
+        }
+    }
+}
+
+/// Apply `--watch-mode` for the rest of the process.
+pub fn set_watch_mode(mode: WatchMode) {
+    let _ = WATCH_MODE.set(mode);
+}
+
+/// The watch mode given by `--watch-mode` or `SOURCE_FAST_WATCH_MODE`, which
+/// take precedence over the root's `[watcher] mode`.
+pub(crate) fn watch_mode_override() -> Option<WatchMode> {
+    if let Some(mode) = WATCH_MODE.get() {
+        return Some(*mode);
+    }
+    let value = std::env::var(WATCH_MODE_ENV).ok()?;
+    match value.parse() {
+        Ok(mode) => Some(mode),
+        Err(_) => {
+            warn!(value = %value, "ignoring invalid {WATCH_MODE_ENV}");
+            None
+        }
+    }
+}
+
+/// Apply `--threads` for the rest of the process.
+pub fn set_threads(threads: usize) {
+    let _ = THREADS.set(threads);
+}
+
+/// The worker thread count given by `--threads` or `SOURCE_FAST_THREADS`,
+/// which take precedence over the root's `[workers] threads`.
+pub(crate) fn threads_override() -> Option<usize> {
+    if let Some(threads) = THREADS.get() {
+        return Some(*threads);
+    }
+    let value = std::env::var(THREADS_ENV).ok()?;
+    match value.parse() {
+        Ok(threads) => Some(threads),
+        Err(_) => {
+            warn!(value = %value, "ignoring invalid {THREADS_ENV}");
+            None
+        }
+    }
+}
+
+/// Apply `sf server --background-nice` for the rest of the process.
+pub fn set_background_nice() {
+    let _ = BACKGROUND_NICE.set(true);
+}
+
+/// Whether indexing runs in the background mode of `--background-nice`,
+/// given here or to the server that started this daemon.
+pub(crate) fn background_nice() -> bool {
+    *BACKGROUND_NICE.get_or_init(|| std::env::var_os(BACKGROUND_NICE_ENV).is_some())
+}
+
+/// Throttle scans when [`background_nice`] is on; see
+/// [`Throttle::background`].
+pub(crate) fn apply_background_nice() {
+    if background_nice() {
+        source_fast_fs::set_throttle(Throttle::background());
+    }
+}
+
+/// Size the worker pool for scans and snippet extraction from `config`'s
+/// `[workers]` section and `--threads`. The first root to be opened decides
+/// for the whole process. `--background-nice` always lowers the workers'
+/// priority.
+fn configure_workers(config: &IndexConfig) {
+    let mut workers = config.workers.clone();
+    if let Some(threads) = threads_override() {
+        workers.threads = threads;
+    }
+    workers.low_priority |= background_nice();
+    if let Err(err) = source_fast_core::configure_workers(&workers) {
+        warn!(error = %err, "using the default worker pool");
+    }
+}
+
+/// Per-root directory under the user cache dir used by
+/// [`DbLocation::Global`].
+pub fn global_db_dir(root: &Path) -> PathBuf {
+    use sha2::{Digest, Sha256};
+
+    let digest = Sha256::digest(normalize_path_for_prefix(&normalize_path(root)).as_bytes());
+    let hash: String = digest[..8].iter().map(|b| format!("{b:02x}")).collect();
+    let cache = dirs::cache_dir()
+        .or_else(|| dirs::home_dir().map(|home| home.join(".cache")))
+        .unwrap_or_else(|| PathBuf::from("."));
+    cache.join("source_fast").join(hash)
+}
+
+/// Resolve the index path for `root`. Without an explicit location, an
+/// existing repo-local index wins, then an existing global one; new indexes
+/// go into the repo unless `.source_fast/` cannot be created there.
 pub fn default_db_path(root: &Path) -> PathBuf {
-    let mut dir = root.to_path_buf();
-    dir.push(".source_fast");
+    let repo_dir = root.join(".source_fast");
+    let location = configured_db_location().unwrap_or_else(|| {
+        if repo_dir.join("index.mdb").exists() {
+            DbLocation::Repo
+        } else if global_db_dir(root).join("index.mdb").exists() {
+            DbLocation::Global
+        } else if std::fs::create_dir_all(&repo_dir).is_ok() {
+            DbLocation::Repo
+        } else {
+            DbLocation::Global
+        }
+    });
+
+    let dir = match location {
+        DbLocation::Repo => repo_dir,
+        DbLocation::Global => {
+            let dir = global_db_dir(root);
+            if std::fs::create_dir_all(&dir).is_ok() {
+                let _ = std::fs::write(dir.join(GLOBAL_ROOT_MARKER), root.display().to_string());
+            }
+            dir
+        }
+    };
     let _ = std::fs::create_dir_all(&dir);
-    dir.push("index.mdb");
-    dir
+    dir.join("index.mdb")
 }
 
 // ---------------------------------------------------------------------------
@@ -50,6 +226,53 @@ fn clean_display_path(path: &str) -> &str {
     path.strip_prefix(r"\\?\").unwrap_or(path)
 }
 
+/// Root that search output prints paths relative to, once `--relative`
+/// (or its default) has been applied.
+static RELATIVE_ROOT: OnceLock<PathBuf> = OnceLock::new();
+
+/// Print paths below `root` relative to it for the rest of the process
+/// when `relative` (`--relative`/`--absolute`) says so, or by default when
+/// the current directory is inside `root`.
+fn apply_relative_paths(root: &Path, relative: Option<bool>) {
+    let relative = relative.unwrap_or_else(|| {
+        let cwd = default_root();
+        cwd.canonicalize().unwrap_or(cwd).starts_with(root)
+    });
+    if relative {
+        let _ = RELATIVE_ROOT.set(PathBuf::from(clean_display_path(&root.to_string_lossy())));
+    }
+}
+
+/// `path` as search output prints it: without the `\\?\` prefix and,
+/// with relative paths on, relative to the root.
+fn display_path(path: &str) -> Cow<'_, str> {
+    let path = clean_display_path(path);
+    let relative = RELATIVE_ROOT
+        .get()
+        .and_then(|root| Path::new(path).strip_prefix(root).ok())
+        .filter(|relative| !relative.as_os_str().is_empty());
+    match relative {
+        Some(relative) => Cow::Owned(relative.to_string_lossy().into_owned()),
+        None => Cow::Borrowed(path),
+    }
+}
+
+/// Print `path` as one entry of a path list: newline-terminated, or
+/// NUL-terminated with `-0` so `xargs -0` takes paths with spaces or
+/// newlines intact. The path's bytes are written as they are on disk,
+/// without escaping.
+fn print_path_entry(path: &str, null: bool) {
+    let mut out = io::stdout().lock();
+    #[cfg(unix)]
+    {
+        use std::os::unix::ffi::OsStrExt;
+        let _ = out.write_all(decode_path(path).as_os_str().as_bytes());
+    }
+    #[cfg(not(unix))]
+    let _ = out.write_all(path.as_bytes());
+    let _ = out.write_all(if null { b"\0" } else { b"\n" });
+}
+
 /// Truncate a line to `max_chars` characters, appending `...` if truncated.
 fn truncate_line(line: &str, max_chars: usize) -> String {
     if line.len() <= max_chars {
@@ -91,7 +314,7 @@ fn truncate_to_display_width(s: &str, max_width: usize) -> String {
 }
 
 /// Build a combined file-filter regex from --file-regex, --ext, and --glob.
-fn build_file_filter(
+pub(crate) fn build_file_filter(
     file_regex: &Option<String>,
     ext: &[String],
     glob: &Option<String>,
@@ -264,7 +487,7 @@ fn best_effort_stop_daemon(db_path: &Path) {
         return;
     }
 
-    if !is_leader_active_readonly(db_path).unwrap_or(false) {
+    if !daemon::live_leader_active(db_path).unwrap_or(false) {
         return;
     }
 
@@ -275,7 +498,7 @@ fn best_effort_stop_daemon(db_path: &Path) {
 
     let deadline = Instant::now() + Duration::from_secs(5);
     while Instant::now() < deadline {
-        if !is_leader_active_readonly(db_path).unwrap_or(true) {
+        if !daemon::live_leader_active(db_path).unwrap_or(true) {
             break;
         }
         std::thread::sleep(Duration::from_millis(100));
@@ -319,6 +542,8 @@ fn render_watch_frame(snapshot: &WatchSnapshot, tick: usize) -> (String, String)
         "ETA done".to_string()
     } else if snapshot.phase == IndexPhase::Failed {
         "ETA failed".to_string()
+    } else if snapshot.phase == IndexPhase::Paused {
+        "ETA paused".to_string()
     } else {
         // Use file-based throughput for ETA (more stable than byte-based
         // since large files cause byte throughput to spike unpredictably).
@@ -391,20 +616,20 @@ fn print_watch_summary(snapshot: &WatchSnapshot) {
     } else {
         0
     };
-    let status = if snapshot.phase == IndexPhase::Complete {
-        "✓"
-    } else {
-        "✗"
+    let status = match snapshot.phase {
+        IndexPhase::Complete => "✓",
+        IndexPhase::Paused => "⏸",
+        _ => "✗",
     };
     let summary = format!(
         "{status} Indexed {} files ({}) in {} {}",
         snapshot.processed_files,
         format_bytes(snapshot.processed_bytes),
         format_eta(elapsed_secs),
-        if snapshot.phase == IndexPhase::Complete {
-            format!("{} files/sec", rate)
-        } else {
-            "before failing".to_string()
+        match snapshot.phase {
+            IndexPhase::Complete => format!("{} files/sec", rate),
+            IndexPhase::Paused => "before pausing; run `sf index watch` to continue".to_string(),
+            _ => "before failing".to_string(),
         }
     );
     eprint!("\r\x1b[1A\x1b[2K{summary}\n\x1b[2K\n");
@@ -458,6 +683,9 @@ fn watch_progress_polling(db_path: &Path) {
             None if status == daemon::index_status::FAILED => {
                 "\x1b[31m✗ Index build failed.\x1b[0m".to_string()
             }
+            None if status == daemon::index_status::PAUSED => {
+                "Index build paused; run `sf index watch` to continue.".to_string()
+            }
             _ if status.is_empty() || status == daemon::index_status::BUILDING => {
                 "Waiting for daemon...".to_string()
             }
@@ -468,7 +696,10 @@ fn watch_progress_polling(db_path: &Path) {
         let truncated = truncate_to_display_width(&line, term_width);
         eprint!("\r\x1b[2K{truncated}");
 
-        if status == daemon::index_status::COMPLETE || status == daemon::index_status::FAILED {
+        if status == daemon::index_status::COMPLETE
+            || status == daemon::index_status::FAILED
+            || status == daemon::index_status::PAUSED
+        {
             eprintln!();
             break;
         }
@@ -486,15 +717,7 @@ fn remove_db_files(db_path: &Path) {
 }
 
 fn is_corrupt_db(err: &IndexError) -> bool {
-    match err {
-        IndexError::Db(db_err) => {
-            db_err.contains("Invalid")
-                || db_err.contains("corrupted")
-                || db_err.contains("MDB_INVALID")
-                || db_err.contains("MDB_VERSION_MISMATCH")
-        }
-        _ => false,
-    }
+    matches!(err, IndexError::Corrupt(_))
 }
 
 fn primary_worktree_root(root: &Path) -> Option<PathBuf> {
@@ -534,14 +757,23 @@ fn roots_equivalent(lhs: &str, rhs: &str) -> bool {
     normalize_path_for_prefix(lhs) == normalize_path_for_prefix(rhs)
 }
 
+/// Record `root` as the root of `index`, unless another process holds the
+/// writer lease and is left to record it.
 fn set_index_root(index: &PersistentIndex, root: &Path) -> Result<(), IndexError> {
-    index.set_meta(INDEX_ROOT_META, &normalize_path(root))
+    match index.set_meta(INDEX_ROOT_META, &normalize_path(root)) {
+        Err(IndexError::Lease(reason)) => {
+            debug!(%reason, "writer lease held elsewhere; leaving the index root to it");
+            Ok(())
+        }
+        other => other,
+    }
 }
 
 fn validate_index_for_root(index: &PersistentIndex, root: &Path) -> Result<bool, IndexError> {
     let expected_root = normalize_path(root);
-    if let Some(stored_root) = index.get_meta(INDEX_ROOT_META)?
-        && !roots_equivalent(&stored_root, &expected_root)
+    let stored_root = index.get_meta(INDEX_ROOT_META)?;
+    if let Some(stored_root) = &stored_root
+        && !roots_equivalent(stored_root, &expected_root)
     {
         warn!(
             stored_root = %stored_root,
@@ -561,7 +793,10 @@ fn validate_index_for_root(index: &PersistentIndex, root: &Path) -> Result<bool,
         return Ok(false);
     }
 
-    set_index_root(index, root)?;
+    // Readers open the index too; only an index without a root needs one.
+    if stored_root.is_none() {
+        set_index_root(index, root)?;
+    }
     Ok(true)
 }
 
@@ -625,10 +860,62 @@ fn copy_db_from_primary_worktree(root: &Path, db_path: &Path) -> Option<PathBuf>
     }
 }
 
+/// The content store of the index at `db_path`, opening content sealed
+/// with `root`'s `[encryption]` key and redacting `[secrets]` matches.
+pub(crate) fn open_content_store(root: &Path, db_path: &Path) -> Option<ContentStore> {
+    let config = IndexConfig::load(root).unwrap_or_else(|err| {
+        warn!(error = %err, "failed to load index config; using defaults");
+        IndexConfig::default()
+    });
+    configure_workers(&config);
+    let key = ContentKey::from_config(&config.encryption).unwrap_or_else(|err| {
+        warn!(error = %err, "cannot open encrypted content; reading files from disk");
+        None
+    });
+    let secrets = SecretRules::from_config(&config.secrets).unwrap_or_else(|err| {
+        warn!(error = %err, "ignoring invalid [secrets] patterns");
+        None
+    });
+    let store = ContentStore::open(db_path).ok()?;
+    Some(store.with_key(key.map(Arc::new)).with_secret_rules(secrets))
+}
+
+/// Open (or create) the index for `root` and apply the root's
+/// `.source_fast/config.toml` to it. An index built with a different
+/// tokenizer is cleared so the next scan rebuilds it, unless another
+/// process holds the writer lease; see [`ensure_settings_as_writer`].
 pub(crate) fn open_index_with_worktree_copy(
     root: &Path,
     db_path: &Path,
 ) -> Result<PersistentIndex, IndexError> {
+    configure_index(root, open_index_for_root(root, db_path)?)
+}
+
+/// Apply `root`'s `.source_fast/config.toml` to `index`.
+fn configure_index(root: &Path, index: PersistentIndex) -> Result<PersistentIndex, IndexError> {
+    let config = IndexConfig::load(root)?;
+    configure_workers(&config);
+    let policy = ContentPolicy::from_config(root, &config)?;
+    index.set_content_policy(policy);
+    index.set_idle_commit(config.writer.idle_commit());
+    index.set_sync_mode(config.writer.sync)?;
+    if index.ensure_settings()? {
+        info!(root = %root.display(), "index built with a different tokenizer; it will be rebuilt");
+    }
+    Ok(index)
+}
+
+/// Clear or re-key `index` for the configured tokenizer and content key
+/// once this process holds its writer lease; opening it while another
+/// process held the lease left that step out.
+pub(crate) fn ensure_settings_as_writer(index: &PersistentIndex) -> Result<(), IndexError> {
+    if index.ensure_settings()? {
+        info!(db = %index.db_path().display(), "index built with a different tokenizer; it will be rebuilt");
+    }
+    Ok(())
+}
+
+fn open_index_for_root(root: &Path, db_path: &Path) -> Result<PersistentIndex, IndexError> {
     if let Some(parent) = db_path.parent() {
         std::fs::create_dir_all(parent).map_err(IndexError::Io)?;
     }
@@ -803,304 +1090,756 @@ pub struct SearchOpts {
     pub limit: usize,
     pub json: bool,
     pub files_only: bool,
+    /// List the files that do not match instead.
+    pub files_without_match: bool,
+    /// End each listed path with NUL instead of a newline (`-0`).
+    pub null: bool,
     pub count: bool,
+    /// Print nothing; the exit code alone says whether anything matched.
+    pub quiet: bool,
+    /// Print `path:line:text` per matching line, without color or notes.
+    pub porcelain: bool,
+    /// Drop hits whose file does not contain the query verbatim.
+    pub verify: bool,
+    /// Files read at once while extracting snippets.
+    pub read_concurrency: usize,
+    /// Aggregate hits into groups instead of listing files.
+    pub group_by: Option<GroupBy>,
+    /// Count every occurrence per file and directory instead of listing
+    /// matches.
+    pub heatmap: bool,
+    /// Which occurrences count: only those in code, comments or string
+    /// literals, and verbatim or as an identifier in any spelling.
+    pub match_filter: MatchFilter,
+    /// Print paths relative to the root (`--relative`/`--absolute`); by
+    /// default when the current directory is inside it.
+    pub relative: Option<bool>,
+    /// Add the column of each match to `--porcelain` lines.
+    pub column: bool,
+    /// Print matches for editors instead (`--format`).
+    pub format: Option<OutputFormat>,
+}
+
+/// Line formats of `sf search --format` that editors parse directly.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum OutputFormat {
+    /// `path:line:column:text` per occurrence, as `rg --vimgrep` prints.
+    Vimgrep,
+}
+
+impl SearchOpts {
+    /// Whether hits are verified before they are printed. Text output
+    /// verifies through its snippet read instead, which also applies the
+    /// match kind; without verification the match kind still needs a read.
+    fn verifies_up_front(&self, output_mode: SearchOutputMode) -> bool {
+        if output_mode == SearchOutputMode::Heatmap {
+            // Counting reads every file; those without a match count zero.
+            false
+        } else if output_mode.streams_snippets() {
+            !self.match_filter.is_verbatim() && !self.verify
+        } else {
+            self.verify || !self.match_filter.is_verbatim()
+        }
+    }
 }
 
-#[derive(Clone, Copy)]
+#[derive(Clone, Copy, PartialEq, Eq)]
 enum SearchOutputMode {
     Text,
     Json,
     FilesOnly,
+    FilesWithoutMatch,
     Count,
+    Porcelain,
+    Vimgrep,
+    Quiet,
+    Grouped,
+    Heatmap,
 }
 
 impl SearchOutputMode {
-    fn from_flags(count: bool, files_only: bool, json: bool) -> Self {
-        if count {
+    fn from_opts(opts: &SearchOpts) -> Self {
+        if opts.quiet {
+            Self::Quiet
+        } else if opts.count {
             Self::Count
-        } else if files_only {
+        } else if opts.files_only {
             Self::FilesOnly
-        } else if json {
+        } else if opts.files_without_match {
+            Self::FilesWithoutMatch
+        } else if opts.group_by.is_some() {
+            Self::Grouped
+        } else if opts.heatmap {
+            Self::Heatmap
+        } else if opts.format == Some(OutputFormat::Vimgrep) {
+            Self::Vimgrep
+        } else if opts.json {
             Self::Json
+        } else if opts.porcelain {
+            Self::Porcelain
         } else {
             Self::Text
         }
     }
+
+    /// Whether progress notes and explanations go to stderr.
+    fn notes(self) -> bool {
+        !matches!(self, Self::Porcelain | Self::Vimgrep | Self::Quiet)
+    }
+
+    /// Whether matches are confirmed by reading snippets while printing
+    /// instead of by a separate verification pass.
+    fn streams_snippets(self) -> bool {
+        matches!(self, Self::Text | Self::Porcelain | Self::Vimgrep)
+    }
+
+    /// How many verified hits the mode shows at most with result limit
+    /// `limit` (0 for all), so verification can stop there. Modes that
+    /// count or group every match need all of them.
+    fn verify_limit(self, limit: usize) -> usize {
+        match self {
+            Self::Quiet => 1,
+            Self::Text | Self::Porcelain | Self::Vimgrep | Self::FilesOnly | Self::Json => limit,
+            Self::Count | Self::FilesWithoutMatch | Self::Grouped | Self::Heatmap => 0,
+        }
+    }
 }
 
-pub async fn run_search_with_daemon(opts: SearchOpts) -> Result<(), Box<dyn std::error::Error>> {
-    let command_started = Instant::now();
-    let output_mode = SearchOutputMode::from_flags(opts.count, opts.files_only, opts.json);
-    let root = resolve_root(opts.root);
-    let db_path = opts.db.unwrap_or_else(|| default_db_path(&root));
-    let query = opts.query;
+/// Run `sf search`. Returns whether anything matched, for the exit code.
+pub async fn run_search_with_daemon(opts: SearchOpts) -> Result<bool, Box<dyn std::error::Error>> {
+    let output_mode = SearchOutputMode::from_opts(&opts);
+    let root = resolve_root(opts.root.clone());
+    apply_relative_paths(&root, opts.relative);
+    let db_path = opts.db.clone().unwrap_or_else(|| default_db_path(&root));
+    let query = opts.query.as_str();
     let limit = opts.limit;
 
     // Build the file filter regex from --file-regex, --ext, or --glob.
     let file_regex = build_file_filter(&opts.file_regex, &opts.ext, &opts.glob)?;
 
-    let first_time = !db_path.exists();
     info!(
         root = %root.display(),
         db = %db_path.display(),
         query = %query,
         file_regex = ?file_regex.as_ref().map(|re| re.as_str()),
         wait = opts.wait,
-        first_time,
+        first_time = !db_path.exists(),
         "search command starting"
     );
-
-    // Ensure a daemon (or MCP server) is keeping the index warm.
-    let ensure_started = Instant::now();
-    let was_running = daemon::ensure_daemon(&root, &db_path)?;
-    info!(
-        root = %root.display(),
-        db = %db_path.display(),
-        was_running,
-        elapsed_ms = ensure_started.elapsed().as_millis() as u64,
-        "ensure_daemon finished for search command"
-    );
-
-    if first_time {
-        eprintln!(
-            "Starting index for the first time. Results will be partial until indexing completes."
-        );
-    }
-
-    if !was_running {
-        let daemon_wait_started = Instant::now();
-        let confirmed = daemon::wait_for_daemon(&db_path, Duration::from_secs(3));
-        info!(
-            db = %db_path.display(),
-            confirmed,
-            elapsed_ms = daemon_wait_started.elapsed().as_millis() as u64,
-            "daemon readiness wait finished for search command"
-        );
-        if !confirmed {
-            warn!("Daemon did not confirm in 3 s, proceeding with search anyway");
-        }
+    if !prepare_search(&root, &db_path, opts.wait, output_mode)? {
+        return Ok(false);
     }
 
-    // If --wait, block until index is complete.
-    if opts.wait {
-        let index_wait_started = Instant::now();
-        let complete = daemon::wait_for_index_complete(&db_path, Duration::from_secs(120));
-        info!(
-            db = %db_path.display(),
-            complete,
-            elapsed_ms = index_wait_started.elapsed().as_millis() as u64,
-            "index completion wait finished for search command"
+    // Get trigram search hits (fast — bitmap intersection only, no file I/O).
+    // Ask the daemon first; it already has the index open.
+    let mut hits = Vec::new();
+    let mut generation = 0;
+    for lookup in lookup_queries(query, opts.match_filter) {
+        let rpc_outcome = rpc::search_within(&db_path, &lookup, file_regex.as_ref(), None);
+        debug!(
+            via_daemon = rpc_outcome.is_some(),
+            lookup = %lookup,
+            "search command trigram lookup"
         );
-        if !complete {
-            eprintln!("Timed out waiting for index to complete (120 s).");
+        let search_result = match rpc_outcome {
+            Some(outcome) => Ok(outcome),
+            None => search_database_file_within(&db_path, &lookup, file_regex.as_ref(), None),
+        };
+        match search_result {
+            Ok(outcome) => {
+                hits.extend(outcome.hits);
+                generation = generation.max(outcome.generation);
+            }
+            Err(err) => {
+                error!(db = %db_path.display(), query = %query, error = ?err, "search command failed");
+                return Err(err.into());
+            }
         }
     }
-
-    if !db_path.exists() {
-        // DB hasn't been created yet (daemon just started). Nothing to search.
-        info!(
-            db = %db_path.display(),
-            elapsed_ms = command_started.elapsed().as_millis() as u64,
-            "search command finished before database directory was created"
-        );
-        return Ok(());
-    }
-
-    // Check completeness for the disclaimer.
-    if let Ok(Some(status)) = read_meta_readonly(&db_path, daemon::meta_keys::INDEX_STATUS) {
-        debug!(db = %db_path.display(), index_status = %status, "search command observed index status");
-        if status != daemon::index_status::COMPLETE {
-            eprintln!("Note: index is still building. Results may be incomplete.");
+    // A query too short for any trigram has no postings to look up.
+    let mut scanned = false;
+    if hits.is_empty() {
+        match short_query_in_database(&db_path, query, file_regex.as_ref()) {
+            Ok(Some(ShortQuery::Scan(files))) => {
+                hits = files;
+                scanned = true;
+            }
+            Ok(Some(ShortQuery::Paths {
+                files_indexed,
+                hits: mut paths,
+            })) => {
+                paths.retain(|hit| path_is_within_root(&hit.path, &root));
+                return print_path_fallback(
+                    output_mode,
+                    query,
+                    files_indexed,
+                    &paths,
+                    limit,
+                    opts.null,
+                );
+            }
+            Ok(None) => {}
+            Err(err) => warn!(query = %query, error = ?err, "short query fallback failed"),
         }
     }
-
-    // Get trigram search hits (fast — bitmap intersection only, no file I/O).
-    let mut hits = match search_database_file_filtered(&db_path, &query, file_regex.as_ref()) {
-        Ok(h) => h,
-        Err(err) => {
-            error!(db = %db_path.display(), query = %query, error = ?err, "search command failed");
-            std::process::exit(1);
-        }
-    };
     hits.retain(|hit| path_is_within_root(&hit.path, &root));
     hits.sort_by(|a, b| a.path.cmp(&b.path));
+    hits.dedup_by(|a, b| a.path == b.path);
+    let candidates = hits.len();
+    let no_results = || diagnose_no_results(&db_path, query, candidates);
+    // Snippets and verification prefer the indexed copy of each file.
+    let store = open_content_store(&root, &db_path);
+    // A scan lists every file, so it is verified whatever the flags say.
+    if scanned || opts.verifies_up_front(output_mode) {
+        hits = verify_hits_limited(
+            hits,
+            query,
+            store.as_ref(),
+            opts.read_concurrency,
+            opts.match_filter,
+            output_mode.verify_limit(limit),
+        );
+    }
 
     let total = hits.len();
     let display_limit = if limit > 0 { limit } else { total };
 
     match output_mode {
+        SearchOutputMode::Quiet => return Ok(total > 0),
         SearchOutputMode::Count => {
             println!("{total}");
-            return Ok(());
+            return Ok(total > 0);
         }
         SearchOutputMode::FilesOnly => {
+            if total == 0
+                && let Some(no_results) = no_results()
+            {
+                eprint!("{}", no_results.explain(query));
+            }
             for (i, hit) in hits.iter().enumerate() {
                 if i >= display_limit {
                     break;
                 }
-                println!("{}", clean_display_path(&hit.path));
+                print_path_entry(&display_path(&hit.path), opts.null);
             }
             if total > display_limit {
                 eprintln!("... and {} more (use -l 0 for all)", total - display_limit);
             }
-            return Ok(());
-        }
-        SearchOutputMode::Json => {
-            return print_json_results(&hits, &query, display_limit);
+            return Ok(total > 0);
         }
-        SearchOutputMode::Text => {}
-    }
-
-    // ---- Default: streaming rg-style output with snippets ----
-    let done = Arc::new(std::sync::atomic::AtomicBool::new(false));
-    let (tx, rx) = std::sync::mpsc::sync_channel::<(String, Vec<source_fast_core::Snippet>)>(32);
-
-    let query_for_workers = query.clone();
-    let done_for_workers = Arc::clone(&done);
-    std::thread::spawn(move || {
-        use rayon::prelude::*;
-        hits.par_iter().for_each(|hit| {
-            if done_for_workers.load(std::sync::atomic::Ordering::Relaxed) {
-                return;
+        SearchOutputMode::FilesWithoutMatch => {
+            let unmatched = files_without_match(&db_path, &root, file_regex.as_ref(), &hits)?;
+            let shown = if limit > 0 { limit } else { unmatched.len() };
+            for path in unmatched.iter().take(shown) {
+                print_path_entry(&display_path(path), opts.null);
             }
-            let path = PathBuf::from(&hit.path);
-            let snippets = extract_snippets(&path, &query_for_workers).unwrap_or_default();
-            if tx.send((hit.path.clone(), snippets)).is_err() {
-                done_for_workers.store(true, std::sync::atomic::Ordering::Relaxed);
+            if unmatched.len() > shown {
+                eprintln!(
+                    "... and {} more (use -l 0 for all)",
+                    unmatched.len() - shown
+                );
             }
-        });
-    });
-
-    let mut printed = 0usize;
-    let mut no_snippet_paths: Vec<String> = Vec::new();
-
-    for (path, snippets) in &rx {
-        if !snippets.is_empty() {
-            for snippet in snippets {
-                let path_str = snippet.path.display().to_string();
-                let display_path = clean_display_path(&path_str);
-                println!("\x1b[35m{display_path}\x1b[0m:{}", snippet.line_number);
-                for (line_no, line) in &snippet.lines {
-                    let truncated = truncate_line(line, 200);
-                    if line.contains(&query) {
-                        println!("\x1b[32m{line_no}\x1b[0m:{truncated}");
-                    } else {
-                        println!("\x1b[2m{line_no}\x1b[0m:{truncated}");
-                    }
-                }
-                println!();
+            return Ok(!unmatched.is_empty());
+        }
+        SearchOutputMode::Json => {
+            let mut output = search_results_json(
+                &hits,
+                query,
+                display_limit,
+                store.as_ref(),
+                opts.read_concurrency,
+                opts.match_filter,
+            );
+            if total == 0
+                && let Some(no_results) = no_results()
+            {
+                output["diagnostics"] = no_results.to_json();
             }
-            printed += 1;
-        } else {
-            no_snippet_paths.push(path);
+            set_generation(&mut output, generation);
+            println!("{}", serde_json::to_string_pretty(&output)?);
+            return Ok(total > 0);
         }
-        if printed + no_snippet_paths.len() >= display_limit {
-            break;
+        SearchOutputMode::Grouped => {
+            let groups = group_hits(
+                &hits,
+                &root,
+                query,
+                store.as_ref(),
+                opts.read_concurrency,
+                opts.match_filter,
+            );
+            if opts.json {
+                let mut output = groups_json(&groups, query, total, limit);
+                if total == 0
+                    && let Some(no_results) = no_results()
+                {
+                    output["diagnostics"] = no_results.to_json();
+                }
+                set_generation(&mut output, generation);
+                println!("{}", serde_json::to_string_pretty(&output)?);
+            } else if total == 0 {
+                if let Some(no_results) = no_results() {
+                    eprint!("{}", no_results.explain(query));
+                }
+            } else {
+                print!("{}", render_groups(&groups, limit));
+            }
+            return Ok(total > 0);
         }
-    }
-    done.store(true, std::sync::atomic::Ordering::Relaxed);
-    drop(rx);
-
-    for path in &no_snippet_paths {
-        if printed >= display_limit {
-            break;
+        SearchOutputMode::Heatmap => {
+            let heatmap = heatmap(&hits, &root, query, store.as_ref(), opts.match_filter);
+            if opts.json {
+                let mut output = heatmap_json(&heatmap, query, limit);
+                set_generation(&mut output, generation);
+                println!("{}", serde_json::to_string_pretty(&output)?);
+            } else if heatmap.files.is_empty() {
+                if let Some(no_results) = no_results() {
+                    eprint!("{}", no_results.explain(query));
+                }
+            } else {
+                print!("{}", render_heatmap(&heatmap, limit));
+            }
+            return Ok(!heatmap.files.is_empty());
         }
-        println!("{}", clean_display_path(path));
-        printed += 1;
+        SearchOutputMode::Text | SearchOutputMode::Porcelain | SearchOutputMode::Vimgrep => {}
     }
 
-    if total > display_limit {
-        eprintln!("... and {} more (use -l 0 for all)", total - display_limit);
+    let printed = print_matches(
+        &opts,
+        output_mode,
+        &hits,
+        query,
+        store.as_ref(),
+        display_limit,
+        "",
+    );
+    if printed == 0
+        && output_mode.notes()
+        && let Some(no_results) = no_results()
+    {
+        eprint!("{}", no_results.explain(query));
     }
 
-    Ok(())
+    Ok(printed > 0)
 }
 
-fn print_json_results(
-    hits: &[source_fast_core::SearchHit],
-    query: &str,
-    limit: usize,
-) -> Result<(), Box<dyn std::error::Error>> {
-    use serde_json::{Value, json};
+/// `sf search --db-only`: search the index at `db_path` on its own, such as
+/// a copy exported by CI, with no root and no daemon. Paths are printed as
+/// stored and nothing outside the index is read unless `snippets` is set;
+/// hits are then verified and printed as usual. Returns whether anything
+/// matched.
+pub fn run_search_db_only(
+    opts: SearchOpts,
+    db_path: &Path,
+    snippets: bool,
+) -> Result<bool, Box<dyn std::error::Error>> {
+    let output_mode = SearchOutputMode::from_opts(&opts);
+    if !db_path.exists() {
+        return Err(format!("no index at {}", db_path.display()).into());
+    }
+    let query = opts.query.as_str();
+    let file_regex = build_file_filter(&opts.file_regex, &opts.ext, &opts.glob)?;
+    info!(db = %db_path.display(), query = %query, snippets, "db-only search starting");
+
+    let mut hits = Vec::new();
+    let mut generation = 0;
+    for lookup in lookup_queries(query, opts.match_filter) {
+        let outcome = search_database_file_within(db_path, &lookup, file_regex.as_ref(), None)?;
+        hits.extend(outcome.hits);
+        generation = generation.max(outcome.generation);
+    }
+    hits.sort_by(|a, b| a.path.cmp(&b.path));
+    hits.dedup_by(|a, b| a.path == b.path);
 
-    let mut results = Vec::new();
-    for (i, hit) in hits.iter().enumerate() {
-        if i >= limit {
-            break;
+    // The index's own content copies come first; files missing from it are
+    // read from disk, which is why this needs `--snippets`.
+    let store = if snippets {
+        ContentStore::open(db_path).ok()
+    } else {
+        None
+    };
+    if snippets && opts.verifies_up_front(output_mode) {
+        hits = verify_hits_limited(
+            hits,
+            query,
+            store.as_ref(),
+            opts.read_concurrency,
+            opts.match_filter,
+            output_mode.verify_limit(opts.limit),
+        );
+    }
+
+    let total = hits.len();
+    let display_limit = if opts.limit > 0 { opts.limit } else { total };
+    match output_mode {
+        SearchOutputMode::Quiet => return Ok(total > 0),
+        SearchOutputMode::Count => {
+            println!("{total}");
+            return Ok(total > 0);
         }
-        let path = PathBuf::from(&hit.path);
-        let display_path = clean_display_path(&hit.path).to_string();
-        let snippets = extract_snippets(&path, query).unwrap_or_default();
-        let mut entry = json!({
-            "path": display_path,
-            "file_id": hit.file_id,
-        });
-        if let Some(snippet) = snippets.first() {
-            entry["line"] = Value::from(snippet.line_number);
-            entry["snippet"] = Value::from(
-                snippet
-                    .lines
-                    .iter()
-                    .map(|(n, l)| json!({"line": n, "text": l}))
-                    .collect::<Vec<_>>(),
+        SearchOutputMode::Json => {
+            let mut output = if snippets {
+                search_results_json(
+                    &hits,
+                    query,
+                    display_limit,
+                    store.as_ref(),
+                    opts.read_concurrency,
+                    opts.match_filter,
+                )
+            } else {
+                serde_json::json!({
+                    "query": query,
+                    "total": total,
+                    "results": hits
+                        .iter()
+                        .take(display_limit)
+                        .map(|hit| serde_json::json!({"path": hit.path, "file_id": hit.file_id}))
+                        .collect::<Vec<_>>(),
+                })
+            };
+            set_generation(&mut output, generation);
+            println!("{}", serde_json::to_string_pretty(&output)?);
+            return Ok(total > 0);
+        }
+        SearchOutputMode::Text | SearchOutputMode::Porcelain | SearchOutputMode::Vimgrep
+            if snippets =>
+        {
+            let printed = print_matches(
+                &opts,
+                output_mode,
+                &hits,
+                query,
+                store.as_ref(),
+                display_limit,
+                "",
             );
+            return Ok(printed > 0);
         }
-        entry["snippets"] = Value::from(
-            snippets
-                .iter()
-                .map(|snippet| {
-                    json!({
-                        "line": snippet.line_number,
-                        "lines": snippet
-                            .lines
-                            .iter()
-                            .map(|(n, l)| json!({"line": n, "text": l}))
-                            .collect::<Vec<_>>()
-                    })
-                })
-                .collect::<Vec<_>>(),
-        );
-        results.push(entry);
+        _ => {}
     }
-    let output = json!({
-        "query": query,
-        "total": hits.len(),
-        "results": results,
-    });
-    println!("{}", serde_json::to_string_pretty(&output)?);
-    Ok(())
+
+    for hit in hits.iter().take(display_limit) {
+        print_path_entry(&hit.path, opts.null);
+    }
+    if total > display_limit && output_mode.notes() {
+        eprintln!("... and {} more (use -l 0 for all)", total - display_limit);
+    }
+    Ok(total > 0)
 }
 
-pub async fn run_file_search_with_daemon(
-    root: Option<PathBuf>,
-    db: Option<PathBuf>,
-    pattern: String,
-    wait: bool,
-) -> Result<(), Box<dyn std::error::Error>> {
-    let command_started = Instant::now();
-    let root = resolve_root(root);
-    let db_path = db.unwrap_or_else(|| default_db_path(&root));
+/// `sf search --explain`: report how the index plans `opts.query` and how
+/// many files each stage leaves, instead of the matches themselves. Reads
+/// the index directly, without the daemon. Returns whether anything matched.
+pub async fn run_search_explain(opts: SearchOpts) -> Result<bool, Box<dyn std::error::Error>> {
+    let output_mode = SearchOutputMode::from_opts(&opts);
+    let root = resolve_root(opts.root.clone());
+    let db_path = opts.db.clone().unwrap_or_else(|| default_db_path(&root));
+    let query = opts.query.as_str();
+    let file_regex = build_file_filter(&opts.file_regex, &opts.ext, &opts.glob)?;
+
+    info!(root = %root.display(), db = %db_path.display(), query = %query, "search explain requested");
+    if !prepare_search(&root, &db_path, opts.wait, output_mode)? {
+        return Ok(false);
+    }
+
+    let plan = explain_database_file(&db_path, query, file_regex.as_ref())?;
+    let mut hits = plan.hits.clone();
+    hits.retain(|hit| path_is_within_root(&hit.path, &root));
+    let under_root = hits.len();
+    let verified = if opts.verify {
+        let store = open_content_store(&root, &db_path);
+        Some(verify_hits_filtered(hits, query, store.as_ref(), opts.match_filter).len())
+    } else {
+        None
+    };
+    let matched = verified.unwrap_or(under_root) > 0;
+
+    if opts.json {
+        let mut output = serde_json::to_value(&plan)?;
+        output["query"] = query.into();
+        output["file_regex"] = file_regex.as_ref().map(Regex::as_str).into();
+        output["under_root"] = under_root.into();
+        output["verified"] = verified.into();
+        println!("{}", serde_json::to_string_pretty(&output)?);
+        return Ok(matched);
+    }
+
+    println!("Query:        {query:?} ({} trigrams)", plan.steps.len());
+    println!(
+        "Index:        {} files, generation {}",
+        plan.files_indexed, plan.generation
+    );
+    if plan.steps.is_empty() {
+        println!("Plan:         the query is too short for any trigram; search scans files or");
+        println!("              matches paths instead");
+        return Ok(matched);
+    }
+    println!("Plan:         most selective trigram first");
+    println!(
+        "  {:<10}  {:>10}  {:>10}  ACTION",
+        "TRIGRAM", "POSTINGS", "CANDIDATES"
+    );
+    for step in &plan.steps {
+        let action = match step.action {
+            PlanAction::Intersected => "intersected",
+            PlanAction::CheckedInContent => "stop trigram, checked in content",
+            PlanAction::Skipped => "skipped, no candidates left",
+        };
+        println!(
+            "  {:<10}  {:>10}  {:>10}  {action}",
+            format!("{:?}", step.trigram),
+            step.postings,
+            step.candidates
+        );
+    }
+    println!("Candidates:   {}", plan.candidates);
+    match &file_regex {
+        Some(re) => println!(
+            "File filter:  {} of {} match {}",
+            plan.path_matches,
+            plan.candidates,
+            re.as_str()
+        ),
+        None => println!("File filter:  none"),
+    }
+    println!(
+        "Content:      {} left after stop trigram checks and duplicate paths",
+        plan.hits.len()
+    );
+    println!("Under root:   {under_root}");
+    match verified {
+        Some(verified) => println!("Verified:     {verified} contain the query verbatim"),
+        None => println!("Verified:     skipped (--no-verify)"),
+    }
+    Ok(matched)
+}
+
+/// `sf search --stdin`: look up every query of `queries` in one request to
+/// the daemon (or one read of the index), then print the results query by
+/// query. Returns whether any query matched.
+pub async fn run_batch_search_with_daemon(
+    opts: SearchOpts,
+    queries: Vec<String>,
+) -> Result<bool, Box<dyn std::error::Error>> {
+    let output_mode = SearchOutputMode::from_opts(&opts);
+    let root = resolve_root(opts.root.clone());
+    apply_relative_paths(&root, opts.relative);
+    let db_path = opts.db.clone().unwrap_or_else(|| default_db_path(&root));
+    let file_regex = build_file_filter(&opts.file_regex, &opts.ext, &opts.glob)?;
 
-    let first_time = !db_path.exists();
     info!(
         root = %root.display(),
         db = %db_path.display(),
-        pattern = %pattern,
-        wait,
-        first_time,
-        "search-file command starting"
+        queries = queries.len(),
+        file_regex = ?file_regex.as_ref().map(|re| re.as_str()),
+        wait = opts.wait,
+        "batch search command starting"
+    );
+    if queries.is_empty() || !prepare_search(&root, &db_path, opts.wait, output_mode)? {
+        return Ok(false);
+    }
+
+    let lookups: Vec<Vec<String>> = queries
+        .iter()
+        .map(|query| lookup_queries(query, opts.match_filter))
+        .collect();
+    let flat_lookups = lookups.concat();
+    let rpc_hits = rpc::search_batch(&db_path, &flat_lookups, file_regex.as_ref());
+    debug!(
+        via_daemon = rpc_hits.is_some(),
+        "batch search command trigram lookup"
     );
+    let flat_batch = match rpc_hits {
+        Some(batch) => batch,
+        None => search_database_file_batch(&db_path, &flat_lookups, file_regex.as_ref())?,
+    };
+    // Merge the outcomes of each query's lookups back together.
+    let mut flat_batch = flat_batch.into_iter();
+    let batch: Vec<(Vec<SearchHit>, u64)> = lookups
+        .iter()
+        .map(|spellings| {
+            let outcomes: Vec<SearchOutcome> = flat_batch.by_ref().take(spellings.len()).collect();
+            let generation = outcomes
+                .iter()
+                .map(|outcome| outcome.generation)
+                .max()
+                .unwrap_or_default();
+            let hits = outcomes.into_iter().flat_map(|outcome| outcome.hits);
+            (hits.collect(), generation)
+        })
+        .collect();
+
+    let store = open_content_store(&root, &db_path);
+    let mut matched_any = false;
+    let mut json_results = Vec::new();
+    for (query, (mut hits, generation)) in queries.iter().zip(batch) {
+        let mut scanned = false;
+        if hits.is_empty()
+            && let Ok(Some(ShortQuery::Scan(files))) =
+                short_query_in_database(&db_path, query, file_regex.as_ref())
+        {
+            hits = files;
+            scanned = true;
+        }
+        hits.retain(|hit| path_is_within_root(&hit.path, &root));
+        hits.sort_by(|a, b| a.path.cmp(&b.path));
+        hits.dedup_by(|a, b| a.path == b.path);
+        if scanned || opts.verifies_up_front(output_mode) {
+            hits = verify_hits_limited(
+                hits,
+                query,
+                store.as_ref(),
+                opts.read_concurrency,
+                opts.match_filter,
+                output_mode.verify_limit(opts.limit),
+            );
+        }
+
+        let total = hits.len();
+        let display_limit = if opts.limit > 0 { opts.limit } else { total };
+        let matched = match output_mode {
+            SearchOutputMode::Quiet => total > 0,
+            SearchOutputMode::Count => {
+                println!("{query}\t{total}");
+                total > 0
+            }
+            SearchOutputMode::FilesOnly => {
+                for hit in hits.iter().take(display_limit) {
+                    print_path_entry(&format!("{query}\t{}", display_path(&hit.path)), opts.null);
+                }
+                total > 0
+            }
+            SearchOutputMode::FilesWithoutMatch => {
+                let unmatched = files_without_match(&db_path, &root, file_regex.as_ref(), &hits)?;
+                let shown = if opts.limit > 0 {
+                    opts.limit
+                } else {
+                    unmatched.len()
+                };
+                for path in unmatched.iter().take(shown) {
+                    print_path_entry(&format!("{query}\t{}", display_path(path)), opts.null);
+                }
+                !unmatched.is_empty()
+            }
+            SearchOutputMode::Json => {
+                let mut output = search_results_json(
+                    &hits,
+                    query,
+                    display_limit,
+                    store.as_ref(),
+                    opts.read_concurrency,
+                    opts.match_filter,
+                );
+                set_generation(&mut output, generation);
+                json_results.push(output);
+                total > 0
+            }
+            SearchOutputMode::Text => {
+                println!("\x1b[1m{query}\x1b[0m");
+                let printed = print_matches(
+                    &opts,
+                    output_mode,
+                    &hits,
+                    query,
+                    store.as_ref(),
+                    display_limit,
+                    "",
+                );
+                println!();
+                printed > 0
+            }
+            SearchOutputMode::Porcelain | SearchOutputMode::Vimgrep => {
+                let prefix = format!("{query}\t");
+                print_matches(
+                    &opts,
+                    output_mode,
+                    &hits,
+                    query,
+                    store.as_ref(),
+                    display_limit,
+                    &prefix,
+                ) > 0
+            }
+            SearchOutputMode::Grouped => {
+                let groups = group_hits(
+                    &hits,
+                    &root,
+                    query,
+                    store.as_ref(),
+                    opts.read_concurrency,
+                    opts.match_filter,
+                );
+                if opts.json {
+                    let mut output = groups_json(&groups, query, total, opts.limit);
+                    set_generation(&mut output, generation);
+                    json_results.push(output);
+                } else {
+                    println!("\x1b[1m{query}\x1b[0m");
+                    print!("{}", render_groups(&groups, opts.limit));
+                    println!();
+                }
+                total > 0
+            }
+            SearchOutputMode::Heatmap => {
+                let heatmap = heatmap(&hits, &root, query, store.as_ref(), opts.match_filter);
+                if opts.json {
+                    let mut output = heatmap_json(&heatmap, query, opts.limit);
+                    set_generation(&mut output, generation);
+                    json_results.push(output);
+                } else {
+                    println!("\x1b[1m{query}\x1b[0m");
+                    print!("{}", render_heatmap(&heatmap, opts.limit));
+                    println!();
+                }
+                !heatmap.files.is_empty()
+            }
+        };
+        matched_any |= matched;
+    }
+    if output_mode == SearchOutputMode::Json
+        || (matches!(
+            output_mode,
+            SearchOutputMode::Grouped | SearchOutputMode::Heatmap
+        ) && opts.json)
+    {
+        println!("{}", serde_json::to_string_pretty(&json_results)?);
+    }
+
+    Ok(matched_any)
+}
+
+/// What the index is asked for `query`: the query itself or, when it is
+/// searched as an identifier, each of its spellings.
+fn lookup_queries(query: &str, match_filter: MatchFilter) -> Vec<String> {
+    if match_filter.identifier {
+        identifier_spellings(query)
+    } else {
+        vec![query.to_string()]
+    }
+}
+
+/// Make sure a daemon (or MCP server) keeps the index of `root` warm and,
+/// with `wait`, that it is complete. `false` when there is no index to
+/// search yet.
+fn prepare_search(
+    root: &Path,
+    db_path: &Path,
+    wait: bool,
+    output_mode: SearchOutputMode,
+) -> Result<bool, Box<dyn std::error::Error>> {
+    let first_time = !db_path.exists();
     let ensure_started = Instant::now();
-    let was_running = daemon::ensure_daemon(&root, &db_path)?;
+    let was_running = daemon::ensure_daemon(root, db_path)?;
     info!(
         root = %root.display(),
         db = %db_path.display(),
         was_running,
         elapsed_ms = ensure_started.elapsed().as_millis() as u64,
-        "ensure_daemon finished for search-file command"
+        "ensure_daemon finished for search command"
     );
 
-    if first_time {
+    if first_time && output_mode.notes() {
         eprintln!(
             "Starting index for the first time. Results will be partial until indexing completes."
         );
@@ -1108,500 +1847,2509 @@ pub async fn run_file_search_with_daemon(
 
     if !was_running {
         let daemon_wait_started = Instant::now();
-        let confirmed = daemon::wait_for_daemon(&db_path, Duration::from_secs(3));
+        let confirmed = daemon::wait_for_daemon(db_path, Duration::from_secs(3));
         info!(
             db = %db_path.display(),
             confirmed,
             elapsed_ms = daemon_wait_started.elapsed().as_millis() as u64,
-            "daemon readiness wait finished for search-file command"
+            "daemon readiness wait finished for search command"
         );
         if !confirmed {
             warn!("Daemon did not confirm in 3 s, proceeding with search anyway");
         }
     }
 
+    // If --wait, block until index is complete.
     if wait {
         let index_wait_started = Instant::now();
-        let complete = daemon::wait_for_index_complete(&db_path, Duration::from_secs(120));
+        let complete = daemon::wait_for_index_complete(db_path, Duration::from_secs(120));
         info!(
             db = %db_path.display(),
             complete,
             elapsed_ms = index_wait_started.elapsed().as_millis() as u64,
-            "index completion wait finished for search-file command"
+            "index completion wait finished for search command"
         );
-        if !complete {
+        if !complete && output_mode.notes() {
             eprintln!("Timed out waiting for index to complete (120 s).");
         }
     }
 
     if !db_path.exists() {
+        // DB hasn't been created yet (daemon just started). Nothing to search.
         info!(
             db = %db_path.display(),
-            elapsed_ms = command_started.elapsed().as_millis() as u64,
-            "search-file command finished before database directory was created"
+            "search command finished before database directory was created"
         );
-        return Ok(());
+        return Ok(false);
     }
 
-    let mut hits = match search_files_in_database(&db_path, &pattern) {
-        Ok(h) => h,
-        Err(err) => {
-            error!(db = %db_path.display(), pattern = %pattern, error = ?err, "search-file command failed");
-            std::process::exit(1);
+    // Check completeness for the disclaimer.
+    if let Ok(Some(status)) = read_meta_readonly(db_path, daemon::meta_keys::INDEX_STATUS) {
+        debug!(db = %db_path.display(), index_status = %status, "search command observed index status");
+        if status == daemon::index_status::PAUSED && output_mode.notes() {
+            eprintln!("Note: index build is paused. Results may be incomplete.");
+        } else if status != daemon::index_status::COMPLETE && output_mode.notes() {
+            eprintln!("Note: index is still building. Results may be incomplete.");
+        } else if was_running
+            && output_mode.notes()
+            && let Some(warning) = index_drift_warning(root, db_path)
+        {
+            eprint!("{warning}");
         }
-    };
-    hits.retain(|hit| path_is_within_root(&hit.path, &root));
-
-    info!(
-        db = %db_path.display(),
-        pattern = %pattern,
-        hits = hits.len(),
-        elapsed_ms = command_started.elapsed().as_millis() as u64,
-        "search-file command completed"
-    );
+    }
+    Ok(true)
+}
 
-    for hit in hits {
-        println!("{}", clean_display_path(&hit.path));
+/// [`head_drift_warning`] for the index at `db_path`, unless a healthy
+/// watcher keeps it current.
+fn index_drift_warning(root: &Path, db_path: &Path) -> Option<String> {
+    let leader = read_leader_readonly(db_path)
+        .ok()
+        .flatten()
+        .map(|(holder, _)| holder);
+    let health = read_meta_readonly(db_path, daemon::meta_keys::WATCHER_HEALTH)
+        .ok()
+        .flatten();
+    if daemon::watcher_keeps_current(leader.as_deref(), health.as_deref()) {
+        return None;
     }
+    let git_head = read_meta_readonly(db_path, "git_head").ok().flatten()?;
+    let git = GitLocation::from_meta(|key| read_meta_readonly(db_path, key).ok().flatten());
+    head_drift_warning(root, &git.or_env(), &git_head)
+}
 
-    Ok(())
+/// A warning that the index, last synced to `git_head`, lags HEAD of the
+/// repository of `root`, if it does. Only worth giving when no healthy
+/// watcher runs: commits alone change no files, and checkouts reach a
+/// watcher as file events.
+pub(crate) fn head_drift_warning(root: &Path, git: &GitLocation, git_head: &str) -> Option<String> {
+    let hint = "run `sf index watch` to catch up";
+    Some(match head_drift(root, git, git_head)? {
+        HeadDrift::Behind(1) => format!("Warning: index is 1 commit behind HEAD; {hint}.\n"),
+        HeadDrift::Behind(n) => format!("Warning: index is {n} commits behind HEAD; {hint}.\n"),
+        HeadDrift::Diverged => {
+            format!("Warning: index was built from a commit HEAD does not descend from; {hint}.\n")
+        }
+    })
 }
 
-// ---------------------------------------------------------------------------
-// Management commands
-// ---------------------------------------------------------------------------
+/// Stream the snippets of `hits` in the text, porcelain or vimgrep format,
+/// stopping after `display_limit` files; porcelain and vimgrep lines start
+/// with `prefix`.
+/// Returns the number of files printed.
+fn print_matches(
+    opts: &SearchOpts,
+    output_mode: SearchOutputMode,
+    hits: &[SearchHit],
+    query: &str,
+    store: Option<&ContentStore>,
+    display_limit: usize,
+    prefix: &str,
+) -> usize {
+    let mut printed = 0usize;
+    let mut rejected = 0usize;
+    let visit = |hit: &SearchHit, snippets: io::Result<Vec<Snippet>>| {
+        let snippets = snippets.unwrap_or_default();
+        let line_format = matches!(
+            output_mode,
+            SearchOutputMode::Porcelain | SearchOutputMode::Vimgrep
+        );
+        if line_format && !snippets.is_empty() {
+            // Each snippet is centred on one matching line; the context
+            // lines around it are not printed.
+            let path_str = snippets[0].path.display().to_string();
+            let matching: BTreeMap<_, _> = snippets
+                .iter()
+                .filter_map(|snippet| {
+                    snippet
+                        .lines
+                        .iter()
+                        .find(|(line_no, _)| *line_no == snippet.line_number)
+                        .map(|(line_no, line)| (*line_no, (snippet.column, line)))
+                })
+                .collect();
+            for (line_no, (column, line)) in matching {
+                let path = display_path(&path_str);
+                if output_mode == SearchOutputMode::Vimgrep {
+                    // Like `rg --vimgrep`, one line per occurrence.
+                    // Columns past the first are only known for
+                    // verbatim matches.
+                    let columns: Vec<usize> = if opts.match_filter.is_verbatim() {
+                        line.match_indices(query)
+                            .map(|(offset, _)| offset + 1)
+                            .collect()
+                    } else {
+                        vec![column]
+                    };
+                    for column in columns {
+                        println!("{prefix}{path}:{line_no}:{column}:{line}");
+                    }
+                } else if opts.column {
+                    println!("{prefix}{path}:{line_no}:{column}:{line}");
+                } else {
+                    println!("{prefix}{path}:{line_no}:{line}");
+                }
+            }
+        } else if !snippets.is_empty() {
+            // One snippet per matching line, so their count tells a passing
+            // mention from a hotspot.
+            let noun = if snippets.len() == 1 {
+                "match"
+            } else {
+                "matches"
+            };
+            let path_str = snippets[0].path.display().to_string();
+            println!(
+                "\x1b[1m{}\x1b[0m ({} {noun})",
+                display_path(&path_str),
+                snippets.len()
+            );
+            for snippet in snippets {
+                let path_str = snippet.path.display().to_string();
+                let shown_path = display_path(&path_str);
+                println!(
+                    "\x1b[35m{shown_path}\x1b[0m:{}:{}",
+                    snippet.line_number, snippet.column
+                );
+                for (line_no, line) in &snippet.lines {
+                    let truncated = truncate_line(line, 200);
+                    if *line_no == snippet.line_number || line.contains(query) {
+                        println!("\x1b[32m{line_no}\x1b[0m:{truncated}");
+                    } else {
+                        println!("\x1b[2m{line_no}\x1b[0m:{truncated}");
+                    }
+                }
+                println!();
+            }
+        } else if opts.verify {
+            rejected += 1;
+            return ControlFlow::Continue(());
+        } else {
+            println!("{prefix}{}", display_path(&hit.path));
+        }
+        printed += 1;
+        if printed >= display_limit {
+            ControlFlow::Break(())
+        } else {
+            ControlFlow::Continue(())
+        }
+    };
+    // On a terminal, each file is printed as soon as it has been read, so
+    // broad queries show their first matches at once. Piped output and the
+    // line formats keep path order.
+    if output_mode == SearchOutputMode::Text && io::stdout().is_terminal() {
+        stream_snippets_unordered(
+            hits,
+            query,
+            store,
+            opts.read_concurrency,
+            opts.match_filter,
+            visit,
+        );
+    } else {
+        stream_snippets_filtered(
+            hits,
+            query,
+            store,
+            opts.read_concurrency,
+            opts.match_filter,
+            visit,
+        );
+    }
 
-fn format_eta(seconds: u64) -> String {
-    if seconds < 60 {
-        return format!("{seconds}s");
+    let remaining = hits.len() - printed - rejected;
+    if remaining > 0 && output_mode.notes() {
+        eprintln!("... and {remaining} more (use -l 0 for all)");
     }
+    printed
+}
 
-    let minutes = seconds / 60;
-    let secs = seconds % 60;
-    if minutes < 60 {
-        return format!("{minutes}m {secs}s");
+/// The indexed files under `root` that pass `file_regex` but are not among
+/// the matching `hits`, sorted by path: `sf search --files-without-match`.
+fn files_without_match(
+    db_path: &Path,
+    root: &Path,
+    file_regex: Option<&Regex>,
+    hits: &[SearchHit],
+) -> Result<Vec<String>, IndexError> {
+    let matched: std::collections::HashSet<&str> =
+        hits.iter().map(|hit| hit.path.as_str()).collect();
+    Ok(list_files_in_database(db_path, file_regex, None)?
+        .into_iter()
+        .filter(|file| {
+            path_is_within_root(&file.path, root) && !matched.contains(file.path.as_str())
+        })
+        .map(|file| file.path)
+        .collect())
+}
+
+/// Answer a query too short for the index, in an index too large to scan,
+/// with the files whose path contains it. Content results stay empty, so
+/// the search counts as matching nothing.
+fn print_path_fallback(
+    output_mode: SearchOutputMode,
+    query: &str,
+    files_indexed: u64,
+    paths: &[SearchHit],
+    limit: usize,
+    null: bool,
+) -> Result<bool, Box<dyn std::error::Error>> {
+    let display_limit = if limit > 0 { limit } else { paths.len() };
+    match output_mode {
+        SearchOutputMode::Quiet
+        | SearchOutputMode::Porcelain
+        | SearchOutputMode::Vimgrep
+        | SearchOutputMode::FilesWithoutMatch => {
+            return Ok(false);
+        }
+        SearchOutputMode::Count => println!("0"),
+        SearchOutputMode::Json => {
+            let mut output = search_results_json(&[], query, 0, None, 1, MatchFilter::default());
+            let index = SearchDiagnostics {
+                files_indexed,
+                query_too_short: true,
+                missing_trigrams: Vec::new(),
+            };
+            output["diagnostics"] = NoResults::new(index, 0, 0).to_json();
+            output["path_matches"] = paths
+                .iter()
+                .take(display_limit)
+                .map(|hit| serde_json::json!({ "path": display_path(&hit.path), "file_id": hit.file_id }))
+                .collect();
+            println!("{}", serde_json::to_string_pretty(&output)?);
+            return Ok(false);
+        }
+        SearchOutputMode::Text
+        | SearchOutputMode::FilesOnly
+        | SearchOutputMode::Grouped
+        | SearchOutputMode::Heatmap => {
+            for hit in paths.iter().take(display_limit) {
+                print_path_entry(&display_path(&hit.path), null);
+            }
+            if paths.len() > display_limit {
+                eprintln!(
+                    "... and {} more (use -l 0 for all)",
+                    paths.len() - display_limit
+                );
+            }
+        }
     }
+    eprint!("{}", path_fallback_note(query, files_indexed, paths.len()));
+    Ok(false)
+}
 
-    let hours = minutes / 60;
-    let mins = minutes % 60;
-    format!("{hours}h {mins}m")
+/// Why a short query was answered with `paths` file paths only.
+pub(crate) fn path_fallback_note(query: &str, files_indexed: u64, paths: usize) -> String {
+    let mut note = format!(
+        "{query:?} is shorter than 3 bytes, the minimum the index can answer, and the \
+         {files_indexed} indexed files are too many to read directly (limit \
+         {SHORT_QUERY_SCAN_LIMIT}).\n"
+    );
+    if paths == 0 {
+        note.push_str("No file path contains it either.\n");
+    } else {
+        note.push_str(&format!(
+            "Listed instead: {paths} files whose path contains it.\n"
+        ));
+    }
+    note
 }
 
-fn estimate_eta_seconds(progress: &IndexProgress) -> Option<u64> {
-    let started_at_ms = progress.started_at_ms?;
-    let elapsed_ms = std::time::SystemTime::now()
-        .duration_since(std::time::UNIX_EPOCH)
-        .ok()?
-        .as_millis()
-        .saturating_sub(started_at_ms as u128) as u64;
+/// Why a search came back empty: what the index holds for the query, and
+/// which hits the search itself dropped.
+pub(crate) struct NoResults {
+    index: SearchDiagnostics,
+    /// Hits outside the root or the path filter.
+    excluded: usize,
+    /// Hits whose file does not contain the query verbatim.
+    unverified: usize,
+}
 
-    let total_bytes = progress.total_bytes?;
-    if total_bytes > 0 && progress.processed_bytes > 0 && progress.processed_bytes < total_bytes {
-        let remaining_bytes = total_bytes.saturating_sub(progress.processed_bytes);
-        let eta_ms = elapsed_ms
-            .saturating_mul(remaining_bytes)
-            .checked_div(progress.processed_bytes)?;
-        return Some((eta_ms / 1000).max(1));
+impl NoResults {
+    /// `matched` hits came straight from the index; `candidates` of them
+    /// passed the root and path filters, and none survived verification.
+    pub(crate) fn new(index: SearchDiagnostics, matched: usize, candidates: usize) -> Self {
+        Self {
+            index,
+            excluded: matched.saturating_sub(candidates),
+            unverified: candidates,
+        }
     }
 
-    let total_files = progress.total_files?;
-    if total_files > 0 && progress.processed_files > 0 && progress.processed_files < total_files {
-        let remaining_files = total_files.saturating_sub(progress.processed_files);
-        let eta_ms = elapsed_ms
-            .saturating_mul(remaining_files as u64)
-            .checked_div(progress.processed_files as u64)?;
-        return Some((eta_ms / 1000).max(1));
+    pub(crate) fn to_json(&self) -> serde_json::Value {
+        serde_json::json!({
+            "files_indexed": self.index.files_indexed,
+            "query_too_short": self.index.query_too_short,
+            "missing_trigrams": self.index.missing_trigrams,
+            "excluded_by_filter": self.excluded,
+            "unverified": self.unverified,
+        })
     }
 
-    None
-}
+    /// A short explanation, one reason per line.
+    pub(crate) fn explain(&self, query: &str) -> String {
+        let mut reasons = Vec::new();
+        if self.index.files_indexed == 0 {
+            reasons.push("The index is empty; it may still be building.".to_string());
+        }
+        if self.index.query_too_short && self.unverified > 0 {
+            reasons.push(format!(
+                "The query is shorter than 3 bytes, so all {} files in scope were read directly; \
+                 none contains it.",
+                self.unverified
+            ));
+        } else if self.index.query_too_short {
+            reasons.push(
+                "The query is shorter than 3 bytes, the minimum the index can answer.".to_string(),
+            );
+        } else if !self.index.missing_trigrams.is_empty() {
+            let missing: Vec<String> = self
+                .index
+                .missing_trigrams
+                .iter()
+                .map(|trigram| format!("{trigram:?}"))
+                .collect();
+            reasons.push(format!("No indexed file contains {}.", missing.join(", ")));
+        }
+        if self.excluded > 0 {
+            reasons.push(format!(
+                "{} matching files are outside the root or the path filter.",
+                self.excluded
+            ));
+        }
+        if self.unverified > 0 && !self.index.query_too_short {
+            reasons.push(format!(
+                "{} files contain every trigram of the query but not the query itself.",
+                self.unverified
+            ));
+        }
+        if reasons.is_empty() {
+            reasons.push(
+                "Every trigram of the query is indexed, but no file contains them all.".to_string(),
+            );
+        }
 
-fn format_remaining_lease(expires_at_ms: i64) -> Option<String> {
-    let now_ms = std::time::SystemTime::now()
-        .duration_since(std::time::UNIX_EPOCH)
-        .ok()?
-        .as_millis() as i64;
-    let remaining_ms = expires_at_ms.saturating_sub(now_ms);
-    if remaining_ms <= 0 {
-        return Some("expired".to_string());
+        let mut text = format!(
+            "No results for {query:?} ({} files indexed).\n",
+            self.index.files_indexed
+        );
+        for reason in reasons {
+            text.push_str(&reason);
+            text.push('\n');
+        }
+        text
     }
-    Some(format_eta((remaining_ms as u64).div_ceil(1000)))
 }
 
-pub async fn run_stop(
-    root: Option<PathBuf>,
-    db: Option<PathBuf>,
-) -> Result<(), Box<dyn std::error::Error>> {
+/// Diagnose an empty search of `db_path` whose root and path filters
+/// left `candidates` hits. `None` when the index cannot be read.
+pub(crate) fn diagnose_no_results(
+    db_path: &Path,
+    query: &str,
+    candidates: usize,
+) -> Option<NoResults> {
+    let index = diagnose_database_file(db_path, query).ok()?;
+    let matched = count_database_file(db_path, query, None).ok()?;
+    Some(NoResults::new(index, matched, candidates))
+}
+
+/// JSON document shared by `sf search --json` and the HTTP API.
+pub(crate) fn search_results_json(
+    hits: &[source_fast_core::SearchHit],
+    query: &str,
+    limit: usize,
+    store: Option<&ContentStore>,
+    read_concurrency: usize,
+    match_filter: MatchFilter,
+) -> serde_json::Value {
+    use serde_json::{Value, json};
+
+    let shown = &hits[..limit.min(hits.len())];
+    let mut results = Vec::with_capacity(shown.len());
+    stream_snippets_filtered(
+        shown,
+        query,
+        store,
+        read_concurrency,
+        match_filter,
+        |hit, snippets| {
+            let snippets = snippets.unwrap_or_default();
+            let mut entry = json!({
+                "path": display_path(&hit.path),
+                "file_id": hit.file_id,
+            });
+            if let Some(snippet) = snippets.first() {
+                entry["line"] = Value::from(snippet.line_number);
+                entry["column"] = Value::from(snippet.column);
+                entry["snippet"] = Value::from(
+                    snippet
+                        .lines
+                        .iter()
+                        .map(|(n, l)| json!({"line": n, "text": l}))
+                        .collect::<Vec<_>>(),
+                );
+            }
+            entry["snippets"] = Value::from(
+                snippets
+                    .iter()
+                    .map(|snippet| {
+                        json!({
+                            "line": snippet.line_number,
+                            "column": snippet.column,
+                            "lines": snippet
+                                .lines
+                                .iter()
+                                .map(|(n, l)| json!({"line": n, "text": l}))
+                                .collect::<Vec<_>>()
+                        })
+                    })
+                    .collect::<Vec<_>>(),
+            );
+            results.push(entry);
+            ControlFlow::Continue(())
+        },
+    );
+    json!({
+        "query": query,
+        "total": hits.len(),
+        "results": results,
+    })
+}
+
+/// Stamp a JSON search document with the index generation it was read
+/// at, so callers can tell when results they kept have gone stale. The
+/// older name `generation` is kept for existing consumers.
+pub(crate) fn set_generation(output: &mut serde_json::Value, generation: u64) {
+    output["index_generation"] = generation.into();
+    output["generation"] = generation.into();
+}
+
+/// Matching lines shown per group by `--group-by`.
+const GROUP_SAMPLES: usize = 2;
+
+/// How `sf search --group-by` and the `group_by` argument of
+/// `search_code` aggregate hits.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, clap::ValueEnum, Deserialize, JsonSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum GroupBy {
+    /// The top-level directory below the root.
+    Dir,
+}
+
+/// The hits of one top-level directory.
+pub(crate) struct HitGroup {
+    /// Directory relative to the root, or `.` for files directly in it.
+    pub dir: String,
+    pub files: usize,
+    /// Up to [`GROUP_SAMPLES`] matching lines as root-relative path, line
+    /// number and text.
+    pub samples: Vec<(String, usize, String)>,
+}
+
+/// Group `hits` by top-level directory below `root`, most files first and
+/// ties in path order. Each group reads its files only until it has
+/// [`GROUP_SAMPLES`] matching lines.
+pub(crate) fn group_hits(
+    hits: &[SearchHit],
+    root: &Path,
+    query: &str,
+    store: Option<&ContentStore>,
+    read_concurrency: usize,
+    match_filter: MatchFilter,
+) -> Vec<HitGroup> {
+    let root = root.display().to_string();
+    let root = Path::new(clean_display_path(&root));
+    let mut by_dir: BTreeMap<String, Vec<SearchHit>> = BTreeMap::new();
+    for hit in hits {
+        by_dir
+            .entry(top_level_dir(&hit.path, root))
+            .or_default()
+            .push(hit.clone());
+    }
+
+    let mut groups: Vec<HitGroup> = by_dir
+        .into_iter()
+        .map(|(dir, hits)| {
+            let mut samples: Vec<(String, usize, String)> = Vec::new();
+            stream_snippets_filtered(
+                &hits,
+                query,
+                store,
+                read_concurrency,
+                match_filter,
+                |hit, snippets| {
+                    let path = relative_display_path(&hit.path, root);
+                    let lines = snippets
+                        .unwrap_or_default()
+                        .into_iter()
+                        .filter_map(|snippet| {
+                            let line_number = snippet.line_number;
+                            snippet.lines.into_iter().find(|(n, _)| *n == line_number)
+                        });
+                    for (line_no, line) in lines {
+                        if samples.len() == GROUP_SAMPLES {
+                            break;
+                        }
+                        if !samples.iter().any(|(p, n, _)| *p == path && *n == line_no) {
+                            samples.push((path.clone(), line_no, line.trim().to_string()));
+                        }
+                    }
+                    if samples.len() == GROUP_SAMPLES {
+                        ControlFlow::Break(())
+                    } else {
+                        ControlFlow::Continue(())
+                    }
+                },
+            );
+            HitGroup {
+                dir,
+                files: hits.len(),
+                samples,
+            }
+        })
+        .collect();
+    groups.sort_by_key(|group| std::cmp::Reverse(group.files));
+    groups
+}
+
+/// First component of `path` below `root`, or `.` for a file directly in
+/// the root.
+fn top_level_dir(path: &str, root: &Path) -> String {
+    let path = Path::new(clean_display_path(path));
+    let relative = path.strip_prefix(root).unwrap_or(path);
+    let mut components = relative.components();
+    match (components.next(), components.next()) {
+        (Some(first), Some(_)) => first.as_os_str().to_string_lossy().into_owned(),
+        _ => ".".to_string(),
+    }
+}
+
+fn relative_display_path(path: &str, root: &Path) -> String {
+    let path = Path::new(clean_display_path(path));
+    path.strip_prefix(root)
+        .unwrap_or(path)
+        .display()
+        .to_string()
+}
+
+/// Text rendering of `groups`, at most `limit` of them (0 = all).
+pub(crate) fn render_groups(groups: &[HitGroup], limit: usize) -> String {
+    let shown = if limit > 0 {
+        limit.min(groups.len())
+    } else {
+        groups.len()
+    };
+    let mut text = String::new();
+    for group in &groups[..shown] {
+        let noun = if group.files == 1 { "file" } else { "files" };
+        text.push_str(&format!("{} ({} {noun})\n", group.dir, group.files));
+        for (path, line_no, line) in &group.samples {
+            text.push_str(&format!(
+                "  {path}:{line_no}: {}\n",
+                truncate_line(line, 200)
+            ));
+        }
+    }
+    if groups.len() > shown {
+        text.push_str(&format!(
+            "... and {} more directories\n",
+            groups.len() - shown
+        ));
+    }
+    text
+}
+
+/// JSON document of `sf search --group-by --json`.
+fn groups_json(groups: &[HitGroup], query: &str, total: usize, limit: usize) -> serde_json::Value {
+    use serde_json::json;
+
+    let shown = if limit > 0 { limit } else { groups.len() };
+    json!({
+        "query": query,
+        "total": total,
+        "groups": groups
+            .iter()
+            .take(shown)
+            .map(|group| {
+                json!({
+                    "dir": group.dir,
+                    "files": group.files,
+                    "samples": group
+                        .samples
+                        .iter()
+                        .map(|(path, line, text)| json!({"path": path, "line": line, "text": text}))
+                        .collect::<Vec<_>>(),
+                })
+            })
+            .collect::<Vec<_>>(),
+    })
+}
+
+/// Occurrence counts of `sf search --heatmap`.
+struct Heatmap {
+    /// Root-relative paths with their occurrences, most first.
+    files: Vec<(String, usize)>,
+    /// Every directory holding a matching file at any depth, with its
+    /// occurrences and matching files, most occurrences first.
+    dirs: Vec<(String, usize, usize)>,
+}
+
+/// Count every occurrence of `query` in `hits` and roll the counts up into
+/// the directories below `root`. Files without an occurrence are dropped.
+fn heatmap(
+    hits: &[SearchHit],
+    root: &Path,
+    query: &str,
+    store: Option<&ContentStore>,
+    match_filter: MatchFilter,
+) -> Heatmap {
+    let root = root.display().to_string();
+    let root = Path::new(clean_display_path(&root));
+    let counts = count_occurrences(hits, query, store, match_filter);
+
+    let mut files = Vec::new();
+    let mut dirs: BTreeMap<String, (usize, usize)> = BTreeMap::new();
+    for (hit, count) in hits.iter().zip(counts) {
+        if count == 0 {
+            continue;
+        }
+        let path = Path::new(clean_display_path(&hit.path));
+        let relative = path.strip_prefix(root).unwrap_or(path);
+        for dir in relative.ancestors().skip(1) {
+            if dir.as_os_str().is_empty() {
+                break;
+            }
+            let entry = dirs
+                .entry(dir.to_string_lossy().replace('\\', "/"))
+                .or_default();
+            entry.0 += count;
+            entry.1 += 1;
+        }
+        files.push((relative.to_string_lossy().replace('\\', "/"), count));
+    }
+    files.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+    let mut dirs: Vec<(String, usize, usize)> = dirs
+        .into_iter()
+        .map(|(dir, (matches, files))| (dir, matches, files))
+        .collect();
+    dirs.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+    Heatmap { files, dirs }
+}
+
+/// Width of the widest bar in `--heatmap` tables.
+const HEATMAP_BAR_WIDTH: usize = 20;
+
+fn heatmap_bar(count: usize, max: usize) -> String {
+    let width = (count * HEATMAP_BAR_WIDTH).div_ceil(max.max(1));
+    "█".repeat(width.max(1))
+}
+
+/// Text rendering of `heatmap`: directories, then files, at most `limit`
+/// rows each (0 = all).
+fn render_heatmap(heatmap: &Heatmap, limit: usize) -> String {
+    let rows = |len: usize| if limit > 0 { limit.min(len) } else { len };
+    let total: usize = heatmap.files.iter().map(|(_, count)| count).sum();
+    let mut text = String::new();
+    if !heatmap.dirs.is_empty() {
+        let max = heatmap.dirs[0].1;
+        text.push_str(&format!("{:>8} {:>6}  directory\n", "matches", "files"));
+        for (dir, matches, files) in &heatmap.dirs[..rows(heatmap.dirs.len())] {
+            text.push_str(&format!(
+                "{matches:>8} {files:>6}  {dir}/  {}\n",
+                heatmap_bar(*matches, max)
+            ));
+        }
+        if heatmap.dirs.len() > rows(heatmap.dirs.len()) {
+            text.push_str(&format!(
+                "... and {} more directories\n",
+                heatmap.dirs.len() - rows(heatmap.dirs.len())
+            ));
+        }
+        text.push('\n');
+    }
+    let max = heatmap.files.first().map_or(0, |(_, count)| *count);
+    text.push_str(&format!("{:>8}  file\n", "matches"));
+    for (path, count) in &heatmap.files[..rows(heatmap.files.len())] {
+        text.push_str(&format!(
+            "{count:>8}  {path}  {}\n",
+            heatmap_bar(*count, max)
+        ));
+    }
+    if heatmap.files.len() > rows(heatmap.files.len()) {
+        text.push_str(&format!(
+            "... and {} more files\n",
+            heatmap.files.len() - rows(heatmap.files.len())
+        ));
+    }
+    text.push_str(&format!(
+        "\n{total} matches in {} files\n",
+        heatmap.files.len()
+    ));
+    text
+}
+
+/// JSON document of `sf search --heatmap --json`.
+fn heatmap_json(heatmap: &Heatmap, query: &str, limit: usize) -> serde_json::Value {
+    use serde_json::json;
+
+    let rows = if limit > 0 { limit } else { usize::MAX };
+    json!({
+        "query": query,
+        "total_matches": heatmap.files.iter().map(|(_, count)| count).sum::<usize>(),
+        "total_files": heatmap.files.len(),
+        "dirs": heatmap
+            .dirs
+            .iter()
+            .take(rows)
+            .map(|(dir, matches, files)| json!({"dir": dir, "matches": matches, "files": files}))
+            .collect::<Vec<_>>(),
+        "files": heatmap
+            .files
+            .iter()
+            .take(rows)
+            .map(|(path, matches)| json!({"path": path, "matches": matches}))
+            .collect::<Vec<_>>(),
+    })
+}
+
+/// "Did you mean" paths offered when a file search matches nothing.
+const FILE_SUGGESTIONS: usize = 5;
+
+/// Indexed paths under `root` close to `pattern`, or that files matching it
+/// were renamed to; see [`suggest_files_in_database`]. Best effort: an
+/// unreadable index suggests nothing.
+pub(crate) fn file_suggestions(db_path: &Path, root: &Path, pattern: &str) -> Vec<FileSuggestion> {
+    match suggest_files_in_database(db_path, pattern, FILE_SUGGESTIONS) {
+        Ok(mut suggestions) => {
+            suggestions.retain(|suggestion| path_is_within_root(&suggestion.path, root));
+            suggestions
+        }
+        Err(err) => {
+            warn!(db = %db_path.display(), error = %err, "failed to compute file suggestions");
+            Vec::new()
+        }
+    }
+}
+
+pub async fn run_file_search_with_daemon(
+    root: Option<PathBuf>,
+    db: Option<PathBuf>,
+    pattern: String,
+    wait: bool,
+    relative: Option<bool>,
+    null: bool,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let command_started = Instant::now();
+    let root = resolve_root(root);
+    apply_relative_paths(&root, relative);
+    let db_path = db.unwrap_or_else(|| default_db_path(&root));
+
+    let first_time = !db_path.exists();
+    info!(
+        root = %root.display(),
+        db = %db_path.display(),
+        pattern = %pattern,
+        wait,
+        first_time,
+        "search-file command starting"
+    );
+    let ensure_started = Instant::now();
+    let was_running = daemon::ensure_daemon(&root, &db_path)?;
+    info!(
+        root = %root.display(),
+        db = %db_path.display(),
+        was_running,
+        elapsed_ms = ensure_started.elapsed().as_millis() as u64,
+        "ensure_daemon finished for search-file command"
+    );
+
+    if first_time {
+        eprintln!(
+            "Starting index for the first time. Results will be partial until indexing completes."
+        );
+    }
+
+    if !was_running {
+        let daemon_wait_started = Instant::now();
+        let confirmed = daemon::wait_for_daemon(&db_path, Duration::from_secs(3));
+        info!(
+            db = %db_path.display(),
+            confirmed,
+            elapsed_ms = daemon_wait_started.elapsed().as_millis() as u64,
+            "daemon readiness wait finished for search-file command"
+        );
+        if !confirmed {
+            warn!("Daemon did not confirm in 3 s, proceeding with search anyway");
+        }
+    }
+
+    if wait {
+        let index_wait_started = Instant::now();
+        let complete = daemon::wait_for_index_complete(&db_path, Duration::from_secs(120));
+        info!(
+            db = %db_path.display(),
+            complete,
+            elapsed_ms = index_wait_started.elapsed().as_millis() as u64,
+            "index completion wait finished for search-file command"
+        );
+        if !complete {
+            eprintln!("Timed out waiting for index to complete (120 s).");
+        }
+    }
+
+    if !db_path.exists() {
+        info!(
+            db = %db_path.display(),
+            elapsed_ms = command_started.elapsed().as_millis() as u64,
+            "search-file command finished before database directory was created"
+        );
+        return Ok(());
+    }
+
+    let mut hits = match search_files_in_database(&db_path, &pattern) {
+        Ok(h) => h,
+        Err(err) => {
+            error!(db = %db_path.display(), pattern = %pattern, error = ?err, "search-file command failed");
+            std::process::exit(1);
+        }
+    };
+    hits.retain(|hit| path_is_within_root(&hit.path, &root));
+
+    info!(
+        db = %db_path.display(),
+        pattern = %pattern,
+        hits = hits.len(),
+        elapsed_ms = command_started.elapsed().as_millis() as u64,
+        "search-file command completed"
+    );
+
+    if hits.is_empty() {
+        let suggestions = file_suggestions(&db_path, &root, &pattern);
+        if !suggestions.is_empty() {
+            eprintln!("No files match `{pattern}`. Did you mean:");
+            for suggestion in suggestions {
+                match suggestion.renamed_from {
+                    Some(from) => eprintln!(
+                        "  {} (renamed from {})",
+                        display_path(&suggestion.path),
+                        display_path(&from)
+                    ),
+                    None => eprintln!("  {}", display_path(&suggestion.path)),
+                }
+            }
+        }
+    }
+    for hit in hits {
+        print_path_entry(&display_path(&hit.path), null);
+    }
+
+    Ok(())
+}
+
+// ---------------------------------------------------------------------------
+// Management commands
+// ---------------------------------------------------------------------------
+
+fn format_eta(seconds: u64) -> String {
+    if seconds < 60 {
+        return format!("{seconds}s");
+    }
+
+    let minutes = seconds / 60;
+    let secs = seconds % 60;
+    if minutes < 60 {
+        return format!("{minutes}m {secs}s");
+    }
+
+    let hours = minutes / 60;
+    let mins = minutes % 60;
+    format!("{hours}h {mins}m")
+}
+
+fn estimate_eta_seconds(progress: &IndexProgress) -> Option<u64> {
+    let started_at_ms = progress.started_at_ms?;
+    let elapsed_ms = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .ok()?
+        .as_millis()
+        .saturating_sub(started_at_ms as u128) as u64;
+
+    let total_bytes = progress.total_bytes?;
+    if total_bytes > 0 && progress.processed_bytes > 0 && progress.processed_bytes < total_bytes {
+        let remaining_bytes = total_bytes.saturating_sub(progress.processed_bytes);
+        let eta_ms = elapsed_ms
+            .saturating_mul(remaining_bytes)
+            .checked_div(progress.processed_bytes)?;
+        return Some((eta_ms / 1000).max(1));
+    }
+
+    let total_files = progress.total_files?;
+    if total_files > 0 && progress.processed_files > 0 && progress.processed_files < total_files {
+        let remaining_files = total_files.saturating_sub(progress.processed_files);
+        let eta_ms = elapsed_ms
+            .saturating_mul(remaining_files as u64)
+            .checked_div(progress.processed_files as u64)?;
+        return Some((eta_ms / 1000).max(1));
+    }
+
+    None
+}
+
+/// One-line watcher summary for `sf status` and the MCP `index_status` tool.
+pub(crate) fn format_watcher_health(health: &WatcherHealth) -> String {
+    let mut text = health.state.as_str().to_string();
+    let mut details = Vec::new();
+    if health.state == WatcherState::Watching && health.backend.as_deref() == Some("poll") {
+        details.push("poll".to_string());
+        if let Some(err) = health.last_error.as_deref() {
+            details.push(format!("native watcher failed: {err}"));
+        }
+    }
+    if health.state == WatcherState::Polling {
+        if let Some(err) = health.last_error.as_deref() {
+            details.push(format!("watcher failed: {err}"));
+        }
+        if let Some(retry_at_ms) = health.next_retry_ms {
+            let remaining_ms = retry_at_ms.saturating_sub(now_millis().max(0) as u64);
+            details.push(format!(
+                "retry in {}",
+                format_eta(remaining_ms.div_ceil(1000))
+            ));
+        }
+    }
+    if health.restarts > 0 {
+        details.push(format!("{} restarts", health.restarts));
+    }
+    if !details.is_empty() {
+        text.push_str(&format!(" ({})", details.join("; ")));
+    }
+    text
+}
+
+fn format_remaining_lease(expires_at_ms: i64) -> Option<String> {
+    let now_ms = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .ok()?
+        .as_millis() as i64;
+    let remaining_ms = expires_at_ms.saturating_sub(now_ms);
+    if remaining_ms <= 0 {
+        return Some("expired".to_string());
+    }
+    Some(format_eta((remaining_ms as u64).div_ceil(1000)))
+}
+
+pub async fn run_start(
+    root: Option<PathBuf>,
+    db: Option<PathBuf>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let root = resolve_root(root);
+    let db_path = db.unwrap_or_else(|| default_db_path(&root));
+    info!(root = %root.display(), db = %db_path.display(), "start command requested");
+
+    if daemon::ensure_daemon(&root, &db_path)? {
+        println!("Daemon already running for {}", root.display());
+        return Ok(());
+    }
+    if !daemon::wait_for_daemon(&db_path, Duration::from_secs(5)) {
+        eprintln!("Warning: daemon did not confirm in 5 s");
+    }
+    println!("Daemon started for {}", root.display());
+    Ok(())
+}
+
+pub async fn run_stop(
+    root: Option<PathBuf>,
+    db: Option<PathBuf>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let root = resolve_root(root);
+    let db_path = db.unwrap_or_else(|| default_db_path(&root));
+    info!(root = %root.display(), db = %db_path.display(), "stop command requested");
+    daemon::stop_daemon(&db_path)?;
+    println!("Stop requested for {}", root.display());
+    Ok(())
+}
+
+pub async fn run_stop_all() -> Result<(), Box<dyn std::error::Error>> {
+    let daemons = daemon::list_all_daemons()?;
+    if daemons.is_empty() {
+        println!("No running daemons found.");
+        return Ok(());
+    }
+    for info in &daemons {
+        daemon::stop_daemon(&info.db_path)?;
+        println!("Stop requested for {}", info.root.display());
+    }
+    Ok(())
+}
+
+pub async fn run_status(
+    root: Option<PathBuf>,
+    db: Option<PathBuf>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let root = resolve_root(root);
+    let db_path = db.unwrap_or_else(|| default_db_path(&root));
+    info!(root = %root.display(), db = %db_path.display(), "status command requested");
+
+    match daemon::daemon_status(&db_path)? {
+        Some(info) => {
+            debug!(
+                root = %info.root.display(),
+                pid = ?info.pid,
+                version = ?info.version,
+                index_status = ?info.index_status,
+                leader_holder = ?info.leader_holder,
+                "status command loaded daemon info"
+            );
+            println!("Root:         {}", info.root.display());
+            println!("Index:        {}", db_path.display());
+            print_index_scope(&db_path);
+            print_opt_out_hints(&db_path);
+            println!(
+                "PID:          {}",
+                info.pid.map_or("unknown".to_string(), |p| p.to_string())
+            );
+            println!(
+                "Version:      {}",
+                info.version.unwrap_or_else(|| "unknown".to_string())
+            );
+            println!(
+                "Index status: {}",
+                info.index_status.unwrap_or_else(|| "unknown".to_string())
+            );
+            if let Some(progress) = info.progress {
+                if let Some(mode) = progress.mode.as_deref() {
+                    println!("Scan mode:    {mode}");
+                }
+                match progress.total_files {
+                    Some(total) => {
+                        println!("Progress:     {}/{} files", progress.processed_files, total);
+                    }
+                    None => {
+                        println!("Progress:     {} files", progress.processed_files);
+                    }
+                }
+                if let Some(current) = progress.current_path.as_deref() {
+                    println!("Processing:   {current}");
+                }
+                if let Some(last) = progress.last_completed_path.as_deref() {
+                    println!("Last file:    {last}");
+                }
+                if progress.phase == IndexPhase::Complete {
+                    println!("ETA:          done");
+                } else if let Some(eta) = estimate_eta_seconds(&progress) {
+                    println!("ETA:          {}", format_eta(eta));
+                }
+            }
+            if let Some(watcher) = &info.watcher {
+                println!("Watcher:      {}", format_watcher_health(watcher));
+            }
+            println!(
+                "Leader:       {}",
+                info.leader_holder.unwrap_or_else(|| "none".to_string())
+            );
+            match rpc::ping(&db_path) {
+                Some(pid) => println!("RPC:          listening (pid {pid})"),
+                None => println!("RPC:          unavailable"),
+            }
+            if let Some(expires_at_ms) = info.leader_expires_ms
+                && let Some(remaining) = format_remaining_lease(expires_at_ms)
+            {
+                println!("Lease TTL:    {remaining}");
+            }
+        }
+        None => {
+            debug!(db = %db_path.display(), "status command found no daemon info");
+            println!("No daemon running for {}", root.display());
+            if db_path.exists() {
+                println!("Index:        {}", db_path.display());
+                print_index_scope(&db_path);
+                print_opt_out_hints(&db_path);
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// The `sf status` lines on what the index at `db_path` covers.
+fn print_index_scope(db_path: &Path) {
+    println!(
+        "Scope:        {}",
+        describe_scope(&current_include_paths(db_path))
+    );
+    if let Some(cursor) = read_meta_readonly(db_path, SCAN_CURSOR_META_KEY)
+        .ok()
+        .flatten()
+        .filter(|cursor| !cursor.is_empty())
+    {
+        println!("Resume after: {cursor}");
+    }
+}
+
+/// The `sf status` hints to stop indexing extensions that bloat the index
+/// at `db_path`; see [`opt_out_hints`].
+fn print_opt_out_hints(db_path: &Path) {
+    let Ok(stats) = extension_stats_in_database(db_path) else {
+        return;
+    };
+    for hint in opt_out_hints(&stats) {
+        println!("Hint:         {hint}");
+    }
+}
+
+/// Extensions of generated or data files that code search rarely needs.
+const NOISY_EXTENSIONS: &[&str] = &["map", "lock", "svg", "csv", "tsv", "log", "snap"];
+
+/// Share of the index a noisy extension has to take before it is worth
+/// suggesting to skip.
+const OPT_OUT_MIN_SHARE: f64 = 0.1;
+
+/// Suggestions to skip the [`NOISY_EXTENSIONS`] that take at least
+/// [`OPT_OUT_MIN_SHARE`] of the index, largest first.
+fn opt_out_hints(stats: &[ExtensionStat]) -> Vec<String> {
+    let total: u64 = stats.iter().map(|stat| stat.bytes).sum();
+    if total == 0 {
+        return Vec::new();
+    }
+    stats
+        .iter()
+        .filter(|stat| NOISY_EXTENSIONS.contains(&stat.extension.as_str()))
+        .filter_map(|stat| {
+            let share = stat.bytes as f64 / total as f64;
+            (share >= OPT_OUT_MIN_SHARE).then(|| {
+                format!(
+                    "`.{ext}` files are {percent:.0}% of the index; add \"*.{ext}\" to \
+                     `force_binary` under `[binary]` in .source_fast/config.toml to skip them",
+                    ext = stat.extension,
+                    percent = share * 100.0
+                )
+            })
+        })
+        .collect()
+}
+
+/// The include paths stored in the index at `db_path`; empty when it
+/// covers the whole root or does not exist yet.
+fn current_include_paths(db_path: &Path) -> Vec<PathBuf> {
+    if !db_path.exists() {
+        return Vec::new();
+    }
+    read_meta_readonly(db_path, INCLUDE_PATHS_META_KEY)
+        .ok()
+        .flatten()
+        .map(|value| parse_include_paths(&value))
+        .unwrap_or_default()
+}
+
+/// The git location stored with the index at `db_path` by `--git-dir` and
+/// `--work-tree`.
+fn current_git_location(db_path: &Path) -> GitLocation {
+    if !db_path.exists() {
+        return GitLocation::default();
+    }
+    GitLocation::from_meta(|key| read_meta_readonly(db_path, key).ok().flatten())
+}
+
+/// The [`GitLocation`] of `--git-dir` and `--work-tree`, made absolute.
+fn resolve_git_location(
+    git_dir: Option<PathBuf>,
+    work_tree: Option<PathBuf>,
+) -> Result<GitLocation, Box<dyn std::error::Error>> {
+    let git_dir = git_dir.map(std::path::absolute).transpose()?;
+    let work_tree = work_tree.map(std::path::absolute).transpose()?;
+    for (flag, dir) in [("--git-dir", &git_dir), ("--work-tree", &work_tree)] {
+        if let Some(dir) = dir
+            && !dir.is_dir()
+        {
+            return Err(format!("{flag} {} is not a directory", dir.display()).into());
+        }
+    }
+    Ok(GitLocation { git_dir, work_tree })
+}
+
+fn describe_scope(include_paths: &[PathBuf]) -> String {
+    if include_paths.is_empty() {
+        return "whole root".to_string();
+    }
+    include_paths
+        .iter()
+        .map(|path| path.display().to_string())
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+/// `paths` (absolute, or relative to `root`) as sorted root-relative
+/// directories, dropping any nested in another. Empty when one of them is
+/// the root itself.
+fn resolve_include_paths(root: &Path, paths: &[PathBuf]) -> Result<Vec<PathBuf>, String> {
+    let mut resolved = Vec::with_capacity(paths.len());
+    for path in paths {
+        let absolute = root
+            .join(path)
+            .canonicalize()
+            .map_err(|err| format!("include path {}: {err}", path.display()))?;
+        if !absolute.is_dir() {
+            return Err(format!(
+                "include path {} is not a directory",
+                path.display()
+            ));
+        }
+        let relative = absolute
+            .strip_prefix(root)
+            .map_err(|_| {
+                format!(
+                    "include path {} is outside {}",
+                    path.display(),
+                    root.display()
+                )
+            })?
+            .to_path_buf();
+        if relative.as_os_str().is_empty() {
+            return Ok(Vec::new());
+        }
+        resolved.push(relative);
+    }
+    resolved.sort();
+    let mut scope: Vec<PathBuf> = Vec::with_capacity(resolved.len());
+    for path in resolved {
+        if !scope.iter().any(|kept| path.starts_with(kept)) {
+            scope.push(path);
+        }
+    }
+    Ok(scope)
+}
+
+pub async fn run_list() -> Result<(), Box<dyn std::error::Error>> {
+    let daemons = daemon::list_all_daemons()?;
+    if daemons.is_empty() {
+        println!("No running daemons found.");
+        return Ok(());
+    }
+
+    for info in &daemons {
+        println!(
+            "{}\tPID={}\tindex={}\tversion={}\tdb={}",
+            info.root.display(),
+            info.pid.map_or("?".to_string(), |p| p.to_string()),
+            info.index_status.as_deref().unwrap_or("?"),
+            info.version.as_deref().unwrap_or("?"),
+            info.db_path.display(),
+        );
+    }
+
+    Ok(())
+}
+
+// ---------------------------------------------------------------------------
+// Index build & watch commands
+// ---------------------------------------------------------------------------
+
+pub struct IndexBuildOpts {
+    pub root: Option<PathBuf>,
+    pub db: Option<PathBuf>,
+    pub bootstrap_url: Option<String>,
+    pub sha256: Option<String>,
+    pub rebuild: bool,
+    pub include_paths: Vec<PathBuf>,
+    pub all_paths: bool,
+    pub git_dir: Option<PathBuf>,
+    pub work_tree: Option<PathBuf>,
+}
+
+pub async fn run_index_build(opts: IndexBuildOpts) -> Result<(), Box<dyn std::error::Error>> {
+    let IndexBuildOpts {
+        root,
+        db,
+        bootstrap_url,
+        sha256,
+        rebuild,
+        include_paths,
+        all_paths,
+        git_dir,
+        work_tree,
+    } = opts;
     let root = resolve_root(root);
     let db_path = db.unwrap_or_else(|| default_db_path(&root));
-    info!(root = %root.display(), db = %db_path.display(), "stop command requested");
-    daemon::stop_daemon(&db_path)?;
-    println!("Stop requested for {}", root.display());
+
+    // A new scope takes a rebuild: files that left it must go and files
+    // that entered it were never scanned.
+    let scope = if all_paths {
+        Some(Vec::new())
+    } else if include_paths.is_empty() {
+        None
+    } else {
+        Some(resolve_include_paths(&root, &include_paths)?)
+    };
+    let scope = scope.filter(|paths| *paths != current_include_paths(&db_path));
+    // So does another repository: the stored HEAD means nothing in it.
+    let git = if git_dir.is_some() {
+        Some(resolve_git_location(git_dir, work_tree)?)
+    } else {
+        None
+    };
+    let git = git.filter(|git| *git != current_git_location(&db_path));
+
+    if let Some(url) = bootstrap_url {
+        let download = db_path.with_file_name("bootstrap.sfpack");
+        eprintln!("Downloading index snapshot from {url}...");
+        {
+            let download = download.clone();
+            task::spawn_blocking(move || {
+                bootstrap::download_snapshot(&url, sha256.as_deref(), &download)
+                    .map_err(|err| err.to_string())
+            })
+            .await??;
+        }
+        let imported = import_snapshot_file(&root, &db_path, download.clone()).await;
+        let _ = std::fs::remove_file(&download);
+        eprintln!("Imported {} files from snapshot", imported?);
+    }
+
+    if rebuild || scope.is_some() || git.is_some() {
+        if let Some(paths) = &scope {
+            eprintln!("Index scope set to {}", describe_scope(paths));
+        }
+        if let Some(GitLocation {
+            git_dir: Some(git_dir),
+            work_tree,
+        }) = &git
+        {
+            eprintln!(
+                "Git repository set to {} (work tree {})",
+                git_dir.display(),
+                work_tree.as_deref().unwrap_or(&root).display()
+            );
+        }
+        eprintln!(
+            "Rebuilding the index of {}; searches use the current index until it is done...",
+            root.display()
+        );
+        {
+            let root = root.clone();
+            let db_path = db_path.clone();
+            task::spawn_blocking(move || {
+                rebuild_index(&root, &db_path, scope, git).map_err(|err| err.to_string())
+            })
+            .await??;
+        }
+        eprintln!(
+            "Rebuilt index swapped in; daemon started for {}",
+            root.display()
+        );
+    } else if daemon::ensure_daemon(&root, &db_path)? {
+        eprintln!("Daemon already running for {}", root.display());
+    } else {
+        eprintln!("Daemon started for {}", root.display());
+    }
+
+    if !daemon::wait_for_daemon(&db_path, Duration::from_secs(5)) {
+        eprintln!("Warning: daemon did not confirm in 5 s");
+    }
+
+    eprintln!("Index building in background. Use `sf index watch` to monitor progress.");
     Ok(())
 }
 
-pub async fn run_stop_all() -> Result<(), Box<dyn std::error::Error>> {
-    let daemons = daemon::list_all_daemons()?;
-    if daemons.is_empty() {
-        println!("No running daemons found.");
+/// A `--max-duration` or `--modified-since` value: seconds, or a number
+/// with an `s`, `m`, `h` or `d` suffix.
+pub fn parse_duration(value: &str) -> Result<Duration, String> {
+    let invalid = || format!("invalid duration `{value}`; use e.g. 90s, 30m, 2h or 2d");
+    let (number, unit) = match value.find(|c: char| !c.is_ascii_digit()) {
+        Some(at) => value.split_at(at),
+        None => (value, "s"),
+    };
+    let number: u64 = number.parse().map_err(|_| invalid())?;
+    let secs = match unit {
+        "s" => number,
+        "m" => number.saturating_mul(60),
+        "h" => number.saturating_mul(3600),
+        "d" => number.saturating_mul(86_400),
+        _ => return Err(invalid()),
+    };
+    Ok(Duration::from_secs(secs))
+}
+
+pub async fn run_index_watch(
+    root: Option<PathBuf>,
+    db: Option<PathBuf>,
+    limits: ScanLimits,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let root = resolve_root(root);
+    let db_path = db.unwrap_or_else(|| default_db_path(&root));
+
+    if !db_path.exists() {
+        let created = open_index_with_worktree_copy(&root, &db_path)?;
+        drop(created);
+    }
+
+    // If a daemon is actively building, attach to its progress instead of killing it.
+    if daemon::live_leader_active(&db_path).unwrap_or(false) {
+        let status = read_meta_readonly(&db_path, daemon::meta_keys::INDEX_STATUS)
+            .ok()
+            .flatten();
+        if status.as_deref() == Some(daemon::index_status::BUILDING) {
+            eprintln!("Daemon is building the index. Attaching to progress...");
+            watch_progress_polling(&db_path);
+            return Ok(());
+        }
+    }
+
+    best_effort_stop_daemon(&db_path);
+
+    // A writer that is not a daemon, such as `sf server`, keeps the lease;
+    // follow its progress rather than opening the index alongside it.
+    if daemon::live_leader_active(&db_path).unwrap_or(false) {
+        eprintln!("Another writer is active. Attaching to persisted progress...");
+        watch_progress_polling(&db_path);
         return Ok(());
     }
-    for info in &daemons {
-        let db = info.root.join(".source_fast").join("index.mdb");
-        daemon::stop_daemon(&db)?;
-        println!("Stop requested for {}", info.root.display());
+
+    let index = Arc::new(open_index_with_worktree_copy(&root, &db_path)?);
+    let holder = daemon::holder_id("watch");
+    let lease_ttl = Duration::from_secs(5);
+
+    let acquired = {
+        let index = Arc::clone(&index);
+        let holder = holder.clone();
+        task::spawn_blocking(move || {
+            index.try_acquire_writer_lease_with(&holder, lease_ttl, daemon::holder_is_orphaned)
+        })
+        .await??
+    };
+
+    if !acquired {
+        eprintln!("Another writer is active. Attaching to persisted progress...");
+        watch_progress_polling(&db_path);
+        return Ok(());
+    }
+
+    index.set_write_enabled(true);
+    ensure_settings_as_writer(&index)?;
+    let _ = index.set_meta_queued(
+        daemon::meta_keys::INDEX_STATUS,
+        daemon::index_status::BUILDING,
+    );
+
+    let state = Arc::new(WatchState::new());
+    let initial_progress = watch_snapshot_to_progress(&state.snapshot());
+    queue_progress_meta(&index, &initial_progress);
+    let _ = index.flush();
+
+    let render_state = Arc::clone(&state);
+    let render_handle = std::thread::spawn(move || {
+        let mut first_frame = true;
+        let mut tick = 0usize;
+        loop {
+            let snapshot = render_state.snapshot();
+            let frame = render_watch_frame(&snapshot, tick);
+            print_watch_frame(&frame, first_frame);
+            first_frame = false;
+            tick = tick.wrapping_add(1);
+
+            if snapshot.phase.is_terminal() {
+                break;
+            }
+
+            std::thread::sleep(Duration::from_millis(16));
+        }
+    });
+
+    let renew_index = Arc::clone(&index);
+    let renew_holder = holder.clone();
+    let renew_failed = Arc::new(AtomicUsize::new(0));
+    let renew_failed_for_thread = Arc::clone(&renew_failed);
+    let renew_handle = std::thread::spawn(move || {
+        loop {
+            std::thread::sleep(Duration::from_secs(2));
+            if matches!(
+                renew_index.get_meta(daemon::meta_keys::INDEX_STATUS),
+                Ok(Some(ref status))
+                    if status == daemon::index_status::COMPLETE
+                        || status == daemon::index_status::FAILED
+                        || status == daemon::index_status::PAUSED
+            ) {
+                break;
+            }
+
+            match renew_index.renew_writer_lease(&renew_holder, lease_ttl) {
+                Ok(true) => {}
+                Ok(false) | Err(_) => {
+                    renew_index.set_write_enabled(false);
+                    renew_failed_for_thread.store(1, Ordering::SeqCst);
+                    break;
+                }
+            }
+        }
+    });
+
+    let callback_state = Arc::clone(&state);
+    let progress_callback: Arc<dyn Fn(ScanEvent) + Send + Sync> = Arc::new(move |event| {
+        callback_state.apply_event(event);
+    });
+
+    let scan_result = {
+        let scan_root = root.clone();
+        let scan_index = Arc::clone(&index);
+        task::spawn_blocking(move || {
+            smart_scan_with_limits(
+                &scan_root,
+                scan_index,
+                progress_callback,
+                Arc::new(AtomicBool::new(false)),
+                limits,
+            )
+        })
+        .await?
+    };
+
+    if renew_failed.load(Ordering::SeqCst) != 0 {
+        state.set_phase(IndexPhase::Failed);
+    } else {
+        match scan_result {
+            Ok(ScanStatus::Complete) => state.set_phase(IndexPhase::Complete),
+            Ok(ScanStatus::Paused) => state.set_phase(IndexPhase::Paused),
+            Err(_) => state.set_phase(IndexPhase::Failed),
+        }
+    }
+
+    let final_snapshot = state.snapshot();
+    let final_progress = watch_snapshot_to_progress(&final_snapshot);
+    queue_progress_meta(&index, &final_progress);
+    let final_status = match final_snapshot.phase {
+        IndexPhase::Complete => daemon::index_status::COMPLETE,
+        IndexPhase::Paused => daemon::index_status::PAUSED,
+        _ => daemon::index_status::FAILED,
+    };
+    let _ = index.set_meta_queued(daemon::meta_keys::INDEX_STATUS, final_status);
+    let _ = index.flush();
+
+    let _ = renew_handle.join();
+    let _ = render_handle.join();
+    print_watch_summary(&final_snapshot);
+
+    index.set_write_enabled(false);
+    let _ = index.release_writer_lease(&holder);
+
+    if renew_failed.load(Ordering::SeqCst) != 0 {
+        return Err("foreground watch lost the writer lease before completion".into());
+    }
+
+    // A daemon would carry on with a paused scan straight away.
+    if scan_result? == ScanStatus::Paused {
+        return Ok(());
+    }
+
+    // Restart daemon for background file watching.
+    drop(index);
+    let _ = daemon::spawn_daemon(&root, &db_path);
+
+    Ok(())
+}
+
+/// Files processed so far, with the percentage once the total is counted.
+pub(crate) fn format_files_progress(p: &IndexProgress) -> String {
+    match p.total_files {
+        Some(total) if total > 0 => {
+            let pct = (p.processed_files as f64 / total as f64 * 100.0).min(100.0);
+            format!("{}/{} files ({pct:.0}%)", p.processed_files, total)
+        }
+        _ => format!("{} files", p.processed_files),
+    }
+}
+
+fn format_progress_line(p: &IndexProgress, status: &str) -> String {
+    let mode = p.mode.as_deref().unwrap_or("scanning");
+    let files_part = format_files_progress(p);
+
+    let bytes_part = match p.total_bytes {
+        Some(total) if total > 0 => {
+            format!(
+                " {}/{}",
+                format_bytes(p.processed_bytes),
+                format_bytes(total)
+            )
+        }
+        _ if p.processed_bytes > 0 => format!(" {}", format_bytes(p.processed_bytes)),
+        _ => String::new(),
+    };
+
+    let bar = match p.total_files {
+        Some(total) if total > 0 => {
+            let ratio = (p.processed_files as f64 / total as f64).min(1.0);
+            let width = 30;
+            let filled = (ratio * width as f64) as usize;
+            let empty = width - filled;
+            format!(" [{}{}]", "█".repeat(filled), "░".repeat(empty))
+        }
+        _ => String::new(),
+    };
+
+    let file_name = p
+        .current_path
+        .as_deref()
+        .or(p.last_completed_path.as_deref())
+        .map(|path| {
+            let name = path.rsplit(['/', '\\']).next().unwrap_or(path);
+            if name.len() > 40 {
+                format!("{}...", &name[..37])
+            } else {
+                name.to_string()
+            }
+        })
+        .unwrap_or_default();
+
+    let eta = if status == daemon::index_status::COMPLETE {
+        " done".to_string()
+    } else {
+        match estimate_eta_seconds(p) {
+            Some(secs) => format!(" ETA {}", format_eta(secs)),
+            None => String::new(),
+        }
+    };
+
+    format!("\x1b[36m{mode}\x1b[0m{bar} {files_part}{bytes_part}{eta} \x1b[2m{file_name}\x1b[0m")
+}
+
+fn format_bytes(bytes: u64) -> String {
+    if bytes < 1024 {
+        format!("{bytes} B")
+    } else if bytes < 1024 * 1024 {
+        format!("{:.1} KB", bytes as f64 / 1024.0)
+    } else if bytes < 1024 * 1024 * 1024 {
+        format!("{:.1} MB", bytes as f64 / (1024.0 * 1024.0))
+    } else {
+        format!("{:.1} GB", bytes as f64 / (1024.0 * 1024.0 * 1024.0))
     }
-    Ok(())
 }
 
-pub async fn run_status(
+// ---------------------------------------------------------------------------
+// Index statistics
+// ---------------------------------------------------------------------------
+
+/// Rows listed by `sf stats` for a report asked for without a count.
+const DEFAULT_STATS_ROWS: usize = 20;
+
+/// Print the trigrams, files and extensions that take the most room in the
+/// index. With no report requested, all of them are printed.
+pub async fn run_stats(
     root: Option<PathBuf>,
     db: Option<PathBuf>,
+    top_trigrams: Option<usize>,
+    largest_files: Option<usize>,
+    extensions: Option<usize>,
+    json: bool,
 ) -> Result<(), Box<dyn std::error::Error>> {
     let root = resolve_root(root);
     let db_path = db.unwrap_or_else(|| default_db_path(&root));
-    info!(root = %root.display(), db = %db_path.display(), "status command requested");
+    info!(root = %root.display(), db = %db_path.display(), "stats command requested");
 
-    match daemon::daemon_status(&db_path)? {
-        Some(info) => {
-            debug!(
-                root = %info.root.display(),
-                pid = ?info.pid,
-                version = ?info.version,
-                index_status = ?info.index_status,
-                leader_holder = ?info.leader_holder,
-                "status command loaded daemon info"
-            );
-            println!("Root:         {}", info.root.display());
-            println!(
-                "PID:          {}",
-                info.pid.map_or("unknown".to_string(), |p| p.to_string())
-            );
+    if !db_path.exists() {
+        return Err(format!(
+            "no index found at {}; build one with `sf index build` first",
+            db_path.display()
+        )
+        .into());
+    }
+    let (top_trigrams, largest_files, extensions) = match (top_trigrams, largest_files, extensions)
+    {
+        (None, None, None) => (
+            Some(DEFAULT_STATS_ROWS),
+            Some(DEFAULT_STATS_ROWS),
+            Some(DEFAULT_STATS_ROWS),
+        ),
+        requested => requested,
+    };
+
+    let (trigrams, files, by_extension) = task::spawn_blocking(move || -> Result<_, IndexError> {
+        let trigrams = top_trigrams
+            .map(|limit| top_trigrams_in_database(&db_path, limit))
+            .transpose()?;
+        let files = largest_files
+            .map(|limit| largest_files_in_database(&db_path, limit))
+            .transpose()?;
+        let by_extension = extensions
+            .map(|_| extension_stats_in_database(&db_path))
+            .transpose()?;
+        Ok((trigrams, files, by_extension))
+    })
+    .await??;
+    // Hints weigh each extension against the whole index, not just the
+    // rows shown.
+    let hints = by_extension
+        .as_deref()
+        .map(opt_out_hints)
+        .unwrap_or_default();
+    let by_extension = by_extension.map(|mut stats| {
+        stats.truncate(extensions.unwrap_or(DEFAULT_STATS_ROWS));
+        stats
+    });
+
+    if json {
+        let mut output = serde_json::json!({});
+        if let Some(trigrams) = &trigrams {
+            output["top_trigrams"] = serde_json::to_value(trigrams)?;
+        }
+        if let Some(files) = &files {
+            output["largest_files"] = serde_json::to_value(files)?;
+        }
+        if let Some(by_extension) = &by_extension {
+            output["extensions"] = serde_json::to_value(by_extension)?;
+            output["hints"] = serde_json::to_value(&hints)?;
+        }
+        println!("{}", serde_json::to_string_pretty(&output)?);
+        return Ok(());
+    }
+
+    if let Some(trigrams) = &trigrams {
+        println!("Top trigrams:");
+        println!("  {:>10}  {:>10}  TRIGRAM", "FILES", "SIZE");
+        for stat in trigrams {
             println!(
-                "Version:      {}",
-                info.version.unwrap_or_else(|| "unknown".to_string())
+                "  {:>10}  {:>10}  {:?}",
+                stat.files,
+                format_bytes(stat.bytes),
+                stat.trigram
             );
+        }
+    }
+    if let Some(files) = &files {
+        if trigrams.is_some() {
+            println!();
+        }
+        println!("Largest files:");
+        println!("  {:>10}  {:>10}  PATH", "TRIGRAMS", "STORED");
+        for stat in files {
+            let path = Path::new(&stat.path);
             println!(
-                "Index status: {}",
-                info.index_status.unwrap_or_else(|| "unknown".to_string())
+                "  {:>10}  {:>10}  {}",
+                stat.trigrams,
+                format_bytes(stat.stored_bytes),
+                path.strip_prefix(&root).unwrap_or(path).display()
             );
-            if let Some(progress) = info.progress {
-                if let Some(mode) = progress.mode.as_deref() {
-                    println!("Scan mode:    {mode}");
-                }
-                match progress.total_files {
-                    Some(total) => {
-                        println!("Progress:     {}/{} files", progress.processed_files, total);
-                    }
-                    None => {
-                        println!("Progress:     {} files", progress.processed_files);
-                    }
-                }
-                if let Some(current) = progress.current_path.as_deref() {
-                    println!("Processing:   {current}");
-                }
-                if let Some(last) = progress.last_completed_path.as_deref() {
-                    println!("Last file:    {last}");
-                }
-                if progress.phase == IndexPhase::Complete {
-                    println!("ETA:          done");
-                } else if let Some(eta) = estimate_eta_seconds(&progress) {
-                    println!("ETA:          {}", format_eta(eta));
-                }
-            }
+        }
+    }
+    if let Some(by_extension) = &by_extension {
+        if trigrams.is_some() || files.is_some() {
+            println!();
+        }
+        println!("Extensions:");
+        println!("  {:>10}  {:>10}  EXTENSION", "FILES", "SIZE");
+        for stat in by_extension {
+            let extension = if stat.extension.is_empty() {
+                "(none)".to_string()
+            } else {
+                format!(".{}", stat.extension)
+            };
             println!(
-                "Leader:       {}",
-                info.leader_holder.unwrap_or_else(|| "none".to_string())
+                "  {:>10}  {:>10}  {extension}",
+                stat.files,
+                format_bytes(stat.bytes)
             );
-            if let Some(expires_at_ms) = info.leader_expires_ms
-                && let Some(remaining) = format_remaining_lease(expires_at_ms)
-            {
-                println!("Lease TTL:    {remaining}");
-            }
         }
-        None => {
-            debug!(db = %db_path.display(), "status command found no daemon info");
-            println!("No daemon running for {}", root.display());
+        for hint in &hints {
+            println!("Hint: {hint}");
         }
     }
+    Ok(())
+}
+
+/// `sf files`: list the indexed files below `under` (the whole root
+/// without it) that pass the extension or glob filter and were modified
+/// within `modified_since`.
+pub async fn run_files(
+    root: Option<PathBuf>,
+    db: Option<PathBuf>,
+    ext: Vec<String>,
+    glob: Option<String>,
+    under: Option<PathBuf>,
+    modified_since: Option<Duration>,
+    json: bool,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let root = resolve_root(root);
+    let db_path = db.unwrap_or_else(|| default_db_path(&root));
+    info!(root = %root.display(), db = %db_path.display(), "files command requested");
+
+    if !db_path.exists() {
+        return Err(format!(
+            "no index found at {}; build one with `sf index build` first",
+            db_path.display()
+        )
+        .into());
+    }
+    let file_regex = build_file_filter(&None, &ext, &glob)?;
+    let now = now_ms() / 1000;
+    let since = modified_since.map(|age| now.saturating_sub(age.as_secs()));
+    let mut files =
+        task::spawn_blocking(move || list_files_in_database(&db_path, file_regex.as_ref(), since))
+            .await??;
+
+    // Normalize the scope once instead of once per file.
+    let scope = match &under {
+        Some(dir) => root.join(dir),
+        None => root.clone(),
+    };
+    let scope = normalize_path_for_prefix(&normalize_path(&scope));
+    files.retain(|file| {
+        normalize_path_for_prefix(&file.path)
+            .strip_prefix(&scope)
+            .is_some_and(|rest| rest.is_empty() || rest.starts_with(std::path::MAIN_SEPARATOR))
+    });
+
+    if json {
+        println!("{}", serde_json::to_string_pretty(&files)?);
+        return Ok(());
+    }
 
+    println!("  {:>10}  {:>10}  PATH", "MODIFIED", "STORED");
+    for file in &files {
+        let path = Path::new(&file.path);
+        let age = format!("{} ago", format_age(now.saturating_sub(file.last_modified)));
+        println!(
+            "  {age:>10}  {:>10}  {}",
+            format_bytes(file.stored_bytes),
+            path.strip_prefix(&root).unwrap_or(path).display()
+        );
+    }
+    eprintln!("{} files", files.len());
     Ok(())
 }
 
-pub async fn run_list() -> Result<(), Box<dyn std::error::Error>> {
-    let daemons = daemon::list_all_daemons()?;
-    if daemons.is_empty() {
-        println!("No running daemons found.");
+/// `seconds` as a rough age: `42s`, `5m 3s`, `2h 10m` or `3d 4h`.
+fn format_age(seconds: u64) -> String {
+    if seconds < 86_400 {
+        return format_eta(seconds);
+    }
+    format!("{}d {}h", seconds / 86_400, seconds % 86_400 / 3600)
+}
+
+/// `sf similar`: list the indexed files whose trigram sets overlap that
+/// of `path` by at least `min_similarity`.
+pub async fn run_similar(
+    root: Option<PathBuf>,
+    db: Option<PathBuf>,
+    path: PathBuf,
+    min_similarity: f64,
+    limit: usize,
+    json: bool,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let root = resolve_root(root);
+    let db_path = db.unwrap_or_else(|| default_db_path(&root));
+    let file = normalize_path(&path);
+    info!(root = %root.display(), db = %db_path.display(), file = %file, "similar command requested");
+
+    if !db_path.exists() {
+        return Err(format!(
+            "no index found at {}; build one with `sf index build` first",
+            db_path.display()
+        )
+        .into());
+    }
+    if !(0.0..=1.0).contains(&min_similarity) {
+        return Err("--min-similarity must be between 0 and 1".into());
+    }
+
+    let limit = if limit > 0 { limit } else { usize::MAX };
+    let lookup = file.clone();
+    let similar = task::spawn_blocking(move || {
+        similar_files_in_database(&db_path, &lookup, min_similarity, limit)
+    })
+    .await??
+    .ok_or_else(|| format!("{} is not indexed", path.display()))?;
+
+    if json {
+        let output = serde_json::json!({
+            "path": file,
+            "min_similarity": min_similarity,
+            "similar": similar,
+        });
+        println!("{}", serde_json::to_string_pretty(&output)?);
         return Ok(());
     }
 
-    for info in &daemons {
+    if similar.is_empty() {
+        eprintln!(
+            "No indexed file is at least {:.0}% similar to {}.",
+            min_similarity * 100.0,
+            path.display()
+        );
+        return Ok(());
+    }
+    println!("  {:>10}  {:>10}  PATH", "SIMILARITY", "SHARED");
+    for stat in &similar {
+        let path = Path::new(&stat.path);
+        let percent = format!("{:.1}%", stat.similarity * 100.0);
         println!(
-            "{}\tPID={}\tindex={}\tversion={}",
-            info.root.display(),
-            info.pid.map_or("?".to_string(), |p| p.to_string()),
-            info.index_status.as_deref().unwrap_or("?"),
-            info.version.as_deref().unwrap_or("?"),
+            "  {percent:>10}  {:>10}  {}",
+            stat.shared,
+            path.strip_prefix(&root).unwrap_or(path).display()
         );
     }
+    Ok(())
+}
+
+// ---------------------------------------------------------------------------
+// Snapshot export & import
+// ---------------------------------------------------------------------------
+
+pub async fn run_export(
+    root: Option<PathBuf>,
+    db: Option<PathBuf>,
+    out: PathBuf,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let root = resolve_root(root);
+    let db_path = db.unwrap_or_else(|| default_db_path(&root));
+    info!(root = %root.display(), db = %db_path.display(), out = %out.display(), "export command requested");
+
+    if !db_path.exists() {
+        return Err(format!(
+            "no index found at {}; build one with `sf index watch` first",
+            db_path.display()
+        )
+        .into());
+    }
+
+    let status = read_meta_readonly(&db_path, daemon::meta_keys::INDEX_STATUS)
+        .ok()
+        .flatten();
+    if status.as_deref() == Some(daemon::index_status::BUILDING) {
+        eprintln!("Warning: the index is still building; the snapshot will be partial.");
+    }
+
+    let out_for_task = out.clone();
+    let snapshot = task::spawn_blocking(move || -> Result<IndexSnapshot, IndexError> {
+        let snapshot = export_snapshot(&db_path, &root)?;
+        snapshot.write_file(&out_for_task)?;
+        Ok(snapshot)
+    })
+    .await??;
+
+    bootstrap::write_checksum_file(&out)?;
+    let size = std::fs::metadata(&out).map(|m| m.len()).unwrap_or(0);
+    eprintln!(
+        "Exported {} files ({} trigrams) to {} ({})",
+        snapshot.files.len(),
+        snapshot.trigrams.len(),
+        out.display(),
+        format_bytes(size)
+    );
+    Ok(())
+}
+
+pub async fn run_import(
+    root: Option<PathBuf>,
+    db: Option<PathBuf>,
+    input: PathBuf,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let root = resolve_root(root);
+    let db_path = db.unwrap_or_else(|| default_db_path(&root));
+    info!(root = %root.display(), db = %db_path.display(), input = %input.display(), "import command requested");
+
+    let imported = import_snapshot_file(&root, &db_path, input).await?;
+    eprintln!("Imported {imported} files into {}", db_path.display());
+
+    // The daemon's startup scan catches the index up with the local
+    // checkout from the snapshot's commit.
+    let _ = daemon::spawn_daemon(&root, &db_path);
+    eprintln!("Daemon started to apply local changes. Use `sf index watch` to monitor progress.");
+    Ok(())
+}
+
+fn another_writer_active() -> IndexError {
+    IndexError::Lease(
+        "another writer is active; stop it with `sf daemon stop`, or quit the `sf server` \
+         serving this index, and retry"
+            .to_string(),
+    )
+}
+
+/// Build a fresh index of `root` next to `db_path` with a full scan while
+/// the current index keeps answering searches, then stop any daemon, swap
+/// the new index in and start a daemon on it. The new index covers
+/// `include_paths` and scans the repository at `git`, or keeps the current
+/// index's scope and repository for `None`.
+pub(crate) fn rebuild_index(
+    root: &Path,
+    db_path: &Path,
+    include_paths: Option<Vec<PathBuf>>,
+    git: Option<GitLocation>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    // Only a daemon is stopped for the swap; fail before the full scan if
+    // another writer would still hold the lease then.
+    if daemon::foreign_writer_active(db_path).unwrap_or(false) {
+        return Err(another_writer_active().into());
+    }
+    let include_paths = include_paths.unwrap_or_else(|| current_include_paths(db_path));
+    let git = git.unwrap_or_else(|| current_git_location(db_path));
+    let staging = staging_db_path(db_path);
+    remove_db_files(&staging);
+    if let Err(err) = build_staging_index(root, &staging, &include_paths, &git) {
+        remove_db_files(&staging);
+        return Err(err.into());
+    }
+
+    if daemon::live_leader_active(db_path).unwrap_or(false) {
+        daemon::stop_daemon(db_path)?;
+    }
+    if !hold_lease_for_swap(db_path).unwrap_or(false) {
+        remove_db_files(&staging);
+        return Err(another_writer_active().into());
+    }
+    if let Err(err) = replace_index(db_path, &staging) {
+        remove_db_files(&staging);
+        return Err(err.into());
+    }
+
+    daemon::spawn_daemon(root, db_path)
+}
+
+/// Take the writer lease of `db_path` as soon as a stopping daemon has
+/// released it, so that a reader such as `sf server` is not promoted to
+/// writer of the index about to be replaced. The lease is left to expire
+/// with the replaced index.
+fn hold_lease_for_swap(db_path: &Path) -> Result<bool, IndexError> {
+    if !db_path.exists() {
+        return Ok(true);
+    }
+    let index = PersistentIndex::open_or_create(db_path)?;
+    let holder = daemon::holder_id("rebuild");
+    let deadline = Instant::now() + Duration::from_secs(5);
+    loop {
+        if index.try_acquire_writer_lease_with(
+            &holder,
+            Duration::from_secs(30),
+            daemon::holder_is_orphaned,
+        )? {
+            return Ok(true);
+        }
+        if Instant::now() >= deadline {
+            return Ok(false);
+        }
+        std::thread::sleep(Duration::from_millis(10));
+    }
+}
+
+fn build_staging_index(
+    root: &Path,
+    staging: &Path,
+    include_paths: &[PathBuf],
+    git: &GitLocation,
+) -> Result<(), IndexError> {
+    let index = configure_index(root, PersistentIndex::open_or_create(staging)?)?;
+    set_index_root(&index, root)?;
+    index.set_include_paths(include_paths)?;
+    git.store(&index)?;
+    let index = Arc::new(index);
+    smart_scan(root, Arc::clone(&index))?;
+    index.set_meta(
+        daemon::meta_keys::INDEX_STATUS,
+        daemon::index_status::COMPLETE,
+    )?;
+    index.flush()
+}
+
+/// Stop any daemon for `db_path` and replace its content with the snapshot
+/// at `input`. Returns the number of imported files.
+async fn import_snapshot_file(
+    root: &Path,
+    db_path: &Path,
+    input: PathBuf,
+) -> Result<usize, Box<dyn std::error::Error>> {
+    let snapshot = task::spawn_blocking(move || IndexSnapshot::read_file(&input)).await??;
+
+    best_effort_stop_daemon(db_path);
+    if daemon::live_leader_active(db_path).unwrap_or(false) {
+        return Err(another_writer_active().into());
+    }
+
+    let index = Arc::new(open_index_with_worktree_copy(root, db_path)?);
+    let holder = daemon::holder_id("import");
+    let acquired = {
+        let index = Arc::clone(&index);
+        let holder = holder.clone();
+        task::spawn_blocking(move || {
+            index.try_acquire_writer_lease_with(
+                &holder,
+                Duration::from_secs(120),
+                daemon::holder_is_orphaned,
+            )
+        })
+        .await??
+    };
+    if !acquired {
+        return Err(another_writer_active().into());
+    }
+
+    let imported = {
+        let index = Arc::clone(&index);
+        let root = root.to_path_buf();
+        task::spawn_blocking(move || {
+            ensure_settings_as_writer(&index)?;
+            index.import_snapshot(&root, &snapshot)
+        })
+        .await?
+    };
+    let _ = index.release_writer_lease(&holder);
+    Ok(imported?)
+}
+
+// ---------------------------------------------------------------------------
+// Search history & saved queries
+// ---------------------------------------------------------------------------
+
+/// Record an `sf search` run with `args` (everything after `search`) when
+/// `[history] enabled` is set for `root`. Failures are only logged; they
+/// never fail the search.
+pub fn record_search(root: Option<PathBuf>, args: Vec<String>) {
+    let root = resolve_root(root);
+    let config = match IndexConfig::load(&root) {
+        Ok(config) if config.history.enabled => config,
+        Ok(_) => return,
+        Err(err) => {
+            warn!(error = %err, "failed to load index config; not recording search history");
+            return;
+        }
+    };
+    let entry = HistoryEntry {
+        at_ms: now_ms(),
+        args,
+    };
+    if let Err(err) = record_history(&root, entry, config.history.max_entries) {
+        warn!(error = %err, "failed to record search history");
+    }
+}
+
+/// The saved query `name` of `root`, for `sf search --saved`.
+pub fn saved_query(
+    root: Option<PathBuf>,
+    name: &str,
+) -> Result<SavedQuery, Box<dyn std::error::Error>> {
+    let root = resolve_root(root);
+    SavedQueries::load(&root)?
+        .queries
+        .remove(name)
+        .ok_or_else(|| format!("no saved query `{name}`; `sf history --saved` lists them").into())
+}
+
+/// `sf history`: the last `limit` recorded searches (0 for all), oldest
+/// first, or with `saved` the saved queries. `clear` forgets the history.
+pub fn run_history(
+    root: Option<PathBuf>,
+    limit: usize,
+    saved: bool,
+    clear: bool,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let root = resolve_root(root);
+    if clear {
+        clear_history(&root)?;
+        eprintln!("Search history cleared.");
+        return Ok(());
+    }
+    if saved {
+        let saved = SavedQueries::load(&root)?;
+        if saved.queries.is_empty() {
+            eprintln!("No saved queries. Save one with `sf save-query <name> <query>`.");
+        }
+        for (name, query) in &saved.queries {
+            let mut args = query.args.clone();
+            args.push(query.query.clone());
+            println!("{name}\tsf search {}", shell_words(&args));
+        }
+        return Ok(());
+    }
+
+    let entries = read_history(&root)?;
+    if entries.is_empty() {
+        let enabled = IndexConfig::load(&root)
+            .map(|config| config.history.enabled)
+            .unwrap_or(false);
+        if !enabled {
+            eprintln!(
+                "Search history is off; set `enabled = true` under [history] in config.toml."
+            );
+        }
+        return Ok(());
+    }
+    let shown = if limit > 0 { limit } else { entries.len() };
+    let now = now_ms();
+    for entry in &entries[entries.len().saturating_sub(shown)..] {
+        let age = format!("{} ago", format_age(now.saturating_sub(entry.at_ms) / 1000));
+        println!("  {age:>10}  sf search {}", shell_words(&entry.args));
+    }
+    Ok(())
+}
 
+/// `sf save-query`: save `query` with the `sf search` arguments `args`
+/// under `name`, replacing any query of that name, or with `delete` remove
+/// it. `args` are expected to have been checked already.
+pub fn run_save_query(
+    root: Option<PathBuf>,
+    name: String,
+    query: Option<String>,
+    args: Vec<String>,
+    delete: bool,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let root = resolve_root(root);
+    let mut saved = SavedQueries::load(&root)?;
+    if delete {
+        if saved.queries.remove(&name).is_none() {
+            return Err(format!("no saved query `{name}`").into());
+        }
+        saved.save(&root)?;
+        eprintln!("Deleted saved query `{name}`.");
+        return Ok(());
+    }
+    let query = query.ok_or("a query is required")?;
+    let replaced = saved
+        .queries
+        .insert(name.clone(), SavedQuery { query, args })
+        .is_some();
+    saved.save(&root)?;
+    let verb = if replaced { "Updated" } else { "Saved" };
+    eprintln!("{verb} `{name}`; run it with `sf search --saved {name}`.");
     Ok(())
 }
 
+/// `args` joined for display, single-quoting those a shell would split or
+/// expand.
+fn shell_words(args: &[String]) -> String {
+    args.iter()
+        .map(|arg| {
+            let plain = !arg.is_empty()
+                && arg
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || "-_./=:,+@%".contains(c));
+            if plain {
+                arg.clone()
+            } else {
+                format!("'{}'", arg.replace('\'', r"'\''"))
+            }
+        })
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
 // ---------------------------------------------------------------------------
-// Index build & watch commands
+// Audits
 // ---------------------------------------------------------------------------
 
-pub async fn run_index_build(
+/// Report formats of `sf audit`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum AuditFormat {
+    Markdown,
+    Json,
+}
+
+/// A `--fail-on` value: `info`, `warning` or `error`.
+pub fn parse_severity(value: &str) -> Result<Severity, String> {
+    match value {
+        "info" => Ok(Severity::Info),
+        "warning" => Ok(Severity::Warning),
+        "error" => Ok(Severity::Error),
+        _ => Err(format!(
+            "invalid severity `{value}`; use info, warning or error"
+        )),
+    }
+}
+
+/// `sf audit`: evaluate the rules in `rules_path` against the index of
+/// `root` and print the report, or write it to `out`. Returns whether a
+/// finding is at least as severe as `fail_on`, for the exit code.
+pub async fn run_audit(
     root: Option<PathBuf>,
     db: Option<PathBuf>,
-) -> Result<(), Box<dyn std::error::Error>> {
+    rules_path: PathBuf,
+    format: AuditFormat,
+    out: Option<PathBuf>,
+    fail_on: Option<Severity>,
+) -> Result<bool, Box<dyn std::error::Error>> {
     let root = resolve_root(root);
     let db_path = db.unwrap_or_else(|| default_db_path(&root));
+    info!(root = %root.display(), db = %db_path.display(), rules = %rules_path.display(), "audit command requested");
 
-    let was_running = daemon::ensure_daemon(&root, &db_path)?;
-    if was_running {
-        eprintln!("Daemon already running for {}", root.display());
-    } else {
-        eprintln!("Daemon started for {}", root.display());
+    if !db_path.exists() {
+        return Err(format!(
+            "no index found at {}; build one with `sf index build` first",
+            db_path.display()
+        )
+        .into());
     }
-
-    if !daemon::wait_for_daemon(&db_path, Duration::from_secs(5)) {
-        eprintln!("Warning: daemon did not confirm in 5 s");
+    let rules = AuditRules::load(&rules_path)?;
+    let report = task::spawn_blocking(move || {
+        let store = open_content_store(&root, &db_path);
+        audit_database_file(&db_path, &root, &rules, store.as_ref())
+    })
+    .await??;
+
+    let text = match format {
+        AuditFormat::Markdown => report.to_markdown(),
+        AuditFormat::Json => serde_json::to_string_pretty(&report)? + "\n",
+    };
+    match &out {
+        Some(path) => std::fs::write(path, text)?,
+        None => print!("{text}"),
+    }
+    let failing = report
+        .findings
+        .iter()
+        .filter(|finding| fail_on.is_some_and(|fail_on| finding.severity >= fail_on))
+        .count();
+    eprintln!(
+        "{} findings from {} rules",
+        report.findings.len(),
+        report.rules.len()
+    );
+    if failing > 0 {
+        eprintln!("{failing} at or above --fail-on");
     }
+    Ok(failing > 0)
+}
 
-    eprintln!("Index building in background. Use `sf index watch` to monitor progress.");
-    Ok(())
+// ---------------------------------------------------------------------------
+// Replace
+// ---------------------------------------------------------------------------
+
+pub struct ReplaceOpts {
+    pub root: Option<PathBuf>,
+    pub db: Option<PathBuf>,
+    pub pattern: String,
+    pub replacement: String,
+    pub ext: Vec<String>,
+    pub glob: Option<String>,
+    /// Unchanged lines shown around each change.
+    pub context: usize,
+    /// Rewrite the files instead of only previewing the diff.
+    pub write: bool,
 }
 
-pub async fn run_index_watch(
-    root: Option<PathBuf>,
-    db: Option<PathBuf>,
-) -> Result<(), Box<dyn std::error::Error>> {
+/// `sf replace`: replace the pattern in the indexed files under the root
+/// that contain it, printing a unified diff per file. Only with `write` are
+/// the files rewritten and reindexed.
+pub async fn run_replace(opts: ReplaceOpts) -> Result<(), Box<dyn std::error::Error>> {
+    let ReplaceOpts {
+        root,
+        db,
+        pattern,
+        replacement,
+        ext,
+        glob,
+        context,
+        write,
+    } = opts;
     let root = resolve_root(root);
     let db_path = db.unwrap_or_else(|| default_db_path(&root));
+    info!(root = %root.display(), db = %db_path.display(), pattern = %pattern, write, "replace command requested");
 
     if !db_path.exists() {
-        let created = open_index_with_worktree_copy(&root, &db_path)?;
-        drop(created);
+        return Err(format!(
+            "no index found at {}; build one with `sf index build` first",
+            db_path.display()
+        )
+        .into());
     }
-
-    // If a daemon is actively building, attach to its progress instead of killing it.
-    if is_leader_active_readonly(&db_path).unwrap_or(false) {
-        let status = read_meta_readonly(&db_path, daemon::meta_keys::INDEX_STATUS)
-            .ok()
-            .flatten();
-        if status.as_deref() == Some(daemon::index_status::BUILDING) {
-            eprintln!("Daemon is building the index. Attaching to progress...");
-            watch_progress_polling(&db_path);
-            return Ok(());
-        }
+    if pattern.len() < 3 {
+        return Err(
+            "the pattern must be at least 3 characters to be looked up in the index".into(),
+        );
     }
-
-    best_effort_stop_daemon(&db_path);
-
-    let index = Arc::new(open_index_with_worktree_copy(&root, &db_path)?);
-    let holder = {
-        use std::time::{SystemTime, UNIX_EPOCH};
-
-        let nanos = SystemTime::now()
-            .duration_since(UNIX_EPOCH)
-            .unwrap_or_default()
-            .as_nanos();
-        format!("watch:{}:{nanos}", std::process::id())
+    let file_regex = build_file_filter(&None, &ext, &glob)?;
+
+    let edits = {
+        let (root, db_path) = (root.clone(), db_path.clone());
+        task::spawn_blocking(move || -> Result<Vec<FileEdit>, IndexError> {
+            let mut hits =
+                search_database_file_within(&db_path, &pattern, file_regex.as_ref(), None)?.hits;
+            hits.retain(|hit| path_is_within_root(&hit.path, &root));
+            hits.sort_by(|a, b| a.path.cmp(&b.path));
+            plan_replace(&hits, &pattern, &replacement)
+        })
+        .await??
     };
-    let lease_ttl = Duration::from_secs(5);
 
-    let acquired = {
-        let index = Arc::clone(&index);
-        let holder = holder.clone();
-        task::spawn_blocking(move || index.try_acquire_writer_lease(&holder, lease_ttl)).await??
-    };
+    let mut out = io::stdout().lock();
+    for edit in &edits {
+        let label = Path::new(&edit.path)
+            .strip_prefix(&root)
+            .map(|relative| relative.to_string_lossy().replace('\\', "/"))
+            .unwrap_or_else(|_| edit.path.clone());
+        write!(out, "{}", edit.unified_diff(&label, context))?;
+    }
+    out.flush()?;
+    drop(out);
 
-    if !acquired {
-        eprintln!("Another writer is active. Attaching to persisted progress...");
-        watch_progress_polling(&db_path);
+    let replacements: usize = edits.iter().map(|edit| edit.replacements).sum();
+    if !write {
+        eprintln!(
+            "{replacements} replacements in {} files (dry run; pass --write to apply)",
+            edits.len()
+        );
         return Ok(());
     }
 
-    index.set_write_enabled(true);
-    let _ = index.set_meta_queued(
-        daemon::meta_keys::INDEX_STATUS,
-        daemon::index_status::BUILDING,
-    );
-
-    let state = Arc::new(WatchState::new());
-    let initial_progress = watch_snapshot_to_progress(&state.snapshot());
-    queue_progress_meta(&index, &initial_progress);
-    let _ = index.flush();
-
-    let render_state = Arc::clone(&state);
-    let render_handle = std::thread::spawn(move || {
-        let mut first_frame = true;
-        let mut tick = 0usize;
-        loop {
-            let snapshot = render_state.snapshot();
-            let frame = render_watch_frame(&snapshot, tick);
-            print_watch_frame(&frame, first_frame);
-            first_frame = false;
-            tick = tick.wrapping_add(1);
-
-            if snapshot.phase.is_terminal() {
-                break;
-            }
-
-            std::thread::sleep(Duration::from_millis(16));
-        }
-    });
-
-    let renew_index = Arc::clone(&index);
-    let renew_holder = holder.clone();
-    let renew_failed = Arc::new(AtomicUsize::new(0));
-    let renew_failed_for_thread = Arc::clone(&renew_failed);
-    let renew_handle = std::thread::spawn(move || {
-        loop {
-            std::thread::sleep(Duration::from_secs(2));
-            if matches!(
-                renew_index.get_meta(daemon::meta_keys::INDEX_STATUS),
-                Ok(Some(ref status))
-                    if status == daemon::index_status::COMPLETE
-                        || status == daemon::index_status::FAILED
-            ) {
-                break;
-            }
-
-            match renew_index.renew_writer_lease(&renew_holder, lease_ttl) {
-                Ok(true) => {}
-                Ok(false) | Err(_) => {
-                    renew_index.set_write_enabled(false);
-                    renew_failed_for_thread.store(1, Ordering::SeqCst);
-                    break;
-                }
+    let mut written = Vec::with_capacity(edits.len());
+    let mut failed = 0;
+    for edit in &edits {
+        match edit.apply() {
+            Ok(()) => written.push(decode_path(&edit.path)),
+            Err(err) => {
+                eprintln!("skipped {}: {err}", edit.path);
+                failed += 1;
             }
         }
-    });
-
-    let callback_state = Arc::clone(&state);
-    let progress_callback: Arc<dyn Fn(ScanEvent) + Send + Sync> = Arc::new(move |event| {
-        callback_state.apply_event(event);
-    });
-
-    let scan_result = {
-        let scan_root = root.clone();
-        let scan_index = Arc::clone(&index);
-        task::spawn_blocking(move || {
-            smart_scan_with_progress(&scan_root, scan_index, progress_callback)
-        })
-        .await?
-    };
-
-    if renew_failed.load(Ordering::SeqCst) != 0 {
-        state.set_phase(IndexPhase::Failed);
-    } else if scan_result.is_ok() {
-        state.set_phase(IndexPhase::Complete);
-    } else {
-        state.set_phase(IndexPhase::Failed);
     }
-
-    let final_snapshot = state.snapshot();
-    let final_progress = watch_snapshot_to_progress(&final_snapshot);
-    queue_progress_meta(&index, &final_progress);
-    let final_status = if final_snapshot.phase == IndexPhase::Complete {
-        daemon::index_status::COMPLETE
-    } else {
-        daemon::index_status::FAILED
-    };
-    let _ = index.set_meta_queued(daemon::meta_keys::INDEX_STATUS, final_status);
-    let _ = index.flush();
-
-    let _ = renew_handle.join();
-    let _ = render_handle.join();
-    print_watch_summary(&final_snapshot);
-
-    index.set_write_enabled(false);
-    let _ = index.release_writer_lease(&holder);
-
-    if renew_failed.load(Ordering::SeqCst) != 0 {
-        return Err("foreground watch lost the writer lease before completion".into());
+    eprintln!(
+        "{replacements} replacements written to {} files",
+        written.len()
+    );
+    if !written.is_empty() && !reindex_paths(&root, &db_path, written).await? {
+        eprintln!("another index writer is running; it picks up the changes");
+    }
+    if failed > 0 {
+        return Err(
+            format!("{failed} files changed while replacing; nothing was written to them").into(),
+        );
     }
-
-    scan_result?;
-
-    // Restart daemon for background file watching.
-    drop(index);
-    let _ = daemon::spawn_daemon(&root, &db_path);
-
     Ok(())
 }
 
-fn format_progress_line(p: &IndexProgress, status: &str) -> String {
-    let mode = p.mode.as_deref().unwrap_or("scanning");
-
-    let files_part = match p.total_files {
-        Some(total) if total > 0 => {
-            let pct = (p.processed_files as f64 / total as f64 * 100.0).min(100.0);
-            format!("{}/{} files ({pct:.0}%)", p.processed_files, total)
-        }
-        _ => format!("{} files", p.processed_files),
-    };
-
-    let bytes_part = match p.total_bytes {
-        Some(total) if total > 0 => {
-            format!(
-                " {}/{}",
-                format_bytes(p.processed_bytes),
-                format_bytes(total)
+/// Reindex `paths` after `sf replace --write`. A live daemon or server
+/// applies them through its watcher instead; returns `false` then.
+async fn reindex_paths(
+    root: &Path,
+    db_path: &Path,
+    paths: Vec<PathBuf>,
+) -> Result<bool, Box<dyn std::error::Error>> {
+    if daemon::live_leader_active(db_path).unwrap_or(false) {
+        return Ok(false);
+    }
+    let index = Arc::new(open_index_with_worktree_copy(root, db_path)?);
+    let holder = daemon::holder_id("replace");
+    let acquired = {
+        let index = Arc::clone(&index);
+        let holder = holder.clone();
+        task::spawn_blocking(move || {
+            index.try_acquire_writer_lease_with(
+                &holder,
+                Duration::from_secs(30),
+                daemon::holder_is_orphaned,
             )
-        }
-        _ if p.processed_bytes > 0 => format!(" {}", format_bytes(p.processed_bytes)),
-        _ => String::new(),
-    };
-
-    let bar = match p.total_files {
-        Some(total) if total > 0 => {
-            let ratio = (p.processed_files as f64 / total as f64).min(1.0);
-            let width = 30;
-            let filled = (ratio * width as f64) as usize;
-            let empty = width - filled;
-            format!(" [{}{}]", "█".repeat(filled), "░".repeat(empty))
-        }
-        _ => String::new(),
+        })
+        .await??
     };
-
-    let file_name = p
-        .current_path
-        .as_deref()
-        .or(p.last_completed_path.as_deref())
-        .map(|path| {
-            let name = path.rsplit(['/', '\\']).next().unwrap_or(path);
-            if name.len() > 40 {
-                format!("{}...", &name[..37])
-            } else {
-                name.to_string()
+    if !acquired {
+        return Ok(false);
+    }
+    let reindexed = {
+        let index = Arc::clone(&index);
+        task::spawn_blocking(move || -> Result<(), IndexError> {
+            ensure_settings_as_writer(&index)?;
+            for path in &paths {
+                index.index_path(path)?;
             }
+            index.flush()
         })
-        .unwrap_or_default();
-
-    let eta = if status == daemon::index_status::COMPLETE {
-        " done".to_string()
-    } else {
-        match estimate_eta_seconds(p) {
-            Some(secs) => format!(" ETA {}", format_eta(secs)),
-            None => String::new(),
-        }
+        .await?
     };
-
-    format!("\x1b[36m{mode}\x1b[0m{bar} {files_part}{bytes_part}{eta} \x1b[2m{file_name}\x1b[0m")
-}
-
-fn format_bytes(bytes: u64) -> String {
-    if bytes < 1024 {
-        format!("{bytes} B")
-    } else if bytes < 1024 * 1024 {
-        format!("{:.1} KB", bytes as f64 / 1024.0)
-    } else if bytes < 1024 * 1024 * 1024 {
-        format!("{:.1} MB", bytes as f64 / (1024.0 * 1024.0))
-    } else {
-        format!("{:.1} GB", bytes as f64 / (1024.0 * 1024.0 * 1024.0))
-    }
+    let _ = index.release_writer_lease(&holder);
+    reindexed?;
+    Ok(true)
 }
@@ -1,16 +1,20 @@
 use std::path::{Path, PathBuf};
-use std::sync::Arc;
 use std::sync::atomic::{AtomicBool, Ordering};
-use std::sync::mpsc;
+use std::sync::{Arc, Mutex, mpsc};
 use std::time::{Duration, Instant};
 
 use serde::{Deserialize, Serialize};
-use source_fast_core::{IndexError, PersistentIndex};
-use source_fast_fs::{background_watcher_with_cancel, smart_scan_with_progress_cancel};
-use source_fast_progress::{IndexProgress, ScanEvent};
+use source_fast_core::{IndexConfig, IndexError, PersistentIndex, WatcherConfig};
+use source_fast_fs::{
+    background_watcher_with_health, reconcile_periodically, set_indexing_paused,
+    smart_scan_prioritized,
+};
+use source_fast_progress::{IndexProgress, ScanEvent, WatcherHealth, WatcherState};
 use tokio::task;
 use tracing::{debug, error, info, warn};
 
+use crate::cli::GLOBAL_ROOT_MARKER;
+
 /// Meta keys used for daemon IPC via LMDB metadata.
 pub mod meta_keys {
     pub const SHUTDOWN_REQUESTED: &str = "shutdown_requested";
@@ -18,6 +22,7 @@ pub mod meta_keys {
     pub const INDEX_PROGRESS: &str = "index_progress";
     pub const DAEMON_PID: &str = "daemon_pid";
     pub const DAEMON_VERSION: &str = "daemon_version";
+    pub const WATCHER_HEALTH: &str = "watcher_health";
 }
 
 pub mod index_status {
@@ -26,16 +31,19 @@ pub mod index_status {
     pub const BUILDING: &str = IndexPhase::Building.as_str();
     pub const COMPLETE: &str = IndexPhase::Complete.as_str();
     pub const FAILED: &str = IndexPhase::Failed.as_str();
+    pub const PAUSED: &str = IndexPhase::Paused.as_str();
 }
 
 /// Information about a running daemon discovered from the leader table.
 #[derive(Debug)]
 pub struct DaemonInfo {
     pub root: PathBuf,
+    pub db_path: PathBuf,
     pub pid: Option<u32>,
     pub version: Option<String>,
     pub index_status: Option<String>,
     pub progress: Option<IndexProgress>,
+    pub watcher: Option<WatcherHealth>,
     pub leader_holder: Option<String>,
     pub leader_expires_ms: Option<i64>,
 }
@@ -93,12 +101,175 @@ fn now_ms() -> u64 {
 }
 
 pub(crate) fn writer_holder_id() -> String {
+    holder_id("pid")
+}
+
+/// A writer lease holder id, `<role>:<pid>@<host>:<nanos>`. The pid and
+/// host let [`holder_is_orphaned`] recognise a lease left by a crash.
+pub(crate) fn holder_id(role: &str) -> String {
     use std::time::{SystemTime, UNIX_EPOCH};
     let nanos = SystemTime::now()
         .duration_since(UNIX_EPOCH)
         .unwrap_or_default()
         .as_nanos();
-    format!("pid:{}:{nanos}", std::process::id())
+    format!("{role}:{}@{}:{nanos}", std::process::id(), host_name())
+}
+
+/// Whether `holder` names a process on this machine that no longer runs.
+/// Holders from other machines, or in an older id format, are never
+/// orphaned; their leases still expire after the TTL.
+pub(crate) fn holder_is_orphaned(holder: &str) -> bool {
+    let Some((pid, host)) = holder_process(holder) else {
+        return false;
+    };
+    host == host_name() && pid != std::process::id() && !process_alive(pid)
+}
+
+/// The pid and host of a [`holder_id`].
+fn holder_process(holder: &str) -> Option<(u32, &str)> {
+    let (_, rest) = holder.split_once(':')?;
+    let (pid, rest) = rest.split_once('@')?;
+    let (host, _) = rest.rsplit_once(':')?;
+    Some((pid.parse().ok()?, host))
+}
+
+fn host_name() -> String {
+    #[cfg(unix)]
+    {
+        let mut buf = [0u8; 256];
+        // Safety: the buffer is valid for its full length; gethostname
+        // truncates longer names.
+        if unsafe { libc::gethostname(buf.as_mut_ptr().cast(), buf.len()) } == 0 {
+            let len = buf.iter().position(|&b| b == 0).unwrap_or(buf.len());
+            return String::from_utf8_lossy(&buf[..len]).into_owned();
+        }
+        String::new()
+    }
+    #[cfg(windows)]
+    {
+        std::env::var("COMPUTERNAME").unwrap_or_default()
+    }
+}
+
+#[cfg(unix)]
+fn process_alive(pid: u32) -> bool {
+    let Ok(pid) = libc::pid_t::try_from(pid) else {
+        return true;
+    };
+    // Safety: signal 0 only checks that the process exists.
+    if unsafe { libc::kill(pid, 0) } == 0 {
+        return true;
+    }
+    std::io::Error::last_os_error().raw_os_error() != Some(libc::ESRCH)
+}
+
+#[cfg(windows)]
+fn process_alive(pid: u32) -> bool {
+    const PROCESS_QUERY_LIMITED_INFORMATION: u32 = 0x1000;
+    const ERROR_INVALID_PARAMETER: i32 = 87;
+    const STILL_ACTIVE: u32 = 259;
+
+    unsafe extern "system" {
+        fn OpenProcess(dwDesiredAccess: u32, bInheritHandle: i32, dwProcessId: u32) -> isize;
+        fn GetExitCodeProcess(hProcess: isize, lpExitCode: *mut u32) -> i32;
+        fn CloseHandle(hObject: isize) -> i32;
+    }
+
+    // Safety: the handle is checked before use and closed afterwards.
+    unsafe {
+        let handle = OpenProcess(PROCESS_QUERY_LIMITED_INFORMATION, 0, pid);
+        if handle == 0 {
+            return std::io::Error::last_os_error().raw_os_error() != Some(ERROR_INVALID_PARAMETER);
+        }
+        let mut code = 0u32;
+        let ok = GetExitCodeProcess(handle, &mut code);
+        CloseHandle(handle);
+        ok == 0 || code == STILL_ACTIVE
+    }
+}
+
+/// Resolve on Ctrl-C or, on unix, SIGTERM.
+async fn termination_signal() {
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{SignalKind, signal};
+        match signal(SignalKind::terminate()) {
+            Ok(mut terminate) => {
+                tokio::select! {
+                    _ = tokio::signal::ctrl_c() => {}
+                    _ = terminate.recv() => {}
+                }
+            }
+            Err(_) => {
+                let _ = tokio::signal::ctrl_c().await;
+            }
+        }
+    }
+    #[cfg(windows)]
+    {
+        let _ = tokio::signal::ctrl_c().await;
+    }
+}
+
+/// Pause indexing of the indexes `served` returns on `SIGUSR1` and resume
+/// it on `SIGUSR2`, e.g. around a large checkout or build; see
+/// [`set_indexing_paused`]. Runs until the process exits.
+#[cfg(unix)]
+pub(crate) async fn pause_on_signals(served: impl Fn() -> Vec<Arc<PersistentIndex>>) {
+    use tokio::signal::unix::{SignalKind, signal};
+    let (Ok(mut pause), Ok(mut resume)) = (
+        signal(SignalKind::user_defined1()),
+        signal(SignalKind::user_defined2()),
+    ) else {
+        warn!("cannot listen for SIGUSR1/SIGUSR2, indexing cannot be paused by signal");
+        return;
+    };
+    loop {
+        let paused = tokio::select! {
+            _ = pause.recv() => true,
+            _ = resume.recv() => false,
+        };
+        for index in served() {
+            let result = task::spawn_blocking(move || set_indexing_paused(&index, paused)).await;
+            if let Ok(Err(err)) = result {
+                warn!(error = %err, paused, "failed to record indexing pause");
+            }
+        }
+        info!(paused, "indexing pause changed by signal");
+    }
+}
+
+/// Whether a live writer holds the lease of `db_path`. Unlike
+/// [`source_fast_core::is_leader_active_readonly`], a lease left by a
+/// crashed process on this machine does not count.
+pub(crate) fn live_leader_active(db_path: &Path) -> source_fast_core::IndexResult<bool> {
+    Ok(source_fast_core::read_leader_readonly(db_path)?
+        .is_some_and(|(holder, _)| !holder_is_orphaned(&holder)))
+}
+
+/// Whether a live writer other than the daemon recorded in `db_path`,
+/// such as an `sf server`, holds its lease: one stopping the daemon leaves
+/// in place.
+pub(crate) fn foreign_writer_active(db_path: &Path) -> source_fast_core::IndexResult<bool> {
+    let Some((holder, _)) = source_fast_core::read_leader_readonly(db_path)? else {
+        return Ok(false);
+    };
+    if holder_is_orphaned(&holder) {
+        return Ok(false);
+    }
+    let daemon_pid = source_fast_core::read_meta_readonly(db_path, meta_keys::DAEMON_PID)?
+        .and_then(|pid| pid.parse::<u32>().ok());
+    Ok(!holder_process(&holder)
+        .is_some_and(|(pid, host)| host == host_name() && Some(pid) == daemon_pid))
+}
+
+/// Whether a live writer's file watcher keeps the index current, from the
+/// lease holder `leader` and the persisted [`WatcherHealth`] JSON.
+pub(crate) fn watcher_keeps_current(leader: Option<&str>, health: Option<&str>) -> bool {
+    leader.is_some_and(|holder| !holder_is_orphaned(holder))
+        && health
+            .and_then(|json| serde_json::from_str::<WatcherHealth>(json).ok())
+            .is_some_and(|health| health.state == WatcherState::Watching)
 }
 
 pub(crate) async fn try_acquire_writer_lease(
@@ -107,7 +278,16 @@ pub(crate) async fn try_acquire_writer_lease(
     lease_ttl: Duration,
     component: &'static str,
 ) -> bool {
-    match task::spawn_blocking(move || index.try_acquire_writer_lease(&holder, lease_ttl)).await {
+    match task::spawn_blocking(move || {
+        let acquired =
+            index.try_acquire_writer_lease_with(&holder, lease_ttl, holder_is_orphaned)?;
+        if acquired && let Err(err) = crate::cli::ensure_settings_as_writer(&index) {
+            warn!(component, error = %err, "failed to apply index settings as writer");
+        }
+        Ok::<_, IndexError>(acquired)
+    })
+    .await
+    {
         Ok(Ok(v)) => v,
         Ok(Err(err)) => {
             warn!(component, error = %err, "writer lease acquire failed");
@@ -199,20 +379,101 @@ impl ProgressWriter {
     }
 }
 
+/// A scan progress callback that folds events into an [`IndexProgress`]
+/// and persists it, throttled, for the MCP `index_status` tool.
+pub(crate) fn progress_recorder(
+    index: Arc<PersistentIndex>,
+) -> Arc<dyn Fn(ScanEvent) + Send + Sync> {
+    let progress = IndexProgress::building(now_ms());
+    let mut writer = ProgressWriter::new(index);
+    writer.persist(&progress, true);
+    let state = Mutex::new((progress, writer));
+    Arc::new(move |event| {
+        let force = matches!(event, ScanEvent::Finished | ScanEvent::Failed);
+        let mut state = state
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        let (progress, writer) = &mut *state;
+        progress.apply_event(event, now_ms());
+        writer.persist(progress, force);
+    })
+}
+
+/// A watcher health callback that persists every report for `sf status`.
+pub(crate) fn watcher_health_recorder(
+    index: Arc<PersistentIndex>,
+) -> Arc<dyn Fn(WatcherHealth) + Send + Sync> {
+    Arc::new(move |health| {
+        if let Ok(json) = serde_json::to_string(&health) {
+            let _ = index.set_meta_queued(meta_keys::WATCHER_HEALTH, &json);
+        }
+    })
+}
+
+/// Start the periodic reconciliation scan configured in the root's
+/// `[reconcile]` section, until `cancel` is set. Runs after the initial
+/// scan so the two never overlap.
+pub(crate) fn spawn_reconcile(root: PathBuf, index: Arc<PersistentIndex>, cancel: Arc<AtomicBool>) {
+    let config = IndexConfig::load(&root).unwrap_or_else(|err| {
+        warn!(root = %root.display(), error = %err, "invalid config, using default reconcile interval");
+        IndexConfig::default()
+    });
+    if let Some(interval) = config.reconcile.interval() {
+        info!(root = %root.display(), interval_secs = interval.as_secs(), "reconciliation scans enabled");
+        task::spawn(reconcile_periodically(root, index, interval, cancel));
+    }
+}
+
+/// Preload the postings of the most common tokens within the root's
+/// `[warmup] budget_mb`, so the first searches after a scan do not read
+/// them from disk. Does nothing when the budget is 0.
+pub(crate) fn spawn_warm_up(root: &Path, index: Arc<PersistentIndex>) {
+    let config = IndexConfig::load(root).unwrap_or_else(|err| {
+        warn!(root = %root.display(), error = %err, "invalid config, skipping index warm-up");
+        IndexConfig::default()
+    });
+    let Some(budget) = config.warmup.budget_bytes() else {
+        return;
+    };
+    task::spawn_blocking(move || match index.warm_up(budget) {
+        Ok(warmed) => info!(
+            tokens = warmed.tokens,
+            bytes = warmed.bytes,
+            "index warm-up finished"
+        ),
+        Err(err) => warn!(error = %err, "index warm-up failed"),
+    });
+}
+
+/// The root's `[watcher]` settings, with the watch mode overridden by
+/// `--watch-mode` or `SOURCE_FAST_WATCH_MODE`.
+pub(crate) fn watcher_config(root: &Path) -> WatcherConfig {
+    let mut config = IndexConfig::load(root)
+        .unwrap_or_else(|err| {
+            warn!(root = %root.display(), error = %err, "invalid config, using default watcher settings");
+            IndexConfig::default()
+        })
+        .watcher;
+    if let Some(mode) = crate::cli::watch_mode_override() {
+        config.mode = mode;
+    }
+    config
+}
+
 /// The actual daemon main loop (invoked by `sf _daemon`).
 /// Extracted from the MCP server's election loop in mcp.rs.
 pub async fn run_daemon(root: PathBuf, db_path: PathBuf) -> Result<(), Box<dyn std::error::Error>> {
     init_daemon_tracing(&db_path);
 
     info!(root = %root.display(), db = %db_path.display(), "daemon starting");
+    crate::cli::apply_background_nice();
 
     let index = Arc::new(crate::cli::open_index_with_worktree_copy(&root, &db_path)?);
-
-    // Clear stale state from a previous run.
-    index.set_meta(meta_keys::SHUTDOWN_REQUESTED, "false")?;
-    index.set_meta(meta_keys::INDEX_STATUS, index_status::BUILDING)?;
-    index.set_meta(meta_keys::DAEMON_PID, &std::process::id().to_string())?;
-    index.set_meta(meta_keys::DAEMON_VERSION, env!("CARGO_PKG_VERSION"))?;
+    #[cfg(unix)]
+    task::spawn(pause_on_signals({
+        let index = Arc::clone(&index);
+        move || vec![Arc::clone(&index)]
+    }));
 
     // Register in the global daemon list.
     let _ = register_daemon(&root, &db_path, std::process::id());
@@ -222,7 +483,23 @@ pub async fn run_daemon(root: PathBuf, db_path: PathBuf) -> Result<(), Box<dyn s
     let lease_ttl = Duration::from_secs(5);
     let is_writer = Arc::new(AtomicBool::new(false));
     let index_ready = Arc::new(AtomicBool::new(false));
-    persist_progress(&index, &IndexProgress::building(now_ms()));
+
+    let rpc_task = task::spawn(crate::rpc::serve(db_path.clone(), Arc::clone(&index)));
+    let mcp_task = task::spawn(crate::mcp::serve_shared(
+        db_path.clone(),
+        root.clone(),
+        Arc::clone(&index),
+        Arc::clone(&index_ready),
+    ));
+
+    let interrupted = Arc::new(AtomicBool::new(false));
+    {
+        let interrupted = Arc::clone(&interrupted);
+        task::spawn(async move {
+            termination_signal().await;
+            interrupted.store(true, Ordering::SeqCst);
+        });
+    }
 
     let mut writer_started = false;
     let mut writer_cancel: Option<Arc<AtomicBool>> = None;
@@ -245,6 +522,14 @@ pub async fn run_daemon(root: PathBuf, db_path: PathBuf) -> Result<(), Box<dyn s
             info!("daemon: shutdown requested via meta, exiting gracefully");
             break;
         }
+        if index.is_replaced().unwrap_or(false) {
+            info!("daemon: index replaced by a rebuild, exiting");
+            break;
+        }
+        if interrupted.load(Ordering::SeqCst) {
+            info!("daemon: terminated by signal, exiting gracefully");
+            break;
+        }
 
         // ---- Leader election ----
         if !is_writer.load(Ordering::SeqCst) {
@@ -254,6 +539,14 @@ pub async fn run_daemon(root: PathBuf, db_path: PathBuf) -> Result<(), Box<dyn s
 
             if acquired {
                 index.set_write_enabled(true);
+                // Clear stale state from a previous run. Only the writer
+                // does, so a daemon that gives up leaves the running one's
+                // pid and status alone.
+                index.set_meta(meta_keys::SHUTDOWN_REQUESTED, "false")?;
+                index.set_meta(meta_keys::INDEX_STATUS, index_status::BUILDING)?;
+                index.set_meta(meta_keys::DAEMON_PID, &std::process::id().to_string())?;
+                index.set_meta(meta_keys::DAEMON_VERSION, env!("CARGO_PKG_VERSION"))?;
+                persist_progress(&index, &IndexProgress::building(now_ms()));
                 is_writer.store(true, Ordering::SeqCst);
                 give_up_count = 0;
                 info!(role = "writer", "daemon promoted role=writer");
@@ -280,6 +573,9 @@ pub async fn run_daemon(root: PathBuf, db_path: PathBuf) -> Result<(), Box<dyn s
                 let ready_for_scan = Arc::clone(&index_ready);
                 let index_for_progress = Arc::clone(&index);
                 let cancel_for_scan = Arc::clone(&cancel);
+                let index_for_reconcile = Arc::clone(&index);
+                let root_for_reconcile = root.clone();
+                let cancel_for_reconcile = Arc::clone(&cancel);
                 task::spawn(async move {
                     let (progress_tx, progress_rx) = mpsc::channel::<ScanEvent>();
                     let progress_thread = std::thread::spawn(move || {
@@ -310,7 +606,7 @@ pub async fn run_daemon(root: PathBuf, db_path: PathBuf) -> Result<(), Box<dyn s
                             let _ = progress_tx.send(event);
                         });
                     let res = task::spawn_blocking(move || {
-                        smart_scan_with_progress_cancel(
+                        smart_scan_prioritized(
                             &root_for_scan,
                             index_for_scan,
                             progress_callback,
@@ -325,6 +621,12 @@ pub async fn run_daemon(root: PathBuf, db_path: PathBuf) -> Result<(), Box<dyn s
                             drop(final_progress_tx);
                             let _ = progress_thread.join();
                             info!("daemon: initial index build completed");
+                            spawn_warm_up(&root_for_reconcile, Arc::clone(&index_for_reconcile));
+                            spawn_reconcile(
+                                root_for_reconcile,
+                                index_for_reconcile,
+                                cancel_for_reconcile,
+                            );
                         }
                         Ok(Err(IndexError::Cancelled)) => {
                             drop(final_progress_tx);
@@ -371,10 +673,14 @@ pub async fn run_daemon(root: PathBuf, db_path: PathBuf) -> Result<(), Box<dyn s
                 let root_for_watcher = root.clone();
                 let cancel_for_watcher = Arc::clone(&cancel);
                 task::spawn(async move {
-                    if let Err(err) = background_watcher_with_cancel(
+                    let health = watcher_health_recorder(Arc::clone(&index_for_watcher));
+                    let config = watcher_config(&root_for_watcher);
+                    if let Err(err) = background_watcher_with_health(
                         root_for_watcher,
                         index_for_watcher,
                         cancel_for_watcher,
+                        config,
+                        health,
                     )
                     .await
                     {
@@ -407,6 +713,10 @@ pub async fn run_daemon(root: PathBuf, db_path: PathBuf) -> Result<(), Box<dyn s
     if let Some(cancel) = writer_cancel.take() {
         cancel.store(true, Ordering::SeqCst);
     }
+    for endpoint in [rpc_task, mcp_task] {
+        endpoint.abort();
+        let _ = endpoint.await;
+    }
     let _ = index.release_writer_lease(&holder);
     let _ = deregister_daemon(&root);
     let shutdown_file = shutdown_signal_path(&db_path);
@@ -456,7 +766,17 @@ pub fn spawn_daemon(root: &Path, db_path: &Path) -> Result<(), Box<dyn std::erro
             SetHandleInformation(stderr_h, HANDLE_FLAG_INHERIT, 0);
         }
 
-        let result = Command::new(&exe)
+        let mut command = Command::new(&exe);
+        if let Some(mode) = crate::cli::watch_mode_override() {
+            command.env(crate::cli::WATCH_MODE_ENV, mode.as_str());
+        }
+        if let Some(threads) = crate::cli::threads_override() {
+            command.env(crate::cli::THREADS_ENV, threads.to_string());
+        }
+        if crate::cli::background_nice() {
+            command.env(crate::cli::BACKGROUND_NICE_ENV, "1");
+        }
+        let result = command
             .arg("_daemon")
             .arg("--root")
             .arg(root)
@@ -483,8 +803,18 @@ pub fn spawn_daemon(root: &Path, db_path: &Path) -> Result<(), Box<dyn std::erro
 
         // Safety: setsid() is safe to call in a pre_exec hook. It creates a new
         // session so the child isn't killed when the parent terminal closes.
+        let mut command = Command::new(&exe);
+        if let Some(mode) = crate::cli::watch_mode_override() {
+            command.env(crate::cli::WATCH_MODE_ENV, mode.as_str());
+        }
+        if let Some(threads) = crate::cli::threads_override() {
+            command.env(crate::cli::THREADS_ENV, threads.to_string());
+        }
+        if crate::cli::background_nice() {
+            command.env(crate::cli::BACKGROUND_NICE_ENV, "1");
+        }
         unsafe {
-            Command::new(&exe)
+            command
                 .arg("_daemon")
                 .arg("--root")
                 .arg(root)
@@ -556,7 +886,7 @@ pub fn ensure_daemon(root: &Path, db_path: &Path) -> Result<bool, Box<dyn std::e
     }
 
     // Check if a leader (daemon or MCP server) is already active.
-    if source_fast_core::is_leader_active_readonly(db_path)? {
+    if live_leader_active(db_path)? {
         info!(db = %db_path.display(), "leader already active, reusing existing daemon");
         return Ok(true);
     }
@@ -712,6 +1042,8 @@ pub fn daemon_status(db_path: &Path) -> Result<Option<DaemonInfo>, Box<dyn std::
     let idx_status = source_fast_core::read_meta_readonly(db_path, meta_keys::INDEX_STATUS)?;
     let progress = source_fast_core::read_meta_readonly(db_path, meta_keys::INDEX_PROGRESS)?
         .and_then(|json| serde_json::from_str(&json).ok());
+    let watcher = source_fast_core::read_meta_readonly(db_path, meta_keys::WATCHER_HEALTH)?
+        .and_then(|json| serde_json::from_str(&json).ok());
 
     if leader_info.is_none() && pid.is_none() {
         debug!(db = %db_path.display(), "daemon status found no leader and no recorded pid");
@@ -724,18 +1056,24 @@ pub fn daemon_status(db_path: &Path) -> Result<Option<DaemonInfo>, Box<dyn std::
         .into_iter()
         .find(|e| e.db_path == db_path_str)
         .map(|e| PathBuf::from(e.root));
+    let marker_root = db_path
+        .parent()
+        .and_then(|dir| std::fs::read_to_string(dir.join(GLOBAL_ROOT_MARKER)).ok())
+        .map(PathBuf::from);
     let info = DaemonInfo {
-        root: registry_root.unwrap_or_else(|| {
+        root: registry_root.or(marker_root).unwrap_or_else(|| {
             db_path
                 .parent()
                 .and_then(|p| p.parent())
                 .unwrap_or(Path::new("."))
                 .to_path_buf()
         }),
+        db_path: db_path.to_path_buf(),
         pid,
         version,
         index_status: idx_status,
         progress,
+        watcher,
         leader_holder: leader_info.as_ref().map(|(h, _)| h.clone()),
         leader_expires_ms: leader_info.map(|(_, e)| e),
     };
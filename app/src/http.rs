@@ -0,0 +1,353 @@
+//! HTTP JSON API (`sf serve-http`) for web UIs and editor plugins that
+//! cannot speak MCP over stdio.
+//!
+//! The server does not own the index: it makes sure a daemon keeps the
+//! index up to date and answers requests the same way the CLI does.
+
+use std::error::Error;
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
+
+use axum::extract::{Query, State};
+use axum::http::{HeaderMap, StatusCode, header};
+use axum::response::{IntoResponse, Response};
+use axum::routing::{get, post};
+use axum::{Json, Router};
+use serde::Deserialize;
+use serde_json::{Value, json};
+use source_fast_core::{
+    DEFAULT_READ_CONCURRENCY, METRICS, MatchFilter, MetricsSnapshot, path_is_within_root,
+    read_meta_readonly, search_database_file_within, search_files_in_database, verify_hits_limited,
+};
+use tokio::task;
+use tracing::{info, warn};
+
+use crate::cli::{
+    build_file_filter, default_db_path, diagnose_no_results, file_suggestions, rebuild_index,
+    resolve_root, search_results_json, set_generation,
+};
+use crate::{daemon, rpc};
+
+struct HttpState {
+    root: PathBuf,
+    db_path: PathBuf,
+    /// Set while a `/reindex` rebuild runs; rebuilds share a staging
+    /// directory, so only one may run at a time.
+    rebuilding: AtomicBool,
+}
+
+/// Clears [`HttpState::rebuilding`] when the rebuild task ends.
+struct RebuildGuard(Arc<HttpState>);
+
+impl Drop for RebuildGuard {
+    fn drop(&mut self) {
+        self.0.rebuilding.store(false, Ordering::SeqCst);
+    }
+}
+
+struct ApiError(StatusCode, String);
+
+impl IntoResponse for ApiError {
+    fn into_response(self) -> Response {
+        (self.0, Json(json!({ "error": self.1 }))).into_response()
+    }
+}
+
+type ApiResult = Result<Json<Value>, ApiError>;
+
+fn bad_request(err: impl ToString) -> ApiError {
+    ApiError(StatusCode::BAD_REQUEST, err.to_string())
+}
+
+fn internal(err: impl ToString) -> ApiError {
+    ApiError(StatusCode::INTERNAL_SERVER_ERROR, err.to_string())
+}
+
+#[derive(Deserialize)]
+struct SearchParams {
+    q: String,
+    /// Comma-separated extensions, e.g. `rs,cs`.
+    #[serde(default)]
+    ext: Option<String>,
+    #[serde(default)]
+    glob: Option<String>,
+    #[serde(default)]
+    file_regex: Option<String>,
+    /// Maximum number of results (0 = unlimited).
+    #[serde(default = "default_http_limit")]
+    limit: usize,
+    /// Latency budget; partial results are marked `"truncated": true`.
+    #[serde(default)]
+    timeout_ms: Option<u64>,
+    /// Skip confirming that each file contains the query verbatim.
+    #[serde(default)]
+    no_verify: bool,
+}
+
+fn default_http_limit() -> usize {
+    50
+}
+
+#[derive(Deserialize)]
+struct SearchFileParams {
+    pattern: String,
+}
+
+async fn search(
+    State(state): State<Arc<HttpState>>,
+    Query(params): Query<SearchParams>,
+) -> ApiResult {
+    let ext: Vec<String> = params
+        .ext
+        .iter()
+        .flat_map(|ext| ext.split(','))
+        .filter(|ext| !ext.is_empty())
+        .map(str::to_string)
+        .collect();
+    let file_regex =
+        build_file_filter(&params.file_regex, &ext, &params.glob).map_err(bad_request)?;
+
+    let body = task::spawn_blocking(move || {
+        let timeout = params.timeout_ms.map(Duration::from_millis);
+        let outcome =
+            match rpc::search_within(&state.db_path, &params.q, file_regex.as_ref(), timeout) {
+                Some(outcome) => outcome,
+                None => search_database_file_within(
+                    &state.db_path,
+                    &params.q,
+                    file_regex.as_ref(),
+                    timeout,
+                )
+                .map_err(internal)?,
+            };
+        let mut hits: Vec<_> = outcome
+            .hits
+            .into_iter()
+            .filter(|hit| path_is_within_root(&hit.path, &state.root))
+            .collect();
+        hits.sort_by(|a, b| a.path.cmp(&b.path));
+        let candidates = hits.len();
+        let store = crate::cli::open_content_store(&state.root, &state.db_path);
+        if !params.no_verify {
+            hits = verify_hits_limited(
+                hits,
+                &params.q,
+                store.as_ref(),
+                DEFAULT_READ_CONCURRENCY,
+                MatchFilter::default(),
+                params.limit,
+            );
+        }
+
+        let limit = if params.limit == 0 {
+            hits.len()
+        } else {
+            params.limit
+        };
+        let mut body = search_results_json(
+            &hits,
+            &params.q,
+            limit,
+            store.as_ref(),
+            DEFAULT_READ_CONCURRENCY,
+            MatchFilter::default(),
+        );
+        if hits.is_empty()
+            && !outcome.truncated
+            && let Some(no_results) = diagnose_no_results(&state.db_path, &params.q, candidates)
+        {
+            body["diagnostics"] = no_results.to_json();
+        }
+        body["truncated"] = outcome.truncated.into();
+        set_generation(&mut body, outcome.generation);
+        body["index_status"] = index_status(&state).into();
+        Ok::<_, ApiError>(body)
+    })
+    .await
+    .map_err(internal)??;
+    Ok(Json(body))
+}
+
+async fn search_file(
+    State(state): State<Arc<HttpState>>,
+    Query(params): Query<SearchFileParams>,
+) -> ApiResult {
+    let body = task::spawn_blocking(move || {
+        let hits = search_files_in_database(&state.db_path, &params.pattern).map_err(internal)?;
+        let results: Vec<Value> = hits
+            .iter()
+            .filter(|hit| path_is_within_root(&hit.path, &state.root))
+            .map(|hit| json!({ "path": hit.path, "file_id": hit.file_id }))
+            .collect();
+        let mut body = json!({
+            "pattern": params.pattern,
+            "total": results.len(),
+            "results": results,
+            "index_status": index_status(&state),
+        });
+        if results.is_empty() {
+            let suggestions = file_suggestions(&state.db_path, &state.root, &params.pattern);
+            body["suggestions"] = json!(suggestions);
+        }
+        Ok::<_, ApiError>(body)
+    })
+    .await
+    .map_err(internal)??;
+    Ok(Json(body))
+}
+
+async fn status(State(state): State<Arc<HttpState>>) -> ApiResult {
+    let body = task::spawn_blocking(move || {
+        let info = daemon::daemon_status(&state.db_path).map_err(|err| err.to_string());
+        let mut body = json!({
+            "root": state.root.display().to_string(),
+            "db": state.db_path.display().to_string(),
+            "index_status": index_status(&state),
+            "daemon": null,
+        });
+        if let Some(info) = info.map_err(internal)? {
+            body["daemon"] = json!({
+                "pid": info.pid,
+                "version": info.version,
+                "progress": info.progress,
+                "watcher": info.watcher,
+                "leader": info.leader_holder,
+            });
+        }
+        Ok::<_, ApiError>(body)
+    })
+    .await
+    .map_err(internal)??;
+    Ok(Json(body))
+}
+
+async fn reindex(
+    State(state): State<Arc<HttpState>>,
+    headers: HeaderMap,
+) -> Result<impl IntoResponse, ApiError> {
+    if !from_loopback_client(&headers) {
+        return Err(ApiError(
+            StatusCode::FORBIDDEN,
+            "reindex is only accepted from a local client".to_string(),
+        ));
+    }
+    if state.rebuilding.swap(true, Ordering::SeqCst) {
+        return Err(ApiError(
+            StatusCode::CONFLICT,
+            "a reindex is already running".to_string(),
+        ));
+    }
+    let guard = RebuildGuard(state);
+    info!(root = %guard.0.root.display(), "http reindex requested");
+    // The current index keeps answering searches while the new one builds.
+    task::spawn_blocking(move || {
+        let state = &guard.0;
+        if let Err(err) = rebuild_index(&state.root, &state.db_path, None, None) {
+            warn!(root = %state.root.display(), error = %err, "http reindex failed");
+        }
+    });
+    Ok((
+        StatusCode::ACCEPTED,
+        Json(json!({ "status": daemon::index_status::BUILDING })),
+    ))
+}
+
+/// Whether a request names this machine in `Host` and, if it has one, in
+/// `Origin`. A web page can make the browser post to the server (directly,
+/// or through a DNS name rebound to 127.0.0.1), but the browser then sends
+/// the page's origin and host name, not a loopback one.
+fn from_loopback_client(headers: &HeaderMap) -> bool {
+    let header = |name| {
+        headers
+            .get(name)
+            .map(|value| value.to_str().ok().map(str::to_string))
+    };
+    let host_ok = match header(header::HOST) {
+        Some(Some(host)) => is_loopback_host(&host),
+        _ => false,
+    };
+    let origin_ok = match header(header::ORIGIN) {
+        None => true,
+        Some(Some(origin)) => origin
+            .strip_prefix("http://")
+            .or_else(|| origin.strip_prefix("https://"))
+            .is_some_and(is_loopback_host),
+        Some(None) => false,
+    };
+    host_ok && origin_ok
+}
+
+/// Whether `authority` (`host[:port]`) names the loopback interface.
+fn is_loopback_host(authority: &str) -> bool {
+    let host = match authority.strip_prefix('[') {
+        Some(rest) => rest.split_once(']').map_or(rest, |(host, _)| host),
+        None => authority
+            .rsplit_once(':')
+            .map_or(authority, |(host, _)| host),
+    };
+    host.eq_ignore_ascii_case("localhost")
+        || host
+            .parse::<std::net::IpAddr>()
+            .is_ok_and(|ip| ip.is_loopback())
+}
+
+/// Serve the daemon's counters, or this process's when no daemon answers.
+async fn metrics(State(state): State<Arc<HttpState>>) -> Result<Response, ApiError> {
+    let snapshot = task::spawn_blocking(move || {
+        rpc::metrics(&state.db_path).unwrap_or_else(|| METRICS.snapshot())
+    })
+    .await
+    .map_err(internal)?;
+    Ok(prometheus_response(&snapshot))
+}
+
+/// A `/metrics` response in the Prometheus text format.
+pub(crate) fn prometheus_response(snapshot: &MetricsSnapshot) -> Response {
+    (
+        [(header::CONTENT_TYPE, "text/plain; version=0.0.4")],
+        snapshot.to_prometheus(),
+    )
+        .into_response()
+}
+
+fn index_status(state: &HttpState) -> Option<String> {
+    read_meta_readonly(&state.db_path, daemon::meta_keys::INDEX_STATUS)
+        .ok()
+        .flatten()
+}
+
+pub async fn run_http_server(
+    root: Option<PathBuf>,
+    db: Option<PathBuf>,
+    addr: SocketAddr,
+    with_metrics: bool,
+) -> Result<(), Box<dyn Error>> {
+    let root = resolve_root(root);
+    let db_path = db.unwrap_or_else(|| default_db_path(&root));
+    info!(root = %root.display(), db = %db_path.display(), %addr, "http server starting");
+
+    daemon::ensure_daemon(&root, &db_path)?;
+
+    let state = Arc::new(HttpState {
+        root,
+        db_path,
+        rebuilding: AtomicBool::new(false),
+    });
+    let mut app = Router::new()
+        .route("/search", get(search))
+        .route("/search-file", get(search_file))
+        .route("/status", get(status))
+        .route("/reindex", post(reindex));
+    if with_metrics {
+        app = app.route("/metrics", get(metrics));
+    }
+    let app = app.with_state(state);
+
+    let listener = tokio::net::TcpListener::bind(addr).await?;
+    eprintln!("Listening on http://{}", listener.local_addr()?);
+    axum::serve(listener, app).await?;
+    Ok(())
+}
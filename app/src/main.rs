@@ -1,20 +1,44 @@
+use std::ffi::OsString;
+use std::net::SocketAddr;
 use std::path::PathBuf;
+use std::time::Duration;
 
-use clap::{Parser, Subcommand};
+use clap::{CommandFactory, Parser, Subcommand};
+use clap_complete::Shell;
+use source_fast_core::{DEFAULT_READ_CONCURRENCY, MatchFilter, MatchKind, WatchMode};
+use source_fast_fs::ScanLimits;
 
+mod bench;
+mod bootstrap;
+mod cache;
 mod cli;
 mod daemon;
+mod http;
 mod mcp;
+mod registry;
+mod rpc;
 
 use crate::cli::{
-    default_db_path, init_tracing_cli, init_tracing_server, resolve_root,
-    run_file_search_with_daemon, run_index_build, run_index_watch, run_list,
-    run_search_with_daemon, run_status, run_stop, run_stop_all,
+    DbLocation, IndexBuildOpts, ReplaceOpts, default_db_path, init_tracing_cli,
+    init_tracing_server, resolve_root, run_audit, run_batch_search_with_daemon, run_export,
+    run_file_search_with_daemon, run_files, run_history, run_import, run_index_build,
+    run_index_watch, run_list, run_replace, run_save_query, run_search_db_only, run_search_explain,
+    run_search_with_daemon, run_similar, run_start, run_stats, run_status, run_stop, run_stop_all,
 };
-use crate::mcp::run_server;
+use crate::mcp::{McpTransport, run_server};
 
 #[derive(Subcommand, Debug)]
 enum DaemonCommand {
+    /// Start the daemon for this repository if it is not running. The daemon
+    /// keeps the index open and answers `sf search` over a local socket.
+    Start {
+        /// Root directory
+        #[arg(long)]
+        root: Option<PathBuf>,
+        /// Path to database file
+        #[arg(long)]
+        db: Option<PathBuf>,
+    },
     /// Show daemon and index status for this repository.
     Status {
         /// Root directory
@@ -59,6 +83,36 @@ enum IndexCommand {
         /// Path to database file
         #[arg(long)]
         db: Option<PathBuf>,
+        /// Seed the index from a snapshot (`sf export`) at this URL, then
+        /// catch up with local changes
+        #[arg(long)]
+        bootstrap_url: Option<String>,
+        /// Expected SHA-256 of the snapshot [default: fetched from <URL>.sha256]
+        #[arg(long, requires = "bootstrap_url")]
+        sha256: Option<String>,
+        /// Stop the daemon, drop all indexed content and rebuild the index
+        /// with a full scan instead of catching up with changes
+        #[arg(long, conflicts_with = "bootstrap_url")]
+        rebuild: bool,
+        /// Index only this directory (relative to the root); repeat for
+        /// several. Changing the set rebuilds the index
+        #[arg(
+            long = "include-path",
+            value_name = "DIR",
+            conflicts_with = "bootstrap_url"
+        )]
+        include_paths: Vec<PathBuf>,
+        /// Drop the include paths and index the whole root again
+        #[arg(long, conflicts_with_all = ["include_paths", "bootstrap_url"])]
+        all_paths: bool,
+        /// Git dir of the root when it is not found from the root, e.g. the
+        /// bare repository a checkout was exported from [default:
+        /// $GIT_DIR, or discovered]. Changing it rebuilds the index
+        #[arg(long, value_name = "DIR", conflicts_with = "bootstrap_url")]
+        git_dir: Option<PathBuf>,
+        /// Work tree of --git-dir [default: $GIT_WORK_TREE, or the root]
+        #[arg(long, value_name = "DIR", requires = "git_dir")]
+        work_tree: Option<PathBuf>,
     },
     /// Watch the indexing progress with a live display.
     Watch {
@@ -68,6 +122,44 @@ enum IndexCommand {
         /// Path to database file
         #[arg(long)]
         db: Option<PathBuf>,
+        /// Pause a full scan after about this many files; the next run
+        /// resumes where it stopped
+        #[arg(long, value_name = "N")]
+        max_files: Option<usize>,
+        /// Pause a full scan after this long (e.g. `90s`, `30m`, `2h`); the
+        /// next run resumes where it stopped
+        #[arg(long, value_name = "DURATION", value_parser = cli::parse_duration)]
+        max_duration: Option<Duration>,
+    },
+}
+
+#[derive(Subcommand, Debug)]
+enum BenchCommand {
+    /// Build a fresh index and report files/sec and MB/sec as JSON.
+    Index {
+        /// Root directory
+        #[arg(long)]
+        root: Option<PathBuf>,
+        /// Index to build into; it is cleared first [default: a temporary
+        /// directory, removed afterwards]
+        #[arg(long)]
+        db: Option<PathBuf>,
+    },
+    /// Time queries against the existing index and report latency
+    /// percentiles as JSON.
+    Search {
+        /// Root directory
+        #[arg(long)]
+        root: Option<PathBuf>,
+        /// Path to database file
+        #[arg(long, hide = true)]
+        db: Option<PathBuf>,
+        /// File with one query per line
+        #[arg(long)]
+        queries: PathBuf,
+        /// Timed runs per query, after one warm-up run
+        #[arg(long, default_value = "5")]
+        runs: usize,
     },
 }
 
@@ -100,13 +192,135 @@ enum Command {
         #[arg(short, long)]
         json: bool,
         /// Print only file paths, no snippets (like rg -l)
-        #[arg(long)]
+        #[arg(long, visible_alias = "files-with-matches")]
         files_only: bool,
-        /// Print only the match count
+        /// Print the paths of indexed files that do not match (like rg
+        /// --files-without-match)
+        #[arg(long, conflicts_with_all = ["files_only", "count", "quiet", "json", "porcelain", "group_by", "format", "explain", "db_only"])]
+        files_without_match: bool,
+        /// End each path printed by --files-only or --files-without-match
+        /// with NUL instead of a newline, for `xargs -0`
+        #[arg(short = '0', long)]
+        null: bool,
+        /// Print only the number of matching files, without reading
+        /// snippets
         #[arg(short, long)]
         count: bool,
+        /// Print nothing; exit 0 if anything matched, 1 if not
+        #[arg(short, long)]
+        quiet: bool,
+        /// Stable script-friendly output: `path:line:text` for every matching
+        /// line, no color, context or notes on stderr
+        #[arg(long, conflicts_with = "json")]
+        porcelain: bool,
+        /// Add the 1-based byte column of the match to --porcelain lines:
+        /// `path:line:column:text`, as `rg --column` prints them
+        #[arg(long, requires = "porcelain")]
+        column: bool,
+        /// Print matches in an editor's format: `vimgrep` gives
+        /// `path:line:column:text` per occurrence, like `rg --vimgrep`
+        #[arg(long, value_enum, value_name = "FORMAT", conflicts_with_all = ["json", "porcelain", "count", "quiet", "files_only", "group_by", "explain"])]
+        format: Option<cli::OutputFormat>,
+        /// Skip confirming that each file contains the query verbatim
+        /// (faster, but may list files that only contain its trigrams)
+        #[arg(long)]
+        no_verify: bool,
+        /// Number of files read at once while extracting snippets
+        #[arg(long, default_value_t = DEFAULT_READ_CONCURRENCY)]
+        read_concurrency: usize,
+        /// Only match occurrences in code, skipping comments and string
+        /// literals (classified by file extension; unknown languages are
+        /// all code)
+        #[arg(long, conflicts_with_all = ["only_comments", "only_strings"])]
+        only_code: bool,
+        /// Only match occurrences in comments
+        #[arg(long, conflicts_with = "only_strings")]
+        only_comments: bool,
+        /// Only match occurrences in string literals
+        #[arg(long)]
+        only_strings: bool,
+        /// Match the query as an identifier in any naming convention:
+        /// `readFile` also finds `read_file`, `ReadFile` and `READ_FILE`
+        #[arg(long)]
+        identifier: bool,
+        /// Aggregate matching files per top-level directory, with counts
+        /// and a few matching lines each; -l limits the directories shown
+        #[arg(long, value_enum, value_name = "KEY", conflicts_with_all = ["count", "quiet", "files_only", "porcelain"])]
+        group_by: Option<cli::GroupBy>,
+        /// Count every occurrence (not just matching files or lines) and
+        /// print a table of files and directories, most matches first; -l
+        /// limits the rows of each
+        #[arg(long, conflicts_with_all = ["count", "quiet", "files_only", "files_without_match", "porcelain", "format", "group_by", "explain", "db_only"])]
+        heatmap: bool,
+        /// Read queries from stdin, one per line, and search them all in
+        /// one batch; output lines carry their query
+        #[arg(long, conflicts_with = "query")]
+        stdin: bool,
+        /// Instead of the matches, report how the query is planned: each
+        /// trigram's posting count in intersection order and the files left
+        /// after intersection, path filtering and verification
+        #[arg(long, conflicts_with_all = ["stdin", "count", "quiet", "files_only", "porcelain", "group_by", "identifier"])]
+        explain: bool,
+        /// Search this index file on its own (e.g. a copy exported by CI):
+        /// no root or daemon, stored paths printed verbatim, and no file
+        /// reads unless --snippets is given
+        #[arg(long, value_name = "DB", conflicts_with_all = ["root", "db", "wait", "stdin", "explain", "group_by"])]
+        db_only: Option<PathBuf>,
+        /// With --db-only, print snippets, reading files the index holds no
+        /// copy of from disk
+        #[arg(long, requires = "db_only")]
+        snippets: bool,
+        /// Print paths relative to the root (default when the current
+        /// directory is inside it)
+        #[arg(long, overrides_with = "absolute")]
+        relative: bool,
+        /// Print absolute paths
+        #[arg(long, overrides_with = "relative")]
+        absolute: bool,
+        /// Run the query saved under this name with `sf save-query`; other
+        /// options given here are added to its own
+        #[arg(long, value_name = "NAME", conflicts_with_all = ["stdin", "query"])]
+        saved: Option<String>,
         /// Search query (minimum 3 characters)
-        query: String,
+        #[arg(required_unless_present_any = ["stdin", "saved"])]
+        query: Option<String>,
+    },
+    /// Save a search under a name, for `sf search --saved <name>`.
+    SaveQuery {
+        /// Root directory
+        #[arg(long)]
+        root: Option<PathBuf>,
+        /// Remove the saved query instead
+        #[arg(long)]
+        delete: bool,
+        /// Name to save the query under
+        name: String,
+        /// Search query
+        #[arg(required_unless_present = "delete")]
+        query: Option<String>,
+        /// `sf search` options that go with the query (e.g. -e rs
+        /// --only-comments)
+        #[arg(
+            trailing_var_arg = true,
+            allow_hyphen_values = true,
+            conflicts_with = "delete"
+        )]
+        args: Vec<String>,
+    },
+    /// List recent searches (with `[history] enabled`) or saved queries.
+    History {
+        /// Root directory
+        #[arg(long)]
+        root: Option<PathBuf>,
+        /// Number of recent searches to show (0 for all)
+        #[arg(short, long, default_value = "20")]
+        limit: usize,
+        /// List the saved queries instead
+        #[arg(long)]
+        saved: bool,
+        /// Forget the recorded searches
+        #[arg(long, conflicts_with = "saved")]
+        clear: bool,
     },
     /// Search files by path. Auto-starts a background daemon if not running.
     SearchFile {
@@ -119,6 +333,16 @@ enum Command {
         /// Block until the index is fully built before returning results
         #[arg(long)]
         wait: bool,
+        /// Print paths relative to the root (default when the current
+        /// directory is inside it)
+        #[arg(long, overrides_with = "absolute")]
+        relative: bool,
+        /// Print absolute paths
+        #[arg(long, overrides_with = "relative")]
+        absolute: bool,
+        /// End each path with NUL instead of a newline, for `xargs -0`
+        #[arg(short = '0', long)]
+        null: bool,
         /// Pattern to match file paths (case-insensitive substring)
         pattern: String,
     },
@@ -133,7 +357,159 @@ enum Command {
         #[command(subcommand)]
         command: IndexCommand,
     },
-    /// Run MCP server over stdio.
+    /// Measure indexing throughput or query latency.
+    Bench {
+        #[command(subcommand)]
+        command: BenchCommand,
+    },
+    /// Show which trigrams, files and extensions take the most room in the
+    /// index.
+    Stats {
+        /// Root directory
+        #[arg(long)]
+        root: Option<PathBuf>,
+        /// Path to database file
+        #[arg(long, hide = true)]
+        db: Option<PathBuf>,
+        /// List the N trigrams posted by the most files [default N: 20]
+        #[arg(long, value_name = "N", num_args = 0..=1, default_missing_value = "20")]
+        top_trigrams: Option<usize>,
+        /// List the N files posted under the most trigrams [default N: 20]
+        #[arg(long, value_name = "N", num_args = 0..=1, default_missing_value = "20")]
+        largest_files: Option<usize>,
+        /// List the N extensions taking the most room, with hints to skip
+        /// generated ones [default N: 20]
+        #[arg(long, value_name = "N", num_args = 0..=1, default_missing_value = "20")]
+        extensions: Option<usize>,
+        /// Output as JSON
+        #[arg(short, long)]
+        json: bool,
+    },
+    /// List the indexed files with when they were modified and how much
+    /// of their text the index stores.
+    Files {
+        /// Root directory
+        #[arg(long)]
+        root: Option<PathBuf>,
+        /// Path to database file
+        #[arg(long, hide = true)]
+        db: Option<PathBuf>,
+        /// Only files with this extension (e.g. -e rs -e cs)
+        #[arg(short = 'e', long = "ext")]
+        ext: Vec<String>,
+        /// Only files matching this glob pattern (e.g. -g '*.rs')
+        #[arg(short, long)]
+        glob: Option<String>,
+        /// Only files below this directory (relative to the root)
+        #[arg(long, value_name = "DIR")]
+        under: Option<PathBuf>,
+        /// Only files modified within this long before now (e.g. `30m`,
+        /// `2h`, `2d`), as recorded when they were indexed
+        #[arg(long, value_name = "DURATION", value_parser = cli::parse_duration)]
+        modified_since: Option<Duration>,
+        /// Output as JSON
+        #[arg(short, long)]
+        json: bool,
+    },
+    /// Find copies and near-duplicates of a file among the indexed files,
+    /// by the overlap of their trigram sets.
+    Similar {
+        /// Root directory
+        #[arg(long)]
+        root: Option<PathBuf>,
+        /// Path to database file
+        #[arg(long, hide = true)]
+        db: Option<PathBuf>,
+        /// Least Jaccard similarity of the trigram sets to report, from 0
+        /// to 1
+        #[arg(long, default_value = "0.5", value_name = "RATIO")]
+        min_similarity: f64,
+        /// Maximum number of files to list (0 for unlimited)
+        #[arg(short, long, default_value = "20")]
+        limit: usize,
+        /// Output as JSON
+        #[arg(short, long)]
+        json: bool,
+        /// The indexed file to compare against
+        path: PathBuf,
+    },
+    /// Evaluate audit rules (named queries with file filters and a
+    /// severity) against the index and report their matches.
+    Audit {
+        /// Root directory
+        #[arg(long)]
+        root: Option<PathBuf>,
+        /// Path to database file
+        #[arg(long, hide = true)]
+        db: Option<PathBuf>,
+        /// TOML file of `[[rule]]` tables
+        #[arg(long, value_name = "FILE")]
+        rules: PathBuf,
+        /// Report format
+        #[arg(long, value_enum, default_value = "markdown")]
+        format: cli::AuditFormat,
+        /// Write the report to this file instead of stdout
+        #[arg(short, long, value_name = "FILE")]
+        out: Option<PathBuf>,
+        /// Exit 1 if a finding is at least this severe (info, warning or
+        /// error)
+        #[arg(long, value_name = "SEVERITY", value_parser = cli::parse_severity)]
+        fail_on: Option<source_fast_core::Severity>,
+    },
+    /// Replace a literal pattern in every indexed file containing it.
+    /// Prints a unified diff per file; files are only changed with
+    /// `--write`.
+    Replace {
+        /// Root directory
+        #[arg(long)]
+        root: Option<PathBuf>,
+        /// Path to database file
+        #[arg(long, hide = true)]
+        db: Option<PathBuf>,
+        /// Only files with this extension (e.g. -e rs -e cs)
+        #[arg(short = 'e', long = "ext")]
+        ext: Vec<String>,
+        /// Only files matching this glob pattern (e.g. -g '*.rs')
+        #[arg(short, long)]
+        glob: Option<String>,
+        /// Unchanged lines shown around each change
+        #[arg(short = 'C', long, default_value_t = 3)]
+        context: usize,
+        /// Only preview the changes (the default)
+        #[arg(long, conflicts_with = "write")]
+        dry_run: bool,
+        /// Rewrite the files and reindex them
+        #[arg(long)]
+        write: bool,
+        /// Text to replace, matched verbatim
+        pattern: String,
+        /// Text to put in its place
+        replacement: String,
+    },
+    /// Write the index to a portable snapshot file.
+    Export {
+        /// Root directory
+        #[arg(long)]
+        root: Option<PathBuf>,
+        /// Path to database file
+        #[arg(long, hide = true)]
+        db: Option<PathBuf>,
+        /// Snapshot file to write
+        #[arg(short, long, default_value = "index.sfpack")]
+        out: PathBuf,
+    },
+    /// Replace the index with a snapshot written by `sf export`.
+    Import {
+        /// Root directory
+        #[arg(long)]
+        root: Option<PathBuf>,
+        /// Path to database file
+        #[arg(long, hide = true)]
+        db: Option<PathBuf>,
+        /// Snapshot file to read
+        input: PathBuf,
+    },
+    /// Run MCP server over stdio or streamable HTTP.
     Server {
         /// Root directory to index and watch
         #[arg(long)]
@@ -141,6 +517,71 @@ enum Command {
         /// Path to database file
         #[arg(long)]
         db: Option<PathBuf>,
+        /// Transport to serve MCP on
+        #[arg(long, value_enum, default_value_t)]
+        transport: McpTransport,
+        /// Address to listen on with --transport http
+        #[arg(long, default_value = "127.0.0.1:7778")]
+        addr: SocketAddr,
+        /// Share one server per repository: proxy stdio to the background
+        /// daemon, starting it if needed
+        #[arg(long, conflicts_with = "transport")]
+        shared: bool,
+        /// Export performance metrics: `/metrics` (Prometheus) with
+        /// --transport http, periodic log lines otherwise
+        #[arg(long)]
+        metrics: bool,
+        /// Only return results under this root-relative path or glob
+        /// (repeatable; adds to `[mcp] allow_paths`)
+        #[arg(long = "allow-path", value_name = "PATH", conflicts_with = "shared")]
+        allow_paths: Vec<String>,
+        /// Never return results under this root-relative path or glob
+        /// (repeatable; adds to `[mcp] deny_paths`)
+        #[arg(long = "deny-path", value_name = "PATH", conflicts_with = "shared")]
+        deny_paths: Vec<String>,
+        /// Print result paths relative to the root instead of absolute
+        #[arg(long, conflicts_with = "shared")]
+        relative: bool,
+        /// Serve every repo listed in this TOML file (`[[repo]]` tables with
+        /// `root` and an optional `name`) instead of a single root
+        #[arg(long, value_name = "FILE", conflicts_with_all = ["root", "db", "shared"])]
+        registry: Option<PathBuf>,
+        /// Let the `add_root` tool serve directories under DIR (repeatable);
+        /// without it `add_root` is refused
+        #[arg(long = "allow-add-root", value_name = "DIR", conflicts_with = "shared")]
+        add_root_parents: Vec<PathBuf>,
+        /// Index at low priority so builds keep the machine: at most 200
+        /// files a second on background-priority threads, pausing while the
+        /// load average exceeds the number of cores
+        #[arg(long)]
+        background_nice: bool,
+    },
+    /// Serve search over a local HTTP JSON API.
+    ServeHttp {
+        /// Root directory to index and watch
+        #[arg(long)]
+        root: Option<PathBuf>,
+        /// Path to database file
+        #[arg(long, hide = true)]
+        db: Option<PathBuf>,
+        /// Address to listen on
+        #[arg(long, default_value = "127.0.0.1:7777")]
+        addr: SocketAddr,
+        /// Serve Prometheus metrics at `/metrics`
+        #[arg(long)]
+        metrics: bool,
+    },
+    /// Print a shell completion script, e.g. `sf completions bash > /etc/bash_completion.d/sf`.
+    Completions {
+        /// Shell to generate completions for
+        #[arg(value_enum)]
+        shell: Shell,
+    },
+    /// Print the sf man page, or write one page per subcommand to a directory.
+    Man {
+        /// Directory to write sf.1, sf-search.1, ... to
+        #[arg(long)]
+        out_dir: Option<PathBuf>,
     },
     /// Internal: daemon process (not user-facing).
     #[command(name = "_daemon", hide = true)]
@@ -166,13 +607,103 @@ struct Args {
     #[arg(long)]
     skill: bool,
 
+    /// Where to keep the index when --db is not given
+    /// [env: SOURCE_FAST_DB_LOCATION] [default: existing index, else repo]
+    #[arg(long, global = true, value_enum)]
+    db_location: Option<DbLocation>,
+
+    /// How the index writer watches for changes: auto, native or poll
+    /// [env: SOURCE_FAST_WATCH_MODE] [default: [watcher] mode, else auto]
+    #[arg(long, global = true)]
+    watch_mode: Option<WatchMode>,
+
+    /// Worker threads for scanning and snippet extraction
+    /// [env: SOURCE_FAST_THREADS] [default: [workers] threads, else one per core]
+    #[arg(long, global = true)]
+    threads: Option<usize>,
+
     #[command(subcommand)]
     command: Option<Command>,
 }
 
+/// Replace `sf search --saved NAME` by the saved query's own arguments,
+/// followed by any others given alongside `--saved`.
+fn expand_saved_query(args: Args) -> Result<Args, Box<dyn std::error::Error>> {
+    let Some(Command::Search {
+        saved: Some(name),
+        root,
+        ..
+    }) = &args.command
+    else {
+        return Ok(args);
+    };
+    let saved = cli::saved_query(root.clone(), name)?;
+    let mut argv: Vec<OsString> = Vec::new();
+    let mut original = std::env::args_os();
+    argv.extend(original.next());
+    let mut in_search = false;
+    while let Some(arg) = original.next() {
+        if arg == "--saved" {
+            original.next();
+            continue;
+        }
+        if arg.to_str().is_some_and(|arg| arg.starts_with("--saved=")) {
+            continue;
+        }
+        let starts_search = !in_search && arg == "search";
+        argv.push(arg);
+        if starts_search {
+            in_search = true;
+            argv.extend(saved.args.iter().map(OsString::from));
+        }
+    }
+    argv.extend([OsString::from("--"), OsString::from(saved.query)]);
+    Ok(Args::parse_from(argv))
+}
+
+/// The arguments after `search` on the command line, as history records
+/// them.
+fn search_args() -> Vec<String> {
+    std::env::args()
+        .skip_while(|arg| arg != "search")
+        .skip(1)
+        .collect()
+}
+
+/// Check that `args` followed by `query` is a valid `sf search` command
+/// line before it is saved.
+fn check_search_args(args: &[String], query: &str) -> Result<(), clap::Error> {
+    let argv = ["sf", "search"]
+        .into_iter()
+        .chain(args.iter().map(String::as_str))
+        .chain(["--", query]);
+    Args::try_parse_from(argv).map(drop)
+}
+
+/// `--relative`/`--absolute`, or `None` to pick by the current directory.
+fn path_style(relative: bool, absolute: bool) -> Option<bool> {
+    if relative {
+        Some(true)
+    } else if absolute {
+        Some(false)
+    } else {
+        None
+    }
+}
+
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
-    let args = Args::parse();
+    let args = expand_saved_query(Args::parse())?;
+
+    if let Some(location) = args.db_location {
+        cli::set_db_location(location);
+    }
+    if let Some(mode) = args.watch_mode {
+        cli::set_watch_mode(mode);
+    }
+    if let Some(threads) = args.threads {
+        cli::set_threads(threads);
+    }
 
     if args.skill {
         print!("{}", include_str!("skill.md"));
@@ -195,14 +726,44 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
             limit,
             json,
             files_only,
+            files_without_match,
+            null,
             count,
+            quiet,
+            porcelain,
+            column,
+            format,
+            no_verify,
+            read_concurrency,
+            only_code,
+            only_comments,
+            only_strings,
+            identifier,
+            group_by,
+            heatmap,
+            stdin,
+            explain,
+            db_only,
+            snippets,
+            relative,
+            absolute,
+            saved: _,
             query,
         } => {
+            if null && !(files_only || files_without_match) {
+                Args::command()
+                    .error(
+                        clap::error::ErrorKind::MissingRequiredArgument,
+                        "-0/--null needs --files-only or --files-without-match",
+                    )
+                    .exit();
+            }
             init_tracing_cli();
+            cli::record_search(root.clone(), search_args());
             let opts = cli::SearchOpts {
                 root,
                 db,
-                query,
+                query: query.unwrap_or_default(),
                 ext,
                 glob,
                 file_regex,
@@ -210,22 +771,104 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
                 limit,
                 json,
                 files_only,
+                files_without_match,
+                null,
                 count,
+                quiet,
+                porcelain,
+                verify: !no_verify,
+                read_concurrency,
+                group_by,
+                heatmap,
+                match_filter: MatchFilter {
+                    kind: if only_code {
+                        Some(MatchKind::Code)
+                    } else if only_comments {
+                        Some(MatchKind::Comment)
+                    } else if only_strings {
+                        Some(MatchKind::String)
+                    } else {
+                        None
+                    },
+                    identifier,
+                },
+                relative: path_style(relative, absolute),
+                column,
+                format,
+            };
+            // grep's exit codes: 0 matched, 1 no match, 2 error.
+            let result = if let Some(db_path) = db_only {
+                run_search_db_only(opts, &db_path, snippets)
+            } else if explain {
+                run_search_explain(opts).await
+            } else if stdin {
+                let queries: Vec<String> = std::io::stdin()
+                    .lines()
+                    .map_while(Result::ok)
+                    .map(|line| line.trim().to_string())
+                    .filter(|line| !line.is_empty())
+                    .collect();
+                run_batch_search_with_daemon(opts, queries).await
+            } else {
+                run_search_with_daemon(opts).await
             };
-            run_search_with_daemon(opts).await?;
+            match result {
+                Ok(true) => {}
+                Ok(false) => std::process::exit(1),
+                Err(err) => {
+                    eprintln!("Error: {err}");
+                    std::process::exit(2);
+                }
+            }
+        }
+        Command::SaveQuery {
+            root,
+            delete,
+            name,
+            query,
+            args,
+        } => {
+            init_tracing_cli();
+            if let Some(query) = &query
+                && let Err(err) = check_search_args(&args, query)
+            {
+                err.exit();
+            }
+            run_save_query(root, name, query, args, delete)?;
+        }
+        Command::History {
+            root,
+            limit,
+            saved,
+            clear,
+        } => {
+            init_tracing_cli();
+            run_history(root, limit, saved, clear)?;
         }
         Command::SearchFile {
             root,
             db,
             wait,
+            relative,
+            absolute,
+            null,
             pattern,
         } => {
             init_tracing_cli();
-            run_file_search_with_daemon(root, db, pattern, wait).await?;
+            run_file_search_with_daemon(
+                root,
+                db,
+                pattern,
+                wait,
+                path_style(relative, absolute),
+                null,
+            )
+            .await?;
         }
         Command::Daemon { command } => {
             init_tracing_cli();
             match command {
+                DaemonCommand::Start { root, db } => run_start(root, db).await?,
                 DaemonCommand::Status { root, db } => run_status(root, db).await?,
                 DaemonCommand::Stop { root, db, all } => {
                     if all {
@@ -241,14 +884,188 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
             init_tracing_cli();
             match command {
                 IndexCommand::Status { root, db } => run_status(root, db).await?,
-                IndexCommand::Build { root, db } => run_index_build(root, db).await?,
-                IndexCommand::Watch { root, db } => run_index_watch(root, db).await?,
+                IndexCommand::Build {
+                    root,
+                    db,
+                    bootstrap_url,
+                    sha256,
+                    rebuild,
+                    include_paths,
+                    all_paths,
+                    git_dir,
+                    work_tree,
+                } => {
+                    run_index_build(IndexBuildOpts {
+                        root,
+                        db,
+                        bootstrap_url,
+                        sha256,
+                        rebuild,
+                        include_paths,
+                        all_paths,
+                        git_dir,
+                        work_tree,
+                    })
+                    .await?
+                }
+                IndexCommand::Watch {
+                    root,
+                    db,
+                    max_files,
+                    max_duration,
+                } => {
+                    let limits = ScanLimits {
+                        max_files,
+                        max_duration,
+                    };
+                    run_index_watch(root, db, limits).await?
+                }
+            }
+        }
+        Command::Bench { command } => {
+            init_tracing_cli();
+            match command {
+                BenchCommand::Index { root, db } => bench::run_bench_index(root, db).await?,
+                BenchCommand::Search {
+                    root,
+                    db,
+                    queries,
+                    runs,
+                } => bench::run_bench_search(root, db, queries, runs).await?,
             }
         }
-        Command::Server { root, db } => {
+        Command::Stats {
+            root,
+            db,
+            top_trigrams,
+            largest_files,
+            extensions,
+            json,
+        } => {
+            init_tracing_cli();
+            run_stats(root, db, top_trigrams, largest_files, extensions, json).await?;
+        }
+        Command::Files {
+            root,
+            db,
+            ext,
+            glob,
+            under,
+            modified_since,
+            json,
+        } => {
+            init_tracing_cli();
+            run_files(root, db, ext, glob, under, modified_since, json).await?;
+        }
+        Command::Similar {
+            root,
+            db,
+            min_similarity,
+            limit,
+            json,
+            path,
+        } => {
+            init_tracing_cli();
+            run_similar(root, db, path, min_similarity, limit, json).await?;
+        }
+        Command::Audit {
+            root,
+            db,
+            rules,
+            format,
+            out,
+            fail_on,
+        } => {
+            init_tracing_cli();
+            if run_audit(root, db, rules, format, out, fail_on).await? {
+                std::process::exit(1);
+            }
+        }
+        Command::Replace {
+            root,
+            db,
+            ext,
+            glob,
+            context,
+            dry_run: _,
+            write,
+            pattern,
+            replacement,
+        } => {
+            init_tracing_cli();
+            run_replace(ReplaceOpts {
+                root,
+                db,
+                pattern,
+                replacement,
+                ext,
+                glob,
+                context,
+                write,
+            })
+            .await?;
+        }
+        Command::Export { root, db, out } => {
+            init_tracing_cli();
+            run_export(root, db, out).await?;
+        }
+        Command::Import { root, db, input } => {
+            init_tracing_cli();
+            run_import(root, db, input).await?;
+        }
+        Command::Server {
+            root,
+            db,
+            transport,
+            addr,
+            shared,
+            metrics,
+            allow_paths,
+            deny_paths,
+            relative,
+            registry,
+            add_root_parents,
+            background_nice,
+        } => {
             init_tracing_server();
-            run_server(root, db).await?;
+            if background_nice {
+                cli::set_background_nice();
+                cli::apply_background_nice();
+            }
+            run_server(mcp::ServerOpts {
+                root,
+                db,
+                transport,
+                addr,
+                shared,
+                metrics,
+                allow_paths,
+                deny_paths,
+                relative,
+                registry,
+                add_root_parents,
+            })
+            .await?;
         }
+        Command::ServeHttp {
+            root,
+            db,
+            addr,
+            metrics,
+        } => {
+            init_tracing_cli();
+            http::run_http_server(root, db, addr, metrics).await?;
+        }
+        Command::Completions { shell } => {
+            clap_complete::generate(shell, &mut Args::command(), "sf", &mut std::io::stdout());
+        }
+        Command::Man { out_dir } => match out_dir {
+            Some(dir) => {
+                std::fs::create_dir_all(&dir)?;
+                clap_mangen::generate_to(Args::command(), &dir)?;
+            }
+            None => clap_mangen::Man::new(Args::command()).render(&mut std::io::stdout())?,
+        },
         Command::InternalDaemon { root, db } => {
             let root = resolve_root(root);
             let db_path = db.unwrap_or_else(|| default_db_path(&root));
@@ -1,34 +1,354 @@
 use std::error::Error;
-use std::path::PathBuf;
-use std::sync::Arc;
-use std::sync::atomic::{AtomicBool, Ordering};
-use std::time::Duration;
+use std::net::SocketAddr;
+use std::ops::ControlFlow;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::{Duration, Instant};
 
 use regex::Regex;
 use rmcp::{
     ErrorData as McpError, ServerHandler, ServiceExt,
     handler::server::{router::tool::ToolRouter, wrapper::Parameters},
     model::{
-        CallToolResult, Content, Implementation, ProtocolVersion, ServerCapabilities, ServerInfo,
+        CallToolResult, Content, Implementation, LoggingLevel, LoggingMessageNotificationParam,
+        ProtocolVersion, ServerCapabilities, ServerInfo,
     },
+    service::{NotificationContext, Peer, RoleServer},
     tool, tool_handler, tool_router,
-    transport::stdio,
+    transport::{
+        stdio,
+        streamable_http_server::{
+            StreamableHttpServerConfig, StreamableHttpService, session::local::LocalSessionManager,
+        },
+    },
 };
 use schemars::JsonSchema;
 use serde::Deserialize;
-use source_fast_core::{IndexError, PersistentIndex, extract_snippets, path_is_within_root};
-use source_fast_fs::{background_watcher_with_cancel, smart_scan_with_progress_cancel};
-use source_fast_progress::ScanEvent;
+use source_fast_core::{
+    AuditFinding, AuditRule, AuditRules, DEFAULT_READ_CONCURRENCY, FindingTracker, IndexConfig,
+    IndexError, IndexResult, MatchFilter, PathScope, PersistentIndex, Severity, ShortQuery,
+    Snippet, audit_index, path_is_within_root, stream_snippets, verify_hits,
+};
+use source_fast_fs::{
+    GitLocation, background_watcher_pausable, catch_up_scan, indexing_paused,
+    replay_watcher_journals, set_indexing_paused, smart_scan_prioritized,
+};
+use source_fast_progress::{IndexProgress, ScanEvent, WatcherHealth};
+use tokio::io::AsyncWriteExt;
 use tokio::task;
-use tracing::{error, info};
+use tracing::{error, info, warn};
 
-use crate::cli::{default_db_path, open_index_with_worktree_copy, resolve_root};
+use crate::cache::LruCache;
+use crate::cli::{
+    GroupBy, NoResults, default_db_path, format_files_progress, format_watcher_health, group_hits,
+    head_drift_warning, open_index_with_worktree_copy, parse_severity, path_fallback_note,
+    render_groups, resolve_root,
+};
+use crate::daemon::{index_status, meta_keys, watcher_keeps_current};
+use crate::registry::{Registry, expand_home, repo_name};
 
-#[derive(Clone)]
-pub struct SearchServer {
-    index: Arc<PersistentIndex>,
+/// Number of `search_code` responses kept per server.
+const SEARCH_CACHE_CAPACITY: usize = 128;
+
+/// Responses keyed by the request and the index generation they were
+/// computed against, so any committed write makes older entries unreachable.
+type SearchCache = LruCache<(SearchCodeArgs, u64), CallToolResult>;
+
+/// How often a repo's watched queries check whether the index changed.
+const WATCH_POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+/// How many times, a poll interval apart, a repo whose index a rebuild
+/// replaced is reopened before the server gives up on it.
+const REOPEN_ATTEMPTS: u32 = 10;
+
+/// Connected clients, by session number, that watched queries notify.
+type Peers = Mutex<Vec<(u64, Peer<RoleServer>)>>;
+
+/// A root served by [`SearchServer`], with its index.
+pub struct Repo {
+    name: String,
     root: PathBuf,
+    index: Arc<PersistentIndex>,
+    /// Paths results are limited to, on top of the root.
+    scope: PathScope,
+    /// Print result paths relative to `root` (`--relative`).
+    relative_paths: bool,
+    /// Queries re-run whenever the index changes (`[[mcp.watch]]` and
+    /// `watch_query`).
+    watches: RwLock<Vec<AuditRule>>,
     index_ready: Arc<AtomicBool>,
+    /// Set once a rebuild replaced the index; the server then serves the
+    /// root from the rebuilt one (see [`SearchServer::spawn_reopen`]).
+    retired: Arc<AtomicBool>,
+}
+
+impl Repo {
+    pub fn new(
+        name: String,
+        root: PathBuf,
+        index: Arc<PersistentIndex>,
+        index_ready: Arc<AtomicBool>,
+    ) -> Self {
+        Self {
+            name,
+            root,
+            index,
+            scope: PathScope::default(),
+            relative_paths: false,
+            watches: RwLock::new(Vec::new()),
+            index_ready,
+            retired: Arc::new(AtomicBool::new(false)),
+        }
+    }
+
+    /// Only return paths within `scope`.
+    pub fn with_scope(mut self, scope: PathScope) -> Self {
+        self.scope = scope;
+        self
+    }
+
+    /// Print result paths relative to the root.
+    pub fn with_relative_paths(mut self, relative_paths: bool) -> Self {
+        self.relative_paths = relative_paths;
+        self
+    }
+
+    /// Watch `watches` from the start.
+    pub fn with_watches(self, watches: Vec<AuditRule>) -> Self {
+        *self
+            .watches
+            .write()
+            .unwrap_or_else(|poisoned| poisoned.into_inner()) = watches;
+        self
+    }
+
+    fn watched(&self) -> Vec<AuditRule> {
+        self.watches
+            .read()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+            .clone()
+    }
+
+    /// `path` as results show it: without the `\\?\` prefix and, with
+    /// relative paths on, relative to the root.
+    fn display_path(&self, path: &str) -> String {
+        let path = clean_path(path);
+        if self.relative_paths
+            && let Ok(relative) =
+                Path::new(path).strip_prefix(clean_path(&self.root.to_string_lossy()))
+            && !relative.as_os_str().is_empty()
+        {
+            return relative.to_string_lossy().into_owned();
+        }
+        path.to_string()
+    }
+
+    fn is_ready(&self) -> bool {
+        self.index_ready.load(Ordering::SeqCst)
+    }
+
+    /// Whether `path` is under the root and within the configured scope.
+    fn may_return(&self, path: &str) -> bool {
+        path_is_within_root(path, &self.root) && self.scope.contains(path)
+    }
+
+    /// [`head_drift_warning`] for this repo, unless a healthy watcher
+    /// keeps its index current.
+    fn drift_warning(&self) -> Option<String> {
+        let leader = self
+            .index
+            .read_leader_info()
+            .ok()
+            .flatten()
+            .map(|(holder, _)| holder);
+        let health = self
+            .index
+            .get_meta(meta_keys::WATCHER_HEALTH)
+            .ok()
+            .flatten();
+        if watcher_keeps_current(leader.as_deref(), health.as_deref()) {
+            return None;
+        }
+        let git_head = self.index.get_meta("git_head").ok().flatten()?;
+        head_drift_warning(&self.root, &GitLocation::load(&self.index), &git_head)
+    }
+
+    /// The `index_status` report for this repo.
+    fn status(&self) -> String {
+        let ready = self.is_ready();
+        let index = if ready {
+            index_status::COMPLETE
+        } else {
+            index_status::BUILDING
+        };
+        let mut text = format!("index: {index}\n");
+        if !ready {
+            let progress = self
+                .index
+                .get_meta(meta_keys::INDEX_PROGRESS)
+                .ok()
+                .flatten()
+                .and_then(|json| serde_json::from_str::<IndexProgress>(&json).ok())
+                .unwrap_or_default();
+            text.push_str(&format!("progress: {}", format_files_progress(&progress)));
+            if let Some(mode) = progress.mode.as_deref() {
+                text.push_str(&format!(", {mode}"));
+            }
+            text.push('\n');
+        }
+        let watcher = self
+            .index
+            .get_meta(meta_keys::WATCHER_HEALTH)
+            .ok()
+            .flatten()
+            .and_then(|json| serde_json::from_str::<WatcherHealth>(&json).ok())
+            .map_or_else(
+                || "unknown".to_string(),
+                |health| format_watcher_health(&health),
+            );
+        text.push_str(&format!("watcher: {watcher}\n"));
+        if indexing_paused(&self.index) {
+            text.push_str(
+                "indexing: paused (resume_indexing applies the changes made meanwhile)\n",
+            );
+        }
+        let watched = self.watched();
+        if !watched.is_empty() {
+            let names: Vec<&str> = watched.iter().map(|rule| rule.name.as_str()).collect();
+            text.push_str(&format!("watched queries: {}\n", names.join(", ")));
+        }
+        text
+    }
+}
+
+/// Starts served repos: opens the index and competes for its writer
+/// lease. Repos added at runtime are also written to the registry.
+pub struct RepoLauncher {
+    holder: String,
+    allow_paths: Vec<String>,
+    deny_paths: Vec<String>,
+    relative_paths: bool,
+    registry: Option<PathBuf>,
+    /// Directories `add_root` may serve roots under (`--allow-add-root`);
+    /// with none, `add_root` is refused.
+    add_root_parents: Vec<PathBuf>,
+    /// Held while `add_root` starts a repo, so roots are added one at a time.
+    adding: Mutex<()>,
+}
+
+impl RepoLauncher {
+    fn start(&self, name: String, root: PathBuf, db_path: &Path) -> Result<Repo, Box<dyn Error>> {
+        let config = IndexConfig::load(&root)?;
+        let scope = path_scope(&root, &config, &self.allow_paths, &self.deny_paths)?;
+        self.launch(name, root, db_path, config, scope)
+    }
+
+    /// Whether `add_root` may serve `root`.
+    fn may_add(&self, root: &Path) -> bool {
+        self.add_root_parents
+            .iter()
+            .any(|parent| root.starts_with(parent))
+    }
+
+    /// Start `root` for `add_root` and append it to `repos`, unless a repo
+    /// of that name or root is served already. The index is opened before
+    /// `repos` is locked.
+    ///
+    /// The root's own `[mcp]` config cannot widen what the operator allowed:
+    /// `--allow-path`, when given, replaces its `allow_paths`, and the deny
+    /// lists add up.
+    fn add(
+        &self,
+        repos: &RwLock<Vec<Arc<Repo>>>,
+        name: String,
+        root: PathBuf,
+    ) -> Result<Arc<Repo>, McpError> {
+        let _adding = self
+            .adding
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        if let Some(existing) = repos
+            .read()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+            .iter()
+            .find(|repo| repo.name == name || repo.root == root)
+        {
+            return Err(McpError::invalid_params(
+                format!(
+                    "{} is already served as repo `{}`",
+                    existing.root.display(),
+                    existing.name
+                ),
+                None,
+            ));
+        }
+
+        let started = IndexConfig::load(&root)
+            .map_err(Box::<dyn Error>::from)
+            .and_then(|config| {
+                let allow = if self.allow_paths.is_empty() {
+                    config.mcp.allow_paths.as_slice()
+                } else {
+                    self.allow_paths.as_slice()
+                };
+                let deny = [config.mcp.deny_paths.as_slice(), &self.deny_paths].concat();
+                let scope = PathScope::new(&root, allow, &deny)?;
+                let db_path = default_db_path(&root);
+                self.launch(name, root, &db_path, config, scope)
+            })
+            .map_err(|err| SearchServer::internal_error("add_root_failed", err.to_string()))?;
+        let repo = Arc::new(started);
+        repos
+            .write()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+            .push(Arc::clone(&repo));
+        Ok(repo)
+    }
+
+    /// Open the index of `root` at `db_path` and compete for its writer
+    /// lease.
+    fn launch(
+        &self,
+        name: String,
+        root: PathBuf,
+        db_path: &Path,
+        config: IndexConfig,
+        scope: PathScope,
+    ) -> Result<Repo, Box<dyn Error>> {
+        info!("root: {}", root.display());
+        info!("db: {}", db_path.display());
+        let index = Arc::new(open_index_with_worktree_copy(&root, db_path)?);
+        let index_ready = Arc::new(AtomicBool::new(false));
+        let repo = Repo::new(
+            name,
+            root.clone(),
+            Arc::clone(&index),
+            Arc::clone(&index_ready),
+        )
+        .with_scope(scope)
+        .with_relative_paths(self.relative_paths)
+        .with_watches(config.mcp.watch);
+        spawn_writer_election(
+            root,
+            index,
+            index_ready,
+            Arc::clone(&repo.retired),
+            self.holder.clone(),
+        );
+        Ok(repo)
+    }
+}
+
+#[derive(Clone)]
+pub struct SearchServer {
+    /// Served repos; the first is searched when a request names none.
+    repos: Arc<RwLock<Vec<Arc<Repo>>>>,
+    /// Starts repos for `add_root`; `None` where roots cannot be added.
+    launcher: Option<Arc<RepoLauncher>>,
+    cache: Arc<Mutex<SearchCache>>,
+    /// Clients notified of new matches of watched queries.
+    peers: Arc<Peers>,
     tool_router: ToolRouter<SearchServer>,
 }
 
@@ -37,11 +357,496 @@ impl SearchServer {
         let full = format!("{code}: {}", message.into());
         McpError::internal_error(full, None)
     }
+
+    /// Snapshot of the served repos.
+    fn repos(&self) -> Vec<Arc<Repo>> {
+        self.repos
+            .read()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+            .clone()
+    }
+
+    /// Pause or resume indexing of `repo`; see [`set_indexing_paused`].
+    async fn set_paused(&self, repo: &Arc<Repo>, paused: bool) -> Result<(), McpError> {
+        let index = Arc::clone(&repo.index);
+        task::spawn_blocking(move || set_indexing_paused(&index, paused))
+            .await
+            .map_err(|e| Self::internal_error("pause_task_failed", e.to_string()))?
+            .map_err(|e| Self::internal_error("pause_failed", e.to_string()))?;
+        info!(repo = %repo.name, paused, "indexing pause changed");
+        Ok(())
+    }
+
+    /// The repo called `name`, or the default one.
+    fn repo(&self, name: Option<&str>) -> Result<Arc<Repo>, McpError> {
+        let repos = self.repos();
+        let repo = match name {
+            Some(name) => repos.iter().find(|repo| repo.name == name),
+            None => repos.first(),
+        };
+        repo.cloned().ok_or_else(|| {
+            let message = match name {
+                Some(name) => format!("unknown repo `{name}`; list_repos shows the available ones"),
+                None => "no repos are registered; add one with add_root".to_string(),
+            };
+            McpError::invalid_params(message, None)
+        })
+    }
+
+    /// Re-run the watched queries of `repo` whenever its index changes,
+    /// for as long as the server runs, and report matches a change added.
+    fn spawn_watch(&self, repo: Arc<Repo>) {
+        let peers = Arc::clone(&self.peers);
+        task::spawn(async move {
+            let mut tracker = FindingTracker::default();
+            let mut checked: Option<(u64, Vec<AuditRule>)> = None;
+            let mut interval = tokio::time::interval(WATCH_POLL_INTERVAL);
+            loop {
+                interval.tick().await;
+                if repo.retired.load(Ordering::SeqCst) {
+                    break;
+                }
+                let rules = repo.watched();
+                if rules.is_empty() || !repo.is_ready() {
+                    continue;
+                }
+                let Ok(generation) = repo.index.generation() else {
+                    continue;
+                };
+                if let Some((previous_generation, previous)) = &checked {
+                    if *previous_generation == generation && *previous == rules {
+                        continue;
+                    }
+                    // A rule replaced under the same name starts over.
+                    for rule in &rules {
+                        if previous
+                            .iter()
+                            .any(|old| old.name == rule.name && old != rule)
+                        {
+                            tracker.forget(&rule.name);
+                        }
+                    }
+                }
+
+                let audited = Arc::clone(&repo);
+                let audit_rules = AuditRules {
+                    rules: rules.clone(),
+                };
+                let report = match task::spawn_blocking(move || {
+                    audit_index(&audited.index, &audited.root, &audit_rules)
+                })
+                .await
+                {
+                    Ok(Ok(report)) => report,
+                    Ok(Err(err)) => {
+                        warn!(repo = %repo.name, error = %err, "watched queries failed");
+                        continue;
+                    }
+                    Err(join_err) => {
+                        error!(repo = %repo.name, "watched queries panicked: {join_err}");
+                        continue;
+                    }
+                };
+                checked = Some((generation, rules));
+                for finding in tracker.update(&report) {
+                    let path = repo.root.join(&finding.path);
+                    if !repo.may_return(&path.to_string_lossy()) {
+                        continue;
+                    }
+                    let message = report
+                        .rules
+                        .iter()
+                        .find(|rule| rule.name == finding.rule)
+                        .and_then(|rule| rule.message.as_deref());
+                    notify_new_match(&repo, &finding, message, &peers).await;
+                }
+            }
+        });
+    }
+
+    /// Once a rebuild has replaced the index of `repo`, serve its root from
+    /// the rebuilt index instead, keeping its name, position, scope and
+    /// watches.
+    fn spawn_reopen(&self, repo: Arc<Repo>) {
+        let Some(launcher) = self.launcher.clone() else {
+            return;
+        };
+        let server = self.clone();
+        task::spawn(async move {
+            let mut interval = tokio::time::interval(WATCH_POLL_INTERVAL);
+            while !repo.retired.load(Ordering::SeqCst) {
+                interval.tick().await;
+            }
+            let name = repo.name.clone();
+            let root = repo.root.clone();
+            let db_path = repo.index.db_path().to_path_buf();
+            let watches = repo.watched();
+            let scope = repo.scope.clone();
+            let position = {
+                let mut repos = server
+                    .repos
+                    .write()
+                    .unwrap_or_else(|poisoned| poisoned.into_inner());
+                let position = repos.iter().position(|served| Arc::ptr_eq(served, &repo));
+                repos.retain(|served| !Arc::ptr_eq(served, &repo));
+                position
+            };
+            // This process can only open the path again once every handle
+            // on the old index is gone.
+            drop(repo);
+
+            for attempt in 1..=REOPEN_ATTEMPTS {
+                let launcher = Arc::clone(&launcher);
+                let (repo_name, repo_root, repo_db) = (name.clone(), root.clone(), db_path.clone());
+                let started = task::spawn_blocking(move || {
+                    launcher
+                        .start(repo_name, repo_root, &repo_db)
+                        .map_err(|err| err.to_string())
+                })
+                .await;
+                match started {
+                    Ok(Ok(repo)) => {
+                        let repo = Arc::new(repo.with_scope(scope).with_watches(watches));
+                        {
+                            let mut repos = server
+                                .repos
+                                .write()
+                                .unwrap_or_else(|poisoned| poisoned.into_inner());
+                            let position = position.unwrap_or(repos.len()).min(repos.len());
+                            repos.insert(position, Arc::clone(&repo));
+                        }
+                        server.spawn_watch(Arc::clone(&repo));
+                        server.spawn_reopen(repo);
+                        info!(repo = %name, "reopened the rebuilt index");
+                        return;
+                    }
+                    Ok(Err(err)) => {
+                        warn!(repo = %name, attempt, error = %err, "failed to reopen the rebuilt index");
+                    }
+                    Err(join_err) => {
+                        error!(repo = %name, "reopening the rebuilt index panicked: {join_err}");
+                        return;
+                    }
+                }
+                interval.tick().await;
+            }
+            error!(repo = %name, "gave up reopening the rebuilt index; restart the server");
+        });
+    }
+
+    fn cached(&self, key: &(SearchCodeArgs, u64)) -> Option<CallToolResult> {
+        self.cache
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+            .get(key)
+    }
+
+    /// Explain an empty result whose root and path filters left
+    /// `candidates` hits.
+    async fn no_results(&self, repo: &Repo, query: &str, candidates: usize) -> Option<Content> {
+        let index = Arc::clone(&repo.index);
+        let owned = query.to_string();
+        let no_results = task::spawn_blocking(move || {
+            let diagnostics = index.diagnose(&owned).ok()?;
+            let matched = index.search_count(&owned, None).ok()?;
+            Some(NoResults::new(diagnostics, matched, candidates))
+        })
+        .await
+        .ok()
+        .flatten()?;
+        Some(Content::text(format!(
+            "{}diagnostics: {}\n",
+            no_results.explain(query),
+            no_results.to_json()
+        )))
+    }
+
+    /// Run `search_code` uncached. The flag reports whether the latency
+    /// budget cut the results short.
+    async fn run_search(
+        &self,
+        repo: &Repo,
+        args: &SearchCodeArgs,
+        index_building: bool,
+    ) -> Result<(CallToolResult, bool, u64), McpError> {
+        // Build file filter from ext, glob, or file_regex.
+        let file_regex = build_mcp_file_filter(&args.file_regex, &args.ext, &args.glob)
+            .map_err(|e| Self::internal_error("invalid_filter", e.to_string()))?;
+
+        let query = args.query.clone();
+        let index = Arc::clone(&repo.index);
+        let filter = file_regex.clone();
+        let files_only = args.files_only;
+        let count = args.count_only;
+        let limit = if args.limit == 0 {
+            usize::MAX
+        } else {
+            args.limit
+        };
+
+        let timeout = args.timeout_ms.map(Duration::from_millis);
+        let deadline = timeout.map(|timeout| Instant::now() + timeout);
+
+        let outcome = task::spawn_blocking(move || {
+            index.search_filtered_within(&query, file_regex.as_ref(), timeout)
+        })
+        .await
+        .map_err(|e| Self::internal_error("search_task_failed", e.to_string()))?
+        .map_err(|e| Self::internal_error("search_failed", e.to_string()))?;
+        let mut truncated = outcome.truncated;
+        let generation = outcome.generation;
+        let mut hits = outcome.hits;
+
+        let mut contents = Vec::new();
+        if index_building {
+            contents.push(Content::text(match source_fast_fs::throttle() {
+                Some(throttle) => format!(
+                    "Warning: index is still building at low priority (--background-nice, \
+                     at most {} files/s, paused while the system is busy). Results may be \
+                     incomplete for a while; retry later.\n",
+                    throttle.files_per_second()
+                ),
+                None => "Warning: index is still building. Results may be incomplete. Retry in a few seconds.\n"
+                    .to_string(),
+            }));
+        }
+
+        // A query too short for any trigram has no postings to look up.
+        let mut scanned = false;
+        if hits.is_empty() && !truncated {
+            let index = Arc::clone(&repo.index);
+            let query = args.query.clone();
+            let short = task::spawn_blocking(move || index.short_query(&query, filter.as_ref()))
+                .await
+                .map_err(|e| Self::internal_error("search_task_failed", e.to_string()))?
+                .map_err(|e| Self::internal_error("search_failed", e.to_string()))?;
+            match short {
+                Some(ShortQuery::Scan(files)) => {
+                    hits = files;
+                    scanned = true;
+                }
+                Some(ShortQuery::Paths {
+                    files_indexed,
+                    hits: mut paths,
+                }) => {
+                    paths.retain(|hit| repo.may_return(&hit.path));
+                    if count {
+                        contents.push(Content::text("0".to_string()));
+                    } else {
+                        for hit in paths.iter().take(limit) {
+                            contents
+                                .push(Content::text(format!("{}\n", repo.display_path(&hit.path))));
+                        }
+                    }
+                    contents.push(Content::text(path_fallback_note(
+                        &args.query,
+                        files_indexed,
+                        paths.len(),
+                    )));
+                    return Ok((CallToolResult::success(contents), false, generation));
+                }
+                None => {}
+            }
+        }
+
+        hits.retain(|hit| repo.may_return(&hit.path));
+        let candidates = hits.len();
+        // Snippet output verifies through the snippet read instead; a scan
+        // lists every file, so it is verified whatever the arguments say.
+        let verify = !args.no_verify;
+        let store = repo.index.content_store();
+        if scanned || (verify && (count || files_only || args.group_by.is_some())) {
+            hits = verify_hits(hits, &args.query, Some(&store));
+        }
+
+        // --count mode
+        if count {
+            contents.push(Content::text(format!("{}", hits.len())));
+            if truncated {
+                contents.push(truncated_marker(args.timeout_ms));
+            } else if hits.is_empty() {
+                contents.extend(self.no_results(repo, &args.query, candidates).await);
+            }
+            return Ok((CallToolResult::success(contents), truncated, generation));
+        }
+
+        // --files-only mode
+        if files_only {
+            for (i, hit) in hits.iter().enumerate() {
+                if i >= limit {
+                    break;
+                }
+                contents.push(Content::text(format!("{}\n", repo.display_path(&hit.path))));
+            }
+            if hits.len() > limit {
+                contents.push(Content::text(format!(
+                    "... and {} more results\n",
+                    hits.len() - limit
+                )));
+            }
+            if truncated {
+                contents.push(truncated_marker(args.timeout_ms));
+            } else if hits.is_empty() {
+                contents.extend(self.no_results(repo, &args.query, candidates).await);
+            }
+            return Ok((CallToolResult::success(contents), truncated, generation));
+        }
+
+        if args.group_by.is_some() {
+            let groups = group_hits(
+                &hits,
+                &repo.root,
+                &args.query,
+                Some(&store),
+                DEFAULT_READ_CONCURRENCY,
+                MatchFilter::default(),
+            );
+            if !groups.is_empty() {
+                contents.push(Content::text(render_groups(&groups, args.limit)));
+            }
+            if truncated {
+                contents.push(truncated_marker(args.timeout_ms));
+            } else if hits.is_empty() {
+                contents.extend(self.no_results(repo, &args.query, candidates).await);
+            }
+            return Ok((CallToolResult::success(contents), truncated, generation));
+        }
+
+        // Default: snippets with context
+        let mut shown = 0;
+        let mut rejected = 0;
+        let mut matches: Vec<FileMatches> = Vec::new();
+        stream_snippets(
+            &hits,
+            &args.query,
+            Some(&store),
+            DEFAULT_READ_CONCURRENCY,
+            |hit, snippets| {
+                // Snippets read files from disk, so they count against the budget too.
+                if deadline.is_some_and(|deadline| Instant::now() >= deadline) {
+                    truncated = true;
+                    return ControlFlow::Break(());
+                }
+                let snippets = match snippets {
+                    Ok(snippets) if !snippets.is_empty() => snippets,
+                    _ if verify => {
+                        rejected += 1;
+                        return ControlFlow::Continue(());
+                    }
+                    _ => Vec::new(),
+                };
+                matches.push(FileMatches {
+                    path: repo.display_path(&hit.path),
+                    snippets,
+                });
+                shown += 1;
+                if shown >= limit {
+                    ControlFlow::Break(())
+                } else {
+                    ControlFlow::Continue(())
+                }
+            },
+        );
+        contents.extend(render_within_budget(matches, &args.query, args.max_bytes));
+
+        let remaining = hits.len() - shown - rejected;
+        if remaining > 0 {
+            contents.push(Content::text(format!("... and {remaining} more results\n")));
+        }
+        if truncated {
+            contents.push(truncated_marker(args.timeout_ms));
+        } else if shown == 0 {
+            contents.extend(self.no_results(repo, &args.query, candidates).await);
+        }
+
+        Ok((CallToolResult::success(contents), truncated, generation))
+    }
 }
 
-#[derive(Deserialize, JsonSchema)]
+/// The snippets of one file in a `search_code` response; empty when the
+/// file was not verified and is listed by path only.
+struct FileMatches {
+    path: String,
+    snippets: Vec<Snippet>,
+}
+
+impl FileMatches {
+    fn render(&self) -> String {
+        if self.snippets.is_empty() {
+            return format!("{}\n", self.path);
+        }
+        let mut text = String::new();
+        for snippet in &self.snippets {
+            text.push_str(&format!(
+                "{}:{}:{}\n",
+                self.path, snippet.line_number, snippet.column
+            ));
+            for (line_no, line) in &snippet.lines {
+                text.push_str(&format!("{line_no}: {line}\n"));
+            }
+            text.push('\n');
+        }
+        text
+    }
+
+    /// `path:line:column` of the first match, and how many more the file
+    /// holds.
+    fn summary(&self) -> String {
+        match self.snippets.split_first() {
+            Some((first, rest)) if !rest.is_empty() => format!(
+                "{}:{}:{} (+{} more matches)\n",
+                self.path,
+                first.line_number,
+                first.column,
+                rest.len()
+            ),
+            Some((first, _)) => format!("{}:{}:{}\n", self.path, first.line_number, first.column),
+            None => format!("{}\n", self.path),
+        }
+    }
+}
+
+/// Render `matches` most relevant first: files whose path contains the
+/// query, then files with more matching lines. Full snippets are emitted
+/// until the next file would exceed `max_bytes` (0 = unlimited); every
+/// file after that is only listed in a trailing summary.
+fn render_within_budget(
+    mut matches: Vec<FileMatches>,
+    query: &str,
+    max_bytes: usize,
+) -> Vec<Content> {
+    matches.sort_by_key(|file| {
+        (
+            std::cmp::Reverse(file.path.contains(query)),
+            std::cmp::Reverse(file.snippets.len()),
+        )
+    });
+
+    let mut contents = Vec::new();
+    let mut used = 0;
+    let mut rest = matches.iter();
+    for file in rest.by_ref() {
+        let text = file.render();
+        if max_bytes > 0 && used + text.len() > max_bytes {
+            let mut summary = format!(
+                "Snippets omitted to stay within max_bytes ({max_bytes}); remaining matches:\n"
+            );
+            for file in std::iter::once(file).chain(rest) {
+                summary.push_str(&file.summary());
+            }
+            contents.push(Content::text(summary));
+            break;
+        }
+        used += text.len();
+        contents.push(Content::text(text));
+    }
+    contents
+}
+
+#[derive(Clone, PartialEq, Eq, Hash, Deserialize, JsonSchema)]
 pub struct SearchCodeArgs {
-    /// Substring to search for (minimum 3 characters).
+    /// Substring to search for. Queries under 3 bytes read every file of a
+    /// small index, or match file paths only in a large one.
     pub query: String,
     /// Filter results by file extension (e.g. ["rs", "cs"]).
     #[serde(default)]
@@ -55,126 +860,454 @@ pub struct SearchCodeArgs {
     /// Return only file paths without snippets.
     #[serde(default)]
     pub files_only: bool,
-    /// Return only the match count.
+    /// Return only the number of matching files, without reading
+    /// snippets.
+    #[serde(default, alias = "count")]
+    pub count_only: bool,
+    /// Aggregate matching files per top-level directory ("dir"), with
+    /// counts and a few matching lines each. `limit` then bounds the
+    /// directories listed.
     #[serde(default)]
-    pub count: bool,
+    pub group_by: Option<GroupBy>,
     /// Maximum number of results (0 = unlimited, default 50).
     #[serde(default = "default_mcp_limit")]
     pub limit: usize,
+    /// Size budget for snippet output in bytes (0 = unlimited, default
+    /// 32768). Files past the budget are summarized as `path:line:column` only.
+    #[serde(default = "default_mcp_max_bytes")]
+    pub max_bytes: usize,
+    /// Latency budget in milliseconds. When it runs out, the results found
+    /// so far are returned with a `truncated: true` marker.
+    #[serde(default)]
+    pub timeout_ms: Option<u64>,
+    /// Skip confirming that each file contains the query verbatim. Faster,
+    /// but may return files that only contain the query's trigrams.
+    #[serde(default)]
+    pub no_verify: bool,
+    /// Repository to search, by name from `list_repos`. Defaults to the
+    /// first one.
+    #[serde(default)]
+    pub repo: Option<String>,
+}
+
+#[derive(Deserialize, JsonSchema)]
+pub struct WatchQueryArgs {
+    /// Name of the watch, reported with its matches. Watching under an
+    /// existing name replaces that watch.
+    pub name: String,
+    /// Substring to watch for (at least 3 bytes).
+    pub query: String,
+    /// "info", "warning" (default) or "error"; the notification level.
+    #[serde(default)]
+    pub severity: Option<String>,
+    /// Shown with each match.
+    #[serde(default)]
+    pub message: Option<String>,
+    /// Only watch files with these extensions (e.g. ["rs"]).
+    #[serde(default)]
+    pub ext: Vec<String>,
+    /// Only watch paths matching one of these root-relative globs.
+    #[serde(default)]
+    pub paths: Vec<String>,
+    /// Never report paths matching these root-relative globs.
+    #[serde(default)]
+    pub exclude: Vec<String>,
+    /// Repository to watch, by name from `list_repos`. Defaults to the
+    /// first one.
+    #[serde(default)]
+    pub repo: Option<String>,
+}
+
+#[derive(Deserialize, JsonSchema)]
+pub struct UnwatchQueryArgs {
+    /// Name the query is watched under.
+    pub name: String,
+    /// Repository, by name from `list_repos`. Defaults to the first one.
+    #[serde(default)]
+    pub repo: Option<String>,
+}
+
+#[derive(Deserialize, JsonSchema)]
+pub struct PauseIndexingArgs {
+    /// Repository, by name from `list_repos`. Defaults to the first one.
+    #[serde(default)]
+    pub repo: Option<String>,
+}
+
+#[derive(Deserialize, JsonSchema)]
+pub struct AddRootArgs {
+    /// Directory to index and watch.
+    pub root: String,
+    /// Name to search it by. Defaults to the directory name.
+    #[serde(default)]
+    pub name: Option<String>,
 }
 
 fn default_mcp_limit() -> usize {
     50
 }
 
+fn default_mcp_max_bytes() -> usize {
+    32 * 1024
+}
+
 #[tool_router]
 impl SearchServer {
-    pub fn new(index: Arc<PersistentIndex>, root: PathBuf, index_ready: Arc<AtomicBool>) -> Self {
-        Self {
-            index,
-            root,
-            index_ready,
+    pub fn new(repos: Vec<Repo>) -> Self {
+        let repos: Vec<Arc<Repo>> = repos.into_iter().map(Arc::new).collect();
+        let server = Self {
+            repos: Arc::new(RwLock::new(repos.clone())),
+            launcher: None,
+            cache: Arc::new(Mutex::new(LruCache::new(SEARCH_CACHE_CAPACITY))),
+            peers: Arc::new(Mutex::new(Vec::new())),
             tool_router: Self::tool_router(),
+        };
+        for repo in repos {
+            server.spawn_watch(repo);
+        }
+        server
+    }
+
+    /// Let `add_root` start repos with `launcher`, and reopen with it the
+    /// repos whose index a rebuild replaces.
+    pub fn with_launcher(mut self, launcher: RepoLauncher) -> Self {
+        self.launcher = Some(Arc::new(launcher));
+        for repo in self.repos() {
+            self.spawn_reopen(repo);
         }
+        self
     }
 
     #[tool(
-        description = "Stateful code search over the current workspace using a persistent on-disk trigram index that is kept up-to-date with file changes. For large monorepos or huge codebases, prefer this tool over ad-hoc text search. Supports filtering by extension, glob, or regex. Returns snippets with context by default, or just file paths/count."
+        description = "Stateful code search over the current workspace using a persistent on-disk trigram index that is kept up-to-date with file changes. For large monorepos or huge codebases, prefer this tool over ad-hoc text search. Supports filtering by extension, glob, or regex. Returns snippets with context by default, or just file paths (files_only), the number of matching files (count_only) or per-directory counts (group_by)."
     )]
     pub async fn search_code(
         &self,
         Parameters(args): Parameters<SearchCodeArgs>,
     ) -> Result<CallToolResult, McpError> {
-        let index_building = !self.index_ready.load(Ordering::SeqCst);
+        // Results computed against a partial index would go stale without
+        // the generation changing, so only cache once the index is ready.
+        let repo = self.repo(args.repo.as_deref())?;
+        let index_ready = repo.is_ready();
+        let generation = if index_ready {
+            repo.index.generation().ok()
+        } else {
+            None
+        };
+        // HEAD can move without the generation changing, so the drift
+        // warning is checked on every call and never cached.
+        let drift = if index_ready {
+            let repo = Arc::clone(&repo);
+            task::spawn_blocking(move || repo.drift_warning())
+                .await
+                .ok()
+                .flatten()
+        } else {
+            None
+        };
+        let with_drift = |mut result: CallToolResult| {
+            if let Some(warning) = &drift {
+                result.content.insert(0, Content::text(warning.clone()));
+            }
+            result
+        };
+        if let Some(generation) = generation
+            && let Some(result) = self.cached(&(args.clone(), generation))
+        {
+            return Ok(with_drift(result));
+        }
 
-        // Build file filter from ext, glob, or file_regex.
-        let file_regex = build_mcp_file_filter(&args.file_regex, &args.ext, &args.glob)
-            .map_err(|e| Self::internal_error("invalid_filter", e.to_string()))?;
+        let (mut result, truncated, index_generation) =
+            self.run_search(&repo, &args, !index_ready).await?;
+        result.content.push(generation_marker(index_generation));
+        if let Some(generation) = generation
+            && !truncated
+        {
+            self.cache
+                .lock()
+                .unwrap_or_else(|poisoned| poisoned.into_inner())
+                .insert((args, generation), result.clone());
+        }
+        Ok(with_drift(result))
+    }
 
-        let query = args.query.clone();
-        let index = Arc::clone(&self.index);
-        let root = self.root.clone();
-        let files_only = args.files_only;
-        let count = args.count;
-        let limit = if args.limit == 0 {
-            usize::MAX
-        } else {
-            args.limit
+    #[tool(
+        description = "Report whether the search index is complete or still building (with files processed and a percentage while the initial scan runs), and whether a file watcher keeps it up to date or periodic rescans stand in for a failed one. Poll it to show indexing progress."
+    )]
+    pub async fn index_status(&self) -> Result<CallToolResult, McpError> {
+        let text = match self.repos().as_slice() {
+            [] => "no repos registered\n".to_string(),
+            [repo] => repo.status(),
+            repos => repos
+                .iter()
+                .map(|repo| format!("repo: {}\n{}", repo.name, repo.status()))
+                .collect::<Vec<_>>()
+                .join("\n"),
         };
+        Ok(CallToolResult::success(vec![Content::text(text)]))
+    }
 
-        let mut hits =
-            task::spawn_blocking(move || index.search_filtered(&query, file_regex.as_ref()))
-                .await
-                .map_err(|e| Self::internal_error("search_task_failed", e.to_string()))?
-                .map_err(|e| Self::internal_error("search_failed", e.to_string()))?;
-        hits.retain(|hit| path_is_within_root(&hit.path, &root));
+    #[tool(
+        description = "Stop applying file changes to the index, e.g. before a large checkout or a build that churns many files. Changes are still collected and are applied by resume_indexing, or the repository is rescanned if too many piled up. Searches keep working against the index as it was."
+    )]
+    pub async fn pause_indexing(
+        &self,
+        Parameters(args): Parameters<PauseIndexingArgs>,
+    ) -> Result<CallToolResult, McpError> {
+        let repo = self.repo(args.repo.as_deref())?;
+        self.set_paused(&repo, true).await?;
+        Ok(CallToolResult::success(vec![Content::text(format!(
+            "indexing of repo `{}` paused; call resume_indexing to apply the changes made meanwhile\n",
+            repo.name
+        ))]))
+    }
 
-        let mut contents = Vec::new();
-        if index_building {
-            contents.push(Content::text(
-                "Warning: index is still building. Results may be incomplete. Retry in a few seconds.\n"
-                    .to_string(),
+    #[tool(
+        description = "Resume indexing paused with pause_indexing (or SIGUSR1) and apply the file changes made meanwhile."
+    )]
+    pub async fn resume_indexing(
+        &self,
+        Parameters(args): Parameters<PauseIndexingArgs>,
+    ) -> Result<CallToolResult, McpError> {
+        let repo = self.repo(args.repo.as_deref())?;
+        self.set_paused(&repo, false).await?;
+        Ok(CallToolResult::success(vec![Content::text(format!(
+            "indexing of repo `{}` resumed\n",
+            repo.name
+        ))]))
+    }
+
+    #[tool(
+        description = "List the repositories this server searches: name, root directory and whether the index is complete. Pass a name as `repo` to search_code; the first one is searched when none is given."
+    )]
+    pub async fn list_repos(&self) -> Result<CallToolResult, McpError> {
+        let mut text = String::new();
+        for (i, repo) in self.repos().iter().enumerate() {
+            let root = repo.root.display().to_string();
+            let index = if repo.is_ready() {
+                index_status::COMPLETE
+            } else {
+                index_status::BUILDING
+            };
+            let default = if i == 0 { ", default" } else { "" };
+            text.push_str(&format!(
+                "{}: {} (index: {index}{default})\n",
+                repo.name,
+                clean_path(&root)
             ));
         }
-
-        // --count mode
-        if count {
-            contents.push(Content::text(format!("{}", hits.len())));
-            return Ok(CallToolResult::success(contents));
+        if text.is_empty() {
+            text.push_str("no repos registered\n");
         }
+        Ok(CallToolResult::success(vec![Content::text(text)]))
+    }
 
-        // --files-only mode
-        if files_only {
-            for (i, hit) in hits.iter().enumerate() {
-                if i >= limit {
-                    break;
-                }
-                contents.push(Content::text(format!("{}\n", clean_path(&hit.path))));
-            }
-            if hits.len() > limit {
-                contents.push(Content::text(format!(
-                    "... and {} more results\n",
-                    hits.len() - limit
-                )));
-            }
-            return Ok(CallToolResult::success(contents));
+    #[tool(
+        description = "Start indexing and watching another directory without restarting the server, and make it searchable through search_code's `repo` argument. Only directories under those the server allows with --allow-add-root are accepted. With a registry file, the repo is also remembered there for the next start."
+    )]
+    pub async fn add_root(
+        &self,
+        Parameters(args): Parameters<AddRootArgs>,
+    ) -> Result<CallToolResult, McpError> {
+        let Some(launcher) = self.launcher.clone() else {
+            return Err(McpError::invalid_params(
+                "this server cannot add roots; run `sf server` directly instead of through the shared daemon",
+                None,
+            ));
+        };
+        if launcher.add_root_parents.is_empty() {
+            return Err(McpError::invalid_params(
+                "adding roots is disabled; start the server with `--allow-add-root DIR` to allow roots under DIR",
+                None,
+            ));
+        }
+        let root = resolve_root(Some(expand_home(Path::new(&args.root))));
+        if !root.is_dir() {
+            return Err(McpError::invalid_params(
+                format!("{} is not a directory", root.display()),
+                None,
+            ));
+        }
+        if !launcher.may_add(&root) {
+            return Err(McpError::invalid_params(
+                format!(
+                    "{} is not under a directory allowed with --allow-add-root",
+                    root.display()
+                ),
+                None,
+            ));
         }
+        let name = args.name.unwrap_or_else(|| repo_name(&root));
 
-        // Default: snippets with context
-        let query_for_snippets = args.query.clone();
-        for (i, hit) in hits.iter().enumerate() {
-            if i >= limit {
-                break;
-            }
-            let path = PathBuf::from(&hit.path);
-            let display = clean_path(&hit.path);
-            match extract_snippets(&path, &query_for_snippets) {
-                Ok(snippets) if !snippets.is_empty() => {
-                    let mut text = String::new();
-                    for snippet in snippets {
-                        text.push_str(&format!("{}:{}\n", display, snippet.line_number));
-                        for (line_no, line) in &snippet.lines {
-                            text.push_str(&format!("{line_no}: {line}\n"));
-                        }
-                        text.push('\n');
-                    }
-                    contents.push(Content::text(text));
-                }
-                _ => {
-                    contents.push(Content::text(format!("{display}\n")));
+        let repo = {
+            let launcher = Arc::clone(&launcher);
+            let repos = Arc::clone(&self.repos);
+            let (name, root) = (name.clone(), root.clone());
+            task::spawn_blocking(move || launcher.add(&repos, name, root))
+                .await
+                .map_err(|e| Self::internal_error("add_root_task_failed", e.to_string()))??
+        };
+        self.spawn_watch(Arc::clone(&repo));
+        self.spawn_reopen(repo);
+        info!(repo = %name, root = %root.display(), "added repo");
+
+        let persisted = match &launcher.registry {
+            Some(path) => match Registry::add(path, &name, &root) {
+                Ok(()) => format!("saved to {}", path.display()),
+                Err(err) => {
+                    warn!(registry = %path.display(), error = %err, "failed to save added repo");
+                    format!("not saved: {err}")
                 }
+            },
+            None => "not saved: the server was started without --registry".to_string(),
+        };
+        let root = root.display().to_string();
+        Ok(CallToolResult::success(vec![Content::text(format!(
+            "added repo `{name}`: {} (index: {}; {persisted})\n",
+            clean_path(&root),
+            index_status::BUILDING
+        ))]))
+    }
+
+    #[tool(
+        description = "Watch a query for the rest of the session: whenever a file change adds a line matching it (in files passing the ext/paths/exclude filters), the server logs it and sends a logging notification naming the rule, file, line and text. Matches that exist when the watch starts are not reported. Use it to guard against reintroducing banned patterns."
+    )]
+    pub async fn watch_query(
+        &self,
+        Parameters(args): Parameters<WatchQueryArgs>,
+    ) -> Result<CallToolResult, McpError> {
+        let repo = self.repo(args.repo.as_deref())?;
+        let severity = match args.severity.as_deref() {
+            Some(severity) => {
+                parse_severity(severity).map_err(|err| McpError::invalid_params(err, None))?
             }
-        }
+            None => Default::default(),
+        };
+        let rule = AuditRule {
+            name: args.name,
+            query: args.query,
+            severity,
+            message: args.message,
+            ext: args.ext,
+            paths: args.paths,
+            exclude: args.exclude,
+            only: None,
+            identifier: false,
+        };
+        let mut watches = repo
+            .watches
+            .write()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        let mut rules = watches.clone();
+        rules.retain(|existing| existing.name != rule.name);
+        rules.push(rule.clone());
+        let rules = AuditRules::new(rules)
+            .map_err(|err| McpError::invalid_params(err.to_string(), None))?;
+        *watches = rules.rules;
+        info!(repo = %repo.name, rule = %rule.name, query = %rule.query, "watching query");
+        Ok(CallToolResult::success(vec![Content::text(format!(
+            "watching `{}` as `{}` in repo `{}` ({} watched)\n",
+            rule.query,
+            rule.name,
+            repo.name,
+            watches.len()
+        ))]))
+    }
 
-        if hits.len() > limit {
-            contents.push(Content::text(format!(
-                "... and {} more results\n",
-                hits.len() - limit
-            )));
+    #[tool(
+        description = "Stop watching a query started with watch_query or configured in [[mcp.watch]]."
+    )]
+    pub async fn unwatch_query(
+        &self,
+        Parameters(args): Parameters<UnwatchQueryArgs>,
+    ) -> Result<CallToolResult, McpError> {
+        let repo = self.repo(args.repo.as_deref())?;
+        let mut watches = repo
+            .watches
+            .write()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        let before = watches.len();
+        watches.retain(|rule| rule.name != args.name);
+        if watches.len() == before {
+            return Err(McpError::invalid_params(
+                format!("no watched query is named `{}`", args.name),
+                None,
+            ));
         }
+        info!(repo = %repo.name, rule = %args.name, "stopped watching query");
+        Ok(CallToolResult::success(vec![Content::text(format!(
+            "stopped watching `{}` ({} watched)\n",
+            args.name,
+            watches.len()
+        ))]))
+    }
+}
 
-        Ok(CallToolResult::success(contents))
+/// Log a match of a watched query that a change added, and notify every
+/// connected client. Clients that are gone are dropped.
+async fn notify_new_match(
+    repo: &Repo,
+    finding: &AuditFinding,
+    message: Option<&str>,
+    peers: &Peers,
+) {
+    let path = repo.display_path(&repo.root.join(&finding.path).to_string_lossy());
+    warn!(
+        repo = %repo.name,
+        rule = %finding.rule,
+        path = %path,
+        line = finding.line,
+        "new match for watched query"
+    );
+    let level = match finding.severity {
+        Severity::Info => LoggingLevel::Info,
+        Severity::Warning => LoggingLevel::Warning,
+        Severity::Error => LoggingLevel::Error,
+    };
+    let data = serde_json::json!({
+        "repo": repo.name,
+        "rule": finding.rule,
+        "severity": finding.severity,
+        "message": message,
+        "path": path,
+        "line": finding.line,
+        "column": finding.column,
+        "text": finding.text,
+    });
+    let connected = peers
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner())
+        .clone();
+    let mut gone = Vec::new();
+    for (session, peer) in connected {
+        let param = LoggingMessageNotificationParam {
+            level,
+            logger: Some("watch_query".to_string()),
+            data: data.clone(),
+        };
+        if peer.notify_logging_message(param).await.is_err() {
+            gone.push(session);
+        }
     }
+    if !gone.is_empty() {
+        peers
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+            .retain(|(session, _)| !gone.contains(session));
+    }
+}
+
+/// The index generation a `search_code` response was read at. Agents can
+/// compare it across calls to tell when results they kept are stale.
+fn generation_marker(generation: u64) -> Content {
+    Content::text(format!("index_generation: {generation}\n"))
+}
+
+fn truncated_marker(timeout_ms: Option<u64>) -> Content {
+    Content::text(format!(
+        "truncated: true (search stopped after {} ms; results are partial)\n",
+        timeout_ms.unwrap_or_default()
+    ))
 }
 
 #[tool_handler]
@@ -182,14 +1315,27 @@ impl ServerHandler for SearchServer {
     fn get_info(&self) -> ServerInfo {
         ServerInfo {
             instructions: Some(
-                "Stateful source code search server. It maintains a persistent trigram index on disk and keeps it in sync with file changes. For huge codebases or monorepos, prefer using the `search_code` tool first before falling back to raw text search."
+                "Stateful source code search server. It maintains a persistent trigram index on disk and keeps it in sync with file changes. For huge codebases or monorepos, prefer using the `search_code` tool first before falling back to raw text search. When it serves several repositories, `list_repos` names them."
                     .to_string(),
             ),
             protocol_version: ProtocolVersion::V_2024_11_05,
-            capabilities: ServerCapabilities::builder().enable_tools().build(),
+            capabilities: ServerCapabilities::builder()
+                .enable_logging()
+                .enable_tools()
+                .build(),
             server_info: Implementation::from_build_env(),
         }
     }
+
+    /// Remember the client, so watched queries can notify it.
+    async fn on_initialized(&self, context: NotificationContext<RoleServer>) {
+        static SESSIONS: AtomicU64 = AtomicU64::new(0);
+        let session = SESSIONS.fetch_add(1, Ordering::Relaxed);
+        self.peers
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+            .push((session, context.peer));
+    }
 }
 
 /// Strip the `\\?\` extended path prefix on Windows.
@@ -218,6 +1364,16 @@ fn build_mcp_file_filter(
     Ok(None)
 }
 
+/// How `sf server` talks to MCP clients.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum McpTransport {
+    /// JSON-RPC over stdin/stdout; one client per process.
+    #[default]
+    Stdio,
+    /// Streamable HTTP (with SSE) at `/mcp`; any number of clients.
+    Http,
+}
+
 #[derive(Clone, Copy, PartialEq, Eq)]
 enum McpRole {
     Reader,
@@ -233,34 +1389,264 @@ impl McpRole {
     }
 }
 
-pub async fn run_server(root: Option<PathBuf>, db: Option<PathBuf>) -> Result<(), Box<dyn Error>> {
-    let root = resolve_root(root);
-    let db_path = db.unwrap_or_else(|| default_db_path(&root));
+/// The `[mcp]` path scope of `config`, widened by `--allow-path` and
+/// `--deny-path`.
+fn path_scope(
+    root: &Path,
+    config: &IndexConfig,
+    allow: &[String],
+    deny: &[String],
+) -> IndexResult<PathScope> {
+    let allow = [config.mcp.allow_paths.as_slice(), allow].concat();
+    let deny = [config.mcp.deny_paths.as_slice(), deny].concat();
+    PathScope::new(root, &allow, &deny)
+}
+
+/// Endpoint name of the daemon's shared MCP server.
+const MCP_ENDPOINT: &str = "mcp";
+
+/// How long `sf server --shared` waits for the daemon's MCP endpoint.
+const SHARED_CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Serve MCP sessions for `sf server --shared` on the daemon's local
+/// endpoint, so every client shares the daemon's index, watcher and writer.
+pub async fn serve_shared(
+    db_path: PathBuf,
+    root: PathBuf,
+    index: Arc<PersistentIndex>,
+    index_ready: Arc<AtomicBool>,
+) {
+    if crate::rpc::connect(&db_path, MCP_ENDPOINT).await.is_ok() {
+        warn!(db = %db_path.display(), "another daemon already serves MCP for this index");
+        return;
+    }
+    let (scope, watches) = match IndexConfig::load(&root)
+        .and_then(|config| Ok((path_scope(&root, &config, &[], &[])?, config.mcp.watch)))
+    {
+        Ok(loaded) => loaded,
+        Err(err) => {
+            error!(error = %err, "invalid [mcp] config; not serving shared MCP");
+            return;
+        }
+    };
+    let repo = Repo::new(repo_name(&root), root, index, index_ready)
+        .with_scope(scope)
+        .with_watches(watches);
+    let server = SearchServer::new(vec![repo]);
+    crate::rpc::listen(&db_path, MCP_ENDPOINT, move |stream| {
+        let server = server.clone();
+        task::spawn(async move {
+            match server.serve(stream).await {
+                Ok(service) => {
+                    let _ = service.waiting().await;
+                }
+                Err(err) => warn!("shared MCP session failed: {err:?}"),
+            }
+        });
+    })
+    .await;
+}
+
+/// Forward stdio to the daemon's shared MCP endpoint until either side
+/// closes. Returns `Ok(false)` if the endpoint never came up.
+async fn proxy_to_daemon(root: &Path, db_path: &Path) -> Result<bool, Box<dyn Error>> {
+    crate::daemon::ensure_daemon(root, db_path)?;
+
+    let deadline = Instant::now() + SHARED_CONNECT_TIMEOUT;
+    let stream = loop {
+        match crate::rpc::connect(db_path, MCP_ENDPOINT).await {
+            Ok(stream) => break stream,
+            Err(_) if Instant::now() < deadline => {
+                tokio::time::sleep(Duration::from_millis(100)).await;
+            }
+            Err(err) => {
+                warn!(error = %err, "shared MCP endpoint unavailable, serving in-process");
+                return Ok(false);
+            }
+        }
+    };
+    info!(db = %db_path.display(), "proxying stdio to shared MCP server");
+
+    let (mut reader, mut writer) = tokio::io::split(stream);
+    let upstream = async {
+        tokio::io::copy(&mut tokio::io::stdin(), &mut writer).await?;
+        // Let the daemon finish answering before the session closes.
+        writer.shutdown().await?;
+        std::future::pending::<std::io::Result<()>>().await
+    };
+    let mut stdout = tokio::io::stdout();
+    let downstream = tokio::io::copy(&mut reader, &mut stdout);
+    tokio::select! {
+        res = upstream => res?,
+        res = downstream => {
+            res?;
+        }
+    }
+    Ok(true)
+}
+
+/// How often `sf server --metrics` logs counters on the stdio transport.
+const METRICS_LOG_INTERVAL: Duration = Duration::from_secs(60);
+
+pub struct ServerOpts {
+    pub root: Option<PathBuf>,
+    pub db: Option<PathBuf>,
+    pub transport: McpTransport,
+    pub addr: SocketAddr,
+    pub shared: bool,
+    pub metrics: bool,
+    /// Root-relative globs results are limited to (`--allow-path`).
+    pub allow_paths: Vec<String>,
+    /// Root-relative globs never returned (`--deny-path`).
+    pub deny_paths: Vec<String>,
+    /// Print result paths relative to each repo's root (`--relative`).
+    pub relative: bool,
+    /// Serve every repo listed in this file instead of one root.
+    pub registry: Option<PathBuf>,
+    /// Directories `add_root` may serve roots under (`--allow-add-root`).
+    pub add_root_parents: Vec<PathBuf>,
+}
+
+pub async fn run_server(opts: ServerOpts) -> Result<(), Box<dyn Error>> {
+    let ServerOpts {
+        root,
+        db,
+        transport,
+        addr,
+        shared,
+        metrics,
+        allow_paths,
+        deny_paths,
+        relative,
+        registry,
+        add_root_parents,
+    } = opts;
+    let targets: Vec<(String, PathBuf, PathBuf)> = match &registry {
+        Some(path) => Registry::load(path)?
+            .resolve()?
+            .into_iter()
+            .map(|repo| {
+                let db_path = default_db_path(&repo.root);
+                (repo.name, repo.root, db_path)
+            })
+            .collect(),
+        None => {
+            let root = resolve_root(root);
+            let db_path = db.unwrap_or_else(|| default_db_path(&root));
+            if shared
+                && transport == McpTransport::Stdio
+                && proxy_to_daemon(&root, &db_path).await?
+            {
+                return Ok(());
+            }
+            vec![(repo_name(&root), root, db_path)]
+        }
+    };
+    if metrics && transport == McpTransport::Stdio {
+        task::spawn(log_metrics_periodically());
+    }
 
     info!("source_fast MCP server starting");
-    info!("root: {}", root.display());
-    info!("db: {}", db_path.display());
+    let launcher = RepoLauncher {
+        holder: crate::daemon::writer_holder_id(),
+        allow_paths,
+        deny_paths,
+        relative_paths: relative,
+        registry,
+        add_root_parents: add_root_parents
+            .into_iter()
+            .map(|dir| resolve_root(Some(expand_home(&dir))))
+            .collect(),
+        adding: Mutex::new(()),
+    };
+    let mut repos = Vec::with_capacity(targets.len());
+    for (name, root, db_path) in targets {
+        repos.push(launcher.start(name, root, &db_path)?);
+    }
+    let holder = launcher.holder.clone();
+
+    let server = SearchServer::new(repos).with_launcher(launcher);
+    let served = Arc::clone(&server.repos);
+    #[cfg(unix)]
+    task::spawn(crate::daemon::pause_on_signals({
+        let served = Arc::clone(&served);
+        move || {
+            served
+                .read()
+                .unwrap_or_else(|poisoned| poisoned.into_inner())
+                .iter()
+                .map(|repo| Arc::clone(&repo.index))
+                .collect()
+        }
+    }));
+    let served_result: Result<(), Box<dyn Error>> = match transport {
+        McpTransport::Stdio => match server.serve(stdio()).await {
+            Ok(service) => service.waiting().await.map(drop).map_err(Into::into),
+            Err(e) => {
+                error!("source_fast MCP serve error: {e:?}");
+                Err(e.into())
+            }
+        },
+        McpTransport::Http => serve_http(server, addr, metrics).await,
+    };
+
+    // Release the writer leases, including those of added repos, so other
+    // processes can acquire them immediately, even if serving failed.
+    let served = served
+        .read()
+        .unwrap_or_else(|poisoned| poisoned.into_inner())
+        .clone();
+    for repo in served {
+        let _ = repo.index.release_writer_lease(&holder);
+    }
+    info!("MCP server shut down, writer lease released");
 
-    let index = Arc::new(open_index_with_worktree_copy(&root, &db_path)?);
-    let index_ready = Arc::new(AtomicBool::new(false));
+    served_result
+}
 
-    // Leader election: ensure only one process writes to the index at a time.
-    // If we are not the writer, we still serve best-effort searches.
-    let holder = crate::daemon::writer_holder_id();
-    let holder_for_cleanup = holder.clone();
+/// Leader election for the index of `root`: ensure only one process writes
+/// to it at a time. While this process holds the lease it scans, watches
+/// and reconciles the root; otherwise it still serves best-effort searches.
+///
+/// The file watcher outlives each term as writer: losing the lease pauses
+/// it and regaining the lease resumes it, with its watches and queued
+/// changes intact. Once the initial scan has finished, a re-promotion only
+/// runs an incremental catch-up scan, and only if another writer committed
+/// in between.
+///
+/// Once a rebuild replaces the index, everything started for it stops and
+/// `retired` is set.
+fn spawn_writer_election(
+    election_root: PathBuf,
+    election_index: Arc<PersistentIndex>,
+    election_ready: Arc<AtomicBool>,
+    retired: Arc<AtomicBool>,
+    holder: String,
+) {
     let lease_ttl = Duration::from_secs(5);
-    let election_index = Arc::clone(&index);
-    let election_root = root.clone();
-    let election_ready = Arc::clone(&index_ready);
-    let is_writer = Arc::new(AtomicBool::new(false));
-    let is_writer_for_task = Arc::clone(&is_writer);
+    let is_writer_for_task = Arc::new(AtomicBool::new(false));
 
     task::spawn(async move {
         let mut role_logged: Option<McpRole> = None;
         let mut writer_started = false;
         let mut writer_cancel: Option<Arc<AtomicBool>> = None;
+        let mut watcher_paused: Option<Arc<AtomicBool>> = None;
+        let watcher_cancel = Arc::new(AtomicBool::new(false));
+        let scanned = Arc::new(AtomicBool::new(false));
+        let mut demoted_at_generation: Option<u64> = None;
 
         loop {
+            if election_index.is_replaced().unwrap_or(false) {
+                if let Some(cancel) = writer_cancel.take() {
+                    cancel.store(true, Ordering::SeqCst);
+                }
+                watcher_cancel.store(true, Ordering::SeqCst);
+                election_index.set_write_enabled(false);
+                info!(root = %election_root.display(), "index replaced by a rebuild, reopening");
+                retired.store(true, Ordering::SeqCst);
+                break;
+            }
+
             if !is_writer_for_task.load(Ordering::SeqCst) {
                 let acquired = crate::daemon::try_acquire_writer_lease(
                     Arc::clone(&election_index),
@@ -277,6 +1663,9 @@ pub async fn run_server(root: Option<PathBuf>, db: Option<PathBuf>) -> Result<()
                     info!(role = McpRole::Writer.as_str(), "promoted role=writer");
                 } else {
                     election_index.set_write_enabled(false);
+                    // Readers never scan; follow the status the writer persists.
+                    election_ready
+                        .store(writer_reports_complete(&election_index), Ordering::SeqCst);
                     if role_logged != Some(McpRole::Reader) {
                         info!(role = McpRole::Reader.as_str(), "role selected role=reader");
                         role_logged = Some(McpRole::Reader);
@@ -294,54 +1683,39 @@ pub async fn run_server(root: Option<PathBuf>, db: Option<PathBuf>) -> Result<()
                     writer_started = true;
                     let cancel = Arc::new(AtomicBool::new(false));
                     writer_cancel = Some(Arc::clone(&cancel));
-                    // Kick off initial indexing in the background so the MCP server can start
-                    // responding to requests immediately.
-                    let index_for_scan = Arc::clone(&election_index);
-                    let root_for_scan = election_root.clone();
-                    let ready_for_scan = Arc::clone(&election_ready);
-                    let cancel_for_scan = Arc::clone(&cancel);
-                    task::spawn(async move {
-                        let res = task::spawn_blocking(move || {
-                            smart_scan_with_progress_cancel(
-                                &root_for_scan,
-                                index_for_scan,
-                                Arc::new(|_: ScanEvent| {}),
-                                cancel_for_scan,
-                            )
-                        })
-                        .await;
-                        match res {
-                            Ok(Ok(())) => {
-                                ready_for_scan.store(true, Ordering::SeqCst);
-                                info!("MCP server: initial index build completed");
-                            }
-                            Ok(Err(IndexError::Cancelled)) => {
-                                info!("MCP server: initial index build cancelled");
-                            }
-                            Ok(Err(err)) => {
-                                error!("MCP server: initial index build failed: {err}");
-                            }
-                            Err(join_err) => {
-                                error!("MCP server: initial index task panicked: {join_err}");
-                            }
-                        }
-                    });
-
-                    // Start background file watcher to keep the index up-to-date.
-                    let index_for_watcher = Arc::clone(&election_index);
-                    let root_for_watcher = election_root.clone();
-                    let cancel_for_watcher = Arc::clone(&cancel);
-                    task::spawn(async move {
-                        if let Err(err) = background_watcher_with_cancel(
-                            root_for_watcher,
-                            index_for_watcher,
-                            cancel_for_watcher,
-                        )
-                        .await
-                        {
-                            error!("file watcher stopped: {err}");
+                    if scanned.load(Ordering::SeqCst) {
+                        let changed =
+                            demoted_at_generation.take() != election_index.generation().ok();
+                        spawn_catch_up(
+                            election_root.clone(),
+                            Arc::clone(&election_index),
+                            Arc::clone(&election_ready),
+                            cancel,
+                            changed,
+                        );
+                    } else {
+                        spawn_initial_scan(
+                            election_root.clone(),
+                            Arc::clone(&election_index),
+                            Arc::clone(&election_ready),
+                            Arc::clone(&scanned),
+                            cancel,
+                        );
+                    }
+
+                    match &watcher_paused {
+                        Some(paused) => paused.store(false, Ordering::SeqCst),
+                        None => {
+                            let paused = Arc::new(AtomicBool::new(false));
+                            watcher_paused = Some(Arc::clone(&paused));
+                            spawn_watcher(
+                                election_root.clone(),
+                                Arc::clone(&election_index),
+                                Arc::clone(&watcher_cancel),
+                                paused,
+                            );
                         }
-                    });
+                    }
                 }
 
                 // Renew lease.
@@ -358,7 +1732,11 @@ pub async fn run_server(root: Option<PathBuf>, db: Option<PathBuf>) -> Result<()
                     if let Some(cancel) = writer_cancel.take() {
                         cancel.store(true, Ordering::SeqCst);
                     }
+                    if let Some(paused) = &watcher_paused {
+                        paused.store(true, Ordering::SeqCst);
+                    }
                     election_index.set_write_enabled(false);
+                    demoted_at_generation = election_index.generation().ok();
                     is_writer_for_task.store(false, Ordering::SeqCst);
                     writer_started = false;
                     election_ready.store(false, Ordering::SeqCst);
@@ -370,20 +1748,180 @@ pub async fn run_server(root: Option<PathBuf>, db: Option<PathBuf>) -> Result<()
             tokio::time::sleep(Duration::from_millis(500)).await;
         }
     });
+}
 
-    // Start rmcp-based MCP server on stdio.
-    let server = SearchServer::new(index.clone(), root.clone(), index_ready);
+/// Whether the writer of `index`, in this process or another, reports the
+/// index complete.
+fn writer_reports_complete(index: &PersistentIndex) -> bool {
+    matches!(
+        index.get_meta(meta_keys::INDEX_STATUS),
+        Ok(Some(status)) if status == index_status::COMPLETE
+    )
+}
 
-    let service = server
-        .serve(stdio())
-        .await
-        .inspect_err(|e| error!("source_fast MCP serve error: {e:?}"))?;
+/// Kick off the initial index build in the background so the MCP server
+/// can start responding to requests immediately, then start reconciling.
+fn spawn_initial_scan(
+    root: PathBuf,
+    index: Arc<PersistentIndex>,
+    ready: Arc<AtomicBool>,
+    scanned: Arc<AtomicBool>,
+    cancel: Arc<AtomicBool>,
+) {
+    task::spawn(async move {
+        let _ = index.set_meta_queued(meta_keys::INDEX_STATUS, index_status::BUILDING);
+        let progress = crate::daemon::progress_recorder(Arc::clone(&index));
+        let progress_for_scan = Arc::clone(&progress);
+        let (root_for_scan, index_for_scan) = (root.clone(), Arc::clone(&index));
+        let cancel_for_scan = Arc::clone(&cancel);
+        let res = task::spawn_blocking(move || {
+            smart_scan_prioritized(
+                &root_for_scan,
+                index_for_scan,
+                progress_for_scan,
+                cancel_for_scan,
+            )
+        })
+        .await;
+        match res {
+            Ok(Ok(())) => {
+                progress(ScanEvent::Finished);
+                let _ = index.set_meta_queued(meta_keys::INDEX_STATUS, index_status::COMPLETE);
+                scanned.store(true, Ordering::SeqCst);
+                ready.store(true, Ordering::SeqCst);
+                info!("MCP server: initial index build completed");
+                crate::daemon::spawn_warm_up(&root, Arc::clone(&index));
+                crate::daemon::spawn_reconcile(root, index, cancel);
+            }
+            Ok(Err(IndexError::Cancelled)) => {
+                info!("MCP server: initial index build cancelled");
+            }
+            Ok(Err(err)) => {
+                progress(ScanEvent::Failed);
+                let _ = index.set_meta_queued(meta_keys::INDEX_STATUS, index_status::FAILED);
+                error!("MCP server: initial index build failed: {err}");
+            }
+            Err(join_err) => {
+                progress(ScanEvent::Failed);
+                let _ = index.set_meta_queued(meta_keys::INDEX_STATUS, index_status::FAILED);
+                error!("MCP server: initial index task panicked: {join_err}");
+            }
+        }
+    });
+}
+
+/// Catch up after regaining the writer lease: an incremental scan of what
+/// git reports as changed if another writer committed meanwhile (`changed`),
+/// otherwise only a replay of the journal a writer that died left behind;
+/// the resumed watcher applies the rest. Then start reconciling again.
+fn spawn_catch_up(
+    root: PathBuf,
+    index: Arc<PersistentIndex>,
+    ready: Arc<AtomicBool>,
+    cancel: Arc<AtomicBool>,
+    changed: bool,
+) {
+    task::spawn(async move {
+        let (root_for_scan, index_for_scan) = (root.clone(), Arc::clone(&index));
+        let cancel_for_scan = Arc::clone(&cancel);
+        let res = task::spawn_blocking(move || {
+            if changed {
+                catch_up_scan(
+                    &root_for_scan,
+                    index_for_scan,
+                    Arc::new(|_| {}),
+                    cancel_for_scan,
+                )
+            } else {
+                replay_watcher_journals(&root_for_scan, &index_for_scan, &cancel_for_scan)
+                    .map(|_| ())
+            }
+        })
+        .await;
+        match res {
+            Ok(Ok(())) => info!("MCP server: catch-up scan completed"),
+            Ok(Err(IndexError::Cancelled)) => {
+                info!("MCP server: catch-up scan cancelled");
+                return;
+            }
+            Ok(Err(err)) => warn!("MCP server: catch-up scan failed: {err}"),
+            Err(join_err) => error!("MCP server: catch-up scan panicked: {join_err}"),
+        }
+        ready.store(true, Ordering::SeqCst);
+        crate::daemon::spawn_reconcile(root, index, cancel);
+    });
+}
 
-    service.waiting().await?;
+/// Start the background file watcher that keeps the index up to date. It
+/// runs until `cancel` is set; `paused` is set while another process holds
+/// the writer lease.
+fn spawn_watcher(
+    root: PathBuf,
+    index: Arc<PersistentIndex>,
+    cancel: Arc<AtomicBool>,
+    paused: Arc<AtomicBool>,
+) {
+    task::spawn(async move {
+        let health = crate::daemon::watcher_health_recorder(Arc::clone(&index));
+        let config = crate::daemon::watcher_config(&root);
+        if let Err(err) =
+            background_watcher_pausable(root, index, cancel, paused, config, health).await
+        {
+            error!("file watcher stopped: {err}");
+        }
+    });
+}
 
-    // Release the writer lease so other processes can acquire it immediately.
-    let _ = index.release_writer_lease(&holder_for_cleanup);
-    info!("MCP server shut down, writer lease released");
+/// Serve MCP over streamable HTTP until Ctrl-C. Every client session gets
+/// its own [`SearchServer`] handle over the shared index.
+async fn serve_http(
+    server: SearchServer,
+    addr: SocketAddr,
+    with_metrics: bool,
+) -> Result<(), Box<dyn Error>> {
+    let service = StreamableHttpService::new(
+        move || Ok(server.clone()),
+        Arc::new(LocalSessionManager::default()),
+        StreamableHttpServerConfig::default(),
+    );
+    let mut app = axum::Router::new().nest_service("/mcp", service);
+    if with_metrics {
+        app = app.route(
+            "/metrics",
+            axum::routing::get(|| async {
+                crate::http::prometheus_response(&source_fast_core::METRICS.snapshot())
+            }),
+        );
+    }
 
+    let listener = tokio::net::TcpListener::bind(addr).await?;
+    let local_addr = listener.local_addr()?;
+    info!("MCP server listening on http://{local_addr}/mcp");
+    eprintln!("MCP server listening on http://{local_addr}/mcp");
+    axum::serve(listener, app)
+        .with_graceful_shutdown(async {
+            let _ = tokio::signal::ctrl_c().await;
+        })
+        .await?;
     Ok(())
 }
+
+async fn log_metrics_periodically() {
+    let mut interval = tokio::time::interval(METRICS_LOG_INTERVAL);
+    interval.tick().await;
+    loop {
+        interval.tick().await;
+        let m = source_fast_core::METRICS.snapshot();
+        info!(
+            files_indexed = m.files_indexed,
+            files_removed = m.files_removed,
+            writer_batches = m.batches_committed,
+            writer_commit_secs = m.commit_seconds,
+            writer_queue_depth = m.writer_queue_depth,
+            bitmap_decodes = m.bitmap_decodes,
+            searches = m.searches,
+            search_secs = m.search_seconds,
+            "metrics"
+        );
+    }
+}
@@ -0,0 +1,100 @@
+//! The repository registry read by `sf server --registry`.
+//!
+//! A TOML file listing the roots one MCP server searches:
+//!
+//! ```toml
+//! [[repo]]
+//! name = "api"            # optional, defaults to the root's directory name
+//! root = "~/src/api"
+//! ```
+//!
+//! Repos added at runtime with the MCP `add_root` tool are appended.
+
+use std::error::Error;
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+
+use crate::cli::resolve_root;
+
+#[derive(Debug, Default, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct Registry {
+    #[serde(default, rename = "repo")]
+    pub repos: Vec<RegistryEntry>,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct RegistryEntry {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    pub root: PathBuf,
+}
+
+/// A registry entry with its name filled in and its root resolved.
+#[derive(Debug, Clone)]
+pub struct RegisteredRepo {
+    pub name: String,
+    pub root: PathBuf,
+}
+
+impl Registry {
+    /// Load the registry at `path`; a missing file is an empty registry.
+    pub fn load(path: &Path) -> Result<Self, Box<dyn Error>> {
+        let text = match std::fs::read_to_string(path) {
+            Ok(text) => text,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(Self::default()),
+            Err(err) => {
+                return Err(format!("failed to read registry {}: {err}", path.display()).into());
+            }
+        };
+        toml::from_str(&text)
+            .map_err(|err| format!("invalid registry {}: {}", path.display(), err.message()).into())
+    }
+
+    /// Append `root` as repo `name` to the registry at `path`, creating
+    /// the file if needed.
+    pub fn add(path: &Path, name: &str, root: &Path) -> Result<(), Box<dyn Error>> {
+        let mut registry = Self::load(path)?;
+        registry.repos.push(RegistryEntry {
+            name: Some(name.to_string()),
+            root: root.to_path_buf(),
+        });
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        std::fs::write(path, toml::to_string(&registry)?)?;
+        Ok(())
+    }
+
+    /// The registered roots in file order, rejecting duplicate names. An
+    /// empty registry is fine: roots can be added with `add_root`.
+    pub fn resolve(&self) -> Result<Vec<RegisteredRepo>, Box<dyn Error>> {
+        let mut repos: Vec<RegisteredRepo> = Vec::with_capacity(self.repos.len());
+        for entry in &self.repos {
+            let root = resolve_root(Some(expand_home(&entry.root)));
+            let name = entry.name.clone().unwrap_or_else(|| repo_name(&root));
+            if repos.iter().any(|repo| repo.name == name) {
+                return Err(format!("registry lists repo `{name}` more than once").into());
+            }
+            repos.push(RegisteredRepo { name, root });
+        }
+        Ok(repos)
+    }
+}
+
+/// Name of the repo at `root` when none is given: its directory name.
+pub fn repo_name(root: &Path) -> String {
+    root.file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_else(|| root.display().to_string())
+}
+
+/// `path` with a leading `~` replaced by the home directory.
+pub fn expand_home(path: &Path) -> PathBuf {
+    match (path.strip_prefix("~"), dirs::home_dir()) {
+        (Ok(rest), Some(home)) => home.join(rest),
+        _ => path.to_path_buf(),
+    }
+}
@@ -0,0 +1,386 @@
+//! Local RPC between CLI commands and a running daemon.
+//!
+//! The daemon listens on a Unix socket next to the index (`daemon.sock`), or
+//! on a named pipe derived from the index path on Windows. The protocol is
+//! one JSON [`Request`] per line, answered by one JSON [`Response`] line.
+//! Clients treat any connection or protocol failure as "no daemon" and fall
+//! back to reading the index directly.
+//!
+//! [`listen`] and [`connect`] are shared with the daemon's MCP endpoint
+//! (see [`crate::mcp`]), which uses a second endpoint name.
+
+use std::io::{BufRead, BufReader, Write};
+use std::path::Path;
+use std::sync::Arc;
+
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use source_fast_core::{METRICS, MetricsSnapshot, PersistentIndex, SearchHit, SearchOutcome};
+use tokio::io::{AsyncBufReadExt, AsyncRead, AsyncWrite, AsyncWriteExt};
+use tracing::{debug, info, warn};
+
+/// How long a client waits for the daemon before falling back.
+#[cfg(unix)]
+const CLIENT_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(2);
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(tag = "method", rename_all = "snake_case")]
+pub enum Request {
+    Ping,
+    Search {
+        query: String,
+        file_regex: Option<String>,
+        #[serde(default)]
+        timeout_ms: Option<u64>,
+    },
+    SearchBatch {
+        queries: Vec<String>,
+        file_regex: Option<String>,
+    },
+    Metrics,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(tag = "result", rename_all = "snake_case")]
+pub enum Response {
+    Pong {
+        pid: u32,
+    },
+    Hits {
+        hits: Vec<SearchHit>,
+        #[serde(default)]
+        truncated: bool,
+        #[serde(default)]
+        generation: u64,
+    },
+    BatchHits {
+        hits: Vec<Vec<SearchHit>>,
+        #[serde(default)]
+        generation: u64,
+    },
+    Metrics {
+        metrics: MetricsSnapshot,
+    },
+    Error {
+        message: String,
+    },
+}
+
+/// Endpoint name of the search RPC.
+const RPC_ENDPOINT: &str = "daemon";
+
+/// A connected local socket or named pipe.
+pub trait LocalStream: AsyncRead + AsyncWrite + Unpin + Send {}
+
+impl<T: AsyncRead + AsyncWrite + Unpin + Send> LocalStream for T {}
+
+#[cfg(unix)]
+fn socket_path(db_path: &Path, endpoint: &str) -> std::path::PathBuf {
+    db_path
+        .parent()
+        .unwrap_or(Path::new("."))
+        .join(format!("{endpoint}.sock"))
+}
+
+#[cfg(windows)]
+fn pipe_name(db_path: &Path, endpoint: &str) -> String {
+    use std::hash::{Hash, Hasher};
+
+    let mut hasher = std::collections::hash_map::DefaultHasher::new();
+    source_fast_core::normalize_path_for_prefix(&db_path.to_string_lossy()).hash(&mut hasher);
+    format!(r"\\.\pipe\source_fast-{endpoint}-{:016x}", hasher.finish())
+}
+
+fn handle_request(index: &PersistentIndex, request: Request) -> Response {
+    match request {
+        Request::Ping => Response::Pong {
+            pid: std::process::id(),
+        },
+        Request::Search {
+            query,
+            file_regex,
+            timeout_ms,
+        } => {
+            let file_regex = match parse_file_regex(file_regex) {
+                Ok(re) => re,
+                Err(response) => return response,
+            };
+            let timeout = timeout_ms.map(std::time::Duration::from_millis);
+            match index.search_filtered_within(&query, file_regex.as_ref(), timeout) {
+                Ok(SearchOutcome {
+                    hits,
+                    truncated,
+                    generation,
+                }) => Response::Hits {
+                    hits,
+                    truncated,
+                    generation,
+                },
+                Err(err) => Response::Error {
+                    message: err.to_string(),
+                },
+            }
+        }
+        Request::SearchBatch {
+            queries,
+            file_regex,
+        } => {
+            let file_regex = match parse_file_regex(file_regex) {
+                Ok(re) => re,
+                Err(response) => return response,
+            };
+            match index.search_batch(&queries, file_regex.as_ref()) {
+                Ok(outcomes) => Response::BatchHits {
+                    generation: outcomes.first().map_or(0, |outcome| outcome.generation),
+                    hits: outcomes.into_iter().map(|outcome| outcome.hits).collect(),
+                },
+                Err(err) => Response::Error {
+                    message: err.to_string(),
+                },
+            }
+        }
+        Request::Metrics => Response::Metrics {
+            metrics: METRICS.snapshot(),
+        },
+    }
+}
+
+fn parse_file_regex(file_regex: Option<String>) -> Result<Option<Regex>, Response> {
+    file_regex
+        .as_deref()
+        .map(Regex::new)
+        .transpose()
+        .map_err(|err| Response::Error {
+            message: format!("invalid file regex: {err}"),
+        })
+}
+
+async fn serve_connection<S>(stream: S, index: Arc<PersistentIndex>)
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    let (reader, mut writer) = tokio::io::split(stream);
+    let mut lines = tokio::io::BufReader::new(reader).lines();
+    while let Ok(Some(line)) = lines.next_line().await {
+        let response = match serde_json::from_str::<Request>(&line) {
+            Ok(request) => {
+                debug!(?request, "rpc request");
+                let index = Arc::clone(&index);
+                tokio::task::spawn_blocking(move || handle_request(&index, request))
+                    .await
+                    .unwrap_or_else(|err| Response::Error {
+                        message: format!("request panicked: {err}"),
+                    })
+            }
+            Err(err) => Response::Error {
+                message: format!("malformed request: {err}"),
+            },
+        };
+        let Ok(mut json) = serde_json::to_string(&response) else {
+            break;
+        };
+        json.push('\n');
+        if writer.write_all(json.as_bytes()).await.is_err() {
+            break;
+        }
+    }
+}
+
+/// Accept RPC connections for `db_path` until the task is dropped.
+pub async fn serve(db_path: std::path::PathBuf, index: Arc<PersistentIndex>) {
+    if ping(&db_path).is_some() {
+        warn!(db = %db_path.display(), "another daemon already serves this index; rpc disabled");
+        return;
+    }
+    listen(&db_path, RPC_ENDPOINT, move |stream| {
+        tokio::spawn(serve_connection(stream, Arc::clone(&index)));
+    })
+    .await;
+}
+
+/// Accept connections on the local `endpoint` for `db_path` and hand each
+/// one to `on_connect` until the task is dropped, which also removes the
+/// socket file.
+#[cfg(unix)]
+pub async fn listen<F>(db_path: &Path, endpoint: &str, on_connect: F)
+where
+    F: Fn(Box<dyn LocalStream>),
+{
+    /// Removes the socket file when the listening task is dropped.
+    struct SocketFile(std::path::PathBuf);
+
+    impl Drop for SocketFile {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_file(&self.0);
+        }
+    }
+
+    let path = socket_path(db_path, endpoint);
+    let _ = std::fs::remove_file(&path);
+    let listener = match tokio::net::UnixListener::bind(&path) {
+        Ok(listener) => listener,
+        Err(err) => {
+            warn!(socket = %path.display(), error = %err, "failed to bind socket; endpoint disabled");
+            return;
+        }
+    };
+    let socket = SocketFile(path);
+    info!(socket = %socket.0.display(), "listening");
+    loop {
+        match listener.accept().await {
+            Ok((stream, _)) => on_connect(Box::new(stream)),
+            Err(err) => warn!(socket = %socket.0.display(), error = %err, "accept failed"),
+        }
+    }
+}
+
+/// Accept connections on the local `endpoint` for `db_path` and hand each
+/// one to `on_connect` until the task is dropped.
+#[cfg(windows)]
+pub async fn listen<F>(db_path: &Path, endpoint: &str, on_connect: F)
+where
+    F: Fn(Box<dyn LocalStream>),
+{
+    use tokio::net::windows::named_pipe::ServerOptions;
+
+    let name = pipe_name(db_path, endpoint);
+    let mut server = match ServerOptions::new().first_pipe_instance(true).create(&name) {
+        Ok(server) => server,
+        Err(err) => {
+            warn!(pipe = %name, error = %err, "failed to create pipe; endpoint disabled");
+            return;
+        }
+    };
+    info!(pipe = %name, "listening");
+    loop {
+        if let Err(err) = server.connect().await {
+            warn!(pipe = %name, error = %err, "accept failed");
+            continue;
+        }
+        let connected = server;
+        server = match ServerOptions::new().create(&name) {
+            Ok(server) => server,
+            Err(err) => {
+                warn!(pipe = %name, error = %err, "failed to create pipe instance");
+                return;
+            }
+        };
+        on_connect(Box::new(connected));
+    }
+}
+
+/// Connect to the local `endpoint` for `db_path`.
+pub async fn connect(db_path: &Path, endpoint: &str) -> std::io::Result<Box<dyn LocalStream>> {
+    #[cfg(unix)]
+    let stream = tokio::net::UnixStream::connect(socket_path(db_path, endpoint)).await?;
+    #[cfg(windows)]
+    let stream =
+        tokio::net::windows::named_pipe::ClientOptions::new().open(pipe_name(db_path, endpoint))?;
+    Ok(Box::new(stream))
+}
+
+/// Send one request to the daemon for `db_path`. Returns `None` when no
+/// daemon answers.
+pub fn call(db_path: &Path, request: &Request) -> Option<Response> {
+    #[cfg(unix)]
+    let stream = {
+        let stream =
+            std::os::unix::net::UnixStream::connect(socket_path(db_path, RPC_ENDPOINT)).ok()?;
+        stream.set_read_timeout(Some(CLIENT_TIMEOUT)).ok()?;
+        stream.set_write_timeout(Some(CLIENT_TIMEOUT)).ok()?;
+        stream
+    };
+    #[cfg(windows)]
+    let stream = std::fs::OpenOptions::new()
+        .read(true)
+        .write(true)
+        .open(pipe_name(db_path, RPC_ENDPOINT))
+        .ok()?;
+
+    let mut line = serde_json::to_string(request).ok()?;
+    line.push('\n');
+    let mut writer = &stream;
+    writer.write_all(line.as_bytes()).ok()?;
+
+    let mut reply = String::new();
+    BufReader::new(&stream).read_line(&mut reply).ok()?;
+    serde_json::from_str(&reply).ok()
+}
+
+/// Returns the daemon's pid if one answers on the RPC endpoint.
+pub fn ping(db_path: &Path) -> Option<u32> {
+    match call(db_path, &Request::Ping)? {
+        Response::Pong { pid } => Some(pid),
+        _ => None,
+    }
+}
+
+/// Run a content search through the daemon, with an optional latency
+/// budget; see [`PersistentIndex::search_filtered_within`]. `None` means the
+/// caller should search the database directly.
+pub fn search_within(
+    db_path: &Path,
+    query: &str,
+    file_regex: Option<&Regex>,
+    timeout: Option<std::time::Duration>,
+) -> Option<SearchOutcome> {
+    let request = Request::Search {
+        query: query.to_string(),
+        file_regex: file_regex.map(|re| re.as_str().to_string()),
+        timeout_ms: timeout.map(|timeout| timeout.as_millis() as u64),
+    };
+    match call(db_path, &request)? {
+        Response::Hits {
+            hits,
+            truncated,
+            generation,
+        } => Some(SearchOutcome {
+            hits,
+            truncated,
+            generation,
+        }),
+        Response::Error { message } => {
+            warn!(error = %message, "daemon search failed; falling back to direct read");
+            None
+        }
+        Response::Pong { .. } | Response::BatchHits { .. } | Response::Metrics { .. } => None,
+    }
+}
+
+/// Run several content searches through the daemon in one request; see
+/// [`PersistentIndex::search_batch`]. `None` means the caller should search
+/// the database directly.
+pub fn search_batch(
+    db_path: &Path,
+    queries: &[String],
+    file_regex: Option<&Regex>,
+) -> Option<Vec<SearchOutcome>> {
+    let request = Request::SearchBatch {
+        queries: queries.to_vec(),
+        file_regex: file_regex.map(|re| re.as_str().to_string()),
+    };
+    match call(db_path, &request)? {
+        Response::BatchHits { hits, generation } if hits.len() == queries.len() => Some(
+            hits.into_iter()
+                .map(|hits| SearchOutcome {
+                    hits,
+                    truncated: false,
+                    generation,
+                })
+                .collect(),
+        ),
+        Response::Error { message } => {
+            warn!(error = %message, "daemon batch search failed; falling back to direct read");
+            None
+        }
+        _ => None,
+    }
+}
+
+/// Fetch the daemon's performance counters.
+pub fn metrics(db_path: &Path) -> Option<MetricsSnapshot> {
+    match call(db_path, &Request::Metrics)? {
+        Response::Metrics { metrics } => Some(metrics),
+        _ => None,
+    }
+}
@@ -20,11 +20,25 @@ impl McpServerProcess {
     }
 
     pub fn spawn_with_log(root: &Path, log_path: Option<PathBuf>) -> Self {
+        Self::spawn_with_args(root, log_path, &[])
+    }
+
+    /// Spawn `sf server` with extra flags (e.g. `--shared`).
+    pub fn spawn_with_args(root: &Path, log_path: Option<PathBuf>, args: &[&str]) -> Self {
+        let mut cmd = Command::new(env!("CARGO_BIN_EXE_sf"));
+        cmd.arg("server").args(args).arg("--root").arg(root);
+        Self::spawn_command(cmd, log_path)
+    }
+
+    /// Spawn `sf server --registry <registry>` with extra flags.
+    pub fn spawn_registry(registry: &Path, args: &[&str]) -> Self {
         let mut cmd = Command::new(env!("CARGO_BIN_EXE_sf"));
-        cmd.arg("server")
-            .arg("--root")
-            .arg(root)
-            .stdin(Stdio::piped())
+        cmd.arg("server").args(args).arg("--registry").arg(registry);
+        Self::spawn_command(cmd, None)
+    }
+
+    fn spawn_command(mut cmd: Command, log_path: Option<PathBuf>) -> Self {
+        cmd.stdin(Stdio::piped())
             .stdout(Stdio::piped())
             .stderr(Stdio::null());
 
@@ -93,9 +107,18 @@ impl McpServerProcess {
             ),
             None => format!(r#"{{"query":{}}}"#, serde_json::to_string(query).unwrap()),
         };
+        self.call_search_code_with_args(id, &args)
+    }
+
+    /// Call `search_code` with a raw JSON arguments object.
+    pub fn call_search_code_with_args(&mut self, id: u64, args: &str) -> Value {
+        self.call_tool(id, "search_code", args)
+    }
 
+    /// Call tool `name` with a raw JSON arguments object.
+    pub fn call_tool(&mut self, id: u64, name: &str, args: &str) -> Value {
         let req = format!(
-            r#"{{"jsonrpc":"2.0","id":{id},"method":"tools/call","params":{{"name":"search_code","arguments":{args}}}}}"#
+            r#"{{"jsonrpc":"2.0","id":{id},"method":"tools/call","params":{{"name":"{name}","arguments":{args}}}}}"#
         );
         self.send_line(&req);
 
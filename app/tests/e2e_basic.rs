@@ -129,6 +129,35 @@ fn test_search_file_by_path() {
     );
 }
 
+/// `sf files` lists what the index holds, filtered by extension and
+/// directory.
+#[test]
+fn test_files_lists_indexed_files() {
+    let fix = TestFixture::new();
+    fix.add_file("src/main.rs", "fn main() {}");
+    fix.add_file("src/notes.md", "# notes");
+    fix.add_file("docs/guide.rs", "fn guide() {}");
+    fix.search("fn main");
+
+    let output = fix
+        .sf()
+        .args(["files", "--root"])
+        .arg(fix.root())
+        .args(["-e", "rs", "--under", "src", "--modified-since", "1d", "-j"])
+        .output()
+        .expect("sf files failed");
+    assert!(output.status.success());
+    let files: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    let paths: Vec<&str> = files
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|file| file["path"].as_str().unwrap())
+        .collect();
+    assert_eq!(paths.len(), 1, "{paths:?}");
+    assert!(paths[0].ends_with("main.rs"), "{paths:?}");
+}
+
 #[test]
 fn test_daemon_and_index_status_commands() {
     let fix = TestFixture::new();
@@ -165,6 +194,155 @@ fn test_daemon_and_index_status_commands() {
         .stdout(predicate::str::contains("Index status:"));
 }
 
+/// Additional: `sf daemon start` brings up a daemon that answers searches
+/// over its local socket.
+#[cfg(unix)]
+#[test]
+fn test_daemon_start_serves_rpc() {
+    let fix = TestFixture::new();
+    fix.add_file("src/lib.rs", "pub fn rpc_probe() {}");
+
+    fix.sf()
+        .args(["daemon", "start", "--root"])
+        .arg(fix.root())
+        .assert()
+        .success();
+    let output = fix.search("rpc_probe");
+    assert!(String::from_utf8_lossy(&output.stdout).contains("lib.rs"));
+
+    assert!(
+        fix.root().join(".source_fast").join("daemon.sock").exists(),
+        "daemon should listen on a socket next to the index"
+    );
+    fix.sf()
+        .args(["daemon", "status", "--root"])
+        .arg(fix.root())
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("RPC:          listening"));
+}
+
+/// Send one HTTP/1.1 request and return the status code and body.
+fn http_request(addr: &str, method: &str, path: &str) -> (u16, String) {
+    http_request_with(addr, method, path, &format!("Host: {addr}\r\n"))
+}
+
+/// Like [`http_request`], with `headers` (each ending in CRLF) in place of
+/// the default `Host` line.
+fn http_request_with(addr: &str, method: &str, path: &str, headers: &str) -> (u16, String) {
+    use std::io::{Read, Write};
+
+    let mut stream = std::net::TcpStream::connect(addr).unwrap();
+    write!(
+        stream,
+        "{method} {path} HTTP/1.1\r\n{headers}Content-Length: 0\r\nConnection: close\r\n\r\n"
+    )
+    .unwrap();
+    let mut response = String::new();
+    stream.read_to_string(&mut response).unwrap();
+    let status = response
+        .split_whitespace()
+        .nth(1)
+        .and_then(|code| code.parse().ok())
+        .unwrap_or(0);
+    let body = response.split_once("\r\n\r\n").map_or("", |(_, body)| body);
+    (status, body.to_string())
+}
+
+fn http_json(addr: &str, method: &str, path: &str) -> (u16, serde_json::Value) {
+    let (status, body) = http_request(addr, method, path);
+    (status, serde_json::from_str(&body).unwrap_or_default())
+}
+
+/// Additional: `sf serve-http` answers search, search-file, status and
+/// reindex requests with JSON, and serves Prometheus metrics.
+#[test]
+fn test_serve_http_json_api() {
+    let fix = TestFixture::new();
+    fix.add_file("src/lib.rs", "pub fn http_probe() {}");
+    fix.add_file("docs/readme.md", "no match here");
+    fix.search("http_probe");
+
+    let addr = {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        listener.local_addr().unwrap().to_string()
+    };
+    let mut server = std::process::Command::new(env!("CARGO_BIN_EXE_sf"))
+        .args(["serve-http", "--metrics", "--addr", &addr, "--root"])
+        .arg(fix.root())
+        .stdout(std::process::Stdio::null())
+        .stderr(std::process::Stdio::null())
+        .spawn()
+        .unwrap();
+    let deadline = std::time::Instant::now() + std::time::Duration::from_secs(30);
+    while std::net::TcpStream::connect(&addr).is_err() {
+        assert!(
+            std::time::Instant::now() < deadline,
+            "serve-http did not start"
+        );
+        std::thread::sleep(std::time::Duration::from_millis(100));
+    }
+
+    let (status, body) = http_json(&addr, "GET", "/search?q=http_probe&ext=rs");
+    assert_eq!(status, 200, "{body}");
+    assert_eq!(body["total"], 1, "{body}");
+    assert!(
+        body["results"][0]["path"]
+            .as_str()
+            .unwrap()
+            .ends_with("lib.rs")
+    );
+
+    let (status, body) = http_json(&addr, "GET", "/search-file?pattern=readme");
+    assert_eq!(status, 200, "{body}");
+    assert_eq!(body["total"], 1, "{body}");
+
+    let (status, body) = http_json(&addr, "GET", "/status");
+    assert_eq!(status, 200, "{body}");
+    assert_eq!(body["index_status"], "complete", "{body}");
+
+    let (status, body) = http_json(&addr, "GET", "/search?q=http_probe&file_regex=(");
+    assert_eq!(status, 400, "invalid regex should be rejected: {body}");
+    assert!(body["error"].is_string());
+
+    let (status, body) = http_request(&addr, "GET", "/metrics");
+    assert_eq!(status, 200, "{body}");
+    assert!(
+        body.contains("source_fast_search_duration_seconds_count"),
+        "metrics should include the search latency histogram: {body}"
+    );
+
+    // A browser page posting cross-site, or through a rebound host name,
+    // must not be able to start a rebuild.
+    let (status, body) = http_request_with(
+        &addr,
+        "POST",
+        "/reindex",
+        &format!("Host: {addr}\r\nOrigin: http://evil.example\r\n"),
+    );
+    assert_eq!(status, 403, "{body}");
+    let (status, body) = http_request_with(&addr, "POST", "/reindex", "Host: evil.example\r\n");
+    assert_eq!(status, 403, "{body}");
+
+    // Rebuilds share a staging directory: a second request made while
+    // one runs is refused.
+    let posts: Vec<_> = (0..2)
+        .map(|_| {
+            let addr = addr.clone();
+            std::thread::spawn(move || http_json(&addr, "POST", "/reindex"))
+        })
+        .collect();
+    let mut statuses: Vec<u16> = posts
+        .into_iter()
+        .map(|post| post.join().unwrap().0)
+        .collect();
+    statuses.sort();
+    assert_eq!(statuses, [202, 409]);
+
+    let _ = server.kill();
+    let _ = server.wait();
+}
+
 #[test]
 fn test_index_watch_foreground_scan_completes() {
     let fix = TestFixture::new();
@@ -206,3 +384,402 @@ fn test_index_watch_foreground_scan_completes() {
         "foreground watch should have indexed lib.rs, got: {hits:?}"
     );
 }
+
+/// Additional: `sf export` on one checkout, `sf import` on another.
+/// Expected: the imported index answers searches under the new root.
+#[test]
+fn test_export_import_snapshot() {
+    let fix = TestFixture::new();
+    fix.git_init();
+    fix.add_file("src/lib.rs", "pub fn snapshot_probe() {}");
+    fix.git_commit("initial");
+    let _ = fix.search("snapshot_probe");
+    fix.stop();
+
+    let pack = fix.root().join("index.sfpack");
+    let output = fix
+        .sf()
+        .arg("export")
+        .arg("--root")
+        .arg(fix.root())
+        .arg("--out")
+        .arg(&pack)
+        .output()
+        .expect("sf export failed");
+    assert!(
+        output.status.success(),
+        "export failed: {}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+
+    // A second checkout; TestFixture serializes per binary, so use a bare dir.
+    let other = assert_fs::TempDir::new().unwrap();
+    std::fs::create_dir_all(other.path().join("src")).unwrap();
+    std::fs::write(
+        other.path().join("src/lib.rs"),
+        "pub fn snapshot_probe() {}",
+    )
+    .unwrap();
+    let output = fix
+        .sf()
+        .arg("import")
+        .arg("--root")
+        .arg(other.path())
+        .arg(&pack)
+        .output()
+        .expect("sf import failed");
+    let _ = fix
+        .sf()
+        .arg("daemon")
+        .arg("stop")
+        .arg("--root")
+        .arg(other.path())
+        .output();
+    assert!(
+        output.status.success(),
+        "import failed: {}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+
+    let other_db = other.path().join(".source_fast").join("index.mdb");
+    let hits = source_fast_core::search_database_file_filtered(&other_db, "snapshot_probe", None)
+        .expect("searching the imported index should succeed");
+    assert!(
+        hits.iter()
+            .any(|hit| source_fast_core::path_is_within_root(&hit.path, other.path())),
+        "imported paths should live under the new root, got: {hits:?}"
+    );
+}
+
+/// Serve `files` (URL path -> body) over plain HTTP on a loopback port.
+fn serve_files(files: Vec<(String, Vec<u8>)>) -> String {
+    use std::io::{BufRead, BufReader, Write};
+
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let base = format!("http://{}", listener.local_addr().unwrap());
+    std::thread::spawn(move || {
+        for stream in listener.incoming() {
+            let Ok(mut stream) = stream else { continue };
+            let mut request_line = String::new();
+            let mut reader = BufReader::new(stream.try_clone().unwrap());
+            let _ = reader.read_line(&mut request_line);
+            let mut header = String::new();
+            while reader.read_line(&mut header).is_ok_and(|n| n > 2) {
+                header.clear();
+            }
+            let path = request_line.split_whitespace().nth(1).unwrap_or("");
+            let response = match files.iter().find(|(route, _)| route == path) {
+                Some((_, body)) => {
+                    let mut response = format!(
+                        "HTTP/1.1 200 OK\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+                        body.len()
+                    )
+                    .into_bytes();
+                    response.extend_from_slice(body);
+                    response
+                }
+                None => b"HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\nConnection: close\r\n\r\n"
+                    .to_vec(),
+            };
+            let _ = stream.write_all(&response);
+        }
+    });
+    base
+}
+
+/// Additional: `sf index build --bootstrap-url` seeds the index from a
+/// published snapshot after verifying its checksum.
+#[test]
+fn test_index_build_bootstrap_url() {
+    let fix = TestFixture::new();
+    fix.git_init();
+    fix.add_file("src/lib.rs", "pub fn bootstrap_probe() {}");
+    fix.git_commit("initial");
+    let _ = fix.search("bootstrap_probe");
+    fix.stop();
+
+    let pack = fix.root().join("index.sfpack");
+    let output = fix
+        .sf()
+        .arg("export")
+        .arg("--root")
+        .arg(fix.root())
+        .arg("--out")
+        .arg(&pack)
+        .output()
+        .expect("sf export failed");
+    assert!(output.status.success());
+    let base = serve_files(vec![
+        ("/index.sfpack".to_string(), std::fs::read(&pack).unwrap()),
+        (
+            "/index.sfpack.sha256".to_string(),
+            std::fs::read(fix.root().join("index.sfpack.sha256")).unwrap(),
+        ),
+    ]);
+    let url = format!("{base}/index.sfpack");
+
+    let other = assert_fs::TempDir::new().unwrap();
+    std::fs::create_dir_all(other.path().join("src")).unwrap();
+    std::fs::write(
+        other.path().join("src/lib.rs"),
+        "pub fn bootstrap_probe() {}",
+    )
+    .unwrap();
+    let build = |extra: &[&str]| {
+        fix.sf()
+            .args(["index", "build", "--root"])
+            .arg(other.path())
+            .args(["--bootstrap-url", &url])
+            .args(extra)
+            .output()
+            .expect("sf index build failed")
+    };
+
+    let output = build(&["--sha256", &"0".repeat(64)]);
+    assert!(!output.status.success(), "a bad checksum must be rejected");
+    assert!(
+        String::from_utf8_lossy(&output.stderr).contains("checksum mismatch"),
+        "stderr: {}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+
+    let output = build(&[]);
+    let stderr = String::from_utf8_lossy(&output.stderr).to_string();
+    let search = fix
+        .sf()
+        .args(["search", "--wait", "--root"])
+        .arg(other.path())
+        .arg("bootstrap_probe")
+        .output()
+        .expect("sf search failed");
+    let _ = fix
+        .sf()
+        .args(["daemon", "stop", "--root"])
+        .arg(other.path())
+        .output();
+    assert!(output.status.success(), "bootstrap failed: {stderr}");
+    assert!(stderr.contains("Imported 1 files"), "stderr: {stderr}");
+    assert!(
+        String::from_utf8_lossy(&search.stdout).contains("lib.rs"),
+        "bootstrapped index should answer searches"
+    );
+}
+
+#[test]
+fn test_index_build_rebuild_replaces_index() {
+    let fix = TestFixture::new();
+    fix.git_init();
+    fix.add_file("src/lib.rs", "pub fn rebuild_probe() {}");
+    fix.git_commit("initial");
+    let _ = fix.search("rebuild_probe");
+    fix.stop();
+
+    // An entry only a rebuild from scratch drops.
+    let db_path = fix.db_path();
+    {
+        let index = source_fast_core::PersistentIndex::open_or_create(&db_path).unwrap();
+        let ghost = fix.root().join("src").join("ghost.rs");
+        index
+            .index_content(&ghost.to_string_lossy(), "pub fn ghost_probe() {}", 1)
+            .unwrap();
+        index.flush().unwrap();
+    }
+
+    let output = fix
+        .sf()
+        .args(["index", "build", "--rebuild", "--root"])
+        .arg(fix.root())
+        .output()
+        .expect("sf index build --rebuild failed");
+    let ghost = source_fast_core::search_database_file_filtered(&db_path, "ghost_probe", None);
+    let probe = source_fast_core::search_database_file_filtered(&db_path, "rebuild_probe", None);
+    fix.stop();
+    assert!(
+        output.status.success(),
+        "rebuild failed: {}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    assert!(
+        ghost.unwrap().is_empty(),
+        "rebuild should drop stale entries"
+    );
+    assert_eq!(probe.unwrap().len(), 1);
+    assert!(!source_fast_core::staging_db_path(&db_path).exists());
+}
+
+#[test]
+fn test_index_build_rebuild_refused_while_another_writer_holds_lease() {
+    let fix = TestFixture::new();
+    fix.add_file("src/lib.rs", "pub fn lease_probe() {}");
+    let _ = fix.search("lease_probe");
+    fix.stop();
+
+    // A writer stopping the daemon does not stop, e.g. an `sf server`.
+    let db_path = fix.db_path();
+    {
+        let index = source_fast_core::PersistentIndex::open_or_create(&db_path).unwrap();
+        assert!(
+            index
+                .try_acquire_writer_lease("pid:1@elsewhere:1", std::time::Duration::from_secs(600))
+                .unwrap()
+        );
+    }
+
+    let output = fix
+        .sf()
+        .args(["index", "build", "--rebuild", "--root"])
+        .arg(fix.root())
+        .output()
+        .expect("sf index build --rebuild failed");
+    assert!(!output.status.success());
+    assert!(
+        String::from_utf8_lossy(&output.stderr).contains("another writer is active"),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    assert!(!source_fast_core::staging_db_path(&db_path).exists());
+}
+
+/// Additional: `--db-location global` keeps the index out of the checkout,
+/// and later commands find it without the flag.
+#[cfg(target_os = "linux")]
+#[test]
+fn test_global_db_location() {
+    let fix = TestFixture::new();
+    fix.add_file("src/lib.rs", "pub fn global_cache_probe() {}");
+    let cache = assert_fs::TempDir::new().unwrap();
+    let sf = |args: &[&str]| {
+        fix.sf()
+            .env("XDG_CACHE_HOME", cache.path())
+            .args(args)
+            .arg("--root")
+            .arg(fix.root())
+            .output()
+            .expect("sf failed")
+    };
+
+    let search = sf(&[
+        "search",
+        "--db-location",
+        "global",
+        "--wait",
+        "global_cache_probe",
+    ]);
+    let status = sf(&["daemon", "status"]);
+    let _ = sf(&["daemon", "stop"]);
+
+    assert!(
+        String::from_utf8_lossy(&search.stdout).contains("lib.rs"),
+        "search should work with a global index: {}",
+        String::from_utf8_lossy(&search.stderr)
+    );
+    assert!(
+        !fix.db_path().exists(),
+        "no index should be created inside the checkout"
+    );
+    let status = String::from_utf8_lossy(&status.stdout);
+    let cache_dir = cache.path().join("source_fast");
+    assert!(
+        status.contains(&cache_dir.display().to_string()),
+        "status should report the global index path: {status}"
+    );
+}
+
+/// Additional: `sf bench index` and `sf bench search` report JSON.
+/// Expected: the index bench leaves the checkout's index alone, and the
+/// search bench times every query.
+#[test]
+fn test_bench_reports_json() {
+    let fix = TestFixture::new();
+    fix.git_init();
+    fix.add_file("src/lib.rs", "pub fn bench_probe() {}");
+    fix.add_file("src/copy.rs", "pub fn bench_probe() {}");
+    fix.git_commit("initial");
+
+    let output = fix
+        .sf()
+        .args(["bench", "index", "--root"])
+        .arg(fix.root())
+        .output()
+        .expect("sf bench index failed");
+    assert!(
+        output.status.success(),
+        "bench index failed: {}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    let report: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(report["benchmark"], "index");
+    assert_eq!(report["files"], 2);
+    assert!(report["files_per_sec"].as_f64().unwrap() > 0.0);
+    assert!(
+        !fix.db_path().exists(),
+        "bench must not touch the repo index"
+    );
+
+    let _ = fix.search("bench_probe");
+    fix.stop();
+    fix.add_file("queries.txt", "bench_probe\n\nno_such_symbol_anywhere\n");
+    let output = fix
+        .sf()
+        .args([
+            "bench",
+            "search",
+            "--runs",
+            "3",
+            "--queries",
+            "queries.txt",
+            "--root",
+        ])
+        .arg(fix.root())
+        .output()
+        .expect("sf bench search failed");
+    assert!(
+        output.status.success(),
+        "bench search failed: {}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    let report: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(report["queries"], 2);
+    assert_eq!(report["samples"], 6);
+    assert_eq!(report["results"][0]["hits"], 2);
+    assert_eq!(report["results"][1]["hits"], 0);
+    assert!(report["p95_ms"].as_f64().unwrap() >= report["p50_ms"].as_f64().unwrap());
+}
+
+/// Shell completions and man pages
+/// Expected: every supported shell gets a script naming the subcommands,
+/// and `sf man --out-dir` writes one page per subcommand.
+#[test]
+fn test_completions_and_man_pages() {
+    let fix = TestFixture::new();
+
+    for shell in ["bash", "zsh", "fish", "powershell"] {
+        let output = fix
+            .sf()
+            .args(["completions", shell])
+            .output()
+            .expect("sf completions failed");
+        assert!(output.status.success(), "{shell} completions failed");
+        let script = String::from_utf8_lossy(&output.stdout);
+        assert!(
+            script.contains("search-file"),
+            "{shell} completions miss subcommands"
+        );
+    }
+
+    fix.sf()
+        .args(["man"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains(".TH sf 1"));
+
+    let out_dir = fix.root().join("man");
+    fix.sf()
+        .args(["man", "--out-dir"])
+        .arg(&out_dir)
+        .assert()
+        .success();
+    assert!(out_dir.join("sf.1").exists());
+    assert!(out_dir.join("sf-search.1").exists());
+    assert!(out_dir.join("sf-daemon-start.1").exists());
+}
@@ -75,6 +75,39 @@ fn test_reader_promotes_after_writer_exit() {
     let _ = server_b.call_search_code(42, "promote_target", None);
 }
 
+/// A reader never scans, but reports the index complete once the writer has
+/// finished building it.
+#[test]
+fn test_reader_reports_writer_readiness() {
+    let fix = TestFixture::new();
+    fix.add_file("src/main.rs", "fn reader_ready_target() {}\n");
+
+    let log_a = log_path(&fix.root(), "server_a.log");
+    let mut server_a = McpServerProcess::spawn_with_log(&fix.root(), Some(log_a.clone()));
+    let _ = server_a.initialize();
+    wait_for_log(&log_a, "role=writer", Duration::from_secs(5));
+
+    let log_b = log_path(&fix.root(), "server_b.log");
+    let mut server_b = McpServerProcess::spawn_with_log(&fix.root(), Some(log_b.clone()));
+    let _ = server_b.initialize();
+    wait_for_log(&log_b, "role=reader", Duration::from_secs(5));
+
+    let deadline = Instant::now() + Duration::from_secs(30);
+    let mut id = 50u64;
+    loop {
+        let resp = server_b.call_tool(id, "index_status", "{}");
+        id += 1;
+        if resp.to_string().contains("index: complete") {
+            break;
+        }
+        assert!(
+            Instant::now() < deadline,
+            "reader never saw the index complete: {resp}"
+        );
+        std::thread::sleep(Duration::from_millis(200));
+    }
+}
+
 /// If multiple servers start concurrently, exactly one should become writer (others readers).
 #[test]
 fn test_only_one_writer_with_multiple_servers() {
@@ -113,3 +146,52 @@ fn test_only_one_writer_with_multiple_servers() {
         "Expected exactly one writer; got a={a}, b={b}, c={c}"
     );
 }
+
+/// `sf server --shared` proxies every client to the daemon, so several
+/// editor windows share one watcher/writer instead of competing for it.
+#[test]
+fn test_shared_servers_proxy_to_daemon() {
+    let fix = TestFixture::new();
+    fix.add_file("src/main.rs", "fn shared_target() {}\n");
+
+    let log_a = log_path(&fix.root(), "server_a.log");
+    let log_b = log_path(&fix.root(), "server_b.log");
+    let mut server_a =
+        McpServerProcess::spawn_with_args(&fix.root(), Some(log_a.clone()), &["--shared"]);
+    let mut server_b =
+        McpServerProcess::spawn_with_args(&fix.root(), Some(log_b.clone()), &["--shared"]);
+
+    for server in [&mut server_a, &mut server_b] {
+        let init = server.initialize();
+        assert!(init.get("result").is_some(), "initialize failed: {init}");
+    }
+
+    let deadline = Instant::now() + Duration::from_secs(30);
+    for server in [&mut server_a, &mut server_b] {
+        let mut id = 10u64;
+        loop {
+            let resp = server.call_search_code(id, "shared_target", None);
+            id += 1;
+            if resp.to_string().contains("main.rs") {
+                break;
+            }
+            assert!(
+                Instant::now() < deadline,
+                "shared server never returned results: {resp}"
+            );
+            std::thread::sleep(Duration::from_millis(200));
+        }
+    }
+
+    for log in [&log_a, &log_b] {
+        let text = std::fs::read_to_string(log).unwrap_or_default();
+        assert!(
+            text.contains("proxying stdio to shared MCP server"),
+            "server should proxy to the daemon: {text}"
+        );
+        assert!(
+            !text.contains("role=writer"),
+            "a shared server must not become writer itself: {text}"
+        );
+    }
+}
@@ -102,3 +102,553 @@ fn test_mcp_search_code_eventually_no_warning() {
 
     panic!("Expected readiness warning to disappear; last response: {last:?}");
 }
+
+/// A zero latency budget returns immediately with the truncation marker.
+#[test]
+fn test_mcp_search_code_timeout_marks_truncated() {
+    let fix = TestFixture::new();
+    fix.add_file("src/main.rs", "fn budget_target() {}\n");
+    fix.search("budget_target");
+
+    let mut server = McpServerProcess::spawn(&fix.root());
+    let _init = server.initialize();
+
+    let resp = server.call_search_code_with_args(10, r#"{"query":"budget_target","timeout_ms":0}"#);
+    assert!(!response_has_error(&resp), "{resp}");
+    assert!(
+        response_text_blob(&resp).contains("truncated: true"),
+        "expected truncation marker: {resp}"
+    );
+
+    let resp =
+        server.call_search_code_with_args(11, r#"{"query":"budget_target","timeout_ms":60000}"#);
+    let text = response_text_blob(&resp);
+    assert!(!text.contains("truncated"), "unexpected truncation: {resp}");
+    assert!(
+        text.contains("index_generation: "),
+        "expected the index generation: {resp}"
+    );
+}
+
+/// `count_only` answers with the number of matching files; the older
+/// `count` spelling still works.
+#[test]
+fn test_mcp_search_code_count_only() {
+    let fix = TestFixture::new();
+    fix.add_file("src/a.rs", "fn counted_target() {}\n");
+    fix.add_file("src/b.rs", "counted_target();\ncounted_target();\n");
+    fix.add_file("src/c.rs", "fn other() {}\n");
+    fix.search("counted_target");
+
+    let mut server = McpServerProcess::spawn(&fix.root());
+    let _init = server.initialize();
+
+    let deadline = Instant::now() + Duration::from_secs(30);
+    let mut id = 20u64;
+    loop {
+        let resp = server
+            .call_search_code_with_args(id, r#"{"query":"counted_target","count_only":true}"#);
+        id += 1;
+        assert!(!response_has_error(&resp), "{resp}");
+        let text = response_text_blob(&resp);
+        if !text.contains("building") {
+            assert!(text.starts_with('2'), "expected two files: {text}");
+            assert!(!text.contains("a.rs"), "no paths or snippets: {text}");
+            break;
+        }
+        assert!(
+            Instant::now() < deadline,
+            "index never became ready: {text}"
+        );
+        std::thread::sleep(Duration::from_millis(200));
+    }
+
+    let resp = server.call_search_code_with_args(id, r#"{"query":"counted_target","count":true}"#);
+    assert!(response_text_blob(&resp).starts_with('2'), "{resp}");
+}
+
+/// Snippets past `max_bytes` collapse into a `path:line` summary, with the
+/// file holding the most matches rendered in full first.
+#[test]
+fn test_mcp_search_code_max_bytes_summarizes_overflow() {
+    let fix = TestFixture::new();
+    fix.add_file("src/a.rs", "fn sized_target() {}\n");
+    fix.add_file(
+        "src/b.rs",
+        "sized_target();\nsized_target();\nsized_target();\n",
+    );
+    fix.add_file("src/c.rs", "fn other() {}\n\nfn x() { sized_target() }\n");
+
+    let mut server = McpServerProcess::spawn(&fix.root());
+    let _init = server.initialize();
+
+    let unlimited = r#"{"query":"sized_target","max_bytes":0}"#;
+    let deadline = Instant::now() + Duration::from_secs(30);
+    let mut id = 30u64;
+    let blocks: Vec<String> = loop {
+        let resp = server.call_search_code_with_args(id, unlimited);
+        id += 1;
+        let text = response_text_blob(&resp);
+        if text.contains("c.rs") && !text.contains("building") {
+            break resp["result"]["content"]
+                .as_array()
+                .unwrap()
+                .iter()
+                .filter_map(|item| item["text"].as_str().map(str::to_string))
+                .filter(|text| !text.starts_with("index_generation: "))
+                .collect();
+        }
+        assert!(
+            Instant::now() < deadline,
+            "index never became ready: {text}"
+        );
+        std::thread::sleep(Duration::from_millis(200));
+    };
+    assert_eq!(blocks.len(), 3, "one block per file: {blocks:?}");
+    assert!(
+        blocks[0].contains("b.rs:1:1\n"),
+        "b.rs should come first: {blocks:?}"
+    );
+
+    // Room for the first file only.
+    let args = format!(
+        r#"{{"query":"sized_target","max_bytes":{}}}"#,
+        blocks[0].len() + 1
+    );
+    let text = response_text_blob(&server.call_search_code_with_args(id, &args));
+    let summary = text
+        .find("Snippets omitted")
+        .unwrap_or_else(|| panic!("expected an overflow summary: {text}"));
+    assert!(text[..summary].contains("b.rs:1:1\n"), "{text}");
+    assert!(!text[..summary].contains("a.rs"), "{text}");
+    assert!(text[summary..].contains("a.rs:1:4\n"), "{text}");
+    assert!(text[summary..].contains("c.rs:3:10\n"), "{text}");
+}
+
+/// `index_status` reports the index phase and the file watcher's health.
+#[test]
+fn test_mcp_index_status_reports_watcher() {
+    let fix = TestFixture::new();
+    fix.add_file("src/main.rs", "fn status_target() {}\n");
+
+    let mut server = McpServerProcess::spawn(&fix.root());
+    let _init = server.initialize();
+
+    let deadline = Instant::now() + Duration::from_secs(30);
+    let mut id = 40u64;
+    loop {
+        let resp = server.call_tool(id, "index_status", "{}");
+        id += 1;
+        assert!(!response_has_error(&resp), "{resp}");
+        let text = response_text_blob(&resp);
+        if text.contains("index: complete") && text.contains("watcher: watching") {
+            break;
+        }
+        assert!(
+            Instant::now() < deadline,
+            "watcher never reported healthy: {text}"
+        );
+        std::thread::sleep(Duration::from_millis(200));
+    }
+}
+
+/// While the initial scan runs, `index_status` reports how far it got.
+#[test]
+fn test_mcp_index_status_reports_scan_progress() {
+    let fix = TestFixture::new();
+    for i in 0..2000 {
+        fix.add_file(
+            &format!("src/gen_{i}.rs"),
+            &format!("pub fn progress_{i}() {{}}\n"),
+        );
+    }
+
+    let mut server = McpServerProcess::spawn(&fix.root());
+    let _init = server.initialize();
+
+    let deadline = Instant::now() + Duration::from_secs(60);
+    let mut id = 50u64;
+    loop {
+        let resp = server.call_tool(id, "index_status", "{}");
+        id += 1;
+        assert!(!response_has_error(&resp), "{resp}");
+        let text = response_text_blob(&resp);
+        if text.contains("index: complete") {
+            assert!(!text.contains("progress:"), "{text}");
+            break;
+        }
+        // Best-effort: the scan may finish before the first poll.
+        assert!(text.contains("progress: "), "{text}");
+        assert!(text.contains(" files"), "{text}");
+        assert!(
+            Instant::now() < deadline,
+            "index never became ready: {text}"
+        );
+        std::thread::sleep(Duration::from_millis(100));
+    }
+}
+
+/// Repeated searches may be served from the cache, but an index change must
+/// not leave them stale.
+#[test]
+fn test_mcp_search_code_cache_sees_index_changes() {
+    let fix = TestFixture::new();
+    fix.add_file("src/a.rs", "fn cache_target() {}\n");
+
+    let mut server = McpServerProcess::spawn(&fix.root());
+    let _init = server.initialize();
+
+    let args = r#"{"query":"cache_target","files_only":true}"#;
+    let deadline = Instant::now() + Duration::from_secs(30);
+    let mut id = 20u64;
+    let first = loop {
+        let text = response_text_blob(&server.call_search_code_with_args(id, args));
+        id += 1;
+        if text.contains("a.rs") && !text.contains("building") {
+            break text;
+        }
+        assert!(
+            Instant::now() < deadline,
+            "index never became ready: {text}"
+        );
+        std::thread::sleep(Duration::from_millis(200));
+    };
+    let again = response_text_blob(&server.call_search_code_with_args(id, args));
+    id += 1;
+    assert_eq!(first, again);
+
+    fix.add_file("src/b.rs", "fn cache_target_too() {}\n");
+    let deadline = Instant::now() + Duration::from_secs(30);
+    loop {
+        let text = response_text_blob(&server.call_search_code_with_args(id, args));
+        id += 1;
+        if text.contains("b.rs") {
+            break;
+        }
+        assert!(
+            Instant::now() < deadline,
+            "cached result never picked up the new file: {text}"
+        );
+        std::thread::sleep(Duration::from_millis(200));
+    }
+}
+
+/// A server reading while the daemon writes moves over to the index a
+/// rebuild swaps in, and follows the new daemon's writes to it.
+#[test]
+fn test_mcp_reopens_rebuilt_index() {
+    let fix = TestFixture::new();
+    fix.add_file("src/a.rs", "fn reopen_target() {}\n");
+    fix.search("reopen_target");
+
+    let mut server = McpServerProcess::spawn(&fix.root());
+    let _init = server.initialize();
+    let first = response_text_blob(
+        &server.call_search_code_with_args(30, r#"{"query":"reopen_target","files_only":true}"#),
+    );
+    assert!(first.contains("a.rs"), "{first}");
+
+    let output = fix
+        .sf()
+        .args(["index", "build", "--rebuild", "--root"])
+        .arg(fix.root())
+        .output()
+        .expect("sf index build --rebuild failed");
+    assert!(
+        output.status.success(),
+        "rebuild failed: {}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+
+    // Only the rebuilt index, which the new daemon writes, gets this file.
+    std::thread::sleep(Duration::from_secs(2));
+    fix.add_file("src/b.rs", "fn reopen_target_too() {}\n");
+    let deadline = Instant::now() + Duration::from_secs(30);
+    let mut id = 31u64;
+    loop {
+        let text =
+            response_text_blob(&server.call_search_code_with_args(
+                id,
+                r#"{"query":"reopen_target_too","files_only":true}"#,
+            ));
+        id += 1;
+        if text.contains("b.rs") {
+            break;
+        }
+        assert!(
+            Instant::now() < deadline,
+            "server never searched the rebuilt index: {text}"
+        );
+        std::thread::sleep(Duration::from_millis(200));
+    }
+    fix.stop();
+}
+
+/// `--allow-path` and `--deny-path` limit what `search_code` returns, even
+/// though every file is indexed.
+#[test]
+fn test_mcp_search_code_respects_path_scope() {
+    let fix = TestFixture::new();
+    fix.add_file("src/lib.rs", "fn scoped_target() {}\n");
+    fix.add_file("src/internal/keys.rs", "fn scoped_target() {}\n");
+    fix.add_file("tools/gen.rs", "fn scoped_target() {}\n");
+
+    let mut server = McpServerProcess::spawn_with_args(
+        &fix.root(),
+        None,
+        &["--allow-path", "src", "--deny-path", "src/internal"],
+    );
+    let _init = server.initialize();
+
+    let args = r#"{"query":"scoped_target","files_only":true}"#;
+    let deadline = Instant::now() + Duration::from_secs(30);
+    let mut id = 30u64;
+    let text = loop {
+        let text = response_text_blob(&server.call_search_code_with_args(id, args));
+        id += 1;
+        if text.contains("lib.rs") && !text.contains("building") {
+            break text;
+        }
+        assert!(
+            Instant::now() < deadline,
+            "index never became ready: {text}"
+        );
+        std::thread::sleep(Duration::from_millis(200));
+    };
+    assert!(!text.contains("keys.rs"), "denied path returned: {text}");
+    assert!(
+        !text.contains("gen.rs"),
+        "path outside allow list returned: {text}"
+    );
+}
+
+/// `sf server --registry` serves several roots: `list_repos` names them
+/// and `search_code` searches the one passed as `repo`.
+#[test]
+fn test_mcp_registry_serves_several_repos() {
+    let fix = TestFixture::new();
+    fix.add_file("alpha/src/lib.rs", "fn registry_target_alpha() {}\n");
+    fix.add_file("beta/src/lib.rs", "fn registry_target_beta() {}\n");
+    let alpha = fix.root().join("alpha");
+    let beta = fix.root().join("beta");
+    let registry = fix.root().join("repos.toml");
+    std::fs::write(
+        &registry,
+        format!(
+            "[[repo]]\nroot = {:?}\n\n[[repo]]\nname = \"other\"\nroot = {:?}\n",
+            alpha.display().to_string(),
+            beta.display().to_string()
+        ),
+    )
+    .unwrap();
+
+    let mut server = McpServerProcess::spawn_registry(&registry, &[]);
+    let _init = server.initialize();
+
+    let repos = response_text_blob(&server.call_tool(40, "list_repos", "{}"));
+    assert!(repos.contains("alpha: "), "list_repos: {repos}");
+    assert!(repos.contains("other: "), "list_repos: {repos}");
+
+    let deadline = Instant::now() + Duration::from_secs(30);
+    let mut id = 41u64;
+    loop {
+        let text = response_text_blob(&server.call_search_code_with_args(
+            id,
+            r#"{"query":"registry_target_beta","files_only":true,"repo":"other"}"#,
+        ));
+        id += 1;
+        if text.contains("lib.rs") && !text.contains("building") {
+            break;
+        }
+        assert!(
+            Instant::now() < deadline,
+            "beta never became searchable: {text}"
+        );
+        std::thread::sleep(Duration::from_millis(200));
+    }
+    let default =
+        response_text_blob(&server.call_search_code_with_args(
+            id,
+            r#"{"query":"registry_target_beta","files_only":true}"#,
+        ));
+    assert!(
+        !default.contains("lib.rs"),
+        "default repo is alpha: {default}"
+    );
+
+    let unknown =
+        server.call_search_code_with_args(id + 1, r#"{"query":"registry_target","repo":"nope"}"#);
+    assert!(response_has_error(&unknown));
+}
+
+/// `add_root` starts serving a directory at runtime and records it in the
+/// registry, which may not exist yet. Only directories under one allowed
+/// with `--allow-add-root` are accepted, and their own config cannot widen
+/// the operator's path scope.
+#[test]
+fn test_mcp_add_root_at_runtime() {
+    let fix = TestFixture::new();
+    fix.add_file("late/src/lib.rs", "fn added_root_target() {}\n");
+    fix.add_file("late/secret/keys.rs", "fn added_root_target() {}\n");
+    fix.add_file(
+        "late/.source_fast/config.toml",
+        "[mcp]\nallow_paths = [\"secret\"]\n",
+    );
+    fix.add_file("elsewhere/src/lib.rs", "fn added_root_target() {}\n");
+    let late = fix.root().join("late");
+    let registry = fix.root().join("config").join("repos.toml");
+
+    let mut closed = McpServerProcess::spawn_registry(&registry, &[]);
+    let _init = closed.initialize();
+    let args = serde_json::json!({ "root": late.display().to_string() }).to_string();
+    let refused = closed.call_tool(49, "add_root", &args);
+    assert!(response_has_error(&refused), "add_root accepted: {refused}");
+    closed.kill();
+
+    let allowed = late.display().to_string();
+    let mut server = McpServerProcess::spawn_registry(
+        &registry,
+        &["--allow-add-root", &allowed, "--allow-path", "src"],
+    );
+    let _init = server.initialize();
+    let empty = server.call_search_code_with_args(50, r#"{"query":"added_root_target"}"#);
+    assert!(response_has_error(&empty));
+
+    let outside = serde_json::json!({ "root": fix.root().join("elsewhere").display().to_string() })
+        .to_string();
+    let refused = server.call_tool(60, "add_root", &outside);
+    assert!(
+        response_has_error(&refused),
+        "root outside --allow-add-root accepted: {refused}"
+    );
+
+    let added = server.call_tool(51, "add_root", &args);
+    assert!(!response_has_error(&added), "add_root failed: {added}");
+    assert!(response_text_blob(&added).contains("added repo `late`"));
+    let again = server.call_tool(52, "add_root", &args);
+    assert!(response_has_error(&again), "duplicate root accepted");
+
+    let deadline = Instant::now() + Duration::from_secs(30);
+    let mut id = 53u64;
+    let text = loop {
+        let text = response_text_blob(&server.call_search_code_with_args(
+            id,
+            r#"{"query":"added_root_target","files_only":true,"repo":"late"}"#,
+        ));
+        id += 1;
+        if text.contains("lib.rs") && !text.contains("building") {
+            break text;
+        }
+        assert!(
+            Instant::now() < deadline,
+            "added root never became searchable: {text}"
+        );
+        std::thread::sleep(Duration::from_millis(200));
+    };
+    assert!(
+        !text.contains("keys.rs"),
+        "the added root's config widened --allow-path: {text}"
+    );
+
+    let saved = std::fs::read_to_string(&registry).expect("registry written");
+    assert!(saved.contains("name = \"late\""), "registry: {saved}");
+}
+
+/// POST one JSON-RPC message to the streamable HTTP endpoint. Returns the
+/// `Mcp-Session-Id` header and the first JSON payload of the SSE reply.
+fn mcp_http_post(
+    addr: &str,
+    session: Option<&str>,
+    body: &str,
+) -> (Option<String>, Option<serde_json::Value>) {
+    use std::io::{BufRead, BufReader, Write};
+
+    let mut stream = std::net::TcpStream::connect(addr).unwrap();
+    stream
+        .set_read_timeout(Some(Duration::from_secs(10)))
+        .unwrap();
+    let session_header = session
+        .map(|id| format!("Mcp-Session-Id: {id}\r\n"))
+        .unwrap_or_default();
+    write!(
+        stream,
+        "POST /mcp HTTP/1.1\r\nHost: {addr}\r\nContent-Type: application/json\r\n\
+         Accept: application/json, text/event-stream\r\n{session_header}\
+         Content-Length: {}\r\nConnection: close\r\n\r\n{body}",
+        body.len()
+    )
+    .unwrap();
+
+    let mut session_id = None;
+    for line in BufReader::new(stream).lines() {
+        let Ok(line) = line else { break };
+        if let Some((name, value)) = line.split_once(':')
+            && name.eq_ignore_ascii_case("mcp-session-id")
+        {
+            session_id = Some(value.trim().to_string());
+        }
+        if let Some(data) = line.strip_prefix("data:")
+            && let Ok(json) = serde_json::from_str(data.trim())
+        {
+            return (session_id, Some(json));
+        }
+    }
+    (session_id, None)
+}
+
+/// `sf server --transport http` serves search_code to clients over
+/// streamable HTTP instead of stdio.
+#[test]
+fn test_mcp_streamable_http_transport() {
+    let fix = TestFixture::new();
+    fix.add_file("src/main.rs", "fn http_transport_target() {}\n");
+
+    let addr = {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        listener.local_addr().unwrap().to_string()
+    };
+    let mut server = std::process::Command::new(env!("CARGO_BIN_EXE_sf"))
+        .args(["server", "--transport", "http", "--addr", &addr, "--root"])
+        .arg(fix.root())
+        .stdout(std::process::Stdio::null())
+        .stderr(std::process::Stdio::null())
+        .spawn()
+        .unwrap();
+    let deadline = Instant::now() + Duration::from_secs(30);
+    while std::net::TcpStream::connect(&addr).is_err() {
+        assert!(Instant::now() < deadline, "MCP HTTP server did not start");
+        std::thread::sleep(Duration::from_millis(100));
+    }
+
+    let (session, init) = mcp_http_post(
+        &addr,
+        None,
+        r#"{"jsonrpc":"2.0","id":1,"method":"initialize","params":{"protocolVersion":"2024-11-05","capabilities":{},"clientInfo":{"name":"test","version":"1.0"}}}"#,
+    );
+    let session = session.expect("initialize should return a session id");
+    assert!(init.unwrap()["result"]["serverInfo"].is_object());
+    mcp_http_post(
+        &addr,
+        Some(&session),
+        r#"{"jsonrpc":"2.0","method":"notifications/initialized"}"#,
+    );
+
+    for id in 2u64.. {
+        let body = format!(
+            r#"{{"jsonrpc":"2.0","id":{id},"method":"tools/call","params":{{"name":"search_code","arguments":{{"query":"http_transport_target"}}}}}}"#
+        );
+        let (_, resp) = mcp_http_post(&addr, Some(&session), &body);
+        let resp = resp.expect("tools/call should return a response");
+        if !response_has_error(&resp) && response_text_blob(&resp).contains("main.rs") {
+            break;
+        }
+        assert!(
+            Instant::now() < deadline,
+            "search over HTTP never found the file; last response: {resp}"
+        );
+        std::thread::sleep(Duration::from_millis(200));
+    }
+
+    let _ = server.kill();
+    let _ = server.wait();
+}
@@ -217,9 +217,10 @@ fn test_search_empty_directory() {
 
     // Search empty directory should not crash
     let output = fix.search("nonexistent_query");
-    assert!(
-        output.status.success(),
-        "Search on empty directory should not crash"
+    assert_eq!(
+        output.status.code(),
+        Some(1),
+        "Search on empty directory should not crash, only report no match"
     );
 }
 
@@ -234,9 +235,10 @@ fn test_search_only_ignored() {
 
     // Should not crash
     let output = fix.search("ignored content");
-    assert!(
-        output.status.success(),
-        "Search with only ignored files should not crash"
+    assert_eq!(
+        output.status.code(),
+        Some(1),
+        "Search with only ignored files should not crash, only report no match"
     );
 }
 
@@ -194,9 +194,34 @@ fn test_minimum_query_length() {
         "3-char query should work"
     );
 
-    // 2 char query behavior depends on implementation
-    // Just ensure it doesn't crash
-    let _ = fix.search("ab");
+    // Shorter queries have no trigrams; a small index is read directly
+    fix.add_file("src/other.rs", "fn other() {}");
+    let output = fix.search("ab");
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(output.status.success());
+    assert!(
+        stdout.contains("main.rs"),
+        "2-char query should scan: {}",
+        stdout
+    );
+    assert!(!stdout.contains("other.rs"), "Scan must verify: {}", stdout);
+
+    let output = fix
+        .sf()
+        .args(["search", "--root"])
+        .arg(fix.root())
+        .args(["--wait", "--count", "--no-verify", "ab"])
+        .output()
+        .expect("sf search failed");
+    assert_eq!(String::from_utf8_lossy(&output.stdout).trim(), "1");
+
+    let output = fix.search("zq");
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        stderr.contains("read directly"),
+        "Should explain the scan: {}",
+        stderr
+    );
 }
 
 /// Additional: Special characters in search
@@ -278,3 +303,372 @@ fn main() {
         stdout
     );
 }
+
+/// Additional: trigram-only matches are verified away
+/// The file holds every trigram of the query, but never the query itself.
+#[test]
+fn test_search_verifies_literal_match() {
+    let fix = TestFixture::new();
+    fix.add_file("src/main.rs", "fn abcde() {} // cdefg\n");
+
+    let output = fix.search("abcdefg");
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(
+        !stdout.contains("main.rs"),
+        "Trigram-only match should be dropped: {}",
+        stdout
+    );
+
+    let count = |extra: &[&str]| {
+        let output = fix
+            .sf()
+            .args(["search", "--root"])
+            .arg(fix.root())
+            .args(["--count", "abcdefg"])
+            .args(extra)
+            .output()
+            .expect("sf search failed");
+        String::from_utf8_lossy(&output.stdout).trim().to_string()
+    };
+    assert_eq!(count(&[]), "0");
+    assert_eq!(count(&["--no-verify"]), "1");
+
+    let output = fix
+        .sf()
+        .args(["search", "--root"])
+        .arg(fix.root())
+        .args(["--no-verify", "abcdefg"])
+        .output()
+        .expect("sf search failed");
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(
+        stdout.contains("main.rs"),
+        "--no-verify should list the candidate: {}",
+        stdout
+    );
+}
+
+/// Additional: empty results explain themselves
+#[test]
+fn test_search_no_results_diagnostics() {
+    let fix = TestFixture::new();
+    fix.add_file("src/main.rs", "fn present_marker() {}\n");
+
+    let output = fix.search("absent_zqxj_marker");
+    assert_eq!(output.status.code(), Some(1), "no match exits 1");
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        stderr.contains("No results for \"absent_zqxj_marker\""),
+        "Should explain the empty result: {}",
+        stderr
+    );
+    assert!(
+        stderr.contains("zqx"),
+        "Should name a missing trigram: {}",
+        stderr
+    );
+
+    let output = fix
+        .sf()
+        .args(["search", "--root"])
+        .arg(fix.root())
+        .args(["--wait", "--json", "absent_zqxj_marker"])
+        .output()
+        .expect("sf search failed");
+    let json: serde_json::Value =
+        serde_json::from_slice(&output.stdout).expect("search --json should print JSON");
+    let diagnostics = &json["diagnostics"];
+    assert_eq!(diagnostics["files_indexed"], 1);
+    assert_eq!(diagnostics["query_too_short"], false);
+    assert!(
+        diagnostics["missing_trigrams"]
+            .as_array()
+            .is_some_and(|missing| missing.iter().any(|t| t == "zqx")),
+        "Should list missing trigrams: {}",
+        json
+    );
+
+    let output = fix
+        .sf()
+        .args(["search", "--root"])
+        .arg(fix.root())
+        .args(["--wait", "--json", "present_marker"])
+        .output()
+        .expect("sf search failed");
+    let json: serde_json::Value =
+        serde_json::from_slice(&output.stdout).expect("search --json should print JSON");
+    assert!(
+        json.get("diagnostics").is_none(),
+        "Hits need no diagnostics"
+    );
+}
+
+#[test]
+fn test_search_snippets_stop_at_limit_in_path_order() {
+    let fix = TestFixture::new();
+    for i in 0..12 {
+        fix.add_file(&format!("src/f{i:02}.rs"), "fn limited_marker() {}\n");
+    }
+
+    let output = fix
+        .sf()
+        .args(["search", "--root"])
+        .arg(fix.root())
+        .args(["--read-concurrency", "2", "-l", "3", "limited_marker"])
+        .output()
+        .expect("sf search failed");
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    let shown: Vec<&str> = stdout
+        .lines()
+        .filter(|line| line.ends_with(" (1 match)"))
+        .filter_map(|line| line.split("src/").nth(1))
+        .filter_map(|rest| rest.split(".rs").next())
+        .collect();
+    assert_eq!(shown, ["f00", "f01", "f02"], "stdout: {stdout}");
+    assert!(
+        stderr.contains("... and 9 more"),
+        "Remaining hits should be reported: {stderr}"
+    );
+}
+
+#[test]
+fn test_search_header_counts_matching_lines() {
+    let fix = TestFixture::new();
+    fix.add_file(
+        "src/hot.rs",
+        "fn counted_marker_a() {}\nfn other() {}\nfn counted_marker_b() {}\nfn counted_marker_c() {}\n",
+    );
+    fix.add_file("src/cold.rs", "fn counted_marker_once() {}\n");
+
+    let output = fix.search("counted_marker");
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let header = |name: &str| {
+        stdout
+            .lines()
+            .find(|line| line.contains(name) && line.contains(" ("))
+            .map(str::to_string)
+    };
+    let hot = header("hot.rs").expect("hot.rs should have a header");
+    assert!(hot.ends_with(" (3 matches)"), "stdout: {stdout}");
+    let cold = header("cold.rs").expect("cold.rs should have a header");
+    assert!(cold.ends_with(" (1 match)"), "stdout: {stdout}");
+}
+
+/// Scripting: grep-compatible exit codes, `--quiet` and `--porcelain`
+/// Expected: 0 on a match, 1 without one, 2 on an error; quiet prints
+/// nothing, porcelain prints one `path:line:text` per matching line.
+#[test]
+fn test_search_exit_codes_quiet_and_porcelain() {
+    let fix = TestFixture::new();
+    fix.add_file(
+        "src/lib.rs",
+        "fn exit_code_probe() {}\n// unrelated\nexit_code_probe();\n",
+    );
+    let _ = fix.search("exit_code_probe");
+
+    let search = |args: &[&str]| {
+        fix.sf()
+            .args(["search", "--root"])
+            .arg(fix.root())
+            .args(args)
+            .output()
+            .expect("sf search failed")
+    };
+
+    let output = search(&["-q", "exit_code_probe"]);
+    assert_eq!(output.status.code(), Some(0));
+    assert!(output.stdout.is_empty() && output.stderr.is_empty());
+
+    let output = search(&["-q", "absent_exit_probe"]);
+    assert_eq!(output.status.code(), Some(1));
+    assert!(output.stdout.is_empty() && output.stderr.is_empty());
+
+    let output = search(&["--porcelain", "exit_code_probe"]);
+    assert_eq!(output.status.code(), Some(0));
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let lines: Vec<&str> = stdout.lines().collect();
+    assert_eq!(lines.len(), 2, "one line per match: {stdout}");
+    assert!(
+        lines[0].ends_with("lib.rs:1:fn exit_code_probe() {}"),
+        "{stdout}"
+    );
+    assert!(
+        lines[1].ends_with("lib.rs:3:exit_code_probe();"),
+        "{stdout}"
+    );
+    assert!(!stdout.contains('\x1b'), "no color codes: {stdout}");
+
+    let output = search(&["--porcelain", "absent_exit_probe"]);
+    assert_eq!(output.status.code(), Some(1));
+    assert!(output.stdout.is_empty() && output.stderr.is_empty());
+
+    let output = search(&["--file-regex", "(unclosed", "exit_code_probe"]);
+    assert_eq!(output.status.code(), Some(2));
+}
+
+/// Batch search: `sf search --stdin` with one query per line
+/// Expected: one lookup answers every query; output lines carry their
+/// query, and the exit code reflects whether any query matched.
+#[test]
+fn test_search_stdin_batch() {
+    let fix = TestFixture::new();
+    fix.add_file("src/a.rs", "fn batch_alpha_probe() {}\n");
+    fix.add_file("src/b.rs", "fn batch_beta_probe() {}\n");
+    let _ = fix.search("batch_alpha_probe");
+
+    let batch = |args: &[&str], stdin: &str| {
+        fix.sf()
+            .args(["search", "--root"])
+            .arg(fix.root())
+            .args(["--stdin"])
+            .args(args)
+            .write_stdin(stdin)
+            .output()
+            .expect("sf search --stdin failed")
+    };
+    let queries = "batch_alpha_probe\n\nbatch_beta_probe\nbatch_missing_probe\n";
+
+    let output = batch(&["-c"], queries);
+    assert_eq!(output.status.code(), Some(0));
+    assert_eq!(
+        String::from_utf8_lossy(&output.stdout),
+        "batch_alpha_probe\t1\nbatch_beta_probe\t1\nbatch_missing_probe\t0\n"
+    );
+
+    let output = batch(&["--porcelain"], queries);
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let lines: Vec<&str> = stdout.lines().collect();
+    assert_eq!(lines.len(), 2, "{stdout}");
+    assert!(lines[0].starts_with("batch_alpha_probe\t"), "{stdout}");
+    assert!(
+        lines[0].ends_with("a.rs:1:fn batch_alpha_probe() {}"),
+        "{stdout}"
+    );
+    assert!(lines[1].starts_with("batch_beta_probe\t"), "{stdout}");
+
+    let output = batch(&["-j"], queries);
+    let json: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    let results = json.as_array().expect("one object per query");
+    assert_eq!(results.len(), 3);
+    assert_eq!(results[2]["query"], "batch_missing_probe");
+    assert_eq!(results[2]["total"], 0);
+
+    let output = batch(&["-q"], "batch_missing_probe\n");
+    assert_eq!(output.status.code(), Some(1));
+}
+
+/// `--group-by dir` lists each top-level directory with its file count,
+/// largest first, and a matching line from it.
+#[test]
+fn test_search_group_by_dir() {
+    let fix = TestFixture::new();
+    fix.add_file("api/a.rs", "fn grouped_probe() {}\n");
+    fix.add_file("api/nested/b.rs", "grouped_probe();\n");
+    fix.add_file("web/c.ts", "grouped_probe();\n");
+    fix.add_file("top.txt", "grouped_probe\n");
+    fix.search("grouped_probe");
+
+    let grouped = |extra: &[&str]| {
+        fix.sf()
+            .args(["search", "--root"])
+            .arg(fix.root())
+            .args(["--group-by", "dir", "grouped_probe"])
+            .args(extra)
+            .output()
+            .expect("sf search --group-by failed")
+    };
+
+    let output = grouped(&[]);
+    assert_eq!(output.status.code(), Some(0));
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let headers: Vec<&str> = stdout.lines().filter(|l| !l.starts_with(' ')).collect();
+    assert_eq!(
+        headers,
+        ["api (2 files)", ". (1 file)", "web (1 file)"],
+        "{stdout}"
+    );
+    assert!(
+        stdout.contains("  web/c.ts:1: grouped_probe();"),
+        "{stdout}"
+    );
+
+    let output = grouped(&["-j"]);
+    let json: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(json["total"], 4);
+    assert_eq!(json["groups"][0]["dir"], "api");
+    assert_eq!(json["groups"][0]["files"], 2);
+    assert_eq!(json["groups"][0]["samples"].as_array().unwrap().len(), 2);
+}
+
+/// `--only-code`, `--only-comments` and `--only-strings` keep the files and
+/// lines where the query occurs in that part of the source.
+#[test]
+fn test_search_only_code_comments_strings() {
+    let fix = TestFixture::new();
+    fix.add_file("src/code.rs", "fn kind_probe() {}\n");
+    fix.add_file("src/comment.rs", "// see kind_probe\nfn other() {}\n");
+    fix.add_file("src/string.py", "name = \"kind_probe\"\n");
+    fix.search("kind_probe");
+
+    let files = |flag: &str| {
+        let output = fix
+            .sf()
+            .args(["search", "--root"])
+            .arg(fix.root())
+            .args(["--files-only", flag, "kind_probe"])
+            .output()
+            .expect("sf search failed");
+        let stdout = String::from_utf8_lossy(&output.stdout).into_owned();
+        let mut names: Vec<String> = stdout
+            .lines()
+            .map(|line| line.rsplit(['/', '\\']).next().unwrap().to_string())
+            .collect();
+        names.sort();
+        names
+    };
+    assert_eq!(files("--only-code"), ["code.rs"]);
+    assert_eq!(files("--only-comments"), ["comment.rs"]);
+    assert_eq!(files("--only-strings"), ["string.py"]);
+
+    let output = fix
+        .sf()
+        .args(["search", "--root"])
+        .arg(fix.root())
+        .args(["--porcelain", "--only-comments", "kind_probe"])
+        .output()
+        .expect("sf search failed");
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert_eq!(stdout.lines().count(), 1, "{stdout}");
+    assert!(
+        stdout.ends_with("comment.rs:1:// see kind_probe\n"),
+        "{stdout}"
+    );
+}
+
+#[test]
+fn test_search_identifier_matches_naming_conventions() {
+    let fix = TestFixture::new();
+    fix.add_file("src/lib.rs", "pub fn parse_config_file() {}\n");
+    fix.add_file("src/app.ts", "export function parseConfigFile() {}\n");
+    fix.add_file("src/Consts.cs", "const int PARSE_CONFIG_FILE = 1;\n");
+    fix.add_file("src/other.rs", "fn reparse_config_files() {}\n");
+    fix.search("parse_config_file");
+
+    let output = fix
+        .sf()
+        .args(["search", "--root"])
+        .arg(fix.root())
+        .args(["--porcelain", "--identifier", "ParseConfigFile"])
+        .output()
+        .expect("sf search failed");
+    assert!(output.status.success());
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let mut names: Vec<&str> = stdout
+        .lines()
+        .map(|line| line.rsplit(['/', '\\']).next().unwrap())
+        .map(|line| line.split(':').next().unwrap())
+        .collect();
+    names.sort();
+    assert_eq!(names, ["Consts.cs", "app.ts", "lib.rs"], "{stdout}");
+}
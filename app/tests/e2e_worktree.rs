@@ -52,8 +52,9 @@ fn sf_index(root: &Path) {
         .arg("_ensure_indexed_")
         .output()
         .expect("sf search --wait failed");
+    // The probe matches nothing, so a clean run exits 1 like grep.
     assert!(
-        output.status.success(),
+        matches!(output.status.code(), Some(0 | 1)),
         "sf search --wait failed: {}",
         String::from_utf8_lossy(&output.stderr)
     );
@@ -87,8 +88,9 @@ fn sf_search(root: &Path, query: &str) -> String {
         .arg(query)
         .output()
         .expect("sf search failed");
+    // Exit code 1 only means nothing matched.
     assert!(
-        output.status.success(),
+        matches!(output.status.code(), Some(0 | 1)),
         "sf search failed: {}",
         String::from_utf8_lossy(&output.stderr)
     );
@@ -684,12 +686,14 @@ fn test_wt18_paths_are_absolute() {
     sf_index(worktree_root);
 
     let stdout = sf_search(worktree_root, "absolute_path_wt18");
-    // Strip ANSI escape codes for assertion.
-    let stripped = strip_ansi(&stdout);
+    assert!(stdout.contains("absolute_path_wt18"), "{stdout}");
     #[cfg(windows)]
     {
-        // The output format is PATH:LINE_NO on the first line of each result.
-        let first_line = stripped.lines().next().unwrap_or("");
+        // Strip ANSI escape codes for assertion.
+        let stripped = strip_ansi(&stdout);
+        // Each file starts with a `PATH (N matches)` header, then
+        // PATH:LINE_NO:COLUMN on the first line of each snippet.
+        let first_line = stripped.lines().nth(1).unwrap_or("");
         let mut parts = first_line.rsplitn(2, ':');
         let _line_no = parts.next();
         let file_part = parts.next().unwrap_or(first_line);
@@ -0,0 +1,237 @@
+//! Reading files stored inside archives (zip/jar and `.crate` tarballs).
+//!
+//! Archive members are indexed under virtual paths of the form
+//! `<archive path>!/<member path>`, e.g. `libs/foo.jar!/com/Foo.java`.
+
+use std::io::{self, Read};
+use std::path::Path;
+
+use crate::config::ArchiveConfig;
+
+/// Separator between the archive path and the member path.
+pub const ARCHIVE_SEPARATOR: &str = "!/";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ArchiveKind {
+    Zip,
+    TarGz,
+}
+
+impl ArchiveKind {
+    /// Map a configured extension to the archive format it denotes.
+    pub fn from_extension(ext: &str) -> Option<Self> {
+        match ext.to_ascii_lowercase().as_str() {
+            "zip" | "jar" | "war" | "ear" | "aar" | "whl" | "nupkg" => Some(Self::Zip),
+            "crate" | "tgz" => Some(Self::TarGz),
+            _ => None,
+        }
+    }
+}
+
+/// A single regular file read out of an archive.
+pub struct ArchiveEntry {
+    pub name: String,
+    pub data: Vec<u8>,
+}
+
+/// Build the virtual path for `member` inside `archive`.
+pub fn virtual_path(archive: &str, member: &str) -> String {
+    format!("{archive}{ARCHIVE_SEPARATOR}{member}")
+}
+
+/// Split a virtual path into `(archive path, member path)`.
+/// Returns `None` for ordinary paths.
+pub fn split_virtual_path(path: &str) -> Option<(&str, &str)> {
+    let idx = path.find(ARCHIVE_SEPARATOR)?;
+    let (archive, rest) = path.split_at(idx);
+    let member = &rest[ARCHIVE_SEPARATOR.len()..];
+    if archive.is_empty() || member.is_empty() {
+        return None;
+    }
+    Some((archive, member))
+}
+
+/// Read every regular file in `path` that is at most `max_entry_bytes` long.
+pub fn read_entries(
+    path: &Path,
+    kind: ArchiveKind,
+    max_entry_bytes: u64,
+) -> io::Result<Vec<ArchiveEntry>> {
+    let file = std::fs::File::open(path)?;
+    match kind {
+        ArchiveKind::Zip => read_zip_entries(file, max_entry_bytes),
+        ArchiveKind::TarGz => read_tar_gz_entries(file, max_entry_bytes),
+    }
+}
+
+/// Read a single member from an archive, used for on-demand snippet
+/// extraction. The member is read whole, but sizes come from the archive's
+/// headers, so at most `max_entry_bytes` is reserved up front.
+pub fn read_entry(
+    path: &Path,
+    kind: ArchiveKind,
+    member: &str,
+    max_entry_bytes: u64,
+) -> io::Result<Vec<u8>> {
+    let file = std::fs::File::open(path)?;
+    match kind {
+        ArchiveKind::Zip => {
+            let mut archive = zip::ZipArchive::new(file).map_err(zip_error)?;
+            let mut entry = archive.by_name(member).map_err(zip_error)?;
+            let mut data = Vec::with_capacity(entry.size().min(max_entry_bytes) as usize);
+            entry.read_to_end(&mut data)?;
+            Ok(data)
+        }
+        ArchiveKind::TarGz => {
+            let mut archive = tar::Archive::new(flate2::read::GzDecoder::new(file));
+            for entry in archive.entries()? {
+                let mut entry = entry?;
+                if entry.path()?.to_string_lossy() == member {
+                    let mut data = Vec::with_capacity(entry.size().min(max_entry_bytes) as usize);
+                    entry.read_to_end(&mut data)?;
+                    return Ok(data);
+                }
+            }
+            Err(io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("{member} not found in {}", path.display()),
+            ))
+        }
+    }
+}
+
+/// Read the content behind `path`, resolving virtual archive paths.
+pub fn read_path_bytes(path: &Path) -> io::Result<Vec<u8>> {
+    let path_str = crate::text::encode_path(path);
+    if let Some((archive, member)) = split_virtual_path(&path_str) {
+        let archive_path = &crate::text::decode_path(archive);
+        if let Some(kind) = archive_path
+            .extension()
+            .and_then(|ext| ext.to_str())
+            .and_then(ArchiveKind::from_extension)
+            && archive_path.is_file()
+        {
+            // Only bounds the allocation, so the default does for members
+            // indexed under a larger configured limit too.
+            let max_entry_bytes = ArchiveConfig::default().max_entry_bytes;
+            return read_entry(archive_path, kind, member, max_entry_bytes);
+        }
+    }
+    std::fs::read(path)
+}
+
+fn read_zip_entries(file: std::fs::File, max_entry_bytes: u64) -> io::Result<Vec<ArchiveEntry>> {
+    let mut archive = zip::ZipArchive::new(file).map_err(zip_error)?;
+    let mut entries = Vec::new();
+    for idx in 0..archive.len() {
+        let mut entry = archive.by_index(idx).map_err(zip_error)?;
+        if !entry.is_file() || entry.size() > max_entry_bytes {
+            continue;
+        }
+        let Some(name) = entry.enclosed_name() else {
+            continue;
+        };
+        let name = name.to_string_lossy().replace('\\', "/");
+        let mut data = Vec::with_capacity(entry.size() as usize);
+        entry.read_to_end(&mut data)?;
+        entries.push(ArchiveEntry { name, data });
+    }
+    Ok(entries)
+}
+
+fn read_tar_gz_entries(file: std::fs::File, max_entry_bytes: u64) -> io::Result<Vec<ArchiveEntry>> {
+    let mut archive = tar::Archive::new(flate2::read::GzDecoder::new(file));
+    let mut entries = Vec::new();
+    for entry in archive.entries()? {
+        let mut entry = entry?;
+        if !entry.header().entry_type().is_file() || entry.size() > max_entry_bytes {
+            continue;
+        }
+        let name = entry.path()?.to_string_lossy().into_owned();
+        let mut data = Vec::with_capacity(entry.size() as usize);
+        entry.read_to_end(&mut data)?;
+        entries.push(ArchiveEntry { name, data });
+    }
+    Ok(entries)
+}
+
+fn zip_error(err: zip::result::ZipError) -> io::Error {
+    match err {
+        zip::result::ZipError::Io(err) => err,
+        zip::result::ZipError::FileNotFound => io::Error::new(io::ErrorKind::NotFound, err),
+        other => io::Error::new(io::ErrorKind::InvalidData, other),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+
+    fn write_zip(path: &Path, files: &[(&str, &str)]) {
+        let file = std::fs::File::create(path).unwrap();
+        let mut zip = zip::ZipWriter::new(file);
+        for (name, content) in files {
+            zip.start_file(*name, zip::write::SimpleFileOptions::default())
+                .unwrap();
+            zip.write_all(content.as_bytes()).unwrap();
+        }
+        zip.finish().unwrap();
+    }
+
+    #[test]
+    fn test_split_virtual_path() {
+        assert_eq!(
+            split_virtual_path("/r/libs/foo.jar!/com/Foo.java"),
+            Some(("/r/libs/foo.jar", "com/Foo.java"))
+        );
+        assert_eq!(split_virtual_path("/r/src/main.rs"), None);
+        assert_eq!(split_virtual_path("/r/foo.jar!/"), None);
+    }
+
+    #[test]
+    fn test_read_zip_entries_and_member() {
+        let dir = tempfile::tempdir().unwrap();
+        let jar = dir.path().join("foo.jar");
+        write_zip(
+            &jar,
+            &[("com/Foo.java", "class Foo {}"), ("big.txt", "0123456789")],
+        );
+
+        let entries = read_entries(&jar, ArchiveKind::Zip, 5 * 1024).unwrap();
+        assert_eq!(entries.len(), 2);
+        let entries = read_entries(&jar, ArchiveKind::Zip, 9).unwrap();
+        assert_eq!(entries.len(), 0, "entries above the size cap are skipped");
+
+        let virtual_member = virtual_path(&jar.to_string_lossy(), "com/Foo.java");
+        let data = read_path_bytes(Path::new(&virtual_member)).unwrap();
+        assert_eq!(data, b"class Foo {}");
+    }
+
+    #[test]
+    fn test_read_tar_gz_entries() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("demo-0.1.0.crate");
+        let file = std::fs::File::create(&path).unwrap();
+        let encoder = flate2::write::GzEncoder::new(file, flate2::Compression::default());
+        let mut builder = tar::Builder::new(encoder);
+        let content = b"pub fn demo() {}";
+        let mut header = tar::Header::new_gnu();
+        header.set_size(content.len() as u64);
+        header.set_mode(0o644);
+        header.set_cksum();
+        builder
+            .append_data(&mut header, "demo-0.1.0/src/lib.rs", &content[..])
+            .unwrap();
+        builder.into_inner().unwrap().finish().unwrap();
+
+        let entries = read_entries(&path, ArchiveKind::TarGz, 1024).unwrap();
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0].name, "demo-0.1.0/src/lib.rs");
+        let data = read_entry(&path, ArchiveKind::TarGz, "demo-0.1.0/src/lib.rs", 1024).unwrap();
+        assert_eq!(data, content);
+        // The limit only bounds the allocation made before reading.
+        let data = read_entry(&path, ArchiveKind::TarGz, "demo-0.1.0/src/lib.rs", 4).unwrap();
+        assert_eq!(data, content);
+    }
+}
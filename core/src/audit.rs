@@ -0,0 +1,528 @@
+//! Code audits: named queries evaluated against an index (`sf audit`).
+//!
+//! A rules file lists one `[[rule]]` table per check:
+//!
+//! ```toml
+//! [[rule]]
+//! name = "no-dbg"
+//! query = "dbg!("
+//! severity = "error"            # info, warning (default) or error
+//! message = "remove debug output before merging"
+//! ext = ["rs"]                  # only these extensions
+//! paths = ["src/**"]            # only paths matching one of these globs
+//! exclude = ["src/bin/**"]      # never paths matching these
+//! only = "code"                 # code, comments or strings
+//! ```
+//!
+//! Every rule query is looked up in one read of the index, then confirmed
+//! line by line like `sf search` snippets, so each finding names the line
+//! and column of a verbatim (or filtered) match.
+
+use std::collections::HashSet;
+use std::ops::ControlFlow;
+use std::path::Path;
+
+use globset::{Glob, GlobSet, GlobSetBuilder};
+use serde::{Deserialize, Serialize};
+
+use crate::error::{IndexError, IndexResult};
+use crate::identifier::identifier_spellings;
+use crate::model::{SearchHit, SearchOutcome};
+use crate::search::{DEFAULT_READ_CONCURRENCY, MatchFilter, stream_snippets_filtered};
+use crate::storage::{
+    ContentStore, PersistentIndex, path_suffix_after_root, search_database_file_batch,
+};
+use crate::syntax::MatchKind;
+use crate::text::path_is_within_root;
+
+/// How much a finding matters. Ordered from least to most severe.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Severity {
+    Info,
+    #[default]
+    Warning,
+    Error,
+}
+
+impl Severity {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Info => "info",
+            Self::Warning => "warning",
+            Self::Error => "error",
+        }
+    }
+}
+
+/// Which occurrences a rule counts, as `sf search --only-*` selects them.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum RuleScope {
+    Code,
+    Comments,
+    Strings,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct AuditRule {
+    pub name: String,
+    pub query: String,
+    #[serde(default)]
+    pub severity: Severity,
+    /// Shown with the rule's findings.
+    #[serde(default)]
+    pub message: Option<String>,
+    /// File extensions (without the dot) the rule is limited to.
+    #[serde(default)]
+    pub ext: Vec<String>,
+    /// Root-relative globs the rule is limited to.
+    #[serde(default)]
+    pub paths: Vec<String>,
+    /// Root-relative globs the rule never reports.
+    #[serde(default)]
+    pub exclude: Vec<String>,
+    #[serde(default)]
+    pub only: Option<RuleScope>,
+    /// Match the query as an identifier in any naming convention.
+    #[serde(default)]
+    pub identifier: bool,
+}
+
+impl AuditRule {
+    fn match_filter(&self) -> MatchFilter {
+        MatchFilter {
+            kind: self.only.map(|scope| match scope {
+                RuleScope::Code => MatchKind::Code,
+                RuleScope::Comments => MatchKind::Comment,
+                RuleScope::Strings => MatchKind::String,
+            }),
+            identifier: self.identifier,
+        }
+    }
+
+    /// The spellings of the query looked up in the index.
+    fn lookups(&self) -> Vec<String> {
+        if self.identifier {
+            identifier_spellings(&self.query)
+        } else {
+            vec![self.query.clone()]
+        }
+    }
+}
+
+/// The rules of an audit, in the order they are reported.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct AuditRules {
+    #[serde(default, rename = "rule")]
+    pub rules: Vec<AuditRule>,
+}
+
+impl AuditRules {
+    pub fn load(path: &Path) -> IndexResult<Self> {
+        let text = std::fs::read_to_string(path)?;
+        Self::parse(&text).map_err(|err| match err {
+            IndexError::Config(msg) => IndexError::Config(format!("{}: {msg}", path.display())),
+            other => other,
+        })
+    }
+
+    /// Parse a rules file, rejecting duplicate names, queries too short
+    /// for the index and invalid globs.
+    pub fn parse(text: &str) -> IndexResult<Self> {
+        let rules: Self =
+            toml::from_str(text).map_err(|err| IndexError::Config(err.message().to_string()))?;
+        Self::new(rules.rules)
+    }
+
+    /// Check `rules` as [`AuditRules::parse`] does.
+    pub fn new(rules: Vec<AuditRule>) -> IndexResult<Self> {
+        let rules = Self { rules };
+        let mut names = HashSet::new();
+        for rule in &rules.rules {
+            if !names.insert(rule.name.as_str()) {
+                return Err(IndexError::Config(format!(
+                    "rule `{}` is defined twice",
+                    rule.name
+                )));
+            }
+            if rule.query.len() < 3 {
+                return Err(IndexError::Config(format!(
+                    "rule `{}`: query must be at least 3 characters",
+                    rule.name
+                )));
+            }
+            PathFilter::new(rule)?;
+        }
+        Ok(rules)
+    }
+}
+
+/// The `ext`, `paths` and `exclude` limits of one rule.
+struct PathFilter {
+    ext: Vec<String>,
+    paths: Option<GlobSet>,
+    exclude: GlobSet,
+}
+
+impl PathFilter {
+    fn new(rule: &AuditRule) -> IndexResult<Self> {
+        let build = |globs: &[String]| -> IndexResult<GlobSet> {
+            let mut builder = GlobSetBuilder::new();
+            for glob in globs {
+                let glob = Glob::new(glob)
+                    .map_err(|err| IndexError::Config(format!("rule `{}`: {err}", rule.name)))?;
+                builder.add(glob);
+            }
+            builder
+                .build()
+                .map_err(|err| IndexError::Config(format!("rule `{}`: {err}", rule.name)))
+        };
+        Ok(Self {
+            ext: rule.ext.clone(),
+            paths: if rule.paths.is_empty() {
+                None
+            } else {
+                Some(build(&rule.paths)?)
+            },
+            exclude: build(&rule.exclude)?,
+        })
+    }
+
+    /// Whether the rule applies to `relative`, a root-relative path with
+    /// `/` separators.
+    fn allows(&self, relative: &str) -> bool {
+        let ext_ok = self.ext.is_empty()
+            || Path::new(relative)
+                .extension()
+                .is_some_and(|ext| self.ext.iter().any(|want| ext == want.as_str()));
+        ext_ok
+            && self
+                .paths
+                .as_ref()
+                .is_none_or(|paths| paths.is_match(relative))
+            && !self.exclude.is_match(relative)
+    }
+}
+
+/// One matching line.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct AuditFinding {
+    pub rule: String,
+    pub severity: Severity,
+    /// Path relative to the root, with `/` separators.
+    pub path: String,
+    pub line: usize,
+    pub column: usize,
+    pub text: String,
+}
+
+/// A rule and how many lines it matched.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct RuleSummary {
+    pub name: String,
+    pub severity: Severity,
+    pub message: Option<String>,
+    pub matches: usize,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct AuditReport {
+    pub rules: Vec<RuleSummary>,
+    /// Grouped by rule, in rule order, then by path and line.
+    pub findings: Vec<AuditFinding>,
+}
+
+impl AuditReport {
+    /// The severity of the worst finding, if there is any.
+    pub fn worst(&self) -> Option<Severity> {
+        self.findings.iter().map(|finding| finding.severity).max()
+    }
+
+    /// A Markdown rendering: a summary table, then each rule that matched
+    /// with its findings.
+    pub fn to_markdown(&self) -> String {
+        let mut out =
+            String::from("# Audit report\n\n| Rule | Severity | Matches |\n|---|---|---|\n");
+        for rule in &self.rules {
+            out.push_str(&format!(
+                "| {} | {} | {} |\n",
+                rule.name,
+                rule.severity.as_str(),
+                rule.matches
+            ));
+        }
+        for rule in self.rules.iter().filter(|rule| rule.matches > 0) {
+            out.push_str(&format!(
+                "\n## {} ({})\n\n",
+                rule.name,
+                rule.severity.as_str()
+            ));
+            if let Some(message) = &rule.message {
+                out.push_str(&format!("{message}\n\n"));
+            }
+            for finding in self.findings.iter().filter(|f| f.rule == rule.name) {
+                out.push_str(&format!(
+                    "- `{}:{}:{}` `{}`\n",
+                    finding.path,
+                    finding.line,
+                    finding.column,
+                    finding.text.trim().replace('`', "'")
+                ));
+            }
+        }
+        out
+    }
+}
+
+/// Evaluate `rules` against the index at `db_path`, reporting matches in
+/// files under `root`. File contents come from `store` where it holds a
+/// copy, otherwise from disk.
+pub fn audit_database_file(
+    db_path: &Path,
+    root: &Path,
+    rules: &AuditRules,
+    store: Option<&ContentStore>,
+) -> IndexResult<AuditReport> {
+    audit_with(root, rules, store, |queries| {
+        search_database_file_batch(db_path, queries, None)
+    })
+}
+
+/// [`audit_database_file`] over an open index.
+pub fn audit_index(
+    index: &PersistentIndex,
+    root: &Path,
+    rules: &AuditRules,
+) -> IndexResult<AuditReport> {
+    let store = index.content_store();
+    audit_with(root, rules, Some(&store), |queries| {
+        index.search_batch(queries, None)
+    })
+}
+
+fn audit_with(
+    root: &Path,
+    rules: &AuditRules,
+    store: Option<&ContentStore>,
+    search_batch: impl FnOnce(&[&str]) -> IndexResult<Vec<SearchOutcome>>,
+) -> IndexResult<AuditReport> {
+    let lookups: Vec<Vec<String>> = rules.rules.iter().map(AuditRule::lookups).collect();
+    let flat: Vec<&str> = lookups.iter().flatten().map(String::as_str).collect();
+    let mut outcomes = search_batch(&flat)?.into_iter();
+
+    let mut report = AuditReport::default();
+    for (rule, lookups) in rules.rules.iter().zip(&lookups) {
+        let filter = PathFilter::new(rule)?;
+        let mut hits: Vec<(SearchHit, String)> = Vec::new();
+        for outcome in outcomes.by_ref().take(lookups.len()) {
+            hits.extend(outcome.hits.into_iter().filter_map(|hit| {
+                if !path_is_within_root(&hit.path, root) {
+                    return None;
+                }
+                let relative = path_suffix_after_root(&hit.path, root)?
+                    .replace(std::path::MAIN_SEPARATOR, "/");
+                filter.allows(&relative).then_some((hit, relative))
+            }));
+        }
+        hits.sort_by(|a, b| a.1.cmp(&b.1));
+        hits.dedup_by(|a, b| a.0.path == b.0.path);
+        let (hits, relatives): (Vec<SearchHit>, Vec<String>) = hits.into_iter().unzip();
+
+        let mut matches = 0;
+        let mut index = 0;
+        stream_snippets_filtered(
+            &hits,
+            &rule.query,
+            store,
+            DEFAULT_READ_CONCURRENCY,
+            rule.match_filter(),
+            |_, snippets| {
+                let relative = &relatives[index];
+                index += 1;
+                for snippet in snippets.unwrap_or_default() {
+                    let text = snippet
+                        .lines
+                        .iter()
+                        .find(|(line, _)| *line == snippet.line_number)
+                        .map(|(_, text)| text.clone())
+                        .unwrap_or_default();
+                    report.findings.push(AuditFinding {
+                        rule: rule.name.clone(),
+                        severity: rule.severity,
+                        path: relative.clone(),
+                        line: snippet.line_number,
+                        column: snippet.column,
+                        text,
+                    });
+                    matches += 1;
+                }
+                ControlFlow::Continue(())
+            },
+        );
+        report.rules.push(RuleSummary {
+            name: rule.name.clone(),
+            severity: rule.severity,
+            message: rule.message.clone(),
+            matches,
+        });
+    }
+    Ok(report)
+}
+
+/// Tells the findings of successive audits with the same rules apart
+/// from those already seen, for watched queries that report only what a
+/// change introduced.
+///
+/// Findings are compared by rule, path and line text, so edits that only
+/// move a match to another line do not report it again. The first audit
+/// of a rule, including one added later, only records its findings.
+#[derive(Debug, Default)]
+pub struct FindingTracker {
+    seen: HashSet<(String, String, String)>,
+    rules: HashSet<String>,
+}
+
+impl FindingTracker {
+    /// The findings of `report` not seen in the previous one.
+    pub fn update(&mut self, report: &AuditReport) -> Vec<AuditFinding> {
+        let mut seen = HashSet::with_capacity(report.findings.len());
+        let mut new = Vec::new();
+        for finding in &report.findings {
+            let key = (
+                finding.rule.clone(),
+                finding.path.clone(),
+                finding.text.clone(),
+            );
+            if self.rules.contains(&finding.rule) && !self.seen.contains(&key) {
+                new.push(finding.clone());
+            }
+            seen.insert(key);
+        }
+        self.seen = seen;
+        self.rules = report.rules.iter().map(|rule| rule.name.clone()).collect();
+        new
+    }
+
+    /// Treat the next findings of `rule` as a first audit again, as after
+    /// the rule changed.
+    pub fn forget(&mut self, rule: &str) {
+        self.rules.remove(rule);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_rejects_duplicate_and_short_rules() {
+        let duplicate = r#"
+            [[rule]]
+            name = "todo"
+            query = "TODO"
+
+            [[rule]]
+            name = "todo"
+            query = "FIXME"
+        "#;
+        assert!(AuditRules::parse(duplicate).is_err());
+        let short = "[[rule]]\nname = \"x\"\nquery = \"ab\"\n";
+        assert!(AuditRules::parse(short).is_err());
+        let bad_glob = "[[rule]]\nname = \"x\"\nquery = \"abc\"\npaths = [\"[\"]\n";
+        assert!(AuditRules::parse(bad_glob).is_err());
+        assert!(AuditRules::parse("").unwrap().rules.is_empty());
+    }
+
+    #[test]
+    fn test_audit_reports_filtered_findings() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path().join("repo");
+        std::fs::create_dir_all(root.join("src/bin")).unwrap();
+        std::fs::write(
+            root.join("src/lib.rs"),
+            "fn main() {\n    dbg!(1);\n    // TODO: tidy\n}\n",
+        )
+        .unwrap();
+        std::fs::write(root.join("src/bin/tool.rs"), "fn main() { dbg!(2); }\n").unwrap();
+        std::fs::write(root.join("notes.md"), "dbg!( in docs\nTODO list\n").unwrap();
+
+        let db_path = dir.path().join("index.mdb");
+        let index = PersistentIndex::open_or_create(&db_path).unwrap();
+        for file in ["src/lib.rs", "src/bin/tool.rs", "notes.md"] {
+            index.index_path(&root.join(file)).unwrap();
+        }
+        index.flush().unwrap();
+        drop(index);
+
+        let rules = AuditRules::parse(
+            r#"
+            [[rule]]
+            name = "no-dbg"
+            query = "dbg!("
+            severity = "error"
+            ext = ["rs"]
+            exclude = ["src/bin/**"]
+
+            [[rule]]
+            name = "todo"
+            query = "TODO"
+            severity = "info"
+            only = "comments"
+            "#,
+        )
+        .unwrap();
+        let report = audit_database_file(&db_path, &root, &rules, None).unwrap();
+
+        let found: Vec<(&str, &str, usize, usize)> = report
+            .findings
+            .iter()
+            .map(|f| (f.rule.as_str(), f.path.as_str(), f.line, f.column))
+            .collect();
+        // notes.md has no comments, so its TODO does not count.
+        assert_eq!(
+            found,
+            [("no-dbg", "src/lib.rs", 2, 5), ("todo", "src/lib.rs", 3, 8)]
+        );
+        assert_eq!(report.worst(), Some(Severity::Error));
+        assert_eq!(report.rules[0].matches, 1);
+        assert!(
+            report
+                .to_markdown()
+                .contains("- `src/lib.rs:2:5` `dbg!(1);`")
+        );
+    }
+
+    #[test]
+    fn test_tracker_reports_only_new_findings() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path().join("repo");
+        std::fs::create_dir_all(&root).unwrap();
+        let file = root.join("lib.rs");
+        std::fs::write(&file, "fn a() { dbg!(1); }\n").unwrap();
+        let index = PersistentIndex::open_or_create(&dir.path().join("index.mdb")).unwrap();
+        index.index_path(&file).unwrap();
+        index.flush().unwrap();
+
+        let rules = AuditRules::parse("[[rule]]\nname = \"no-dbg\"\nquery = \"dbg!(\"\n").unwrap();
+        let mut tracker = FindingTracker::default();
+        let report = audit_index(&index, &root, &rules).unwrap();
+        assert_eq!(report.findings.len(), 1);
+        assert!(tracker.update(&report).is_empty());
+
+        // The old match moves down a line; only the added one is new.
+        std::fs::write(&file, "fn b() { dbg!(2); }\nfn a() { dbg!(1); }\n").unwrap();
+        index.index_path(&file).unwrap();
+        index.flush().unwrap();
+        let new = tracker.update(&audit_index(&index, &root, &rules).unwrap());
+        let new: Vec<(usize, &str)> = new.iter().map(|f| (f.line, f.text.as_str())).collect();
+        assert_eq!(new, [(1, "fn b() { dbg!(2); }")]);
+        assert!(
+            tracker
+                .update(&audit_index(&index, &root, &rules).unwrap())
+                .is_empty()
+        );
+    }
+}
@@ -0,0 +1,233 @@
+//! Embedding an index in other tools without the `sf` CLI.
+//!
+//! [`IndexBuilder`] opens (or creates) the index of a root with the root's
+//! `.source_fast/config.toml` plus any overrides, and returns an
+//! [`IndexHandle`] for indexing and searching. Scanning and watching live in
+//! `source_fast_fs`, which adds them to the handle through its
+//! `IndexHandleExt` trait.
+//!
+//! ```no_run
+//! use source_fast_core::IndexBuilder;
+//!
+//! let handle = IndexBuilder::new("/path/to/repo")
+//!     .ignore("vendor/**")
+//!     .max_file_size(512 * 1024)
+//!     .open()?;
+//! handle.index_path("/path/to/repo/src/main.rs".as_ref())?;
+//! handle.flush()?;
+//! for hit in handle.search("fn main")? {
+//!     println!("{}", hit.path);
+//! }
+//! # Ok::<(), source_fast_core::IndexError>(())
+//! ```
+
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use regex::Regex;
+
+use crate::config::IndexConfig;
+use crate::error::{IndexError, IndexResult};
+use crate::model::{SearchHit, SearchResult};
+use crate::storage::PersistentIndex;
+use crate::text::{ContentExtractor, ContentPolicy};
+
+/// Options for opening the index of a root; see the [module docs](self).
+pub struct IndexBuilder {
+    root: PathBuf,
+    db_path: Option<PathBuf>,
+    config: Option<IndexConfig>,
+    ignore: Vec<String>,
+    max_file_size: Option<u64>,
+    extractors: Vec<(String, Arc<dyn ContentExtractor>)>,
+}
+
+impl IndexBuilder {
+    pub fn new(root: impl Into<PathBuf>) -> Self {
+        Self {
+            root: root.into(),
+            db_path: None,
+            config: None,
+            ignore: Vec::new(),
+            max_file_size: None,
+            extractors: Vec::new(),
+        }
+    }
+
+    /// Keep the index at `path` instead of `<root>/.source_fast/index.mdb`.
+    pub fn db_path(mut self, path: impl Into<PathBuf>) -> Self {
+        self.db_path = Some(path.into());
+        self
+    }
+
+    /// Use `config` instead of loading `<root>/.source_fast/config.toml`.
+    pub fn config(mut self, config: IndexConfig) -> Self {
+        self.config = Some(config);
+        self
+    }
+
+    /// Never index files matching `glob`, relative to the root. Adds to
+    /// `[binary] force_binary`.
+    pub fn ignore(mut self, glob: impl Into<String>) -> Self {
+        self.ignore.push(glob.into());
+        self
+    }
+
+    /// Skip files larger than `bytes`; overrides `[binary] max_file_bytes`.
+    pub fn max_file_size(mut self, bytes: u64) -> Self {
+        self.max_file_size = Some(bytes);
+        self
+    }
+
+    /// Extract the text of files with extension `ext` with `extractor`.
+    pub fn extractor(mut self, ext: &str, extractor: Arc<dyn ContentExtractor>) -> Self {
+        self.extractors.push((ext.to_string(), extractor));
+        self
+    }
+
+    /// Open the index, creating it if needed. An index built with a
+    /// different tokenizer is cleared, so the next scan rebuilds it.
+    pub fn open(self) -> IndexResult<IndexHandle> {
+        let root = self.root.canonicalize().map_err(IndexError::Io)?;
+        let db_path = self
+            .db_path
+            .unwrap_or_else(|| root.join(".source_fast").join("index.mdb"));
+        let mut config = match self.config {
+            Some(config) => config,
+            None => IndexConfig::load(&root)?,
+        };
+        config.binary.force_binary.extend(self.ignore);
+        if self.max_file_size.is_some() {
+            config.binary.max_file_bytes = self.max_file_size;
+        }
+
+        let mut policy = ContentPolicy::from_config(&root, &config)?;
+        for (ext, extractor) in self.extractors {
+            policy = policy.with_extractor(&ext, extractor);
+        }
+        if let Some(parent) = db_path.parent() {
+            std::fs::create_dir_all(parent).map_err(IndexError::Io)?;
+        }
+        let index = PersistentIndex::open_or_create(&db_path)?;
+        index.set_content_policy(policy);
+        index.set_idle_commit(config.writer.idle_commit());
+        index.set_sync_mode(config.writer.sync)?;
+        index.ensure_settings()?;
+
+        Ok(IndexHandle {
+            root,
+            db_path,
+            config,
+            index: Arc::new(index),
+        })
+    }
+}
+
+/// An open index of one root, as returned by [`IndexBuilder::open`].
+/// Writes go through a background writer; [`IndexHandle::flush`] waits
+/// for them.
+#[derive(Clone)]
+pub struct IndexHandle {
+    root: PathBuf,
+    db_path: PathBuf,
+    config: IndexConfig,
+    index: Arc<PersistentIndex>,
+}
+
+impl IndexHandle {
+    /// The canonical root directory.
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    pub fn db_path(&self) -> &Path {
+        &self.db_path
+    }
+
+    /// The effective configuration, overrides included.
+    pub fn config(&self) -> &IndexConfig {
+        &self.config
+    }
+
+    /// The underlying index, for APIs this handle does not wrap.
+    pub fn index(&self) -> &Arc<PersistentIndex> {
+        &self.index
+    }
+
+    /// Index (or re-index) the file at `path`.
+    pub fn index_path(&self, path: &Path) -> IndexResult<()> {
+        self.index.index_path(path)
+    }
+
+    pub fn remove_path(&self, path: &Path) -> IndexResult<()> {
+        self.index.remove_path(path)
+    }
+
+    /// Wait until every queued write is committed, and return the first
+    /// write that failed since the previous flush.
+    pub fn flush(&self) -> IndexResult<()> {
+        self.index.flush()
+    }
+
+    /// Files whose indexed trigrams contain every trigram of `query`.
+    /// Use [`crate::verify_hits`] to drop files without the exact text.
+    pub fn search(&self, query: &str) -> IndexResult<Vec<SearchHit>> {
+        self.index.search(query)
+    }
+
+    /// [`IndexHandle::search`] restricted to paths matching `file_regex`.
+    pub fn search_filtered(&self, query: &str, file_regex: &Regex) -> IndexResult<Vec<SearchHit>> {
+        self.index.search_filtered(query, Some(file_regex))
+    }
+
+    /// Search every query in one read transaction; see
+    /// [`PersistentIndex::search_batch`].
+    pub fn search_batch<Q: AsRef<str>>(&self, queries: &[Q]) -> IndexResult<Vec<Vec<SearchHit>>> {
+        Ok(self
+            .index
+            .search_batch(queries, None)?
+            .into_iter()
+            .map(|outcome| outcome.hits)
+            .collect())
+    }
+
+    /// Search hits with the first matching snippet of each file.
+    pub fn search_with_snippets(&self, query: &str) -> IndexResult<Vec<SearchResult>> {
+        self.index.search_with_snippets(query)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_builder_applies_ignore_and_size_limit() {
+        let temp = TempDir::new().unwrap();
+        let root = temp.path();
+        std::fs::create_dir_all(root.join("vendor")).unwrap();
+        std::fs::write(root.join("main.rs"), "fn builder_marker() {}").unwrap();
+        std::fs::write(root.join("vendor/lib.rs"), "fn builder_marker() {}").unwrap();
+        std::fs::write(
+            root.join("big.rs"),
+            format!("fn builder_marker() {{}}\n{}", "x".repeat(4096)),
+        )
+        .unwrap();
+
+        let handle = IndexBuilder::new(root)
+            .ignore("vendor/**")
+            .max_file_size(1024)
+            .open()
+            .unwrap();
+        assert!(handle.db_path().starts_with(handle.root()));
+        for name in ["main.rs", "vendor/lib.rs", "big.rs"] {
+            handle.index_path(&handle.root().join(name)).unwrap();
+        }
+        handle.flush().unwrap();
+
+        let hits = handle.search("builder_marker").unwrap();
+        assert_eq!(hits.len(), 1, "{hits:?}");
+        assert!(hits[0].path.ends_with("main.rs"));
+    }
+}
@@ -0,0 +1,579 @@
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+
+use crate::audit::{AuditRule, AuditRules};
+use crate::error::{IndexError, IndexResult};
+
+/// Per-root configuration file, stored next to the index in `.source_fast/`.
+pub const CONFIG_FILE_NAME: &str = "config.toml";
+
+/// Root-level configuration for indexing behaviour.
+///
+/// Loaded from `<root>/.source_fast/config.toml`. Every section is optional;
+/// a missing file yields the defaults.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct IndexConfig {
+    pub binary: BinaryConfig,
+    pub archives: ArchiveConfig,
+    pub content_store: ContentStoreConfig,
+    pub reconcile: ReconcileConfig,
+    pub watcher: WatcherConfig,
+    pub writer: WriterConfig,
+    pub encryption: EncryptionConfig,
+    pub secrets: SecretsConfig,
+    pub mcp: McpConfig,
+    pub history: HistoryConfig,
+    pub workers: WorkersConfig,
+    pub warmup: WarmupConfig,
+}
+
+/// Controls how files are classified as text or binary before indexing.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct BinaryConfig {
+    /// Globs (relative to the root) that are always indexed as text,
+    /// bypassing every heuristic.
+    pub force_text: Vec<String>,
+    /// Globs (relative to the root) that are never indexed.
+    pub force_binary: Vec<String>,
+    /// Fraction of control bytes in the sniff window above which a file is
+    /// treated as binary.
+    pub max_control_ratio: f32,
+    /// Skip minified/generated single-line files such as bundled JS.
+    pub skip_minified: bool,
+    /// Files larger than this many bytes are not indexed. Unlimited when
+    /// unset.
+    pub max_file_bytes: Option<u64>,
+    /// Keep a path-only row for files left out as binary or too large, so
+    /// file path search still finds them. `force_binary` files stay out.
+    pub metadata_only: bool,
+}
+
+impl Default for BinaryConfig {
+    fn default() -> Self {
+        Self {
+            force_text: Vec::new(),
+            force_binary: Vec::new(),
+            max_control_ratio: 0.1,
+            skip_minified: true,
+            max_file_bytes: None,
+            metadata_only: false,
+        }
+    }
+}
+
+/// Opt-in indexing of files stored inside archives. Members are indexed
+/// under virtual paths like `libs/foo.jar!/com/Foo.java`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ArchiveConfig {
+    pub enabled: bool,
+    /// Archive extensions to open. Supported: zip-family (`zip`, `jar`,
+    /// `war`, `ear`, `aar`, `whl`, `nupkg`) and gzipped tarballs (`crate`, `tgz`).
+    pub extensions: Vec<String>,
+    /// Members larger than this are skipped.
+    pub max_entry_bytes: u64,
+}
+
+impl Default for ArchiveConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            extensions: vec!["zip".to_string(), "jar".to_string(), "crate".to_string()],
+            max_entry_bytes: 1024 * 1024,
+        }
+    }
+}
+
+/// Opt-in copy of each indexed file's content, zstd-compressed inside the
+/// index. Snippets and match verification read it instead of the file, so
+/// they reflect what was indexed even after the file changed or was deleted.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ContentStoreConfig {
+    pub enabled: bool,
+    /// Files with more text than this are not stored.
+    pub max_file_bytes: u64,
+}
+
+impl Default for ContentStoreConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            max_file_bytes: 1024 * 1024,
+        }
+    }
+}
+
+/// Periodic `smart_scan` run by the index writer on top of the file
+/// watcher, to repair changes whose events were missed.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ReconcileConfig {
+    /// Minutes between scans; 0 disables them.
+    pub interval_minutes: u64,
+}
+
+impl Default for ReconcileConfig {
+    fn default() -> Self {
+        Self {
+            interval_minutes: 10,
+        }
+    }
+}
+
+impl ReconcileConfig {
+    /// `None` when reconciliation is disabled.
+    pub fn interval(&self) -> Option<Duration> {
+        (self.interval_minutes > 0).then(|| Duration::from_secs(self.interval_minutes * 60))
+    }
+}
+
+/// How the index writer learns about file changes.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum WatchMode {
+    /// Native events (inotify, FSEvents, ReadDirectoryChangesW), switching
+    /// to polling when they are unavailable.
+    #[default]
+    Auto,
+    Native,
+    /// Walk the root every poll interval and compare modification times.
+    /// Slower, but works where native events do not arrive, such as
+    /// NFS/SMB mounts and some container bind mounts.
+    Poll,
+}
+
+impl WatchMode {
+    pub const fn as_str(self) -> &'static str {
+        match self {
+            Self::Auto => "auto",
+            Self::Native => "native",
+            Self::Poll => "poll",
+        }
+    }
+}
+
+impl std::str::FromStr for WatchMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "auto" => Ok(Self::Auto),
+            "native" => Ok(Self::Native),
+            "poll" => Ok(Self::Poll),
+            other => Err(format!(
+                "unknown watch mode {other:?} (expected auto, native or poll)"
+            )),
+        }
+    }
+}
+
+/// Which directories under the root get a watch.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum WatchScope {
+    /// Every directory, recursively.
+    #[default]
+    All,
+    /// Only directories holding files tracked by git, so untracked trees
+    /// (`node_modules`, build output) do not use up inotify watches. Changes
+    /// elsewhere are picked up by reconciliation scans.
+    Tracked,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct WatcherConfig {
+    pub mode: WatchMode,
+    /// How often the polling watcher walks the root.
+    pub poll_interval_ms: u64,
+    pub scope: WatchScope,
+    /// File-name globs of editor temp, swap and backup files. The watcher
+    /// never indexes them, so an atomic save (write a temp file, rename it
+    /// over the original) leaves just the saved file in the index.
+    pub transient_files: Vec<String>,
+}
+
+/// Default `[watcher] transient_files`: temp files of atomic saves, vim
+/// swap files and its `4913` write probe, Emacs and JetBrains backups.
+const DEFAULT_TRANSIENT_FILES: &[&str] = &[
+    "*.tmp",
+    "*.swp",
+    "*.swo",
+    "*.swx",
+    "*~",
+    "4913",
+    ".#*",
+    "#*#",
+    "*.crswap",
+    "*___jb_tmp___",
+    "*___jb_old___",
+];
+
+impl Default for WatcherConfig {
+    fn default() -> Self {
+        Self {
+            mode: WatchMode::Auto,
+            poll_interval_ms: 2000,
+            scope: WatchScope::All,
+            transient_files: DEFAULT_TRANSIENT_FILES
+                .iter()
+                .map(|glob| glob.to_string())
+                .collect(),
+        }
+    }
+}
+
+impl WatcherConfig {
+    pub fn poll_interval(&self) -> Duration {
+        Duration::from_millis(self.poll_interval_ms.max(1))
+    }
+}
+
+/// Encryption of the content store at rest. The key is never stored in
+/// the config; it is read from the environment variable `key_env` as 64
+/// hex digits. Requires `[content_store]`: postings and per-file token
+/// lists are never encrypted.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct EncryptionConfig {
+    pub enabled: bool,
+    pub key_env: String,
+}
+
+impl Default for EncryptionConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            key_env: "SOURCE_FAST_INDEX_KEY".to_string(),
+        }
+    }
+}
+
+/// Regexes for secrets that must never be indexed or shown. Lines
+/// matching any pattern are blanked before indexing and in snippets.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SecretsConfig {
+    pub patterns: Vec<String>,
+    /// Skip a matching file entirely instead of blanking its matching lines.
+    pub skip_files: bool,
+}
+
+/// Paths the MCP server may return, whatever was indexed. Entries are
+/// globs relative to the root and also cover everything below a matching
+/// directory.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct McpConfig {
+    /// When non-empty, only paths under one of these are returned.
+    pub allow_paths: Vec<String>,
+    /// Paths under one of these are never returned; wins over `allow_paths`.
+    pub deny_paths: Vec<String>,
+    /// Queries the server watches while it runs (`[[mcp.watch]]`, with the
+    /// fields of an `sf audit` rule). A change that adds a match is logged
+    /// and sent to clients as a notification.
+    pub watch: Vec<AuditRule>,
+}
+
+/// Durability of index commits, traded against commit latency.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SyncMode {
+    /// Leave flushing to the OS. An OS crash or power loss can lose recent
+    /// commits or, rarely, corrupt the index; suits CI and throwaway
+    /// checkouts.
+    Off,
+    /// Flush data on commit but not the meta page, so an OS crash loses at
+    /// most the last commit.
+    #[default]
+    Normal,
+    /// Flush data and meta page on every commit; nothing committed is lost.
+    /// For network filesystems and indexes that are costly to rebuild.
+    Full,
+}
+
+/// How the index writer groups queued changes into transactions.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct WriterConfig {
+    /// Milliseconds without a new change after which pending changes are
+    /// committed. Bursts closer together than this share one transaction;
+    /// 0 commits as soon as the queue is empty.
+    pub idle_commit_ms: u64,
+    /// How far a commit is flushed to disk before it returns.
+    pub sync: SyncMode,
+}
+
+impl Default for WriterConfig {
+    fn default() -> Self {
+        Self {
+            idle_commit_ms: 100,
+            sync: SyncMode::default(),
+        }
+    }
+}
+
+impl WriterConfig {
+    pub fn idle_commit(&self) -> Duration {
+        Duration::from_millis(self.idle_commit_ms)
+    }
+}
+
+/// Recording of `sf search` runs for `sf history`. Off unless enabled.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct HistoryConfig {
+    pub enabled: bool,
+    /// Searches kept; older ones are dropped.
+    pub max_entries: usize,
+}
+
+impl Default for HistoryConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            max_entries: 200,
+        }
+    }
+}
+
+/// The thread pool that scans, match verification and snippet extraction
+/// run on; see [`configure_workers`](crate::configure_workers).
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct WorkersConfig {
+    /// Worker threads; 0 starts one per CPU core.
+    pub threads: usize,
+    /// Run the workers at background CPU and IO priority, where the
+    /// platform allows it (Linux, macOS).
+    pub low_priority: bool,
+}
+
+/// Preloading of the most common tokens' postings once a server's scan has
+/// finished, so the first searches do not read them from disk.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct WarmupConfig {
+    /// Megabytes of postings to preload; 0 disables the warm-up.
+    pub budget_mb: u64,
+}
+
+impl WarmupConfig {
+    /// The budget in bytes; `None` when the warm-up is disabled.
+    pub fn budget_bytes(&self) -> Option<u64> {
+        (self.budget_mb > 0).then(|| self.budget_mb.saturating_mul(1024 * 1024))
+    }
+}
+
+impl IndexConfig {
+    pub fn path_for_root(root: &Path) -> PathBuf {
+        root.join(".source_fast").join(CONFIG_FILE_NAME)
+    }
+
+    /// Load the config for `root`, falling back to defaults when the file
+    /// does not exist.
+    pub fn load(root: &Path) -> IndexResult<Self> {
+        Self::load_from(&Self::path_for_root(root))
+    }
+
+    pub fn load_from(path: &Path) -> IndexResult<Self> {
+        let text = match std::fs::read_to_string(path) {
+            Ok(text) => text,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(Self::default()),
+            Err(err) => return Err(err.into()),
+        };
+        Self::parse(&text).map_err(|err| match err {
+            IndexError::Config(msg) => IndexError::Config(format!("{}: {msg}", path.display())),
+            other => other,
+        })
+    }
+
+    pub fn parse(text: &str) -> IndexResult<Self> {
+        let config: Self =
+            toml::from_str(text).map_err(|err| IndexError::Config(err.message().to_string()))?;
+        AuditRules::new(config.mcp.watch.clone()).map_err(|err| match err {
+            IndexError::Config(msg) => IndexError::Config(format!("[[mcp.watch]] {msg}")),
+            other => other,
+        })?;
+        if config.encryption.enabled && !config.content_store.enabled {
+            return Err(IndexError::Config(
+                "[encryption] only seals stored file contents; enable [content_store] as well"
+                    .to_string(),
+            ));
+        }
+        Ok(config)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_empty_is_default() {
+        assert_eq!(IndexConfig::parse("").unwrap(), IndexConfig::default());
+    }
+
+    #[test]
+    fn test_parse_binary_section() {
+        let config = IndexConfig::parse(
+            r#"
+            [binary]
+            force_text = ["*.dat"]
+            force_binary = ["vendor/**"]
+            skip_minified = false
+            max_file_bytes = 1048576
+            "#,
+        )
+        .unwrap();
+        assert_eq!(config.binary.force_text, vec!["*.dat"]);
+        assert_eq!(config.binary.force_binary, vec!["vendor/**"]);
+        assert!(!config.binary.skip_minified);
+        assert_eq!(config.binary.max_file_bytes, Some(1048576));
+        assert_eq!(config.binary.max_control_ratio, 0.1);
+    }
+
+    #[test]
+    fn test_parse_archives_section() {
+        let config = IndexConfig::parse("[archives]\nenabled = true\n").unwrap();
+        assert!(config.archives.enabled);
+        assert_eq!(config.archives.extensions, vec!["zip", "jar", "crate"]);
+    }
+
+    #[test]
+    fn test_parse_content_store_section() {
+        let config = IndexConfig::parse("[content_store]\nenabled = true\n").unwrap();
+        assert!(config.content_store.enabled);
+        assert_eq!(config.content_store.max_file_bytes, 1024 * 1024);
+    }
+
+    #[test]
+    fn test_parse_reconcile_section() {
+        assert_eq!(
+            IndexConfig::default().reconcile.interval(),
+            Some(Duration::from_secs(600))
+        );
+        let config = IndexConfig::parse("[reconcile]\ninterval_minutes = 0\n").unwrap();
+        assert_eq!(config.reconcile.interval(), None);
+    }
+
+    #[test]
+    fn test_parse_watcher_section() {
+        assert_eq!(IndexConfig::default().watcher.mode, WatchMode::Auto);
+        let config =
+            IndexConfig::parse("[watcher]\nmode = \"poll\"\npoll_interval_ms = 500\n").unwrap();
+        assert_eq!(config.watcher.mode, WatchMode::Poll);
+        assert_eq!(config.watcher.poll_interval(), Duration::from_millis(500));
+        assert!(IndexConfig::parse("[watcher]\nmode = \"inotify\"\n").is_err());
+        assert_eq!(
+            IndexConfig::parse("[watcher]\nscope = \"tracked\"\n")
+                .unwrap()
+                .watcher
+                .scope,
+            WatchScope::Tracked
+        );
+        assert_eq!("native".parse(), Ok(WatchMode::Native));
+        assert!(
+            IndexConfig::default()
+                .watcher
+                .transient_files
+                .contains(&"*.swp".to_string())
+        );
+        assert_eq!(
+            IndexConfig::parse("[watcher]\ntransient_files = [\"*.bak\"]\n")
+                .unwrap()
+                .watcher
+                .transient_files,
+            ["*.bak"]
+        );
+    }
+
+    #[test]
+    fn test_parse_writer_section() {
+        assert_eq!(
+            IndexConfig::default().writer.idle_commit(),
+            Duration::from_millis(100)
+        );
+        let config = IndexConfig::parse("[writer]\nidle_commit_ms = 0\n").unwrap();
+        assert_eq!(config.writer.idle_commit(), Duration::ZERO);
+        assert_eq!(config.writer.sync, SyncMode::Normal);
+        let config = IndexConfig::parse("[writer]\nsync = \"full\"\n").unwrap();
+        assert_eq!(config.writer.sync, SyncMode::Full);
+        assert!(IndexConfig::parse("[writer]\nsync = \"extra\"\n").is_err());
+    }
+
+    #[test]
+    fn test_parse_warmup_section() {
+        assert_eq!(IndexConfig::default().warmup.budget_bytes(), None);
+        let config = IndexConfig::parse("[warmup]\nbudget_mb = 64\n").unwrap();
+        assert_eq!(config.warmup.budget_bytes(), Some(64 * 1024 * 1024));
+    }
+
+    #[test]
+    fn test_parse_encryption_section() {
+        assert!(!IndexConfig::default().encryption.enabled);
+        let config = IndexConfig::parse(
+            "[content_store]\nenabled = true\n[encryption]\nenabled = true\nkey_env = \"REPO_KEY\"\n",
+        )
+        .unwrap();
+        assert!(config.encryption.enabled);
+        assert_eq!(config.encryption.key_env, "REPO_KEY");
+
+        // Nothing would be encrypted without a content store.
+        let err = IndexConfig::parse("[encryption]\nenabled = true\n").unwrap_err();
+        assert!(matches!(err, IndexError::Config(_)));
+    }
+
+    #[test]
+    fn test_parse_secrets_section() {
+        assert!(IndexConfig::default().secrets.patterns.is_empty());
+        let config = IndexConfig::parse(
+            "[secrets]\npatterns = [\"AWS_SECRET\", \"PRIVATE KEY\"]\nskip_files = true\n",
+        )
+        .unwrap();
+        assert_eq!(config.secrets.patterns, vec!["AWS_SECRET", "PRIVATE KEY"]);
+        assert!(config.secrets.skip_files);
+    }
+
+    #[test]
+    fn test_parse_mcp_section() {
+        let config =
+            IndexConfig::parse("[mcp]\nallow_paths = [\"src\"]\ndeny_paths = [\"src/secret\"]\n")
+                .unwrap();
+        assert_eq!(config.mcp.allow_paths, vec!["src"]);
+        assert_eq!(config.mcp.deny_paths, vec!["src/secret"]);
+        assert!(config.mcp.watch.is_empty());
+
+        let config = IndexConfig::parse(
+            r#"
+            [[mcp.watch]]
+            name = "no-unwrap"
+            query = ".unwrap()"
+            ext = ["rs"]
+            "#,
+        )
+        .unwrap();
+        assert_eq!(config.mcp.watch[0].name, "no-unwrap");
+        assert!(IndexConfig::parse("[[mcp.watch]]\nname = \"x\"\nquery = \"ab\"\n").is_err());
+    }
+
+    #[test]
+    fn test_parse_rejects_unknown_keys() {
+        let err = IndexConfig::parse("[binary]\nforce_txt = []\n").unwrap_err();
+        assert!(matches!(err, IndexError::Config(_)));
+    }
+
+    #[test]
+    fn test_load_missing_file_is_default() {
+        let dir = tempfile::tempdir().unwrap();
+        assert_eq!(
+            IndexConfig::load(dir.path()).unwrap(),
+            IndexConfig::default()
+        );
+    }
+}
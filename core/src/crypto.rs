@@ -0,0 +1,173 @@
+//! Encryption at rest for the content store.
+//!
+//! With `[encryption]` enabled, every stored file is sealed with
+//! XChaCha20-Poly1305 under a 256-bit key read from an environment
+//! variable. Paths, trigram postings and per-file token lists
+//! (`file_tokens`) stay in plaintext, since lookups need them; they reveal
+//! which three-byte sequences each file contains. Requires the
+//! `encryption` cargo feature.
+
+use std::fmt;
+
+use crate::config::EncryptionConfig;
+use crate::error::{IndexError, IndexResult};
+
+/// Meta key holding a value sealed with the key the contents were
+/// written with, so a wrong key is reported instead of failing every read.
+pub const KEY_CHECK_META_KEY: &str = "content_key_check";
+
+/// Plaintext of [`KEY_CHECK_META_KEY`].
+const KEY_CHECK_PLAINTEXT: &[u8] = b"source_fast content key";
+
+const KEY_LEN: usize = 32;
+
+/// Key used to seal and open stored file contents.
+#[derive(Clone)]
+#[cfg_attr(not(feature = "encryption"), allow(dead_code))]
+pub struct ContentKey([u8; KEY_LEN]);
+
+impl fmt::Debug for ContentKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("ContentKey(..)")
+    }
+}
+
+impl ContentKey {
+    /// The key named by `config`, or `None` when encryption is disabled.
+    pub fn from_config(config: &EncryptionConfig) -> IndexResult<Option<Self>> {
+        if !config.enabled {
+            return Ok(None);
+        }
+        if !cfg!(feature = "encryption") {
+            return Err(IndexError::Unsupported(
+                "[encryption] is enabled, but source_fast was built without the `encryption` feature"
+                    .to_string(),
+            ));
+        }
+        let var = &config.key_env;
+        let value = std::env::var(var).map_err(|_| {
+            IndexError::Config(format!(
+                "[encryption] is enabled, but ${var} is not set; export the 64-hex-digit key this index is encrypted with (generate one with `openssl rand -hex 32`)"
+            ))
+        })?;
+        Self::from_hex(value.trim())
+            .map(Some)
+            .map_err(|msg| IndexError::Config(format!("${var}: {msg}")))
+    }
+
+    pub(crate) fn from_hex(hex: &str) -> Result<Self, String> {
+        let bytes = decode_hex(hex).ok_or_else(|| "the key is not hex".to_string())?;
+        let key = bytes
+            .try_into()
+            .map_err(|_| format!("expected {} hex digits, got {}", KEY_LEN * 2, hex.len()))?;
+        Ok(Self(key))
+    }
+
+    /// A sealed value proving which key wrote the contents.
+    pub fn key_check(&self) -> IndexResult<String> {
+        let sealed = self.seal(KEY_CHECK_PLAINTEXT)?;
+        Ok(sealed.iter().map(|byte| format!("{byte:02x}")).collect())
+    }
+
+    /// Whether `check`, from [`ContentKey::key_check`], was made with this key.
+    pub fn matches_check(&self, check: &str) -> bool {
+        decode_hex(check)
+            .and_then(|sealed| self.open(&sealed).ok())
+            .is_some_and(|plain| plain == KEY_CHECK_PLAINTEXT)
+    }
+
+    /// Encrypt `plaintext` under a random nonce, which is prepended.
+    #[cfg(feature = "encryption")]
+    pub fn seal(&self, plaintext: &[u8]) -> IndexResult<Vec<u8>> {
+        use chacha20poly1305::aead::{Aead, AeadCore, KeyInit, OsRng};
+        use chacha20poly1305::{Key, XChaCha20Poly1305};
+
+        let cipher = XChaCha20Poly1305::new(Key::from_slice(&self.0));
+        let nonce = XChaCha20Poly1305::generate_nonce(&mut OsRng);
+        let ciphertext = cipher
+            .encrypt(&nonce, plaintext)
+            .map_err(|_| IndexError::Encode("content encryption failed".to_string()))?;
+        Ok([nonce.as_slice(), &ciphertext].concat())
+    }
+
+    /// Decrypt a value written by [`ContentKey::seal`].
+    #[cfg(feature = "encryption")]
+    pub fn open(&self, sealed: &[u8]) -> IndexResult<Vec<u8>> {
+        use chacha20poly1305::aead::{Aead, KeyInit};
+        use chacha20poly1305::{Key, XChaCha20Poly1305, XNonce};
+
+        const NONCE_LEN: usize = 24;
+        if sealed.len() < NONCE_LEN {
+            return Err(IndexError::Corrupt("truncated sealed content".to_string()));
+        }
+        let (nonce, ciphertext) = sealed.split_at(NONCE_LEN);
+        XChaCha20Poly1305::new(Key::from_slice(&self.0))
+            .decrypt(XNonce::from_slice(nonce), ciphertext)
+            .map_err(|_| {
+                IndexError::Config(
+                    "stored content does not decrypt with the configured key".to_string(),
+                )
+            })
+    }
+
+    #[cfg(not(feature = "encryption"))]
+    pub fn seal(&self, _plaintext: &[u8]) -> IndexResult<Vec<u8>> {
+        Err(unsupported())
+    }
+
+    #[cfg(not(feature = "encryption"))]
+    pub fn open(&self, _sealed: &[u8]) -> IndexResult<Vec<u8>> {
+        Err(unsupported())
+    }
+}
+
+fn decode_hex(hex: &str) -> Option<Vec<u8>> {
+    if !hex.len().is_multiple_of(2) || !hex.is_ascii() {
+        return None;
+    }
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&hex[i..i + 2], 16).ok())
+        .collect()
+}
+
+#[cfg(not(feature = "encryption"))]
+fn unsupported() -> IndexError {
+    IndexError::Unsupported("built without the `encryption` feature".to_string())
+}
+
+#[cfg(all(test, feature = "encryption"))]
+mod tests {
+    use super::*;
+
+    fn key(digit: char) -> ContentKey {
+        ContentKey::from_hex(&digit.to_string().repeat(64)).unwrap()
+    }
+
+    #[test]
+    fn test_seal_round_trip() {
+        let sealed = key('a').seal(b"fn secret() {}").unwrap();
+        assert!(!sealed.windows(6).any(|w| w == b"secret"));
+        assert_eq!(key('a').open(&sealed).unwrap(), b"fn secret() {}");
+        assert!(key('b').open(&sealed).is_err());
+    }
+
+    #[test]
+    fn test_key_check_detects_wrong_key() {
+        let check = key('a').key_check().unwrap();
+        assert!(key('a').matches_check(&check));
+        assert!(!key('b').matches_check(&check));
+    }
+
+    #[test]
+    fn test_missing_key_is_a_config_error() {
+        let config = EncryptionConfig {
+            enabled: true,
+            key_env: "SOURCE_FAST_TEST_UNSET_KEY".to_string(),
+        };
+        let err = ContentKey::from_config(&config).unwrap_err();
+        assert!(matches!(err, IndexError::Config(_)));
+        assert!(err.to_string().contains("$SOURCE_FAST_TEST_UNSET_KEY"));
+        assert!(ContentKey::from_hex("abc").is_err());
+    }
+}
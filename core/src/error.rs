@@ -17,11 +17,53 @@ pub enum IndexError {
     #[error("decode error: {0}")]
     Decode(String),
 
+    #[error("config error: {0}")]
+    Config(String),
+
     #[error("database map size exhausted")]
     MapFull,
 
     #[error("operation cancelled")]
     Cancelled,
+
+    /// Reading the git repository failed. Scans fall back to walking the
+    /// filesystem.
+    #[error("git error: {context}: {source}")]
+    Git {
+        context: String,
+        #[source]
+        source: Box<dyn std::error::Error + Send + Sync>,
+    },
+
+    /// Another process holds, or took over, the writer lease.
+    #[error("writer lease error: {0}")]
+    Lease(String),
+
+    /// The writer thread is gone, so queued writes cannot be applied.
+    #[error("index writer has shut down")]
+    WriterClosed,
+
+    /// The index is damaged and has to be rebuilt.
+    #[error("index is corrupt: {0}")]
+    Corrupt(String),
+
+    /// The index or input was written by an incompatible version, or
+    /// exceeds what this version can represent.
+    #[error("unsupported: {0}")]
+    Unsupported(String),
+}
+
+impl IndexError {
+    /// A git failure while `context`, keeping `source` for callers.
+    pub fn git(
+        context: impl Into<String>,
+        source: impl Into<Box<dyn std::error::Error + Send + Sync>>,
+    ) -> Self {
+        IndexError::Git {
+            context: context.into(),
+            source: source.into(),
+        }
+    }
 }
 
 impl From<EncodeError> for IndexError {
@@ -38,11 +80,37 @@ impl From<DecodeError> for IndexError {
 
 impl From<heed::Error> for IndexError {
     fn from(err: heed::Error) -> Self {
-        if matches!(err, heed::Error::Mdb(heed::MdbError::MapFull)) {
-            return IndexError::MapFull;
+        match err {
+            heed::Error::Mdb(heed::MdbError::MapFull) => IndexError::MapFull,
+            heed::Error::Mdb(
+                heed::MdbError::Corrupted
+                | heed::MdbError::Invalid
+                | heed::MdbError::PageNotFound
+                | heed::MdbError::VersionMismatch,
+            ) => IndexError::Corrupt(err.to_string()),
+            _ => IndexError::Db(err.to_string()),
         }
-        IndexError::Db(err.to_string())
     }
 }
 
 pub type IndexResult<T> = Result<T, IndexError>;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_heed_corruption_is_typed() {
+        let err = IndexError::from(heed::Error::Mdb(heed::MdbError::Corrupted));
+        assert!(matches!(err, IndexError::Corrupt(_)));
+        let err = IndexError::from(heed::Error::Mdb(heed::MdbError::MapFull));
+        assert!(matches!(err, IndexError::MapFull));
+    }
+
+    #[test]
+    fn test_git_error_keeps_source() {
+        let err = IndexError::git("reading HEAD", io::Error::other("object missing"));
+        assert_eq!(err.to_string(), "git error: reading HEAD: object missing");
+        assert!(std::error::Error::source(&err).is_some());
+    }
+}
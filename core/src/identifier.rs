@@ -0,0 +1,144 @@
+//! Identifier search: `readFile` also finds `read_file`, `ReadFile` and
+//! `READ_FILE`.
+//!
+//! Identifiers are split into lowercase words at underscores and case
+//! changes; queries also at any other punctuation. A query matches
+//! wherever its words occur, in order and whole, inside one identifier, so `readFile` finds `readFileSync` and
+//! `sync_read_file` but not `threadFile` or `readFiles`.
+
+/// The lowercase words of `ident`, with the byte offset each starts at.
+/// `HTTPServer` splits into `http` and `server`, `utf8Decode` into `utf8`
+/// and `decode`.
+fn words_with_offsets(ident: &str) -> Vec<(usize, String)> {
+    let chars: Vec<(usize, char)> = ident.char_indices().collect();
+    let mut words = Vec::new();
+    let mut word: Option<(usize, String)> = None;
+    for (i, &(offset, c)) in chars.iter().enumerate() {
+        if !c.is_alphanumeric() {
+            words.extend(word.take());
+            continue;
+        }
+        let prev = i.checked_sub(1).map(|p| chars[p].1);
+        let next = chars.get(i + 1).map(|&(_, c)| c);
+        let boundary = c.is_uppercase()
+            && prev.is_some_and(|p| {
+                p.is_lowercase()
+                    || p.is_numeric()
+                    || (p.is_uppercase() && next.is_some_and(char::is_lowercase))
+            });
+        if boundary {
+            words.extend(word.take());
+        }
+        word.get_or_insert_with(|| (offset, String::new()))
+            .1
+            .extend(c.to_lowercase());
+    }
+    words.extend(word);
+    words
+}
+
+/// The lowercase words of the identifier `ident`.
+pub fn identifier_words(ident: &str) -> Vec<String> {
+    words_with_offsets(ident)
+        .into_iter()
+        .map(|(_, word)| word)
+        .collect()
+}
+
+/// The spellings of `query` to look up in the index: as given, then in
+/// camelCase, PascalCase, snake_case and SCREAMING_SNAKE_CASE. Every
+/// identifier the query matches contains one of them verbatim, unless it
+/// mixes conventions (`HTTPServer` for `httpServer`).
+pub fn identifier_spellings(query: &str) -> Vec<String> {
+    let words = identifier_words(query);
+    if words.is_empty() {
+        return Vec::new();
+    }
+    let capitalized: Vec<String> = words
+        .iter()
+        .map(|word| {
+            let mut chars = word.chars();
+            chars
+                .next()
+                .map(|first| first.to_uppercase().chain(chars).collect())
+                .unwrap_or_default()
+        })
+        .collect();
+    let candidates = [
+        query.to_string(),
+        words[0].clone() + &capitalized[1..].concat(),
+        capitalized.concat(),
+        words.join("_"),
+        words.join("_").to_uppercase(),
+    ];
+    let mut spellings: Vec<String> = Vec::with_capacity(candidates.len());
+    for spelling in candidates {
+        if !spellings.contains(&spelling) {
+            spellings.push(spelling);
+        }
+    }
+    spellings
+}
+
+/// Byte offsets in `text` where an identifier matching `query` starts its
+/// matching words, in ascending order.
+pub fn identifier_matches(text: &str, query: &str) -> Vec<usize> {
+    let wanted = identifier_words(query);
+    let mut offsets = Vec::new();
+    if wanted.is_empty() {
+        return offsets;
+    }
+    let is_ident = |c: char| c.is_alphanumeric() || c == '_';
+    let mut rest = text;
+    let mut base = 0;
+    while let Some(start) = rest.find(is_ident) {
+        let len = rest[start..]
+            .find(|c: char| !is_ident(c))
+            .unwrap_or(rest.len() - start);
+        let words = words_with_offsets(&rest[start..start + len]);
+        for window in words.windows(wanted.len()) {
+            if window.iter().map(|(_, word)| word).eq(&wanted) {
+                offsets.push(base + start + window[0].0);
+            }
+        }
+        base += start + len;
+        rest = &rest[start + len..];
+    }
+    offsets
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_identifier_words() {
+        assert_eq!(identifier_words("readFile"), ["read", "file"]);
+        assert_eq!(identifier_words("READ_FILE"), ["read", "file"]);
+        assert_eq!(identifier_words("HTTPServer"), ["http", "server"]);
+        assert_eq!(identifier_words("utf8Decode"), ["utf8", "decode"]);
+        assert_eq!(identifier_words("read-file"), ["read", "file"]);
+        assert!(identifier_words("__").is_empty());
+    }
+
+    #[test]
+    fn test_identifier_spellings() {
+        assert_eq!(
+            identifier_spellings("read_file"),
+            ["read_file", "readFile", "ReadFile", "READ_FILE"]
+        );
+        assert_eq!(identifier_spellings("file"), ["file", "File", "FILE"]);
+        assert!(identifier_spellings("--").is_empty());
+    }
+
+    #[test]
+    fn test_identifier_matches_whole_words() {
+        let text = "read_file(x); ReadFile(); readFileSync(); threadFile; readFiles; READ_FILE";
+        let found: Vec<&str> = identifier_matches(text, "readFile")
+            .into_iter()
+            .map(|offset| &text[offset..offset + 4])
+            .collect();
+        assert_eq!(found, ["read", "Read", "read", "READ"]);
+        assert_eq!(identifier_matches("sync_read_file", "readFile"), [5]);
+    }
+}
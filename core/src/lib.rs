@@ -1,18 +1,68 @@
+pub mod archive;
+pub mod audit;
+pub mod builder;
+pub mod config;
+pub mod crypto;
 pub mod error;
+pub mod identifier;
+pub mod metrics;
 pub mod model;
+pub mod queries;
+pub mod replace;
 pub mod search;
+pub mod snapshot;
 pub mod storage;
+pub mod syntax;
 pub mod text;
+pub mod tokenizer;
+pub mod workers;
 
+pub use audit::{
+    AuditFinding, AuditReport, AuditRule, AuditRules, FindingTracker, RuleScope, RuleSummary,
+    Severity, audit_database_file, audit_index,
+};
+pub use builder::{IndexBuilder, IndexHandle};
+pub use config::{
+    ArchiveConfig, BinaryConfig, ContentStoreConfig, EncryptionConfig, HistoryConfig, IndexConfig,
+    McpConfig, ReconcileConfig, SecretsConfig, SyncMode, WarmupConfig, WatchMode, WatchScope,
+    WatcherConfig, WorkersConfig, WriterConfig,
+};
+pub use crypto::ContentKey;
 pub use error::{IndexError, IndexResult};
-pub use model::{SearchHit, SearchResult, Snippet};
-pub use search::{search_database_file_with_snippets, search_database_file_with_snippets_filtered};
+pub use identifier::identifier_spellings;
+pub use metrics::{METRICS, MetricsSnapshot};
+pub use model::{
+    ExtensionStat, FileIdentity, FileStat, FileSuggestion, IndexedFile, PlanAction, PlanStep,
+    RenameRecord, SearchDiagnostics, SearchHit, SearchOutcome, SearchPlan, SearchResult,
+    ShortQuery, SimilarFile, Snippet, TrigramStat, WarmUp,
+};
+pub use queries::{
+    HistoryEntry, SavedQueries, SavedQuery, clear_history, read_history, record_history,
+};
+pub use replace::{FileEdit, plan_replace};
+pub use search::{
+    DEFAULT_READ_CONCURRENCY, MatchFilter, count_occurrences, search_database_file_with_snippets,
+    search_database_file_with_snippets_filtered, stream_snippets, stream_snippets_filtered,
+    stream_snippets_unordered, verify_hits, verify_hits_filtered, verify_hits_limited,
+};
+pub use snapshot::IndexSnapshot;
 pub use storage::{
-    BulkFileEntry, PersistentIndex, is_leader_active_readonly, now_millis, read_leader_readonly,
-    read_meta_readonly, rewrite_root_paths, search_database_file, search_database_file_filtered,
-    search_files_in_database,
+    BulkFileEntry, ContentHash, ContentStore, INCLUDE_PATHS_META_KEY, PersistentIndex,
+    SHORT_QUERY_SCAN_LIMIT, compress_content, content_hash, count_database_file,
+    diagnose_database_file, explain_database_file, export_snapshot, extension_stats_in_database,
+    is_leader_active_readonly, largest_files_in_database, list_files_in_database, now_millis,
+    parse_include_paths, read_leader_readonly, read_meta_readonly, replace_index,
+    rewrite_root_paths, search_database_file, search_database_file_batch,
+    search_database_file_filtered, search_database_file_within, search_files_in_database,
+    short_query_in_database, similar_files_in_database, staging_db_path, stored_content,
+    suggest_files_in_database, top_trigrams_in_database,
 };
+pub use syntax::MatchKind;
 pub use text::{
-    extract_snippet, extract_snippets, normalize_path, normalize_path_for_prefix,
-    path_is_within_root,
+    CASE_INSENSITIVE_PATHS, ContentExtractor, ContentPolicy, ExtractorRegistry, MarkdownExtractor,
+    PathScope, PlainTextExtractor, SecretRules, TransientFiles, decode_path, encode_path,
+    extract_snippet, extract_snippets, file_contains, file_identity, file_modified_timestamp,
+    fold_path, normalize_path, normalize_path_for_prefix, path_is_within_root,
 };
+pub use tokenizer::{Token, Tokenizer, TrigramTokenizer};
+pub use workers::configure_workers;
@@ -0,0 +1,234 @@
+//! Process-wide performance counters.
+//!
+//! Instrumented code bumps relaxed atomics on [`METRICS`]. Exporters take a
+//! [`MetricsSnapshot`], which is serializable so a daemon can hand its
+//! numbers to another process, and render it as Prometheus text.
+
+use std::fmt::Write;
+use std::sync::atomic::{AtomicI64, AtomicU64, Ordering};
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+
+/// Upper bounds, in milliseconds, of the search latency histogram buckets.
+pub const SEARCH_LATENCY_BUCKETS_MS: [u64; 10] = [1, 2, 5, 10, 25, 50, 100, 250, 500, 1000];
+
+/// Counters for this process.
+pub static METRICS: Metrics = Metrics::new();
+
+pub struct Metrics {
+    files_indexed: AtomicU64,
+    files_removed: AtomicU64,
+    tokens_indexed: AtomicU64,
+    batches_committed: AtomicU64,
+    commit_micros: AtomicU64,
+    writer_queue_depth: AtomicI64,
+    bitmap_decodes: AtomicU64,
+    searches: AtomicU64,
+    search_micros: AtomicU64,
+    search_buckets: [AtomicU64; SEARCH_LATENCY_BUCKETS_MS.len()],
+}
+
+impl Metrics {
+    const fn new() -> Self {
+        Self {
+            files_indexed: AtomicU64::new(0),
+            files_removed: AtomicU64::new(0),
+            tokens_indexed: AtomicU64::new(0),
+            batches_committed: AtomicU64::new(0),
+            commit_micros: AtomicU64::new(0),
+            writer_queue_depth: AtomicI64::new(0),
+            bitmap_decodes: AtomicU64::new(0),
+            searches: AtomicU64::new(0),
+            search_micros: AtomicU64::new(0),
+            search_buckets: [const { AtomicU64::new(0) }; SEARCH_LATENCY_BUCKETS_MS.len()],
+        }
+    }
+
+    pub(crate) fn record_batch(&self, upserts: u64, removes: u64, tokens: u64, elapsed: Duration) {
+        self.files_indexed.fetch_add(upserts, Ordering::Relaxed);
+        self.files_removed.fetch_add(removes, Ordering::Relaxed);
+        self.tokens_indexed.fetch_add(tokens, Ordering::Relaxed);
+        self.batches_committed.fetch_add(1, Ordering::Relaxed);
+        self.commit_micros
+            .fetch_add(elapsed.as_micros() as u64, Ordering::Relaxed);
+    }
+
+    pub(crate) fn job_queued(&self) {
+        self.writer_queue_depth.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn job_dequeued(&self) {
+        self.writer_queue_depth.fetch_sub(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn bitmap_decoded(&self) {
+        self.bitmap_decodes.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn record_search(&self, elapsed: Duration) {
+        self.searches.fetch_add(1, Ordering::Relaxed);
+        self.search_micros
+            .fetch_add(elapsed.as_micros() as u64, Ordering::Relaxed);
+        let millis = elapsed.as_secs_f64() * 1000.0;
+        if let Some(bucket) = SEARCH_LATENCY_BUCKETS_MS
+            .iter()
+            .position(|&bound| millis <= bound as f64)
+        {
+            self.search_buckets[bucket].fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    pub fn snapshot(&self) -> MetricsSnapshot {
+        let mut cumulative = 0;
+        let search_latency_buckets = self
+            .search_buckets
+            .iter()
+            .map(|bucket| {
+                cumulative += bucket.load(Ordering::Relaxed);
+                cumulative
+            })
+            .collect();
+        MetricsSnapshot {
+            files_indexed: self.files_indexed.load(Ordering::Relaxed),
+            files_removed: self.files_removed.load(Ordering::Relaxed),
+            tokens_indexed: self.tokens_indexed.load(Ordering::Relaxed),
+            batches_committed: self.batches_committed.load(Ordering::Relaxed),
+            commit_seconds: micros_to_secs(self.commit_micros.load(Ordering::Relaxed)),
+            writer_queue_depth: self.writer_queue_depth.load(Ordering::Relaxed).max(0) as u64,
+            bitmap_decodes: self.bitmap_decodes.load(Ordering::Relaxed),
+            searches: self.searches.load(Ordering::Relaxed),
+            search_seconds: micros_to_secs(self.search_micros.load(Ordering::Relaxed)),
+            search_latency_buckets,
+        }
+    }
+}
+
+fn micros_to_secs(micros: u64) -> f64 {
+    micros as f64 / 1_000_000.0
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct MetricsSnapshot {
+    pub files_indexed: u64,
+    pub files_removed: u64,
+    /// Tokens (trigrams) written for indexed files.
+    pub tokens_indexed: u64,
+    pub batches_committed: u64,
+    /// Total time spent applying and committing writer batches.
+    pub commit_seconds: f64,
+    /// Jobs sent to the writer thread that it has not picked up yet.
+    pub writer_queue_depth: u64,
+    /// Posting bitmaps decoded by searches and index updates.
+    pub bitmap_decodes: u64,
+    pub searches: u64,
+    pub search_seconds: f64,
+    /// Cumulative search counts per [`SEARCH_LATENCY_BUCKETS_MS`] bound.
+    pub search_latency_buckets: Vec<u64>,
+}
+
+impl MetricsSnapshot {
+    /// Render in the Prometheus text exposition format.
+    pub fn to_prometheus(&self) -> String {
+        let mut out = String::new();
+        let mut metric = |name: &str, kind: &str, help: &str, value: String| {
+            let _ = writeln!(out, "# HELP source_fast_{name} {help}");
+            let _ = writeln!(out, "# TYPE source_fast_{name} {kind}");
+            let _ = writeln!(out, "source_fast_{name} {value}");
+        };
+        metric(
+            "files_indexed_total",
+            "counter",
+            "Files written to the index.",
+            self.files_indexed.to_string(),
+        );
+        metric(
+            "files_removed_total",
+            "counter",
+            "Files removed from the index.",
+            self.files_removed.to_string(),
+        );
+        metric(
+            "tokens_indexed_total",
+            "counter",
+            "Tokens written for indexed files.",
+            self.tokens_indexed.to_string(),
+        );
+        metric(
+            "writer_batches_total",
+            "counter",
+            "Writer batches committed.",
+            self.batches_committed.to_string(),
+        );
+        metric(
+            "writer_commit_seconds_total",
+            "counter",
+            "Time spent applying and committing writer batches.",
+            self.commit_seconds.to_string(),
+        );
+        metric(
+            "writer_queue_depth",
+            "gauge",
+            "Jobs waiting for the writer thread.",
+            self.writer_queue_depth.to_string(),
+        );
+        metric(
+            "bitmap_decodes_total",
+            "counter",
+            "Posting bitmaps decoded.",
+            self.bitmap_decodes.to_string(),
+        );
+
+        let name = "source_fast_search_duration_seconds";
+        let _ = writeln!(out, "# HELP {name} Content search latency.");
+        let _ = writeln!(out, "# TYPE {name} histogram");
+        for (bound, count) in SEARCH_LATENCY_BUCKETS_MS
+            .iter()
+            .zip(&self.search_latency_buckets)
+        {
+            let le = *bound as f64 / 1000.0;
+            let _ = writeln!(out, "{name}_bucket{{le=\"{le}\"}} {count}");
+        }
+        let _ = writeln!(out, "{name}_bucket{{le=\"+Inf\"}} {}", self.searches);
+        let _ = writeln!(out, "{name}_sum {}", self.search_seconds);
+        let _ = writeln!(out, "{name}_count {}", self.searches);
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_search_histogram_is_cumulative() {
+        let metrics = Metrics::new();
+        metrics.record_search(Duration::from_micros(500));
+        metrics.record_search(Duration::from_millis(30));
+        metrics.record_search(Duration::from_secs(5));
+
+        let snapshot = metrics.snapshot();
+        assert_eq!(snapshot.searches, 3);
+        assert_eq!(snapshot.search_latency_buckets[0], 1);
+        // 30 ms lands in the 50 ms bucket; buckets below it only hold the 0.5 ms search.
+        assert_eq!(snapshot.search_latency_buckets[4], 1);
+        assert_eq!(snapshot.search_latency_buckets[5], 2);
+        assert_eq!(*snapshot.search_latency_buckets.last().unwrap(), 2);
+    }
+
+    #[test]
+    fn test_prometheus_rendering() {
+        let metrics = Metrics::new();
+        metrics.job_queued();
+        metrics.record_batch(2, 1, 40, Duration::from_millis(3));
+        metrics.record_search(Duration::from_millis(4));
+
+        let text = metrics.snapshot().to_prometheus();
+        assert!(text.contains("source_fast_files_indexed_total 2\n"));
+        assert!(text.contains("source_fast_writer_queue_depth 1\n"));
+        assert!(text.contains("# TYPE source_fast_search_duration_seconds histogram"));
+        assert!(text.contains("source_fast_search_duration_seconds_bucket{le=\"0.005\"} 1\n"));
+        assert!(text.contains("source_fast_search_duration_seconds_bucket{le=\"+Inf\"} 1\n"));
+        assert!(text.contains("source_fast_search_duration_seconds_count 1\n"));
+    }
+}
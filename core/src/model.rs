@@ -1,15 +1,202 @@
 use std::path::PathBuf;
 
-#[derive(Debug, Clone)]
+use serde::{Deserialize, Serialize};
+
+/// Which file on disk a path refers to: device and inode on Unix, volume
+/// serial number and file index on Windows. Hard links and different
+/// spellings of one path share it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct FileIdentity {
+    pub device: u64,
+    pub inode: u64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct SearchHit {
     pub file_id: u32,
     pub path: String,
 }
 
+/// Hits of a search that may have stopped at its deadline.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct SearchOutcome {
+    /// Sorted by path, ascending.
+    pub hits: Vec<SearchHit>,
+    /// The deadline passed before every candidate was checked; `hits` holds
+    /// the ones found so far.
+    pub truncated: bool,
+    /// Generation of the committed snapshot the search read (see
+    /// [`PersistentIndex::generation`](crate::PersistentIndex::generation)).
+    /// A search never sees a partially committed write, so a later search
+    /// at the same generation returns the same hits.
+    #[serde(default)]
+    pub generation: u64,
+}
+
+/// What the index knows about a query, to explain an empty result.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct SearchDiagnostics {
+    pub files_indexed: u64,
+    /// The query yields no tokens, so the index cannot answer it.
+    pub query_too_short: bool,
+    /// Query tokens that no indexed file contains.
+    pub missing_trigrams: Vec<String>,
+}
+
+/// How a search was planned and what each stage left, for
+/// `sf search --explain`; see
+/// [`PersistentIndex::explain`](crate::PersistentIndex::explain).
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct SearchPlan {
+    pub files_indexed: u64,
+    pub generation: u64,
+    /// Query tokens in the order search visits them, most selective first.
+    /// Empty when the query is too short for the index.
+    pub steps: Vec<PlanStep>,
+    /// Files posted under every intersected token.
+    pub candidates: u64,
+    /// Candidates whose path passes the file filter.
+    pub path_matches: u64,
+    /// Hits left after stop tokens were checked against content and
+    /// duplicate paths of one file were collapsed, before verification.
+    pub hits: Vec<SearchHit>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PlanStep {
+    pub trigram: String,
+    /// Files posted under the token.
+    pub postings: u64,
+    pub action: PlanAction,
+    /// Candidates left after this step.
+    pub candidates: u64,
+}
+
+/// What search did with one query token.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PlanAction {
+    /// Its postings were intersected with the candidates.
+    Intersected,
+    /// A stop token, checked against the content of the few remaining
+    /// candidates instead of intersected.
+    CheckedInContent,
+    /// No candidates were left to narrow down.
+    Skipped,
+}
+
+/// How much of the index one token accounts for; see
+/// [`top_trigrams_in_database`](crate::top_trigrams_in_database).
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TrigramStat {
+    pub trigram: String,
+    /// Files posted under the token.
+    pub files: u64,
+    /// Bytes its posting lists take in the index.
+    pub bytes: u64,
+}
+
+/// What [`PersistentIndex::warm_up`](crate::PersistentIndex::warm_up)
+/// preloaded.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct WarmUp {
+    /// Tokens whose postings were read.
+    pub tokens: usize,
+    /// Bytes of postings read.
+    pub bytes: u64,
+}
+
+/// How much of the index one file accounts for; see
+/// [`largest_files_in_database`](crate::largest_files_in_database).
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FileStat {
+    pub path: String,
+    /// Distinct tokens the file is posted under.
+    pub trigrams: u64,
+    /// Compressed size of its text in the content store, 0 if not stored.
+    pub stored_bytes: u64,
+}
+
+/// How much of the index the files with one extension account for; see
+/// [`extension_stats_in_database`](crate::extension_stats_in_database).
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ExtensionStat {
+    /// Lowercased, without the dot; empty for files without one.
+    pub extension: String,
+    pub files: u64,
+    /// Compressed size of their stored text and token lists.
+    pub bytes: u64,
+}
+
+/// An indexed file as the files table records it; see
+/// [`list_files_in_database`](crate::list_files_in_database).
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct IndexedFile {
+    pub file_id: u32,
+    pub path: String,
+    /// Modification time when it was indexed, in seconds since the Unix
+    /// epoch.
+    pub last_modified: u64,
+    /// Compressed size of its text in the content store, 0 if not stored.
+    pub stored_bytes: u64,
+}
+
+/// A file whose tokens overlap another's; see
+/// [`similar_files_in_database`](crate::similar_files_in_database).
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SimilarFile {
+    pub path: String,
+    /// Jaccard similarity of the two files' token sets, from 0 to 1.
+    pub similarity: f64,
+    /// Distinct tokens the two files share.
+    pub shared: u64,
+    /// Distinct tokens of this file.
+    pub trigrams: u64,
+}
+
+/// A rename a scan or the file watcher saw, kept so lookups of the old
+/// path can point at the new one.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RenameRecord {
+    pub from: String,
+    pub to: String,
+    /// When it was recorded, in milliseconds since the Unix epoch.
+    pub at_ms: i64,
+}
+
+/// A "did you mean" candidate for a path lookup that matched nothing; see
+/// [`suggest_files_in_database`](crate::suggest_files_in_database).
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FileSuggestion {
+    pub path: String,
+    /// The recorded old path matching the pattern, if `path` is where it
+    /// was renamed to.
+    pub renamed_from: Option<String>,
+    /// Edits between the pattern and the closest part of `path`; 0 for
+    /// renames.
+    pub distance: usize,
+}
+
+/// How a query too short to yield any token can still be answered.
+#[derive(Debug, Clone)]
+pub enum ShortQuery {
+    /// The index is small enough to check every file directly: these are
+    /// all the files passing the path filter, none of them verified yet.
+    Scan(Vec<SearchHit>),
+    /// Too many files to scan; only the files whose path contains the
+    /// query (case-insensitively) are offered.
+    Paths {
+        files_indexed: u64,
+        hits: Vec<SearchHit>,
+    },
+}
+
 #[derive(Debug, Clone)]
 pub struct Snippet {
     pub path: PathBuf,
     pub line_number: usize,
+    /// 1-based byte column of the first match on `line_number`.
+    pub column: usize,
     pub lines: Vec<(usize, String)>,
 }
 
@@ -20,4 +207,8 @@ pub struct SearchResult {
     pub snippet: Option<Snippet>,
     pub snippets: Vec<Snippet>,
     pub snippet_error: Option<String>,
+    /// Generation of the index snapshot the file was found in; see
+    /// [`SearchOutcome::generation`]. Results fetched at an older
+    /// generation may be stale.
+    pub index_generation: u64,
 }
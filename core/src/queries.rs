@@ -0,0 +1,206 @@
+//! Search history and saved queries.
+//!
+//! Both live next to `config.toml` in `<root>/.source_fast/`, outside the
+//! index, so they survive rebuilds and can be written while another process
+//! holds the writer lease. A query is kept as the `sf search` arguments it
+//! was run with, filters included, and replayed by parsing them again.
+//!
+//! History is opt-in (`[history] enabled`): one JSON object per line in
+//! [`HISTORY_FILE_NAME`], oldest first. Saved queries are a TOML table in
+//! [`SAVED_QUERIES_FILE_NAME`], meant to be shared:
+//!
+//! ```toml
+//! [queries.todo]
+//! query = "TODO"
+//! args = ["--only-comments", "-e", "rs"]
+//! ```
+
+use std::collections::BTreeMap;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+
+use crate::error::{IndexError, IndexResult};
+
+pub const HISTORY_FILE_NAME: &str = "history.jsonl";
+pub const SAVED_QUERIES_FILE_NAME: &str = "queries.toml";
+
+/// One `sf search` run.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct HistoryEntry {
+    /// Milliseconds since the Unix epoch.
+    pub at_ms: u64,
+    /// The arguments after `sf search`.
+    pub args: Vec<String>,
+}
+
+pub fn history_path(root: &Path) -> PathBuf {
+    root.join(".source_fast").join(HISTORY_FILE_NAME)
+}
+
+/// The recorded searches of `root`, oldest first. Lines that do not parse
+/// are skipped.
+pub fn read_history(root: &Path) -> IndexResult<Vec<HistoryEntry>> {
+    let text = match std::fs::read_to_string(history_path(root)) {
+        Ok(text) => text,
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(err) => return Err(err.into()),
+    };
+    Ok(text
+        .lines()
+        .filter_map(|line| serde_json::from_str(line).ok())
+        .collect())
+}
+
+/// Append `entry` to the history of `root`, keeping the newest
+/// `max_entries`. Repeating the previous search does not add a line.
+pub fn record_history(root: &Path, entry: HistoryEntry, max_entries: usize) -> IndexResult<()> {
+    let mut entries = read_history(root)?;
+    if entries.last().is_some_and(|last| last.args == entry.args) {
+        return Ok(());
+    }
+    let path = history_path(root);
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    if entries.len() < max_entries {
+        let mut file = std::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&path)?;
+        writeln!(file, "{}", encode_entry(&entry)?)?;
+        return Ok(());
+    }
+    entries.push(entry);
+    let keep = entries.split_off(entries.len().saturating_sub(max_entries));
+    let mut text = String::new();
+    for entry in &keep {
+        text.push_str(&encode_entry(entry)?);
+        text.push('\n');
+    }
+    write_replacing(&path, &text)
+}
+
+pub fn clear_history(root: &Path) -> IndexResult<()> {
+    match std::fs::remove_file(history_path(root)) {
+        Err(err) if err.kind() != std::io::ErrorKind::NotFound => Err(err.into()),
+        _ => Ok(()),
+    }
+}
+
+fn encode_entry(entry: &HistoryEntry) -> IndexResult<String> {
+    serde_json::to_string(entry).map_err(|err| IndexError::Encode(err.to_string()))
+}
+
+/// A named search: the query and the `sf search` filter arguments that go
+/// with it.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct SavedQuery {
+    pub query: String,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub args: Vec<String>,
+}
+
+/// The saved queries of a root, by name.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SavedQueries {
+    pub queries: BTreeMap<String, SavedQuery>,
+}
+
+impl SavedQueries {
+    pub fn path_for_root(root: &Path) -> PathBuf {
+        root.join(".source_fast").join(SAVED_QUERIES_FILE_NAME)
+    }
+
+    /// Load the saved queries of `root`; none when the file does not exist.
+    pub fn load(root: &Path) -> IndexResult<Self> {
+        let path = Self::path_for_root(root);
+        let text = match std::fs::read_to_string(&path) {
+            Ok(text) => text,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(Self::default()),
+            Err(err) => return Err(err.into()),
+        };
+        toml::from_str(&text)
+            .map_err(|err| IndexError::Config(format!("{}: {}", path.display(), err.message())))
+    }
+
+    pub fn save(&self, root: &Path) -> IndexResult<()> {
+        let path = Self::path_for_root(root);
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        let text = toml::to_string(self).map_err(|err| IndexError::Encode(err.to_string()))?;
+        write_replacing(&path, &text)
+    }
+}
+
+/// Replace `path` with `text` through a temporary file, so readers never
+/// see a half-written file.
+fn write_replacing(path: &Path, text: &str) -> IndexResult<()> {
+    let tmp = path.with_extension("tmp");
+    std::fs::write(&tmp, text)?;
+    std::fs::rename(&tmp, path)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(at_ms: u64, query: &str) -> HistoryEntry {
+        HistoryEntry {
+            at_ms,
+            args: vec!["-e".to_string(), "rs".to_string(), query.to_string()],
+        }
+    }
+
+    #[test]
+    fn test_history_keeps_newest_entries() {
+        let dir = tempfile::tempdir().unwrap();
+        assert!(read_history(dir.path()).unwrap().is_empty());
+
+        for (i, query) in ["a", "b", "b", "c", "d"].iter().enumerate() {
+            record_history(dir.path(), entry(i as u64, query), 3).unwrap();
+        }
+        let queries: Vec<String> = read_history(dir.path())
+            .unwrap()
+            .into_iter()
+            .map(|entry| entry.args[2].clone())
+            .collect();
+        assert_eq!(queries, ["b", "c", "d"]);
+
+        clear_history(dir.path()).unwrap();
+        assert!(read_history(dir.path()).unwrap().is_empty());
+        clear_history(dir.path()).unwrap();
+    }
+
+    #[test]
+    fn test_saved_queries_round_trip() {
+        let dir = tempfile::tempdir().unwrap();
+        assert_eq!(
+            SavedQueries::load(dir.path()).unwrap(),
+            SavedQueries::default()
+        );
+
+        let mut saved = SavedQueries::default();
+        saved.queries.insert(
+            "todo".to_string(),
+            SavedQuery {
+                query: "TODO".to_string(),
+                args: vec!["--only-comments".to_string()],
+            },
+        );
+        saved.queries.insert(
+            "unsafe".to_string(),
+            SavedQuery {
+                query: "unsafe {".to_string(),
+                args: Vec::new(),
+            },
+        );
+        saved.save(dir.path()).unwrap();
+        assert_eq!(SavedQueries::load(dir.path()).unwrap(), saved);
+    }
+}
@@ -0,0 +1,197 @@
+//! Literal search-and-replace over the files an index search returns
+//! (`sf replace`).
+//!
+//! The index only narrows down which files to open: each candidate is read
+//! from disk, since that is what gets rewritten, and files that no longer
+//! contain the pattern are left alone. A [`FileEdit`] holds the old and new
+//! text of one file, renders as a unified diff for the preview and writes
+//! the new text back only if the file did not change in between.
+
+use crate::error::{IndexError, IndexResult};
+use crate::model::SearchHit;
+use crate::text::decode_path;
+
+/// The rewrite of one file.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FileEdit {
+    pub path: String,
+    /// Occurrences of the pattern that were replaced.
+    pub replacements: usize,
+    original: String,
+    edited: String,
+}
+
+/// Plan replacing every occurrence of `pattern` with `replacement` in the
+/// files of `hits`. Both must be single lines, so every edited line stays
+/// one line. Files that cannot be read as UTF-8 or do not contain `pattern`
+/// are skipped.
+pub fn plan_replace(
+    hits: &[SearchHit],
+    pattern: &str,
+    replacement: &str,
+) -> IndexResult<Vec<FileEdit>> {
+    if pattern.is_empty() {
+        return Err(IndexError::Unsupported(
+            "cannot replace an empty pattern".to_string(),
+        ));
+    }
+    if pattern.contains('\n') || replacement.contains('\n') {
+        return Err(IndexError::Unsupported(
+            "the pattern and replacement must not span lines".to_string(),
+        ));
+    }
+    let mut edits = Vec::new();
+    for hit in hits {
+        let Ok(original) = std::fs::read_to_string(decode_path(&hit.path)) else {
+            continue;
+        };
+        let replacements = original.matches(pattern).count();
+        if replacements == 0 {
+            continue;
+        }
+        let edited = original.replace(pattern, replacement);
+        edits.push(FileEdit {
+            path: hit.path.clone(),
+            replacements,
+            original,
+            edited,
+        });
+    }
+    Ok(edits)
+}
+
+impl FileEdit {
+    /// A unified diff of the edit with `context` unchanged lines around
+    /// each change, naming the file `label`.
+    pub fn unified_diff(&self, label: &str, context: usize) -> String {
+        let old: Vec<&str> = self.original.split_inclusive('\n').collect();
+        let new: Vec<&str> = self.edited.split_inclusive('\n').collect();
+        let changed: Vec<usize> = (0..old.len()).filter(|&i| old[i] != new[i]).collect();
+
+        let mut out = format!("--- a/{label}\n+++ b/{label}\n");
+        let mut start = 0;
+        while start < changed.len() {
+            // Changes closer than two contexts apart share a hunk.
+            let mut end = start;
+            while end + 1 < changed.len() && changed[end + 1] - changed[end] <= 2 * context + 1 {
+                end += 1;
+            }
+            let first = changed[start].saturating_sub(context);
+            let last = (changed[end] + context).min(old.len() - 1);
+            let count = last - first + 1;
+            out.push_str(&format!(
+                "@@ -{},{count} +{},{count} @@\n",
+                first + 1,
+                first + 1
+            ));
+            for i in first..=last {
+                if old[i] == new[i] {
+                    push_diff_line(&mut out, ' ', old[i]);
+                } else {
+                    push_diff_line(&mut out, '-', old[i]);
+                    push_diff_line(&mut out, '+', new[i]);
+                }
+            }
+            start = end + 1;
+        }
+        out
+    }
+
+    /// Write the edited text to the file, unless it changed since it was
+    /// read. The file is replaced through a temporary file in the same
+    /// directory, keeping its permissions; symlinks are written through.
+    pub fn apply(&self) -> IndexResult<()> {
+        let path = &decode_path(&self.path);
+        if std::fs::read_to_string(path)? != self.original {
+            return Err(std::io::Error::other(format!(
+                "{} changed since it was read; run the replacement again",
+                self.path
+            ))
+            .into());
+        }
+        if std::fs::symlink_metadata(path)?.file_type().is_symlink() {
+            std::fs::write(path, &self.edited)?;
+            return Ok(());
+        }
+        let permissions = std::fs::metadata(path)?.permissions();
+        let file_name = path.file_name().unwrap_or_default().to_string_lossy();
+        let tmp = path.with_file_name(format!(".{file_name}.sf-replace.tmp"));
+        std::fs::write(&tmp, &self.edited)?;
+        std::fs::set_permissions(&tmp, permissions)?;
+        if let Err(err) = std::fs::rename(&tmp, path) {
+            let _ = std::fs::remove_file(&tmp);
+            return Err(err.into());
+        }
+        Ok(())
+    }
+}
+
+/// Append `line` with its diff `marker`, noting a missing final newline
+/// the way `diff` does.
+fn push_diff_line(out: &mut String, marker: char, line: &str) {
+    out.push(marker);
+    out.push_str(line);
+    if !line.ends_with('\n') {
+        out.push_str("\n\\ No newline at end of file\n");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use super::*;
+
+    fn hit(path: &Path) -> SearchHit {
+        SearchHit {
+            file_id: 0,
+            path: path.to_string_lossy().into_owned(),
+        }
+    }
+
+    #[test]
+    fn test_replace_previews_and_applies() {
+        let dir = tempfile::tempdir().unwrap();
+        let lib = dir.path().join("lib.rs");
+        let lines: Vec<String> = (1..=12).map(|i| format!("line {i}\n")).collect();
+        let text = format!("old_name();\n{}old_name(old_name);\n", lines.concat());
+        std::fs::write(&lib, &text).unwrap();
+        let other = dir.path().join("other.rs");
+        std::fs::write(&other, "nothing here").unwrap();
+
+        let edits = plan_replace(&[hit(&lib), hit(&other)], "old_name", "new_name").unwrap();
+        assert_eq!(edits.len(), 1);
+        assert_eq!(edits[0].replacements, 3);
+
+        let diff = edits[0].unified_diff("lib.rs", 1);
+        assert_eq!(
+            diff,
+            "--- a/lib.rs\n+++ b/lib.rs\n\
+             @@ -1,2 +1,2 @@\n-old_name();\n+new_name();\n line 1\n\
+             @@ -13,2 +13,2 @@\n line 12\n-old_name(old_name);\n+new_name(new_name);\n"
+        );
+
+        edits[0].apply().unwrap();
+        assert_eq!(
+            std::fs::read_to_string(&lib).unwrap(),
+            text.replace("old_name", "new_name")
+        );
+        // The file no longer holds what the edit was planned from.
+        assert!(edits[0].apply().is_err());
+    }
+
+    #[test]
+    fn test_replace_rejects_multiline_and_marks_missing_newline() {
+        assert!(plan_replace(&[], "a\nb", "c").is_err());
+        assert!(plan_replace(&[], "", "c").is_err());
+
+        let dir = tempfile::tempdir().unwrap();
+        let file = dir.path().join("a.txt");
+        std::fs::write(&file, "keep\nfoo").unwrap();
+        let edits = plan_replace(&[hit(&file)], "foo", "bar").unwrap();
+        assert_eq!(
+            edits[0].unified_diff("a.txt", 3),
+            "--- a/a.txt\n+++ b/a.txt\n@@ -1,2 +1,2 @@\n keep\n-foo\n\\ No newline at end of file\n+bar\n\\ No newline at end of file\n"
+        );
+    }
+}
@@ -1,38 +1,379 @@
+use std::borrow::Cow;
+use std::collections::BTreeMap;
+use std::ops::ControlFlow;
 use std::path::Path;
-use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::mpsc;
 
 use rayon::prelude::*;
 use regex::Regex;
 
 use crate::IndexResult;
-use crate::model::{SearchHit, SearchResult};
-use crate::storage::search_database_file_filtered;
-use crate::text::extract_snippets;
-
-pub fn attach_snippets(hits: Vec<SearchHit>, query: &str) -> Vec<SearchResult> {
-    hits.into_par_iter()
-        .map(|hit| {
-            let path = PathBuf::from(&hit.path);
-            match extract_snippets(&path, query) {
+use crate::archive;
+use crate::identifier::identifier_matches;
+use crate::model::{SearchHit, SearchResult, Snippet};
+use crate::storage::{ContentStore, search_database_file_within};
+use crate::syntax::{MatchKind, classify};
+use crate::text::{bytes_contain, decode_path, line_columns, snippets_in, snippets_where};
+use crate::workers;
+
+/// Files read at once while extracting snippets. Candidate lists can run
+/// into the thousands, and reading them all at rayon's full width saturates
+/// the disk for results that are never shown.
+pub const DEFAULT_READ_CONCURRENCY: usize = 8;
+
+/// Which occurrences of a query count as matches. The default is every
+/// verbatim occurrence.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct MatchFilter {
+    /// Only occurrences in code, a comment or a string literal.
+    pub kind: Option<MatchKind>,
+    /// Match the query as an identifier in any of its spellings (see
+    /// [`crate::identifier`]) instead of verbatim.
+    pub identifier: bool,
+}
+
+impl MatchFilter {
+    /// Whether every verbatim occurrence matches.
+    pub fn is_verbatim(&self) -> bool {
+        *self == Self::default()
+    }
+}
+
+/// The content of the file at `path`: its copy in `store` when one was
+/// kept, otherwise the file (or archive member) on disk. Lines matching
+/// the store's secret rules are blanked; a file skipped for secrets reads
+/// as empty.
+fn read_hit_content(store: Option<&ContentStore>, path: &str) -> std::io::Result<Vec<u8>> {
+    let bytes = match store.and_then(|store| store.read(path).ok().flatten()) {
+        Some(bytes) => bytes,
+        None => archive::read_path_bytes(&decode_path(path))?,
+    };
+    let Some(rules) = store.and_then(ContentStore::secret_rules) else {
+        return Ok(bytes);
+    };
+    Ok(match rules.redact(&bytes) {
+        Some(Cow::Borrowed(_)) => bytes,
+        Some(Cow::Owned(redacted)) => redacted,
+        None => Vec::new(),
+    })
+}
+
+/// Extract snippets for `hits` with at most `concurrency` files open at
+/// once, handing each result to `visit` in hit order. Once `visit` returns
+/// [`ControlFlow::Break`] no further files are started, so a caller that
+/// stops at its result limit only pays for the reads already in flight.
+pub fn stream_snippets<F>(
+    hits: &[SearchHit],
+    query: &str,
+    store: Option<&ContentStore>,
+    concurrency: usize,
+    visit: F,
+) where
+    F: FnMut(&SearchHit, std::io::Result<Vec<Snippet>>) -> ControlFlow<()>,
+{
+    stream_snippets_filtered(
+        hits,
+        query,
+        store,
+        concurrency,
+        MatchFilter::default(),
+        visit,
+    );
+}
+
+/// Like [`stream_snippets`], but only the lines with an occurrence of the
+/// query that passes `filter` get a snippet.
+pub fn stream_snippets_filtered<F>(
+    hits: &[SearchHit],
+    query: &str,
+    store: Option<&ContentStore>,
+    concurrency: usize,
+    filter: MatchFilter,
+    visit: F,
+) where
+    F: FnMut(&SearchHit, std::io::Result<Vec<Snippet>>) -> ControlFlow<()>,
+{
+    read_snippets(hits, query, store, concurrency, filter, true, visit);
+}
+
+/// Like [`stream_snippets_filtered`], but each result goes to `visit` as
+/// soon as its file has been read, so a slow file does not hold back the
+/// ones after it. Results arrive in no particular order.
+pub fn stream_snippets_unordered<F>(
+    hits: &[SearchHit],
+    query: &str,
+    store: Option<&ContentStore>,
+    concurrency: usize,
+    filter: MatchFilter,
+    visit: F,
+) where
+    F: FnMut(&SearchHit, std::io::Result<Vec<Snippet>>) -> ControlFlow<()>,
+{
+    read_snippets(hits, query, store, concurrency, filter, false, visit);
+}
+
+fn read_snippets<F>(
+    hits: &[SearchHit],
+    query: &str,
+    store: Option<&ContentStore>,
+    concurrency: usize,
+    filter: MatchFilter,
+    in_order: bool,
+    visit: F,
+) where
+    F: FnMut(&SearchHit, std::io::Result<Vec<Snippet>>) -> ControlFlow<()>,
+{
+    let read = |hit: &SearchHit| {
+        read_hit_content(store, &hit.path)
+            .and_then(|bytes| filtered_snippets(Path::new(&hit.path), &bytes, query, filter))
+    };
+    read_hits(hits, concurrency, in_order, read, visit);
+}
+
+/// Run `read` on each of `hits` with at most `concurrency` at once and hand
+/// the results to `visit`, in hit order unless `in_order` is false. Once
+/// `visit` returns [`ControlFlow::Break`] no further hits are started.
+fn read_hits<T, R, F>(hits: &[SearchHit], concurrency: usize, in_order: bool, read: R, mut visit: F)
+where
+    T: Send,
+    R: Fn(&SearchHit) -> T + Sync,
+    F: FnMut(&SearchHit, T) -> ControlFlow<()>,
+{
+    let workers = concurrency.clamp(1, hits.len().max(1));
+    let next = AtomicUsize::new(0);
+    let stop = AtomicBool::new(false);
+
+    std::thread::scope(|scope| {
+        let (tx, rx) = mpsc::sync_channel(workers);
+        for _ in 0..workers {
+            let tx = tx.clone();
+            let (next, stop, read) = (&next, &stop, &read);
+            scope.spawn(move || {
+                while !stop.load(Ordering::Relaxed) {
+                    let i = next.fetch_add(1, Ordering::Relaxed);
+                    let Some(hit) = hits.get(i) else {
+                        break;
+                    };
+                    if tx.send((i, read(hit))).is_err() {
+                        break;
+                    }
+                }
+            });
+        }
+        drop(tx);
+
+        // Readers finish out of order; unless told otherwise, hold results
+        // back until the earlier ones arrive.
+        let mut pending = BTreeMap::new();
+        let mut expected = 0;
+        for (i, result) in rx {
+            if !in_order {
+                if visit(&hits[i], result).is_break() {
+                    stop.store(true, Ordering::Relaxed);
+                    return;
+                }
+                continue;
+            }
+            pending.insert(i, result);
+            while let Some(result) = pending.remove(&expected) {
+                if visit(&hits[expected], result).is_break() {
+                    stop.store(true, Ordering::Relaxed);
+                    return;
+                }
+                expected += 1;
+            }
+        }
+    });
+}
+
+/// Snippets for each of `hits`, found at index generation
+/// `index_generation`.
+pub fn attach_snippets(
+    hits: Vec<SearchHit>,
+    query: &str,
+    store: Option<&ContentStore>,
+    index_generation: u64,
+) -> Vec<SearchResult> {
+    let mut results = Vec::with_capacity(hits.len());
+    stream_snippets(
+        &hits,
+        query,
+        store,
+        DEFAULT_READ_CONCURRENCY,
+        |hit, snippets| {
+            results.push(match snippets {
                 Ok(snippets) => SearchResult {
                     file_id: hit.file_id,
-                    path: hit.path,
+                    path: hit.path.clone(),
                     snippet: snippets.first().cloned(),
                     snippets,
                     snippet_error: None,
+                    index_generation,
                 },
                 Err(err) => SearchResult {
                     file_id: hit.file_id,
-                    path: hit.path,
+                    path: hit.path.clone(),
                     snippet: None,
                     snippets: Vec::new(),
                     snippet_error: Some(err.to_string()),
+                    index_generation,
                 },
-            }
-        })
+            });
+            ControlFlow::Continue(())
+        },
+    );
+    results
+}
+
+/// Line numbers (1-based) of `text`, the content of `path`, holding an
+/// occurrence of `query` that passes `filter`, each with the column of its
+/// first such occurrence.
+fn matching_lines(
+    path: &Path,
+    text: &str,
+    query: &str,
+    filter: MatchFilter,
+) -> BTreeMap<usize, usize> {
+    line_columns(text, match_offsets(path, text, query, filter))
+}
+
+/// Byte offsets of every occurrence of `query` in `text`, the content of
+/// `path`, that passes `filter`.
+fn match_offsets(path: &Path, text: &str, query: &str, filter: MatchFilter) -> Vec<usize> {
+    let offsets: Vec<usize> = if filter.identifier {
+        identifier_matches(text, query)
+    } else if query.is_empty() {
+        Vec::new()
+    } else {
+        text.match_indices(query)
+            .map(|(offset, _)| offset)
+            .collect()
+    };
+    let Some(kind) = filter.kind else {
+        return offsets;
+    };
+    let kinds = classify(path, text);
+    offsets
+        .into_iter()
+        .filter(|&offset| kinds[offset] == kind)
         .collect()
 }
 
+/// How many occurrences of `query` that pass `filter` each file of `hits`
+/// holds, in hit order. Unlike snippets, which show a line once, every
+/// occurrence counts. Files that can no longer be read count zero.
+pub fn count_occurrences(
+    hits: &[SearchHit],
+    query: &str,
+    store: Option<&ContentStore>,
+    filter: MatchFilter,
+) -> Vec<usize> {
+    workers::install(|| {
+        hits.par_iter()
+            .map(|hit| {
+                read_hit_content(store, &hit.path).map_or(0, |bytes| {
+                    let text = String::from_utf8_lossy(&bytes);
+                    match_offsets(Path::new(&hit.path), &text, query, filter).len()
+                })
+            })
+            .collect()
+    })
+}
+
+/// Snippets of `bytes`, the content of `path`, for the lines where `query`
+/// occurs in a way that passes `filter`.
+fn filtered_snippets(
+    path: &Path,
+    bytes: &[u8],
+    query: &str,
+    filter: MatchFilter,
+) -> std::io::Result<Vec<Snippet>> {
+    if filter.is_verbatim() {
+        return snippets_in(path, bytes, query);
+    }
+    let lines = matching_lines(path, &String::from_utf8_lossy(bytes), query, filter);
+    snippets_where(path, bytes, |line_no, _| lines.get(&line_no).copied())
+}
+
+/// Drop hits whose content does not contain `query` verbatim, or can no
+/// longer be read.
+pub fn verify_hits(
+    hits: Vec<SearchHit>,
+    query: &str,
+    store: Option<&ContentStore>,
+) -> Vec<SearchHit> {
+    verify_hits_filtered(hits, query, store, MatchFilter::default())
+}
+
+/// Like [`verify_hits`], but a hit is kept only when the query occurs in
+/// it in a way that passes `filter`.
+pub fn verify_hits_filtered(
+    hits: Vec<SearchHit>,
+    query: &str,
+    store: Option<&ContentStore>,
+    filter: MatchFilter,
+) -> Vec<SearchHit> {
+    verify_hits_limited(hits, query, store, DEFAULT_READ_CONCURRENCY, filter, 0)
+}
+
+/// Like [`verify_hits_filtered`], reading at most `concurrency` files at
+/// once in hit order and none once `limit` hits (0 for all) have verified.
+/// The hits after that point are returned unchecked, so a caller can still
+/// tell how many candidates it did not show.
+pub fn verify_hits_limited(
+    hits: Vec<SearchHit>,
+    query: &str,
+    store: Option<&ContentStore>,
+    concurrency: usize,
+    filter: MatchFilter,
+    limit: usize,
+) -> Vec<SearchHit> {
+    verify_hits_with(hits, concurrency, limit, |hit| {
+        hit_matches(store, hit, query, filter)
+    })
+}
+
+fn verify_hits_with(
+    hits: Vec<SearchHit>,
+    concurrency: usize,
+    limit: usize,
+    matches: impl Fn(&SearchHit) -> bool + Sync,
+) -> Vec<SearchHit> {
+    let mut verified = Vec::new();
+    let mut checked = 0;
+    read_hits(&hits, concurrency, true, matches, |hit, matched| {
+        checked += 1;
+        if matched {
+            verified.push(hit.clone());
+        }
+        if limit > 0 && verified.len() >= limit {
+            ControlFlow::Break(())
+        } else {
+            ControlFlow::Continue(())
+        }
+    });
+    verified.extend(hits.into_iter().skip(checked));
+    verified
+}
+
+/// Whether the content of `hit` holds an occurrence of `query` that passes
+/// `filter`; unreadable files do not.
+fn hit_matches(
+    store: Option<&ContentStore>,
+    hit: &SearchHit,
+    query: &str,
+    filter: MatchFilter,
+) -> bool {
+    read_hit_content(store, &hit.path).is_ok_and(|bytes| {
+        if filter.is_verbatim() {
+            return bytes_contain(&bytes, query);
+        }
+        let text = String::from_utf8_lossy(&bytes);
+        !matching_lines(Path::new(&hit.path), &text, query, filter).is_empty()
+    })
+}
+
 pub fn search_database_file_with_snippets(
     path: &Path,
     query: &str,
@@ -45,6 +386,234 @@ pub fn search_database_file_with_snippets_filtered(
     query: &str,
     file_regex: Option<&Regex>,
 ) -> IndexResult<Vec<SearchResult>> {
-    let hits = search_database_file_filtered(path, query, file_regex)?;
-    Ok(attach_snippets(hits, query))
+    let outcome = search_database_file_within(path, query, file_regex, None)?;
+    let store = ContentStore::open(path)?;
+    Ok(attach_snippets(
+        outcome.hits,
+        query,
+        Some(&store),
+        outcome.generation,
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn write_hits(dir: &Path, count: usize) -> Vec<SearchHit> {
+        (0..count)
+            .map(|i| {
+                let path = dir.join(format!("file_{i:02}.txt"));
+                std::fs::write(&path, format!("line\nneedle {i}\n")).unwrap();
+                SearchHit {
+                    file_id: i as u32,
+                    path: path.to_string_lossy().into_owned(),
+                }
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_stream_snippets_visits_in_hit_order() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut hits = write_hits(dir.path(), 40);
+        hits.insert(
+            5,
+            SearchHit {
+                file_id: 99,
+                path: dir
+                    .path()
+                    .join("missing.txt")
+                    .to_string_lossy()
+                    .into_owned(),
+            },
+        );
+
+        let mut seen = Vec::new();
+        stream_snippets(&hits, "needle", None, 4, |hit, snippets| {
+            assert_eq!(snippets.is_err(), hit.file_id == 99);
+            seen.push(hit.file_id);
+            ControlFlow::Continue(())
+        });
+        let expected: Vec<u32> = hits.iter().map(|hit| hit.file_id).collect();
+        assert_eq!(seen, expected);
+    }
+
+    #[test]
+    fn test_kind_filters_verification_and_snippets() {
+        let dir = tempfile::tempdir().unwrap();
+        let code = dir.path().join("code.rs");
+        let comment = dir.path().join("comment.rs");
+        std::fs::write(&code, "fn needle() {}\n// needle\n").unwrap();
+        std::fs::write(&comment, "// needle\n").unwrap();
+        let hits: Vec<SearchHit> = [&code, &comment]
+            .iter()
+            .enumerate()
+            .map(|(i, path)| SearchHit {
+                file_id: i as u32,
+                path: path.to_string_lossy().into_owned(),
+            })
+            .collect();
+
+        let code_only = MatchFilter {
+            kind: Some(MatchKind::Code),
+            ..MatchFilter::default()
+        };
+        let verified = verify_hits_filtered(hits.clone(), "needle", None, code_only);
+        assert_eq!(verified.len(), 1);
+        assert_eq!(verified[0].file_id, 0);
+
+        let mut lines = Vec::new();
+        stream_snippets_filtered(
+            &hits,
+            "needle",
+            None,
+            2,
+            MatchFilter {
+                kind: Some(MatchKind::Comment),
+                ..MatchFilter::default()
+            },
+            |_, snippets| {
+                lines.extend(snippets.unwrap().iter().map(|s| s.line_number));
+                ControlFlow::Continue(())
+            },
+        );
+        assert_eq!(lines, [2, 1]);
+    }
+
+    #[test]
+    fn test_identifier_filter_matches_other_spellings() {
+        let dir = tempfile::tempdir().unwrap();
+        let snake = dir.path().join("snake.py");
+        let other = dir.path().join("other.rs");
+        std::fs::write(&snake, "import os\n\ndef read_file(path):\n    pass\n").unwrap();
+        std::fs::write(&other, "fn thread_file() {}\n").unwrap();
+        let hits: Vec<SearchHit> = [&snake, &other]
+            .iter()
+            .enumerate()
+            .map(|(i, path)| SearchHit {
+                file_id: i as u32,
+                path: path.to_string_lossy().into_owned(),
+            })
+            .collect();
+        let identifier = MatchFilter {
+            identifier: true,
+            ..MatchFilter::default()
+        };
+
+        let verified = verify_hits_filtered(hits.clone(), "readFile", None, identifier);
+        assert_eq!(verified.len(), 1);
+        assert_eq!(verified[0].file_id, 0);
+
+        let mut lines = Vec::new();
+        stream_snippets_filtered(&verified, "ReadFile", None, 2, identifier, |_, snippets| {
+            lines.extend(snippets.unwrap().iter().map(|s| s.line_number));
+            ControlFlow::Continue(())
+        });
+        assert_eq!(lines, [3]);
+    }
+
+    #[test]
+    fn test_limited_verification_stops_reading_at_limit() {
+        let dir = tempfile::tempdir().unwrap();
+        let hits = write_hits(dir.path(), 40);
+
+        let reads = AtomicUsize::new(0);
+        let verified = verify_hits_with(hits.clone(), 1, 3, |hit| {
+            reads.fetch_add(1, Ordering::Relaxed);
+            hit_matches(None, hit, "needle", MatchFilter::default())
+        });
+        // One result may wait in the channel and one more be read before
+        // the reader sees the stop.
+        assert!(reads.load(Ordering::Relaxed) <= 3 + 2);
+        // Every hit matches: three verified, the rest returned unchecked.
+        let ids: Vec<u32> = verified.iter().map(|hit| hit.file_id).collect();
+        assert_eq!(ids, (0..40).collect::<Vec<u32>>());
+
+        let reads = AtomicUsize::new(0);
+        let verified = verify_hits_with(hits, 2, 0, |hit| {
+            reads.fetch_add(1, Ordering::Relaxed);
+            hit_matches(None, hit, "absent", MatchFilter::default())
+        });
+        assert!(verified.is_empty());
+        assert_eq!(reads.load(Ordering::Relaxed), 40);
+    }
+
+    #[test]
+    fn test_stream_snippets_stops_at_break() {
+        let dir = tempfile::tempdir().unwrap();
+        let hits = write_hits(dir.path(), 40);
+
+        let mut seen = Vec::new();
+        stream_snippets(&hits, "needle", None, 3, |hit, snippets| {
+            assert_eq!(snippets.unwrap()[0].line_number, 2);
+            seen.push(hit.file_id);
+            if seen.len() == 5 {
+                ControlFlow::Break(())
+            } else {
+                ControlFlow::Continue(())
+            }
+        });
+        assert_eq!(seen, vec![0, 1, 2, 3, 4]);
+    }
+
+    #[test]
+    fn test_unordered_stream_visits_each_hit_once() {
+        let dir = tempfile::tempdir().unwrap();
+        let hits = write_hits(dir.path(), 40);
+
+        let mut seen = Vec::new();
+        stream_snippets_unordered(
+            &hits,
+            "needle",
+            None,
+            4,
+            MatchFilter::default(),
+            |hit, snippets| {
+                assert_eq!(snippets.unwrap()[0].line_number, 2);
+                seen.push(hit.file_id);
+                ControlFlow::Continue(())
+            },
+        );
+        seen.sort_unstable();
+        let expected: Vec<u32> = hits.iter().map(|hit| hit.file_id).collect();
+        assert_eq!(seen, expected);
+
+        let mut visited = 0;
+        stream_snippets_unordered(&hits, "needle", None, 4, MatchFilter::default(), |_, _| {
+            visited += 1;
+            if visited == 5 {
+                ControlFlow::Break(())
+            } else {
+                ControlFlow::Continue(())
+            }
+        });
+        assert_eq!(visited, 5);
+    }
+
+    #[test]
+    fn test_count_occurrences_counts_every_match() {
+        let dir = tempfile::tempdir().unwrap();
+        let a = dir.path().join("a.rs");
+        std::fs::write(&a, "needle(needle);\n// needle\nneedle\n").unwrap();
+        let hits = vec![
+            SearchHit {
+                file_id: 0,
+                path: a.to_string_lossy().into_owned(),
+            },
+            SearchHit {
+                file_id: 1,
+                path: dir.path().join("gone.rs").to_string_lossy().into_owned(),
+            },
+        ];
+        assert_eq!(
+            count_occurrences(&hits, "needle", None, MatchFilter::default()),
+            [4, 0]
+        );
+        let code = MatchFilter {
+            kind: Some(MatchKind::Code),
+            ..MatchFilter::default()
+        };
+        assert_eq!(count_occurrences(&hits, "needle", None, code), [3, 0]);
+    }
 }
@@ -0,0 +1,146 @@
+//! Portable index snapshots (`.sfpack` files).
+//!
+//! A snapshot stores every indexed file under a path relative to the
+//! indexed root, together with the trigram posting bitmaps, so an index
+//! built once (e.g. in CI) can be imported into any checkout of the same
+//! repository. The per-file trigram lists are not stored; they are rebuilt
+//! from the bitmaps on import.
+//!
+//! On-disk layout: [`SNAPSHOT_MAGIC`], a little-endian `u32` format
+//! version, then a gzip stream holding the bincode-encoded
+//! [`IndexSnapshot`].
+
+use std::io::{Read, Write};
+use std::path::Path;
+
+use bincode::config;
+use flate2::Compression;
+use flate2::read::GzDecoder;
+use flate2::write::GzEncoder;
+use roaring::RoaringBitmap;
+use serde::{Deserialize, Serialize};
+
+use crate::error::{IndexError, IndexResult};
+use crate::tokenizer::Token;
+
+pub const SNAPSHOT_MAGIC: &[u8; 8] = b"SFPACK\r\n";
+
+/// Bump when the encoded [`IndexSnapshot`] layout changes.
+pub const SNAPSHOT_VERSION: u32 = 1;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct IndexSnapshot {
+    /// [`crate::Tokenizer::id`] the trigrams were produced with.
+    pub tokenizer: String,
+    /// Commit the index was built at, if the root is a git repository.
+    pub git_head: Option<String>,
+    pub files: Vec<SnapshotFile>,
+    pub trigrams: Vec<SnapshotTrigram>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SnapshotFile {
+    pub file_id: u32,
+    /// Path relative to the root, always with `/` separators.
+    pub path: String,
+    pub last_modified: u64,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SnapshotTrigram {
+    pub token: Token,
+    pub file_ids: RoaringBitmap,
+}
+
+impl IndexSnapshot {
+    pub fn write_to<W: Write>(&self, mut writer: W) -> IndexResult<()> {
+        writer.write_all(SNAPSHOT_MAGIC)?;
+        writer.write_all(&SNAPSHOT_VERSION.to_le_bytes())?;
+        let mut encoder = GzEncoder::new(writer, Compression::default());
+        bincode::serde::encode_into_std_write(self, &mut encoder, config::standard())?;
+        encoder.finish()?.flush()?;
+        Ok(())
+    }
+
+    pub fn read_from<R: Read>(mut reader: R) -> IndexResult<Self> {
+        let mut magic = [0u8; 8];
+        reader.read_exact(&mut magic)?;
+        if &magic != SNAPSHOT_MAGIC {
+            return Err(IndexError::Decode("not a source_fast snapshot".to_string()));
+        }
+        let mut version = [0u8; 4];
+        reader.read_exact(&mut version)?;
+        let version = u32::from_le_bytes(version);
+        if version != SNAPSHOT_VERSION {
+            return Err(IndexError::Unsupported(format!(
+                "snapshot version {version} (expected {SNAPSHOT_VERSION})"
+            )));
+        }
+        let mut decoder = GzDecoder::new(reader);
+        let snapshot = bincode::serde::decode_from_std_read(&mut decoder, config::standard())?;
+        Ok(snapshot)
+    }
+
+    pub fn write_file(&self, path: &Path) -> IndexResult<()> {
+        // Write to a sibling temp file so a failed export never leaves a
+        // truncated snapshot behind.
+        let tmp = path.with_extension("sfpack.tmp");
+        let file = std::fs::File::create(&tmp)?;
+        if let Err(err) = self.write_to(std::io::BufWriter::new(file)) {
+            let _ = std::fs::remove_file(&tmp);
+            return Err(err);
+        }
+        std::fs::rename(&tmp, path)?;
+        Ok(())
+    }
+
+    pub fn read_file(path: &Path) -> IndexResult<Self> {
+        let file = std::fs::File::open(path)?;
+        Self::read_from(std::io::BufReader::new(file))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sample() -> IndexSnapshot {
+        IndexSnapshot {
+            tokenizer: "trigram-v1".to_string(),
+            git_head: Some("abc123".to_string()),
+            files: vec![SnapshotFile {
+                file_id: 0,
+                path: "src/main.rs".to_string(),
+                last_modified: 42,
+            }],
+            trigrams: vec![SnapshotTrigram {
+                token: *b"fn ",
+                file_ids: RoaringBitmap::from_iter([0u32]),
+            }],
+        }
+    }
+
+    #[test]
+    fn test_snapshot_round_trip() {
+        let snapshot = sample();
+        let mut buf = Vec::new();
+        snapshot.write_to(&mut buf).unwrap();
+        assert!(buf.starts_with(SNAPSHOT_MAGIC));
+
+        let decoded = IndexSnapshot::read_from(buf.as_slice()).unwrap();
+        assert_eq!(decoded, snapshot);
+    }
+
+    #[test]
+    fn test_snapshot_rejects_bad_header() {
+        let err = IndexSnapshot::read_from(&b"not a snapshot at all"[..]).unwrap_err();
+        assert!(matches!(err, IndexError::Decode(_)));
+
+        let mut buf = Vec::new();
+        sample().write_to(&mut buf).unwrap();
+        buf[SNAPSHOT_MAGIC.len()] = 99;
+        let err = IndexSnapshot::read_from(buf.as_slice()).unwrap_err();
+        assert!(matches!(err, IndexError::Unsupported(_)));
+        assert!(err.to_string().contains("version 99"));
+    }
+}
@@ -1,42 +1,176 @@
-use std::collections::HashMap;
+use std::borrow::Cow;
+use std::collections::hash_map::Entry;
+use std::collections::{HashMap, HashSet, VecDeque};
 use std::path::{Path, PathBuf};
-use std::sync::Arc;
-use std::sync::atomic::{AtomicBool, Ordering};
-use std::sync::mpsc;
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, RwLock, mpsc};
 use std::thread;
 use std::thread::JoinHandle;
-use std::time::Duration;
+use std::time::{Duration, Instant};
 
 use bincode::config;
 use heed::byteorder::NativeEndian;
-use heed::types::{Bytes, Str, U32};
-use heed::{Database, Env, EnvOpenOptions, RoTxn, RwTxn};
+use heed::types::{Bytes, DecodeIgnore, Str, U32};
+use heed::{Database, Env, EnvFlags, EnvOpenOptions, FlagSetMode, RoTxn, RwTxn};
+use rayon::prelude::*;
 use regex::Regex;
 use roaring::RoaringBitmap;
 use serde::{Deserialize, Serialize, de::DeserializeOwned};
-use tracing::{debug, error, info};
+use tracing::{debug, debug_span, error, info};
 
+use crate::archive::{self, ARCHIVE_SEPARATOR, ArchiveKind};
+use crate::config::{SyncMode, WriterConfig};
+use crate::crypto::{ContentKey, KEY_CHECK_META_KEY};
 use crate::error::{IndexError, IndexResult};
-use crate::model::{SearchHit, SearchResult};
+use crate::metrics::METRICS;
+use crate::model::{
+    ExtensionStat, FileIdentity, FileStat, FileSuggestion, IndexedFile, PlanAction, PlanStep,
+    RenameRecord, SearchDiagnostics, SearchHit, SearchOutcome, SearchPlan, SearchResult,
+    ShortQuery, SimilarFile, TrigramStat, WarmUp,
+};
+use crate::snapshot::{IndexSnapshot, SnapshotFile, SnapshotTrigram};
 use crate::text::{
-    collect_trigrams, file_modified_timestamp, normalize_path, normalize_path_for_prefix,
-    path_is_within_root, read_text_file,
+    CASE_INSENSITIVE_PATHS, ContentPolicy, SecretRules, decode_path, file_identity,
+    file_modified_timestamp, fold_path, normalize_path, normalize_path_for_prefix,
+    path_is_within_root, read_text_file_with_policy,
 };
+use crate::tokenizer::{TOKENIZER_META_KEY, Token, Tokenizer, default_tokenizer, tokenizer_for_id};
+use crate::workers;
 
 const DEFAULT_MAP_SIZE: usize = 1024 * 1024 * 1024;
 const MAX_MAP_SIZE: usize = 1024 * 1024 * 1024 * 1024;
-const MAX_DBS: u32 = 6;
+const MAX_DBS: u32 = 9;
 const WRITER_LEADER_KEY: &str = "writer";
 
+/// Posting lists are split into shards of `1 << POSTING_SHARD_BITS` file
+/// ids, keyed by the token followed by the big-endian shard number, so a
+/// file update rewrites only the shard holding its id. Keys that are just
+/// the token hold an unsharded list written by older versions; readers
+/// merge it in and writers drain it.
+const POSTING_SHARD_BITS: u32 = 16;
+
+/// Each token's posting count lives next to its shards under the token
+/// followed by this byte; the key length (4) tells it apart from shard keys.
+const POSTING_COUNT_MARKER: u8 = b'#';
+
+/// Search always intersects this many of the most selective tokens.
+const PLANNED_TOKENS: usize = 3;
+
+/// Encoded size of the postings a search needs up front above which they
+/// are decoded in parallel. Below it rayon's overhead outweighs the gain.
+const PARALLEL_DECODE_MIN_BYTES: usize = 64 * 1024;
+
+/// Tokens posted by at least this many files are stop tokens: beyond the
+/// planned ones they are not intersected but checked against the content
+/// of the remaining candidates.
+const STOP_TOKEN_MIN_POSTINGS: u64 = 4096;
+
+/// Stop tokens are only skipped while at most this many candidates remain,
+/// since each candidate costs a file read.
+const MAX_VERIFIED_CANDIDATES: u64 = 256;
+
+/// Granularity at which [`PersistentIndex::warm_up`] touches postings;
+/// reading one byte per page faults the whole page in.
+const WARM_UP_STRIDE: usize = 4096;
+
+/// Queries too short for any token are answered by reading every file
+/// while the index holds at most this many; larger indexes fall back to
+/// matching file paths. See [`PersistentIndex::short_query`].
+pub const SHORT_QUERY_SCAN_LIMIT: u64 = 2_000;
+
+/// Paths whose file ids the writer keeps in memory. When full the cache is
+/// dropped wholesale; misses fall back to a `files_by_path` lookup.
+const FILE_ID_CACHE_CAPACITY: usize = 64 * 1024;
+
+/// Meta key of the counter bumped by every write that changes indexed
+/// content; see [`PersistentIndex::generation`].
+pub const GENERATION_META_KEY: &str = "index_generation";
+
+/// Meta key set on an index that [`replace_index`] moved aside; see
+/// [`PersistentIndex::is_replaced`].
+pub const REPLACED_META_KEY: &str = "replaced_by_rebuild";
+
+/// Meta key of the subtrees of the root an index is restricted to, one
+/// root-relative path per line; see [`PersistentIndex::include_paths`].
+pub const INCLUDE_PATHS_META_KEY: &str = "include_paths";
+
+/// Meta key of the JSON list of [`RenameRecord`]s scans and the watcher
+/// saw, oldest first; see [`suggest_files_in_database`].
+pub const RENAME_HISTORY_META_KEY: &str = "rename_history";
+
+/// Meta key of the next file id the writer hands out. Indexes written
+/// before it existed fall back to scanning `files` once.
+const NEXT_FILE_ID_META_KEY: &str = "next_file_id";
+
+/// Meta key set once `file_trigrams` of an older index has been moved
+/// into `file_tokens`, so later opens skip the table without a write
+/// transaction.
+const FILE_TOKENS_MIGRATED_META_KEY: &str = "file_tokens_migrated";
+
+/// Renames kept in [`RENAME_HISTORY_META_KEY`]; older ones are dropped.
+const RENAME_HISTORY_LIMIT: usize = 1_000;
+
+/// Patterns shorter than this get no fuzzy path suggestions: nearly every
+/// path is within an edit or two of them.
+const MIN_SUGGEST_PATTERN_LEN: usize = 3;
+
 /// Maximum batch size in bytes before the writer thread commits.
 /// Larger batches = fewer commits = faster bulk indexing.
 /// 64 MB is a good balance: ~4k files per batch on typical source code.
 const BATCH_MEMORY_LIMIT: usize = 64 * 1024 * 1024;
 
+/// Longest a batch stays open waiting for the queue to go idle, so a steady
+/// trickle of changes is still committed at least this often.
+const MAX_BATCH_DELAY: Duration = Duration::from_secs(1);
+
+/// Batch size limit while searches are running or were within
+/// [`SEARCH_QUIET_PERIOD`], so commits stay short and new files become
+/// searchable sooner while someone is searching.
+const CONTENDED_BATCH_MEMORY_LIMIT: usize = 4 * 1024 * 1024;
+
+/// How long after a search the writer keeps committing small batches.
+const SEARCH_QUIET_PERIOD: Duration = Duration::from_secs(2);
+
+/// Searches of this process, watched by the writer; see
+/// [`CONTENDED_BATCH_MEMORY_LIMIT`].
+static SEARCH_ACTIVITY: SearchActivity = SearchActivity::new();
+
+/// zstd level for stored contents and blobs; favours indexing speed over
+/// ratio.
+const ZSTD_LEVEL: i32 = 3;
+
+/// Posting bitmaps and per-file trigram lists start with this byte followed
+/// by a codec byte. No bincode value starts with it (varint length prefixes
+/// stop at 254), so untagged blobs from older indexes still decode as
+/// bincode and are rewritten in the new format when next updated.
+const BLOB_TAG: u8 = 0xFF;
+/// Codec byte: payload stored as is.
+const BLOB_RAW: u8 = 1;
+/// Codec byte: payload compressed with zstd.
+const BLOB_ZSTD: u8 = 2;
+
+/// Payloads shorter than this are not worth a zstd frame.
+const BLOB_COMPRESS_MIN_BYTES: usize = 256;
+
+/// Codec byte of a `file_tokens` value that holds no tokens itself but the
+/// content hash of a shared `token_sets` entry.
+const BLOB_SHARED: u8 = 3;
+
+/// Codec byte of a `contents` value sealed with the `[encryption]` key;
+/// the sealed payload is the zstd-compressed text. Unsealed contents are a
+/// bare zstd frame, which never starts with [`BLOB_TAG`].
+const BLOB_SEALED: u8 = 4;
+
+/// A `token_sets` value starts with this many bytes of little-endian
+/// reference count, followed by the encoded tokens.
+const TOKEN_SET_REFS_LEN: usize = 4;
+
 type FilesDb = Database<U32<NativeEndian>, Bytes>;
 type FilesByPathDb = Database<Str, U32<NativeEndian>>;
 type TrigramsDb = Database<Bytes, Bytes>;
-type FileTrigramsDb = Database<U32<NativeEndian>, Bytes>;
+type FileTokensDb = Database<U32<NativeEndian>, Bytes>;
+type ContentsDb = Database<U32<NativeEndian>, Bytes>;
+type TokenSetsDb = Database<Bytes, Bytes>;
 type MetaDb = Database<Str, Str>;
 type LeaderDb = Database<Str, Bytes>;
 
@@ -44,6 +178,26 @@ type LeaderDb = Database<Str, Bytes>;
 struct FileRecord {
     path: String,
     last_modified: u64,
+    /// Lets search collapse hits that are the same file on disk.
+    identity: Option<FileIdentity>,
+}
+
+/// `FileRecord` as written before file identities were tracked.
+#[derive(Deserialize)]
+struct LegacyFileRecord {
+    path: String,
+    last_modified: u64,
+}
+
+fn decode_file_record(bytes: &[u8]) -> IndexResult<FileRecord> {
+    decode_bytes(bytes).or_else(|_| {
+        let legacy: LegacyFileRecord = decode_bytes(bytes)?;
+        Ok(FileRecord {
+            path: legacy.path,
+            last_modified: legacy.last_modified,
+            identity: None,
+        })
+    })
 }
 
 #[derive(Serialize, Deserialize)]
@@ -52,9 +206,47 @@ struct LeaderRecord {
     expires_at_ms: i64,
 }
 
+/// The writer lease holder this handle acquired, if any, shared with its
+/// writer thread.
+type LeaseHolder = Arc<Mutex<Option<String>>>;
+
+/// Refuse a write in `txn` while a holder other than `own` has a live
+/// writer lease. Checked in the transaction that commits, so a process
+/// that lost the lease cannot write alongside the one that took it.
+fn check_lease(dbs: &DbHandles, txn: &RoTxn, own: &LeaseHolder) -> IndexResult<()> {
+    let Some(record) = dbs
+        .leader
+        .get(txn, WRITER_LEADER_KEY)?
+        .map(decode_bytes::<LeaderRecord>)
+        .transpose()?
+    else {
+        return Ok(());
+    };
+    if record.expires_at_ms <= now_millis() {
+        return Ok(());
+    }
+    let own = own.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+    if own.as_deref() == Some(record.holder.as_str()) {
+        return Ok(());
+    }
+    Err(IndexError::Lease(format!(
+        "`{}` holds the writer lease; acquire it before writing",
+        record.holder
+    )))
+}
+
+/// Writer-side view of file ids. Ids are resolved through `files_by_path`
+/// in the batch's write transaction, so opening an index never walks the
+/// path table; only recently used paths are cached. New ids come from
+/// [`NEXT_FILE_ID_META_KEY`] in the same transaction, so a writer taking
+/// over the lease continues where the previous one stopped.
+#[derive(Default)]
 struct FileIdState {
-    file_ids: HashMap<String, u32>,
-    next_file_id: u32,
+    cache: HashMap<String, u32>,
+    /// Index generation after this writer's last commit; any other value
+    /// means another writer has changed files since and the cache may name
+    /// rows it removed or moved.
+    generation: Option<u64>,
 }
 
 #[derive(Clone)]
@@ -62,7 +254,15 @@ pub struct DbHandles {
     files: FilesDb,
     files_by_path: FilesByPathDb,
     trigrams: TrigramsDb,
-    file_trigrams: FileTrigramsDb,
+    /// Each file's sorted tokens, so an update or removal knows which
+    /// postings to touch.
+    file_tokens: FileTokensDb,
+    /// zstd-compressed text of files indexed while the content store was
+    /// enabled.
+    contents: ContentsDb,
+    /// Token lists keyed by [`ContentHash`], shared by every file with that
+    /// content and freed when the last one goes.
+    token_sets: TokenSetsDb,
     /// Publicly accessible for direct polling by `sf index watch`.
     pub meta: MetaDb,
     leader: LeaderDb,
@@ -72,21 +272,50 @@ struct LmdbStorage {
     env: Env,
     dbs: DbHandles,
     ids: FileIdState,
+    /// First batch failure since the last flush, reported by the next one.
+    first_error: Option<IndexError>,
+    lease_holder: LeaseHolder,
+}
+
+/// A file's freshly read state, applied by the writer thread.
+struct FileUpdate {
+    path: String,
+    modified_ts: u64,
+    identity: Option<FileIdentity>,
+    trigrams: Vec<[u8; 3]>,
+    content_hash: ContentHash,
+    /// Compressed text for the content store.
+    content: Option<Vec<u8>>,
+}
+
+/// Content hash standing in for the unread content of path-only rows. It
+/// keys their (empty) shared token set, which tells them apart from files
+/// that are merely too short to have tokens.
+const PATH_ONLY_HASH: ContentHash = [0; 16];
+
+/// The [`FileUpdate`] of a path-only row for the file at `path`.
+fn path_only_update(normalized: String, path: &Path) -> FileUpdate {
+    FileUpdate {
+        path: normalized,
+        modified_ts: file_modified_timestamp(path),
+        identity: file_identity(path),
+        trigrams: Vec::new(),
+        content_hash: PATH_ONLY_HASH,
+        content: None,
+    }
+}
+
+/// Whether `file_id` is a path-only row; see [`PATH_ONLY_HASH`].
+fn is_path_only(rtxn: &RoTxn, dbs: &DbHandles, file_id: u32) -> IndexResult<bool> {
+    Ok(dbs.file_tokens.get(rtxn, &file_id)? == Some(&shared_token_ref(&PATH_ONLY_HASH)[..]))
 }
 
 enum IndexPayload {
-    UpsertFile {
-        path: String,
-        modified_ts: u64,
-        trigrams: Vec<[u8; 3]>,
-    },
-    RemoveFile {
-        path: String,
-    },
-    SetMeta {
-        key: String,
-        value: String,
-    },
+    UpsertFile(FileUpdate),
+    RemoveFile { path: String },
+    RemovePrefix { path: String },
+    SetMeta { key: String, value: String },
+    RecordRenames { renames: Vec<RenameRecord> },
     Flush,
     ReloadIds,
 }
@@ -95,17 +324,37 @@ enum IndexPayload {
 pub struct BulkFileEntry {
     pub path: String,
     pub modified_ts: u64,
+    /// [`file_identity`] of the file, used to collapse duplicate hits.
+    pub identity: Option<FileIdentity>,
     pub trigrams: Vec<[u8; 3]>,
+    /// [`content_hash`] of the text; files with equal hashes share one
+    /// stored token list.
+    pub content_hash: ContentHash,
+    /// Text prepared with [`stored_content`], when the policy stores
+    /// content.
+    pub content: Option<Vec<u8>>,
 }
 
 impl IndexPayload {
     fn estimated_bytes(&self) -> usize {
         match self {
-            IndexPayload::UpsertFile { path, trigrams, .. } => {
-                path.len() + trigrams.len() * 3 + 64 // 64 bytes overhead estimate
+            IndexPayload::UpsertFile(FileUpdate {
+                path,
+                trigrams,
+                content,
+                ..
+            }) => {
+                // 64 bytes overhead estimate
+                path.len() + trigrams.len() * 3 + content.as_ref().map_or(0, Vec::len) + 64
+            }
+            IndexPayload::RemoveFile { path } | IndexPayload::RemovePrefix { path } => {
+                path.len() + 64
             }
-            IndexPayload::RemoveFile { path } => path.len() + 64,
             IndexPayload::SetMeta { key, value } => key.len() + value.len(),
+            IndexPayload::RecordRenames { renames } => renames
+                .iter()
+                .map(|rename| rename.from.len() + rename.to.len() + 32)
+                .sum(),
             IndexPayload::Flush | IndexPayload::ReloadIds => 0,
         }
     }
@@ -116,46 +365,388 @@ struct IndexJob {
     resp: mpsc::Sender<IndexResult<()>>,
 }
 
+enum WriterMsg {
+    Job(IndexJob),
+    /// A job was added to the priority lane.
+    Wake,
+}
+
+/// Jobs the writer takes before anything still waiting in the channel.
+type PriorityLane = Arc<Mutex<VecDeque<IndexJob>>>;
+
+/// Sending half of the writer queue; keeps the queue depth metric current.
+struct JobSender {
+    tx: mpsc::Sender<WriterMsg>,
+    priority: PriorityLane,
+}
+
+impl JobSender {
+    fn send(&self, job: IndexJob) -> IndexResult<()> {
+        METRICS.job_queued();
+        self.tx.send(WriterMsg::Job(job)).map_err(|_| {
+            METRICS.job_dequeued();
+            IndexError::WriterClosed
+        })
+    }
+
+    /// Queue `job` ahead of the jobs already in the channel.
+    fn send_priority(&self, job: IndexJob) -> IndexResult<()> {
+        METRICS.job_queued();
+        self.priority
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+            .push_back(job);
+        self.tx
+            .send(WriterMsg::Wake)
+            .map_err(|_| IndexError::WriterClosed)
+    }
+}
+
 pub struct PersistentIndex {
     db_path: PathBuf,
     env: Env,
     dbs: DbHandles,
-    sender: Option<mpsc::Sender<IndexJob>>,
+    sender: Option<JobSender>,
     writer_handle: Option<JoinHandle<()>>,
     write_enabled: Arc<AtomicBool>,
+    /// Writes are off because another holder's lease was active when the
+    /// index was opened; see [`PersistentIndex::writes_accepted`].
+    lease_held_elsewhere: AtomicBool,
+    lease_holder: LeaseHolder,
+    idle_commit_ms: Arc<AtomicU64>,
+    content_policy: RwLock<Arc<ContentPolicy>>,
+    tokenizer: RwLock<Arc<dyn Tokenizer>>,
 }
 
 impl PersistentIndex {
     pub fn open_or_create(path: &Path) -> IndexResult<Self> {
+        // Not a fresh index at `path` while a rebuild is swapping one in.
+        wait_for_swap(path);
         std::fs::create_dir_all(path)?;
 
         let env = open_env(path)?;
         let dbs = create_databases(&env)?;
-        let ids = load_file_id_state(&env, &dbs)?;
+        let migrated = match migrate_file_trigrams(&env, &dbs) {
+            Err(IndexError::MapFull) => {
+                resize_env_for_map_full(&env)?;
+                migrate_file_trigrams(&env, &dbs)?
+            }
+            other => other?,
+        };
+        if migrated > 0 {
+            info!(
+                files = migrated,
+                "moved per-file tokens out of file_trigrams"
+            );
+        }
 
+        let lease_holder = LeaseHolder::default();
         let storage = LmdbStorage {
             env: env.clone(),
             dbs: dbs.clone(),
-            ids,
+            ids: FileIdState::default(),
+            first_error: None,
+            lease_holder: Arc::clone(&lease_holder),
         };
 
-        let (tx, rx) = mpsc::channel::<IndexJob>();
+        let (tx, rx) = mpsc::channel::<WriterMsg>();
+        let priority = PriorityLane::default();
+        let priority_for_thread = Arc::clone(&priority);
         let write_enabled = Arc::new(AtomicBool::new(true));
         let write_enabled_for_thread = Arc::clone(&write_enabled);
-        let writer_handle =
-            thread::spawn(move || writer_loop(storage, rx, write_enabled_for_thread));
+        let idle_commit_ms = Arc::new(AtomicU64::new(
+            WriterConfig::default().idle_commit().as_millis() as u64,
+        ));
+        let idle_commit_for_thread = Arc::clone(&idle_commit_ms);
+        let writer_handle = thread::spawn(move || {
+            writer_loop(
+                storage,
+                rx,
+                priority_for_thread,
+                write_enabled_for_thread,
+                idle_commit_for_thread,
+            )
+        });
 
-        Ok(Self {
+        let index = Self {
             db_path: path.to_path_buf(),
             env,
             dbs,
-            sender: Some(tx),
+            sender: Some(JobSender { tx, priority }),
             writer_handle: Some(writer_handle),
             write_enabled,
-        })
+            lease_held_elsewhere: AtomicBool::new(false),
+            lease_holder,
+            idle_commit_ms,
+            content_policy: RwLock::new(Arc::new(ContentPolicy::default())),
+            tokenizer: RwLock::new(default_tokenizer()),
+        };
+        // While another process holds the writer lease, writes fail with
+        // `IndexError::Lease` until this one acquires it.
+        if let Some((holder, _)) = index.read_leader_info()? {
+            debug!(%holder, "writer lease held elsewhere; opening with writes disabled");
+            index.write_enabled.store(false, Ordering::SeqCst);
+            index.lease_held_elsewhere.store(true, Ordering::SeqCst);
+        }
+        Ok(index)
+    }
+
+    /// Replace the tokenizer used for indexing and search. Call
+    /// [`PersistentIndex::ensure_tokenizer`] afterwards so an index built
+    /// with a different tokenizer is cleared.
+    pub fn set_tokenizer(&self, tokenizer: Arc<dyn Tokenizer>) {
+        let mut guard = self
+            .tokenizer
+            .write()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        *guard = tokenizer;
+    }
+
+    pub fn tokenizer(&self) -> Arc<dyn Tokenizer> {
+        let guard = self
+            .tokenizer
+            .read()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        Arc::clone(&guard)
+    }
+
+    /// Compare the active tokenizer with the one recorded in meta. On a
+    /// mismatch the indexed content is cleared (forcing the next scan to be
+    /// a full rebuild) and `true` is returned.
+    ///
+    /// Indexes created before the tag existed were built with the default
+    /// trigram tokenizer.
+    pub fn ensure_tokenizer(&self) -> IndexResult<bool> {
+        let active = self.tokenizer().id();
+        let stored = self.get_meta(TOKENIZER_META_KEY)?;
+        let built_with = stored.as_deref().unwrap_or(default_tokenizer().id());
+
+        if built_with == active {
+            if stored.is_none() {
+                self.set_meta(TOKENIZER_META_KEY, active)?;
+            }
+            return Ok(false);
+        }
+
+        info!(
+            built_with,
+            active, "tokenizer changed; clearing index for rebuild"
+        );
+        self.clear_content()?;
+        self.set_meta(TOKENIZER_META_KEY, active)?;
+        Ok(true)
+    }
+
+    /// Check the configured content key against the one the stored
+    /// content was sealed with, recording it on first use. Content sealed
+    /// with a key that is no longer configured is dropped, so it is stored
+    /// again as files change.
+    pub fn ensure_content_key(&self) -> IndexResult<()> {
+        let policy = self.content_policy();
+        let stored = self.get_meta(KEY_CHECK_META_KEY)?;
+        match (policy.content_key(), stored) {
+            (Some(key), Some(check)) if !key.matches_check(&check) => {
+                Err(IndexError::Config(format!(
+                    "the configured encryption key does not match the one {} was encrypted with",
+                    self.db_path.display()
+                )))
+            }
+            (Some(key), None) => {
+                self.clear_stored_contents()?;
+                self.set_meta(KEY_CHECK_META_KEY, &key.key_check()?)
+            }
+            (None, Some(_)) => {
+                info!("encryption disabled; dropping encrypted stored content");
+                self.clear_stored_contents()
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Run [`PersistentIndex::ensure_content_key`] and
+    /// [`PersistentIndex::ensure_tokenizer`], unless another process holds
+    /// the writer lease: clearing or re-keying is then left to it, or to
+    /// this process once it acquires the lease. Returns whether the index
+    /// was cleared for a different tokenizer.
+    pub fn ensure_settings(&self) -> IndexResult<bool> {
+        match self
+            .ensure_content_key()
+            .and_then(|()| self.ensure_tokenizer())
+        {
+            Err(IndexError::Lease(reason)) => {
+                debug!(%reason, "writer lease held elsewhere; leaving index settings to it");
+                Ok(false)
+            }
+            other => other,
+        }
+    }
+
+    /// Empty the content store and forget which key sealed it.
+    fn clear_stored_contents(&self) -> IndexResult<()> {
+        self.flush()?;
+        let mut wtxn = self.write_txn()?;
+        self.dbs.contents.clear(&mut wtxn)?;
+        let _ = self.dbs.meta.delete(&mut wtxn, KEY_CHECK_META_KEY)?;
+        wtxn.commit()?;
+        Ok(())
+    }
+
+    /// Remove every indexed file and trigram, and forget the stored
+    /// `git_head` so the next scan is a full initial scan. Other meta keys
+    /// and the writer lease are kept.
+    pub fn clear_content(&self) -> IndexResult<()> {
+        self.flush()?;
+
+        let mut wtxn = self.write_txn()?;
+        self.dbs.files.clear(&mut wtxn)?;
+        self.dbs.files_by_path.clear(&mut wtxn)?;
+        self.dbs.trigrams.clear(&mut wtxn)?;
+        self.dbs.file_tokens.clear(&mut wtxn)?;
+        self.dbs.contents.clear(&mut wtxn)?;
+        self.dbs.token_sets.clear(&mut wtxn)?;
+        let _ = self.dbs.meta.delete(&mut wtxn, "git_head")?;
+        let _ = self.dbs.meta.delete(&mut wtxn, NEXT_FILE_ID_META_KEY)?;
+        bump_generation(&self.dbs, &mut wtxn)?;
+        wtxn.commit()?;
+
+        self.reload_file_ids()
+    }
+
+    /// Replace the indexed content with `snapshot`, placing its relative
+    /// paths under `root`. The snapshot must have been built with the active
+    /// tokenizer. Returns the number of files imported.
+    ///
+    /// The caller must hold the writer lease (or otherwise ensure no other
+    /// process writes to this index) for the duration of the import.
+    pub fn import_snapshot(&self, root: &Path, snapshot: &IndexSnapshot) -> IndexResult<usize> {
+        let active = self.tokenizer();
+        if snapshot.tokenizer != active.id() {
+            return Err(IndexError::Config(format!(
+                "snapshot was built with tokenizer `{}` but this index uses `{}`",
+                snapshot.tokenizer,
+                active.id()
+            )));
+        }
+
+        self.clear_content()?;
+        let paused = self.pause_writes();
+
+        let prefix = ensure_trailing_separator(&normalize_path(root));
+        let mut file_tokens: HashMap<u32, Vec<Token>> = HashMap::new();
+        for trigram in &snapshot.trigrams {
+            for file_id in &trigram.file_ids {
+                file_tokens.entry(file_id).or_default().push(trigram.token);
+            }
+        }
+        for tokens in file_tokens.values_mut() {
+            tokens.sort_unstable();
+        }
+
+        let mut attempt = 0usize;
+        let result = loop {
+            let result = (|| -> IndexResult<()> {
+                let mut wtxn = self.write_txn()?;
+
+                for file in &snapshot.files {
+                    let path = format!(
+                        "{prefix}{}",
+                        file.path.replace('/', std::path::MAIN_SEPARATOR_STR)
+                    );
+                    let record = FileRecord {
+                        identity: file_identity(&decode_path(&path)),
+                        path,
+                        last_modified: file.last_modified,
+                    };
+                    self.dbs
+                        .files
+                        .put(&mut wtxn, &file.file_id, &encode_bytes(&record)?)?;
+                    self.dbs
+                        .files_by_path
+                        .put(&mut wtxn, record.path.as_str(), &file.file_id)?;
+                    if let Some(tokens) = file_tokens.get(&file.file_id) {
+                        self.dbs.file_tokens.put(
+                            &mut wtxn,
+                            &file.file_id,
+                            &encode_file_tokens(tokens)?,
+                        )?;
+                    }
+                }
+
+                for trigram in &snapshot.trigrams {
+                    put_postings(&self.dbs, &mut wtxn, &trigram.token, &trigram.file_ids)?;
+                }
+
+                if let Some(head) = &snapshot.git_head {
+                    self.dbs.meta.put(&mut wtxn, "git_head", head)?;
+                }
+                self.dbs
+                    .meta
+                    .put(&mut wtxn, TOKENIZER_META_KEY, &snapshot.tokenizer)?;
+                if let Some(max) = snapshot.files.iter().map(|file| file.file_id).max() {
+                    put_next_file_id(&self.dbs, &mut wtxn, max.saturating_add(1))?;
+                }
+                bump_generation(&self.dbs, &mut wtxn)?;
+
+                wtxn.commit()?;
+                Ok(())
+            })();
+
+            if matches!(result, Err(IndexError::MapFull)) && attempt == 0 {
+                attempt += 1;
+                resize_env_for_map_full(&self.env)?;
+                continue;
+            }
+
+            break result;
+        };
+
+        drop(paused);
+        result?;
+        self.reload_file_ids()?;
+        info!(
+            files = snapshot.files.len(),
+            trigrams = snapshot.trigrams.len(),
+            "imported index snapshot"
+        );
+        Ok(snapshot.files.len())
+    }
+
+    /// Make the writer thread re-read file ids after the databases were
+    /// modified behind its back.
+    fn reload_file_ids(&self) -> IndexResult<()> {
+        let (resp_tx, resp_rx) = mpsc::channel();
+        let job = IndexJob {
+            payload: IndexPayload::ReloadIds,
+            resp: resp_tx,
+        };
+        self.sender()?.send(job)?;
+        let _ = resp_rx.recv();
+        Ok(())
     }
 
+    /// Replace the text/binary classification policy used by `index_path`
+    /// and by scanners that read content themselves.
+    pub fn set_content_policy(&self, policy: ContentPolicy) {
+        let mut guard = self
+            .content_policy
+            .write()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        *guard = Arc::new(policy);
+    }
+
+    pub fn content_policy(&self) -> Arc<ContentPolicy> {
+        let guard = self
+            .content_policy
+            .read()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        Arc::clone(&guard)
+    }
+
+    /// Turn writes through this handle on or off. Writes turned off here
+    /// are dropped silently, unlike those refused for a lease held
+    /// elsewhere at open.
     pub fn set_write_enabled(&self, enabled: bool) {
+        self.lease_held_elsewhere.store(false, Ordering::SeqCst);
         self.write_enabled.store(enabled, Ordering::SeqCst);
     }
 
@@ -163,59 +754,219 @@ impl PersistentIndex {
         self.write_enabled.load(Ordering::SeqCst)
     }
 
-    fn sender(&self) -> IndexResult<&mpsc::Sender<IndexJob>> {
-        self.sender
-            .as_ref()
-            .ok_or_else(|| IndexError::Encode("index has been shut down".to_string()))
+    /// Whether a write should be queued: `Ok(false)` when writes were
+    /// turned off with [`PersistentIndex::set_write_enabled`], and
+    /// [`IndexError::Lease`] while another process's lease keeps them off.
+    fn writes_accepted(&self) -> IndexResult<bool> {
+        if self.write_enabled() {
+            return Ok(true);
+        }
+        if self.lease_held_elsewhere.load(Ordering::SeqCst) {
+            return Err(IndexError::Lease(format!(
+                "another process holds the writer lease of {}; acquire it before writing",
+                self.db_path.display()
+            )));
+        }
+        Ok(false)
+    }
+
+    /// Keep the writer thread from writing while this process writes to
+    /// the databases directly; the returned guard restores the previous
+    /// state on every exit path.
+    fn pause_writes(&self) -> WritesPaused<'_> {
+        WritesPaused {
+            write_enabled: &self.write_enabled,
+            was_enabled: self.write_enabled.swap(false, Ordering::SeqCst),
+        }
+    }
+
+    /// Apply `mode` to commits from now on, for every handle on this index
+    /// in the process.
+    pub fn set_sync_mode(&self, mode: SyncMode) -> IndexResult<()> {
+        // LMDB allows only one thread at a time to change env flags.
+        static SET_FLAGS: Mutex<()> = Mutex::new(());
+        let (enable, disable) = match mode {
+            SyncMode::Off => (EnvFlags::NO_SYNC, EnvFlags::empty()),
+            SyncMode::Normal => (EnvFlags::NO_META_SYNC, EnvFlags::NO_SYNC),
+            SyncMode::Full => (
+                EnvFlags::empty(),
+                EnvFlags::NO_SYNC | EnvFlags::NO_META_SYNC,
+            ),
+        };
+        let _guard = SET_FLAGS
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        // SAFETY: serialized by `SET_FLAGS`; the sync flags only change
+        // how commits are flushed, never the on-disk format.
+        unsafe {
+            if !enable.is_empty() {
+                self.env.set_flags(enable, FlagSetMode::Enable)?;
+            }
+            if !disable.is_empty() {
+                self.env.set_flags(disable, FlagSetMode::Disable)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Commit queued changes once no new one arrived for `idle`, instead of
+    /// as soon as the queue is empty. [`PersistentIndex::flush`] still
+    /// commits immediately.
+    pub fn set_idle_commit(&self, idle: Duration) {
+        self.idle_commit_ms
+            .store(idle.as_millis() as u64, Ordering::SeqCst);
+    }
+
+    fn sender(&self) -> IndexResult<&JobSender> {
+        self.sender.as_ref().ok_or(IndexError::WriterClosed)
     }
 
     pub fn index_path(&self, path: &Path) -> IndexResult<()> {
-        if !self.write_enabled() {
+        self.queue_path(path, false)
+    }
+
+    /// Like [`PersistentIndex::index_path`], but the writer takes the file
+    /// before changes already waiting in its queue, e.g. the files an
+    /// initial scan should make searchable first.
+    pub fn index_path_priority(&self, path: &Path) -> IndexResult<()> {
+        self.queue_path(path, true)
+    }
+
+    fn queue_path(&self, path: &Path, priority: bool) -> IndexResult<()> {
+        if !self.writes_accepted()? {
             return Ok(());
         }
 
         let normalized = normalize_path(path);
-        let content = match read_text_file(path)? {
+        let policy = self.content_policy();
+        if let Some(kind) = policy.archive_kind(path) {
+            return self.index_archive(&normalized, kind, &policy);
+        }
+        let content = match read_text_file_with_policy(&decode_path(&normalized), &policy)? {
             Some(content) => content,
+            None if policy.keeps_path_only(path) => {
+                return self.send_update(path_only_update(normalized, path), priority);
+            }
             None => return Ok(()),
         };
         let modified_ts = file_modified_timestamp(path);
-        let trigrams = collect_trigrams(&content);
+        let identity = file_identity(path);
+        let trigrams = self.tokenizer().document_tokens(&content);
+        let content_hash = content_hash(&content);
+        let content = stored_content(&policy, &content)?;
+        let update = FileUpdate {
+            path: normalized,
+            modified_ts,
+            identity,
+            trigrams,
+            content_hash,
+            content,
+        };
+        self.send_update(update, priority)
+    }
+
+    /// Record `path` without reading it: a path-only row that file path
+    /// search finds but content search never does, as `[binary]
+    /// metadata_only` keeps for binary and oversized files.
+    pub fn index_path_only(&self, path: &Path) -> IndexResult<()> {
+        if !self.writes_accepted()? {
+            return Ok(());
+        }
+        self.send_update(path_only_update(normalize_path(path), path), false)
+    }
+
+    fn send_update(&self, update: FileUpdate, priority: bool) -> IndexResult<()> {
         let (resp_tx, _resp_rx) = mpsc::channel();
         let job = IndexJob {
-            payload: IndexPayload::UpsertFile {
-                path: normalized,
-                modified_ts,
-                trigrams,
-            },
+            payload: IndexPayload::UpsertFile(update),
             resp: resp_tx,
         };
 
-        self.sender()?
-            .send(job)
-            .map_err(|_| IndexError::Encode("writer thread has shut down".to_string()))?;
+        let sender = self.sender()?;
+        if priority {
+            sender.send_priority(job)
+        } else {
+            sender.send(job)
+        }
+    }
+
+    /// Index every text member of an archive under virtual
+    /// `archive!/member` paths, and drop members that no longer exist.
+    fn index_archive(
+        &self,
+        archive_path: &str,
+        kind: ArchiveKind,
+        policy: &ContentPolicy,
+    ) -> IndexResult<()> {
+        let archive_file = decode_path(archive_path);
+        let entries = archive::read_entries(&archive_file, kind, policy.max_archive_entry_bytes())?;
+        let modified_ts = file_modified_timestamp(&archive_file);
+
+        let mut indexed = HashSet::new();
+        for entry in entries {
+            let member_path = archive::virtual_path(archive_path, &entry.name);
+            let Some(content) = policy.extract(Path::new(&entry.name), entry.data) else {
+                continue;
+            };
+            self.index_content(&member_path, &content, modified_ts)?;
+            indexed.insert(member_path);
+        }
+
+        for stale in self
+            .archive_members(archive_path)?
+            .into_iter()
+            .filter(|member| !indexed.contains(member))
+        {
+            self.send_job(IndexPayload::RemoveFile { path: stale })?;
+        }
+        Ok(())
+    }
+
+    /// Virtual paths currently indexed for members of `archive_path`.
+    fn archive_members(&self, archive_path: &str) -> IndexResult<Vec<String>> {
+        let prefix = format!("{archive_path}{ARCHIVE_SEPARATOR}");
+        let rtxn = self.env.read_txn()?;
+        let mut members = Vec::new();
+        for entry in self.dbs.files_by_path.prefix_iter(&rtxn, &prefix)? {
+            let (path, _file_id) = entry?;
+            members.push(path.to_string());
+        }
+        drop(rtxn);
+        Ok(members)
+    }
+
+    fn send_job(&self, payload: IndexPayload) -> IndexResult<()> {
+        let (resp_tx, _resp_rx) = mpsc::channel();
+        let job = IndexJob {
+            payload,
+            resp: resp_tx,
+        };
+        self.sender()?.send(job)?;
         Ok(())
     }
 
     /// Index pre-read content for a given path. Skips filesystem I/O.
     /// Used by the packfile-based scanner which reads blobs from git objects.
     pub fn index_content(&self, path: &str, content: &str, modified_ts: u64) -> IndexResult<()> {
-        if !self.write_enabled() {
+        if !self.writes_accepted()? {
             return Ok(());
         }
-        let trigrams = collect_trigrams(content);
+        let trigrams = self.tokenizer().document_tokens(content);
+        let content_hash = content_hash(content);
+        let content = stored_content(&self.content_policy(), content)?;
         let (resp_tx, _resp_rx) = mpsc::channel();
         let job = IndexJob {
-            payload: IndexPayload::UpsertFile {
+            payload: IndexPayload::UpsertFile(FileUpdate {
                 path: path.to_string(),
                 modified_ts,
+                identity: file_identity(&decode_path(path)),
                 trigrams,
-            },
+                content_hash,
+                content,
+            }),
             resp: resp_tx,
         };
-        self.sender()?
-            .send(job)
-            .map_err(|_| IndexError::Encode("writer thread has shut down".to_string()))?;
+        self.sender()?.send(job)?;
         Ok(())
     }
 
@@ -229,27 +980,31 @@ impl PersistentIndex {
     ) -> IndexResult<()> {
         // Drain the writer thread queue and pause it.
         self.flush()?;
-        self.set_write_enabled(false);
+        let paused = self.pause_writes();
 
         // Extend lease TTL to cover the long bulk write (8-10s+).
         // Normal TTL is 5s which would expire mid-transaction.
         // This must happen BEFORE env.write_txn() to avoid deadlock
         // (renew_writer_lease also needs a write txn).
-        if let Ok(Some((holder, _))) = self.read_leader_info() {
+        if let Some(holder) = self.lease_holder() {
             let _ = self.renew_writer_lease(&holder, Duration::from_secs(120));
         }
 
         let mut attempt = 0usize;
         let result = loop {
+            let started = Instant::now();
             let result = (|| -> IndexResult<()> {
-                let mut wtxn = self.env.write_txn()?;
+                let mut wtxn = self.write_txn()?;
 
-                // Write files + files_by_path + file_trigrams
+                // Write files + files_by_path + file_tokens, counting the
+                // files that reference each distinct token set.
+                let mut token_sets: HashMap<ContentHash, (u32, &[Token])> = HashMap::new();
                 for (file_id, entry) in entries.iter().enumerate() {
                     let fid = file_id as u32;
                     let record = FileRecord {
                         path: entry.path.clone(),
                         last_modified: entry.modified_ts,
+                        identity: entry.identity,
                     };
                     let encoded = encode_bytes(&record)?;
                     self.dbs.files.put(&mut wtxn, &fid, &encoded)?;
@@ -258,9 +1013,24 @@ impl PersistentIndex {
                         .put(&mut wtxn, entry.path.as_str(), &fid)?;
 
                     if !entry.trigrams.is_empty() {
-                        let encoded_tri = encode_bytes(&entry.trigrams)?;
-                        self.dbs.file_trigrams.put(&mut wtxn, &fid, &encoded_tri)?;
+                        self.dbs.file_tokens.put(
+                            &mut wtxn,
+                            &fid,
+                            &shared_token_ref(&entry.content_hash),
+                        )?;
+                        token_sets
+                            .entry(entry.content_hash)
+                            .or_insert((0, &entry.trigrams))
+                            .0 += 1;
                     }
+                    if let Some(content) = &entry.content {
+                        self.dbs.contents.put(&mut wtxn, &fid, content)?;
+                    }
+                }
+                for (hash, (refs, tokens)) in &token_sets {
+                    self.dbs
+                        .token_sets
+                        .put(&mut wtxn, hash, &encode_token_set(*refs, tokens)?)?;
                 }
 
                 // Write trigrams in sorted key order for optimal B-tree insertion.
@@ -269,14 +1039,21 @@ impl PersistentIndex {
 
                 for trigram in &sorted_trigrams {
                     if let Some(bitmap) = trigram_map.get(trigram) {
-                        let encoded = encode_bytes(bitmap)?;
-                        self.dbs.trigrams.put(&mut wtxn, &trigram[..], &encoded)?;
+                        put_postings(&self.dbs, &mut wtxn, trigram, bitmap)?;
                     }
                 }
+                put_next_file_id(&self.dbs, &mut wtxn, entries.len() as u32)?;
+                bump_generation(&self.dbs, &mut wtxn)?;
 
                 wtxn.commit()?;
+                let tokens = entries
+                    .iter()
+                    .map(|entry| entry.trigrams.len())
+                    .sum::<usize>();
+                METRICS.record_batch(entries.len() as u64, 0, tokens as u64, started.elapsed());
                 info!(
                     files = entries.len(),
+                    token_sets = token_sets.len(),
                     trigrams = sorted_trigrams.len(),
                     "bulk_cold_index_direct: committed"
                 );
@@ -293,7 +1070,7 @@ impl PersistentIndex {
         };
 
         // Resume writer thread regardless of success/failure.
-        self.set_write_enabled(true);
+        drop(paused);
 
         // Tell writer thread to reload its FileIdState.
         if result.is_ok() {
@@ -312,7 +1089,7 @@ impl PersistentIndex {
     }
 
     pub fn remove_path(&self, path: &Path) -> IndexResult<()> {
-        if !self.write_enabled() {
+        if !self.writes_accepted()? {
             return Ok(());
         }
 
@@ -323,14 +1100,54 @@ impl PersistentIndex {
             resp: resp_tx,
         };
 
-        self.sender()?
-            .send(job)
-            .map_err(|_| IndexError::Encode("writer thread has shut down".to_string()))?;
+        self.sender()?.send(job)?;
+        Ok(())
+    }
+
+    /// Remove `path` and everything indexed beneath it, e.g. after a
+    /// directory was deleted. All matching files go in one job, and each
+    /// affected posting list is rewritten once rather than once per file.
+    pub fn remove_prefix(&self, path: &Path) -> IndexResult<()> {
+        if !self.writes_accepted()? {
+            return Ok(());
+        }
+
+        let normalized = normalize_path(path);
+        let (resp_tx, _resp_rx) = mpsc::channel();
+        let job = IndexJob {
+            payload: IndexPayload::RemovePrefix { path: normalized },
+            resp: resp_tx,
+        };
+
+        self.sender()?.send(job)?;
         Ok(())
     }
 
+    /// Remember that each `(from, to)` pair was renamed, so path lookups of
+    /// `from` can suggest `to`. Goes through the writer thread like other
+    /// changes; only the latest [`RENAME_HISTORY_LIMIT`] renames are kept.
+    pub fn record_renames(&self, renames: &[(PathBuf, PathBuf)]) -> IndexResult<()> {
+        if !self.writes_accepted()? || renames.is_empty() {
+            return Ok(());
+        }
+
+        let at_ms = now_millis();
+        let renames = renames
+            .iter()
+            .map(|(from, to)| RenameRecord {
+                from: normalize_path(from),
+                to: normalize_path(to),
+                at_ms,
+            })
+            .collect();
+        self.send_job(IndexPayload::RecordRenames { renames })
+    }
+
+    /// Wait for every queued change to be committed. Returns the first
+    /// write failure since the previous flush, since queued jobs have no
+    /// other way to report one.
     pub fn flush(&self) -> IndexResult<()> {
-        if !self.write_enabled() {
+        if !self.writes_accepted()? {
             return Ok(());
         }
 
@@ -340,15 +1157,11 @@ impl PersistentIndex {
             resp: resp_tx,
         };
 
-        self.sender()?
-            .send(job)
-            .map_err(|_| IndexError::Encode("writer thread has shut down".to_string()))?;
+        self.sender()?.send(job)?;
 
         match resp_rx.recv() {
             Ok(result) => result,
-            Err(_) => Err(IndexError::Encode(
-                "writer thread dropped response".to_string(),
-            )),
+            Err(_) => Err(IndexError::WriterClosed),
         }
     }
 
@@ -361,44 +1174,249 @@ impl PersistentIndex {
         query: &str,
         file_regex: Option<&Regex>,
     ) -> IndexResult<Vec<SearchHit>> {
-        let rtxn = self.env.read_txn()?;
-        let hits = search_with_rtxn(&rtxn, &self.dbs, query, file_regex)?;
-        drop(rtxn);
-        Ok(hits)
+        Ok(self.search_filtered_within(query, file_regex, None)?.hits)
     }
 
-    pub fn search_with_snippets(&self, query: &str) -> IndexResult<Vec<SearchResult>> {
-        self.search_with_snippets_filtered(query, None)
+    /// Number of files [`PersistentIndex::search_filtered`] returns for
+    /// `query`. Only the index is read; no file is opened.
+    pub fn search_count(&self, query: &str, file_regex: Option<&Regex>) -> IndexResult<usize> {
+        Ok(self.search_filtered(query, file_regex)?.len())
     }
 
-    pub fn search_with_snippets_filtered(
+    /// Like [`PersistentIndex::search_filtered`], but stops once `timeout`
+    /// has elapsed and returns the hits found so far, marked as truncated.
+    pub fn search_filtered_within(
         &self,
         query: &str,
         file_regex: Option<&Regex>,
-    ) -> IndexResult<Vec<SearchResult>> {
-        let hits = self.search_filtered(query, file_regex)?;
-        Ok(crate::search::attach_snippets(hits, query))
+        timeout: Option<Duration>,
+    ) -> IndexResult<SearchOutcome> {
+        let deadline = timeout.map(|timeout| Instant::now() + timeout);
+        let tokenizer = self.tokenizer();
+        let rtxn = self.env.read_txn()?;
+        let outcome = search_with_rtxn(
+            &rtxn,
+            &self.dbs,
+            tokenizer.as_ref(),
+            query,
+            file_regex,
+            deadline,
+            &mut PostingCache::default(),
+        )?;
+        drop(rtxn);
+        Ok(outcome)
     }
 
-    pub fn get_meta(&self, key: &str) -> IndexResult<Option<String>> {
+    /// Search every query of `queries` in one read transaction, decoding
+    /// the postings of tokens they share only once. Outcomes are returned
+    /// per query, in order, all at the same generation.
+    pub fn search_batch<Q: AsRef<str>>(
+        &self,
+        queries: &[Q],
+        file_regex: Option<&Regex>,
+    ) -> IndexResult<Vec<SearchOutcome>> {
+        let tokenizer = self.tokenizer();
         let rtxn = self.env.read_txn()?;
-        let value = self.dbs.meta.get(&rtxn, key)?.map(str::to_string);
-        drop(rtxn);
-        Ok(value)
+        search_batch_with_rtxn(&rtxn, &self.dbs, tokenizer.as_ref(), queries, file_regex)
+    }
+
+    /// Read the postings of the tokens with the largest posting lists,
+    /// largest first, until `budget` bytes have been read. LMDB serves
+    /// postings straight from its memory map, so this leaves them in the
+    /// OS page cache rather than on the heap: they stay warm across
+    /// commits for as long as the OS has room, and cost nothing to drop.
+    pub fn warm_up(&self, budget: u64) -> IndexResult<WarmUp> {
+        let rtxn = self.env.read_txn()?;
+        // Sum the shards (5-byte keys) and unsharded postings of older
+        // indexes (3-byte keys) per token; the 4-byte keys only hold
+        // counts. Reading every key also pulls the tree's inner pages in.
+        let mut totals: HashMap<Token, u64> = HashMap::new();
+        for entry in self.dbs.trigrams.iter(&rtxn)? {
+            let (key, value) = entry?;
+            if key.len() == POSTING_COUNT_KEY_LEN {
+                continue;
+            }
+            let Some(Ok(token)) = key.get(..3).map(Token::try_from) else {
+                continue;
+            };
+            *totals.entry(token).or_default() += value.len() as u64;
+        }
+        let mut sizes: Vec<(Token, u64)> = totals.into_iter().collect();
+        sizes.sort_by_key(|(_, bytes)| std::cmp::Reverse(*bytes));
+
+        let mut warmed = WarmUp::default();
+        for (token, bytes) in sizes {
+            if warmed.bytes + bytes > budget {
+                continue;
+            }
+            for shard in posting_shards(&rtxn, &self.dbs, &token)? {
+                let touched = shard
+                    .iter()
+                    .step_by(WARM_UP_STRIDE)
+                    .fold(0u8, |acc, byte| acc ^ byte);
+                std::hint::black_box(touched);
+            }
+            warmed.tokens += 1;
+            warmed.bytes += bytes;
+        }
+        Ok(warmed)
+    }
+
+    /// Explain what the index holds for `query`; see [`SearchDiagnostics`].
+    pub fn diagnose(&self, query: &str) -> IndexResult<SearchDiagnostics> {
+        let tokenizer = self.tokenizer();
+        let rtxn = self.env.read_txn()?;
+        diagnose_with_rtxn(&rtxn, &self.dbs, tokenizer.as_ref(), query)
+    }
+
+    /// How a search for `query` would be planned and narrowed down; see
+    /// [`SearchPlan`].
+    pub fn explain(&self, query: &str, file_regex: Option<&Regex>) -> IndexResult<SearchPlan> {
+        let tokenizer = self.tokenizer();
+        let rtxn = self.env.read_txn()?;
+        explain_with_rtxn(&rtxn, &self.dbs, tokenizer.as_ref(), query, file_regex)
+    }
+
+    /// Fallback for a query too short for the index; `None` when the query
+    /// yields tokens (or is empty) and [`PersistentIndex::search`] applies.
+    pub fn short_query(
+        &self,
+        query: &str,
+        file_regex: Option<&Regex>,
+    ) -> IndexResult<Option<ShortQuery>> {
+        let tokenizer = self.tokenizer();
+        let rtxn = self.env.read_txn()?;
+        short_query_with_rtxn(&rtxn, &self.dbs, tokenizer.as_ref(), query, file_regex)
+    }
+
+    pub fn search_with_snippets(&self, query: &str) -> IndexResult<Vec<SearchResult>> {
+        self.search_with_snippets_filtered(query, None)
+    }
+
+    pub fn search_with_snippets_filtered(
+        &self,
+        query: &str,
+        file_regex: Option<&Regex>,
+    ) -> IndexResult<Vec<SearchResult>> {
+        let outcome = self.search_filtered_within(query, file_regex, None)?;
+        let store = self.content_store();
+        Ok(crate::search::attach_snippets(
+            outcome.hits,
+            query,
+            Some(&store),
+            outcome.generation,
+        ))
+    }
+
+    /// Read access to the stored file contents, sharing this index's env.
+    pub fn content_store(&self) -> ContentStore {
+        let policy = self.content_policy();
+        ContentStore {
+            env: self.env.clone(),
+            dbs: self.dbs.clone(),
+            key: policy.content_key().cloned(),
+            secrets: policy.secret_rules().cloned(),
+        }
+    }
+
+    /// Counter that changes whenever indexed content changes, across all
+    /// processes sharing the index. Equal generations mean a repeated
+    /// search returns the same hits.
+    pub fn generation(&self) -> IndexResult<u64> {
+        let rtxn = self.env.read_txn()?;
+        read_generation(&rtxn, &self.dbs)
+    }
+
+    /// Whether no file has been indexed yet.
+    pub fn is_empty(&self) -> IndexResult<bool> {
+        let rtxn = self.env.read_txn()?;
+        Ok(self.dbs.files.is_empty(&rtxn)?)
+    }
+
+    /// Whether [`replace_index`] has swapped a rebuilt index in at this
+    /// index's path. This handle keeps reading the old index; long-lived
+    /// holders should drop it and reopen the path.
+    pub fn is_replaced(&self) -> IndexResult<bool> {
+        Ok(self.get_meta(REPLACED_META_KEY)?.as_deref() == Some("true"))
+    }
+
+    /// The root-relative subtrees scans and the watcher are restricted to;
+    /// empty when the whole root is indexed.
+    pub fn include_paths(&self) -> IndexResult<Vec<PathBuf>> {
+        Ok(self
+            .get_meta(INCLUDE_PATHS_META_KEY)?
+            .map(|value| parse_include_paths(&value))
+            .unwrap_or_default())
+    }
+
+    /// Restrict the index to the root-relative subtrees `paths`, or lift
+    /// the restriction when empty. Paths already indexed are left alone;
+    /// callers rebuild the index after changing its scope.
+    pub fn set_include_paths(&self, paths: &[PathBuf]) -> IndexResult<()> {
+        let value = paths
+            .iter()
+            .map(|path| path.to_string_lossy().replace('\\', "/"))
+            .collect::<Vec<_>>()
+            .join("\n");
+        self.set_meta(INCLUDE_PATHS_META_KEY, &value)
+    }
+
+    pub fn get_meta(&self, key: &str) -> IndexResult<Option<String>> {
+        let rtxn = self.env.read_txn()?;
+        let value = self.dbs.meta.get(&rtxn, key)?.map(str::to_string);
+        drop(rtxn);
+        Ok(value)
     }
 
     /// Write meta directly via a write transaction. Use when no writer thread
     /// is active (e.g., during daemon startup/shutdown or from CLI processes).
+    /// Fails with [`IndexError::Lease`] while another process holds the
+    /// writer lease.
     pub fn set_meta(&self, key: &str, value: &str) -> IndexResult<()> {
+        let mut wtxn = self.write_txn()?;
+        self.dbs.meta.put(&mut wtxn, key, value)?;
+        wtxn.commit()?;
+        Ok(())
+    }
+
+    /// Write a meta key addressed to whichever process holds the writer
+    /// lease, such as a request to pause indexing. Unlike
+    /// [`PersistentIndex::set_meta`] it does not need the lease.
+    pub fn set_control_meta(&self, key: &str, value: &str) -> IndexResult<()> {
         let mut wtxn = self.env.write_txn()?;
         self.dbs.meta.put(&mut wtxn, key, value)?;
         wtxn.commit()?;
         Ok(())
     }
 
+    /// A write transaction for writes that bypass the writer thread,
+    /// refused while another process holds the writer lease.
+    fn write_txn(&self) -> IndexResult<RwTxn<'_>> {
+        let wtxn = self.env.write_txn()?;
+        check_lease(&self.dbs, &wtxn, &self.lease_holder)?;
+        Ok(wtxn)
+    }
+
+    /// The writer lease holder this handle last acquired and has not
+    /// released.
+    fn lease_holder(&self) -> Option<String> {
+        self.lease_holder
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+            .clone()
+    }
+
+    fn set_lease_holder(&self, holder: Option<&str>) {
+        *self
+            .lease_holder
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner()) = holder.map(str::to_string);
+    }
+
     /// Queue a meta write through the writer thread channel. Use when the
     /// writer thread is running to avoid competing for the LMDB write lock.
-    /// Fire-and-forget: errors are logged by the writer thread, not returned.
+    /// Fire-and-forget: errors are logged by the writer thread and returned
+    /// by the next [`PersistentIndex::flush`].
     pub fn set_meta_queued(&self, key: &str, value: &str) -> IndexResult<()> {
         let (resp_tx, _resp_rx) = mpsc::channel();
         let job = IndexJob {
@@ -408,13 +1426,25 @@ impl PersistentIndex {
             },
             resp: resp_tx,
         };
-        self.sender()?
-            .send(job)
-            .map_err(|_| IndexError::Encode("writer thread has shut down".to_string()))?;
+        self.sender()?.send(job)?;
         Ok(())
     }
 
+    /// Take the writer lease for `holder` unless another holder's lease is
+    /// still valid, enabling writes through this handle when it succeeds.
     pub fn try_acquire_writer_lease(&self, holder: &str, ttl: Duration) -> IndexResult<bool> {
+        self.try_acquire_writer_lease_with(holder, ttl, |_| false)
+    }
+
+    /// Like [`PersistentIndex::try_acquire_writer_lease`], but also takes
+    /// over an unexpired lease whose holder `orphaned` reports as gone, so a
+    /// crashed writer is replaced without waiting out its TTL.
+    pub fn try_acquire_writer_lease_with(
+        &self,
+        holder: &str,
+        ttl: Duration,
+        orphaned: impl Fn(&str) -> bool,
+    ) -> IndexResult<bool> {
         let now = now_millis();
         let expires_at = now.saturating_add(ttl.as_millis().min(i64::MAX as u128) as i64);
 
@@ -427,7 +1457,9 @@ impl PersistentIndex {
             .transpose()?;
 
         let can_acquire = match current {
-            Some(ref record) => record.expires_at_ms < now || record.holder == holder,
+            Some(ref record) => {
+                record.expires_at_ms < now || record.holder == holder || orphaned(&record.holder)
+            }
             None => true,
         };
 
@@ -443,6 +1475,10 @@ impl PersistentIndex {
         }
 
         wtxn.commit()?;
+        if can_acquire {
+            self.set_lease_holder(Some(holder));
+            self.set_write_enabled(true);
+        }
         Ok(can_acquire)
     }
 
@@ -500,6 +1536,9 @@ impl PersistentIndex {
         }
 
         wtxn.commit()?;
+        if self.lease_holder().as_deref() == Some(holder) {
+            self.set_lease_holder(None);
+        }
         Ok(())
     }
 
@@ -535,7 +1574,7 @@ impl PersistentIndex {
         let mut outside = 0usize;
         for entry in self.dbs.files.iter(&rtxn)? {
             let (_file_id, value) = entry?;
-            let record: FileRecord = decode_bytes(value)?;
+            let record = decode_file_record(value)?;
             if !path_is_within_root(&record.path, root) {
                 outside += 1;
             }
@@ -543,6 +1582,74 @@ impl PersistentIndex {
         drop(rtxn);
         Ok(outside)
     }
+
+    /// The modification time each file under `root` was indexed with,
+    /// keyed by its stored path.
+    pub fn modified_times_within(&self, root: &Path) -> IndexResult<HashMap<String, u64>> {
+        let rtxn = self.env.read_txn()?;
+        let mut times = HashMap::new();
+        for entry in self.dbs.files.iter(&rtxn)? {
+            let (_file_id, value) = entry?;
+            let record = decode_file_record(value)?;
+            if path_is_within_root(&record.path, root) {
+                times.insert(record.path, record.last_modified);
+            }
+        }
+        drop(rtxn);
+        Ok(times)
+    }
+}
+
+/// File contents kept in the index when `[content_store]` is enabled.
+/// Reads go through LMDB's memory map, so a stored file costs no open or
+/// read syscall, only decompression.
+#[derive(Clone)]
+pub struct ContentStore {
+    env: Env,
+    dbs: DbHandles,
+    key: Option<Arc<ContentKey>>,
+    secrets: Option<SecretRules>,
+}
+
+impl ContentStore {
+    /// Open the store of the index at `db_path` for reading.
+    pub fn open(db_path: &Path) -> IndexResult<Self> {
+        let (env, dbs) = open_readonly_env(db_path)?;
+        Ok(Self {
+            env,
+            dbs,
+            key: None,
+            secrets: None,
+        })
+    }
+
+    /// Open sealed content with `key`; without one, reads of sealed
+    /// content fail and callers fall back to the file on disk.
+    pub fn with_key(mut self, key: Option<Arc<ContentKey>>) -> Self {
+        self.key = key;
+        self
+    }
+
+    /// Redact content read for snippets and verification with `rules`.
+    /// Stored copies were redacted when indexed; this also covers files
+    /// read from disk and patterns added since.
+    pub fn with_secret_rules(mut self, rules: Option<SecretRules>) -> Self {
+        self.secrets = rules;
+        self
+    }
+
+    pub fn secret_rules(&self) -> Option<&SecretRules> {
+        self.secrets.as_ref()
+    }
+
+    /// The stored text of `path`, or `None` when it was not stored.
+    pub fn read(&self, path: &str) -> IndexResult<Option<Vec<u8>>> {
+        let rtxn = self.env.read_txn()?;
+        let Some(file_id) = self.dbs.files_by_path.get(&rtxn, path)? else {
+            return Ok(None);
+        };
+        read_stored_content(&rtxn, &self.dbs, file_id, self.key.as_deref())
+    }
 }
 
 impl Drop for PersistentIndex {
@@ -563,11 +1670,84 @@ pub fn search_database_file_filtered(
     query: &str,
     file_regex: Option<&Regex>,
 ) -> IndexResult<Vec<SearchHit>> {
+    Ok(search_database_file_within(path, query, file_regex, None)?.hits)
+}
+
+/// Read-only counterpart of [`PersistentIndex::search_count`].
+pub fn count_database_file(
+    path: &Path,
+    query: &str,
+    file_regex: Option<&Regex>,
+) -> IndexResult<usize> {
+    Ok(search_database_file_filtered(path, query, file_regex)?.len())
+}
+
+/// Read-only counterpart of [`PersistentIndex::search_filtered_within`].
+pub fn search_database_file_within(
+    path: &Path,
+    query: &str,
+    file_regex: Option<&Regex>,
+    timeout: Option<Duration>,
+) -> IndexResult<SearchOutcome> {
+    let deadline = timeout.map(|timeout| Instant::now() + timeout);
     let (env, dbs) = open_readonly_env(path)?;
     let rtxn = env.read_txn()?;
-    let hits = search_with_rtxn(&rtxn, &dbs, query, file_regex)?;
+    let tokenizer = stored_tokenizer(&rtxn, &dbs)?;
+    let outcome = search_with_rtxn(
+        &rtxn,
+        &dbs,
+        tokenizer.as_ref(),
+        query,
+        file_regex,
+        deadline,
+        &mut PostingCache::default(),
+    )?;
     drop(rtxn);
-    Ok(hits)
+    Ok(outcome)
+}
+
+/// Read-only counterpart of [`PersistentIndex::search_batch`].
+pub fn search_database_file_batch<Q: AsRef<str>>(
+    path: &Path,
+    queries: &[Q],
+    file_regex: Option<&Regex>,
+) -> IndexResult<Vec<SearchOutcome>> {
+    let (env, dbs) = open_readonly_env(path)?;
+    let rtxn = env.read_txn()?;
+    let tokenizer = stored_tokenizer(&rtxn, &dbs)?;
+    search_batch_with_rtxn(&rtxn, &dbs, tokenizer.as_ref(), queries, file_regex)
+}
+
+/// Read-only counterpart of [`PersistentIndex::diagnose`].
+pub fn diagnose_database_file(path: &Path, query: &str) -> IndexResult<SearchDiagnostics> {
+    let (env, dbs) = open_readonly_env(path)?;
+    let rtxn = env.read_txn()?;
+    let tokenizer = stored_tokenizer(&rtxn, &dbs)?;
+    diagnose_with_rtxn(&rtxn, &dbs, tokenizer.as_ref(), query)
+}
+
+/// Read-only counterpart of [`PersistentIndex::explain`].
+pub fn explain_database_file(
+    path: &Path,
+    query: &str,
+    file_regex: Option<&Regex>,
+) -> IndexResult<SearchPlan> {
+    let (env, dbs) = open_readonly_env(path)?;
+    let rtxn = env.read_txn()?;
+    let tokenizer = stored_tokenizer(&rtxn, &dbs)?;
+    explain_with_rtxn(&rtxn, &dbs, tokenizer.as_ref(), query, file_regex)
+}
+
+/// Read-only counterpart of [`PersistentIndex::short_query`].
+pub fn short_query_in_database(
+    path: &Path,
+    query: &str,
+    file_regex: Option<&Regex>,
+) -> IndexResult<Option<ShortQuery>> {
+    let (env, dbs) = open_readonly_env(path)?;
+    let rtxn = env.read_txn()?;
+    let tokenizer = stored_tokenizer(&rtxn, &dbs)?;
+    short_query_with_rtxn(&rtxn, &dbs, tokenizer.as_ref(), query, file_regex)
 }
 
 pub fn search_files_in_database(path: &Path, pattern: &str) -> IndexResult<Vec<SearchHit>> {
@@ -578,107 +1758,517 @@ pub fn search_files_in_database(path: &Path, pattern: &str) -> IndexResult<Vec<S
     let (env, dbs) = open_readonly_env(path)?;
     let rtxn = env.read_txn()?;
     let lower_pattern = pattern.to_lowercase();
-    let mut hits = Vec::new();
-
-    for entry in dbs.files.iter(&rtxn)? {
-        let (file_id, value) = entry?;
-        let record: FileRecord = decode_bytes(value)?;
-        if record.path.to_lowercase().contains(&lower_pattern) {
-            hits.push(SearchHit {
-                file_id,
-                path: record.path,
-            });
-        }
-    }
-
+    let hits = matching_files(&rtxn, &dbs, |path| {
+        path.to_lowercase().contains(&lower_pattern)
+    })?;
     drop(rtxn);
-    hits.sort_by(|lhs, rhs| lhs.path.cmp(&rhs.path));
     Ok(hits)
 }
 
-fn ensure_trailing_separator(path: &str) -> String {
-    let sep = std::path::MAIN_SEPARATOR;
-    if path.ends_with(sep) {
-        path.to_string()
-    } else {
-        format!("{path}{sep}")
+/// The `limit` tokens posted by the most files, most common first. Stop
+/// tokens show up here; they are what make an index large.
+pub fn top_trigrams_in_database(path: &Path, limit: usize) -> IndexResult<Vec<TrigramStat>> {
+    let (env, dbs) = open_readonly_env(path)?;
+    let rtxn = env.read_txn()?;
+
+    // Shards of one token are adjacent in key order; merge them.
+    let mut stats: Vec<(Token, RoaringBitmap, u64)> = Vec::new();
+    for entry in dbs.trigrams.iter(&rtxn)? {
+        let (key, value) = entry?;
+        let Some(Ok(token)) = key.get(..3).map(Token::try_from) else {
+            continue;
+        };
+        let bytes = (key.len() + value.len()) as u64;
+        let shard = if key.len() == POSTING_COUNT_KEY_LEN {
+            RoaringBitmap::new()
+        } else {
+            decode_bitmap(value)?
+        };
+        match stats.last_mut() {
+            Some((last, file_ids, total)) if *last == token => {
+                *file_ids |= shard;
+                *total += bytes;
+            }
+            _ => stats.push((token, shard, bytes)),
+        }
     }
+    drop(rtxn);
+
+    let mut stats: Vec<TrigramStat> = stats
+        .into_iter()
+        .map(|(token, file_ids, bytes)| TrigramStat {
+            trigram: String::from_utf8_lossy(&token).into_owned(),
+            files: file_ids.len(),
+            bytes,
+        })
+        .collect();
+    stats.sort_by(|lhs, rhs| {
+        rhs.files
+            .cmp(&lhs.files)
+            .then(rhs.bytes.cmp(&lhs.bytes))
+            .then_with(|| lhs.trigram.cmp(&rhs.trigram))
+    });
+    stats.truncate(limit);
+    Ok(stats)
 }
 
-fn normalize_index_path_string(path: &str) -> String {
-    if cfg!(windows) {
-        path.strip_prefix(r"\\?\")
-            .unwrap_or(path)
-            .replace('/', "\\")
-    } else {
-        path.to_string()
+/// The `limit` files posted under the most tokens, largest first, with the
+/// size of their stored text.
+pub fn largest_files_in_database(path: &Path, limit: usize) -> IndexResult<Vec<FileStat>> {
+    let (env, dbs) = open_readonly_env(path)?;
+    let rtxn = env.read_txn()?;
+
+    let mut stats = Vec::new();
+    for entry in dbs.files.iter(&rtxn)? {
+        let (file_id, value) = entry?;
+        let record = decode_file_record(value)?;
+        let trigrams = read_file_tokens(&rtxn, &dbs, file_id)?.map_or(0, |tokens| tokens.len());
+        let stored_bytes = dbs
+            .contents
+            .get(&rtxn, &file_id)?
+            .map_or(0, |content| content.len());
+        stats.push(FileStat {
+            path: record.path,
+            trigrams: trigrams as u64,
+            stored_bytes: stored_bytes as u64,
+        });
     }
+    drop(rtxn);
+
+    stats.sort_by(|lhs, rhs| {
+        rhs.trigrams
+            .cmp(&lhs.trigrams)
+            .then(rhs.stored_bytes.cmp(&lhs.stored_bytes))
+            .then_with(|| lhs.path.cmp(&rhs.path))
+    });
+    stats.truncate(limit);
+    Ok(stats)
 }
 
-fn path_suffix_after_root(path: &str, root: &Path) -> Option<String> {
-    let path_normalized = normalize_index_path_string(path);
-    let root_normalized = normalize_path(root);
-    let root_prefix = ensure_trailing_separator(&root_normalized);
-    let path_cmp = normalize_path_for_prefix(&path_normalized);
-    let root_cmp = normalize_path_for_prefix(&root_prefix);
+/// Indexed files and their footprint per extension, largest first. Only
+/// value lengths are read, so this is cheap enough for `sf status`.
+pub fn extension_stats_in_database(path: &Path) -> IndexResult<Vec<ExtensionStat>> {
+    let (env, dbs) = open_readonly_env(path)?;
+    let rtxn = env.read_txn()?;
 
-    if !path_cmp.starts_with(&root_cmp) {
-        return None;
+    let mut by_extension: HashMap<String, (u64, u64)> = HashMap::new();
+    for entry in dbs.files.iter(&rtxn)? {
+        let (file_id, value) = entry?;
+        let record = decode_file_record(value)?;
+        let extension = Path::new(&record.path)
+            .extension()
+            .map(|ext| ext.to_string_lossy().to_lowercase())
+            .unwrap_or_default();
+        let bytes = dbs.contents.get(&rtxn, &file_id)?.map_or(0, <[u8]>::len)
+            + dbs.file_tokens.get(&rtxn, &file_id)?.map_or(0, <[u8]>::len);
+        let (files, total) = by_extension.entry(extension).or_default();
+        *files += 1;
+        *total += bytes as u64;
     }
+    drop(rtxn);
 
-    Some(path_normalized[root_prefix.len()..].to_string())
+    let mut stats: Vec<ExtensionStat> = by_extension
+        .into_iter()
+        .map(|(extension, (files, bytes))| ExtensionStat {
+            extension,
+            files,
+            bytes,
+        })
+        .collect();
+    stats.sort_by(|lhs, rhs| {
+        rhs.bytes
+            .cmp(&lhs.bytes)
+            .then_with(|| lhs.extension.cmp(&rhs.extension))
+    });
+    Ok(stats)
 }
 
-pub(crate) fn diff_sorted_trigrams(
-    old: &[[u8; 3]],
-    new: &[[u8; 3]],
-) -> (Vec<[u8; 3]>, Vec<[u8; 3]>) {
-    let mut removed = Vec::new();
-    let mut added = Vec::new();
-    let mut old_idx = 0usize;
-    let mut new_idx = 0usize;
+/// The indexed files whose path matches `file_regex` and that were last
+/// modified at or after `modified_since` (seconds since the Unix epoch),
+/// sorted by path.
+pub fn list_files_in_database(
+    path: &Path,
+    file_regex: Option<&Regex>,
+    modified_since: Option<u64>,
+) -> IndexResult<Vec<IndexedFile>> {
+    let (env, dbs) = open_readonly_env(path)?;
+    let rtxn = env.read_txn()?;
 
-    while old_idx < old.len() && new_idx < new.len() {
-        match old[old_idx].cmp(&new[new_idx]) {
-            std::cmp::Ordering::Less => {
-                removed.push(old[old_idx]);
-                old_idx += 1;
-            }
-            std::cmp::Ordering::Equal => {
-                old_idx += 1;
-                new_idx += 1;
-            }
-            std::cmp::Ordering::Greater => {
-                added.push(new[new_idx]);
-                new_idx += 1;
-            }
+    let mut files = Vec::new();
+    for entry in dbs.files.iter(&rtxn)? {
+        let (file_id, value) = entry?;
+        let record = decode_file_record(value)?;
+        if file_regex.is_some_and(|re| !re.is_match(&record.path))
+            || modified_since.is_some_and(|since| record.last_modified < since)
+        {
+            continue;
         }
+        let stored_bytes = dbs
+            .contents
+            .get(&rtxn, &file_id)?
+            .map_or(0, |content| content.len());
+        files.push(IndexedFile {
+            file_id,
+            path: record.path,
+            last_modified: record.last_modified,
+            stored_bytes: stored_bytes as u64,
+        });
     }
+    drop(rtxn);
 
-    removed.extend_from_slice(&old[old_idx..]);
-    added.extend_from_slice(&new[new_idx..]);
-
-    (removed, added)
+    files.sort_by(|lhs, rhs| lhs.path.cmp(&rhs.path));
+    Ok(files)
 }
 
-/// Rewrite all file paths in the index from `old_root` to `new_root`.
+/// Indexed files whose token set has a Jaccard similarity of at least
+/// `min_similarity` with that of the indexed file `file`, most similar
+/// first, at most `limit` of them. `None` when `file` is not indexed.
 ///
-/// Opens the LMDB environment directly and performs a write transaction
-/// without going through the writer thread. Only safe when no
-/// `PersistentIndex` is active for this `db_path` (no daemon or MCP
-/// server running). Called during worktree copy setup before a daemon starts.
-pub fn rewrite_root_paths(db_path: &Path, old_root: &Path, new_root: &Path) -> IndexResult<()> {
-    let new_norm = normalize_path(new_root);
-    let new_prefix = ensure_trailing_separator(&new_norm);
+/// A file that reaches the threshold shares at least one of the `file`'s
+/// rarest tokens (all but the `min_similarity` fraction of them), so only
+/// the files posted under those are compared.
+pub fn similar_files_in_database(
+    path: &Path,
+    file: &str,
+    min_similarity: f64,
+    limit: usize,
+) -> IndexResult<Option<Vec<SimilarFile>>> {
+    let (env, dbs) = open_readonly_env(path)?;
+    let rtxn = env.read_txn()?;
+    let Some(file_id) = dbs.files_by_path.get(&rtxn, file)? else {
+        return Ok(None);
+    };
+    let tokens = read_file_tokens(&rtxn, &dbs, file_id)?.unwrap_or_default();
+    if tokens.is_empty() {
+        return Ok(Some(Vec::new()));
+    }
+    let min_similarity = min_similarity.clamp(0.0, 1.0);
 
-    if normalize_path_for_prefix(&normalize_path(old_root)) == normalize_path_for_prefix(&new_norm)
-    {
-        return Ok(());
+    let mut by_rarity = Vec::with_capacity(tokens.len());
+    for token in &tokens {
+        let (count, postings) = posting_count_or_load(&rtxn, &dbs, token)?;
+        by_rarity.push((count, token, postings));
+    }
+    by_rarity.sort_by_key(|(count, _, _)| *count);
+    let needed = (min_similarity * tokens.len() as f64).ceil() as usize;
+    let prefix = (tokens.len() - needed.min(tokens.len()) + 1).min(tokens.len());
+    let mut candidates = RoaringBitmap::new();
+    for (_, token, postings) in by_rarity.into_iter().take(prefix) {
+        match postings {
+            Some(postings) => candidates |= postings,
+            None => candidates |= read_postings(&rtxn, &dbs, token)?.unwrap_or_default(),
+        }
     }
+    candidates.remove(file_id);
 
-    let env = open_env(db_path)?;
-    let mut wtxn = env.write_txn()?;
-    let files: FilesDb = env
+    let mut similar = Vec::new();
+    for candidate in candidates {
+        let Some(other) = read_file_tokens(&rtxn, &dbs, candidate)? else {
+            continue;
+        };
+        let shared = count_shared(&tokens, &other);
+        let union = tokens.len() + other.len() - shared;
+        let similarity = shared as f64 / union as f64;
+        if similarity < min_similarity {
+            continue;
+        }
+        let Some(value) = dbs.files.get(&rtxn, &candidate)? else {
+            continue;
+        };
+        similar.push(SimilarFile {
+            path: decode_file_record(value)?.path,
+            similarity,
+            shared: shared as u64,
+            trigrams: other.len() as u64,
+        });
+    }
+    drop(rtxn);
+
+    similar.sort_by(|lhs, rhs| {
+        rhs.similarity
+            .total_cmp(&lhs.similarity)
+            .then_with(|| lhs.path.cmp(&rhs.path))
+    });
+    similar.truncate(limit);
+    Ok(Some(similar))
+}
+
+/// Paths to offer when a path lookup of `pattern` matched nothing, best
+/// first, at most `limit` of them.
+///
+/// Recorded renames whose old path contains `pattern` come first, most
+/// recent first, pointing at where the file (or directory) lives now if that
+/// is still indexed. Then indexed paths that contain `pattern` with at most a
+/// few typos (insertions, deletions, substitutions or swapped neighbours),
+/// closest first.
+pub fn suggest_files_in_database(
+    path: &Path,
+    pattern: &str,
+    limit: usize,
+) -> IndexResult<Vec<FileSuggestion>> {
+    if pattern.is_empty() || limit == 0 {
+        return Ok(Vec::new());
+    }
+
+    let (env, dbs) = open_readonly_env(path)?;
+    let rtxn = env.read_txn()?;
+    let lower_pattern = pattern.to_lowercase();
+    let mut suggestions: Vec<FileSuggestion> = Vec::new();
+
+    let history = read_rename_history(&rtxn, &dbs)?;
+    for (i, rename) in history.iter().enumerate().rev() {
+        if suggestions.len() >= limit {
+            break;
+        }
+        if !rename.from.to_lowercase().contains(&lower_pattern) {
+            continue;
+        }
+        let target = follow_renames(&rename.to, &history[i + 1..]);
+        if suggestions.iter().any(|s| s.path == target) || !path_is_indexed(&rtxn, &dbs, &target)? {
+            continue;
+        }
+        suggestions.push(FileSuggestion {
+            path: target,
+            renamed_from: Some(rename.from.clone()),
+            distance: 0,
+        });
+    }
+
+    let pattern: Vec<char> = lower_pattern.chars().collect();
+    if pattern.len() >= MIN_SUGGEST_PATTERN_LEN && suggestions.len() < limit {
+        let max_distance = (pattern.len() / 4).clamp(1, 3);
+        let mut fuzzy = Vec::new();
+        for entry in dbs.files_by_path.iter(&rtxn)? {
+            let (file, _) = entry?;
+            if suggestions.iter().any(|s| s.path == file) {
+                continue;
+            }
+            let distance = substring_edit_distance(&pattern, &file.to_lowercase());
+            if distance <= max_distance {
+                fuzzy.push(FileSuggestion {
+                    path: file.to_string(),
+                    renamed_from: None,
+                    distance,
+                });
+            }
+        }
+        fuzzy.sort_by(|lhs, rhs| {
+            lhs.distance
+                .cmp(&rhs.distance)
+                .then_with(|| lhs.path.len().cmp(&rhs.path.len()))
+                .then_with(|| lhs.path.cmp(&rhs.path))
+        });
+        suggestions.extend(fuzzy);
+    }
+    drop(rtxn);
+
+    suggestions.truncate(limit);
+    Ok(suggestions)
+}
+
+fn read_rename_history(rtxn: &RoTxn, dbs: &DbHandles) -> IndexResult<Vec<RenameRecord>> {
+    // A history that fails to parse only costs suggestions; start over.
+    Ok(dbs
+        .meta
+        .get(rtxn, RENAME_HISTORY_META_KEY)?
+        .and_then(|value| serde_json::from_str(value).ok())
+        .unwrap_or_default())
+}
+
+fn append_renames(dbs: &DbHandles, wtxn: &mut RwTxn, renames: &[RenameRecord]) -> IndexResult<()> {
+    let mut history = read_rename_history(wtxn, dbs)?;
+    for rename in renames.iter().filter(|r| r.from != r.to) {
+        // Uncommitted renames are seen again by every scan; keep one record.
+        history.retain(|r| r.from != rename.from || r.to != rename.to);
+        history.push(rename.clone());
+    }
+    let excess = history.len().saturating_sub(RENAME_HISTORY_LIMIT);
+    history.drain(..excess);
+    let value =
+        serde_json::to_string(&history).map_err(|err| IndexError::Encode(err.to_string()))?;
+    dbs.meta.put(wtxn, RENAME_HISTORY_META_KEY, &value)?;
+    Ok(())
+}
+
+/// Where `path` ended up after the `later` renames, e.g. `c` for `b`
+/// followed by `b -> c`. Also follows renames of a parent directory.
+fn follow_renames(path: &str, later: &[RenameRecord]) -> String {
+    let mut current = path.to_string();
+    for later in later {
+        if current == later.from {
+            current = later.to.clone();
+        } else if let Some(rest) = current
+            .strip_prefix(later.from.as_str())
+            .filter(|rest| rest.starts_with(['/', '\\']))
+        {
+            current = format!("{}{rest}", later.to);
+        }
+    }
+    current
+}
+
+/// Whether `path` is an indexed file or a directory holding one.
+fn path_is_indexed(rtxn: &RoTxn, dbs: &DbHandles, path: &str) -> IndexResult<bool> {
+    if dbs.files_by_path.get(rtxn, path)?.is_some() {
+        return Ok(true);
+    }
+    let prefix = ensure_trailing_separator(path);
+    Ok(dbs
+        .files_by_path
+        .prefix_iter(rtxn, &prefix)?
+        .next()
+        .is_some())
+}
+
+/// Fewest edits turning `pattern` into some substring of `text`, counting
+/// a swap of two neighbouring characters as one edit (Sellers' algorithm
+/// with optimal string alignment).
+fn substring_edit_distance(pattern: &[char], text: &str) -> usize {
+    let n = pattern.len();
+    // Rows for the previous two text positions; column j is the cost of
+    // matching pattern[..j] ending there. Row 0 is free to start anywhere.
+    let mut before: Vec<usize> = (0..=n).collect();
+    let mut prev: Vec<usize> = (0..=n).collect();
+    let mut curr = vec![0; n + 1];
+    let mut best = n;
+    let mut prev_char: Option<char> = None;
+    for ch in text.chars() {
+        curr[0] = 0;
+        for j in 1..=n {
+            let cost = usize::from(pattern[j - 1] != ch);
+            let mut value = (prev[j - 1] + cost).min(prev[j] + 1).min(curr[j - 1] + 1);
+            if j > 1 && prev_char == Some(pattern[j - 1]) && pattern[j - 2] == ch {
+                value = value.min(before[j - 2] + 1);
+            }
+            curr[j] = value;
+        }
+        best = best.min(curr[n]);
+        std::mem::swap(&mut before, &mut prev);
+        std::mem::swap(&mut prev, &mut curr);
+        prev_char = Some(ch);
+    }
+    best
+}
+
+/// Tokens present in both sorted lists.
+fn count_shared(lhs: &[Token], rhs: &[Token]) -> usize {
+    let (mut i, mut j, mut shared) = (0, 0, 0);
+    while i < lhs.len() && j < rhs.len() {
+        match lhs[i].cmp(&rhs[j]) {
+            std::cmp::Ordering::Less => i += 1,
+            std::cmp::Ordering::Greater => j += 1,
+            std::cmp::Ordering::Equal => {
+                shared += 1;
+                i += 1;
+                j += 1;
+            }
+        }
+    }
+    shared
+}
+
+/// Indexed files whose path satisfies `keep`, sorted by path.
+fn matching_files(
+    rtxn: &RoTxn,
+    dbs: &DbHandles,
+    keep: impl Fn(&str) -> bool,
+) -> IndexResult<Vec<SearchHit>> {
+    let mut hits = HitSet::default();
+    for entry in dbs.files.iter(rtxn)? {
+        let (file_id, value) = entry?;
+        let record = decode_file_record(value)?;
+        if keep(&record.path) {
+            hits.push(file_id, record);
+        }
+    }
+    let mut hits = hits.into_hits();
+    hits.sort_by(|lhs, rhs| lhs.path.cmp(&rhs.path));
+    Ok(hits)
+}
+
+fn ensure_trailing_separator(path: &str) -> String {
+    let sep = std::path::MAIN_SEPARATOR;
+    if path.ends_with(sep) {
+        path.to_string()
+    } else {
+        format!("{path}{sep}")
+    }
+}
+
+fn normalize_index_path_string(path: &str) -> String {
+    if cfg!(windows) {
+        path.strip_prefix(r"\\?\")
+            .unwrap_or(path)
+            .replace('/', "\\")
+    } else {
+        path.to_string()
+    }
+}
+
+pub(crate) fn path_suffix_after_root(path: &str, root: &Path) -> Option<String> {
+    let path_normalized = normalize_index_path_string(path);
+    let root_normalized = normalize_path(root);
+    let root_prefix = ensure_trailing_separator(&root_normalized);
+    let path_cmp = normalize_path_for_prefix(&path_normalized);
+    let root_cmp = normalize_path_for_prefix(&root_prefix);
+
+    if !path_cmp.starts_with(&root_cmp) {
+        return None;
+    }
+
+    Some(path_normalized[root_prefix.len()..].to_string())
+}
+
+pub(crate) fn diff_sorted_trigrams(
+    old: &[[u8; 3]],
+    new: &[[u8; 3]],
+) -> (Vec<[u8; 3]>, Vec<[u8; 3]>) {
+    let mut removed = Vec::new();
+    let mut added = Vec::new();
+    let mut old_idx = 0usize;
+    let mut new_idx = 0usize;
+
+    while old_idx < old.len() && new_idx < new.len() {
+        match old[old_idx].cmp(&new[new_idx]) {
+            std::cmp::Ordering::Less => {
+                removed.push(old[old_idx]);
+                old_idx += 1;
+            }
+            std::cmp::Ordering::Equal => {
+                old_idx += 1;
+                new_idx += 1;
+            }
+            std::cmp::Ordering::Greater => {
+                added.push(new[new_idx]);
+                new_idx += 1;
+            }
+        }
+    }
+
+    removed.extend_from_slice(&old[old_idx..]);
+    added.extend_from_slice(&new[new_idx..]);
+
+    (removed, added)
+}
+
+/// Rewrite all file paths in the index from `old_root` to `new_root`.
+///
+/// Opens the LMDB environment directly and performs a write transaction
+/// without going through the writer thread. Only safe when no
+/// `PersistentIndex` is active for this `db_path` (no daemon or MCP
+/// server running). Called during worktree copy setup before a daemon starts.
+pub fn rewrite_root_paths(db_path: &Path, old_root: &Path, new_root: &Path) -> IndexResult<()> {
+    let new_norm = normalize_path(new_root);
+    let new_prefix = ensure_trailing_separator(&new_norm);
+
+    if normalize_path_for_prefix(&normalize_path(old_root)) == normalize_path_for_prefix(&new_norm)
+    {
+        return Ok(());
+    }
+
+    let env = open_env(db_path)?;
+    let mut wtxn = env.write_txn()?;
+    let files: FilesDb = env
         .open_database(&wtxn, Some("files"))?
         .ok_or_else(|| IndexError::Db("files db missing".to_string()))?;
     let files_by_path: FilesByPathDb = env
@@ -690,7 +2280,7 @@ pub fn rewrite_root_paths(db_path: &Path, old_root: &Path, new_root: &Path) -> I
         let iter = files.iter(&wtxn)?;
         for entry in iter {
             let (file_id, value) = entry?;
-            let record: FileRecord = decode_bytes(value)?;
+            let record = decode_file_record(value)?;
             if let Some(suffix) = path_suffix_after_root(&record.path, old_root) {
                 let new_path = format!("{new_prefix}{suffix}");
                 updates.push((
@@ -699,6 +2289,7 @@ pub fn rewrite_root_paths(db_path: &Path, old_root: &Path, new_root: &Path) -> I
                     FileRecord {
                         path: new_path,
                         last_modified: record.last_modified,
+                        identity: record.identity,
                     },
                 ));
             }
@@ -716,6 +2307,133 @@ pub fn rewrite_root_paths(db_path: &Path, old_root: &Path, new_root: &Path) -> I
     Ok(())
 }
 
+/// Build a portable snapshot of the index at `db_path`. Paths are stored
+/// relative to `root`; files outside it are left out.
+pub fn export_snapshot(db_path: &Path, root: &Path) -> IndexResult<IndexSnapshot> {
+    let (env, dbs) = open_readonly_env(db_path)?;
+    let rtxn = env.read_txn()?;
+    let tokenizer = stored_tokenizer(&rtxn, &dbs)?.id().to_string();
+    let git_head = dbs.meta.get(&rtxn, "git_head")?.map(str::to_string);
+
+    let mut files = Vec::new();
+    let mut exported_ids = RoaringBitmap::new();
+    for entry in dbs.files.iter(&rtxn)? {
+        let (file_id, value) = entry?;
+        let record = decode_file_record(value)?;
+        let Some(suffix) = path_suffix_after_root(&record.path, root) else {
+            continue;
+        };
+        files.push(SnapshotFile {
+            file_id,
+            path: suffix.replace(std::path::MAIN_SEPARATOR, "/"),
+            last_modified: record.last_modified,
+        });
+        exported_ids.insert(file_id);
+    }
+
+    // Shards of one token are adjacent in key order; merge them.
+    let mut trigrams: Vec<SnapshotTrigram> = Vec::new();
+    for entry in dbs.trigrams.iter(&rtxn)? {
+        let (key, value) = entry?;
+        if key.len() == POSTING_COUNT_KEY_LEN {
+            continue;
+        }
+        let Some(Ok(token)) = key.get(..3).map(Token::try_from) else {
+            continue;
+        };
+        let mut file_ids = decode_bitmap(value)?;
+        file_ids &= &exported_ids;
+        if file_ids.is_empty() {
+            continue;
+        }
+        match trigrams.last_mut() {
+            Some(last) if last.token == token => last.file_ids |= file_ids,
+            _ => trigrams.push(SnapshotTrigram { token, file_ids }),
+        }
+    }
+    drop(rtxn);
+
+    Ok(IndexSnapshot {
+        tokenizer,
+        git_head,
+        files,
+        trigrams,
+    })
+}
+
+/// Where a rebuild of the index at `db_path` is built before
+/// [`replace_index`] moves it into place.
+pub fn staging_db_path(db_path: &Path) -> PathBuf {
+    sibling_db_path(db_path, "tmp")
+}
+
+fn sibling_db_path(db_path: &Path, suffix: &str) -> PathBuf {
+    let mut name = db_path.file_name().unwrap_or_default().to_os_string();
+    name.push(".");
+    name.push(suffix);
+    db_path.with_file_name(name)
+}
+
+/// Move the index built at `staging` to `db_path`, replacing the index
+/// there. Processes that have the old index open keep searching it
+/// undisturbed; it is marked so they know to reopen `db_path` (see
+/// [`PersistentIndex::is_replaced`]). On Windows, open handles block the
+/// move, so stop them first.
+pub fn replace_index(db_path: &Path, staging: &Path) -> IndexResult<()> {
+    // heed keeps every env it opens until the process closes it, keyed by
+    // path, so both are closed here: later opens of either path in this
+    // process must map the files found there after the move.
+    if let Ok(env) = open_env(staging) {
+        env.prepare_for_closing();
+    }
+    if !db_path.exists() {
+        std::fs::rename(staging, db_path)?;
+        return Ok(());
+    }
+
+    let (old_env, old_dbs) = open_readonly_env(db_path)?;
+    let mut wtxn = old_env.write_txn()?;
+    old_dbs.meta.put(&mut wtxn, REPLACED_META_KEY, "true")?;
+    wtxn.commit()?;
+
+    // Directories cannot be renamed over each other, so the old index is
+    // moved aside first, leaving `db_path` missing for an instant.
+    let retired = sibling_db_path(db_path, "old");
+    let _ = std::fs::remove_dir_all(&retired);
+    // A failed swap leaves the old index in place, so no longer replaced.
+    let unmark = |err: std::io::Error| -> IndexError {
+        let unmarked = old_env.write_txn().and_then(|mut wtxn| {
+            old_dbs.meta.delete(&mut wtxn, REPLACED_META_KEY)?;
+            wtxn.commit()
+        });
+        if let Err(unmark_err) = unmarked {
+            error!(error = %unmark_err, "failed to unmark the index a failed rebuild kept");
+        }
+        err.into()
+    };
+    if let Err(err) = std::fs::rename(db_path, &retired) {
+        return Err(unmark(err));
+    }
+    if let Err(err) = std::fs::rename(staging, db_path) {
+        let _ = std::fs::rename(&retired, db_path);
+        return Err(unmark(err));
+    }
+    old_env.prepare_for_closing();
+    let _ = std::fs::remove_dir_all(&retired);
+    info!(db = %db_path.display(), "rebuilt index swapped in");
+    Ok(())
+}
+
+/// The paths stored under [`INCLUDE_PATHS_META_KEY`].
+pub fn parse_include_paths(value: &str) -> Vec<PathBuf> {
+    value
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty())
+        .map(PathBuf::from)
+        .collect()
+}
+
 pub fn read_meta_readonly(db_path: &Path, key: &str) -> IndexResult<Option<String>> {
     let (env, dbs) = open_readonly_env(db_path)?;
     let rtxn = env.read_txn()?;
@@ -749,37 +2467,149 @@ pub fn is_leader_active_readonly(db_path: &Path) -> IndexResult<bool> {
 
 impl FileIdState {
     /// Returns (file_id, is_new). `is_new` is true if this file_id was just created.
-    fn get_or_create_file_id(&mut self, path: &str) -> IndexResult<(u32, bool)> {
-        if let Some(&id) = self.file_ids.get(path) {
-            return Ok((id, false));
-        }
-        let file_id = self.next_file_id;
-        self.next_file_id = self
-            .next_file_id
-            .checked_add(1)
-            .ok_or_else(|| IndexError::Encode("file ID space exhausted (u32::MAX)".to_string()))?;
-        self.file_ids.insert(path.to_string(), file_id);
+    fn get_or_create_file_id(
+        &mut self,
+        dbs: &DbHandles,
+        wtxn: &mut RwTxn,
+        path: &str,
+    ) -> IndexResult<(u32, bool)> {
+        if let Some(file_id) = self.lookup(dbs, wtxn, path)? {
+            return Ok((file_id, false));
+        }
+        let file_id = match dbs
+            .meta
+            .get(wtxn, NEXT_FILE_ID_META_KEY)?
+            .and_then(|value| value.parse().ok())
+        {
+            Some(next) => next,
+            None => max_file_id(dbs, wtxn)?.map_or(0, |max| max.saturating_add(1)),
+        };
+        let next = file_id.checked_add(1).ok_or_else(|| {
+            IndexError::Unsupported("file ID space exhausted (u32::MAX)".to_string())
+        })?;
+        put_next_file_id(dbs, wtxn, next)?;
+        self.remember(path, file_id);
         Ok((file_id, true))
     }
 
-    fn remove_file_id(&mut self, path: &str) -> Option<u32> {
-        self.file_ids.remove(path)
+    fn remove_file_id(
+        &mut self,
+        dbs: &DbHandles,
+        wtxn: &RwTxn,
+        path: &str,
+    ) -> IndexResult<Option<u32>> {
+        let file_id = self.lookup(dbs, wtxn, path)?;
+        self.cache.remove(path);
+        Ok(file_id)
+    }
+
+    fn lookup(&mut self, dbs: &DbHandles, wtxn: &RwTxn, path: &str) -> IndexResult<Option<u32>> {
+        if let Some(&file_id) = self.cache.get(path) {
+            return Ok(Some(file_id));
+        }
+        let file_id = dbs.files_by_path.get(wtxn, path)?;
+        if let Some(file_id) = file_id {
+            self.remember(path, file_id);
+        }
+        Ok(file_id)
+    }
+
+    fn remember(&mut self, path: &str, file_id: u32) {
+        if self.cache.len() >= FILE_ID_CACHE_CAPACITY {
+            self.cache.clear();
+        }
+        self.cache.insert(path.to_string(), file_id);
+    }
+}
+
+fn put_next_file_id(dbs: &DbHandles, wtxn: &mut RwTxn, next: u32) -> IndexResult<()> {
+    dbs.meta
+        .put(wtxn, NEXT_FILE_ID_META_KEY, &next.to_string())?;
+    Ok(())
+}
+
+/// Largest file id in use, for indexes without [`NEXT_FILE_ID_META_KEY`].
+/// Keys are native-endian, so LMDB's byte order is not numeric order and
+/// every key has to be visited.
+fn max_file_id(dbs: &DbHandles, rtxn: &RoTxn) -> IndexResult<Option<u32>> {
+    let mut max = None;
+    for entry in dbs.files.remap_data_type::<DecodeIgnore>().iter(rtxn)? {
+        let (file_id, ()) = entry?;
+        max = max.max(Some(file_id));
     }
+    Ok(max)
 }
 
+/// How long opening an index waits for a rebuild to finish moving it into
+/// place, or for this process's handles on the index it replaced to close.
+const REPLACE_WAIT: Duration = Duration::from_secs(2);
+
+/// Open the index at `path`, never the one [`replace_index`] moved away.
 fn open_env(path: &Path) -> IndexResult<Env> {
+    wait_for_swap(path);
+    let env = open_env_once(path)?;
+    // heed hands out the env it opened before for as long as this process
+    // has it cached, even once a rebuild swapped the index or its files
+    // were deleted; close it so the files now at `path` are mapped.
+    let removed = !path.join("data.mdb").exists();
+    if !removed && !is_retired(&env)? {
+        return Ok(env);
+    }
+    debug!(db = %path.display(), removed, "reopening an index whose files were replaced");
+    env.prepare_for_closing().wait_timeout(REPLACE_WAIT);
+    open_env_once(path)
+}
+
+/// Wait out the instant [`replace_index`] leaves `path` missing.
+fn wait_for_swap(path: &Path) {
+    let retired = sibling_db_path(path, "old");
+    let deadline = Instant::now() + REPLACE_WAIT;
+    while !path.exists() && retired.exists() && Instant::now() < deadline {
+        thread::sleep(Duration::from_millis(10));
+    }
+}
+
+/// Whether `env` is an index [`replace_index`] has moved aside.
+fn is_retired(env: &Env) -> IndexResult<bool> {
+    let rtxn = env.read_txn()?;
+    let Some(meta) = env.open_database::<Str, Str>(&rtxn, Some("meta"))? else {
+        return Ok(false);
+    };
+    Ok(meta.get(&rtxn, REPLACED_META_KEY)? == Some("true"))
+}
+
+fn open_env_once(path: &Path) -> IndexResult<Env> {
     let map_size = map_size_for_path(path);
-    unsafe {
-        Ok(EnvOpenOptions::new()
+    let open = || unsafe {
+        EnvOpenOptions::new()
             .max_dbs(MAX_DBS)
             .map_size(map_size)
             // WRITE_MAP: use writable mmap instead of write() syscalls,
             // letting the OS handle page flushing.
             // NO_META_SYNC: skip fsync of meta page on commit — only the
             // last txn can be lost on OS crash. The index is rebuildable
-            // so this is a safe durability trade-off.
-            .flags(heed::EnvFlags::WRITE_MAP | heed::EnvFlags::NO_META_SYNC)
-            .open(path)?)
+            // so this is a safe durability trade-off; `[writer] sync`
+            // changes it (see `PersistentIndex::set_sync_mode`).
+            .flags(EnvFlags::WRITE_MAP | EnvFlags::NO_META_SYNC)
+            .open(path)
+    };
+    let opened = match open() {
+        // Another handle of this process is closing the env (see
+        // `open_env`); the path can be opened again once it has.
+        Err(heed::Error::DatabaseClosing) => {
+            if let Some(closing) = path.canonicalize().ok().and_then(heed::env_closing_event) {
+                closing.wait_timeout(REPLACE_WAIT);
+            }
+            open()
+        }
+        other => other,
+    };
+    match opened {
+        // heed keeps one env per path per process; long-lived processes
+        // (daemon, HTTP server) reopen the same index after it has grown,
+        // so the computed map size differs. Reuse the existing env.
+        Err(heed::Error::BadOpenOptions { env, .. }) => Ok(env),
+        other => Ok(other?),
     }
 }
 
@@ -818,7 +2648,9 @@ fn create_databases(env: &Env) -> IndexResult<DbHandles> {
         files: env.create_database(&mut wtxn, Some("files"))?,
         files_by_path: env.create_database(&mut wtxn, Some("files_by_path"))?,
         trigrams: env.create_database(&mut wtxn, Some("trigrams"))?,
-        file_trigrams: env.create_database(&mut wtxn, Some("file_trigrams"))?,
+        file_tokens: env.create_database(&mut wtxn, Some("file_tokens"))?,
+        contents: env.create_database(&mut wtxn, Some("contents"))?,
+        token_sets: env.create_database(&mut wtxn, Some("token_sets"))?,
         meta: env.create_database(&mut wtxn, Some("meta"))?,
         leader: env.create_database(&mut wtxn, Some("leader"))?,
     };
@@ -826,22 +2658,6 @@ fn create_databases(env: &Env) -> IndexResult<DbHandles> {
     Ok(dbs)
 }
 
-fn load_file_id_state(env: &Env, dbs: &DbHandles) -> IndexResult<FileIdState> {
-    let rtxn = env.read_txn()?;
-    let mut file_ids = HashMap::new();
-    let mut max_id = 0u32;
-    for entry in dbs.files_by_path.iter(&rtxn)? {
-        let (path, file_id) = entry?;
-        max_id = max_id.max(file_id);
-        file_ids.insert(path.to_string(), file_id);
-    }
-    drop(rtxn);
-    Ok(FileIdState {
-        file_ids,
-        next_file_id: max_id.saturating_add(1),
-    })
-}
-
 /// Open the LMDB environment for read-only access. Only read transactions
 /// should be created on this env. In cross-process scenarios (CLI reading
 /// while daemon writes), LMDB handles concurrent access via MVCC.
@@ -851,8 +2667,8 @@ pub fn open_readonly_env(path: &Path) -> IndexResult<(Env, DbHandles)> {
     // time in a given env handle (mdb_dbi_open with named DBs needs MDB_CREATE
     // or at least a write txn). We open with a write txn, then only use read
     // txns afterwards. This is safe for cross-process access because the write
-    // txn is brief (no actual data is written) and LMDB serializes it.
-    let wtxn = env.write_txn()?;
+    // txn is brief (at most it creates an empty table) and LMDB serializes it.
+    let mut wtxn = env.write_txn()?;
     let dbs = DbHandles {
         files: env
             .open_database(&wtxn, Some("files"))?
@@ -863,9 +2679,10 @@ pub fn open_readonly_env(path: &Path) -> IndexResult<(Env, DbHandles)> {
         trigrams: env
             .open_database(&wtxn, Some("trigrams"))?
             .ok_or_else(|| IndexError::Db("index not initialized".to_string()))?,
-        file_trigrams: env
-            .open_database(&wtxn, Some("file_trigrams"))?
-            .ok_or_else(|| IndexError::Db("index not initialized".to_string()))?,
+        // Indexes written by older versions lack these.
+        file_tokens: env.create_database(&mut wtxn, Some("file_tokens"))?,
+        contents: env.create_database(&mut wtxn, Some("contents"))?,
+        token_sets: env.create_database(&mut wtxn, Some("token_sets"))?,
         meta: env
             .open_database(&wtxn, Some("meta"))?
             .ok_or_else(|| IndexError::Db("index not initialized".to_string()))?,
@@ -877,45 +2694,162 @@ pub fn open_readonly_env(path: &Path) -> IndexResult<(Env, DbHandles)> {
     Ok((env, dbs))
 }
 
-fn writer_loop(
-    mut storage: LmdbStorage,
-    rx: mpsc::Receiver<IndexJob>,
-    write_enabled: Arc<AtomicBool>,
-) {
-    loop {
-        let first = match rx.recv() {
-            Ok(job) => job,
-            Err(_) => {
-                debug!("writer_loop sender dropped, exiting");
-                break;
-            }
-        };
+/// Searches in flight and when the last one ended, shared between the
+/// search path and the writer thread.
+struct SearchActivity {
+    in_flight: AtomicUsize,
+    last_ms: AtomicU64,
+}
 
-        let mut batch = Vec::with_capacity(4096);
-        let mut batch_bytes = first.payload.estimated_bytes();
-        batch.push(first);
+impl SearchActivity {
+    const fn new() -> Self {
+        Self {
+            in_flight: AtomicUsize::new(0),
+            last_ms: AtomicU64::new(0),
+        }
+    }
 
-        while batch_bytes < BATCH_MEMORY_LIMIT {
-            match rx.try_recv() {
-                Ok(job) => {
-                    batch_bytes += job.payload.estimated_bytes();
-                    batch.push(job);
-                }
-                Err(mpsc::TryRecvError::Empty) => break,
-                Err(mpsc::TryRecvError::Disconnected) => {
+    /// Count a search as running until the guard is dropped.
+    fn begin(&'static self) -> SearchGuard {
+        self.in_flight.fetch_add(1, Ordering::SeqCst);
+        SearchGuard(self)
+    }
+
+    /// Whether a search is running or ended within [`SEARCH_QUIET_PERIOD`].
+    fn contended(&self) -> bool {
+        let quiet_ms = SEARCH_QUIET_PERIOD.as_millis() as u64;
+        self.in_flight.load(Ordering::SeqCst) > 0
+            || now_ms().saturating_sub(self.last_ms.load(Ordering::SeqCst)) < quiet_ms
+    }
+}
+
+/// See [`PersistentIndex::pause_writes`].
+struct WritesPaused<'a> {
+    write_enabled: &'a AtomicBool,
+    was_enabled: bool,
+}
+
+impl Drop for WritesPaused<'_> {
+    fn drop(&mut self) {
+        self.write_enabled.store(self.was_enabled, Ordering::SeqCst);
+    }
+}
+
+struct SearchGuard(&'static SearchActivity);
+
+impl Drop for SearchGuard {
+    fn drop(&mut self) {
+        self.0.last_ms.store(now_ms(), Ordering::SeqCst);
+        self.0.in_flight.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+fn now_ms() -> u64 {
+    now_millis().max(0) as u64
+}
+
+fn writer_loop(
+    mut storage: LmdbStorage,
+    rx: mpsc::Receiver<WriterMsg>,
+    priority: PriorityLane,
+    write_enabled: Arc<AtomicBool>,
+    idle_commit_ms: Arc<AtomicU64>,
+) {
+    loop {
+        let first = match rx.recv() {
+            Ok(msg) => msg,
+            Err(_) => {
+                debug!("writer_loop sender dropped, exiting");
+                break;
+            }
+        };
+
+        let mut batch = Batch::default();
+        batch.take(first, &priority);
+        if batch.jobs.is_empty() {
+            // The priority jobs this wake-up was for went out with an
+            // earlier batch.
+            continue;
+        }
+
+        let opened = Instant::now();
+        let idle = Duration::from_millis(idle_commit_ms.load(Ordering::SeqCst));
+        let contended = SEARCH_ACTIVITY.contended();
+        let limit = if contended {
+            CONTENDED_BATCH_MEMORY_LIMIT
+        } else {
+            BATCH_MEMORY_LIMIT
+        };
+        while batch.bytes < limit {
+            let msg = match rx.try_recv() {
+                Ok(msg) => msg,
+                Err(mpsc::TryRecvError::Empty) => {
+                    // Keep the batch open while changes keep arriving, unless
+                    // someone is waiting for it to commit.
+                    let remaining = MAX_BATCH_DELAY.saturating_sub(opened.elapsed());
+                    if batch.waited_on || idle.is_zero() || remaining.is_zero() {
+                        break;
+                    }
+                    match rx.recv_timeout(idle.min(remaining)) {
+                        Ok(msg) => msg,
+                        Err(_) => break,
+                    }
+                }
+                Err(mpsc::TryRecvError::Disconnected) => {
                     debug!(
                         "writer_loop channel disconnected while draining, processing remaining batch"
                     );
                     break;
                 }
-            }
+            };
+            batch.take(msg, &priority);
+        }
+
+        debug!(
+            batch_len = batch.jobs.len(),
+            contended, "writer_loop processing batch"
+        );
+        process_batch(&mut storage, batch.jobs, &write_enabled);
+    }
+}
+
+/// Jobs the writer has taken for its next commit.
+#[derive(Default)]
+struct Batch {
+    jobs: Vec<IndexJob>,
+    bytes: usize,
+    waited_on: bool,
+}
+
+impl Batch {
+    /// Add the waiting priority jobs, then the job carried by `msg`.
+    fn take(&mut self, msg: WriterMsg, priority: &Mutex<VecDeque<IndexJob>>) {
+        let urgent: Vec<IndexJob> = priority
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+            .drain(..)
+            .collect();
+        for job in urgent {
+            self.push(job);
+        }
+        if let WriterMsg::Job(job) = msg {
+            self.push(job);
         }
+    }
 
-        debug!(batch_len = batch.len(), "writer_loop processing batch");
-        process_batch(&mut storage, batch, &write_enabled);
+    fn push(&mut self, job: IndexJob) {
+        METRICS.job_dequeued();
+        self.waited_on |= waits_for_commit(&job.payload);
+        self.bytes += job.payload.estimated_bytes();
+        self.jobs.push(job);
     }
 }
 
+/// Jobs whose sender blocks until the batch holding them is committed.
+fn waits_for_commit(payload: &IndexPayload) -> bool {
+    matches!(payload, IndexPayload::Flush | IndexPayload::ReloadIds)
+}
+
 fn process_batch(storage: &mut LmdbStorage, batch: Vec<IndexJob>, write_enabled: &AtomicBool) {
     process_batch_inner(storage, batch, write_enabled, true);
 }
@@ -935,48 +2869,87 @@ fn process_batch_inner(
         return;
     }
 
-    let mut wtxn = match storage.env.write_txn() {
+    let _span = debug_span!("index_batch", jobs = batch.len()).entered();
+    let started = Instant::now();
+    // A clone so the transaction does not borrow `storage`, which a failed
+    // batch needs mutably.
+    let env = storage.env.clone();
+    let mut wtxn = match env.write_txn() {
         Ok(wtxn) => wtxn,
         Err(err) => {
             error!(error = %err, "failed to begin write transaction");
-            broadcast_batch_error(batch, IndexError::Db(err.to_string()));
+            fail_batch(storage, batch, IndexError::from(err));
             return;
         }
     };
+    // Checked in the transaction that commits: a writer whose lease was
+    // taken over must not write alongside its successor.
+    let writes = batch
+        .iter()
+        .any(|job| !matches!(job.payload, Flush | ReloadIds));
+    if writes && let Err(err) = check_lease(&storage.dbs, &wtxn, &storage.lease_holder) {
+        drop(wtxn);
+        error!(error = %err, "index batch refused");
+        fail_batch(storage, batch, err);
+        return;
+    }
+
+    let current = read_generation(&wtxn, &storage.dbs).ok();
+    if current.is_none() || storage.ids.generation != current {
+        storage.ids.cache.clear();
+    }
 
     let ids = &mut storage.ids;
     let dbs = &storage.dbs;
+    let mut postings = PostingEdits::default();
     let mut batch_error: Option<IndexError> = None;
     let mut upserts = 0usize;
     let mut removes = 0usize;
     let mut flushes = 0usize;
+    let mut tokens = 0usize;
 
     for job in &batch {
         match &job.payload {
-            UpsertFile {
-                path,
-                modified_ts,
-                trigrams,
-            } => {
+            UpsertFile(update) => {
                 upserts += 1;
-                if let Err(err) = upsert_file(ids, dbs, &mut wtxn, path, *modified_ts, trigrams) {
+                tokens += update.trigrams.len();
+                if let Err(err) = upsert_file(ids, dbs, &mut wtxn, &mut postings, update) {
                     batch_error = Some(err);
                     break;
                 }
             }
             RemoveFile { path } => {
                 removes += 1;
-                if let Err(err) = remove_file(ids, dbs, &mut wtxn, path) {
+                if let Err(err) = stored_spelling(dbs, &wtxn, path)
+                    .and_then(|path| remove_file(ids, dbs, &mut wtxn, &mut postings, &path))
+                {
                     batch_error = Some(err);
                     break;
                 }
             }
+            RemovePrefix { path } => {
+                match stored_spelling(dbs, &wtxn, path)
+                    .and_then(|path| remove_prefix(ids, dbs, &mut wtxn, &mut postings, &path))
+                {
+                    Ok(removed) => removes += removed,
+                    Err(err) => {
+                        batch_error = Some(err);
+                        break;
+                    }
+                }
+            }
             SetMeta { key, value } => {
                 if let Err(err) = dbs.meta.put(&mut wtxn, key.as_str(), value.as_str()) {
                     batch_error = Some(IndexError::from(err));
                     break;
                 }
             }
+            RecordRenames { renames } => {
+                if let Err(err) = append_renames(dbs, &mut wtxn, renames) {
+                    batch_error = Some(err);
+                    break;
+                }
+            }
             Flush => {
                 flushes += 1;
             }
@@ -986,10 +2959,25 @@ fn process_batch_inner(
         }
     }
 
+    if batch_error.is_none()
+        && let Err(err) = postings.apply(dbs, &mut wtxn)
+    {
+        batch_error = Some(err);
+    }
+    if batch_error.is_none()
+        && upserts + removes > 0
+        && let Err(err) = bump_generation(dbs, &mut wtxn)
+    {
+        batch_error = Some(err);
+    }
+
     debug!(upserts, removes, flushes, "process_batch finished");
+    let generation = read_generation(&wtxn, dbs).ok();
 
     if let Some(err) = batch_error {
         drop(wtxn);
+        // The cache may name rows the aborted transaction created.
+        storage.ids.cache.clear();
         if matches!(err, IndexError::MapFull) && allow_resize {
             match resize_env_for_map_full(&storage.env) {
                 Ok(()) => {
@@ -998,17 +2986,18 @@ fn process_batch_inner(
                 }
                 Err(resize_err) => {
                     error!(error = %resize_err, "failed to resize LMDB map after batch map-full error");
-                    broadcast_batch_error(batch, resize_err);
+                    fail_batch(storage, batch, resize_err);
                     return;
                 }
             }
         }
         error!(error = %err, "index batch failed before commit");
-        broadcast_batch_error(batch, err);
+        fail_batch(storage, batch, err);
         return;
     }
 
     if let Err(err) = wtxn.commit() {
+        storage.ids.cache.clear();
         if matches!(err, heed::Error::Mdb(heed::MdbError::MapFull)) && allow_resize {
             match resize_env_for_map_full(&storage.env) {
                 Ok(()) => {
@@ -1017,39 +3006,57 @@ fn process_batch_inner(
                 }
                 Err(resize_err) => {
                     error!(error = %resize_err, "failed to resize LMDB map after commit map-full error");
-                    broadcast_batch_error(batch, resize_err);
+                    fail_batch(storage, batch, resize_err);
                     return;
                 }
             }
         }
         error!(error = %err, "failed to commit index batch");
-        broadcast_batch_error(batch, IndexError::Db(err.to_string()));
+        fail_batch(storage, batch, IndexError::from(err));
         return;
     }
 
-    debug!("process_batch commit succeeded");
+    METRICS.record_batch(
+        upserts as u64,
+        removes as u64,
+        tokens as u64,
+        started.elapsed(),
+    );
+    debug!(
+        elapsed_ms = started.elapsed().as_millis() as u64,
+        "process_batch commit succeeded"
+    );
+    storage.ids.generation = generation;
 
-    // Check if any job requested a FileIdState reload (after bulk_cold_index_direct).
-    let needs_reload = batch.iter().any(|j| matches!(j.payload, ReloadIds));
-    if needs_reload {
-        match load_file_id_state(&storage.env, &storage.dbs) {
-            Ok(ids) => {
-                storage.ids = ids;
-                debug!("ReloadIds: file ID state reloaded");
-            }
-            Err(err) => error!(error = %err, "ReloadIds: failed to reload file ID state"),
-        }
+    // Ids were rewritten behind the writer's back (bulk load, import, clear).
+    if batch.iter().any(|j| matches!(j.payload, ReloadIds)) {
+        storage.ids = FileIdState::default();
+        debug!("ReloadIds: file ID state reset");
     }
 
     for job in batch {
-        let _ = job.resp.send(Ok(()));
+        let result = match job.payload {
+            Flush => storage.first_error.take().map_or(Ok(()), Err),
+            _ => Ok(()),
+        };
+        let _ = job.resp.send(result);
     }
 }
 
-fn broadcast_batch_error(batch: Vec<IndexJob>, err: IndexError) {
+/// Fail every job of `batch` with `err`. A flush reports the first error
+/// since the previous flush instead; without one, `err` is kept for the next.
+fn fail_batch(storage: &mut LmdbStorage, batch: Vec<IndexJob>, err: IndexError) {
     let msg = err.to_string();
+    storage.first_error.get_or_insert(err);
     for job in batch {
-        let _ = job.resp.send(Err(IndexError::Db(msg.clone())));
+        let err = match job.payload {
+            IndexPayload::Flush => storage
+                .first_error
+                .take()
+                .unwrap_or_else(|| IndexError::Db(msg.clone())),
+            _ => IndexError::Db(msg.clone()),
+        };
+        let _ = job.resp.send(Err(err));
     }
 }
 
@@ -1057,37 +3064,40 @@ fn upsert_file(
     ids: &mut FileIdState,
     dbs: &DbHandles,
     wtxn: &mut RwTxn,
-    path: &str,
-    modified_ts: u64,
-    trigrams: &[[u8; 3]],
+    postings: &mut PostingEdits,
+    update: &FileUpdate,
 ) -> IndexResult<()> {
-    let (file_id, is_new) = ids.get_or_create_file_id(path)?;
+    let FileUpdate {
+        path,
+        modified_ts,
+        identity,
+        trigrams,
+        content_hash,
+        content,
+    } = update;
+    let (path, modified_ts, content) = (path.as_str(), *modified_ts, content.as_deref());
+    let stored = stored_spelling(dbs, wtxn, path)?;
+    let (file_id, is_new) = ids.get_or_create_file_id(dbs, wtxn, &stored)?;
 
     // ---- Fast path: brand-new file, skip all LMDB reads ----
     if is_new {
         let record = FileRecord {
             path: path.to_string(),
             last_modified: modified_ts,
+            identity: *identity,
         };
         let encoded = encode_bytes(&record)?;
         dbs.files.put(wtxn, &file_id, &encoded)?;
         dbs.files_by_path.put(wtxn, path, &file_id)?;
 
-        let encoded_trigrams = encode_bytes(trigrams)?;
-        dbs.file_trigrams.put(wtxn, &file_id, &encoded_trigrams)?;
+        put_file_tokens(dbs, wtxn, file_id, trigrams, content_hash)?;
+        if let Some(content) = content {
+            dbs.contents.put(wtxn, &file_id, content)?;
+        }
 
-        // All trigrams are new — insert file_id into each bitmap.
-        // Read existing bitmap (may exist from other files) and add.
+        // All trigrams are new — insert file_id into each posting list.
         for trigram in trigrams {
-            let mut bitmap = dbs
-                .trigrams
-                .get(wtxn, &trigram[..])?
-                .map(decode_bytes::<RoaringBitmap>)
-                .transpose()?
-                .unwrap_or_default();
-            bitmap.insert(file_id);
-            let encoded = encode_bytes(&bitmap)?;
-            dbs.trigrams.put(wtxn, &trigram[..], &encoded)?;
+            postings.add(trigram, file_id);
         }
 
         return Ok(());
@@ -1097,11 +3107,16 @@ fn upsert_file(
     let existing_record = dbs
         .files
         .get(wtxn, &file_id)?
-        .map(decode_bytes::<FileRecord>)
+        .map(decode_file_record)
         .transpose()?;
 
+    // Timestamps have one-second resolution, so a rewrite within the same
+    // second is only skipped when the content is unchanged too.
     if let Some(existing_record) = &existing_record
-        && existing_record.last_modified >= modified_ts
+        && (existing_record.last_modified > modified_ts
+            || (existing_record.last_modified == modified_ts
+                && dbs.file_tokens.get(wtxn, &file_id)?
+                    == Some(&shared_token_ref(content_hash)[..])))
     {
         return Ok(());
     }
@@ -1112,21 +3127,26 @@ fn upsert_file(
         let _ = dbs
             .files_by_path
             .delete(wtxn, existing_record.path.as_str())?;
+        ids.cache.remove(existing_record.path.as_str());
+        ids.remember(path, file_id);
     }
 
     let record = FileRecord {
         path: path.to_string(),
         last_modified: modified_ts,
+        identity: *identity,
     };
     let encoded = encode_bytes(&record)?;
     dbs.files.put(wtxn, &file_id, &encoded)?;
     dbs.files_by_path.put(wtxn, path, &file_id)?;
+    match content {
+        Some(content) => dbs.contents.put(wtxn, &file_id, content)?,
+        None => {
+            let _ = dbs.contents.delete(wtxn, &file_id)?;
+        }
+    }
 
-    let old_trigrams = dbs
-        .file_trigrams
-        .get(wtxn, &file_id)?
-        .map(decode_bytes::<Vec<[u8; 3]>>)
-        .transpose()?;
+    let old_trigrams = read_file_tokens(wtxn, dbs, file_id)?;
 
     let (removed_trigrams, added_trigrams, needs_write) = match old_trigrams {
         Some(old_trigrams) => {
@@ -1137,70 +3157,159 @@ fn upsert_file(
         None => (Vec::new(), trigrams.to_vec(), true),
     };
 
-    for trigram in removed_trigrams {
-        if let Some(blob) = dbs.trigrams.get(wtxn, &trigram[..])? {
-            let mut bitmap: RoaringBitmap = decode_bytes(blob)?;
-            bitmap.remove(file_id);
-            if bitmap.is_empty() {
-                let _ = dbs.trigrams.delete(wtxn, &trigram[..])?;
-            } else {
-                let encoded = encode_bytes(&bitmap)?;
-                dbs.trigrams.put(wtxn, &trigram[..], &encoded)?;
-            }
-        }
+    for trigram in &removed_trigrams {
+        postings.remove(trigram, file_id);
     }
 
-    if needs_write {
-        let encoded = encode_bytes(trigrams)?;
-        dbs.file_trigrams.put(wtxn, &file_id, &encoded)?;
+    if needs_write
+        || dbs.file_tokens.get(wtxn, &file_id)? != Some(&shared_token_ref(content_hash)[..])
+    {
+        put_file_tokens(dbs, wtxn, file_id, trigrams, content_hash)?;
     }
 
-    for trigram in added_trigrams {
-        let mut bitmap = dbs
-            .trigrams
-            .get(wtxn, &trigram[..])?
-            .map(decode_bytes::<RoaringBitmap>)
-            .transpose()?
-            .unwrap_or_default();
-        bitmap.insert(file_id);
-        let encoded = encode_bytes(&bitmap)?;
-        dbs.trigrams.put(wtxn, &trigram[..], &encoded)?;
+    for trigram in &added_trigrams {
+        postings.add(trigram, file_id);
     }
 
     Ok(())
 }
 
+/// The spelling `path` is indexed under. Where [`CASE_INSENSITIVE_PATHS`],
+/// the watcher may report `src/main.rs` as `src/Main.rs`, or a file
+/// renamed only in case under its new name. [`normalize_path`] resolves
+/// the directories of a path on disk, so only its last component is
+/// looked up among the indexed entries of its directory. Paths indexed
+/// as they are, or not at all, are returned unchanged.
+fn stored_spelling<'a>(dbs: &DbHandles, rtxn: &RoTxn, path: &'a str) -> IndexResult<Cow<'a, str>> {
+    if !CASE_INSENSITIVE_PATHS || path_is_indexed(rtxn, dbs, path)? {
+        return Ok(Cow::Borrowed(path));
+    }
+    let Some(split) = path.rfind(std::path::MAIN_SEPARATOR) else {
+        return Ok(Cow::Borrowed(path));
+    };
+    let (dir, name) = path.split_at(split + 1);
+    let Some(first) = name.chars().next() else {
+        return Ok(Cow::Borrowed(path));
+    };
+    let wanted = fold_path(name);
+    // Keys are sorted bytewise, so each case of the first letter is its
+    // own range.
+    let mut initials: Vec<String> = vec![
+        first.to_string(),
+        first.to_lowercase().collect(),
+        first.to_uppercase().collect(),
+    ];
+    initials.sort();
+    initials.dedup();
+    for initial in initials {
+        for entry in dbs
+            .files_by_path
+            .prefix_iter(rtxn, &format!("{dir}{initial}"))?
+        {
+            let (indexed, _file_id) = entry?;
+            let rest = &indexed[dir.len()..];
+            let component = rest
+                .split(std::path::MAIN_SEPARATOR)
+                .next()
+                .and_then(|component| component.split(ARCHIVE_SEPARATOR).next())
+                .unwrap_or(rest);
+            if fold_path(component) == wanted {
+                return Ok(Cow::Owned(format!("{dir}{component}")));
+            }
+        }
+    }
+    Ok(Cow::Borrowed(path))
+}
+
+/// Remove `path` and, if it is an archive, every member indexed under it.
 fn remove_file(
     ids: &mut FileIdState,
     dbs: &DbHandles,
     wtxn: &mut RwTxn,
+    postings: &mut PostingEdits,
+    path: &str,
+) -> IndexResult<()> {
+    remove_single_file(ids, dbs, wtxn, postings, path)?;
+
+    let prefix = format!("{path}{ARCHIVE_SEPARATOR}");
+    let mut members = Vec::new();
+    for entry in dbs.files_by_path.prefix_iter(wtxn, &prefix)? {
+        let (member, _file_id) = entry?;
+        members.push(member.to_string());
+    }
+    for member in members {
+        remove_single_file(ids, dbs, wtxn, postings, &member)?;
+    }
+    Ok(())
+}
+
+fn remove_single_file(
+    ids: &mut FileIdState,
+    dbs: &DbHandles,
+    wtxn: &mut RwTxn,
+    postings: &mut PostingEdits,
     path: &str,
 ) -> IndexResult<()> {
-    let Some(file_id) = ids.remove_file_id(path) else {
+    let Some(file_id) = ids.remove_file_id(dbs, wtxn, path)? else {
         return Ok(());
     };
 
-    let old_trigrams = dbs
-        .file_trigrams
-        .get(wtxn, &file_id)?
-        .map(decode_bytes::<Vec<[u8; 3]>>)
-        .transpose()?
-        .unwrap_or_default();
-
-    for trigram in old_trigrams {
-        if let Some(blob) = dbs.trigrams.get(wtxn, &trigram[..])? {
-            let mut bitmap: RoaringBitmap = decode_bytes(blob)?;
-            bitmap.remove(file_id);
-            if bitmap.is_empty() {
-                let _ = dbs.trigrams.delete(wtxn, &trigram[..])?;
-            } else {
-                let encoded = encode_bytes(&bitmap)?;
-                dbs.trigrams.put(wtxn, &trigram[..], &encoded)?;
-            }
+    let old_trigrams = read_file_tokens(wtxn, dbs, file_id)?.unwrap_or_default();
+
+    for trigram in &old_trigrams {
+        postings.remove(trigram, file_id);
+    }
+
+    delete_file_rows(dbs, wtxn, path, file_id)
+}
+
+/// Remove `path`, its archive members and every file under it, returning
+/// how many files were removed.
+fn remove_prefix(
+    ids: &mut FileIdState,
+    dbs: &DbHandles,
+    wtxn: &mut RwTxn,
+    postings: &mut PostingEdits,
+    path: &str,
+) -> IndexResult<usize> {
+    let mut doomed = Vec::new();
+    if let Some(file_id) = dbs.files_by_path.get(wtxn, path)? {
+        doomed.push((path.to_string(), file_id));
+    }
+    for prefix in [
+        format!("{path}{ARCHIVE_SEPARATOR}"),
+        ensure_trailing_separator(path),
+    ] {
+        for entry in dbs.files_by_path.prefix_iter(wtxn, &prefix)? {
+            let (member, file_id) = entry?;
+            doomed.push((member.to_string(), file_id));
+        }
+    }
+    if doomed.is_empty() {
+        return Ok(0);
+    }
+
+    for (_, file_id) in &doomed {
+        for token in read_file_tokens(wtxn, dbs, *file_id)?.unwrap_or_default() {
+            postings.remove(&token, *file_id);
         }
     }
 
-    let _ = dbs.file_trigrams.delete(wtxn, &file_id)?;
+    for (member, file_id) in &doomed {
+        ids.cache.remove(member);
+        delete_file_rows(dbs, wtxn, member, *file_id)?;
+    }
+    Ok(doomed.len())
+}
+
+fn delete_file_rows(
+    dbs: &DbHandles,
+    wtxn: &mut RwTxn,
+    path: &str,
+    file_id: u32,
+) -> IndexResult<()> {
+    delete_file_tokens(dbs, wtxn, file_id)?;
+    let _ = dbs.contents.delete(wtxn, &file_id)?;
     let _ = dbs.files.delete(wtxn, &file_id)?;
     let _ = dbs.files_by_path.delete(wtxn, path)?;
     Ok(())
@@ -1217,126 +3326,1813 @@ fn decode_bytes<T: DeserializeOwned>(bytes: &[u8]) -> IndexResult<T> {
     Ok(value)
 }
 
-pub fn now_millis() -> i64 {
-    use std::time::{SystemTime, UNIX_EPOCH};
+/// Identity of a file's text, under which files with the same content
+/// share one stored token list.
+pub type ContentHash = [u8; 16];
 
-    SystemTime::now()
-        .duration_since(UNIX_EPOCH)
-        .unwrap_or_default()
-        .as_millis()
-        .min(i64::MAX as u128) as i64
+pub fn content_hash(text: &str) -> ContentHash {
+    xxhash_rust::xxh3::xxh3_128(text.as_bytes()).to_le_bytes()
 }
 
-fn search_with_rtxn(
+/// Compress `text` for the content store.
+pub fn compress_content(text: &str) -> IndexResult<Vec<u8>> {
+    Ok(zstd::bulk::compress(text.as_bytes(), ZSTD_LEVEL)?)
+}
+
+/// The content store entry for `text` under `policy`, if it keeps one:
+/// compressed, and sealed when encryption is enabled.
+pub fn stored_content(policy: &ContentPolicy, text: &str) -> IndexResult<Option<Vec<u8>>> {
+    if !policy.stores_content(text.len()) {
+        return Ok(None);
+    }
+    let compressed = compress_content(text)?;
+    match policy.content_key() {
+        Some(key) => Ok(Some(
+            [&[BLOB_TAG, BLOB_SEALED][..], &key.seal(&compressed)?].concat(),
+        )),
+        None => Ok(Some(compressed)),
+    }
+}
+
+/// The stored text of `file_id`. Sealed content needs `key`.
+fn read_stored_content(
     rtxn: &RoTxn,
     dbs: &DbHandles,
-    query: &str,
-    file_regex: Option<&Regex>,
-) -> IndexResult<Vec<SearchHit>> {
-    if query.len() < 3 {
-        return Ok(Vec::new());
-    }
+    file_id: u32,
+    key: Option<&ContentKey>,
+) -> IndexResult<Option<Vec<u8>>> {
+    let Some(stored) = dbs.contents.get(rtxn, &file_id)? else {
+        return Ok(None);
+    };
+    let compressed = match stored {
+        [BLOB_TAG, BLOB_SEALED, sealed @ ..] => {
+            let key = key.ok_or_else(|| {
+                IndexError::Config("stored content is encrypted and no key is configured".into())
+            })?;
+            Cow::Owned(key.open(sealed)?)
+        }
+        _ => Cow::Borrowed(stored),
+    };
+    Ok(Some(zstd::stream::decode_all(&*compressed)?))
+}
 
-    let query_trigrams = collect_trigrams(query);
-    if query_trigrams.is_empty() {
-        return Ok(Vec::new());
+fn read_generation(rtxn: &RoTxn, dbs: &DbHandles) -> IndexResult<u64> {
+    Ok(dbs
+        .meta
+        .get(rtxn, GENERATION_META_KEY)?
+        .and_then(|value| value.parse().ok())
+        .unwrap_or(0))
+}
+
+fn bump_generation(dbs: &DbHandles, wtxn: &mut RwTxn) -> IndexResult<()> {
+    let next = read_generation(wtxn, dbs)?.wrapping_add(1);
+    dbs.meta.put(wtxn, GENERATION_META_KEY, &next.to_string())?;
+    Ok(())
+}
+
+/// Tag `payload`, compressing it when that makes it smaller.
+fn encode_blob(payload: &[u8]) -> IndexResult<Vec<u8>> {
+    if payload.len() >= BLOB_COMPRESS_MIN_BYTES {
+        let compressed = zstd::bulk::compress(payload, ZSTD_LEVEL)?;
+        if compressed.len() < payload.len() {
+            return Ok([&[BLOB_TAG, BLOB_ZSTD][..], &compressed].concat());
+        }
     }
+    Ok([&[BLOB_TAG, BLOB_RAW][..], payload].concat())
+}
 
-    let mut bitmaps = Vec::new();
-    for trigram in &query_trigrams {
-        let Some(blob) = dbs.trigrams.get(rtxn, &trigram[..])? else {
-            return Ok(Vec::new());
-        };
-        let bitmap: RoaringBitmap = decode_bytes(blob)?;
-        bitmaps.push(bitmap);
+/// The payload of a tagged blob, or `None` for a legacy bincode blob.
+fn decode_blob(bytes: &[u8]) -> IndexResult<Option<Cow<'_, [u8]>>> {
+    match bytes {
+        [BLOB_TAG, BLOB_RAW, payload @ ..] => Ok(Some(Cow::Borrowed(payload))),
+        [BLOB_TAG, BLOB_ZSTD, payload @ ..] => {
+            Ok(Some(Cow::Owned(zstd::stream::decode_all(payload)?)))
+        }
+        [BLOB_TAG, codec, ..] => Err(IndexError::Unsupported(format!(
+            "unknown blob codec {codec}"
+        ))),
+        _ => Ok(None),
     }
+}
+
+/// Encode with roaring's portable serialization.
+fn encode_bitmap(bitmap: &RoaringBitmap) -> IndexResult<Vec<u8>> {
+    let mut payload = Vec::with_capacity(bitmap.serialized_size());
+    bitmap.serialize_into(&mut payload)?;
+    encode_blob(&payload)
+}
 
-    bitmaps.sort_by_key(|bitmap| bitmap.len());
-    let mut iter = bitmaps.into_iter();
-    let mut result = iter.next().unwrap_or_default();
+fn decode_bitmap(bytes: &[u8]) -> IndexResult<RoaringBitmap> {
+    METRICS.bitmap_decoded();
+    match decode_blob(bytes)? {
+        Some(payload) => Ok(RoaringBitmap::deserialize_from(&payload[..])?),
+        None => decode_bytes(bytes),
+    }
+}
 
-    for bitmap in iter {
-        result &= bitmap;
-        if result.is_empty() {
-            return Ok(Vec::new());
+/// Encode a file's sorted tokens as the varint gaps between their 24-bit
+/// values. Neighbouring tokens share leading bytes, so most gaps fit in one
+/// or two bytes before compression.
+fn encode_file_tokens(tokens: &[Token]) -> IndexResult<Vec<u8>> {
+    let mut payload = Vec::with_capacity(tokens.len() * 2);
+    let mut prev = 0u32;
+    for token in tokens {
+        let value = u32::from_be_bytes([0, token[0], token[1], token[2]]);
+        let mut gap = value.wrapping_sub(prev);
+        prev = value;
+        while gap >= 0x80 {
+            payload.push(gap as u8 | 0x80);
+            gap >>= 7;
         }
+        payload.push(gap as u8);
     }
+    encode_blob(&payload)
+}
 
-    let mut hits = Vec::new();
-    for file_id in result {
-        let Some(value) = dbs.files.get(rtxn, &file_id)? else {
-            continue;
-        };
-        let record: FileRecord = decode_bytes(value)?;
-        if let Some(file_regex) = file_regex
-            && !file_regex.is_match(&record.path)
-        {
-            continue;
+fn decode_file_tokens(bytes: &[u8]) -> IndexResult<Vec<Token>> {
+    let truncated = || IndexError::Corrupt("truncated file token list".to_string());
+    let payload = decode_blob(bytes)?.ok_or_else(truncated)?;
+    let mut tokens = Vec::with_capacity(payload.len() / 2);
+    let mut prev = 0u32;
+    let mut bytes = payload.iter();
+    while let Some(&first) = bytes.next() {
+        let mut gap = u32::from(first & 0x7F);
+        let mut shift = 7;
+        let mut byte = first;
+        while byte & 0x80 != 0 {
+            byte = *bytes.next().ok_or_else(truncated)?;
+            gap |= u32::from(byte & 0x7F) << shift;
+            shift += 7;
         }
-        hits.push(SearchHit {
-            file_id,
-            path: record.path,
-        });
+        prev = prev.wrapping_add(gap);
+        let [_, a, b, c] = prev.to_be_bytes();
+        tokens.push([a, b, c]);
     }
+    Ok(tokens)
+}
 
-    Ok(hits)
+/// The `file_tokens` value pointing at the token set stored under `hash`.
+fn shared_token_ref(hash: &ContentHash) -> Vec<u8> {
+    [&[BLOB_TAG, BLOB_SHARED][..], hash].concat()
 }
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use std::io::Write;
-    use tempfile::TempDir;
+fn encode_token_set(refs: u32, tokens: &[Token]) -> IndexResult<Vec<u8>> {
+    Ok([&refs.to_le_bytes()[..], &encode_file_tokens(tokens)?].concat())
+}
 
-    fn create_test_index() -> (TempDir, PersistentIndex) {
-        let temp_dir = TempDir::new().unwrap();
-        let db_path = temp_dir.path().join("test_index.mdb");
-        let index = PersistentIndex::open_or_create(&db_path).unwrap();
-        (temp_dir, index)
-    }
+fn token_set_refs(value: &[u8]) -> IndexResult<u32> {
+    value
+        .first_chunk::<TOKEN_SET_REFS_LEN>()
+        .map(|refs| u32::from_le_bytes(*refs))
+        .ok_or_else(|| IndexError::Corrupt("truncated token set".to_string()))
+}
 
-    #[test]
-    fn test_create_new_index() {
-        let temp_dir = TempDir::new().unwrap();
-        let db_path = temp_dir.path().join("new_index.mdb");
+/// A file's tokens, following a shared reference to `token_sets`.
+fn read_file_tokens(
+    rtxn: &RoTxn,
+    dbs: &DbHandles,
+    file_id: u32,
+) -> IndexResult<Option<Vec<Token>>> {
+    let Some(value) = dbs.file_tokens.get(rtxn, &file_id)? else {
+        return Ok(None);
+    };
+    let [BLOB_TAG, BLOB_SHARED, hash @ ..] = value else {
+        return decode_file_tokens(value).map(Some);
+    };
+    let set = dbs.token_sets.get(rtxn, hash)?.ok_or_else(|| {
+        IndexError::Corrupt(format!("file {file_id} references a missing token set"))
+    })?;
+    decode_file_tokens(&set[TOKEN_SET_REFS_LEN..]).map(Some)
+}
 
-        let index = PersistentIndex::open_or_create(&db_path);
-        assert!(index.is_ok());
-        assert!(db_path.exists());
+/// Point `file_id` at the shared token set for `hash`, storing `tokens`
+/// there if no other file has that content yet.
+fn put_file_tokens(
+    dbs: &DbHandles,
+    wtxn: &mut RwTxn,
+    file_id: u32,
+    tokens: &[Token],
+    hash: &ContentHash,
+) -> IndexResult<()> {
+    delete_file_tokens(dbs, wtxn, file_id)?;
+    let value = match dbs.token_sets.get(wtxn, hash)? {
+        Some(set) => {
+            let refs = token_set_refs(set)? + 1;
+            [&refs.to_le_bytes()[..], &set[TOKEN_SET_REFS_LEN..]].concat()
+        }
+        None => encode_token_set(1, tokens)?,
+    };
+    dbs.token_sets.put(wtxn, hash, &value)?;
+    dbs.file_tokens
+        .put(wtxn, &file_id, &shared_token_ref(hash))?;
+    Ok(())
+}
+
+/// Drop `file_id`'s tokens, freeing its shared token set once no other
+/// file references it.
+fn delete_file_tokens(dbs: &DbHandles, wtxn: &mut RwTxn, file_id: u32) -> IndexResult<()> {
+    let Some(value) = dbs.file_tokens.get(wtxn, &file_id)? else {
+        return Ok(());
+    };
+    if let [BLOB_TAG, BLOB_SHARED, hash @ ..] = value {
+        let hash = hash.to_vec();
+        if let Some(set) = dbs.token_sets.get(wtxn, &hash)? {
+            match token_set_refs(set)? {
+                0 | 1 => {
+                    let _ = dbs.token_sets.delete(wtxn, &hash)?;
+                }
+                refs => {
+                    let value =
+                        [&(refs - 1).to_le_bytes()[..], &set[TOKEN_SET_REFS_LEN..]].concat();
+                    dbs.token_sets.put(wtxn, &hash, &value)?;
+                }
+            }
+        }
     }
+    let _ = dbs.file_tokens.delete(wtxn, &file_id)?;
+    Ok(())
+}
 
-    #[test]
-    fn test_reopen_existing_index() {
-        let temp_dir = TempDir::new().unwrap();
-        let db_path = temp_dir.path().join("existing_index.mdb");
+/// Decode a `file_trigrams` value of an older index: bincode, or packed
+/// 3-byte tokens in a tagged blob.
+fn decode_legacy_trigrams(bytes: &[u8]) -> IndexResult<Vec<Token>> {
+    let Some(payload) = decode_blob(bytes)? else {
+        return decode_bytes(bytes);
+    };
+    let (tokens, rest) = payload.as_chunks::<3>();
+    if !rest.is_empty() {
+        return Err(IndexError::Corrupt(format!(
+            "trigram blob of {} bytes is not a multiple of 3",
+            payload.len()
+        )));
+    }
+    Ok(tokens.to_vec())
+}
 
+/// Move the per-file token lists of an older index from `file_trigrams`
+/// into `file_tokens` and empty the old table, then record
+/// [`FILE_TOKENS_MIGRATED_META_KEY`]. Returns the number of files moved.
+///
+/// heed cannot delete a named database, so the emptied table stays in the
+/// environment; the marker keeps later opens from looking at it.
+fn migrate_file_trigrams(env: &Env, dbs: &DbHandles) -> IndexResult<usize> {
+    {
+        let rtxn = env.read_txn()?;
+        if dbs
+            .meta
+            .get(&rtxn, FILE_TOKENS_MIGRATED_META_KEY)?
+            .is_some()
         {
-            let _index = PersistentIndex::open_or_create(&db_path).unwrap();
+            return Ok(0);
         }
-
-        let index = PersistentIndex::open_or_create(&db_path);
-        assert!(index.is_ok());
     }
 
-    #[test]
-    fn test_index_and_search_file() {
-        let temp_dir = TempDir::new().unwrap();
-        let db_path = temp_dir.path().join("index.mdb");
-        let index = PersistentIndex::open_or_create(&db_path).unwrap();
+    let mut wtxn = env.write_txn()?;
+    let legacy: Option<FileTokensDb> = env.open_database(&wtxn, Some("file_trigrams"))?;
+    let legacy = match legacy {
+        Some(legacy) if !legacy.is_empty(&wtxn)? => legacy,
+        _ => {
+            dbs.meta
+                .put(&mut wtxn, FILE_TOKENS_MIGRATED_META_KEY, "true")?;
+            wtxn.commit()?;
+            return Ok(0);
+        }
+    };
 
-        let test_file = temp_dir.path().join("test.rs");
+    let mut file_ids = Vec::new();
+    for entry in legacy.remap_data_type::<DecodeIgnore>().iter(&wtxn)? {
+        file_ids.push(entry?.0);
+    }
+    for file_id in &file_ids {
+        let Some(blob) = legacy.get(&wtxn, file_id)? else {
+            continue;
+        };
+        let tokens = decode_legacy_trigrams(blob)?;
+        dbs.file_tokens
+            .put(&mut wtxn, file_id, &encode_file_tokens(&tokens)?)?;
+    }
+    legacy.clear(&mut wtxn)?;
+    dbs.meta
+        .put(&mut wtxn, FILE_TOKENS_MIGRATED_META_KEY, "true")?;
+    wtxn.commit()?;
+    Ok(file_ids.len())
+}
+
+fn posting_shard(file_id: u32) -> u16 {
+    (file_id >> POSTING_SHARD_BITS) as u16
+}
+
+fn posting_key(token: &Token, shard: u16) -> [u8; 5] {
+    let [hi, lo] = shard.to_be_bytes();
+    [token[0], token[1], token[2], hi, lo]
+}
+
+const POSTING_COUNT_KEY_LEN: usize = 4;
+
+fn posting_count_key(token: &Token) -> [u8; POSTING_COUNT_KEY_LEN] {
+    [token[0], token[1], token[2], POSTING_COUNT_MARKER]
+}
+
+/// Number of files posted under `token`, if recorded. Indexes written
+/// before counts existed get one on the token's next update.
+fn read_posting_count(rtxn: &RoTxn, dbs: &DbHandles, token: &Token) -> IndexResult<Option<u64>> {
+    dbs.trigrams
+        .get(rtxn, &posting_count_key(token)[..])?
+        .map(decode_bytes)
+        .transpose()
+}
+
+/// Number of files posted under `token`, loading its postings when no
+/// count is recorded; the loaded postings are returned so they are not
+/// read twice.
+fn posting_count_or_load(
+    rtxn: &RoTxn,
+    dbs: &DbHandles,
+    token: &Token,
+) -> IndexResult<(u64, Option<RoaringBitmap>)> {
+    if let Some(count) = read_posting_count(rtxn, dbs, token)? {
+        return Ok((count, None));
+    }
+    Ok(match read_postings(rtxn, dbs, token)? {
+        Some(postings) => (postings.len(), Some(postings)),
+        None => (0, None),
+    })
+}
+
+/// Record the count after `token`'s postings changed by `delta`.
+fn update_posting_count(
+    dbs: &DbHandles,
+    wtxn: &mut RwTxn,
+    token: &Token,
+    delta: i64,
+) -> IndexResult<()> {
+    let count = match read_posting_count(wtxn, dbs, token)? {
+        Some(count) => count.saturating_add_signed(delta),
+        None => read_postings(wtxn, dbs, token)?.map_or(0, |postings| postings.len()),
+    };
+    let key = posting_count_key(token);
+    if count == 0 {
+        let _ = dbs.trigrams.delete(wtxn, &key[..])?;
+    } else {
+        dbs.trigrams.put(wtxn, &key[..], &encode_bytes(&count)?)?;
+    }
+    Ok(())
+}
+
+/// All file ids posted under `token`, merged across shards.
+fn read_postings(
+    rtxn: &RoTxn,
+    dbs: &DbHandles,
+    token: &Token,
+) -> IndexResult<Option<RoaringBitmap>> {
+    merge_shards(&posting_shards(rtxn, dbs, token)?)
+}
+
+/// The encoded shards posted under `token`, borrowed from `rtxn`.
+fn posting_shards<'t>(
+    rtxn: &'t RoTxn,
+    dbs: &DbHandles,
+    token: &Token,
+) -> IndexResult<Vec<&'t [u8]>> {
+    let mut shards = Vec::new();
+    for entry in dbs.trigrams.prefix_iter(rtxn, &token[..])? {
+        let (key, value) = entry?;
+        if key.len() != POSTING_COUNT_KEY_LEN {
+            shards.push(value);
+        }
+    }
+    Ok(shards)
+}
+
+fn merge_shards(shards: &[&[u8]]) -> IndexResult<Option<RoaringBitmap>> {
+    let mut postings: Option<RoaringBitmap> = None;
+    for shard in shards {
+        let shard = decode_bitmap(shard)?;
+        match &mut postings {
+            Some(postings) => *postings |= shard,
+            None => postings = Some(shard),
+        }
+    }
+    Ok(postings)
+}
+
+/// Write `file_ids` under `token` as shards. Existing postings for the
+/// token must already be cleared.
+fn put_postings(
+    dbs: &DbHandles,
+    wtxn: &mut RwTxn,
+    token: &Token,
+    file_ids: &RoaringBitmap,
+) -> IndexResult<()> {
+    let mut current: Option<(u16, RoaringBitmap)> = None;
+    for file_id in file_ids {
+        let shard = posting_shard(file_id);
+        if let Some((prev, bitmap)) = current.take_if(|(prev, _)| *prev != shard) {
+            dbs.trigrams
+                .put(wtxn, &posting_key(token, prev), &encode_bitmap(&bitmap)?)?;
+        }
+        current
+            .get_or_insert_with(|| (shard, RoaringBitmap::new()))
+            .1
+            .insert(file_id);
+    }
+    if let Some((shard, bitmap)) = current {
+        dbs.trigrams
+            .put(wtxn, &posting_key(token, shard), &encode_bitmap(&bitmap)?)?;
+    }
+    if !file_ids.is_empty() {
+        dbs.trigrams.put(
+            wtxn,
+            &posting_count_key(token),
+            &encode_bytes(&file_ids.len())?,
+        )?;
+    }
+    Ok(())
+}
+
+/// Posting changes of a writer batch, applied in one pass before it
+/// commits. A token shared by many files of the batch then has each of its
+/// shards decoded and rewritten once per batch instead of once per file.
+#[derive(Default)]
+struct PostingEdits {
+    added: HashMap<Token, RoaringBitmap>,
+    removed: HashMap<Token, RoaringBitmap>,
+}
+
+impl PostingEdits {
+    /// Post `file_id` under `token`, undoing an earlier removal.
+    fn add(&mut self, token: &Token, file_id: u32) {
+        if let Some(removed) = self.removed.get_mut(token) {
+            removed.remove(file_id);
+        }
+        self.added.entry(*token).or_default().insert(file_id);
+    }
+
+    /// Drop `file_id` from `token`'s postings, undoing an earlier addition.
+    fn remove(&mut self, token: &Token, file_id: u32) {
+        if let Some(added) = self.added.get_mut(token) {
+            added.remove(file_id);
+        }
+        self.removed.entry(*token).or_default().insert(file_id);
+    }
+
+    fn apply(self, dbs: &DbHandles, wtxn: &mut RwTxn) -> IndexResult<()> {
+        // Each file id is in at most one of the two maps, so the order of
+        // the passes does not matter.
+        for (token, file_ids) in &self.removed {
+            if !file_ids.is_empty() {
+                remove_postings(dbs, wtxn, token, file_ids)?;
+            }
+        }
+        for (token, file_ids) in &self.added {
+            if !file_ids.is_empty() {
+                add_postings(dbs, wtxn, token, file_ids)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Add `file_ids` to `token`'s postings, rewriting each touched shard once.
+fn add_postings(
+    dbs: &DbHandles,
+    wtxn: &mut RwTxn,
+    token: &Token,
+    file_ids: &RoaringBitmap,
+) -> IndexResult<()> {
+    let mut added = 0u64;
+    let mut ids = file_ids.iter().peekable();
+    while let Some(&first) = ids.peek() {
+        let shard = posting_shard(first);
+        let mut group = RoaringBitmap::new();
+        while let Some(file_id) = ids.next_if(|id| posting_shard(*id) == shard) {
+            group.insert(file_id);
+        }
+        let key = posting_key(token, shard);
+        let mut bitmap = dbs
+            .trigrams
+            .get(wtxn, &key[..])?
+            .map(decode_bitmap)
+            .transpose()?
+            .unwrap_or_default();
+        let before = bitmap.len();
+        bitmap |= group;
+        if bitmap.len() == before {
+            continue;
+        }
+        added += bitmap.len() - before;
+        dbs.trigrams.put(wtxn, &key[..], &encode_bitmap(&bitmap)?)?;
+    }
+    if added > 0 {
+        update_posting_count(dbs, wtxn, token, added as i64)?;
+    }
+    Ok(())
+}
+
+/// Remove `file_ids` from `token`'s postings, rewriting each touched shard
+/// (and any unsharded list) once.
+fn remove_postings(
+    dbs: &DbHandles,
+    wtxn: &mut RwTxn,
+    token: &Token,
+    file_ids: &RoaringBitmap,
+) -> IndexResult<()> {
+    let mut shards: Vec<u16> = file_ids.iter().map(posting_shard).collect();
+    shards.dedup();
+    let mut keys: Vec<Vec<u8>> = shards
+        .into_iter()
+        .map(|shard| posting_key(token, shard).to_vec())
+        .collect();
+    keys.push(token.to_vec());
+
+    let mut removed = RoaringBitmap::new();
+    for key in &keys {
+        let Some(blob) = dbs.trigrams.get(wtxn, key)? else {
+            continue;
+        };
+        let mut bitmap = decode_bitmap(blob)?;
+        let hit = &bitmap & file_ids;
+        if hit.is_empty() {
+            continue;
+        }
+        removed |= hit;
+        bitmap -= file_ids;
+        if bitmap.is_empty() {
+            let _ = dbs.trigrams.delete(wtxn, key)?;
+        } else {
+            dbs.trigrams.put(wtxn, key, &encode_bitmap(&bitmap)?)?;
+        }
+    }
+    if !removed.is_empty() {
+        update_posting_count(dbs, wtxn, token, -(removed.len() as i64))?;
+    }
+    Ok(())
+}
+
+/// Whether the file contains every token, judged by its stored content
+/// when there is one. Unreadable files pass, since they cannot be ruled out.
+fn content_has_tokens(
+    rtxn: &RoTxn,
+    dbs: &DbHandles,
+    file_id: u32,
+    path: &str,
+    tokens: &[Token],
+) -> bool {
+    // Sealed content cannot be read here; the file on disk stands in.
+    let stored = read_stored_content(rtxn, dbs, file_id, None).ok().flatten();
+    let Some(bytes) = stored.or_else(|| archive::read_path_bytes(&decode_path(path)).ok()) else {
+        return true;
+    };
+    tokens
+        .iter()
+        .all(|token| bytes.windows(token.len()).any(|window| window == token))
+}
+
+pub fn now_millis() -> i64 {
+    use std::time::{SystemTime, UNIX_EPOCH};
+
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis()
+        .min(i64::MAX as u128) as i64
+}
+
+/// Tokenizer the index was built with, for readers that have no
+/// `PersistentIndex` to ask. Unknown ids fall back to the default.
+fn stored_tokenizer(rtxn: &RoTxn, dbs: &DbHandles) -> IndexResult<Arc<dyn Tokenizer>> {
+    let stored = dbs.meta.get(rtxn, TOKENIZER_META_KEY)?;
+    Ok(stored
+        .and_then(tokenizer_for_id)
+        .unwrap_or_else(default_tokenizer))
+}
+
+/// Posting lists decoded by earlier queries of a batch, by token.
+#[derive(Default)]
+struct PostingCache(HashMap<Token, RoaringBitmap>);
+
+impl PostingCache {
+    fn postings(
+        &mut self,
+        rtxn: &RoTxn,
+        dbs: &DbHandles,
+        token: &Token,
+    ) -> IndexResult<RoaringBitmap> {
+        if let Some(postings) = self.0.get(token) {
+            return Ok(postings.clone());
+        }
+        let postings = read_postings(rtxn, dbs, token)?.unwrap_or_default();
+        self.0.insert(*token, postings.clone());
+        Ok(postings)
+    }
+
+    /// Load the postings of those `tokens` not cached yet, decoding them in
+    /// parallel once they are large enough to be worth it. A read
+    /// transaction must stay on its thread, so only locating the shards
+    /// happens here; decoding them, which on a cold cache is also when
+    /// their pages are read from disk, is spread over rayon's pool.
+    fn prefetch(&mut self, rtxn: &RoTxn, dbs: &DbHandles, tokens: &[Token]) -> IndexResult<()> {
+        let mut pending = Vec::new();
+        let mut bytes = 0;
+        for token in tokens {
+            if self.0.contains_key(token) || pending.iter().any(|(t, _)| t == token) {
+                continue;
+            }
+            let shards = posting_shards(rtxn, dbs, token)?;
+            bytes += shards.iter().map(|shard| shard.len()).sum::<usize>();
+            pending.push((*token, shards));
+        }
+
+        let decode = |(token, shards): (Token, Vec<&[u8]>)| -> IndexResult<(Token, RoaringBitmap)> {
+            Ok((token, merge_shards(&shards)?.unwrap_or_default()))
+        };
+        let decoded = if pending.len() > 1 && bytes >= PARALLEL_DECODE_MIN_BYTES {
+            workers::install(|| {
+                pending
+                    .into_par_iter()
+                    .map(decode)
+                    .collect::<IndexResult<Vec<_>>>()
+            })?
+        } else {
+            pending
+                .into_iter()
+                .map(decode)
+                .collect::<IndexResult<Vec<_>>>()?
+        };
+        self.0.extend(decoded);
+        Ok(())
+    }
+}
+
+fn search_batch_with_rtxn<Q: AsRef<str>>(
+    rtxn: &RoTxn,
+    dbs: &DbHandles,
+    tokenizer: &dyn Tokenizer,
+    queries: &[Q],
+    file_regex: Option<&Regex>,
+) -> IndexResult<Vec<SearchOutcome>> {
+    let mut cache = PostingCache::default();
+    queries
+        .iter()
+        .map(|query| {
+            search_with_rtxn(
+                rtxn,
+                dbs,
+                tokenizer,
+                query.as_ref(),
+                file_regex,
+                None,
+                &mut cache,
+            )
+        })
+        .collect()
+}
+
+fn search_with_rtxn(
+    rtxn: &RoTxn,
+    dbs: &DbHandles,
+    tokenizer: &dyn Tokenizer,
+    query: &str,
+    file_regex: Option<&Regex>,
+    deadline: Option<Instant>,
+    cache: &mut PostingCache,
+) -> IndexResult<SearchOutcome> {
+    let _span = debug_span!("search", query_len = query.len()).entered();
+    let _searching = SEARCH_ACTIVITY.begin();
+    let started = Instant::now();
+    let mut outcome = search_postings(rtxn, dbs, tokenizer, query, file_regex, deadline, cache)?;
+    outcome.generation = read_generation(rtxn, dbs)?;
+    let elapsed = started.elapsed();
+    METRICS.record_search(elapsed);
+    debug!(
+        hits = outcome.hits.len(),
+        truncated = outcome.truncated,
+        elapsed_us = elapsed.as_micros() as u64,
+        "search finished"
+    );
+    Ok(outcome)
+}
+
+fn diagnose_with_rtxn(
+    rtxn: &RoTxn,
+    dbs: &DbHandles,
+    tokenizer: &dyn Tokenizer,
+    query: &str,
+) -> IndexResult<SearchDiagnostics> {
+    let tokens = tokenizer.query_tokens(query);
+    let mut missing_trigrams = Vec::new();
+    for token in &tokens {
+        let (count, _) = posting_count_or_load(rtxn, dbs, token)?;
+        if count == 0 {
+            missing_trigrams.push(String::from_utf8_lossy(token).into_owned());
+        }
+    }
+    Ok(SearchDiagnostics {
+        files_indexed: dbs.files.len(rtxn)?,
+        query_too_short: tokens.is_empty(),
+        missing_trigrams,
+    })
+}
+
+fn short_query_with_rtxn(
+    rtxn: &RoTxn,
+    dbs: &DbHandles,
+    tokenizer: &dyn Tokenizer,
+    query: &str,
+    file_regex: Option<&Regex>,
+) -> IndexResult<Option<ShortQuery>> {
+    if query.is_empty() || !tokenizer.query_tokens(query).is_empty() {
+        return Ok(None);
+    }
+    let in_filter = |path: &str| file_regex.is_none_or(|re| re.is_match(path));
+    let files_indexed = dbs.files.len(rtxn)?;
+    if files_indexed <= SHORT_QUERY_SCAN_LIMIT {
+        // Path-only rows are binary or oversized; reading them is pointless.
+        let mut hits = Vec::new();
+        for hit in matching_files(rtxn, dbs, in_filter)? {
+            if !is_path_only(rtxn, dbs, hit.file_id)? {
+                hits.push(hit);
+            }
+        }
+        return Ok(Some(ShortQuery::Scan(hits)));
+    }
+    let lower_query = query.to_lowercase();
+    let hits = matching_files(rtxn, dbs, |path| {
+        in_filter(path) && path.to_lowercase().contains(&lower_query)
+    })?;
+    Ok(Some(ShortQuery::Paths {
+        files_indexed,
+        hits,
+    }))
+}
+
+fn search_postings(
+    rtxn: &RoTxn,
+    dbs: &DbHandles,
+    tokenizer: &dyn Tokenizer,
+    query: &str,
+    file_regex: Option<&Regex>,
+    deadline: Option<Instant>,
+    cache: &mut PostingCache,
+) -> IndexResult<SearchOutcome> {
+    let expired = || deadline.is_some_and(|deadline| Instant::now() >= deadline);
+    let truncated = || {
+        Ok(SearchOutcome {
+            truncated: true,
+            ..SearchOutcome::default()
+        })
+    };
+
+    let query_trigrams = tokenizer.query_tokens(query);
+    if query_trigrams.is_empty() {
+        return Ok(SearchOutcome::default());
+    }
+
+    // Plan: order tokens by posting count so the most selective ones are
+    // intersected first. Tokens without a recorded count are loaded now.
+    let mut planned = Vec::with_capacity(query_trigrams.len());
+    for trigram in &query_trigrams {
+        if expired() {
+            return truncated();
+        }
+        let (count, postings) = posting_count_or_load(rtxn, dbs, trigram)?;
+        if count == 0 {
+            return Ok(SearchOutcome::default());
+        }
+        planned.push((trigram, count, postings));
+    }
+    planned.sort_by_key(|(_, count, _)| *count);
+
+    // The leading tokens are intersected whatever the counts, so their
+    // postings can be loaded together.
+    let leading: Vec<Token> = planned
+        .iter()
+        .take(PLANNED_TOKENS)
+        .filter(|(_, _, postings)| postings.is_none())
+        .map(|(trigram, _, _)| **trigram)
+        .collect();
+    cache.prefetch(rtxn, dbs, &leading)?;
+
+    let mut result: Option<RoaringBitmap> = None;
+    let mut unverified = Vec::new();
+    for (i, (trigram, count, postings)) in planned.into_iter().enumerate() {
+        if expired() {
+            return truncated();
+        }
+        let candidates = result.as_ref().map_or(u64::MAX, RoaringBitmap::len);
+        if checks_in_content(i, count, candidates) {
+            unverified.push(*trigram);
+            continue;
+        }
+        let bitmap = match postings {
+            Some(postings) => postings,
+            None => cache.postings(rtxn, dbs, trigram)?,
+        };
+        let intersected = match result {
+            Some(mut result) => {
+                result &= bitmap;
+                result
+            }
+            None => bitmap,
+        };
+        if intersected.is_empty() {
+            return Ok(SearchOutcome::default());
+        }
+        result = Some(intersected);
+    }
+    let result = result.unwrap_or_default();
+    if !unverified.is_empty() {
+        debug!(
+            skipped = unverified.len(),
+            candidates = result.len(),
+            "verifying stop tokens against content"
+        );
+    }
+
+    let mut hits = HitSet::default();
+    for file_id in result {
+        if expired() {
+            return Ok(SearchOutcome {
+                hits: hits.into_hits(),
+                truncated: true,
+                ..SearchOutcome::default()
+            });
+        }
+        let Some(value) = dbs.files.get(rtxn, &file_id)? else {
+            continue;
+        };
+        let record = decode_file_record(value)?;
+        if let Some(file_regex) = file_regex
+            && !file_regex.is_match(&record.path)
+        {
+            continue;
+        }
+        if !unverified.is_empty()
+            && !content_has_tokens(rtxn, dbs, file_id, &record.path, &unverified)
+        {
+            continue;
+        }
+        hits.push(file_id, record);
+    }
+
+    Ok(SearchOutcome {
+        hits: hits.into_hits(),
+        ..SearchOutcome::default()
+    })
+}
+
+/// Whether the token at planned position `step`, posted by `postings`
+/// files, is checked against the content of the `candidates` left instead
+/// of intersected.
+fn checks_in_content(step: usize, postings: u64, candidates: u64) -> bool {
+    step >= PLANNED_TOKENS
+        && postings >= STOP_TOKEN_MIN_POSTINGS
+        && candidates <= MAX_VERIFIED_CANDIDATES
+}
+
+/// [`search_postings`] without a deadline, recording each stage.
+fn explain_with_rtxn(
+    rtxn: &RoTxn,
+    dbs: &DbHandles,
+    tokenizer: &dyn Tokenizer,
+    query: &str,
+    file_regex: Option<&Regex>,
+) -> IndexResult<SearchPlan> {
+    let mut plan = SearchPlan {
+        files_indexed: dbs.files.len(rtxn)?,
+        generation: read_generation(rtxn, dbs)?,
+        ..SearchPlan::default()
+    };
+    let mut planned = Vec::new();
+    for token in tokenizer.query_tokens(query) {
+        let (count, postings) = posting_count_or_load(rtxn, dbs, &token)?;
+        planned.push((token, count, postings));
+    }
+    if planned.is_empty() {
+        return Ok(plan);
+    }
+    planned.sort_by_key(|(_, count, _)| *count);
+
+    let mut result: Option<RoaringBitmap> = None;
+    let mut unverified = Vec::new();
+    for (i, (token, count, postings)) in planned.into_iter().enumerate() {
+        let candidates = result.as_ref().map_or(u64::MAX, RoaringBitmap::len);
+        let action = if candidates == 0 {
+            PlanAction::Skipped
+        } else if checks_in_content(i, count, candidates) {
+            unverified.push(token);
+            PlanAction::CheckedInContent
+        } else {
+            let bitmap = match postings {
+                Some(postings) => postings,
+                None => read_postings(rtxn, dbs, &token)?.unwrap_or_default(),
+            };
+            result = Some(match result {
+                Some(mut result) => {
+                    result &= bitmap;
+                    result
+                }
+                None => bitmap,
+            });
+            PlanAction::Intersected
+        };
+        plan.steps.push(PlanStep {
+            trigram: String::from_utf8_lossy(&token).into_owned(),
+            postings: count,
+            action,
+            candidates: result.as_ref().map_or(0, RoaringBitmap::len),
+        });
+    }
+
+    let result = result.unwrap_or_default();
+    plan.candidates = result.len();
+    let mut hits = HitSet::default();
+    for file_id in result {
+        let Some(value) = dbs.files.get(rtxn, &file_id)? else {
+            continue;
+        };
+        let record = decode_file_record(value)?;
+        if let Some(file_regex) = file_regex
+            && !file_regex.is_match(&record.path)
+        {
+            continue;
+        }
+        plan.path_matches += 1;
+        if !unverified.is_empty()
+            && !content_has_tokens(rtxn, dbs, file_id, &record.path, &unverified)
+        {
+            continue;
+        }
+        hits.push(file_id, record);
+    }
+    plan.hits = hits.into_hits();
+    Ok(plan)
+}
+
+/// Search hits, with hits that are the same file on disk (hard links, or
+/// one file indexed under two spellings of its path) collapsed into the
+/// most recently modified record.
+#[derive(Default)]
+struct HitSet {
+    hits: Vec<SearchHit>,
+    by_identity: HashMap<FileIdentity, (usize, u64)>,
+}
+
+impl HitSet {
+    fn push(&mut self, file_id: u32, record: FileRecord) {
+        let hit = SearchHit {
+            file_id,
+            path: record.path,
+        };
+        let Some(identity) = record.identity else {
+            self.hits.push(hit);
+            return;
+        };
+        match self.by_identity.entry(identity) {
+            Entry::Occupied(mut seen) => {
+                let (slot, last_modified) = seen.get_mut();
+                if record.last_modified > *last_modified {
+                    self.hits[*slot] = hit;
+                    *last_modified = record.last_modified;
+                }
+            }
+            Entry::Vacant(seen) => {
+                seen.insert((self.hits.len(), record.last_modified));
+                self.hits.push(hit);
+            }
+        }
+    }
+
+    /// The hits sorted by path, then file id, so their order does not
+    /// depend on the ids a rebuild happened to assign.
+    fn into_hits(self) -> Vec<SearchHit> {
+        let mut hits = self.hits;
+        hits.sort_by(|lhs, rhs| lhs.path.cmp(&rhs.path).then(lhs.file_id.cmp(&rhs.file_id)));
+        hits
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+    use tempfile::TempDir;
+
+    fn create_test_index() -> (TempDir, PersistentIndex) {
+        let temp_dir = TempDir::new().unwrap();
+        let db_path = temp_dir.path().join("test_index.mdb");
+        let index = PersistentIndex::open_or_create(&db_path).unwrap();
+        (temp_dir, index)
+    }
+
+    #[test]
+    fn test_create_new_index() {
+        let temp_dir = TempDir::new().unwrap();
+        let db_path = temp_dir.path().join("new_index.mdb");
+
+        let index = PersistentIndex::open_or_create(&db_path);
+        assert!(index.is_ok());
+        assert!(db_path.exists());
+    }
+
+    #[test]
+    fn test_reopen_existing_index() {
+        let temp_dir = TempDir::new().unwrap();
+        let db_path = temp_dir.path().join("existing_index.mdb");
+
+        {
+            let _index = PersistentIndex::open_or_create(&db_path).unwrap();
+        }
+
+        let index = PersistentIndex::open_or_create(&db_path);
+        assert!(index.is_ok());
+    }
+
+    #[test]
+    fn test_index_and_search_file() {
+        let temp_dir = TempDir::new().unwrap();
+        let db_path = temp_dir.path().join("index.mdb");
+        let index = PersistentIndex::open_or_create(&db_path).unwrap();
+
+        let test_file = temp_dir.path().join("test.rs");
         let mut f = std::fs::File::create(&test_file).unwrap();
         writeln!(f, "fn hello_world() {{").unwrap();
         writeln!(f, "    println!(\"Hello, World!\");").unwrap();
         writeln!(f, "}}").unwrap();
         f.flush().unwrap();
 
-        index.index_path(&test_file).unwrap();
+        index.index_path(&test_file).unwrap();
+        index.flush().unwrap();
+
+        let hits = index.search("hello_world").unwrap();
+        assert_eq!(hits.len(), 1);
+        assert!(hits[0].path.contains("test.rs"));
+    }
+
+    #[test]
+    fn test_warm_up_stays_within_budget() {
+        let (temp_dir, index) = create_test_index();
+        for i in 0..20 {
+            let path = temp_dir.path().join(format!("warm{i}.rs"));
+            std::fs::write(&path, format!("fn warm_up_marker_{i}() {{}}")).unwrap();
+            index.index_path(&path).unwrap();
+        }
+        index.flush().unwrap();
+
+        let all = index.warm_up(u64::MAX).unwrap();
+        assert!(all.tokens > 0 && all.bytes > 0);
+        // Only posting bytes count, once per token; count keys do not.
+        let rtxn = index.env.read_txn().unwrap();
+        let mut tokens = HashSet::new();
+        let mut posting_bytes = 0;
+        for entry in index.dbs.trigrams.iter(&rtxn).unwrap() {
+            let (key, value) = entry.unwrap();
+            if key.len() != POSTING_COUNT_KEY_LEN {
+                tokens.insert(key[..3].to_vec());
+                posting_bytes += value.len() as u64;
+            }
+        }
+        drop(rtxn);
+        assert_eq!(all.bytes, posting_bytes);
+        assert_eq!(all.tokens, tokens.len());
+        assert_eq!(index.warm_up(0).unwrap(), WarmUp::default());
+        let half = index.warm_up(all.bytes / 2).unwrap();
+        assert!(half.bytes <= all.bytes / 2);
+        assert!(half.tokens < all.tokens);
+    }
+
+    #[test]
+    fn test_search_hits_sorted_by_path() {
+        let (temp_dir, index) = create_test_index();
+        // Indexed out of path order, so file ids disagree with paths.
+        for name in ["zeta.rs", "alpha.rs", "mid.rs"] {
+            let path = temp_dir.path().join(name);
+            std::fs::write(&path, "fn ordered_hit_marker() {}").unwrap();
+            index.index_path(&path).unwrap();
+            index.flush().unwrap();
+        }
+
+        let hits = index.search("ordered_hit_marker").unwrap();
+        let names: Vec<&str> = hits
+            .iter()
+            .map(|hit| hit.path.rsplit(['/', '\\']).next().unwrap())
+            .collect();
+        assert_eq!(names, ["alpha.rs", "mid.rs", "zeta.rs"]);
+        let plan = index.explain("ordered_hit_marker", None).unwrap();
+        assert_eq!(plan.hits.len(), 3);
+        assert!(plan.hits.is_sorted_by(|lhs, rhs| lhs.path <= rhs.path));
+    }
+
+    // Windows refuses to move an index that is still open.
+    #[cfg(unix)]
+    #[test]
+    fn test_replace_index_swaps_in_rebuild() {
+        let temp_dir = TempDir::new().unwrap();
+        let db_path = temp_dir.path().join("index.mdb");
+        let old_file = temp_dir.path().join("old.rs");
+        let new_file = temp_dir.path().join("new.rs");
+        std::fs::write(&old_file, "fn old_marker() {}").unwrap();
+        std::fs::write(&new_file, "fn new_marker() {}").unwrap();
+
+        let old = PersistentIndex::open_or_create(&db_path).unwrap();
+        old.index_path(&old_file).unwrap();
+        old.flush().unwrap();
+
+        let staging = staging_db_path(&db_path);
+        let rebuilt = PersistentIndex::open_or_create(&staging).unwrap();
+        rebuilt.index_path(&new_file).unwrap();
+        rebuilt.flush().unwrap();
+        drop(rebuilt);
+
+        replace_index(&db_path, &staging).unwrap();
+        assert!(!staging.exists());
+        assert!(old.is_replaced().unwrap());
+        assert_eq!(old.search("old_marker").unwrap().len(), 1);
+        drop(old);
+
+        let reopened = PersistentIndex::open_or_create(&db_path).unwrap();
+        assert!(!reopened.is_replaced().unwrap());
+        assert!(reopened.search("old_marker").unwrap().is_empty());
+        assert_eq!(reopened.search("new_marker").unwrap().len(), 1);
+    }
+
+    #[test]
+    fn test_failed_replace_keeps_index_unmarked() {
+        let temp_dir = TempDir::new().unwrap();
+        let db_path = temp_dir.path().join("index.mdb");
+        let file = temp_dir.path().join("kept.rs");
+        std::fs::write(&file, "fn kept_marker() {}").unwrap();
+        let index = PersistentIndex::open_or_create(&db_path).unwrap();
+        index.index_path(&file).unwrap();
+        index.flush().unwrap();
+        drop(index);
+
+        let missing = staging_db_path(&db_path);
+        assert!(replace_index(&db_path, &missing).is_err());
+        let index = PersistentIndex::open_or_create(&db_path).unwrap();
+        assert!(!index.is_replaced().unwrap());
+        assert_eq!(index.search("kept_marker").unwrap().len(), 1);
+    }
+
+    #[test]
+    fn test_open_after_swap_by_another_process_maps_rebuild() {
+        let temp_dir = TempDir::new().unwrap();
+        let db_path = temp_dir.path().join("index.mdb");
+        let old_file = temp_dir.path().join("old.rs");
+        let new_file = temp_dir.path().join("new.rs");
+        std::fs::write(&old_file, "fn old_marker() {}").unwrap();
+        std::fs::write(&new_file, "fn new_marker() {}").unwrap();
+
+        // Dropped, but heed keeps the env cached for the path.
+        let old = PersistentIndex::open_or_create(&db_path).unwrap();
+        old.index_path(&old_file).unwrap();
+        old.set_meta(REPLACED_META_KEY, "true").unwrap();
+        old.flush().unwrap();
+        drop(old);
+
+        let staging = staging_db_path(&db_path);
+        let rebuilt = PersistentIndex::open_or_create(&staging).unwrap();
+        rebuilt.index_path(&new_file).unwrap();
+        rebuilt.flush().unwrap();
+        drop(rebuilt);
+        open_env(&staging).unwrap().prepare_for_closing();
+
+        // The moves `replace_index` makes, as seen from another process.
+        std::fs::rename(&db_path, sibling_db_path(&db_path, "old")).unwrap();
+        std::fs::rename(&staging, &db_path).unwrap();
+
+        assert!(
+            search_database_file(&db_path, "old_marker")
+                .unwrap()
+                .is_empty()
+        );
+        assert_eq!(
+            search_database_file(&db_path, "new_marker").unwrap().len(),
+            1
+        );
+        let reopened = PersistentIndex::open_or_create(&db_path).unwrap();
+        assert!(!reopened.is_replaced().unwrap());
+    }
+
+    #[test]
+    fn test_open_after_removing_files_creates_fresh_index() {
+        let temp_dir = TempDir::new().unwrap();
+        let db_path = temp_dir.path().join("index.mdb");
+        let file = temp_dir.path().join("stale.rs");
+        std::fs::write(&file, "fn stale_marker() {}").unwrap();
+
+        // Dropped, but heed keeps the env cached for the path.
+        let old = PersistentIndex::open_or_create(&db_path).unwrap();
+        old.index_path(&file).unwrap();
+        old.flush().unwrap();
+        drop(old);
+
+        std::fs::remove_dir_all(&db_path).unwrap();
+
+        let index = PersistentIndex::open_or_create(&db_path).unwrap();
+        assert!(db_path.join("data.mdb").exists());
+        assert!(index.is_empty().unwrap());
+        assert!(index.search("stale_marker").unwrap().is_empty());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_search_collapses_hard_links() {
+        let (temp_dir, index) = create_test_index();
+        let original = temp_dir.path().join("original.rs");
+        let linked = temp_dir.path().join("linked.rs");
+        let copy = temp_dir.path().join("copy.rs");
+        std::fs::write(&original, "fn hard_link_marker() {}").unwrap();
+        std::fs::hard_link(&original, &linked).unwrap();
+        std::fs::copy(&original, &copy).unwrap();
+        for file in [&original, &linked, &copy] {
+            index.index_path(file).unwrap();
+        }
+        index.flush().unwrap();
+
+        let mut paths: Vec<String> = index
+            .search("hard_link_marker")
+            .unwrap()
+            .into_iter()
+            .map(|hit| hit.path)
+            .collect();
+        paths.sort();
+        assert_eq!(paths.len(), 2, "{paths:?}");
+        assert!(paths[0].ends_with("copy.rs"));
+        assert!(paths[1].ends_with("original.rs"));
+    }
+
+    #[test]
+    fn test_decode_file_record_without_identity() {
+        #[derive(Serialize)]
+        struct Legacy {
+            path: String,
+            last_modified: u64,
+        }
+        let bytes = encode_bytes(&Legacy {
+            path: "/repo/a.rs".to_string(),
+            last_modified: 7,
+        })
+        .unwrap();
+        let record = decode_file_record(&bytes).unwrap();
+        assert_eq!(record.path, "/repo/a.rs");
+        assert_eq!(record.last_modified, 7);
+        assert!(record.identity.is_none());
+    }
+
+    #[test]
+    fn test_search_batch_matches_single_searches() {
+        let temp_dir = TempDir::new().unwrap();
+        let db_path = temp_dir.path().join("index.mdb");
+        let index = PersistentIndex::open_or_create(&db_path).unwrap();
+        for (name, text) in [
+            ("a.rs", "fn batch_alpha() {}"),
+            ("b.rs", "fn batch_beta() {}"),
+            ("c.rs", "fn batch_alpha() { batch_beta() }"),
+        ] {
+            let file = temp_dir.path().join(name);
+            std::fs::write(&file, text).unwrap();
+            index.index_path(&file).unwrap();
+        }
+        index.flush().unwrap();
+
+        let queries = ["batch_alpha", "batch_beta", "batch_gamma", "batch_alpha"];
+        let batch: Vec<Vec<SearchHit>> = index
+            .search_batch(&queries, None)
+            .unwrap()
+            .into_iter()
+            .map(|outcome| outcome.hits)
+            .collect();
+        assert_eq!(batch.len(), queries.len());
+        for (query, hits) in queries.iter().zip(&batch) {
+            let paths = |hits: &[SearchHit]| {
+                let mut paths: Vec<String> = hits.iter().map(|hit| hit.path.clone()).collect();
+                paths.sort();
+                paths
+            };
+            assert_eq!(paths(hits), paths(&index.search(query).unwrap()), "{query}");
+        }
+        assert_eq!(batch[0].len(), 2);
+        assert!(batch[2].is_empty());
+
+        drop(index);
+        let read_only = search_database_file_batch(&db_path, &queries, None).unwrap();
+        assert_eq!(
+            read_only
+                .iter()
+                .map(|outcome| outcome.hits.len())
+                .collect::<Vec<_>>(),
+            [2, 2, 0, 2]
+        );
+        let generation = read_only[0].generation;
+        assert!(generation > 0);
+        assert!(
+            read_only
+                .iter()
+                .all(|outcome| outcome.generation == generation)
+        );
+    }
+
+    #[test]
+    fn test_search_within_timeout_marks_truncated() {
+        let temp_dir = TempDir::new().unwrap();
+        let db_path = temp_dir.path().join("index.mdb");
+        let index = PersistentIndex::open_or_create(&db_path).unwrap();
+        for i in 0..3 {
+            let file = temp_dir.path().join(format!("file{i}.rs"));
+            std::fs::write(&file, "fn budget_marker() {}").unwrap();
+            index.index_path(&file).unwrap();
+        }
+        index.flush().unwrap();
+
+        let outcome = index
+            .search_filtered_within("budget_marker", None, Some(Duration::ZERO))
+            .unwrap();
+        assert!(outcome.truncated);
+        assert!(outcome.hits.is_empty());
+
+        let outcome = index
+            .search_filtered_within("budget_marker", None, Some(Duration::from_secs(60)))
+            .unwrap();
+        assert!(!outcome.truncated);
+        assert_eq!(outcome.hits.len(), 3);
+    }
+
+    #[test]
+    fn test_generation_tracks_content_changes() {
+        let temp_dir = TempDir::new().unwrap();
+        let db_path = temp_dir.path().join("index.mdb");
+        let index = PersistentIndex::open_or_create(&db_path).unwrap();
+        let start = index.generation().unwrap();
+
+        let file = temp_dir.path().join("gen.rs");
+        std::fs::write(&file, "fn generation_marker() {}").unwrap();
+        index.index_path(&file).unwrap();
+        index.flush().unwrap();
+        let after_index = index.generation().unwrap();
+        assert!(after_index > start);
+
+        index.search("generation_marker").unwrap();
+        index.set_meta_queued("unrelated", "value").unwrap();
+        index.flush().unwrap();
+        assert_eq!(index.generation().unwrap(), after_index);
+
+        index.clear_content().unwrap();
+        assert!(index.generation().unwrap() > after_index);
+    }
+
+    #[test]
+    fn test_search_reports_snapshot_generation() {
+        let temp_dir = TempDir::new().unwrap();
+        let db_path = temp_dir.path().join("index.mdb");
+        let index = PersistentIndex::open_or_create(&db_path).unwrap();
+
+        let file = temp_dir.path().join("snap.rs");
+        std::fs::write(&file, "fn snapshot_marker() {}").unwrap();
+        index.index_path(&file).unwrap();
+        index.flush().unwrap();
+        let first = index
+            .search_filtered_within("snapshot_marker", None, None)
+            .unwrap();
+        assert_eq!(first.generation, index.generation().unwrap());
+        assert_eq!(first.hits.len(), 1);
+
+        let other = temp_dir.path().join("snap2.rs");
+        std::fs::write(&other, "fn snapshot_marker() {}").unwrap();
+        index.index_path(&other).unwrap();
         index.flush().unwrap();
+        let second = index
+            .search_filtered_within("snapshot_marker", None, None)
+            .unwrap();
+        assert!(second.generation > first.generation);
+        assert_eq!(second.hits.len(), 2);
+    }
 
-        let hits = index.search("hello_world").unwrap();
+    #[test]
+    fn test_file_ids_resolved_after_reopen() {
+        let temp_dir = TempDir::new().unwrap();
+        let db_path = temp_dir.path().join("index.mdb");
+        let first = temp_dir.path().join("first.rs");
+        let second = temp_dir.path().join("second.rs");
+        std::fs::write(&first, "fn reopen_marker() {}").unwrap();
+        std::fs::write(&second, "fn reopen_marker() {}").unwrap();
+
+        let index = PersistentIndex::open_or_create(&db_path).unwrap();
+        index.index_path(&first).unwrap();
+        index.flush().unwrap();
+        drop(index);
+
+        // A fresh writer knows no paths: it must find `first` in the index
+        // and allocate `second` an unused id.
+        let index = PersistentIndex::open_or_create(&db_path).unwrap();
+        index.index_path(&second).unwrap();
+        index.remove_path(&first).unwrap();
+        index.flush().unwrap();
+
+        let hits = index.search("reopen_marker").unwrap();
         assert_eq!(hits.len(), 1);
-        assert!(hits[0].path.contains("test.rs"));
+        assert!(hits[0].path.ends_with("second.rs"));
+
+        index.index_path(&first).unwrap();
+        index.flush().unwrap();
+        let mut ids: Vec<u32> = index
+            .search("reopen_marker")
+            .unwrap()
+            .iter()
+            .map(|hit| hit.file_id)
+            .collect();
+        ids.sort_unstable();
+        ids.dedup();
+        assert_eq!(ids.len(), 2);
+    }
+
+    #[test]
+    fn test_next_file_id_persisted_across_reopen() {
+        let temp_dir = TempDir::new().unwrap();
+        let db_path = temp_dir.path().join("index.mdb");
+        let first = temp_dir.path().join("first.rs");
+        let second = temp_dir.path().join("second.rs");
+        std::fs::write(&first, "fn next_id_marker() {}").unwrap();
+        std::fs::write(&second, "fn next_id_marker() {}").unwrap();
+
+        let index = PersistentIndex::open_or_create(&db_path).unwrap();
+        index.index_path(&first).unwrap();
+        index.flush().unwrap();
+        let first_id = index.search("next_id_marker").unwrap()[0].file_id;
+        index.remove_path(&first).unwrap();
+        index.flush().unwrap();
+        drop(index);
+
+        // The newest id is gone from `files`, but the counter in meta still
+        // keeps the reopened writer from handing it out again.
+        let index = PersistentIndex::open_or_create(&db_path).unwrap();
+        index.index_path(&second).unwrap();
+        index.flush().unwrap();
+        let hits = index.search("next_id_marker").unwrap();
+        assert_eq!(hits.len(), 1);
+        assert!(hits[0].file_id > first_id);
+        let rtxn = index.env.read_txn().unwrap();
+        let stored = index.dbs.meta.get(&rtxn, NEXT_FILE_ID_META_KEY).unwrap();
+        assert_eq!(stored, Some((hits[0].file_id + 1).to_string().as_str()));
+    }
+
+    #[test]
+    fn test_writers_alternating_on_lease_keep_file_ids_apart() {
+        let temp_dir = TempDir::new().unwrap();
+        let db_path = temp_dir.path().join("index.mdb");
+        let [a1, a2, b1] = ["a1.rs", "a2.rs", "b1.rs"].map(|name| {
+            let path = temp_dir.path().join(name);
+            std::fs::write(&path, "fn handoff_marker() {}").unwrap();
+            path
+        });
+        let a = PersistentIndex::open_or_create(&db_path).unwrap();
+        let b = PersistentIndex::open_or_create(&db_path).unwrap();
+        let ttl = Duration::from_secs(30);
+        let write_as = |index: &PersistentIndex, holder: &str, work: &dyn Fn(&PersistentIndex)| {
+            assert!(index.try_acquire_writer_lease(holder, ttl).unwrap());
+            index.set_write_enabled(true);
+            work(index);
+            index.flush().unwrap();
+            index.set_write_enabled(false);
+            index.release_writer_lease(holder).unwrap();
+        };
+
+        write_as(&a, "a", &|index| index.index_path(&a1).unwrap());
+        // `b` allocates the id `a` would hand out next and gives `a1` a new one.
+        write_as(&b, "b", &|index| {
+            index.index_path(&b1).unwrap();
+            index.remove_path(&a1).unwrap();
+            index.flush().unwrap();
+            index.index_path(&a1).unwrap();
+        });
+        write_as(&a, "a", &|index| {
+            index.index_path(&a2).unwrap();
+            index.remove_path(&a1).unwrap();
+        });
+
+        let hits = b.search("handoff_marker").unwrap();
+        let paths: Vec<_> = hits.iter().map(|hit| hit.path.as_str()).collect();
+        assert_eq!(
+            paths,
+            [normalize_path(&a2).as_str(), normalize_path(&b1).as_str()]
+        );
+        assert_ne!(hits[0].file_id, hits[1].file_id);
+    }
+
+    #[test]
+    fn test_prefetch_decodes_large_postings_in_parallel() {
+        let (_temp_dir, index) = create_test_index();
+        let tokens = [*b"pa1", *b"pa2", *b"pa3"];
+        let mut wtxn = index.env.write_txn().unwrap();
+        for (seed, token) in (1u32..).zip(&tokens) {
+            // Scattered ids, so the shards do not compress to nothing.
+            let file_ids: RoaringBitmap = (0..50_000u32)
+                .map(|i| i.wrapping_mul(2_654_435_761).wrapping_add(seed) % 4_000_000)
+                .collect();
+            put_postings(&index.dbs, &mut wtxn, token, &file_ids).unwrap();
+        }
+        wtxn.commit().unwrap();
+
+        let rtxn = index.env.read_txn().unwrap();
+        let encoded: usize = tokens
+            .iter()
+            .flat_map(|token| posting_shards(&rtxn, &index.dbs, token).unwrap())
+            .map(<[u8]>::len)
+            .sum();
+        assert!(encoded >= PARALLEL_DECODE_MIN_BYTES);
+
+        let mut cache = PostingCache::default();
+        cache.prefetch(&rtxn, &index.dbs, &tokens).unwrap();
+        for token in &tokens {
+            assert_eq!(
+                cache.0.get(token),
+                read_postings(&rtxn, &index.dbs, token).unwrap().as_ref()
+            );
+        }
+    }
+
+    fn add_posting(
+        dbs: &DbHandles,
+        wtxn: &mut RwTxn,
+        token: &Token,
+        file_id: u32,
+    ) -> IndexResult<()> {
+        add_postings(dbs, wtxn, token, &RoaringBitmap::from_iter([file_id]))
+    }
+
+    fn remove_posting(
+        dbs: &DbHandles,
+        wtxn: &mut RwTxn,
+        token: &Token,
+        file_id: u32,
+    ) -> IndexResult<()> {
+        remove_postings(dbs, wtxn, token, &RoaringBitmap::from_iter([file_id]))
+    }
+
+    #[test]
+    fn test_posting_edits_keep_the_last_change_per_file() {
+        let (_temp_dir, index) = create_test_index();
+        let token = *b"edt";
+        let far = 2 << POSTING_SHARD_BITS;
+        let mut wtxn = index.env.write_txn().unwrap();
+        add_posting(&index.dbs, &mut wtxn, &token, 1).unwrap();
+
+        let mut edits = PostingEdits::default();
+        edits.add(&token, 2);
+        edits.add(&token, far);
+        edits.remove(&token, 2);
+        edits.remove(&token, 1);
+        edits.add(&token, 1);
+        edits.add(&token, 3);
+        edits.apply(&index.dbs, &mut wtxn).unwrap();
+        wtxn.commit().unwrap();
+
+        let rtxn = index.env.read_txn().unwrap();
+        let postings = read_postings(&rtxn, &index.dbs, &token).unwrap().unwrap();
+        assert_eq!(postings.iter().collect::<Vec<_>>(), vec![1, 3, far]);
+        assert_eq!(
+            read_posting_count(&rtxn, &index.dbs, &token).unwrap(),
+            Some(3)
+        );
+    }
+
+    #[test]
+    fn test_postings_span_shards_and_legacy_keys() {
+        let (_temp_dir, index) = create_test_index();
+        let token = *b"shd";
+        let far = 3 << POSTING_SHARD_BITS;
+        let legacy: RoaringBitmap = [7u32, 9].into_iter().collect();
+
+        let mut wtxn = index.env.write_txn().unwrap();
+        index
+            .dbs
+            .trigrams
+            .put(&mut wtxn, &token[..], &encode_bytes(&legacy).unwrap())
+            .unwrap();
+        add_posting(&index.dbs, &mut wtxn, &token, 1).unwrap();
+        add_posting(&index.dbs, &mut wtxn, &token, far).unwrap();
+        remove_posting(&index.dbs, &mut wtxn, &token, 9).unwrap();
+        wtxn.commit().unwrap();
+
+        let rtxn = index.env.read_txn().unwrap();
+        let postings = read_postings(&rtxn, &index.dbs, &token).unwrap().unwrap();
+        assert_eq!(postings.iter().collect::<Vec<_>>(), vec![1, 7, far]);
+        let shard = index
+            .dbs
+            .trigrams
+            .get(&rtxn, &posting_key(&token, 3)[..])
+            .unwrap()
+            .map(|blob| decode_bitmap(blob).unwrap())
+            .unwrap();
+        assert_eq!(shard.len(), 1);
+        drop(rtxn);
+
+        let mut wtxn = index.env.write_txn().unwrap();
+        for file_id in [1, 7, far] {
+            remove_posting(&index.dbs, &mut wtxn, &token, file_id).unwrap();
+        }
+        wtxn.commit().unwrap();
+        let rtxn = index.env.read_txn().unwrap();
+        assert!(read_postings(&rtxn, &index.dbs, &token).unwrap().is_none());
+    }
+
+    #[test]
+    fn test_blobs_compress_and_read_legacy_bincode() {
+        let sparse: RoaringBitmap = [3u32, 70_000].into_iter().collect();
+        let dense: RoaringBitmap = (0..50_000u32).step_by(3).collect();
+        for bitmap in [&sparse, &dense] {
+            let blob = encode_bitmap(bitmap).unwrap();
+            assert_eq!(blob[0], BLOB_TAG);
+            assert_eq!(&decode_bitmap(&blob).unwrap(), bitmap);
+            assert_eq!(
+                &decode_bitmap(&encode_bytes(bitmap).unwrap()).unwrap(),
+                bitmap
+            );
+        }
+        assert_eq!(encode_bitmap(&sparse).unwrap()[1], BLOB_RAW);
+        let dense_blob = encode_bitmap(&dense).unwrap();
+        assert_eq!(dense_blob[1], BLOB_ZSTD);
+        assert!(dense_blob.len() < encode_bytes(&dense).unwrap().len());
+
+        let packed = encode_blob(&[*b"abc", *b"abd"].concat()).unwrap();
+        assert_eq!(
+            decode_legacy_trigrams(&packed).unwrap(),
+            vec![*b"abc", *b"abd"]
+        );
+        assert_eq!(
+            decode_legacy_trigrams(&encode_bytes(&vec![*b"xyz"]).unwrap()).unwrap(),
+            vec![*b"xyz"]
+        );
+        assert!(matches!(
+            decode_legacy_trigrams(&[BLOB_TAG, BLOB_RAW, 1, 2]),
+            Err(IndexError::Corrupt(_))
+        ));
+    }
+
+    #[test]
+    fn test_file_tokens_round_trip_as_gaps() {
+        let tokens: Vec<Token> = (0..200u8).map(|b| [b'a', b'b', b]).collect();
+        let blob = encode_file_tokens(&tokens).unwrap();
+        assert!(blob.len() < tokens.len() * 3 / 2);
+        assert_eq!(decode_file_tokens(&blob).unwrap(), tokens);
+
+        let unsorted = vec![*b"zzz", [0, 0, 0], *b"mmm", [0xFF; 3]];
+        let blob = encode_file_tokens(&unsorted).unwrap();
+        assert_eq!(decode_file_tokens(&blob).unwrap(), unsorted);
+        assert!(decode_file_tokens(&[BLOB_TAG, BLOB_RAW, 0x80]).is_err());
+    }
+
+    #[test]
+    fn test_identical_files_share_token_set() {
+        let (temp_dir, index) = create_test_index();
+        let first = temp_dir.path().join("first.rs");
+        let second = temp_dir.path().join("second.rs");
+        for file in [&first, &second] {
+            std::fs::write(file, "fn duplicated_content_marker() {}").unwrap();
+            index.index_path(file).unwrap();
+        }
+        index.flush().unwrap();
+
+        let token_sets = |index: &PersistentIndex| {
+            let rtxn = index.env.read_txn().unwrap();
+            index
+                .dbs
+                .token_sets
+                .iter(&rtxn)
+                .unwrap()
+                .map(|entry| token_set_refs(entry.unwrap().1).unwrap())
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(token_sets(&index), vec![2]);
+        assert_eq!(index.search("duplicated_content_marker").unwrap().len(), 2);
+
+        // Diverging from the shared content takes a set of its own.
+        index
+            .index_content(
+                &normalize_path(&second),
+                "fn diverged_content_marker() {}",
+                u64::MAX,
+            )
+            .unwrap();
+        index.flush().unwrap();
+        let mut refs = token_sets(&index);
+        refs.sort_unstable();
+        assert_eq!(refs, vec![1, 1]);
+        assert_eq!(index.search("duplicated_content_marker").unwrap().len(), 1);
+        assert_eq!(index.search("diverged_content_marker").unwrap().len(), 1);
+
+        index.remove_path(&first).unwrap();
+        index.remove_path(&second).unwrap();
+        index.flush().unwrap();
+        assert!(token_sets(&index).is_empty());
+    }
+
+    #[test]
+    fn test_same_second_rewrite_reindexed() {
+        let (temp_dir, index) = create_test_index();
+        let file = temp_dir.path().join("rewritten.rs");
+        let path = normalize_path(&file);
+        let rewrite = |content: &str, modified_ts: u64| {
+            std::fs::write(&file, content).unwrap();
+            index.index_content(&path, content, modified_ts).unwrap();
+            index.flush().unwrap();
+        };
+
+        rewrite("fn before_rewrite_marker() {}", 1_000);
+        rewrite("fn after_rewrite_marker() {}", 1_000);
+        assert!(index.search("before_rewrite_marker").unwrap().is_empty());
+        assert_eq!(index.search("after_rewrite_marker").unwrap().len(), 1);
+
+        // An older timestamp still loses.
+        rewrite("fn stale_rewrite_marker() {}", 999);
+        assert!(index.search("stale_rewrite_marker").unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_legacy_file_trigrams_migrated() {
+        let (temp_dir, index) = create_test_index();
+        let file = temp_dir.path().join("legacy.rs");
+        std::fs::write(&file, "fn legacy_tokens_marker() {}").unwrap();
+        index.index_path(&file).unwrap();
+        index.flush().unwrap();
+
+        // Rewrite the index as an older version laid it out.
+        let mut wtxn = index.env.write_txn().unwrap();
+        let legacy: FileTokensDb = index
+            .env
+            .create_database(&mut wtxn, Some("file_trigrams"))
+            .unwrap();
+        let file_ids: Vec<u32> = index
+            .dbs
+            .file_tokens
+            .iter(&wtxn)
+            .unwrap()
+            .map(|entry| entry.unwrap().0)
+            .collect();
+        for file_id in &file_ids {
+            let tokens = read_file_tokens(&wtxn, &index.dbs, *file_id)
+                .unwrap()
+                .unwrap();
+            legacy
+                .put(&mut wtxn, file_id, &encode_bytes(&tokens).unwrap())
+                .unwrap();
+        }
+        index.dbs.file_tokens.clear(&mut wtxn).unwrap();
+        index.dbs.token_sets.clear(&mut wtxn).unwrap();
+        index
+            .dbs
+            .meta
+            .delete(&mut wtxn, FILE_TOKENS_MIGRATED_META_KEY)
+            .unwrap();
+        wtxn.commit().unwrap();
+
+        assert_eq!(migrate_file_trigrams(&index.env, &index.dbs).unwrap(), 1);
+        assert_eq!(migrate_file_trigrams(&index.env, &index.dbs).unwrap(), 0);
+        let rtxn = index.env.read_txn().unwrap();
+        assert!(legacy.is_empty(&rtxn).unwrap());
+        assert_eq!(
+            index
+                .dbs
+                .meta
+                .get(&rtxn, FILE_TOKENS_MIGRATED_META_KEY)
+                .unwrap(),
+            Some("true")
+        );
+        assert_eq!(index.dbs.file_tokens.len(&rtxn).unwrap(), 1);
+        drop(rtxn);
+
+        // Removal finds the migrated tokens and clears every posting.
+        index.remove_path(&file).unwrap();
+        index.flush().unwrap();
+        assert!(index.search("legacy_tokens_marker").unwrap().is_empty());
+        let rtxn = index.env.read_txn().unwrap();
+        assert!(read_postings(&rtxn, &index.dbs, b"gac").unwrap().is_none());
+    }
+
+    #[test]
+    fn test_stop_tokens_verified_against_content() {
+        let temp_dir = TempDir::new().unwrap();
+        let db_path = temp_dir.path().join("index.mdb");
+        let index = PersistentIndex::open_or_create(&db_path).unwrap();
+        let full = temp_dir.path().join("full.rs");
+        let partial = temp_dir.path().join("partial.rs");
+        std::fs::write(&full, "fn abcdefg() {}").unwrap();
+        std::fs::write(&partial, "fn abcdef() {}").unwrap();
+        index.index_path(&full).unwrap();
+        index.index_path(&partial).unwrap();
+        index.flush().unwrap();
+
+        // Make `efg` a stop token whose postings wrongly include `partial`.
+        let token = *b"efg";
+        let mut wtxn = index.env.write_txn().unwrap();
+        let mut postings = read_postings(&wtxn, &index.dbs, &token).unwrap().unwrap();
+        postings.insert_range(0..2 * STOP_TOKEN_MIN_POSTINGS as u32);
+        put_postings(&index.dbs, &mut wtxn, &token, &postings).unwrap();
+        wtxn.commit().unwrap();
+
+        let hits = index.search("abcdefg").unwrap();
+        assert_eq!(hits.len(), 1);
+        assert!(hits[0].path.ends_with("full.rs"));
     }
 
     #[test]
@@ -1350,6 +5146,31 @@ mod tests {
         assert!(hits.is_empty());
     }
 
+    #[test]
+    fn test_short_query_scans_small_index() {
+        let (temp_dir, index) = create_test_index();
+        for name in ["a.rs", "b.txt"] {
+            let file = temp_dir.path().join(name);
+            std::fs::write(&file, "x = 1").unwrap();
+            index.index_path(&file).unwrap();
+        }
+        index.flush().unwrap();
+
+        assert!(index.short_query("", None).unwrap().is_none());
+        assert!(index.short_query("x = 1", None).unwrap().is_none());
+        let Some(ShortQuery::Scan(files)) = index.short_query("x", None).unwrap() else {
+            panic!("a two-file index should be scanned");
+        };
+        assert_eq!(files.len(), 2);
+
+        let rs = Regex::new(r"\.rs$").unwrap();
+        let Some(ShortQuery::Scan(files)) = index.short_query("x", Some(&rs)).unwrap() else {
+            panic!("a two-file index should be scanned");
+        };
+        assert_eq!(files.len(), 1);
+        assert!(files[0].path.ends_with("a.rs"));
+    }
+
     #[test]
     fn test_search_with_snippets() {
         let temp_dir = TempDir::new().unwrap();
@@ -1364,6 +5185,7 @@ mod tests {
         let results = index.search_with_snippets("unique_snippet_marker").unwrap();
         assert_eq!(results.len(), 1);
         assert!(results[0].snippet.is_some());
+        assert_eq!(results[0].index_generation, index.generation().unwrap());
     }
 
     #[test]
@@ -1387,19 +5209,359 @@ mod tests {
         let db_path = temp_dir.path().join("index.mdb");
         let index = PersistentIndex::open_or_create(&db_path).unwrap();
 
-        let test_file = temp_dir.path().join("removeme.txt");
-        std::fs::write(&test_file, "unique_content_for_removal").unwrap();
-        index.index_path(&test_file).unwrap();
+        let test_file = temp_dir.path().join("removeme.txt");
+        std::fs::write(&test_file, "unique_content_for_removal").unwrap();
+        index.index_path(&test_file).unwrap();
+        index.flush().unwrap();
+
+        let hits = index.search("unique_content_for_removal").unwrap();
+        assert_eq!(hits.len(), 1);
+
+        index.remove_path(&test_file).unwrap();
+        index.flush().unwrap();
+
+        let hits = index.search("unique_content_for_removal").unwrap();
+        assert!(hits.is_empty());
+    }
+
+    #[cfg(any(windows, target_os = "macos"))]
+    #[test]
+    fn test_paths_differing_in_case_share_a_row() {
+        let (temp_dir, index) = create_test_index();
+        let file = temp_dir.path().join("Main.rs");
+        std::fs::write(&file, "case_folded_marker\n").unwrap();
+        index.index_path(&file).unwrap();
+        index.index_path(&temp_dir.path().join("MAIN.RS")).unwrap();
+        index.flush().unwrap();
+        assert_eq!(index.search("case_folded_marker").unwrap().len(), 1);
+
+        // A deleted file cannot be resolved on disk any more.
+        std::fs::remove_file(&file).unwrap();
+        index.remove_path(&temp_dir.path().join("main.rs")).unwrap();
+        index.flush().unwrap();
+        assert!(index.search("case_folded_marker").unwrap().is_empty());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_non_utf8_path_round_trips() {
+        use std::ffi::OsStr;
+        use std::os::unix::ffi::OsStrExt;
+
+        let (temp_dir, index) = create_test_index();
+        let dir = temp_dir.path().join(OsStr::from_bytes(b"d\xff\xfeir"));
+        std::fs::create_dir(&dir).unwrap();
+        let file = dir.join(OsStr::from_bytes(b"caf\xe9.txt"));
+        std::fs::write(&file, "latin1_named_file_marker\n").unwrap();
+        let sibling = dir.join(OsStr::from_bytes(b"caf\xef.txt"));
+        std::fs::write(&sibling, "latin1_named_file_marker\n").unwrap();
+        index.index_path(&file).unwrap();
+        index.index_path(&sibling).unwrap();
+        index.flush().unwrap();
+
+        let results = index
+            .search_with_snippets("latin1_named_file_marker")
+            .unwrap();
+        assert_eq!(results.len(), 2);
+        let mut paths: Vec<PathBuf> = results
+            .iter()
+            .map(|result| {
+                assert!(result.snippet.is_some(), "read back from {}", result.path);
+                decode_path(&result.path)
+            })
+            .collect();
+        paths.sort();
+        assert_eq!(
+            paths,
+            vec![
+                file.canonicalize().unwrap(),
+                sibling.canonicalize().unwrap()
+            ]
+        );
+
+        index.remove_path(&file).unwrap();
+        index.flush().unwrap();
+        let hits = index.search("latin1_named_file_marker").unwrap();
+        assert_eq!(hits.len(), 1);
+        assert_eq!(decode_path(&hits[0].path), sibling.canonicalize().unwrap());
+    }
+
+    #[test]
+    fn test_diagnose_reports_missing_trigrams() {
+        let (temp_dir, index) = create_test_index();
+        let file = temp_dir.path().join("a.txt");
+        std::fs::write(&file, "present_text").unwrap();
+        index.index_path(&file).unwrap();
+        index.flush().unwrap();
+
+        let diagnostics = index.diagnose("presqq").unwrap();
+        assert_eq!(diagnostics.files_indexed, 1);
+        assert!(!diagnostics.query_too_short);
+        assert_eq!(diagnostics.missing_trigrams, ["esq", "sqq"]);
+
+        assert!(
+            index
+                .diagnose("present")
+                .unwrap()
+                .missing_trigrams
+                .is_empty()
+        );
+        assert!(index.diagnose("pr").unwrap().query_too_short);
+    }
+
+    #[test]
+    fn test_explain_reports_plan_stages() {
+        let (temp_dir, index) = create_test_index();
+        for (name, text) in [
+            ("a.rs", "fn explain_marker() {}"),
+            ("b.txt", "explain_marker"),
+            ("c.rs", "explain only"),
+        ] {
+            let file = temp_dir.path().join(name);
+            std::fs::write(&file, text).unwrap();
+            index.index_path(&file).unwrap();
+        }
+        index.flush().unwrap();
+
+        let filter = Regex::new(r"\.rs$").unwrap();
+        let plan = index.explain("explain_marker", Some(&filter)).unwrap();
+        assert_eq!(plan.files_indexed, 3);
+        assert_eq!(plan.generation, index.generation().unwrap());
+        assert!(!plan.steps.is_empty());
+        assert!(
+            plan.steps
+                .windows(2)
+                .all(|pair| pair[0].postings <= pair[1].postings)
+        );
+        assert!(
+            plan.steps
+                .iter()
+                .all(|step| step.action == PlanAction::Intersected)
+        );
+        assert_eq!(plan.steps.last().unwrap().candidates, 2);
+        assert_eq!(plan.candidates, 2);
+        assert_eq!(plan.path_matches, 1);
+        assert_eq!(plan.hits.len(), 1);
+        assert!(plan.hits[0].path.ends_with("a.rs"));
+
+        let missing = index.explain("explain_zzz", None).unwrap();
+        assert_eq!(missing.steps[0].postings, 0);
+        assert_eq!(missing.candidates, 0);
+        assert!(
+            missing.steps[1..]
+                .iter()
+                .all(|step| step.action == PlanAction::Skipped)
+        );
+        assert!(index.explain("ex", None).unwrap().steps.is_empty());
+    }
+
+    #[test]
+    fn test_index_stats_rank_trigrams_and_files() {
+        let (temp_dir, index) = create_test_index();
+        let large = temp_dir.path().join("large.txt");
+        std::fs::write(&large, "common words fill this larger file").unwrap();
+        index.index_path(&large).unwrap();
+        for name in ["one.txt", "two.txt"] {
+            let file = temp_dir.path().join(name);
+            std::fs::write(&file, "common").unwrap();
+            index.index_path(&file).unwrap();
+        }
+        index.flush().unwrap();
+
+        let top = top_trigrams_in_database(index.db_path(), 2).unwrap();
+        assert_eq!(top.len(), 2);
+        assert!(top.iter().all(|stat| stat.files == 3 && stat.bytes > 0));
+        assert!(top.iter().all(|stat| "common".contains(&stat.trigram)));
+
+        let largest = largest_files_in_database(index.db_path(), 10).unwrap();
+        assert_eq!(largest.len(), 3);
+        assert!(largest[0].path.ends_with("large.txt"));
+        assert!(largest[0].trigrams > largest[1].trigrams);
+        assert_eq!(largest[1].trigrams, 4);
+    }
+
+    #[test]
+    fn test_extension_stats_group_files_by_extension() {
+        let (temp_dir, index) = create_test_index();
+        let files = [
+            ("bundle.js.map", "mapping line\n".repeat(300)),
+            ("Other.MAP", "other mapping\n".repeat(100)),
+            ("main.rs", "fn main() {}".to_string()),
+            ("Makefile", "all: build".to_string()),
+        ];
+        for (name, content) in &files {
+            let file = temp_dir.path().join(name);
+            std::fs::write(&file, content).unwrap();
+            index.index_path(&file).unwrap();
+        }
+        index.flush().unwrap();
+
+        let stats = extension_stats_in_database(index.db_path()).unwrap();
+        let extensions: Vec<&str> = stats.iter().map(|s| s.extension.as_str()).collect();
+        assert_eq!(extensions.len(), 3);
+        assert!(extensions.contains(&"rs") && extensions.contains(&""));
+        let map = stats.iter().find(|s| s.extension == "map").unwrap();
+        assert_eq!(map.files, 2);
+        assert!(stats.iter().all(|s| s.bytes > 0));
+        assert!(stats.windows(2).all(|pair| pair[0].bytes >= pair[1].bytes));
+    }
+
+    #[test]
+    fn test_list_files_filters_by_path_and_mtime() {
+        let (temp_dir, index) = create_test_index();
+        for name in ["b.rs", "a.rs", "c.txt"] {
+            let file = temp_dir.path().join(name);
+            std::fs::write(&file, "listed content").unwrap();
+            index.index_path(&file).unwrap();
+        }
+        index.flush().unwrap();
+
+        let all = list_files_in_database(index.db_path(), None, None).unwrap();
+        assert_eq!(all.len(), 3);
+        assert!(all.windows(2).all(|pair| pair[0].path < pair[1].path));
+        assert!(all.iter().all(|file| file.last_modified > 0));
+
+        let rust = Regex::new(r"\.rs$").unwrap();
+        let listed = list_files_in_database(index.db_path(), Some(&rust), None).unwrap();
+        assert_eq!(listed.len(), 2);
+        assert!(listed[0].path.ends_with("a.rs"));
+
+        let future = all[0].last_modified + 3600;
+        assert!(
+            list_files_in_database(index.db_path(), None, Some(future))
+                .unwrap()
+                .is_empty()
+        );
+    }
+
+    #[test]
+    fn test_similar_files_ranked_by_jaccard() {
+        let (temp_dir, index) = create_test_index();
+        let body = "fn parse_config(path: &str) -> Config { load(path).unwrap() }\n";
+        let files = [
+            ("original.rs", body.to_string()),
+            ("copy.rs", body.to_string()),
+            ("edited.rs", body.replace("unwrap()", "expect(\"config\")")),
+            ("other.rs", "struct Unrelated;\n".to_string()),
+        ];
+        for (name, content) in &files {
+            let file = temp_dir.path().join(name);
+            std::fs::write(&file, content).unwrap();
+            index.index_path(&file).unwrap();
+        }
+        index.flush().unwrap();
+
+        let original = normalize_path(&temp_dir.path().join("original.rs"));
+        let similar = similar_files_in_database(index.db_path(), &original, 0.5, 10)
+            .unwrap()
+            .unwrap();
+        let names: Vec<&str> = similar
+            .iter()
+            .map(|file| file.path.rsplit(['/', '\\']).next().unwrap())
+            .collect();
+        assert_eq!(names, ["copy.rs", "edited.rs"]);
+        assert_eq!(similar[0].similarity, 1.0);
+        assert!(similar[1].similarity < 1.0 && similar[1].similarity >= 0.5);
+
+        let strict = similar_files_in_database(index.db_path(), &original, 1.0, 10)
+            .unwrap()
+            .unwrap();
+        assert_eq!(strict.len(), 1);
+        let missing = normalize_path(&temp_dir.path().join("missing.rs"));
+        assert!(
+            similar_files_in_database(index.db_path(), &missing, 0.5, 10)
+                .unwrap()
+                .is_none()
+        );
+    }
+
+    #[test]
+    fn test_suggest_files_follows_recorded_renames() {
+        let (temp_dir, index) = create_test_index();
+        let old = temp_dir.path().join("old_parser.rs");
+        let mid = temp_dir.path().join("mid_parser.rs");
+        let new = temp_dir.path().join("new_parser.rs");
+        std::fs::write(&new, "fn parse() {}").unwrap();
+        index.index_path(&new).unwrap();
+        index
+            .record_renames(&[(old.clone(), mid.clone()), (mid, new.clone())])
+            .unwrap();
+        index.flush().unwrap();
+
+        let suggestions = suggest_files_in_database(index.db_path(), "old_parser", 5).unwrap();
+        assert_eq!(suggestions[0].path, normalize_path(&new));
+        assert_eq!(
+            suggestions[0].renamed_from.as_deref(),
+            Some(normalize_path(&old).as_str())
+        );
+
+        // Renames whose target is no longer indexed suggest nothing.
+        index.remove_path(&new).unwrap();
+        index.flush().unwrap();
+        let suggestions = suggest_files_in_database(index.db_path(), "old_parser", 5).unwrap();
+        assert!(suggestions.iter().all(|s| s.renamed_from.is_none()));
+    }
+
+    #[test]
+    fn test_suggest_files_tolerates_typos() {
+        let (temp_dir, index) = create_test_index();
+        for name in ["scanner.rs", "watcher.rs", "storage.rs"] {
+            let file = temp_dir.path().join(name);
+            std::fs::write(&file, "fn f() {}").unwrap();
+            index.index_path(&file).unwrap();
+        }
+        index.flush().unwrap();
+
+        let suggestions = suggest_files_in_database(index.db_path(), "scnaner", 5).unwrap();
+        let names: Vec<&str> = suggestions
+            .iter()
+            .map(|s| s.path.rsplit(['/', '\\']).next().unwrap())
+            .collect();
+        assert_eq!(names, ["scanner.rs"]);
+        assert_eq!(suggestions[0].distance, 1);
+
+        assert!(
+            suggest_files_in_database(index.db_path(), "zz", 5)
+                .unwrap()
+                .is_empty()
+        );
+        assert_eq!(substring_edit_distance(&['a', 'b', 'c'], "xxacbxx"), 1);
+        assert_eq!(substring_edit_distance(&['a', 'b', 'c'], "xxabcxx"), 0);
+    }
+
+    #[test]
+    fn test_remove_prefix_drops_directory_tree() {
+        let (temp_dir, index) = create_test_index();
+        let gone = temp_dir.path().join("gone");
+        let kept = temp_dir.path().join("gone_sibling");
+        std::fs::create_dir_all(gone.join("nested")).unwrap();
+        std::fs::create_dir_all(&kept).unwrap();
+        for file in [
+            gone.join("a.txt"),
+            gone.join("b.txt"),
+            gone.join("nested/c.txt"),
+            kept.join("d.txt"),
+        ] {
+            std::fs::write(&file, "shared_prefix_marker").unwrap();
+            index.index_path(&file).unwrap();
+        }
         index.flush().unwrap();
+        assert_eq!(index.search("shared_prefix_marker").unwrap().len(), 4);
 
-        let hits = index.search("unique_content_for_removal").unwrap();
-        assert_eq!(hits.len(), 1);
-
-        index.remove_path(&test_file).unwrap();
+        std::fs::remove_dir_all(&gone).unwrap();
+        index.remove_prefix(&gone).unwrap();
         index.flush().unwrap();
 
-        let hits = index.search("unique_content_for_removal").unwrap();
-        assert!(hits.is_empty());
+        let hits = index.search("shared_prefix_marker").unwrap();
+        assert_eq!(hits.len(), 1);
+        assert!(hits[0].path.ends_with("d.txt"));
+        let rtxn = index.env.read_txn().unwrap();
+        let token: Token = *b"sha";
+        assert_eq!(
+            read_posting_count(&rtxn, &index.dbs, &token).unwrap(),
+            Some(1)
+        );
+        assert_eq!(index.dbs.files.len(&rtxn).unwrap(), 1);
     }
 
     #[test]
@@ -1429,6 +5591,19 @@ mod tests {
         assert_eq!(hits.len(), 1);
     }
 
+    #[test]
+    fn test_include_paths_round_trip() {
+        let (_temp_dir, index) = create_test_index();
+        assert!(index.include_paths().unwrap().is_empty());
+
+        let paths = vec![PathBuf::from("libs/shared"), PathBuf::from("src/team-a")];
+        index.set_include_paths(&paths).unwrap();
+        assert_eq!(index.include_paths().unwrap(), paths);
+
+        index.set_include_paths(&[]).unwrap();
+        assert!(index.include_paths().unwrap().is_empty());
+    }
+
     #[test]
     fn test_meta_get_set() {
         let (_temp_dir, index) = create_test_index();
@@ -1815,6 +5990,32 @@ mod tests {
         assert!(acquired, "should acquire after expiry");
     }
 
+    #[test]
+    fn test_lease_take_over_orphaned_holder() {
+        let temp_dir = TempDir::new().unwrap();
+        let db_path = temp_dir.path().join("index.mdb");
+        let index = PersistentIndex::open_or_create(&db_path).unwrap();
+
+        index
+            .try_acquire_writer_lease("holder_a", Duration::from_secs(60))
+            .unwrap();
+
+        let acquired = index
+            .try_acquire_writer_lease_with("holder_b", Duration::from_secs(5), |h| h == "holder_c")
+            .unwrap();
+        assert!(!acquired, "live holder should keep its lease");
+
+        let acquired = index
+            .try_acquire_writer_lease_with("holder_b", Duration::from_secs(5), |h| h == "holder_a")
+            .unwrap();
+        assert!(
+            acquired,
+            "orphaned lease should be taken over before expiry"
+        );
+        let (leader, _) = index.read_leader_info().unwrap().unwrap();
+        assert_eq!(leader, "holder_b");
+    }
+
     #[test]
     fn test_lease_renew_correct_holder() {
         let temp_dir = TempDir::new().unwrap();
@@ -1892,8 +6093,160 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_set_sync_mode_switches_env_flags() {
+        let temp_dir = TempDir::new().unwrap();
+        let index = PersistentIndex::open_or_create(&temp_dir.path().join("index.mdb")).unwrap();
+        let flags = || EnvFlags::from_bits_truncate(index.env.get_flags().unwrap());
+        assert!(flags().contains(EnvFlags::NO_META_SYNC));
+
+        index.set_sync_mode(SyncMode::Off).unwrap();
+        assert!(flags().contains(EnvFlags::NO_SYNC));
+        index.set_sync_mode(SyncMode::Full).unwrap();
+        assert!(!flags().intersects(EnvFlags::NO_SYNC | EnvFlags::NO_META_SYNC));
+        index.set_sync_mode(SyncMode::Normal).unwrap();
+        assert!(flags().contains(EnvFlags::NO_META_SYNC));
+        assert!(!flags().contains(EnvFlags::NO_SYNC));
+        assert!(flags().contains(EnvFlags::WRITE_MAP));
+    }
+
+    #[test]
+    fn test_open_with_foreign_lease_disables_writes() {
+        let temp_dir = TempDir::new().unwrap();
+        let db_path = temp_dir.path().join("index.mdb");
+        let file = temp_dir.path().join("held.rs");
+        std::fs::write(&file, "fn held_lease_marker() {}").unwrap();
+        {
+            let index = PersistentIndex::open_or_create(&db_path).unwrap();
+            assert!(index.write_enabled());
+            index
+                .try_acquire_writer_lease("holder_a", Duration::from_secs(60))
+                .unwrap();
+        }
+
+        let index = PersistentIndex::open_or_create(&db_path).unwrap();
+        assert!(!index.write_enabled(), "another holder's lease is active");
+        let err = index.index_path(&file).unwrap_err();
+        assert!(matches!(err, IndexError::Lease(_)), "{err}");
+        assert!(matches!(index.flush(), Err(IndexError::Lease(_))));
+        assert!(index.search("held_lease_marker").unwrap().is_empty());
+
+        assert!(
+            !index
+                .try_acquire_writer_lease("holder_b", Duration::from_secs(5))
+                .unwrap()
+        );
+        assert!(!index.write_enabled());
+        index.release_writer_lease("holder_a").unwrap();
+        assert!(
+            index
+                .try_acquire_writer_lease("holder_b", Duration::from_secs(5))
+                .unwrap()
+        );
+        assert!(index.write_enabled());
+        index.index_path(&file).unwrap();
+        index.flush().unwrap();
+        assert_eq!(index.search("held_lease_marker").unwrap().len(), 1);
+    }
+
+    #[test]
+    fn test_writer_that_lost_lease_cannot_write() {
+        let temp_dir = TempDir::new().unwrap();
+        let db_path = temp_dir.path().join("index.mdb");
+        let file = temp_dir.path().join("lost.rs");
+        std::fs::write(&file, "fn lost_lease_marker() {}").unwrap();
+
+        let old = PersistentIndex::open_or_create(&db_path).unwrap();
+        assert!(
+            old.try_acquire_writer_lease("holder_a", Duration::from_secs(60))
+                .unwrap()
+        );
+        let new = PersistentIndex::open_or_create(&db_path).unwrap();
+        assert!(
+            new.try_acquire_writer_lease_with("holder_b", Duration::from_secs(60), |holder| {
+                holder == "holder_a"
+            })
+            .unwrap()
+        );
+
+        // The old writer still has writes enabled, but nothing it sends
+        // is committed while the new holder's lease is live.
+        assert!(old.write_enabled());
+        old.index_path(&file).unwrap();
+        assert!(matches!(old.flush(), Err(IndexError::Lease(_))));
+        assert!(matches!(
+            old.set_meta("lost_key", "old"),
+            Err(IndexError::Lease(_))
+        ));
+        assert!(matches!(old.clear_content(), Err(IndexError::Lease(_))));
+        assert!(new.search("lost_lease_marker").unwrap().is_empty());
+        assert_eq!(new.get_meta("lost_key").unwrap(), None);
+        old.set_control_meta("indexing_paused", "true").unwrap();
+
+        new.index_path(&file).unwrap();
+        new.flush().unwrap();
+        new.set_meta("lost_key", "new").unwrap();
+        assert_eq!(new.search("lost_lease_marker").unwrap().len(), 1);
+        assert_eq!(new.get_meta("lost_key").unwrap().as_deref(), Some("new"));
+        assert_eq!(
+            new.get_meta("indexing_paused").unwrap().as_deref(),
+            Some("true")
+        );
+    }
+
+    #[test]
+    fn test_ensure_settings_left_to_lease_holder() {
+        let temp_dir = TempDir::new().unwrap();
+        let db_path = temp_dir.path().join("index.mdb");
+        let file = temp_dir.path().join("a.rs");
+        std::fs::write(&file, "fn SettingsOwnerMarker() {}").unwrap();
+
+        let writer = PersistentIndex::open_or_create(&db_path).unwrap();
+        assert!(
+            writer
+                .try_acquire_writer_lease("holder_a", Duration::from_secs(60))
+                .unwrap()
+        );
+        assert!(!writer.ensure_settings().unwrap());
+        writer.index_path(&file).unwrap();
+        writer.flush().unwrap();
+
+        let reader = PersistentIndex::open_or_create(&db_path).unwrap();
+        reader.set_tokenizer(Arc::new(LowercaseTrigrams));
+        assert!(!reader.ensure_settings().unwrap(), "left to the writer");
+        assert_eq!(writer.search("SettingsOwnerMarker").unwrap().len(), 1);
+
+        writer.release_writer_lease("holder_a").unwrap();
+        assert!(
+            reader
+                .try_acquire_writer_lease("holder_b", Duration::from_secs(60))
+                .unwrap()
+        );
+        assert!(reader.ensure_settings().unwrap());
+        assert!(reader.search("settingsownermarker").unwrap().is_empty());
+    }
+
     // ============ set_meta_queued tests ============
 
+    #[test]
+    fn test_search_count() {
+        let (temp_dir, index) = create_test_index();
+        for name in ["a.rs", "b.rs", "c.txt"] {
+            std::fs::write(temp_dir.path().join(name), "fn count_marker() {}").unwrap();
+            index.index_path(&temp_dir.path().join(name)).unwrap();
+        }
+        index.flush().unwrap();
+
+        assert_eq!(index.search_count("count_marker", None).unwrap(), 3);
+        let rs = Regex::new(r"\.rs$").unwrap();
+        assert_eq!(index.search_count("count_marker", Some(&rs)).unwrap(), 2);
+        assert_eq!(index.search_count("absent_marker", None).unwrap(), 0);
+        assert_eq!(
+            count_database_file(index.db_path(), "count_marker", Some(&rs)).unwrap(),
+            2
+        );
+    }
+
     #[test]
     fn test_set_meta_queued() {
         let temp_dir = TempDir::new().unwrap();
@@ -1907,6 +6260,121 @@ mod tests {
         assert_eq!(value.as_deref(), Some("test_value"));
     }
 
+    #[test]
+    fn test_is_empty() {
+        let (temp_dir, index) = create_test_index();
+        assert!(index.is_empty().unwrap());
+
+        let file = temp_dir.path().join("one.rs");
+        std::fs::write(&file, "fn one() {}").unwrap();
+        index.index_path(&file).unwrap();
+        index.flush().unwrap();
+        assert!(!index.is_empty().unwrap());
+    }
+
+    #[test]
+    fn test_batch_takes_priority_jobs_first() {
+        fn meta_job(key: &str) -> IndexJob {
+            IndexJob {
+                payload: IndexPayload::SetMeta {
+                    key: key.to_string(),
+                    value: String::new(),
+                },
+                resp: mpsc::channel().0,
+            }
+        }
+        let priority = PriorityLane::default();
+        priority.lock().unwrap().push_back(meta_job("urgent"));
+
+        let mut batch = Batch::default();
+        batch.take(WriterMsg::Job(meta_job("queued")), &priority);
+        batch.take(WriterMsg::Wake, &priority);
+
+        let keys: Vec<&str> = batch
+            .jobs
+            .iter()
+            .map(|job| match &job.payload {
+                IndexPayload::SetMeta { key, .. } => key.as_str(),
+                _ => unreachable!(),
+            })
+            .collect();
+        assert_eq!(keys, ["urgent", "queued"]);
+        assert!(priority.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_index_path_priority() {
+        let (temp_dir, index) = create_test_index();
+        let queued = temp_dir.path().join("queued.rs");
+        let urgent = temp_dir.path().join("urgent.rs");
+        std::fs::write(&queued, "fn queued_marker() {}").unwrap();
+        std::fs::write(&urgent, "fn urgent_marker() {}").unwrap();
+
+        index.index_path(&queued).unwrap();
+        index.index_path_priority(&urgent).unwrap();
+        index.flush().unwrap();
+
+        assert_eq!(index.search("queued_marker").unwrap().len(), 1);
+        assert_eq!(index.search("urgent_marker").unwrap().len(), 1);
+    }
+
+    #[test]
+    fn test_search_activity_marks_the_writer_contended() {
+        static ACTIVITY: SearchActivity = SearchActivity::new();
+        assert!(!ACTIVITY.contended());
+        let searching = ACTIVITY.begin();
+        assert!(ACTIVITY.contended());
+
+        drop(searching);
+        assert_eq!(ACTIVITY.in_flight.load(Ordering::SeqCst), 0);
+        // Recent searches still count until the quiet period is over.
+        assert!(ACTIVITY.contended());
+    }
+
+    #[test]
+    fn test_idle_commit_without_flush() {
+        let (temp_dir, index) = create_test_index();
+        index.set_idle_commit(Duration::from_millis(20));
+        let file = temp_dir.path().join("idle.rs");
+        std::fs::write(&file, "fn idle_commit_marker() {}").unwrap();
+        index.index_path(&file).unwrap();
+
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while index.search("idle_commit_marker").unwrap().is_empty() {
+            assert!(Instant::now() < deadline, "change never committed");
+            thread::sleep(Duration::from_millis(10));
+        }
+    }
+
+    #[test]
+    fn test_flush_does_not_wait_for_idle_commit() {
+        let (temp_dir, index) = create_test_index();
+        index.set_idle_commit(Duration::from_secs(30));
+        let file = temp_dir.path().join("flushed.rs");
+        std::fs::write(&file, "fn flushed_marker() {}").unwrap();
+        index.index_path(&file).unwrap();
+
+        let started = Instant::now();
+        index.flush().unwrap();
+        assert!(started.elapsed() < Duration::from_secs(5));
+        assert_eq!(index.search("flushed_marker").unwrap().len(), 1);
+    }
+
+    #[test]
+    fn test_flush_reports_queued_write_failure_once() {
+        let (temp_dir, index) = create_test_index();
+        // Longer than LMDB's maximum key size, so the queued write fails.
+        index.set_meta_queued(&"k".repeat(1024), "value").unwrap();
+        assert!(index.flush().is_err());
+        index.flush().unwrap();
+
+        let file = temp_dir.path().join("after.rs");
+        std::fs::write(&file, "fn after_failure_marker() {}").unwrap();
+        index.index_path(&file).unwrap();
+        index.flush().unwrap();
+        assert_eq!(index.search("after_failure_marker").unwrap().len(), 1);
+    }
+
     // ============ write_enabled gate tests ============
 
     #[test]
@@ -1947,4 +6415,363 @@ mod tests {
         let results = index.search("write_enabled_test_positive").unwrap();
         assert_eq!(results.len(), 1);
     }
+
+    // ============ Archive indexing tests ============
+
+    fn write_test_jar(path: &Path, files: &[(&str, &str)]) {
+        let file = std::fs::File::create(path).unwrap();
+        let mut zip = zip::ZipWriter::new(file);
+        for (name, content) in files {
+            zip.start_file(*name, zip::write::SimpleFileOptions::default())
+                .unwrap();
+            zip.write_all(content.as_bytes()).unwrap();
+        }
+        zip.finish().unwrap();
+    }
+
+    fn enable_archives(index: &PersistentIndex, root: &Path) {
+        let mut config = crate::config::IndexConfig::default();
+        config.archives.enabled = true;
+        index.set_content_policy(ContentPolicy::from_config(root, &config).unwrap());
+    }
+
+    #[test]
+    fn test_archive_members_indexed_with_virtual_paths() {
+        let (temp_dir, index) = create_test_index();
+        enable_archives(&index, temp_dir.path());
+        let jar = temp_dir.path().join("foo.jar");
+        write_test_jar(&jar, &[("com/Foo.java", "class ArchivedFooMarker {}")]);
+
+        index.index_path(&jar).unwrap();
+        index.flush().unwrap();
+
+        let results = index.search_with_snippets("ArchivedFooMarker").unwrap();
+        assert_eq!(results.len(), 1);
+        assert!(results[0].path.ends_with("foo.jar!/com/Foo.java"));
+        let snippet = results[0].snippet.as_ref().expect("snippet from archive");
+        assert_eq!(snippet.line_number, 1);
+    }
+
+    #[test]
+    fn test_metadata_only_keeps_binary_paths_searchable() {
+        let (temp_dir, index) = create_test_index();
+        let mut config = crate::config::IndexConfig::default();
+        config.binary.metadata_only = true;
+        config.binary.force_binary = vec!["skipped/**".to_string()];
+        index.set_content_policy(ContentPolicy::from_config(temp_dir.path(), &config).unwrap());
+        let weights = temp_dir.path().join("model_weights.bin");
+        std::fs::write(&weights, b"abc\0def\0model_weights_marker").unwrap();
+        std::fs::create_dir_all(temp_dir.path().join("skipped")).unwrap();
+        let skipped = temp_dir.path().join("skipped/blob.bin");
+        std::fs::write(&skipped, b"abc\0def").unwrap();
+        let text = temp_dir.path().join("text.rs");
+        std::fs::write(&text, "fn f() {}").unwrap();
+        for file in [&weights, &skipped, &text] {
+            index.index_path(file).unwrap();
+        }
+        index.flush().unwrap();
+
+        let by_path = search_files_in_database(index.db_path(), "model_weights").unwrap();
+        assert_eq!(by_path.len(), 1);
+        assert!(
+            search_files_in_database(index.db_path(), "blob")
+                .unwrap()
+                .is_empty()
+        );
+        assert!(index.search("model_weights_marker").unwrap().is_empty());
+        let Some(ShortQuery::Scan(scanned)) =
+            short_query_in_database(index.db_path(), "f", None).unwrap()
+        else {
+            panic!("expected a scan of every file");
+        };
+        assert_eq!(scanned.len(), 1);
+        assert!(scanned[0].path.ends_with("text.rs"));
+    }
+
+    #[test]
+    fn test_content_store_serves_deleted_files() {
+        let (temp_dir, index) = create_test_index();
+        let mut config = crate::config::IndexConfig::default();
+        config.content_store.enabled = true;
+        index.set_content_policy(ContentPolicy::from_config(temp_dir.path(), &config).unwrap());
+        let stored = temp_dir.path().join("stored.rs");
+        std::fs::write(&stored, "fn stored_content_marker() {}\n").unwrap();
+        index.index_path(&stored).unwrap();
+        index.flush().unwrap();
+        std::fs::remove_file(&stored).unwrap();
+
+        let results = index.search_with_snippets("stored_content_marker").unwrap();
+        assert_eq!(results.len(), 1);
+        let snippet = results[0].snippet.as_ref().expect("snippet from store");
+        assert_eq!(snippet.line_number, 1);
+
+        let store = index.content_store();
+        let hits = index.search("stored_content_marker").unwrap();
+        assert_eq!(
+            crate::search::verify_hits(hits, "stored_content_marker", Some(&store)).len(),
+            1
+        );
+
+        index.remove_path(Path::new(&results[0].path)).unwrap();
+        index.flush().unwrap();
+        assert!(store.read(&results[0].path).unwrap().is_none());
+    }
+
+    #[cfg(feature = "encryption")]
+    #[test]
+    fn test_encrypted_content_store() {
+        let (temp_dir, index) = create_test_index();
+        let mut config = crate::config::IndexConfig::default();
+        config.content_store.enabled = true;
+        let key = Arc::new(ContentKey::from_hex(&"a".repeat(64)).unwrap());
+        let policy = ContentPolicy::from_config(temp_dir.path(), &config)
+            .unwrap()
+            .with_content_key(Some(key.clone()));
+        index.set_content_policy(policy.clone());
+        index.ensure_content_key().unwrap();
+        let sealed = temp_dir.path().join("sealed.rs");
+        std::fs::write(&sealed, "fn sealed_content_marker() {}\n").unwrap();
+        index.index_path(&sealed).unwrap();
+        index.flush().unwrap();
+        std::fs::remove_file(&sealed).unwrap();
+
+        let results = index.search_with_snippets("sealed_content_marker").unwrap();
+        assert_eq!(results.len(), 1);
+        assert!(results[0].snippet.is_some());
+
+        let rtxn = index.env.read_txn().unwrap();
+        let file_id = index
+            .dbs
+            .files_by_path
+            .get(&rtxn, &results[0].path)
+            .unwrap()
+            .unwrap();
+        let raw = index.dbs.contents.get(&rtxn, &file_id).unwrap().unwrap();
+        assert_eq!(&raw[..2], &[BLOB_TAG, BLOB_SEALED]);
+        assert!(read_stored_content(&rtxn, &index.dbs, file_id, None).is_err());
+        drop(rtxn);
+
+        let other = Arc::new(ContentKey::from_hex(&"b".repeat(64)).unwrap());
+        index.set_content_policy(policy.with_content_key(Some(other)));
+        assert!(matches!(
+            index.ensure_content_key(),
+            Err(IndexError::Config(_))
+        ));
+    }
+
+    #[test]
+    fn test_secret_lines_hidden_from_search_and_snippets() {
+        let (temp_dir, index) = create_test_index();
+        let mut config = crate::config::IndexConfig::default();
+        config.secrets.patterns = vec!["AWS_SECRET".to_string()];
+        index.set_content_policy(ContentPolicy::from_config(temp_dir.path(), &config).unwrap());
+        let env_file = temp_dir.path().join("deploy.sh");
+        std::fs::write(
+            &env_file,
+            "deploy_marker_line\nexport AWS_SECRET=hunter2\nrun\n",
+        )
+        .unwrap();
+        index.index_path(&env_file).unwrap();
+        index.flush().unwrap();
+
+        assert!(index.search("hunter2").unwrap().is_empty());
+        let results = index.search_with_snippets("deploy_marker_line").unwrap();
+        let snippet = results[0].snippet.as_ref().expect("snippet");
+        assert!(
+            snippet
+                .lines
+                .iter()
+                .all(|(_, line)| !line.contains("hunter2"))
+        );
+        assert_eq!(snippet.lines[2], (3, "run".to_string()));
+    }
+
+    #[test]
+    fn test_archive_disabled_by_default() {
+        let (temp_dir, index) = create_test_index();
+        let jar = temp_dir.path().join("foo.jar");
+        write_test_jar(&jar, &[("com/Foo.java", "class DisabledArchiveMarker {}")]);
+
+        index.index_path(&jar).unwrap();
+        index.flush().unwrap();
+
+        assert!(index.search("DisabledArchiveMarker").unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_archive_removal_and_stale_members() {
+        let (temp_dir, index) = create_test_index();
+        enable_archives(&index, temp_dir.path());
+        let jar = temp_dir.path().join("foo.jar");
+        write_test_jar(
+            &jar,
+            &[
+                ("a.txt", "member_alpha_marker"),
+                ("b.txt", "member_beta_marker"),
+            ],
+        );
+        index.index_path(&jar).unwrap();
+        index.flush().unwrap();
+        assert_eq!(index.search("member_beta_marker").unwrap().len(), 1);
+
+        // Rewriting the archive without b.txt drops its virtual path.
+        write_test_jar(&jar, &[("a.txt", "member_alpha_marker")]);
+        index.index_path(&jar).unwrap();
+        index.flush().unwrap();
+        assert!(index.search("member_beta_marker").unwrap().is_empty());
+        assert_eq!(index.search("member_alpha_marker").unwrap().len(), 1);
+
+        std::fs::remove_file(&jar).unwrap();
+        index.remove_path(&jar).unwrap();
+        index.flush().unwrap();
+        assert!(index.search("member_alpha_marker").unwrap().is_empty());
+    }
+
+    // ============ Tokenizer tests ============
+
+    struct LowercaseTrigrams;
+
+    impl Tokenizer for LowercaseTrigrams {
+        fn id(&self) -> &'static str {
+            "lowercase-trigram-test"
+        }
+
+        fn document_tokens(&self, text: &str) -> Vec<crate::tokenizer::Token> {
+            crate::text::collect_trigrams(&text.to_lowercase())
+        }
+
+        fn query_tokens(&self, query: &str) -> Vec<crate::tokenizer::Token> {
+            crate::text::collect_trigrams(&query.to_lowercase())
+        }
+    }
+
+    #[test]
+    fn test_ensure_tokenizer_records_default() {
+        let (_temp_dir, index) = create_test_index();
+        assert!(!index.ensure_tokenizer().unwrap());
+        assert_eq!(
+            index.get_meta(TOKENIZER_META_KEY).unwrap().as_deref(),
+            Some("trigram-v1")
+        );
+    }
+
+    #[test]
+    fn test_ensure_tokenizer_clears_on_change() {
+        let (temp_dir, index) = create_test_index();
+        let file = temp_dir.path().join("a.rs");
+        std::fs::write(&file, "fn TokenizerSwitchMarker() {}").unwrap();
+        index.index_path(&file).unwrap();
+        index.flush().unwrap();
+        index.set_meta("git_head", "abc123").unwrap();
+        assert!(!index.ensure_tokenizer().unwrap());
+
+        index.set_tokenizer(Arc::new(LowercaseTrigrams));
+        assert!(
+            index.ensure_tokenizer().unwrap(),
+            "mismatch triggers rebuild"
+        );
+        assert!(index.search("TokenizerSwitchMarker").unwrap().is_empty());
+        assert_eq!(index.get_meta("git_head").unwrap(), None);
+
+        index.index_path(&file).unwrap();
+        index.flush().unwrap();
+        assert_eq!(index.search("tokenizerswitchmarker").unwrap().len(), 1);
+        assert!(!index.ensure_tokenizer().unwrap());
+    }
+
+    // ============ Snapshot tests ============
+
+    #[test]
+    fn test_snapshot_export_import_relocates_paths() {
+        let temp_dir = TempDir::new().unwrap();
+        let old_root = temp_dir.path().join("ci_checkout");
+        std::fs::create_dir_all(old_root.join("src")).unwrap();
+        std::fs::write(
+            old_root.join("src").join("lib.rs"),
+            "fn snapshot_marker() {}",
+        )
+        .unwrap();
+        std::fs::write(
+            temp_dir.path().join("outside.rs"),
+            "fn snapshot_marker() {}",
+        )
+        .unwrap();
+
+        let old_db = temp_dir.path().join("old.mdb");
+        let index = PersistentIndex::open_or_create(&old_db).unwrap();
+        index.ensure_tokenizer().unwrap();
+        index
+            .index_path(&old_root.join("src").join("lib.rs"))
+            .unwrap();
+        index
+            .index_path(&temp_dir.path().join("outside.rs"))
+            .unwrap();
+        index.flush().unwrap();
+        index.set_meta("git_head", "abc123").unwrap();
+        drop(index);
+
+        let mut snapshot = export_snapshot(&old_db, &old_root).unwrap();
+        assert_eq!(
+            snapshot.files.len(),
+            1,
+            "files outside the root are skipped"
+        );
+        assert_eq!(snapshot.files[0].path, "src/lib.rs");
+        assert_eq!(snapshot.git_head.as_deref(), Some("abc123"));
+        // Mtimes have one-second resolution; make the checkout's edit below
+        // newer than the snapshot.
+        snapshot.files[0].last_modified -= 10;
+
+        let new_root = temp_dir.path().join("dev_checkout");
+        std::fs::create_dir_all(&new_root).unwrap();
+        let new_db = temp_dir.path().join("new.mdb");
+        let index = PersistentIndex::open_or_create(&new_db).unwrap();
+        assert_eq!(index.import_snapshot(&new_root, &snapshot).unwrap(), 1);
+
+        let hits = index.search("snapshot_marker").unwrap();
+        assert_eq!(hits.len(), 1);
+        assert!(path_is_within_root(&hits[0].path, &new_root));
+        assert_eq!(
+            index.get_meta("git_head").unwrap().as_deref(),
+            Some("abc123")
+        );
+
+        // The writer picks up imported ids: re-indexing updates in place.
+        let file = new_root.join("src").join("lib.rs");
+        std::fs::create_dir_all(file.parent().unwrap()).unwrap();
+        std::fs::write(&file, "fn renamed_marker() {}").unwrap();
+        index.index_path(&file).unwrap();
+        index.flush().unwrap();
+        assert!(index.search("snapshot_marker").unwrap().is_empty());
+        assert_eq!(index.search("renamed_marker").unwrap().len(), 1);
+    }
+
+    #[test]
+    fn test_snapshot_import_rejects_tokenizer_mismatch() {
+        let (temp_dir, index) = create_test_index();
+        let snapshot = IndexSnapshot {
+            tokenizer: "lowercase-trigram-test".to_string(),
+            git_head: None,
+            files: Vec::new(),
+            trigrams: Vec::new(),
+        };
+        let err = index
+            .import_snapshot(temp_dir.path(), &snapshot)
+            .unwrap_err();
+        assert!(matches!(err, IndexError::Config(_)));
+    }
+
+    #[test]
+    fn test_pause_writes_restores_previous_state() {
+        let (_temp_dir, index) = create_test_index();
+        {
+            let _paused = index.pause_writes();
+            assert!(!index.write_enabled());
+        }
+        assert!(index.write_enabled());
+
+        index.set_write_enabled(false);
+        drop(index.pause_writes());
+        assert!(!index.write_enabled());
+    }
 }
@@ -0,0 +1,257 @@
+//! Lightweight lexing of source files into code, comments and string
+//! literals, so matches can be filtered by where they occur.
+//!
+//! The lexers only know each language's comment and string delimiters;
+//! they do not parse. Files of unknown languages are all code.
+
+use std::collections::BTreeSet;
+use std::path::Path;
+
+use crate::text::line_numbers;
+
+/// Where in a source file a match sits.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum MatchKind {
+    Code,
+    Comment,
+    String,
+}
+
+/// Comment and string delimiters of a language.
+struct Syntax {
+    line_comments: &'static [&'static str],
+    block_comments: &'static [(&'static str, &'static str)],
+    /// String delimiters, tried in order. A string ends at its delimiter
+    /// (a backslash escapes it) or, unless multiline, at the end of the line.
+    strings: &'static [(&'static str, bool)],
+}
+
+const C_LIKE: Syntax = Syntax {
+    line_comments: &["//"],
+    block_comments: &[("/*", "*/")],
+    strings: &[("\"", false), ("'", false)],
+};
+
+// `'` also starts lifetimes, so only double-quoted strings are lexed.
+const RUST: Syntax = Syntax {
+    line_comments: &["//"],
+    block_comments: &[("/*", "*/")],
+    strings: &[("\"", true)],
+};
+
+const JAVASCRIPT: Syntax = Syntax {
+    line_comments: &["//"],
+    block_comments: &[("/*", "*/")],
+    strings: &[("\"", false), ("'", false), ("`", true)],
+};
+
+const PYTHON: Syntax = Syntax {
+    line_comments: &["#"],
+    block_comments: &[],
+    strings: &[("\"\"\"", true), ("'''", true), ("\"", false), ("'", false)],
+};
+
+const HASH: Syntax = Syntax {
+    line_comments: &["#"],
+    block_comments: &[],
+    strings: &[("\"", false), ("'", false)],
+};
+
+const POWERSHELL: Syntax = Syntax {
+    line_comments: &["#"],
+    block_comments: &[("<#", "#>")],
+    strings: &[("\"", false), ("'", false)],
+};
+
+const PHP: Syntax = Syntax {
+    line_comments: &["//", "#"],
+    block_comments: &[("/*", "*/")],
+    strings: &[("\"", true), ("'", true)],
+};
+
+const SQL: Syntax = Syntax {
+    line_comments: &["--"],
+    block_comments: &[("/*", "*/")],
+    strings: &[("'", false), ("\"", false)],
+};
+
+const LUA: Syntax = Syntax {
+    line_comments: &["--"],
+    block_comments: &[("--[[", "]]")],
+    strings: &[("\"", false), ("'", false)],
+};
+
+const HASKELL: Syntax = Syntax {
+    line_comments: &["--"],
+    block_comments: &[("{-", "-}")],
+    strings: &[("\"", false)],
+};
+
+const CSS: Syntax = Syntax {
+    line_comments: &[],
+    block_comments: &[("/*", "*/")],
+    strings: &[("\"", false), ("'", false)],
+};
+
+const SCSS: Syntax = Syntax {
+    line_comments: &["//"],
+    block_comments: &[("/*", "*/")],
+    strings: &[("\"", false), ("'", false)],
+};
+
+const MARKUP: Syntax = Syntax {
+    line_comments: &[],
+    block_comments: &[("<!--", "-->")],
+    strings: &[("\"", false)],
+};
+
+fn syntax_for(path: &Path) -> Option<&'static Syntax> {
+    let ext = path.extension()?.to_str()?.to_ascii_lowercase();
+    Some(match ext.as_str() {
+        "rs" => &RUST,
+        "c" | "h" | "cc" | "cpp" | "cxx" | "hh" | "hpp" | "hxx" | "m" | "mm" | "cs" | "java"
+        | "kt" | "kts" | "scala" | "swift" | "dart" | "groovy" | "gradle" | "proto" => &C_LIKE,
+        "js" | "jsx" | "mjs" | "cjs" | "ts" | "tsx" | "mts" | "cts" | "go" => &JAVASCRIPT,
+        "py" | "pyi" => &PYTHON,
+        "sh" | "bash" | "zsh" | "fish" | "rb" | "pl" | "pm" | "r" | "yaml" | "yml" | "toml"
+        | "cmake" | "tf" | "nix" | "ex" | "exs" => &HASH,
+        "ps1" | "psm1" | "psd1" => &POWERSHELL,
+        "php" => &PHP,
+        "sql" => &SQL,
+        "lua" => &LUA,
+        "hs" | "elm" => &HASKELL,
+        "css" => &CSS,
+        "scss" | "less" => &SCSS,
+        "html" | "htm" | "xml" | "xaml" | "csproj" | "props" | "targets" | "svg" | "vue" => &MARKUP,
+        _ => return None,
+    })
+}
+
+/// The kind of every byte of `text`, the content of `path`.
+pub fn classify(path: &Path, text: &str) -> Vec<MatchKind> {
+    let bytes = text.as_bytes();
+    let mut kinds = vec![MatchKind::Code; bytes.len()];
+    let Some(syntax) = syntax_for(path) else {
+        return kinds;
+    };
+
+    let mut i = 0;
+    while i < bytes.len() {
+        let rest = &bytes[i..];
+        if let Some((open, close)) = syntax
+            .block_comments
+            .iter()
+            .find(|(open, _)| rest.starts_with(open.as_bytes()))
+        {
+            let body = i + open.len();
+            let end = find(&bytes[body..], close.as_bytes())
+                .map_or(bytes.len(), |at| body + at + close.len());
+            kinds[i..end].fill(MatchKind::Comment);
+            i = end;
+        } else if syntax
+            .line_comments
+            .iter()
+            .any(|open| rest.starts_with(open.as_bytes()))
+        {
+            let end = find(rest, b"\n").map_or(bytes.len(), |at| i + at);
+            kinds[i..end].fill(MatchKind::Comment);
+            i = end;
+        } else if let Some((quote, multiline)) = syntax
+            .strings
+            .iter()
+            .find(|(quote, _)| rest.starts_with(quote.as_bytes()))
+        {
+            let end = string_end(bytes, i + quote.len(), quote.as_bytes(), *multiline);
+            kinds[i..end].fill(MatchKind::String);
+            i = end;
+        } else {
+            i += 1;
+        }
+    }
+    kinds
+}
+
+/// End of a string whose body starts at `start`, past its closing quote.
+fn string_end(bytes: &[u8], start: usize, quote: &[u8], multiline: bool) -> usize {
+    let mut j = start;
+    while j < bytes.len() {
+        if bytes[j] == b'\\' {
+            j += 2;
+        } else if bytes[j..].starts_with(quote) {
+            return j + quote.len();
+        } else if bytes[j] == b'\n' && !multiline {
+            return j;
+        } else {
+            j += 1;
+        }
+    }
+    bytes.len()
+}
+
+fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack
+        .windows(needle.len())
+        .position(|window| window == needle)
+}
+
+/// Line numbers (1-based) of `text`, the content of `path`, holding an
+/// occurrence of `query` that starts in `kind`.
+pub fn lines_with_kind(path: &Path, text: &str, query: &str, kind: MatchKind) -> BTreeSet<usize> {
+    if query.is_empty() {
+        return BTreeSet::new();
+    }
+    let kinds = classify(path, text);
+    line_numbers(
+        text,
+        text.match_indices(query)
+            .map(|(offset, _)| offset)
+            .filter(|&offset| kinds[offset] == kind),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn lines(path: &str, text: &str, query: &str, kind: MatchKind) -> Vec<usize> {
+        lines_with_kind(Path::new(path), text, query, kind)
+            .into_iter()
+            .collect()
+    }
+
+    #[test]
+    fn test_rust_comments_and_strings() {
+        let text = "fn needle() {}\n// calls needle\nlet s = \"needle\";\n/* needle\n needle */ needle();\n";
+        assert_eq!(lines("a.rs", text, "needle", MatchKind::Code), [1, 5]);
+        assert_eq!(lines("a.rs", text, "needle", MatchKind::Comment), [2, 4, 5]);
+        assert_eq!(lines("a.rs", text, "needle", MatchKind::String), [3]);
+    }
+
+    #[test]
+    fn test_strings_hide_comment_markers() {
+        let text = "url = \"http://needle\"\nx = 1 // needle\n";
+        assert_eq!(lines("a.js", text, "needle", MatchKind::String), [1]);
+        assert_eq!(lines("a.js", text, "needle", MatchKind::Comment), [2]);
+    }
+
+    #[test]
+    fn test_escaped_quotes_and_multiline_strings() {
+        let text = "s = 'it\\'s needle'\nd = \"\"\"\nneedle\n\"\"\"\nneedle # needle\n";
+        assert_eq!(lines("a.py", text, "needle", MatchKind::String), [1, 3]);
+        assert_eq!(lines("a.py", text, "needle", MatchKind::Code), [5]);
+        assert_eq!(lines("a.py", text, "needle", MatchKind::Comment), [5]);
+    }
+
+    #[test]
+    fn test_unterminated_single_line_string_ends_at_newline() {
+        let text = "x = \"needle\ny = needle\n";
+        assert_eq!(lines("a.c", text, "needle", MatchKind::Code), [2]);
+    }
+
+    #[test]
+    fn test_unknown_language_is_code() {
+        let text = "// needle\n";
+        assert_eq!(lines("notes.txt", text, "needle", MatchKind::Code), [1]);
+        assert!(lines("notes.txt", text, "needle", MatchKind::Comment).is_empty());
+    }
+}
@@ -0,0 +1,56 @@
+use std::sync::Arc;
+
+use crate::text::collect_trigrams;
+
+/// Token key stored in the `trigrams` database. Tokenizers that produce
+/// longer tokens (words, 4-grams) must fold them into this 3-byte key
+/// space; search results are verified against file content, so collisions
+/// only cost extra candidates.
+pub type Token = [u8; 3];
+
+/// Meta key holding the [`Tokenizer::id`] the index was built with.
+pub const TOKENIZER_META_KEY: &str = "tokenizer";
+
+/// Strategy for turning document and query text into index tokens.
+pub trait Tokenizer: Send + Sync {
+    /// Stable identifier recorded in meta. Bump it whenever the token
+    /// output changes so existing indexes get rebuilt.
+    fn id(&self) -> &'static str;
+
+    /// Sorted, deduplicated tokens for a document.
+    fn document_tokens(&self, text: &str) -> Vec<Token>;
+
+    /// Tokens every matching document must contain. An empty result means
+    /// the query is too short to be answered from the index.
+    fn query_tokens(&self, query: &str) -> Vec<Token>;
+}
+
+/// The default strategy: every 3-byte window of the UTF-8 text.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct TrigramTokenizer;
+
+impl Tokenizer for TrigramTokenizer {
+    fn id(&self) -> &'static str {
+        "trigram-v1"
+    }
+
+    fn document_tokens(&self, text: &str) -> Vec<Token> {
+        collect_trigrams(text)
+    }
+
+    fn query_tokens(&self, query: &str) -> Vec<Token> {
+        collect_trigrams(query)
+    }
+}
+
+pub fn default_tokenizer() -> Arc<dyn Tokenizer> {
+    Arc::new(TrigramTokenizer)
+}
+
+/// Look up a built-in tokenizer by the id stored in meta.
+pub fn tokenizer_for_id(id: &str) -> Option<Arc<dyn Tokenizer>> {
+    match id {
+        "trigram-v1" => Some(default_tokenizer()),
+        _ => None,
+    }
+}
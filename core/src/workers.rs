@@ -0,0 +1,123 @@
+//! The process-wide thread pool for parallel work: scan batches, match
+//! verification and snippet extraction.
+//!
+//! Until [`configure_workers`] is called, [`install`] runs on rayon's
+//! global pool, which starts one thread per core. A configured pool
+//! replaces it for the rest of the process, so a scan on a shared machine
+//! can be kept to a few threads at background priority.
+
+use std::sync::OnceLock;
+
+use rayon::{ThreadPool, ThreadPoolBuilder};
+use tracing::{debug, info};
+
+use crate::config::WorkersConfig;
+use crate::error::{IndexError, IndexResult};
+
+static POOL: OnceLock<ThreadPool> = OnceLock::new();
+
+/// Build the worker pool from `config`. Only the first call takes effect;
+/// later ones return `false` and leave the pool as it is.
+pub fn configure_workers(config: &WorkersConfig) -> IndexResult<bool> {
+    if POOL.get().is_some() {
+        return Ok(false);
+    }
+    let low_priority = config.low_priority;
+    let pool = ThreadPoolBuilder::new()
+        .num_threads(config.threads)
+        .thread_name(|i| format!("sf-worker-{i}"))
+        .start_handler(move |_| {
+            if low_priority {
+                lower_thread_priority();
+            }
+        })
+        .build()
+        .map_err(|err| IndexError::Config(format!("[workers]: {err}")))?;
+    let threads = pool.current_num_threads();
+    let configured = POOL.set(pool).is_ok();
+    if configured {
+        info!(threads, low_priority, "worker pool configured");
+    }
+    Ok(configured)
+}
+
+/// Run `op` on the worker pool, so the parallel iterators inside it use
+/// that pool's threads.
+pub fn install<R: Send>(op: impl FnOnce() -> R + Send) -> R {
+    match POOL.get() {
+        Some(pool) => pool.install(op),
+        None => op(),
+    }
+}
+
+/// Move the calling thread to background CPU and IO priority.
+fn lower_thread_priority() {
+    #[cfg(target_os = "linux")]
+    {
+        // Both calls apply to the thread alone when given its id.
+        const IOPRIO_WHO_PROCESS: libc::c_long = 1;
+        const IOPRIO_IDLE: libc::c_long = 3 << 13;
+        // SAFETY: plain syscalls on the current thread's id.
+        let (cpu, io) = unsafe {
+            let tid = libc::gettid();
+            (
+                libc::setpriority(libc::PRIO_PROCESS, tid as libc::id_t, 10),
+                libc::syscall(
+                    libc::SYS_ioprio_set,
+                    IOPRIO_WHO_PROCESS,
+                    libc::c_long::from(tid),
+                    IOPRIO_IDLE,
+                ),
+            )
+        };
+        if cpu != 0 || io != 0 {
+            debug!(
+                error = %std::io::Error::last_os_error(),
+                "could not lower worker thread priority"
+            );
+        }
+    }
+    #[cfg(target_os = "macos")]
+    {
+        // The background QoS class throttles both CPU and disk IO.
+        // SAFETY: only changes the current thread's QoS class.
+        let status = unsafe {
+            libc::pthread_set_qos_class_self_np(libc::qos_class_t::QOS_CLASS_BACKGROUND, 0)
+        };
+        if status != 0 {
+            debug!(status, "could not lower worker thread priority");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rayon::prelude::*;
+
+    use super::*;
+
+    #[test]
+    fn test_install_runs_on_the_configured_pool() {
+        let config = WorkersConfig {
+            threads: 2,
+            low_priority: true,
+        };
+        assert!(configure_workers(&config).unwrap());
+        assert!(!configure_workers(&WorkersConfig::default()).unwrap());
+
+        let threads = install(rayon::current_num_threads);
+        assert_eq!(threads, 2);
+        let names: Vec<String> = install(|| {
+            (0..8)
+                .into_par_iter()
+                .map(|_| {
+                    std::thread::current()
+                        .name()
+                        .unwrap_or_default()
+                        .to_string()
+                })
+                .collect()
+        });
+        assert!(names.iter().all(|name| name.starts_with("sf-worker-")));
+    }
+}
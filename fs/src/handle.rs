@@ -0,0 +1,76 @@
+//! Scanning and watching for an [`IndexHandle`] opened by embedders.
+
+use std::future::Future;
+use std::sync::Arc;
+use std::sync::atomic::AtomicBool;
+
+use source_fast_core::{IndexError, IndexHandle};
+use source_fast_progress::ScanEvent;
+
+use crate::scanner::smart_scan_with_progress;
+use crate::watcher::background_watcher_with_health;
+
+/// The filesystem side of an [`IndexHandle`]: bring the index up to date
+/// with its root, then keep it current.
+pub trait IndexHandleExt {
+    /// Index whatever changed under the root since the last scan.
+    fn scan(&self) -> Result<(), IndexError> {
+        self.scan_with_progress(Arc::new(|_| {}))
+    }
+
+    fn scan_with_progress(
+        &self,
+        progress: Arc<dyn Fn(ScanEvent) + Send + Sync>,
+    ) -> Result<(), IndexError>;
+
+    /// Watch the root with the handle's `[watcher]` settings until `cancel`
+    /// is set.
+    fn watch(&self, cancel: Arc<AtomicBool>) -> impl Future<Output = notify::Result<()>> + Send;
+}
+
+impl IndexHandleExt for IndexHandle {
+    fn scan_with_progress(
+        &self,
+        progress: Arc<dyn Fn(ScanEvent) + Send + Sync>,
+    ) -> Result<(), IndexError> {
+        smart_scan_with_progress(self.root(), Arc::clone(self.index()), progress)
+    }
+
+    fn watch(&self, cancel: Arc<AtomicBool>) -> impl Future<Output = notify::Result<()>> + Send {
+        background_watcher_with_health(
+            self.root().to_path_buf(),
+            Arc::clone(self.index()),
+            cancel,
+            self.config().watcher.clone(),
+            Arc::new(|_| {}),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use source_fast_core::IndexBuilder;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_handle_scan_indexes_root() {
+        let temp = TempDir::new().unwrap();
+        let root = temp.path().join("root");
+        std::fs::create_dir_all(root.join("src")).unwrap();
+        std::fs::write(root.join("src/lib.rs"), "fn embedded_marker() {}").unwrap();
+        std::fs::write(root.join("skip.log"), "fn embedded_marker() {}").unwrap();
+
+        let handle = IndexBuilder::new(&root)
+            .db_path(temp.path().join("index.mdb"))
+            .ignore("*.log")
+            .open()
+            .unwrap();
+        handle.scan().unwrap();
+        handle.flush().unwrap();
+
+        let hits = handle.search("embedded_marker").unwrap();
+        assert_eq!(hits.len(), 1, "{hits:?}");
+        assert!(hits[0].path.ends_with("lib.rs"));
+    }
+}
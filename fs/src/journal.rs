@@ -0,0 +1,181 @@
+//! Crash-safe record of the changes a watcher has seen but the index has
+//! not committed yet.
+//!
+//! Every watcher appends the paths its events name to a file of its own in
+//! `watcher-journal/` next to the index, and empties it once the changes
+//! are committed. A file left behind by a process that died is replayed by
+//! the next startup scan, which re-indexes or removes each path as it now
+//! is on disk.
+
+use std::collections::HashSet;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::AtomicBool;
+use std::sync::{Arc, OnceLock};
+
+use source_fast_core::{IndexError, PersistentIndex, decode_path, encode_path, now_millis};
+use tracing::{info, warn};
+
+use crate::scanner::apply_changes_by_files_with_progress_cancel;
+
+/// Directory, next to the index, holding one journal per watcher process.
+const JOURNAL_DIR: &str = "watcher-journal";
+
+/// The journal file name of this process: its id and start time, so a
+/// later process reusing the id does not mistake a dead one's journal for
+/// its own.
+fn own_name() -> &'static str {
+    static NAME: OnceLock<String> = OnceLock::new();
+    NAME.get_or_init(|| format!("{}-{}", std::process::id(), now_millis()))
+}
+
+fn journal_dir(index: &PersistentIndex) -> PathBuf {
+    index
+        .db_path()
+        .parent()
+        .unwrap_or(Path::new("."))
+        .join(JOURNAL_DIR)
+}
+
+/// This process's journal for `index`.
+pub(crate) struct Journal {
+    path: PathBuf,
+    written: bool,
+}
+
+impl Journal {
+    pub(crate) fn new(index: &PersistentIndex) -> Self {
+        Self {
+            path: journal_dir(index).join(own_name()),
+            written: false,
+        }
+    }
+
+    /// Append `paths`, NUL-separated. The file is opened for each batch,
+    /// so a replay that removes it never takes later records with it.
+    pub(crate) fn record<'a>(&mut self, paths: impl IntoIterator<Item = &'a PathBuf>) {
+        let mut records = Vec::new();
+        for path in paths {
+            records.extend_from_slice(encode_path(path).as_bytes());
+            records.push(0);
+        }
+        if records.is_empty() {
+            return;
+        }
+        let result = self
+            .path
+            .parent()
+            .map_or(Ok(()), std::fs::create_dir_all)
+            .and_then(|()| {
+                std::fs::OpenOptions::new()
+                    .create(true)
+                    .append(true)
+                    .open(&self.path)
+            })
+            .and_then(|mut file| file.write_all(&records));
+        match result {
+            Ok(()) => self.written = true,
+            Err(err) => {
+                warn!(path = %self.path.display(), error = %err, "failed to journal watcher changes")
+            }
+        }
+    }
+
+    /// Forget the recorded paths once their changes are committed.
+    pub(crate) fn clear(&mut self) {
+        if !self.written {
+            return;
+        }
+        match std::fs::remove_file(&self.path) {
+            Ok(()) => self.written = false,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => self.written = false,
+            Err(err) => {
+                warn!(path = %self.path.display(), error = %err, "failed to clear the watcher journal")
+            }
+        }
+    }
+}
+
+/// Apply the changes journaled by watchers of other processes that did not
+/// get to commit them, then delete their journals. Returns how many paths
+/// were replayed.
+pub fn replay_watcher_journals(
+    root: &Path,
+    index: &PersistentIndex,
+    cancel: &Arc<AtomicBool>,
+) -> Result<usize, IndexError> {
+    let Ok(entries) = std::fs::read_dir(journal_dir(index)) else {
+        return Ok(0);
+    };
+    let mut files = Vec::new();
+    let mut paths: HashSet<PathBuf> = HashSet::new();
+    for entry in entries.flatten() {
+        if entry.file_name() == own_name() {
+            continue;
+        }
+        let file = entry.path();
+        match std::fs::read(&file) {
+            Ok(records) => {
+                paths.extend(
+                    records
+                        .split(|&byte| byte == 0)
+                        .filter(|record| !record.is_empty())
+                        .map(|record| decode_path(&String::from_utf8_lossy(record))),
+                );
+                files.push(file);
+            }
+            Err(err) => {
+                warn!(path = %file.display(), error = %err, "failed to read a watcher journal")
+            }
+        }
+    }
+
+    let replayed = paths.len();
+    if replayed > 0 {
+        info!(
+            paths = replayed,
+            "replaying watcher changes that were not committed"
+        );
+        apply_changes_by_files_with_progress_cancel(
+            root,
+            index,
+            paths,
+            Arc::new(|_| {}),
+            Arc::clone(cancel),
+        )?;
+        index.flush()?;
+    }
+    if index.write_enabled() {
+        for file in files {
+            let _ = std::fs::remove_file(file);
+        }
+    }
+    Ok(replayed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_replay_indexes_journal_left_by_dead_process() {
+        let temp = TempDir::new().unwrap();
+        let root = temp.path().join("root");
+        std::fs::create_dir_all(&root).unwrap();
+        let index = PersistentIndex::open_or_create(&temp.path().join("index.mdb")).unwrap();
+        let edited = root.join("edited.txt");
+        std::fs::write(&edited, "journaled_marker").unwrap();
+
+        let dir = journal_dir(&index);
+        std::fs::create_dir_all(&dir).unwrap();
+        let record = format!("{}\0", encode_path(&edited));
+        std::fs::write(dir.join("1-0"), record).unwrap();
+
+        let cancel = Arc::new(AtomicBool::new(false));
+        assert_eq!(replay_watcher_journals(&root, &index, &cancel).unwrap(), 1);
+        assert_eq!(index.search("journaled_marker").unwrap().len(), 1);
+        assert!(!dir.join("1-0").exists());
+        assert_eq!(replay_watcher_journals(&root, &index, &cancel).unwrap(), 0);
+    }
+}
@@ -1,8 +1,22 @@
+mod handle;
+mod journal;
 mod scanner;
+mod throttle;
 mod watcher;
 
+pub use handle::IndexHandleExt;
+pub use journal::replay_watcher_journals;
 pub use scanner::{
-    DryRunInfo, DryRunMode, dry_run_scan, initial_scan, smart_scan, smart_scan_with_progress,
-    smart_scan_with_progress_cancel,
+    DryRunInfo, DryRunMode, GIT_DIR_META_KEY, GIT_WORK_TREE_META_KEY, GitLocation, HeadDrift,
+    SCAN_CURSOR_META_KEY, ScanLimits, ScanStatus, catch_up_scan, dry_run_scan, head_drift,
+    initial_scan, smart_scan, smart_scan_prioritized, smart_scan_with_limits,
+    smart_scan_with_progress, smart_scan_with_progress_cancel,
 };
-pub use watcher::{background_watcher, background_watcher_with_cancel};
+pub use throttle::{Throttle, set_throttle, throttle};
+pub use watcher::{
+    INDEXING_PAUSED_META_KEY, background_watcher, background_watcher_pausable,
+    background_watcher_with_cancel, background_watcher_with_health, indexing_paused,
+    reconcile_periodically, set_indexing_paused,
+};
+
+pub use source_fast_core::{IndexBuilder, IndexHandle};
@@ -1,17 +1,80 @@
-use std::collections::HashSet;
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
 use std::path::{Path, PathBuf};
 use std::sync::Arc;
 use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
 
 use gix::Repository;
 use gix::bstr::ByteSlice;
 use gix::object::tree::diff::ChangeDetached;
 use ignore::WalkBuilder;
 use rayon::prelude::*;
-use source_fast_core::{IndexError, PersistentIndex, path_is_within_root};
+use source_fast_core::{
+    ContentHash, IndexError, PersistentIndex, content_hash, decode_path, encode_path,
+    file_modified_timestamp, fold_path, normalize_path, path_is_within_root, workers,
+};
 use source_fast_progress::{ScanEvent, ScanMode, ScanPlan};
 use tracing::{debug, info, warn};
 
+use crate::journal::replay_watcher_journals;
+use crate::throttle;
+
+/// Meta key of the last path a full walk indexed, in walk order. Present
+/// while a walk is unfinished, so the next scan resumes after it.
+pub const SCAN_CURSOR_META_KEY: &str = "scan_cursor";
+
+/// Meta keys of the [`GitLocation`] set with `sf index build --git-dir`
+/// and `--work-tree`; empty or absent when the repository is discovered.
+pub const GIT_DIR_META_KEY: &str = "git_dir";
+pub const GIT_WORK_TREE_META_KEY: &str = "git_work_tree";
+
+/// Files a full walk indexes between checkpoints of its cursor.
+const CHECKPOINT_FILES: usize = 2_000;
+
+/// Files [`smart_scan_prioritized`] indexes ahead of the full scan.
+const PRIORITY_FILES: usize = 500;
+
+/// Ancestors of HEAD [`head_drift`] looks through for the indexed commit.
+const DRIFT_WALK_LIMIT: usize = 1_000;
+
+/// Bounds for one run of a full walk; see [`smart_scan_with_limits`].
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ScanLimits {
+    pub max_files: Option<usize>,
+    pub max_duration: Option<Duration>,
+}
+
+impl ScanLimits {
+    fn is_unlimited(&self) -> bool {
+        self.max_files.is_none() && self.max_duration.is_none()
+    }
+
+    fn reached(&self, files: usize, started: Instant) -> bool {
+        self.max_files.is_some_and(|max| files >= max)
+            || self
+                .max_duration
+                .is_some_and(|max| started.elapsed() >= max)
+    }
+}
+
+/// How a scan run ended.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ScanStatus {
+    Complete,
+    /// A limit stopped the walk; the next scan resumes at its cursor.
+    Paused,
+}
+
+/// How HEAD has moved on from the commit an index was last synced to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HeadDrift {
+    /// HEAD descends from the indexed commit, this many commits later.
+    Behind(usize),
+    /// The indexed commit is not a recent ancestor of HEAD: another branch
+    /// was checked out, or history was rewritten.
+    Diverged,
+}
+
 #[derive(Debug, Clone, Copy)]
 pub enum DryRunMode {
     FullScan,
@@ -44,6 +107,176 @@ fn check_cancel(cancel: &AtomicBool) -> Result<(), IndexError> {
     Ok(())
 }
 
+/// The part of the root scans cover: the index's include paths made
+/// absolute, or the whole root when it has none.
+#[derive(Debug, Clone)]
+pub(crate) struct IncludeScope(Vec<PathBuf>);
+
+impl IncludeScope {
+    pub(crate) fn load(root: &Path, index: &PersistentIndex) -> Self {
+        let paths = index.include_paths().unwrap_or_else(|err| {
+            warn!("failed to read include paths from meta: {err}, covering the whole root");
+            Vec::new()
+        });
+        Self(paths.iter().map(|path| root.join(path)).collect())
+    }
+
+    pub(crate) fn is_restricted(&self) -> bool {
+        !self.0.is_empty()
+    }
+
+    /// The directories to walk (or watch) to cover the scope.
+    pub(crate) fn roots<'a>(&'a self, root: &'a Path) -> Vec<&'a Path> {
+        if self.0.is_empty() {
+            vec![root]
+        } else {
+            self.0.iter().map(PathBuf::as_path).collect()
+        }
+    }
+
+    pub(crate) fn contains(&self, path: &Path) -> bool {
+        let path = encode_path(path);
+        self.0.is_empty() || self.0.iter().any(|dir| path_is_within_root(&path, dir))
+    }
+}
+
+/// Where the git repository of a root lives when it cannot be discovered
+/// upwards from the root: a git dir elsewhere, or the bare repository a
+/// checkout was exported from.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct GitLocation {
+    /// The git dir; the repository is discovered from the root when unset.
+    pub git_dir: Option<PathBuf>,
+    /// The work tree of `git_dir` [default: the root].
+    pub work_tree: Option<PathBuf>,
+}
+
+impl GitLocation {
+    /// `$GIT_DIR` and `$GIT_WORK_TREE`, relative to the current directory.
+    pub fn from_env() -> Self {
+        let var = |name: &str| {
+            std::env::var_os(name)
+                .filter(|value| !value.is_empty())
+                .and_then(|value| std::path::absolute(value).ok())
+        };
+        Self {
+            git_dir: var("GIT_DIR"),
+            work_tree: var("GIT_WORK_TREE"),
+        }
+    }
+
+    /// The location stored in an index's meta, read through `meta`.
+    pub fn from_meta(meta: impl Fn(&str) -> Option<String>) -> Self {
+        let stored = |key: &str| {
+            meta(key)
+                .filter(|value| !value.is_empty())
+                .map(|value| decode_path(&value))
+        };
+        Self {
+            git_dir: stored(GIT_DIR_META_KEY),
+            work_tree: stored(GIT_WORK_TREE_META_KEY),
+        }
+    }
+
+    /// This location, or [`GitLocation::from_env`] when it is unset.
+    pub fn or_env(self) -> Self {
+        if self == Self::default() {
+            Self::from_env()
+        } else {
+            self
+        }
+    }
+
+    /// The location scans of `index` use: the stored one, or the
+    /// environment's.
+    pub fn load(index: &PersistentIndex) -> Self {
+        Self::from_meta(|key| index.get_meta(key).ok().flatten()).or_env()
+    }
+
+    /// Store the location in the meta of `index`, whose scans and watcher
+    /// then use it.
+    pub fn store(&self, index: &PersistentIndex) -> Result<(), IndexError> {
+        let value = |path: &Option<PathBuf>| path.as_deref().map(encode_path).unwrap_or_default();
+        index.set_meta(GIT_DIR_META_KEY, &value(&self.git_dir))?;
+        index.set_meta(GIT_WORK_TREE_META_KEY, &value(&self.work_tree))
+    }
+
+    /// The repository of `root`: the one at `git_dir`, checked out in the
+    /// work tree even when it is bare, or else the one `root` is in.
+    pub(crate) fn open(
+        &self,
+        root: &Path,
+    ) -> Result<Repository, Box<dyn std::error::Error + Send + Sync>> {
+        let Some(git_dir) = &self.git_dir else {
+            return Ok(gix::discover(root)?);
+        };
+        let work_tree = self.work_tree.as_deref().unwrap_or(root);
+        let options = gix::open::Options::default().config_overrides([
+            "core.bare=false".to_string(),
+            format!("core.worktree={}", work_tree.display()),
+        ]);
+        Ok(gix::open_opts(git_dir, options)?)
+    }
+}
+
+/// Git's own files below a root, which are never indexed: anything named
+/// `.git` at any depth (a directory, or in linked worktrees and submodules
+/// a file pointing at the real git dir), the git dir the root's `.git`
+/// file points at, and `$GIT_DIR`.
+#[derive(Debug, Clone)]
+pub(crate) struct GitPaths {
+    root: PathBuf,
+    /// Git dirs outside a `.git` entry, with the common dir of a linked
+    /// worktree's git dir.
+    dirs: Vec<PathBuf>,
+}
+
+impl GitPaths {
+    pub(crate) fn load(root: &Path) -> Self {
+        let mut dirs = Vec::new();
+        if let Some(git_dir) = std::env::var_os("GIT_DIR").filter(|dir| !dir.is_empty()) {
+            dirs.extend(std::path::absolute(git_dir).ok());
+        }
+        let dot_git = root.join(".git");
+        if dot_git.is_file() {
+            dirs.extend(gitdir_of(&dot_git));
+        }
+        let common: Vec<PathBuf> = dirs.iter().filter_map(|dir| commondir_of(dir)).collect();
+        dirs.extend(common);
+        Self {
+            root: root.to_path_buf(),
+            dirs,
+        }
+    }
+
+    pub(crate) fn contains(&self, path: &Path) -> bool {
+        let relative = path.strip_prefix(&self.root).unwrap_or(path);
+        if relative
+            .components()
+            .any(|component| component.as_os_str() == ".git")
+        {
+            return true;
+        }
+        let path = encode_path(path);
+        self.dirs.iter().any(|dir| path_is_within_root(&path, dir))
+    }
+}
+
+/// The git dir a `.git` file points at with its `gitdir: <path>` line,
+/// relative to the file's directory.
+fn gitdir_of(dot_git: &Path) -> Option<PathBuf> {
+    let text = std::fs::read_to_string(dot_git).ok()?;
+    let target = text.lines().next()?.strip_prefix("gitdir:")?.trim();
+    Some(dot_git.parent()?.join(target))
+}
+
+/// The repository's common git dir when `git_dir` belongs to a linked
+/// worktree, as named by its `commondir` file.
+fn commondir_of(git_dir: &Path) -> Option<PathBuf> {
+    let text = std::fs::read_to_string(git_dir.join("commondir")).ok()?;
+    Some(git_dir.join(text.trim()))
+}
+
 /// Smart scan entry point.
 ///
 /// - If this is the first run (no `git_head` stored) or incremental diff fails,
@@ -71,12 +304,29 @@ pub fn smart_scan_with_progress_cancel(
     progress: Arc<dyn Fn(ScanEvent) + Send + Sync>,
     cancel: Arc<AtomicBool>,
 ) -> Result<(), IndexError> {
+    smart_scan_with_limits(root, index, progress, cancel, ScanLimits::default()).map(|_| ())
+}
+
+/// [`smart_scan_with_progress_cancel`] for staged indexing of huge roots.
+///
+/// A full scan (the first scan of a git repository, every scan of other
+/// roots) becomes a checkpointed walk that stops once `limits` are
+/// reached, returning [`ScanStatus::Paused`]. An unfinished walk, whether
+/// paused or interrupted, is resumed by the next scan before anything
+/// else. Incremental scans ignore `limits`.
+pub fn smart_scan_with_limits(
+    root: &Path,
+    index: Arc<PersistentIndex>,
+    progress: Arc<dyn Fn(ScanEvent) + Send + Sync>,
+    cancel: Arc<AtomicBool>,
+    limits: ScanLimits,
+) -> Result<ScanStatus, IndexError> {
     check_cancel(&cancel)?;
-    let repo = match gix::discover(root) {
+    let repo = match GitLocation::load(&index).open(root) {
         Ok(repo) => repo,
         Err(err) => {
             debug!("smart_scan: no git repository detected: {err}, falling back to full scan");
-            return initial_scan_with_progress_cancel(root, index, progress, cancel);
+            return initial_scan_with_limits(root, index, progress, cancel, limits);
         }
     };
 
@@ -84,12 +334,31 @@ pub fn smart_scan_with_progress_cancel(
         Ok(commit) => commit,
         Err(err) => {
             debug!("smart_scan: failed to read git HEAD commit: {err}, falling back to full scan");
-            return initial_scan_with_progress_cancel(root, index, progress, cancel);
+            return initial_scan_with_limits(root, index, progress, cancel, limits);
         }
     };
 
     let current_id = head.id;
     let current_str = current_id.to_string();
+    // Mid-rebase, -merge or -bisect HEAD moves through commits that are
+    // soon left behind. Only the worktree is scanned then, and `git_head`
+    // stays at the last settled HEAD, so the first scan after the
+    // operation diffs from there.
+    let operation = repo.state();
+    let settled = operation.is_none();
+    if let Some(operation) = &operation {
+        info!(
+            "smart_scan: {operation:?} in progress at HEAD {current_str}, scanning the worktree only"
+        );
+    }
+
+    if scan_cursor(&index).is_some() {
+        info!("smart_scan: resuming an unfinished full scan");
+        if !settled {
+            return initial_scan_with_limits(root, index, progress, cancel, limits);
+        }
+        return staged_git_scan(root, index, progress, cancel, limits, &current_str);
+    }
 
     let stored_head = match index.get_meta("git_head") {
         Ok(v) => v,
@@ -105,14 +374,19 @@ pub fn smart_scan_with_progress_cancel(
         .unwrap_or_else(|| root.to_path_buf());
 
     let mut candidates: HashSet<PathBuf> = HashSet::new();
+    let mut renames = Vec::new();
 
     match stored_head {
+        Some(_) if !settled => {
+            let worktree_paths = collect_worktree_changes(&repo, &workdir, &mut renames)?;
+            candidates.extend(worktree_paths);
+        }
         Some(ref stored) if stored == &current_str => {
             info!(
                 "smart_scan: git_head matches current HEAD ({}), checking worktree changes",
                 stored
             );
-            let worktree_paths = collect_worktree_candidates(&repo, &workdir)?;
+            let worktree_paths = collect_worktree_changes(&repo, &workdir, &mut renames)?;
             candidates.extend(worktree_paths);
         }
         Some(ref stored) => {
@@ -120,34 +394,53 @@ pub fn smart_scan_with_progress_cancel(
                 "smart_scan: attempting incremental diff from {} to {}",
                 stored, current_str
             );
-            match collect_head_diff_candidates(&repo, &workdir, stored, &current_str) {
+            match collect_head_diff_candidates(&repo, &workdir, stored, &current_str, &mut renames)
+            {
                 Ok(diff_paths) => {
                     info!(
                         "smart_scan: tree diff produced {} candidate paths",
                         diff_paths.len()
                     );
                     candidates.extend(diff_paths);
-                    let worktree_paths = collect_worktree_candidates(&repo, &workdir)?;
+                    let worktree_paths = collect_worktree_changes(&repo, &workdir, &mut renames)?;
+                    candidates.extend(worktree_paths);
+                }
+                // A fetch into a shallow clone can drop the commit the
+                // index was built from; the files it would have diffed are
+                // found by comparing modification times instead.
+                Err(err @ IndexError::Git { .. }) if repo.is_shallow() => {
+                    info!(
+                        "smart_scan: incremental diff failed in a shallow clone: {err}, \
+                         comparing tracked files with the index instead"
+                    );
+                    candidates.extend(collect_stale_tracked_candidates(
+                        &repo, &workdir, root, &index,
+                    )?);
+                    let worktree_paths = collect_worktree_changes(&repo, &workdir, &mut renames)?;
                     candidates.extend(worktree_paths);
                 }
-                Err(err) => {
+                Err(err @ IndexError::Git { .. }) => {
                     warn!("smart_scan: incremental diff failed: {err}, falling back to full scan");
                     // Fallback: full scan, then store current HEAD.
-                    initial_scan_with_progress_cancel(
-                        root,
-                        Arc::clone(&index),
-                        Arc::clone(&progress),
-                        Arc::clone(&cancel),
-                    )?;
-                    if let Err(err) = index.set_meta("git_head", &current_str) {
-                        warn!("smart_scan: failed to store git_head in meta: {err}");
-                    } else {
-                        info!("smart_scan: stored git_head={} in meta", current_str);
-                    }
-                    return Ok(());
+                    return staged_git_scan(root, index, progress, cancel, limits, &current_str);
                 }
+                Err(err) => return Err(err),
             }
         }
+        None if !settled => {
+            info!("smart_scan: no git_head stored in index yet, walking without a checkpoint");
+            return initial_scan_with_limits(root, index, progress, cancel, limits);
+        }
+        // The packfile load writes into an empty index only; files indexed
+        // ahead of it (see `smart_scan_prioritized`) are kept by walking.
+        // A throttled scan walks too, since the load cannot be paced.
+        None if !limits.is_unlimited()
+            || throttle::throttle().is_some()
+            || !index.is_empty().unwrap_or(false) =>
+        {
+            info!("smart_scan: no git_head stored in index yet, starting a staged full scan");
+            return staged_git_scan(root, index, progress, cancel, limits, &current_str);
+        }
         None => {
             info!("smart_scan: no git_head stored in index yet (first run?)");
             initial_git_scan_with_progress(
@@ -158,7 +451,7 @@ pub fn smart_scan_with_progress_cancel(
                 Arc::clone(&progress),
                 Arc::clone(&cancel),
             )?;
-            return Ok(());
+            return Ok(ScanStatus::Complete);
         }
     }
 
@@ -172,13 +465,14 @@ pub fn smart_scan_with_progress_cancel(
         progress(ScanEvent::Finished);
         debug!("smart_scan: no incremental candidates to process");
         // Even if there were no changes, make sure the HEAD checkpoint is up to date.
-        if let Err(err) = index.set_meta("git_head", &current_str) {
+        if settled && let Err(err) = index.set_meta("git_head", &current_str) {
             warn!("smart_scan: failed to store git_head in meta: {err}");
         }
-        return Ok(());
+        return Ok(ScanStatus::Complete);
     }
 
-    let (candidate_files, candidate_bytes) = count_candidates(root, candidates.clone());
+    let scope = IncludeScope::load(root, &index);
+    let (candidate_files, candidate_bytes) = count_candidates(root, &scope, candidates.clone());
     progress(ScanEvent::Started(ScanPlan {
         mode: ScanMode::Incremental,
         total_files: candidate_files,
@@ -193,21 +487,162 @@ pub fn smart_scan_with_progress_cancel(
     )?;
     progress(ScanEvent::Finished);
 
-    if let Err(err) = index.set_meta("git_head", &current_str) {
+    if let Err(err) = index.record_renames(&renames) {
+        warn!("smart_scan: failed to record renames: {err}");
+    }
+    if !settled {
+        debug!("smart_scan: keeping git_head at the last settled HEAD");
+    } else if let Err(err) = index.set_meta("git_head", &current_str) {
         warn!("smart_scan: failed to store git_head in meta: {err}");
     } else {
         info!("smart_scan: stored git_head={} in meta", current_str);
     }
 
-    Ok(())
+    Ok(ScanStatus::Complete)
+}
+
+/// [`smart_scan_with_progress_cancel`] for server mode, after replaying
+/// the changes a watcher that died did not commit. When the index
+/// still needs a full scan, the files most likely to be searched first —
+/// uncommitted changes, then the most recently modified files — go through
+/// the writer's priority lane and are committed before the rest of the
+/// root is walked.
+pub fn smart_scan_prioritized(
+    root: &Path,
+    index: Arc<PersistentIndex>,
+    progress: Arc<dyn Fn(ScanEvent) + Send + Sync>,
+    cancel: Arc<AtomicBool>,
+) -> Result<(), IndexError> {
+    check_cancel(&cancel)?;
+    replay_watcher_journals(root, &index, &cancel)?;
+    if index.is_empty()? || scan_cursor(&index).is_some() {
+        let scope = IncludeScope::load(root, &index);
+        let paths = priority_paths(root, &GitLocation::load(&index), &scope, PRIORITY_FILES);
+        info!(
+            files = paths.len(),
+            "smart_scan: indexing recently modified files first"
+        );
+        workers::install(|| {
+            paths.par_iter().try_for_each(|path| {
+                check_cancel(&cancel)?;
+                throttle::pace();
+                index.index_path_priority(path)
+            })
+        })?;
+        index.flush()?;
+    }
+    smart_scan_with_progress_cancel(root, index, progress, cancel)
+}
+
+/// Catch-up for a writer taking over an index another process kept
+/// current: finishes an unfinished full walk, and in a git repository
+/// checks what git reports as changed (and the diff from the stored HEAD,
+/// if it moved), but never starts a new full walk of a root that was
+/// already indexed. Elsewhere the watcher's queued changes, and the
+/// journal of a writer that died (see [`replay_watcher_journals`]), cover
+/// the handover.
+pub fn catch_up_scan(
+    root: &Path,
+    index: Arc<PersistentIndex>,
+    progress: Arc<dyn Fn(ScanEvent) + Send + Sync>,
+    cancel: Arc<AtomicBool>,
+) -> Result<(), IndexError> {
+    check_cancel(&cancel)?;
+    replay_watcher_journals(root, &index, &cancel)?;
+    let in_git = GitLocation::load(&index)
+        .open(root)
+        .is_ok_and(|repo| repo.head_commit().is_ok());
+    if !in_git && scan_cursor(&index).is_none() && !index.is_empty()? {
+        debug!("catch_up_scan: not a git repository, relying on watcher events");
+        return Ok(());
+    }
+    smart_scan_with_progress_cancel(root, index, progress, cancel)
+}
+
+/// How HEAD of the repository of `root`, found through `git`, differs
+/// from `indexed_head`, the index's stored `git_head`, or `None` when they
+/// match or there is no repository to compare with.
+pub fn head_drift(root: &Path, git: &GitLocation, indexed_head: &str) -> Option<HeadDrift> {
+    let repo = git.open(root).ok()?;
+    let head = repo.head_commit().ok()?;
+    let indexed = gix::ObjectId::from_hex(indexed_head.as_bytes()).ok();
+    if indexed == Some(head.id) {
+        return None;
+    }
+    let ancestors = head.ancestors().all().ok()?;
+    for (behind, info) in ancestors.take(DRIFT_WALK_LIMIT).enumerate() {
+        if Some(info.ok()?.id) == indexed {
+            return Some(HeadDrift::Behind(behind));
+        }
+    }
+    Some(HeadDrift::Diverged)
+}
+
+/// Up to `limit` files of `scope`: those git reports as changed, then the
+/// rest by modification time, newest first.
+fn priority_paths(
+    root: &Path,
+    git: &GitLocation,
+    scope: &IncludeScope,
+    limit: usize,
+) -> Vec<PathBuf> {
+    let mut paths: Vec<PathBuf> = match git.open(root) {
+        Ok(repo) => {
+            let workdir = repo
+                .work_dir()
+                .map(|p| p.to_path_buf())
+                .unwrap_or_else(|| root.to_path_buf());
+            collect_worktree_candidates(&repo, &workdir).unwrap_or_default()
+        }
+        Err(_) => Vec::new(),
+    };
+    let exclude_dir = root.join(".source_fast");
+    let git = GitPaths::load(root);
+    paths.retain(|path| {
+        let path_string = encode_path(path);
+        path_is_within_root(&path_string, root)
+            && !path_is_within_root(&path_string, &exclude_dir)
+            && !git.contains(path)
+            && path.is_file()
+            && scope.contains(path)
+    });
+    paths.sort();
+    paths.dedup();
+    paths.truncate(limit);
+
+    let mut recent: Vec<(std::time::SystemTime, PathBuf)> = Vec::new();
+    for entry in full_scan_walker(root, scope, None) {
+        let Ok(entry) = entry else {
+            continue;
+        };
+        if !entry.file_type().is_some_and(|ft| ft.is_file()) {
+            continue;
+        }
+        if let Some(modified) = entry.metadata().ok().and_then(|m| m.modified().ok()) {
+            recent.push((modified, entry.into_path()));
+        }
+    }
+    recent.sort_by_key(|(modified, _)| std::cmp::Reverse(*modified));
+
+    let seen: HashSet<PathBuf> = paths.iter().cloned().collect();
+    let room = limit.saturating_sub(paths.len());
+    paths.extend(
+        recent
+            .into_iter()
+            .map(|(_, path)| path)
+            .filter(|path| !seen.contains(path))
+            .take(room),
+    );
+    paths
 }
 
 pub fn dry_run_scan(root: &Path, index: Arc<PersistentIndex>) -> Result<DryRunInfo, IndexError> {
-    let repo = match gix::discover(root) {
+    let scope = IncludeScope::load(root, &index);
+    let repo = match GitLocation::load(&index).open(root) {
         Ok(repo) => repo,
         Err(err) => {
             debug!("dry_run_scan: no git repository detected: {err}");
-            let (files, bytes) = count_full_scan(root)?;
+            let (files, bytes) = count_full_scan(root, &scope)?;
             let estimated = estimate_seconds(files, bytes);
             return Ok(DryRunInfo {
                 mode: DryRunMode::FullScan,
@@ -222,7 +657,7 @@ pub fn dry_run_scan(root: &Path, index: Arc<PersistentIndex>) -> Result<DryRunIn
         Ok(commit) => commit,
         Err(err) => {
             debug!("dry_run_scan: failed to read git HEAD commit: {err}");
-            let (files, bytes) = count_full_scan(root)?;
+            let (files, bytes) = count_full_scan(root, &scope)?;
             let estimated = estimate_seconds(files, bytes);
             return Ok(DryRunInfo {
                 mode: DryRunMode::FullScan,
@@ -250,23 +685,52 @@ pub fn dry_run_scan(root: &Path, index: Arc<PersistentIndex>) -> Result<DryRunIn
         .unwrap_or_else(|| root.to_path_buf());
 
     let mut candidates: HashSet<PathBuf> = HashSet::new();
+    let settled = repo.state().is_none();
     let mode = match stored_head {
+        // See `smart_scan_with_limits`: mid-operation only the worktree
+        // is scanned, or walked when nothing was indexed yet.
+        Some(_) if !settled => {
+            candidates.extend(collect_worktree_candidates(&repo, &workdir)?);
+            DryRunMode::Incremental
+        }
+        None if !settled => {
+            let (files, bytes) = count_full_scan(root, &scope)?;
+            return Ok(DryRunInfo {
+                mode: DryRunMode::FullScan,
+                candidate_files: files,
+                candidate_bytes: bytes,
+                estimated_seconds: estimate_seconds(files, bytes),
+            });
+        }
         Some(ref stored) if stored == &current_str => {
             let worktree_paths = collect_worktree_candidates(&repo, &workdir)?;
             candidates.extend(worktree_paths);
             DryRunMode::Incremental
         }
         Some(ref stored) => {
-            match collect_head_diff_candidates(&repo, &workdir, stored, &current_str) {
+            match collect_head_diff_candidates(
+                &repo,
+                &workdir,
+                stored,
+                &current_str,
+                &mut Vec::new(),
+            ) {
                 Ok(diff_paths) => {
                     candidates.extend(diff_paths);
                     let worktree_paths = collect_worktree_candidates(&repo, &workdir)?;
                     candidates.extend(worktree_paths);
                     DryRunMode::Incremental
                 }
-                Err(err) => {
+                Err(IndexError::Git { .. }) if repo.is_shallow() => {
+                    candidates.extend(collect_stale_tracked_candidates(
+                        &repo, &workdir, root, &index,
+                    )?);
+                    candidates.extend(collect_worktree_candidates(&repo, &workdir)?);
+                    DryRunMode::Incremental
+                }
+                Err(err @ IndexError::Git { .. }) => {
                     warn!("dry_run_scan: incremental diff failed: {err}");
-                    let (files, bytes) = count_full_scan(root)?;
+                    let (files, bytes) = count_full_scan(root, &scope)?;
                     let estimated = estimate_seconds(files, bytes);
                     return Ok(DryRunInfo {
                         mode: DryRunMode::FullScan,
@@ -275,6 +739,7 @@ pub fn dry_run_scan(root: &Path, index: Arc<PersistentIndex>) -> Result<DryRunIn
                         estimated_seconds: estimated,
                     });
                 }
+                Err(err) => return Err(err),
             }
         }
         None => {
@@ -286,7 +751,7 @@ pub fn dry_run_scan(root: &Path, index: Arc<PersistentIndex>) -> Result<DryRunIn
         }
     };
 
-    let (files, bytes) = count_candidates(root, candidates);
+    let (files, bytes) = count_candidates(root, &scope, candidates);
     let estimated = estimate_seconds(files, bytes);
     Ok(DryRunInfo {
         mode,
@@ -299,10 +764,20 @@ pub fn dry_run_scan(root: &Path, index: Arc<PersistentIndex>) -> Result<DryRunIn
 fn collect_worktree_candidates(
     repo: &Repository,
     workdir: &Path,
+) -> Result<Vec<PathBuf>, IndexError> {
+    collect_worktree_changes(repo, workdir, &mut Vec::new())
+}
+
+/// [`collect_worktree_candidates`], also adding the `(old, new)` paths of
+/// renames git detected to `renames`.
+fn collect_worktree_changes(
+    repo: &Repository,
+    workdir: &Path,
+    renames: &mut Vec<(PathBuf, PathBuf)>,
 ) -> Result<Vec<PathBuf>, IndexError> {
     use gix::status::index_worktree::iter::Item;
 
-    let mut paths = Vec::new();
+    let mut paths = collect_staged_candidates(repo, workdir);
 
     // Use gix's status API to find modified/untracked files
     let status = match repo.status(gix::progress::Discard) {
@@ -337,50 +812,54 @@ fn collect_worktree_candidates(
         // Get the path from the status item based on its variant
         match &item {
             Item::Modification { rela_path, .. } => {
-                let rel_str = match std::str::from_utf8(rela_path.as_bytes()) {
+                let rel = match git_rel_path(rela_path.as_bytes()) {
                     Ok(s) => s,
                     Err(err) => {
                         warn!("collect_worktree_candidates: non-utf8 path: {err}");
                         continue;
                     }
                 };
-                paths.push(workdir.join(rel_str));
+                paths.push(workdir.join(rel));
             }
             Item::DirectoryContents { entry, .. } => {
-                let rel_str = match std::str::from_utf8(entry.rela_path.as_bytes()) {
+                let rel = match git_rel_path(entry.rela_path.as_bytes()) {
                     Ok(s) => s,
                     Err(err) => {
                         warn!("collect_worktree_candidates: non-utf8 path: {err}");
                         continue;
                     }
                 };
-                paths.push(workdir.join(rel_str));
+                paths.push(workdir.join(rel));
             }
             Item::Rewrite {
                 source,
                 dirwalk_entry,
+                copy,
                 ..
             } => {
                 // Add the source (old) path
                 let source_path = source.rela_path();
-                let source_str = match std::str::from_utf8(source_path.as_bytes()) {
+                let source_rel = match git_rel_path(source_path.as_bytes()) {
                     Ok(s) => s,
                     Err(err) => {
                         warn!("collect_worktree_candidates: non-utf8 source path: {err}");
                         continue;
                     }
                 };
-                paths.push(workdir.join(source_str));
+                paths.push(workdir.join(source_rel));
 
                 // Add the destination (new) path
-                let dest_str = match std::str::from_utf8(dirwalk_entry.rela_path.as_bytes()) {
+                let dest_rel = match git_rel_path(dirwalk_entry.rela_path.as_bytes()) {
                     Ok(s) => s,
                     Err(err) => {
                         warn!("collect_worktree_candidates: non-utf8 dest path: {err}");
                         continue;
                     }
                 };
-                paths.push(workdir.join(dest_str));
+                paths.push(workdir.join(dest_rel));
+                if !copy {
+                    renames.push((workdir.join(source_rel), workdir.join(dest_rel)));
+                }
             }
         }
     }
@@ -388,6 +867,70 @@ fn collect_worktree_candidates(
     Ok(paths)
 }
 
+/// A repository-relative git path as a path. Git stores paths as bytes,
+/// which on Unix are taken as they are; elsewhere they must be UTF-8.
+fn git_rel_path(rel: &[u8]) -> Result<&Path, std::str::Utf8Error> {
+    #[cfg(unix)]
+    {
+        use std::os::unix::ffi::OsStrExt;
+        Ok(Path::new(std::ffi::OsStr::from_bytes(rel)))
+    }
+    #[cfg(not(unix))]
+    {
+        std::str::from_utf8(rel).map(Path::new)
+    }
+}
+
+/// Paths whose staged state differs from HEAD: staged additions,
+/// modifications, deletions and both sides of staged renames. Once the
+/// worktree matches the git index (after `git rm`, `git mv` or
+/// `git add -A`), the index-worktree status no longer reports them.
+fn collect_staged_candidates(repo: &Repository, workdir: &Path) -> Vec<PathBuf> {
+    let head_index = match repo
+        .head_tree_id()
+        .map_err(|err| err.to_string())
+        .and_then(|tree| repo.index_from_tree(&tree).map_err(|err| err.to_string()))
+    {
+        Ok(index) => index,
+        Err(err) => {
+            debug!("collect_staged_candidates: no HEAD tree to compare against: {err}");
+            return Vec::new();
+        }
+    };
+    let git_index = match repo.index_or_empty() {
+        Ok(index) => index,
+        Err(err) => {
+            warn!("collect_staged_candidates: failed to read git index: {err}");
+            return Vec::new();
+        }
+    };
+
+    let mut in_head: HashMap<&[u8], gix::ObjectId> = head_index
+        .entries()
+        .iter()
+        .map(|entry| (entry.path(&head_index).as_bytes(), entry.id))
+        .collect();
+    let mut changed = Vec::new();
+    for entry in git_index.entries() {
+        let rel_path = entry.path(&git_index).as_bytes();
+        if in_head.remove(rel_path) != Some(entry.id) {
+            changed.push(rel_path);
+        }
+    }
+    changed.extend(in_head.into_keys());
+
+    changed
+        .into_iter()
+        .filter_map(|rel_path| match git_rel_path(rel_path) {
+            Ok(rel_str) => Some(workdir.join(rel_str)),
+            Err(err) => {
+                warn!("collect_staged_candidates: non-utf8 path: {err}");
+                None
+            }
+        })
+        .collect()
+}
+
 fn collect_index_candidates(
     repo: &Repository,
     workdir: &Path,
@@ -398,60 +941,80 @@ fn collect_index_candidates(
         Ok(git_index) => {
             for entry in git_index.entries() {
                 let rel_path = entry.path(&git_index);
-                let rel_str = match std::str::from_utf8(rel_path.as_bytes()) {
+                let rel = match git_rel_path(rel_path.as_bytes()) {
                     Ok(s) => s,
                     Err(err) => {
                         warn!("collect_index_candidates: non-utf8 path in index: {err}");
                         continue;
                     }
                 };
-                candidates.insert(workdir.join(rel_str));
+                candidates.insert(workdir.join(rel));
             }
         }
         Err(err) => {
-            return Err(IndexError::Encode(format!(
-                "collect_index_candidates: failed to read git index: {err}"
-            )));
+            return Err(IndexError::git("reading the git index", err));
         }
     }
 
     Ok(candidates)
 }
 
+/// Directories under `root` (including `root`) that hold files tracked by
+/// git, or `None` when `root` has no repository.
+pub(crate) fn tracked_directories(root: &Path, git: &GitLocation) -> Option<BTreeSet<PathBuf>> {
+    let repo = git.open(root).ok()?;
+    let workdir = repo.work_dir()?.to_path_buf();
+    let files = match collect_index_candidates(&repo, &workdir) {
+        Ok(files) => files,
+        Err(err) => {
+            warn!("tracked_directories: {err}");
+            return None;
+        }
+    };
+
+    let mut dirs = BTreeSet::from([root.to_path_buf()]);
+    for file in &files {
+        // Ancestors of a directory already in the set are in it too.
+        let mut dir = file.parent();
+        while let Some(current) = dir {
+            if !current.starts_with(root) || !dirs.insert(current.to_path_buf()) {
+                break;
+            }
+            dir = current.parent();
+        }
+    }
+    Some(dirs)
+}
+
 fn collect_head_diff_candidates(
     repo: &Repository,
     workdir: &Path,
     stored_head: &str,
     current_head: &str,
+    renames: &mut Vec<(PathBuf, PathBuf)>,
 ) -> Result<Vec<PathBuf>, IndexError> {
     use gix::hash::ObjectId;
 
     let old_id = ObjectId::from_hex(stored_head.as_bytes())
-        .map_err(|e| IndexError::Encode(format!("invalid stored git_head {stored_head}: {e}")))?;
-
-    let old_commit = repo.find_commit(old_id).map_err(|e| {
-        IndexError::Encode(format!(
-            "failed to find stored HEAD commit {stored_head}: {e}"
-        ))
-    })?;
-    let new_commit = repo.head_commit().map_err(|e| {
-        IndexError::Encode(format!("failed to read current HEAD {current_head}: {e}"))
-    })?;
-
-    let old_tree = old_commit.tree().map_err(|e| {
-        IndexError::Encode(format!(
-            "failed to read tree for old HEAD {stored_head}: {e}"
-        ))
-    })?;
-    let new_tree = new_commit.tree().map_err(|e| {
-        IndexError::Encode(format!(
-            "failed to read tree for new HEAD {current_head}: {e}"
-        ))
-    })?;
+        .map_err(|e| IndexError::git(format!("parsing stored git_head {stored_head}"), e))?;
+
+    let old_commit = repo
+        .find_commit(old_id)
+        .map_err(|e| IndexError::git(format!("finding stored HEAD commit {stored_head}"), e))?;
+    let new_commit = repo
+        .head_commit()
+        .map_err(|e| IndexError::git(format!("reading current HEAD {current_head}"), e))?;
+
+    let old_tree = old_commit
+        .tree()
+        .map_err(|e| IndexError::git(format!("reading tree of old HEAD {stored_head}"), e))?;
+    let new_tree = new_commit
+        .tree()
+        .map_err(|e| IndexError::git(format!("reading tree of new HEAD {current_head}"), e))?;
 
     let changes = repo
         .diff_tree_to_tree(&old_tree, &new_tree, None)
-        .map_err(|e| IndexError::Encode(format!("tree diff failed: {e}")))?;
+        .map_err(|e| IndexError::git("diffing HEAD trees", e))?;
 
     if changes.is_empty() {
         info!("smart_scan: tree diff reported no changes between heads");
@@ -468,46 +1031,50 @@ fn collect_head_diff_candidates(
         match change {
             ChangeDetached::Addition { location, .. } => {
                 let rel = location.as_bstr();
-                let rel_str = std::str::from_utf8(rel.as_bytes()).map_err(|e| {
-                    IndexError::Encode(format!("non-utf8 path in addition {rel:?}: {e}"))
+                let rel_path = git_rel_path(rel.as_bytes()).map_err(|e| {
+                    IndexError::git(format!("non-utf8 path in addition {rel:?}"), e)
                 })?;
-                let abs = workdir.join(rel_str);
+                let abs = workdir.join(rel_path);
                 paths.push(abs);
             }
             ChangeDetached::Modification { location, .. } => {
                 let rel = location.as_bstr();
-                let rel_str = std::str::from_utf8(rel.as_bytes()).map_err(|e| {
-                    IndexError::Encode(format!("non-utf8 path in modification {rel:?}: {e}"))
+                let rel_path = git_rel_path(rel.as_bytes()).map_err(|e| {
+                    IndexError::git(format!("non-utf8 path in modification {rel:?}"), e)
                 })?;
-                let abs = workdir.join(rel_str);
+                let abs = workdir.join(rel_path);
                 paths.push(abs);
             }
             ChangeDetached::Rewrite {
                 source_location,
                 location,
+                copy,
                 ..
             } => {
                 // For renames/rewrites, we need BOTH paths:
                 // - source_location (old path) to remove from index
                 // - location (new path) to add to index
                 let old_rel = source_location.as_bstr();
-                let old_rel_str = std::str::from_utf8(old_rel.as_bytes()).map_err(|e| {
-                    IndexError::Encode(format!("non-utf8 path in rewrite source {old_rel:?}: {e}"))
+                let old_path = git_rel_path(old_rel.as_bytes()).map_err(|e| {
+                    IndexError::git(format!("non-utf8 path in rewrite source {old_rel:?}"), e)
                 })?;
-                paths.push(workdir.join(old_rel_str));
+                paths.push(workdir.join(old_path));
 
                 let new_rel = location.as_bstr();
-                let new_rel_str = std::str::from_utf8(new_rel.as_bytes()).map_err(|e| {
-                    IndexError::Encode(format!("non-utf8 path in rewrite dest {new_rel:?}: {e}"))
+                let new_path = git_rel_path(new_rel.as_bytes()).map_err(|e| {
+                    IndexError::git(format!("non-utf8 path in rewrite dest {new_rel:?}"), e)
                 })?;
-                paths.push(workdir.join(new_rel_str));
+                paths.push(workdir.join(new_path));
+                if !copy {
+                    renames.push((workdir.join(old_path), workdir.join(new_path)));
+                }
             }
             ChangeDetached::Deletion { location, .. } => {
                 let rel = location.as_bstr();
-                let rel_str = std::str::from_utf8(rel.as_bytes()).map_err(|e| {
-                    IndexError::Encode(format!("non-utf8 path in deletion {rel:?}: {e}"))
+                let rel_path = git_rel_path(rel.as_bytes()).map_err(|e| {
+                    IndexError::git(format!("non-utf8 path in deletion {rel:?}"), e)
                 })?;
-                let abs = workdir.join(rel_str);
+                let abs = workdir.join(rel_path);
                 paths.push(abs);
             }
         }
@@ -516,9 +1083,61 @@ fn collect_head_diff_candidates(
     Ok(paths)
 }
 
-fn count_full_scan(root: &Path) -> Result<(usize, u64), IndexError> {
+/// Tracked files whose modification time differs from the one they were
+/// indexed with or that are not indexed yet, and indexed files under
+/// `root` that no longer exist or changed — what a tree diff would have
+/// found, without needing the commit the index was built from. Unchanged
+/// files cost a `stat`, not a read.
+fn collect_stale_tracked_candidates(
+    repo: &Repository,
+    workdir: &Path,
+    root: &Path,
+    index: &PersistentIndex,
+) -> Result<HashSet<PathBuf>, IndexError> {
+    let mut indexed: HashMap<String, u64> = index
+        .modified_times_within(root)?
+        .into_iter()
+        .map(|(path, modified)| (fold_path(&path).into_owned(), modified))
+        .collect();
+    let mut candidates = HashSet::new();
+    for path in collect_index_candidates(repo, workdir)? {
+        if !path.starts_with(root) {
+            continue;
+        }
+        let key = fold_path(&normalize_path(&path)).into_owned();
+        if indexed.remove(&key) != Some(file_modified_timestamp(&path)) {
+            candidates.insert(path);
+        }
+    }
+    // Indexed files git no longer tracks: removed, or untracked and
+    // possibly edited since.
+    for (path, modified) in indexed {
+        let path = decode_path(&path);
+        if file_modified_timestamp(&path) != modified {
+            candidates.insert(path);
+        }
+    }
+    info!(
+        "smart_scan: {} tracked or indexed files changed since they were indexed",
+        candidates.len()
+    );
+    Ok(candidates)
+}
+
+/// Walk the files of `scope` below `root`, honouring ignore files and
+/// skipping [`GitPaths`] and `.source_fast`. With `after`, paths up to and
+/// including it in path order are skipped, and so are whole directories
+/// that sort before it.
+fn full_scan_walker(root: &Path, scope: &IncludeScope, after: Option<&Path>) -> ignore::Walk {
     let exclude_dir = root.join(".source_fast");
-    let walker = WalkBuilder::new(root)
+    let git = GitPaths::load(root);
+    let after = after.map(Path::to_path_buf);
+    let roots = scope.roots(root);
+    let mut builder = WalkBuilder::new(roots[0]);
+    for dir in &roots[1..] {
+        builder.add(dir);
+    }
+    builder
         .hidden(false)
         .ignore(true)
         .git_ignore(true)
@@ -526,17 +1145,24 @@ fn count_full_scan(root: &Path) -> Result<(usize, u64), IndexError> {
         .parents(true)
         .filter_entry(move |entry| {
             let path = entry.path();
-            if path.starts_with(&exclude_dir) {
-                return false;
-            }
-            if let Some(name) = path.file_name().and_then(|n| n.to_str())
-                && name == ".git"
-            {
-                return false;
-            }
-            true
+            !path.starts_with(&exclude_dir)
+                && !git.contains(path)
+                && after
+                    .as_deref()
+                    .is_none_or(|after| !walked_before(path, after))
         })
-        .build();
+        .build()
+}
+
+/// Whether a walk in path order has already passed `path` once it reached
+/// `after`: `path` sorts before it, or is it, and is not one of the
+/// directories holding it.
+fn walked_before(path: &Path, after: &Path) -> bool {
+    path == after || (path < after && !after.starts_with(path))
+}
+
+fn count_full_scan(root: &Path, scope: &IncludeScope) -> Result<(usize, u64), IndexError> {
+    let walker = full_scan_walker(root, scope, None);
 
     let mut files = 0usize;
     let mut bytes = 0u64;
@@ -563,27 +1189,12 @@ fn count_full_scan(root: &Path) -> Result<(usize, u64), IndexError> {
     Ok((files, bytes))
 }
 
-fn collect_full_scan_entries(root: &Path) -> Result<Vec<(PathBuf, u64)>, IndexError> {
-    let exclude_dir = root.join(".source_fast");
-    let walker = WalkBuilder::new(root)
-        .hidden(false)
-        .ignore(true)
-        .git_ignore(true)
-        .git_exclude(true)
-        .parents(true)
-        .filter_entry(move |entry| {
-            let path = entry.path();
-            if path.starts_with(&exclude_dir) {
-                return false;
-            }
-            if let Some(name) = path.file_name().and_then(|n| n.to_str())
-                && name == ".git"
-            {
-                return false;
-            }
-            true
-        })
-        .build();
+fn collect_full_scan_entries(
+    root: &Path,
+    scope: &IncludeScope,
+    after: Option<&Path>,
+) -> Result<Vec<(PathBuf, u64)>, IndexError> {
+    let walker = full_scan_walker(root, scope, after);
 
     let mut entries = Vec::new();
 
@@ -607,20 +1218,25 @@ fn collect_full_scan_entries(root: &Path) -> Result<Vec<(PathBuf, u64)>, IndexEr
     Ok(entries)
 }
 
-fn count_candidates(root: &Path, candidates: HashSet<PathBuf>) -> (usize, u64) {
+fn count_candidates(
+    root: &Path,
+    scope: &IncludeScope,
+    candidates: HashSet<PathBuf>,
+) -> (usize, u64) {
     let exclude_dir = root.join(".source_fast");
-    let git_dir = root.join(".git");
+    let git = GitPaths::load(root);
 
     let mut files = 0usize;
     let mut bytes = 0u64;
 
     for path in candidates {
-        let path_string = path.to_string_lossy();
+        let path_string = encode_path(&path);
         if !path_is_within_root(&path_string, root) {
             continue;
         }
         if path_is_within_root(&path_string, &exclude_dir)
-            || path_is_within_root(&path_string, &git_dir)
+            || git.contains(&path)
+            || !scope.contains(&path)
         {
             continue;
         }
@@ -655,7 +1271,7 @@ fn initial_git_scan_with_progress(
         workdir.display()
     );
 
-    let repo = match gix::discover(workdir) {
+    let repo = match GitLocation::load(&index).open(workdir) {
         Ok(r) => r,
         Err(err) => {
             warn!("initial_git_scan: failed to open repository: {err} – falling back to full walk");
@@ -711,6 +1327,10 @@ fn initial_git_scan_with_progress(
 
     let mut blob_entries: Vec<(String, gix::ObjectId)> = Vec::new();
     collect_tree_blobs(&repo, root_tree_id.into(), "", &mut blob_entries);
+    let scope = IncludeScope::load(root, &index);
+    if scope.is_restricted() {
+        blob_entries.retain(|(rel_path, _)| scope.contains(&workdir.join(rel_path)));
+    }
 
     // Count total bytes for progress (estimate from blob count)
     let total_files = blob_entries.len();
@@ -743,6 +1363,9 @@ fn initial_git_scan_with_progress(
     info!("initial_git_scan: reading blobs from packfile...");
     let read_start = std::time::Instant::now();
 
+    let policy = index.content_policy();
+    let mut archive_paths: Vec<PathBuf> = Vec::new();
+    let mut path_only: Vec<PathBuf> = Vec::new();
     let mut raw_files: Vec<(String, String)> = Vec::with_capacity(total_files);
     let mut actual_bytes: u64 = 0;
     let mut read_count = 0usize;
@@ -754,26 +1377,34 @@ fn initial_git_scan_with_progress(
         };
         let data: &[u8] = obj.data.as_ref();
 
-        let sniff_len = data.len().min(8192);
-        if data[..sniff_len].contains(&0) {
+        let abs_path = format!(
+            "{workdir_str}{sep}{}",
+            rel_path.replace('/', &sep.to_string())
+        );
+
+        if policy.archive_kind(Path::new(&abs_path)).is_some() {
+            archive_paths.push(PathBuf::from(abs_path));
             continue;
         }
-        let Ok(text) = std::str::from_utf8(data) else {
+        let text = if policy.skip_by_name(Path::new(&abs_path)) {
+            None
+        } else {
+            policy.extract(Path::new(&abs_path), data.to_vec())
+        };
+        let Some(text) = text else {
+            if policy.keeps_path_only(Path::new(&abs_path)) {
+                path_only.push(PathBuf::from(abs_path));
+            }
             continue;
         };
         if text.len() < 3 {
             continue;
         }
 
-        let abs_path = format!(
-            "{workdir_str}{sep}{}",
-            rel_path.replace('/', &sep.to_string())
-        );
-
         progress(ScanEvent::FileStarted(abs_path.clone()));
 
         actual_bytes += data.len() as u64;
-        raw_files.push((abs_path.clone(), text.to_string()));
+        raw_files.push((abs_path.clone(), text));
         read_count += 1;
 
         progress(ScanEvent::FileFinished {
@@ -804,21 +1435,43 @@ fn initial_git_scan_with_progress(
     const TRIGRAM_SPACE: usize = 256 * 256 * 256;
     let extract_start = std::time::Instant::now();
 
-    // Assign file_ids and extract trigrams in parallel.
-    let file_trigrams: Vec<(String, Vec<[u8; 3]>)> = raw_files
-        .par_iter()
-        .map(|(path, text)| (path.clone(), source_fast_core::text::collect_trigrams(text)))
-        .collect();
+    // Identical contents (vendored copies, generated files) are tokenized
+    // once and share a stored token list.
+    let hashes: Vec<ContentHash> = workers::install(|| {
+        raw_files
+            .par_iter()
+            .map(|(_, text)| content_hash(text))
+            .collect()
+    });
+    let mut originals: HashMap<ContentHash, usize> = HashMap::new();
+    for (i, hash) in hashes.iter().enumerate() {
+        originals.entry(*hash).or_insert(i);
+    }
+    let tokenizer = index.tokenizer();
+    let token_sets: HashMap<ContentHash, Vec<[u8; 3]>> = workers::install(|| {
+        originals
+            .into_par_iter()
+            .map(|(hash, i)| (hash, tokenizer.document_tokens(&raw_files[i].1)))
+            .collect()
+    });
 
-    // Build BulkFileEntry vec (sequential, trivial).
-    let entries: Vec<source_fast_core::BulkFileEntry> = file_trigrams
-        .iter()
-        .map(|(path, trigrams)| source_fast_core::BulkFileEntry {
-            path: path.clone(),
-            modified_ts: 1,
-            trigrams: trigrams.clone(),
-        })
-        .collect();
+    // Assign file_ids and collect trigrams (and stored content) in parallel.
+    let entries: Vec<source_fast_core::BulkFileEntry> = workers::install(|| {
+        raw_files
+            .par_iter()
+            .zip(&hashes)
+            .map(|((path, text), hash)| source_fast_core::BulkFileEntry {
+                path: path.clone(),
+                modified_ts: 1,
+                identity: source_fast_core::file_identity(Path::new(path)),
+                trigrams: token_sets[hash].clone(),
+                content_hash: *hash,
+                content: source_fast_core::stored_content(&policy, text)
+                    .ok()
+                    .flatten(),
+            })
+            .collect()
+    });
 
     // Build fixed-size trigram→bitmap array. Direct indexing, no hashing.
     // 16M entries × 8 bytes (empty RoaringBitmap) ≈ 128 MB.
@@ -826,10 +1479,10 @@ fn initial_git_scan_with_progress(
         .map(|_| roaring::RoaringBitmap::new())
         .collect();
 
-    for (file_id, (_path, trigrams)) in file_trigrams.iter().enumerate() {
+    for (file_id, entry) in entries.iter().enumerate() {
         check_cancel(&cancel)?;
         let fid = file_id as u32;
-        for tri in trigrams {
+        for tri in &entry.trigrams {
             let idx = (tri[0] as usize) << 16 | (tri[1] as usize) << 8 | tri[2] as usize;
             bitmaps[idx].insert(fid);
         }
@@ -855,8 +1508,9 @@ fn initial_git_scan_with_progress(
     drop(bitmaps); // free the 128 MB
 
     info!(
-        "initial_git_scan: extracted trigrams for {} files, {} unique trigrams in {:?}",
+        "initial_git_scan: extracted trigrams for {} files ({} distinct contents), {} unique trigrams in {:?}",
         entries.len(),
+        token_sets.len(),
         trigram_map.len(),
         extract_start.elapsed()
     );
@@ -870,6 +1524,37 @@ fn initial_git_scan_with_progress(
         write_start.elapsed()
     );
 
+    // Binary and oversized files skipped above keep a path-only row when
+    // `[binary] metadata_only` is set.
+    if !path_only.is_empty() {
+        info!(
+            "initial_git_scan: recording {} path-only files",
+            path_only.len()
+        );
+        for path in &path_only {
+            check_cancel(&cancel)?;
+            index.index_path_only(path)?;
+        }
+        index.flush()?;
+    }
+
+    // Archives are read from the working tree so their members can be
+    // indexed under virtual paths (only when archive indexing is enabled).
+    if !archive_paths.is_empty() {
+        progress(ScanEvent::PhaseChanged("indexing archives".into()));
+        info!(
+            "initial_git_scan: indexing {} archives from filesystem",
+            archive_paths.len()
+        );
+        apply_changes_by_files_with_progress_cancel(
+            root,
+            &index,
+            archive_paths,
+            Arc::clone(&progress),
+            Arc::clone(&cancel),
+        )?;
+    }
+
     // Phase 2d: Pick up dirty/untracked files from the working tree
     // (packfile only has committed content)
     match collect_worktree_candidates(&repo, workdir) {
@@ -953,7 +1638,7 @@ fn apply_changes_by_files_with_progress(
     )
 }
 
-fn apply_changes_by_files_with_progress_cancel(
+pub(crate) fn apply_changes_by_files_with_progress_cancel(
     root: &Path,
     index: &PersistentIndex,
     files: impl IntoIterator<Item = PathBuf>,
@@ -961,29 +1646,64 @@ fn apply_changes_by_files_with_progress_cancel(
     cancel: Arc<AtomicBool>,
 ) -> Result<(), IndexError> {
     let exclude_dir = root.join(".source_fast");
-    let git_dir = root.join(".git");
+    let git = GitPaths::load(root);
+    let scope = IncludeScope::load(root, index);
 
     // Collect candidates first so we can parallelize.
     let candidates: Vec<PathBuf> = files
         .into_iter()
         .filter(|path| {
-            let path_string = path.to_string_lossy();
+            let path_string = encode_path(path);
             path_is_within_root(&path_string, root)
                 && !path_is_within_root(&path_string, &exclude_dir)
-                && !path_is_within_root(&path_string, &git_dir)
+                && !git.contains(path)
+                && scope.contains(path)
         })
         .collect();
 
     let changed = AtomicUsize::new(0);
-
-    candidates.par_iter().for_each(|path| {
+    let (present, missing): (Vec<&PathBuf>, Vec<&PathBuf>) =
+        candidates.iter().partition(|path| path.exists());
+
+    // Files under a deleted directory are removed with one prefix job.
+    let mut removals: BTreeMap<PathBuf, Vec<&PathBuf>> = BTreeMap::new();
+    for path in missing {
+        removals
+            .entry(topmost_missing(root, path))
+            .or_default()
+            .push(path);
+    }
+    for (top, paths) in removals {
         if cancel.load(Ordering::Relaxed) {
-            return;
+            break;
         }
-        if path.exists() {
-            if !path.is_file() {
+        let result = if paths.len() == 1 && *paths[0] == top {
+            index.remove_path(&top)
+        } else {
+            index.remove_prefix(&top)
+        };
+        if let Err(err) = result {
+            warn!(
+                "smart_scan: failed to remove path {} from index: {err}",
+                top.display()
+            );
+            continue;
+        }
+        changed.fetch_add(paths.len(), Ordering::Relaxed);
+        for path in paths {
+            progress(ScanEvent::FileFinished {
+                path: path.display().to_string(),
+                bytes: 0,
+            });
+        }
+    }
+
+    workers::install(|| {
+        present.par_iter().for_each(|path| {
+            if cancel.load(Ordering::Relaxed) || !path.is_file() {
                 return;
             }
+            throttle::pace();
             let bytes = path.metadata().map(|m| m.len()).unwrap_or(0);
             progress(ScanEvent::FileStarted(path.display().to_string()));
             if let Err(err) = index.index_path(path) {
@@ -995,18 +1715,7 @@ fn apply_changes_by_files_with_progress_cancel(
                 path: path.display().to_string(),
                 bytes,
             });
-        } else if let Err(err) = index.remove_path(path) {
-            warn!(
-                "smart_scan: failed to remove path {} from index: {err}",
-                path.display()
-            );
-        } else {
-            changed.fetch_add(1, Ordering::Relaxed);
-            progress(ScanEvent::FileFinished {
-                path: path.display().to_string(),
-                bytes: 0,
-            });
-        }
+        })
     });
 
     check_cancel(&cancel)?;
@@ -1025,6 +1734,19 @@ fn apply_changes_by_files_with_progress_cancel(
     Ok(())
 }
 
+/// The highest missing ancestor of the missing `path` that is still below
+/// `root`, or `path` itself when its parent exists.
+fn topmost_missing(root: &Path, path: &Path) -> PathBuf {
+    let mut top = path;
+    while let Some(parent) = top.parent() {
+        if parent == root || !parent.starts_with(root) || parent.exists() {
+            break;
+        }
+        top = parent;
+    }
+    top.to_path_buf()
+}
+
 /// Initial full scan using filesystem walk.
 ///
 /// This is the current behaviour: walk the tree in parallel, index every file,
@@ -1047,10 +1769,68 @@ fn initial_scan_with_progress_cancel(
     progress: Arc<dyn Fn(ScanEvent) + Send + Sync>,
     cancel: Arc<AtomicBool>,
 ) -> Result<(), IndexError> {
+    initial_scan_with_limits(root, index, progress, cancel, ScanLimits::default()).map(|_| ())
+}
+
+/// The cursor of an unfinished full walk, if any.
+fn scan_cursor(index: &PersistentIndex) -> Option<PathBuf> {
+    match index.get_meta(SCAN_CURSOR_META_KEY) {
+        Ok(cursor) => cursor
+            .filter(|cursor| !cursor.is_empty())
+            .map(|cursor| decode_path(&cursor)),
+        Err(err) => {
+            warn!("initial_scan: failed to read scan cursor from meta: {err}, starting over");
+            None
+        }
+    }
+}
+
+/// A full walk of a git root that stores `current_head` once it has
+/// finished, so later scans continue incrementally from there.
+fn staged_git_scan(
+    root: &Path,
+    index: Arc<PersistentIndex>,
+    progress: Arc<dyn Fn(ScanEvent) + Send + Sync>,
+    cancel: Arc<AtomicBool>,
+    limits: ScanLimits,
+    current_head: &str,
+) -> Result<ScanStatus, IndexError> {
+    let status = initial_scan_with_limits(root, Arc::clone(&index), progress, cancel, limits)?;
+    if status == ScanStatus::Complete {
+        if let Err(err) = index.set_meta("git_head", current_head) {
+            warn!("smart_scan: failed to store git_head in meta: {err}");
+        } else {
+            info!("smart_scan: stored git_head={} in meta", current_head);
+        }
+    }
+    Ok(status)
+}
+
+/// Walk the root in path order, indexing files in chunks and storing the
+/// last path of each chunk as [`SCAN_CURSOR_META_KEY`]. A walk that finds
+/// a cursor skips everything up to it; the cursor is cleared once the
+/// walk finishes.
+fn initial_scan_with_limits(
+    root: &Path,
+    index: Arc<PersistentIndex>,
+    progress: Arc<dyn Fn(ScanEvent) + Send + Sync>,
+    cancel: Arc<AtomicBool>,
+    limits: ScanLimits,
+) -> Result<ScanStatus, IndexError> {
     check_cancel(&cancel)?;
-    info!("initial_scan: starting parallel walk at {}", root.display());
+    let cursor = scan_cursor(&index);
+    match &cursor {
+        Some(cursor) => info!(
+            "initial_scan: resuming walk at {} after {}",
+            root.display(),
+            cursor.display()
+        ),
+        None => info!("initial_scan: starting parallel walk at {}", root.display()),
+    }
 
-    let entries = collect_full_scan_entries(root)?;
+    let mut entries =
+        collect_full_scan_entries(root, &IncludeScope::load(root, &index), cursor.as_deref())?;
+    entries.sort_unstable_by(|(lhs, _), (rhs, _)| lhs.cmp(rhs));
     let total_files = entries.len();
     let total_bytes = entries
         .iter()
@@ -1061,47 +1841,72 @@ fn initial_scan_with_progress_cancel(
         total_bytes,
     }));
 
-    let counter = Arc::new(AtomicUsize::new(0));
-    let index_for_scan = Arc::clone(&index);
-    let counter_for_scan = Arc::clone(&counter);
-    let progress_for_scan = Arc::clone(&progress);
+    let counter = AtomicUsize::new(0);
+    let started = Instant::now();
+    let mut status = ScanStatus::Complete;
+    let chunk_len = limits
+        .max_files
+        .map_or(CHECKPOINT_FILES, |max| max.clamp(1, CHECKPOINT_FILES));
+    let mut chunks = entries.chunks(chunk_len).peekable();
+    while let Some(chunk) = chunks.next() {
+        workers::install(|| {
+            chunk.par_iter().for_each(|(path, bytes)| {
+                if cancel.load(Ordering::Relaxed) {
+                    return;
+                }
+                throttle::pace();
 
-    entries.into_par_iter().for_each(|(path, bytes)| {
-        if cancel.load(Ordering::Relaxed) {
-            return;
-        }
-        let index = Arc::clone(&index_for_scan);
-        let counter = Arc::clone(&counter_for_scan);
-        let progress = Arc::clone(&progress_for_scan);
+                progress(ScanEvent::FileStarted(path.display().to_string()));
+
+                let done = counter.fetch_add(1, Ordering::Relaxed) + 1;
+                if done.is_multiple_of(500) {
+                    info!("initial_scan: indexed {} files so far", done);
+                }
+
+                if let Err(err) = index.index_path(path) {
+                    warn!(
+                        "initial_scan worker: failed to index {}: {:?}",
+                        path.display(),
+                        err
+                    );
+                }
+                progress(ScanEvent::FileFinished {
+                    path: path.display().to_string(),
+                    bytes: *bytes,
+                });
+            })
+        });
 
-        progress(ScanEvent::FileStarted(path.display().to_string()));
+        check_cancel(&cancel)?;
 
-        let done = counter.fetch_add(1, Ordering::Relaxed) + 1;
-        if done.is_multiple_of(500) {
-            info!("initial_scan: indexed {} files so far", done);
+        // Queued behind the chunk's files, so the cursor never commits
+        // ahead of them.
+        if let Some((last, _)) = chunk.last() {
+            index.set_meta_queued(SCAN_CURSOR_META_KEY, &encode_path(last))?;
         }
+        index.flush()?;
 
-        if let Err(err) = index.index_path(&path) {
-            warn!(
-                "initial_scan worker: failed to index {}: {:?}",
-                path.display(),
-                err
-            );
+        if chunks.peek().is_some() && limits.reached(counter.load(Ordering::Relaxed), started) {
+            status = ScanStatus::Paused;
+            break;
         }
-        progress(ScanEvent::FileFinished {
-            path: path.display().to_string(),
-            bytes,
-        });
-    });
+    }
 
-    check_cancel(&cancel)?;
+    let done = counter.load(Ordering::Relaxed);
+    if status == ScanStatus::Paused {
+        info!(
+            "initial_scan: paused after {} files, the next scan resumes from here",
+            done
+        );
+        return Ok(status);
+    }
 
-    debug!("initial_scan: parallel walk finished, flushing index");
+    debug!("initial_scan: parallel walk finished, clearing scan cursor");
+    index.set_meta_queued(SCAN_CURSOR_META_KEY, "")?;
     index.flush()?;
-    let done = counter.load(Ordering::Relaxed);
     info!("initial_scan: completed, indexed {} files in total", done);
     progress(ScanEvent::Finished);
-    Ok(())
+    Ok(status)
 }
 
 #[cfg(test)]
@@ -1147,6 +1952,31 @@ mod tests {
             .expect("git commit failed");
     }
 
+    #[test]
+    fn test_tracked_directories_skip_untracked_trees() {
+        let temp = TempDir::new().unwrap();
+        let root = temp.path().canonicalize().unwrap();
+        init_git_repo(&root);
+        std::fs::create_dir_all(root.join("src/nested")).unwrap();
+        std::fs::write(root.join("src/nested/lib.rs"), "fn main() {}").unwrap();
+        std::fs::write(root.join("README.md"), "readme").unwrap();
+        git_add_commit(&root, "initial");
+        std::fs::create_dir_all(root.join("node_modules/pkg")).unwrap();
+        std::fs::write(root.join("node_modules/pkg/index.js"), "x").unwrap();
+
+        let dirs: Vec<PathBuf> = tracked_directories(&root, &GitLocation::default())
+            .unwrap()
+            .into_iter()
+            .collect();
+        assert_eq!(
+            dirs,
+            vec![root.clone(), root.join("src"), root.join("src/nested")]
+        );
+
+        let plain = TempDir::new().unwrap();
+        assert!(tracked_directories(plain.path(), &GitLocation::default()).is_none());
+    }
+
     // ============ Initial Scan Tests ============
 
     #[test]
@@ -1220,6 +2050,31 @@ mod tests {
         // (We don't search for git internal content since we don't know what's there)
     }
 
+    #[test]
+    fn test_initial_scan_skips_git_files_and_their_gitdirs() {
+        let temp_dir = TempDir::new().unwrap();
+        let root = temp_dir.path();
+        // Linked worktrees and submodules have a `.git` file naming the
+        // real git dir, here one inside the root.
+        std::fs::create_dir(root.join("gitdata")).unwrap();
+        std::fs::write(root.join("gitdata/HEAD"), "gitdir_content_marker\n").unwrap();
+        std::fs::write(root.join(".git"), "gitdir: gitdata\n").unwrap();
+        std::fs::create_dir(root.join("sub")).unwrap();
+        std::fs::write(
+            root.join("sub/.git"),
+            "gitdir: ../gitdata/modules/sub_marker\n",
+        )
+        .unwrap();
+        std::fs::write(root.join("sub/lib.rs"), "worktree_content_marker\n").unwrap();
+
+        let index = create_test_index(root);
+        initial_scan(root, Arc::clone(&index)).unwrap();
+
+        assert_eq!(index.search("worktree_content_marker").unwrap().len(), 1);
+        assert!(index.search("gitdir_content_marker").unwrap().is_empty());
+        assert!(index.search("sub_marker").unwrap().is_empty());
+    }
+
     #[test]
     fn test_initial_scan_respects_gitignore() {
         let temp_dir = TempDir::new().unwrap();
@@ -1298,6 +2153,78 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_smart_scan_mid_rebase_keeps_settled_head() {
+        let temp_dir = TempDir::new().unwrap();
+        let root = temp_dir.path();
+        init_git_repo(root);
+        std::fs::write(root.join("a.txt"), "settled_marker").unwrap();
+        git_add_commit(root, "settled");
+        let index = create_test_index(root);
+        smart_scan(root, Arc::clone(&index)).unwrap();
+        let settled = index.get_meta("git_head").unwrap().unwrap();
+
+        // A rebase stopped after replaying a commit.
+        std::fs::write(root.join("b.txt"), "replayed_marker").unwrap();
+        git_add_commit(root, "replayed");
+        std::fs::create_dir(root.join(".git/rebase-merge")).unwrap();
+        std::fs::write(root.join("a.txt"), "conflict_marker").unwrap();
+        smart_scan(root, Arc::clone(&index)).unwrap();
+
+        assert_eq!(index.search("conflict_marker").unwrap().len(), 1);
+        assert_eq!(index.get_meta("git_head").unwrap().unwrap(), settled);
+
+        std::fs::remove_dir(root.join(".git/rebase-merge")).unwrap();
+        smart_scan(root, Arc::clone(&index)).unwrap();
+        assert_eq!(index.search("replayed_marker").unwrap().len(), 1);
+        assert_ne!(index.get_meta("git_head").unwrap().unwrap(), settled);
+    }
+
+    #[test]
+    fn test_smart_scan_past_shallow_boundary_skips_full_walk() {
+        let temp_dir = TempDir::new().unwrap();
+        let root = temp_dir.path();
+        init_git_repo(root);
+        std::fs::write(root.join("a.txt"), "before_marker").unwrap();
+        std::fs::write(root.join("b.txt"), "removed_marker").unwrap();
+        git_add_commit(root, "first");
+        let index = create_test_index(root);
+        smart_scan(root, Arc::clone(&index)).unwrap();
+
+        // A fetch into a shallow clone dropped the indexed commit.
+        index
+            .set_meta("git_head", "1111111111111111111111111111111111111111")
+            .unwrap();
+        std::fs::write(root.join("a.txt"), "after_marker").unwrap();
+        std::fs::File::options()
+            .write(true)
+            .open(root.join("a.txt"))
+            .unwrap()
+            .set_modified(std::time::UNIX_EPOCH + Duration::from_secs(1_000_000))
+            .unwrap();
+        std::fs::remove_file(root.join("b.txt")).unwrap();
+        git_add_commit(root, "second");
+        let head = gix::open(root).unwrap().head_id().unwrap().to_string();
+        std::fs::write(root.join(".git/shallow"), format!("{head}\n")).unwrap();
+
+        let full_walk = Arc::new(AtomicBool::new(false));
+        let seen = Arc::clone(&full_walk);
+        let progress: Arc<dyn Fn(ScanEvent) + Send + Sync> = Arc::new(move |event: ScanEvent| {
+            if let ScanEvent::Started(plan) = event
+                && !matches!(plan.mode, ScanMode::Incremental)
+            {
+                seen.store(true, Ordering::Relaxed);
+            }
+        });
+        smart_scan_with_progress(root, Arc::clone(&index), progress).unwrap();
+
+        assert!(!full_walk.load(Ordering::Relaxed));
+        assert_eq!(index.search("after_marker").unwrap().len(), 1);
+        assert!(index.search("before_marker").unwrap().is_empty());
+        assert!(index.search("removed_marker").unwrap().is_empty());
+        assert_eq!(index.get_meta("git_head").unwrap().unwrap(), head);
+    }
+
     #[test]
     fn test_smart_scan_no_changes_is_noop() {
         let temp_dir = TempDir::new().unwrap();
@@ -1344,6 +2271,63 @@ mod tests {
         assert_eq!(hits.len(), 1);
     }
 
+    #[test]
+    fn test_head_drift_counts_commits_since_indexed_head() {
+        let temp_dir = TempDir::new().unwrap();
+        init_git_repo(temp_dir.path());
+        std::fs::write(temp_dir.path().join("file.txt"), "drift").unwrap();
+        git_add_commit(temp_dir.path(), "First commit");
+
+        let index = create_test_index(temp_dir.path());
+        smart_scan(temp_dir.path(), Arc::clone(&index)).unwrap();
+        let indexed = index.get_meta("git_head").unwrap().unwrap();
+        assert_eq!(
+            head_drift(temp_dir.path(), &GitLocation::default(), &indexed),
+            None
+        );
+
+        git_add_commit(temp_dir.path(), "Second commit");
+        git_add_commit(temp_dir.path(), "Third commit");
+        assert_eq!(
+            head_drift(temp_dir.path(), &GitLocation::default(), &indexed),
+            Some(HeadDrift::Behind(2))
+        );
+        assert_eq!(
+            head_drift(temp_dir.path(), &GitLocation::default(), &"0".repeat(40)),
+            Some(HeadDrift::Diverged)
+        );
+    }
+
+    #[test]
+    fn test_smart_scan_records_committed_renames() {
+        let temp_dir = TempDir::new().unwrap();
+        init_git_repo(temp_dir.path());
+        let body = "fn renamed_marker() { println!(\"same content\"); }\n";
+        std::fs::write(temp_dir.path().join("old_name.rs"), body).unwrap();
+        git_add_commit(temp_dir.path(), "Initial commit");
+
+        let index = create_test_index(temp_dir.path());
+        smart_scan(temp_dir.path(), Arc::clone(&index)).unwrap();
+
+        std::fs::rename(
+            temp_dir.path().join("old_name.rs"),
+            temp_dir.path().join("new_name.rs"),
+        )
+        .unwrap();
+        git_add_commit(temp_dir.path(), "Rename");
+        smart_scan(temp_dir.path(), Arc::clone(&index)).unwrap();
+        index.flush().unwrap();
+
+        let suggestions =
+            source_fast_core::suggest_files_in_database(index.db_path(), "old_name", 5).unwrap();
+        assert!(
+            suggestions
+                .first()
+                .is_some_and(|s| s.path.ends_with("new_name.rs") && s.renamed_from.is_some()),
+            "expected a rename suggestion, got {suggestions:?}"
+        );
+    }
+
     #[test]
     fn test_smart_scan_detects_dirty_state() {
         let temp_dir = TempDir::new().unwrap();
@@ -1366,6 +2350,37 @@ mod tests {
         assert_eq!(hits.len(), 1);
     }
 
+    #[test]
+    fn test_smart_scan_applies_staged_deletes_and_renames() {
+        let temp_dir = TempDir::new().unwrap();
+        let root = temp_dir.path();
+        init_git_repo(root);
+        std::fs::write(root.join("deleted.txt"), "staged_delete_marker").unwrap();
+        std::fs::write(root.join("moved.txt"), "staged_rename_marker").unwrap();
+        git_add_commit(root, "Initial commit");
+
+        let index = create_test_index(root);
+        smart_scan(root, Arc::clone(&index)).unwrap();
+        assert_eq!(index.search("staged_delete_marker").unwrap().len(), 1);
+
+        for args in [
+            &["rm", "-q", "deleted.txt"][..],
+            &["mv", "moved.txt", "renamed.txt"][..],
+        ] {
+            Command::new("git")
+                .args(args)
+                .current_dir(root)
+                .output()
+                .expect("git failed");
+        }
+        smart_scan(root, Arc::clone(&index)).unwrap();
+
+        assert!(index.search("staged_delete_marker").unwrap().is_empty());
+        let hits = index.search("staged_rename_marker").unwrap();
+        assert_eq!(hits.len(), 1);
+        assert!(hits[0].path.ends_with("renamed.txt"));
+    }
+
     #[test]
     fn test_smart_scan_detects_untracked_files() {
         let temp_dir = TempDir::new().unwrap();
@@ -1392,8 +2407,221 @@ mod tests {
         assert_eq!(hits.len(), 1);
     }
 
+    #[test]
+    fn test_initial_scan_respects_include_paths() {
+        let temp_dir = TempDir::new().unwrap();
+        let root = temp_dir.path();
+        std::fs::create_dir_all(root.join("src/team-a")).unwrap();
+        std::fs::create_dir_all(root.join("src/team-b")).unwrap();
+        std::fs::write(root.join("src/team-a/a.rs"), "scoped_marker_one").unwrap();
+        std::fs::write(root.join("src/team-b/b.rs"), "scoped_marker_two").unwrap();
+
+        let index = create_test_index(root);
+        index
+            .set_include_paths(&[PathBuf::from("src/team-a")])
+            .unwrap();
+        initial_scan(root, Arc::clone(&index)).unwrap();
+
+        assert_eq!(index.search("scoped_marker_one").unwrap().len(), 1);
+        assert!(index.search("scoped_marker_two").unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_smart_scan_respects_include_paths() {
+        let temp_dir = TempDir::new().unwrap();
+        let root = temp_dir.path();
+        init_git_repo(root);
+        std::fs::create_dir_all(root.join("libs/shared")).unwrap();
+        std::fs::write(root.join("libs/shared/lib.rs"), "committed_inside").unwrap();
+        std::fs::write(root.join("top.rs"), "committed_outside").unwrap();
+        git_add_commit(root, "Initial commit");
+
+        let index = create_test_index(root);
+        index
+            .set_include_paths(&[PathBuf::from("libs/shared")])
+            .unwrap();
+        smart_scan(root, Arc::clone(&index)).unwrap();
+        assert_eq!(index.search("committed_inside").unwrap().len(), 1);
+        assert!(index.search("committed_outside").unwrap().is_empty());
+
+        std::fs::write(root.join("libs/shared/new.rs"), "untracked_inside").unwrap();
+        std::fs::write(root.join("new.rs"), "untracked_outside").unwrap();
+        smart_scan(root, Arc::clone(&index)).unwrap();
+        assert_eq!(index.search("untracked_inside").unwrap().len(), 1);
+        assert!(index.search("untracked_outside").unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_initial_scan_pauses_and_resumes() {
+        let temp_dir = TempDir::new().unwrap();
+        let root = temp_dir.path();
+        for name in ["a", "b", "c", "d", "e"] {
+            std::fs::write(
+                root.join(format!("{name}.txt")),
+                format!("staged_{name}_marker"),
+            )
+            .unwrap();
+        }
+        let index = create_test_index(root);
+        let indexed = |index: &PersistentIndex| {
+            ["a", "b", "c", "d", "e"]
+                .into_iter()
+                .filter(|name| {
+                    !index
+                        .search(&format!("staged_{name}_marker"))
+                        .unwrap()
+                        .is_empty()
+                })
+                .count()
+        };
+        let limits = ScanLimits {
+            max_files: Some(2),
+            max_duration: None,
+        };
+        let run = |limits| {
+            initial_scan_with_limits(
+                root,
+                Arc::clone(&index),
+                Arc::new(|_| {}),
+                Arc::new(AtomicBool::new(false)),
+                limits,
+            )
+            .unwrap()
+        };
+
+        assert_eq!(run(limits), ScanStatus::Paused);
+        assert_eq!(indexed(&index), 2);
+        assert_eq!(scan_cursor(&index), Some(root.join("b.txt")));
+
+        assert_eq!(run(limits), ScanStatus::Paused);
+        assert_eq!(indexed(&index), 4);
+
+        assert_eq!(run(ScanLimits::default()), ScanStatus::Complete);
+        assert_eq!(indexed(&index), 5);
+        assert_eq!(scan_cursor(&index), None);
+    }
+
+    #[test]
+    fn test_initial_scan_resumes_after_interruption() {
+        let temp_dir = TempDir::new().unwrap();
+        let root = temp_dir.path();
+        std::fs::write(root.join("done.txt"), "before_cursor_marker").unwrap();
+        std::fs::write(root.join("todo.txt"), "after_cursor_marker").unwrap();
+
+        let index = create_test_index(root);
+        index
+            .set_meta(
+                SCAN_CURSOR_META_KEY,
+                &root.join("done.txt").to_string_lossy(),
+            )
+            .unwrap();
+        initial_scan(root, Arc::clone(&index)).unwrap();
+
+        assert!(index.search("before_cursor_marker").unwrap().is_empty());
+        assert_eq!(index.search("after_cursor_marker").unwrap().len(), 1);
+        assert_eq!(scan_cursor(&index), None);
+    }
+
+    #[test]
+    fn test_full_scan_walk_skips_up_to_cursor() {
+        let temp_dir = TempDir::new().unwrap();
+        let root = temp_dir.path();
+        for rel in ["a/one.txt", "a/two.txt", "b/one.txt", "b/two.txt", "c.txt"] {
+            let path = root.join(rel);
+            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+            std::fs::write(path, rel).unwrap();
+        }
+        let index = create_test_index(root);
+        let scope = IncludeScope::load(root, &index);
+
+        let cursor = root.join("b").join("one.txt");
+        let mut walked: Vec<PathBuf> = collect_full_scan_entries(root, &scope, Some(&cursor))
+            .unwrap()
+            .into_iter()
+            .map(|(path, _)| path)
+            .collect();
+        walked.sort();
+        assert_eq!(walked, [root.join("b").join("two.txt"), root.join("c.txt")]);
+
+        assert!(walked_before(&root.join("a"), &cursor));
+        assert!(!walked_before(&root.join("b"), &cursor));
+        assert!(walked_before(&cursor, &cursor));
+    }
+
+    #[test]
+    fn test_smart_scan_with_limits_stages_first_git_scan() {
+        let temp_dir = TempDir::new().unwrap();
+        let root = temp_dir.path();
+        init_git_repo(root);
+        std::fs::write(root.join("one.rs"), "first_staged_file").unwrap();
+        std::fs::write(root.join("two.rs"), "second_staged_file").unwrap();
+        git_add_commit(root, "Initial commit");
+
+        let index = create_test_index(root);
+        let limits = ScanLimits {
+            max_files: Some(1),
+            max_duration: None,
+        };
+        let status = smart_scan_with_limits(
+            root,
+            Arc::clone(&index),
+            Arc::new(|_| {}),
+            Arc::new(AtomicBool::new(false)),
+            limits,
+        )
+        .unwrap();
+        assert_eq!(status, ScanStatus::Paused);
+        assert!(index.get_meta("git_head").unwrap().is_none());
+
+        smart_scan(root, Arc::clone(&index)).unwrap();
+        assert_eq!(index.search("first_staged_file").unwrap().len(), 1);
+        assert_eq!(index.search("second_staged_file").unwrap().len(), 1);
+        assert!(index.get_meta("git_head").unwrap().is_some());
+        assert_eq!(scan_cursor(&index), None);
+    }
+
     // ============ Apply Changes Tests ============
 
+    #[test]
+    fn test_priority_paths_newest_first() {
+        let temp_dir = TempDir::new().unwrap();
+        let root = temp_dir.path();
+        let now = std::time::SystemTime::now();
+        for (name, age) in [("old.txt", 300), ("newest.txt", 0), ("newer.txt", 60)] {
+            let path = root.join(name);
+            std::fs::write(&path, "content").unwrap();
+            let file = std::fs::File::options().write(true).open(&path).unwrap();
+            file.set_modified(now - Duration::from_secs(age)).unwrap();
+        }
+
+        let paths = priority_paths(root, &GitLocation::default(), &IncludeScope(Vec::new()), 2);
+        assert_eq!(paths, [root.join("newest.txt"), root.join("newer.txt")]);
+    }
+
+    #[test]
+    fn test_smart_scan_prioritized_indexes_changes_then_walks() {
+        let temp_dir = TempDir::new().unwrap();
+        let root = temp_dir.path();
+        init_git_repo(root);
+        std::fs::write(root.join("committed.txt"), "committed_content_xyz").unwrap();
+        git_add_commit(root, "initial");
+        std::fs::write(root.join("dirty.txt"), "dirty_content_xyz").unwrap();
+
+        let index = create_test_index(root);
+        smart_scan_prioritized(
+            root,
+            Arc::clone(&index),
+            Arc::new(|_| {}),
+            Arc::new(AtomicBool::new(false)),
+        )
+        .unwrap();
+
+        assert_eq!(index.search("committed_content_xyz").unwrap().len(), 1);
+        assert_eq!(index.search("dirty_content_xyz").unwrap().len(), 1);
+        assert!(index.get_meta("git_head").unwrap().is_some());
+        assert!(scan_cursor(&index).is_none());
+    }
+
     #[test]
     fn test_apply_changes_adds_new_files() {
         let temp_dir = TempDir::new().unwrap();
@@ -1447,6 +2675,40 @@ mod tests {
         assert!(hits.is_empty());
     }
 
+    #[test]
+    fn test_apply_changes_removes_deleted_directory() {
+        let temp_dir = TempDir::new().unwrap();
+        let index = create_test_index(temp_dir.path());
+
+        let dir = temp_dir.path().join("doomed");
+        std::fs::create_dir_all(dir.join("nested")).unwrap();
+        let files = [
+            dir.join("a.txt"),
+            dir.join("nested/b.txt"),
+            dir.join("c.txt"),
+        ];
+        for file in &files {
+            std::fs::write(file, "doomed_dir_content").unwrap();
+            index.index_path(file).unwrap();
+        }
+        index.flush().unwrap();
+        assert_eq!(index.search("doomed_dir_content").unwrap().len(), 3);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(topmost_missing(temp_dir.path(), &files[1]), dir);
+
+        // Files missing from the candidate list go with their directory.
+        apply_changes_by_files_with_progress(
+            temp_dir.path(),
+            &index,
+            files[..2].to_vec(),
+            Arc::new(|_| {}),
+        )
+        .unwrap();
+
+        assert!(index.search("doomed_dir_content").unwrap().is_empty());
+    }
+
     #[test]
     fn test_apply_changes_skips_directories() {
         let temp_dir = TempDir::new().unwrap();
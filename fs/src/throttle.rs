@@ -0,0 +1,147 @@
+//! Pacing of scans for background indexing (`sf server --background-nice`).
+//!
+//! Once [`set_throttle`] is called, every file a scan indexes first waits
+//! for a slot under the files-per-second cap, and for the system load
+//! average to drop below the limit. Nothing waits otherwise.
+
+use std::sync::{Mutex, OnceLock};
+use std::time::{Duration, Instant};
+
+use tracing::{debug, info};
+
+/// How often the load average is read, and how long a scan sleeps before
+/// reading it again while the system is busy.
+const LOAD_CHECK_INTERVAL: Duration = Duration::from_secs(1);
+
+static THROTTLE: OnceLock<Throttle> = OnceLock::new();
+
+/// Limits on how hard a scan may work.
+#[derive(Debug)]
+pub struct Throttle {
+    files_per_second: u32,
+    max_load: Option<f64>,
+    next_slot: Mutex<Instant>,
+    load_checked: Mutex<Option<(Instant, bool)>>,
+}
+
+impl Throttle {
+    /// Index at most `files_per_second` files a second, and none while the
+    /// 1-minute load average is above `max_load`.
+    pub fn new(files_per_second: u32, max_load: Option<f64>) -> Self {
+        Self {
+            files_per_second: files_per_second.max(1),
+            max_load,
+            next_slot: Mutex::new(Instant::now()),
+            load_checked: Mutex::new(None),
+        }
+    }
+
+    /// Background defaults: 200 files a second, paused while the load
+    /// average exceeds the number of CPU cores.
+    pub fn background() -> Self {
+        let cores = std::thread::available_parallelism().map_or(1, |n| n.get());
+        Self::new(200, Some(cores as f64))
+    }
+
+    pub fn files_per_second(&self) -> u32 {
+        self.files_per_second
+    }
+
+    /// Block until the next file may be indexed.
+    fn pace(&self) {
+        while self.overloaded() {
+            std::thread::sleep(LOAD_CHECK_INTERVAL);
+        }
+        let interval = Duration::from_secs(1) / self.files_per_second;
+        let slot = {
+            let mut next = self
+                .next_slot
+                .lock()
+                .unwrap_or_else(|poisoned| poisoned.into_inner());
+            let slot = (*next).max(Instant::now());
+            *next = slot + interval;
+            slot
+        };
+        std::thread::sleep(slot.saturating_duration_since(Instant::now()));
+    }
+
+    /// Whether the load average is above `max_load`, read at most once per
+    /// [`LOAD_CHECK_INTERVAL`].
+    fn overloaded(&self) -> bool {
+        let Some(max_load) = self.max_load else {
+            return false;
+        };
+        let mut checked = self
+            .load_checked
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        if let Some((at, overloaded)) = *checked
+            && at.elapsed() < LOAD_CHECK_INTERVAL
+        {
+            return overloaded;
+        }
+        let overloaded = load_average().is_some_and(|load| load > max_load);
+        if overloaded {
+            debug!(max_load, "system load is high, pausing the scan");
+        }
+        *checked = Some((Instant::now(), overloaded));
+        overloaded
+    }
+}
+
+/// Throttle scans for the rest of the process. Only the first call takes
+/// effect.
+pub fn set_throttle(throttle: Throttle) {
+    let files_per_second = throttle.files_per_second;
+    let max_load = throttle.max_load;
+    if THROTTLE.set(throttle).is_ok() {
+        info!(
+            files_per_second,
+            ?max_load,
+            "background indexing throttle enabled"
+        );
+    }
+}
+
+/// The throttle set by [`set_throttle`], if any.
+pub fn throttle() -> Option<&'static Throttle> {
+    THROTTLE.get()
+}
+
+/// Wait for the throttle, if one is set, before indexing a file.
+pub(crate) fn pace() {
+    if let Some(throttle) = THROTTLE.get() {
+        throttle.pace();
+    }
+}
+
+/// The 1-minute load average, where the platform reports one.
+fn load_average() -> Option<f64> {
+    #[cfg(unix)]
+    {
+        let mut load = [0.0f64; 1];
+        // SAFETY: `load` has room for the one sample requested.
+        let samples = unsafe { libc::getloadavg(load.as_mut_ptr(), 1) };
+        (samples == 1).then_some(load[0])
+    }
+    #[cfg(not(unix))]
+    {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pace_spaces_files_out() {
+        let throttle = Throttle::new(50, None);
+        let started = Instant::now();
+        for _ in 0..6 {
+            throttle.pace();
+        }
+        // The first file goes at once, the other five 20 ms apart.
+        assert!(started.elapsed() >= Duration::from_millis(100));
+    }
+}
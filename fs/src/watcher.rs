@@ -1,14 +1,54 @@
-use std::collections::HashMap;
+use std::collections::{BTreeSet, HashMap};
 use std::path::{Path, PathBuf};
 use std::sync::Arc;
 use std::sync::atomic::{AtomicBool, Ordering};
-use std::time::Duration;
+use std::time::{Duration, Instant};
 
-use notify::event::{CreateKind, ModifyKind, RemoveKind};
-use notify::{Config, Event, EventKind, RecommendedWatcher, RecursiveMode, Watcher};
-use source_fast_core::PersistentIndex;
+use notify::event::{CreateKind, MetadataKind, ModifyKind, RemoveKind, RenameMode};
+use notify::{
+    Config, ErrorKind, Event, EventKind, PollWatcher, RecommendedWatcher, RecursiveMode, Watcher,
+};
+use source_fast_core::{
+    IndexError, PersistentIndex, TransientFiles, WatchMode, WatchScope, WatcherConfig, now_millis,
+};
+use source_fast_progress::{WatcherHealth, WatcherState};
 use tokio::sync::mpsc;
-use tracing::{error, warn};
+use tracing::{error, info, warn};
+
+use crate::journal::Journal;
+use crate::scanner::{
+    GitLocation, GitPaths, IncludeScope, smart_scan_with_progress_cancel, tracked_directories,
+};
+
+/// Delay before the first attempt to recreate a failed watcher; it doubles
+/// with every failure in a row, up to [`RESTART_BACKOFF_MAX`].
+const RESTART_BACKOFF_MIN: Duration = Duration::from_secs(1);
+const RESTART_BACKOFF_MAX: Duration = Duration::from_secs(60);
+
+/// Changes a paused watcher holds on to; past this many it drops them and
+/// runs a catch-up scan on resume instead.
+const PAUSED_PENDING_MAX: usize = 10_000;
+
+/// Meta key set to `true` while indexing is paused on request (the
+/// `pause_indexing` MCP tool, `SIGUSR1` to `sf server`). Kept in the index
+/// so whichever process holds the writer lease honours it.
+pub const INDEXING_PAUSED_META_KEY: &str = "indexing_paused";
+
+/// Whether indexing of `index` is paused on request.
+pub fn indexing_paused(index: &PersistentIndex) -> bool {
+    matches!(index.get_meta(INDEXING_PAUSED_META_KEY), Ok(Some(value)) if value == "true")
+}
+
+/// Pause or resume indexing of `index` on request. While paused, watchers
+/// collect changes without applying them and reconciliation scans are
+/// skipped; on resume the changes are applied, or the root rescanned if
+/// too many piled up.
+pub fn set_indexing_paused(index: &PersistentIndex, paused: bool) -> Result<(), IndexError> {
+    index.set_control_meta(
+        INDEXING_PAUSED_META_KEY,
+        if paused { "true" } else { "false" },
+    )
+}
 
 pub async fn background_watcher(root: PathBuf, index: Arc<PersistentIndex>) -> notify::Result<()> {
     background_watcher_with_cancel(root, index, Arc::new(AtomicBool::new(false))).await
@@ -18,72 +58,468 @@ pub async fn background_watcher_with_cancel(
     root: PathBuf,
     index: Arc<PersistentIndex>,
     cancel: Arc<AtomicBool>,
+) -> notify::Result<()> {
+    background_watcher_with_health(
+        root,
+        index,
+        cancel,
+        WatcherConfig::default(),
+        Arc::new(|_| {}),
+    )
+    .await
+}
+
+/// Where a watcher gets its events from.
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Backend {
+    Native,
+    Poll,
+}
+
+impl Backend {
+    const fn as_str(self) -> &'static str {
+        match self {
+            Self::Native => "native",
+            Self::Poll => "poll",
+        }
+    }
+}
+
+/// Keep `index` current with filesystem events under `root` until `cancel`
+/// is set, reporting every change of [`WatcherHealth`] to `health`.
+///
+/// `config.mode` picks native events, polling, or native events with a
+/// switch to polling once they fail. A watcher that fails (watch limit
+/// exhausted, root gone, backend stopped) is recreated with exponential
+/// backoff; until then a `smart_scan` before each attempt picks up what its
+/// events would have. Event overflows keep the watcher but trigger the same
+/// catch-up scan.
+pub async fn background_watcher_with_health(
+    root: PathBuf,
+    index: Arc<PersistentIndex>,
+    cancel: Arc<AtomicBool>,
+    config: WatcherConfig,
+    health: Arc<dyn Fn(WatcherHealth) + Send + Sync>,
+) -> notify::Result<()> {
+    background_watcher_pausable(
+        root,
+        index,
+        cancel,
+        Arc::new(AtomicBool::new(false)),
+        config,
+        health,
+    )
+    .await
+}
+
+/// [`background_watcher_with_health`] that can be paused, for a process
+/// that loses and regains the writer lease.
+///
+/// While `paused` is set, or indexing is paused on request (see
+/// [`set_indexing_paused`]), the watches stay in place and changes are still
+/// collected and debounced, but none is applied; resuming applies them.
+/// If more than [`PAUSED_PENDING_MAX`] pile up, or events are dropped
+/// meanwhile, a catch-up scan runs on resume instead.
+pub async fn background_watcher_pausable(
+    root: PathBuf,
+    index: Arc<PersistentIndex>,
+    cancel: Arc<AtomicBool>,
+    paused: Arc<AtomicBool>,
+    config: WatcherConfig,
+    health: Arc<dyn Fn(WatcherHealth) + Send + Sync>,
+) -> notify::Result<()> {
+    let mut status = WatcherHealth::default();
+    let mut backoff = RESTART_BACKOFF_MIN;
+    let mut failed = false;
+    let mut native_failed = false;
+
+    while !cancel.load(Ordering::Relaxed) {
+        let backend = match config.mode {
+            WatchMode::Native => Backend::Native,
+            WatchMode::Poll => Backend::Poll,
+            WatchMode::Auto if native_failed => Backend::Poll,
+            WatchMode::Auto => Backend::Native,
+        };
+        let on_ready = || {
+            if failed {
+                status.restarts += 1;
+                info!(root = %root.display(), restarts = status.restarts, "file watcher recreated");
+            }
+            status.state = WatcherState::Watching;
+            status.backend = Some(backend.as_str().to_string());
+            status.since_ms = now_ms();
+            status.next_retry_ms = None;
+            health(status.clone());
+        };
+        let err = match watch(&root, &index, &cancel, &paused, backend, &config, on_ready).await {
+            Ok(()) => break,
+            Err(err) => err,
+        };
+
+        let message = describe(&err);
+        if config.mode == WatchMode::Auto && backend == Backend::Native {
+            warn!(root = %root.display(), error = %message, "native file watching unavailable, polling instead");
+            native_failed = true;
+            status.last_error = Some(message);
+            continue;
+        }
+
+        if !failed || status.state == WatcherState::Watching {
+            backoff = RESTART_BACKOFF_MIN;
+        }
+        failed = true;
+        warn!(
+            root = %root.display(),
+            error = %message,
+            retry_in_ms = backoff.as_millis() as u64,
+            "file watcher failed, polling until it is recreated"
+        );
+        let retry_at = Instant::now() + backoff;
+        status.state = WatcherState::Polling;
+        status.since_ms = now_ms();
+        status.last_error = Some(message);
+        status.next_retry_ms = Some(now_ms() + backoff.as_millis() as u64);
+        health(status.clone());
+
+        // A paused watcher is recreated once it resumes; the catch-up then
+        // covers everything it missed.
+        while is_held(&paused, &index) && !cancel.load(Ordering::Relaxed) {
+            tokio::time::sleep(Duration::from_millis(100)).await;
+        }
+        catch_up(&root, &index, &cancel).await;
+        while !cancel.load(Ordering::Relaxed) && Instant::now() < retry_at {
+            tokio::time::sleep(Duration::from_millis(100)).await;
+        }
+        backoff = (backoff * 2).min(RESTART_BACKOFF_MAX);
+    }
+
+    status.state = WatcherState::Stopped;
+    status.since_ms = now_ms();
+    status.next_retry_ms = None;
+    health(status);
+    Ok(())
+}
+
+/// Run a catch-up `smart_scan` of `root` every `interval` until `cancel` is
+/// set, so changes whose events never arrived (network drives, editors that
+/// replace files in unusual ways) still reach the index.
+pub async fn reconcile_periodically(
+    root: PathBuf,
+    index: Arc<PersistentIndex>,
+    interval: Duration,
+    cancel: Arc<AtomicBool>,
+) {
+    loop {
+        let next = Instant::now() + interval;
+        loop {
+            if cancel.load(Ordering::Relaxed) {
+                return;
+            }
+            let remaining = next.saturating_duration_since(Instant::now());
+            if remaining.is_zero() {
+                break;
+            }
+            tokio::time::sleep(remaining.min(Duration::from_millis(500))).await;
+        }
+        if indexing_paused(&index) {
+            info!(root = %root.display(), "indexing paused, skipping reconciliation scan");
+            continue;
+        }
+        let started = Instant::now();
+        catch_up(&root, &index, &cancel).await;
+        info!(
+            root = %root.display(),
+            elapsed_ms = started.elapsed().as_millis() as u64,
+            "reconciliation scan finished"
+        );
+    }
+}
+
+/// Run one watcher until `cancel` is set (`Ok`) or it fails (`Err`).
+/// `on_ready` is called once every watch is in place.
+async fn watch(
+    root: &Path,
+    index: &Arc<PersistentIndex>,
+    cancel: &Arc<AtomicBool>,
+    paused: &AtomicBool,
+    backend: Backend,
+    config: &WatcherConfig,
+    on_ready: impl FnOnce(),
 ) -> notify::Result<()> {
     let (tx, mut rx) = mpsc::unbounded_channel::<notify::Result<Event>>();
+    let handler = move |res| {
+        let _ = tx.send(res);
+    };
 
-    let mut watcher: RecommendedWatcher = RecommendedWatcher::new(
-        move |res| {
-            let _ = tx.send(res);
-        },
-        Config::default(),
-    )?;
+    let mut watcher: Box<dyn Watcher + Send> = match backend {
+        Backend::Native => Box::new(RecommendedWatcher::new(handler, Config::default())?),
+        Backend::Poll => Box::new(PollWatcher::new(
+            handler,
+            Config::default().with_poll_interval(config.poll_interval()),
+        )?),
+    };
 
-    watcher.watch(&root, RecursiveMode::Recursive)?;
+    // With include paths only their subtrees are watched; events for
+    // anything else are dropped below.
+    let scope = IncludeScope::load(root, index);
+    match tracked_scope(root, &GitLocation::load(index), config) {
+        Some(dirs) => {
+            let dirs: Vec<&PathBuf> = dirs
+                .iter()
+                .filter(|dir| dir.is_dir() && scope.contains(dir))
+                .collect();
+            info!(root = %root.display(), directories = dirs.len(), "watching git-tracked directories");
+            for dir in dirs {
+                watcher.watch(dir, RecursiveMode::NonRecursive)?;
+            }
+        }
+        None => {
+            for dir in scope.roots(root) {
+                if scope.is_restricted() && !dir.is_dir() {
+                    warn!(path = %dir.display(), "include path is not a directory, not watching it");
+                    continue;
+                }
+                watcher.watch(dir, RecursiveMode::Recursive)?;
+            }
+        }
+    }
+    on_ready();
 
     let exclude_dir = root.join(".source_fast");
+    let git = GitPaths::load(root);
+    let transient = TransientFiles::new(&config.transient_files).unwrap_or_else(|err| {
+        warn!(error = %err, "ignoring invalid [watcher] transient_files");
+        TransientFiles::default()
+    });
     let mut pending: HashMap<PathBuf, PendingAction> = HashMap::new();
+    // Paths in `pending`, kept on disk until committed so a crash does not
+    // lose them.
+    let mut journal = Journal::new(index);
+    // `(from, to)` of the renames behind `pending`, for path suggestions.
+    let mut renames: Vec<(PathBuf, PathBuf)> = Vec::new();
     let debounce = Duration::from_millis(500);
     let poll = Duration::from_millis(100);
-    let mut last_event_at: Option<std::time::Instant> = None;
+    let mut last_event_at: Option<Instant> = None;
+    let mut failure = None;
+    // Changes went unseen while paused, so resuming needs a catch-up scan.
+    let mut missed = false;
+    let mut was_paused = false;
 
     while !cancel.load(Ordering::Relaxed) {
+        let is_paused = is_held(paused, index);
+        if was_paused && !is_paused {
+            if missed {
+                info!(root = %root.display(), "file watcher resumed, rescanning missed changes");
+                pending.clear();
+                renames.clear();
+                missed = false;
+                catch_up(root, index, cancel).await;
+            } else {
+                info!(root = %root.display(), queued = pending.len(), "file watcher resumed");
+                drain_pending(&mut pending, &mut renames, index, &mut journal).await;
+            }
+            last_event_at = None;
+        }
+        was_paused = is_paused;
+
         match tokio::time::timeout(poll, rx.recv()).await {
-            Ok(Some(Ok(event))) => {
-                collect_event(event, &exclude_dir, &mut pending);
-                last_event_at = Some(std::time::Instant::now());
+            Ok(Some(Ok(event))) if event.need_rescan() && is_paused => {
+                pending.clear();
+                renames.clear();
+                missed = true;
+            }
+            Ok(Some(Ok(event))) if event.need_rescan() => {
+                warn!(root = %root.display(), "file watcher dropped events, rescanning");
+                drain_pending(&mut pending, &mut renames, index, &mut journal).await;
+                last_event_at = None;
+                catch_up(root, index, cancel).await;
+            }
+            Ok(Some(Ok(_))) if is_paused && missed => {}
+            Ok(Some(Ok(mut event))) => {
+                event.paths.retain(|path| scope.contains(path));
+                journal.record(
+                    event
+                        .paths
+                        .iter()
+                        .filter(|path| !path.starts_with(&exclude_dir) && !git.contains(path)),
+                );
+                collect_event(
+                    event,
+                    &exclude_dir,
+                    &git,
+                    &transient,
+                    &mut pending,
+                    &mut renames,
+                );
+                last_event_at = Some(Instant::now());
+                if is_paused && pending.len() > PAUSED_PENDING_MAX {
+                    pending.clear();
+                    renames.clear();
+                    missed = true;
+                }
+            }
+            Ok(Some(Err(err))) if is_fatal(&err, root) => {
+                failure = Some(err);
+                break;
             }
             Ok(Some(Err(err))) => {
                 warn!("file watcher error: {err}");
             }
-            Ok(None) => break,
+            Ok(None) => {
+                failure = Some(notify::Error::generic(
+                    "file watcher stopped delivering events",
+                ));
+                break;
+            }
             Err(_) => {}
         }
 
-        if !pending.is_empty()
+        if !is_paused
+            && !pending.is_empty()
             && last_event_at
                 .map(|last| last.elapsed() >= debounce)
                 .unwrap_or(false)
         {
-            drain_pending(&mut pending, &index).await;
+            drain_pending(&mut pending, &mut renames, index, &mut journal).await;
             last_event_at = None;
         }
     }
 
-    if !pending.is_empty() && !cancel.load(Ordering::Relaxed) {
-        drain_pending(&mut pending, &index).await;
+    if !pending.is_empty() && !cancel.load(Ordering::Relaxed) && !is_held(paused, index) {
+        drain_pending(&mut pending, &mut renames, index, &mut journal).await;
     }
 
-    Ok(())
+    failure.map_or(Ok(()), Err)
+}
+
+/// The directories to watch one by one under `[watcher] scope = "tracked"`,
+/// or `None` to watch the whole root recursively.
+fn tracked_scope(
+    root: &Path,
+    git: &GitLocation,
+    config: &WatcherConfig,
+) -> Option<BTreeSet<PathBuf>> {
+    if config.scope != WatchScope::Tracked {
+        return None;
+    }
+    let dirs = tracked_directories(root, git);
+    if dirs.is_none() {
+        warn!(root = %root.display(), "not a git repository, watching every directory");
+    }
+    dirs
+}
+
+/// `err` as reported in logs and [`WatcherHealth::last_error`], with a way
+/// out for the inotify watch limit.
+fn describe(err: &notify::Error) -> String {
+    if matches!(err.kind, ErrorKind::MaxFilesWatch) {
+        return "inotify watch limit reached (fs.inotify.max_user_watches); raise it with \
+                `sudo sysctl fs.inotify.max_user_watches=524288`, or set \
+                `[watcher] scope = \"tracked\"` or `mode = \"poll\"` in .source_fast/config.toml"
+            .to_string();
+    }
+    err.to_string()
+}
+
+/// Errors after which the watcher no longer covers the whole root.
+fn is_fatal(err: &notify::Error, root: &Path) -> bool {
+    matches!(err.kind, ErrorKind::MaxFilesWatch) || !root.exists()
+}
+
+/// Pick up changes that happened without (reliable) events.
+async fn catch_up(root: &Path, index: &Arc<PersistentIndex>, cancel: &Arc<AtomicBool>) {
+    let root = root.to_path_buf();
+    let index = Arc::clone(index);
+    let cancel = Arc::clone(cancel);
+    let result = tokio::task::spawn_blocking(move || {
+        smart_scan_with_progress_cancel(&root, index, Arc::new(|_| {}), cancel)
+    })
+    .await;
+    match result {
+        Ok(Ok(())) | Ok(Err(IndexError::Cancelled)) => {}
+        Ok(Err(err)) => warn!("catch-up scan failed: {err}"),
+        Err(join_err) => error!("catch-up scan panicked: {join_err}"),
+    }
+}
+
+/// Whether a watcher must hold its changes back: this process lost the
+/// writer lease (`paused`) or indexing is paused on request.
+fn is_held(paused: &AtomicBool, index: &PersistentIndex) -> bool {
+    paused.load(Ordering::Relaxed) || indexing_paused(index)
+}
+
+fn now_ms() -> u64 {
+    now_millis().max(0) as u64
 }
 
 #[derive(Clone, Copy)]
 enum PendingAction {
     Upsert,
     Remove,
+    /// A directory (or a path of unknown kind) went away; drop everything
+    /// indexed beneath it in one job.
+    RemovePrefix,
 }
 
-fn collect_event(event: Event, exclude_dir: &Path, pending: &mut HashMap<PathBuf, PendingAction>) {
+/// Queue the changes `event` reports, and the renames it names both ends
+/// of. Transient editor files and [`GitPaths`] are never indexed, but the
+/// removal of a transient file is still applied so no ghost survives.
+fn collect_event(
+    event: Event,
+    exclude_dir: &Path,
+    git: &GitPaths,
+    transient: &TransientFiles,
+    pending: &mut HashMap<PathBuf, PendingAction>,
+    renames: &mut Vec<(PathBuf, PathBuf)>,
+) {
+    let indexable = |path: &Path| {
+        !path.starts_with(exclude_dir) && !git.contains(path) && !transient.contains(path)
+    };
     match event.kind {
         EventKind::Modify(ModifyKind::Data(_))
         | EventKind::Modify(ModifyKind::Any)
         | EventKind::Create(CreateKind::File) => {
             for path in event.paths {
-                if path.starts_with(exclude_dir) {
+                if !indexable(&path) {
+                    continue;
+                }
+                pending.insert(path, PendingAction::Upsert);
+            }
+        }
+        // The polling watcher reports new entries and changed write times
+        // without saying whether the path is a file.
+        EventKind::Modify(ModifyKind::Metadata(MetadataKind::WriteTime))
+        | EventKind::Create(CreateKind::Any) => {
+            for path in event.paths {
+                if !indexable(&path) || path.is_dir() {
                     continue;
                 }
                 pending.insert(path, PendingAction::Upsert);
             }
         }
+        // An atomic save renames a temp file over the original. Backends
+        // differ in which ends of a rename they report, so each path is
+        // judged by what is there now: a name that went away is dropped
+        // (with anything beneath it), a file that arrived is indexed.
+        // Renamed directories are left to reconciliation.
+        EventKind::Modify(ModifyKind::Name(kind)) => {
+            if let (RenameMode::Both, [from, to]) = (kind, event.paths.as_slice())
+                && indexable(from)
+                && indexable(to)
+            {
+                renames.push((from.clone(), to.clone()));
+            }
+            for path in event.paths {
+                if path.starts_with(exclude_dir) {
+                    continue;
+                }
+                if !path.exists() {
+                    pending.insert(path, PendingAction::RemovePrefix);
+                } else if path.is_file() && indexable(&path) {
+                    pending.insert(path, PendingAction::Upsert);
+                }
+            }
+        }
         EventKind::Remove(RemoveKind::File) => {
             for path in event.paths {
                 if path.starts_with(exclude_dir) {
@@ -92,15 +528,55 @@ fn collect_event(event: Event, exclude_dir: &Path, pending: &mut HashMap<PathBuf
                 pending.insert(path, PendingAction::Remove);
             }
         }
+        EventKind::Remove(RemoveKind::Folder) | EventKind::Remove(RemoveKind::Any) => {
+            for path in event.paths {
+                if path.starts_with(exclude_dir) {
+                    continue;
+                }
+                pending.insert(path, PendingAction::RemovePrefix);
+            }
+        }
         _ => {}
     }
 }
 
 async fn drain_pending(
     pending: &mut HashMap<PathBuf, PendingAction>,
+    renames: &mut Vec<(PathBuf, PathBuf)>,
     index: &Arc<PersistentIndex>,
+    journal: &mut Journal,
 ) {
+    let renames = std::mem::take(renames);
+    if !renames.is_empty() {
+        let index = Arc::clone(index);
+        match tokio::task::spawn_blocking(move || index.record_renames(&renames)).await {
+            Ok(Ok(())) => {}
+            Ok(Err(err)) => warn!(error = %err, "failed to record renames"),
+            Err(join_err) => error!(error = %join_err, "watcher task panicked"),
+        }
+    }
+
     let events = std::mem::take(pending);
+    let removed_dirs: Vec<PathBuf> = events
+        .iter()
+        .filter(|(_, action)| matches!(action, PendingAction::RemovePrefix))
+        .map(|(path, _)| path.clone())
+        .collect();
+    // Removals beneath a removed directory are covered by its prefix job.
+    let mut events: Vec<(PathBuf, PendingAction)> = events
+        .into_iter()
+        .filter(|(path, action)| {
+            matches!(action, PendingAction::Upsert)
+                || !removed_dirs
+                    .iter()
+                    .any(|dir| path != dir && path.starts_with(dir))
+        })
+        .collect();
+    // Directory removals run first so files recreated beneath them survive.
+    events.sort_by_key(|(_, action)| !matches!(action, PendingAction::RemovePrefix));
+
+    // Paths the index could not take stay journaled for the next replay.
+    let mut failed = Vec::new();
     for (path, action) in events {
         let index_clone = Arc::clone(index);
         let path_for_thread = path.clone();
@@ -112,14 +588,328 @@ async fn drain_pending(
             PendingAction::Remove => {
                 tokio::task::spawn_blocking(move || index_clone.remove_path(&path_for_thread)).await
             }
+            PendingAction::RemovePrefix => {
+                tokio::task::spawn_blocking(move || index_clone.remove_prefix(&path_for_thread))
+                    .await
+            }
+        };
+
+        match result {
+            Ok(Ok(())) => {}
+            Ok(Err(err)) => {
+                warn!(path = %path_display, error = %err, "failed to apply watcher change");
+                failed.push(path);
+            }
+            Err(join_err) => {
+                error!(
+                    path = %path_display,
+                    error = %join_err,
+                    "watcher task panicked"
+                );
+                failed.push(path);
+            }
+        }
+    }
+
+    // Drop the journal only once everything above is committed.
+    let index = Arc::clone(index);
+    match tokio::task::spawn_blocking(move || index.flush().map(|()| index.write_enabled())).await {
+        Ok(Ok(true)) => {
+            journal.clear();
+            journal.record(&failed);
+        }
+        Ok(Ok(false)) => {}
+        Ok(Err(err)) => warn!(error = %err, "failed to commit watcher changes"),
+        Err(join_err) => error!(error = %join_err, "watcher task panicked"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Mutex;
+    use tempfile::TempDir;
+
+    #[tokio::test]
+    async fn test_drain_keeps_failed_paths_journaled() {
+        let temp = TempDir::new().unwrap();
+        let root = temp.path().join("root");
+        let indexed = root.join("indexed.txt");
+        let unreadable = root.join("unreadable.txt");
+        std::fs::create_dir_all(&unreadable).unwrap();
+        std::fs::write(&indexed, "drained_marker").unwrap();
+        let index =
+            Arc::new(PersistentIndex::open_or_create(&temp.path().join("index.mdb")).unwrap());
+        index.set_write_enabled(true);
+
+        let mut journal = Journal::new(&index);
+        journal.record([&indexed, &unreadable]);
+        let mut pending = HashMap::from([
+            (indexed.clone(), PendingAction::Upsert),
+            (unreadable.clone(), PendingAction::Upsert),
+        ]);
+        drain_pending(&mut pending, &mut Vec::new(), &index, &mut journal).await;
+
+        assert_eq!(index.search("drained_marker").unwrap().len(), 1);
+        let journals: Vec<_> = std::fs::read_dir(temp.path().join("watcher-journal"))
+            .unwrap()
+            .map(|entry| std::fs::read(entry.unwrap().path()).unwrap())
+            .collect();
+        assert_eq!(
+            journals,
+            [format!("{}\0", source_fast_core::encode_path(&unreadable)).into_bytes()]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_watcher_polls_until_it_can_be_recreated() {
+        let temp = TempDir::new().unwrap();
+        let root = temp.path().join("root");
+        let index =
+            Arc::new(PersistentIndex::open_or_create(&temp.path().join("index.mdb")).unwrap());
+        let cancel = Arc::new(AtomicBool::new(false));
+        let reports = Arc::new(Mutex::new(Vec::<WatcherHealth>::new()));
+        let latest = {
+            let reports = Arc::clone(&reports);
+            move || reports.lock().unwrap().last().cloned()
         };
 
-        if let Err(join_err) = result {
-            error!(
-                path = %path_display,
-                error = %join_err,
-                "watcher task panicked"
+        let watcher = tokio::spawn(background_watcher_with_health(
+            root.clone(),
+            index,
+            Arc::clone(&cancel),
+            WatcherConfig {
+                mode: WatchMode::Native,
+                ..WatcherConfig::default()
+            },
+            {
+                let reports = Arc::clone(&reports);
+                Arc::new(move |health| reports.lock().unwrap().push(health))
+            },
+        ));
+
+        let deadline = Instant::now() + Duration::from_secs(10);
+        let wait_for = |state: WatcherState| {
+            let latest = latest.clone();
+            async move {
+                while latest().is_none_or(|health| health.state != state) {
+                    assert!(Instant::now() < deadline, "never reached {state:?}");
+                    tokio::time::sleep(Duration::from_millis(50)).await;
+                }
+                latest().unwrap()
+            }
+        };
+
+        // The root does not exist yet, so the first watcher cannot start.
+        let polling = wait_for(WatcherState::Polling).await;
+        assert!(polling.last_error.is_some());
+        assert!(polling.next_retry_ms.is_some());
+
+        std::fs::create_dir_all(&root).unwrap();
+        let watching = wait_for(WatcherState::Watching).await;
+        assert_eq!(watching.restarts, 1);
+
+        cancel.store(true, Ordering::Relaxed);
+        watcher.await.unwrap().unwrap();
+        assert_eq!(latest().unwrap().state, WatcherState::Stopped);
+    }
+
+    #[tokio::test]
+    async fn test_poll_mode_indexes_new_files() {
+        let temp = TempDir::new().unwrap();
+        let root = temp.path().join("root");
+        std::fs::create_dir_all(&root).unwrap();
+        let index =
+            Arc::new(PersistentIndex::open_or_create(&temp.path().join("index.mdb")).unwrap());
+        let cancel = Arc::new(AtomicBool::new(false));
+        let backend = Arc::new(Mutex::new(None));
+
+        let watcher = tokio::spawn(background_watcher_with_health(
+            root.clone(),
+            Arc::clone(&index),
+            Arc::clone(&cancel),
+            WatcherConfig {
+                mode: WatchMode::Poll,
+                poll_interval_ms: 50,
+                ..WatcherConfig::default()
+            },
+            {
+                let backend = Arc::clone(&backend);
+                Arc::new(move |health| *backend.lock().unwrap() = health.backend)
+            },
+        ));
+
+        let deadline = Instant::now() + Duration::from_secs(10);
+        while backend.lock().unwrap().is_none() {
+            assert!(Instant::now() < deadline, "poll watcher never started");
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+        assert_eq!(backend.lock().unwrap().as_deref(), Some("poll"));
+
+        std::fs::write(root.join("polled.txt"), "polled_marker").unwrap();
+        while index.search("polled_marker").unwrap().is_empty() {
+            assert!(
+                Instant::now() < deadline,
+                "poll watcher never indexed the file"
+            );
+            tokio::time::sleep(Duration::from_millis(50)).await;
+        }
+
+        cancel.store(true, Ordering::Relaxed);
+        watcher.await.unwrap().unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_atomic_save_leaves_one_entry() {
+        let temp = TempDir::new().unwrap();
+        let root = temp.path().join("root");
+        std::fs::create_dir_all(&root).unwrap();
+        let index =
+            Arc::new(PersistentIndex::open_or_create(&temp.path().join("index.mdb")).unwrap());
+        let cancel = Arc::new(AtomicBool::new(false));
+        let ready = Arc::new(AtomicBool::new(false));
+
+        let watcher = tokio::spawn(background_watcher_with_health(
+            root.clone(),
+            Arc::clone(&index),
+            Arc::clone(&cancel),
+            WatcherConfig::default(),
+            {
+                let ready = Arc::clone(&ready);
+                Arc::new(move |health| {
+                    if health.state == WatcherState::Watching {
+                        ready.store(true, Ordering::SeqCst);
+                    }
+                })
+            },
+        ));
+
+        let deadline = Instant::now() + Duration::from_secs(10);
+        while !ready.load(Ordering::SeqCst) {
+            assert!(Instant::now() < deadline, "watcher never started");
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+
+        let saved = root.join("doc.txt");
+        let temp_file = root.join("doc.txt.tmp");
+        std::fs::write(&temp_file, "atomic_save_marker").unwrap();
+        std::fs::rename(&temp_file, &saved).unwrap();
+        std::fs::write(root.join("4913"), "atomic_save_marker").unwrap();
+        std::fs::remove_file(root.join("4913")).unwrap();
+
+        while index.search("atomic_save_marker").unwrap().is_empty() {
+            assert!(
+                Instant::now() < deadline,
+                "the saved file was never indexed"
+            );
+            tokio::time::sleep(Duration::from_millis(50)).await;
+        }
+        // Let any straggling events drain.
+        tokio::time::sleep(Duration::from_millis(1000)).await;
+        let hits = index.search("atomic_save_marker").unwrap();
+        assert_eq!(hits.len(), 1, "{hits:?}");
+        assert!(hits[0].path.ends_with("doc.txt"));
+
+        cancel.store(true, Ordering::Relaxed);
+        watcher.await.unwrap().unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_paused_watcher_applies_changes_on_resume() {
+        let temp = TempDir::new().unwrap();
+        let root = temp.path().join("root");
+        std::fs::create_dir_all(&root).unwrap();
+        let index =
+            Arc::new(PersistentIndex::open_or_create(&temp.path().join("index.mdb")).unwrap());
+        let cancel = Arc::new(AtomicBool::new(false));
+        let paused = Arc::new(AtomicBool::new(false));
+        let ready = Arc::new(AtomicBool::new(false));
+
+        let watcher = tokio::spawn(background_watcher_pausable(
+            root.clone(),
+            Arc::clone(&index),
+            Arc::clone(&cancel),
+            Arc::clone(&paused),
+            WatcherConfig::default(),
+            {
+                let ready = Arc::clone(&ready);
+                Arc::new(move |health| {
+                    if health.state == WatcherState::Watching {
+                        ready.store(true, Ordering::SeqCst);
+                    }
+                })
+            },
+        ));
+
+        let deadline = Instant::now() + Duration::from_secs(10);
+        while !ready.load(Ordering::SeqCst) {
+            assert!(Instant::now() < deadline, "watcher never started");
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+
+        paused.store(true, Ordering::SeqCst);
+        tokio::time::sleep(Duration::from_millis(200)).await;
+        std::fs::write(root.join("queued.txt"), "paused_marker").unwrap();
+        // Well past the debounce: nothing is applied while paused.
+        tokio::time::sleep(Duration::from_millis(1000)).await;
+        assert!(index.search("paused_marker").unwrap().is_empty());
+
+        paused.store(false, Ordering::SeqCst);
+        while index.search("paused_marker").unwrap().is_empty() {
+            assert!(
+                Instant::now() < deadline,
+                "the queued change was never applied"
             );
+            tokio::time::sleep(Duration::from_millis(50)).await;
         }
+
+        // Pausing on request holds changes back the same way.
+        set_indexing_paused(&index, true).unwrap();
+        tokio::time::sleep(Duration::from_millis(200)).await;
+        std::fs::write(root.join("requested.txt"), "requested_marker").unwrap();
+        tokio::time::sleep(Duration::from_millis(1000)).await;
+        assert!(index.search("requested_marker").unwrap().is_empty());
+
+        set_indexing_paused(&index, false).unwrap();
+        let deadline = Instant::now() + Duration::from_secs(10);
+        while index.search("requested_marker").unwrap().is_empty() {
+            assert!(
+                Instant::now() < deadline,
+                "the change held back on request was never applied"
+            );
+            tokio::time::sleep(Duration::from_millis(50)).await;
+        }
+
+        cancel.store(true, Ordering::Relaxed);
+        watcher.await.unwrap().unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_reconcile_picks_up_unwatched_changes() {
+        let temp = TempDir::new().unwrap();
+        let root = temp.path().join("root");
+        std::fs::create_dir_all(&root).unwrap();
+        let index =
+            Arc::new(PersistentIndex::open_or_create(&temp.path().join("index.mdb")).unwrap());
+        let cancel = Arc::new(AtomicBool::new(false));
+        let task = tokio::spawn(reconcile_periodically(
+            root.clone(),
+            Arc::clone(&index),
+            Duration::from_millis(100),
+            Arc::clone(&cancel),
+        ));
+
+        std::fs::write(root.join("late.txt"), "reconciled_marker").unwrap();
+        let deadline = Instant::now() + Duration::from_secs(10);
+        while index.search("reconciled_marker").unwrap().is_empty() {
+            assert!(
+                Instant::now() < deadline,
+                "reconciliation never indexed the file"
+            );
+            tokio::time::sleep(Duration::from_millis(50)).await;
+        }
+
+        cancel.store(true, Ordering::Relaxed);
+        task.await.unwrap();
     }
 }
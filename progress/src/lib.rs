@@ -7,6 +7,8 @@ pub enum IndexPhase {
     Building,
     Complete,
     Failed,
+    /// A staged build stopped at its limits; the next scan resumes it.
+    Paused,
 }
 
 impl IndexPhase {
@@ -15,11 +17,12 @@ impl IndexPhase {
             Self::Building => "building",
             Self::Complete => "complete",
             Self::Failed => "failed",
+            Self::Paused => "paused",
         }
     }
 
     pub const fn is_terminal(self) -> bool {
-        matches!(self, Self::Complete | Self::Failed)
+        matches!(self, Self::Complete | Self::Failed | Self::Paused)
     }
 }
 
@@ -122,6 +125,43 @@ impl IndexProgress {
     }
 }
 
+/// What keeps an index current between scans.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum WatcherState {
+    /// Filesystem events are applied as they arrive.
+    #[default]
+    Watching,
+    /// The watcher failed; periodic scans stand in while it is recreated.
+    Polling,
+    /// The watcher was shut down with its owner.
+    Stopped,
+}
+
+impl WatcherState {
+    pub const fn as_str(self) -> &'static str {
+        match self {
+            Self::Watching => "watching",
+            Self::Polling => "polling",
+            Self::Stopped => "stopped",
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Default, PartialEq, Eq)]
+pub struct WatcherHealth {
+    pub state: WatcherState,
+    /// Event source of the running watcher: `native` or `poll`.
+    pub backend: Option<String>,
+    /// When `state` was entered.
+    pub since_ms: u64,
+    /// Times the watcher was recreated after failing.
+    pub restarts: u32,
+    pub last_error: Option<String>,
+    /// While polling: when the watcher is next recreated.
+    pub next_retry_ms: Option<u64>,
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -137,4 +177,18 @@ mod tests {
         let decoded: IndexProgress = serde_json::from_str(&json).unwrap();
         assert_eq!(decoded.phase, IndexPhase::Complete);
     }
+
+    #[test]
+    fn watcher_state_uses_wire_strings() {
+        let health = WatcherHealth {
+            state: WatcherState::Polling,
+            ..Default::default()
+        };
+        let json = serde_json::to_string(&health).unwrap();
+        assert!(json.contains(r#""state":"polling""#));
+        assert_eq!(
+            serde_json::from_str::<WatcherHealth>(&json).unwrap(),
+            health
+        );
+    }
 }